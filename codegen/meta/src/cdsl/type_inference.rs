@@ -78,7 +78,9 @@ impl Constraint {
                 }
 
                 // Trivially false.
-                if (&ts1.lanes & &ts2.lanes).is_empty() {
+                if (&ts1.lanes & &ts2.lanes).is_empty()
+                    && (&ts1.dynamic_lanes & &ts2.dynamic_lanes).is_empty()
+                {
                     return true;
                 }
 
@@ -20,6 +20,7 @@ pub(crate) enum ValueType {
     Reference(ReferenceType),
     Special(SpecialType),
     Vector(VectorType),
+    DynamicVector(DynamicVectorType),
 }
 
 impl ValueType {
@@ -44,6 +45,7 @@ impl ValueType {
             ValueType::Reference(r) => r.doc(),
             ValueType::Special(s) => s.doc(),
             ValueType::Vector(ref v) => v.doc(),
+            ValueType::DynamicVector(ref v) => v.doc(),
         }
     }
 
@@ -54,13 +56,15 @@ impl ValueType {
             ValueType::Reference(r) => r.lane_bits(),
             ValueType::Special(s) => s.lane_bits(),
             ValueType::Vector(ref v) => v.lane_bits(),
+            ValueType::DynamicVector(ref v) => v.lane_bits(),
         }
     }
 
-    /// Return the number of lanes.
+    /// Return the number of lanes, or the minimum number of lanes of a dynamic vector.
     pub fn lane_count(&self) -> u64 {
         match *self {
             ValueType::Vector(ref v) => v.lane_count(),
+            ValueType::DynamicVector(ref v) => v.minimum_lane_count(),
             _ => 1,
         }
     }
@@ -71,12 +75,13 @@ impl ValueType {
     }
 
     /// Find the unique number associated with this type.
-    pub fn number(&self) -> Option<u8> {
+    pub fn number(&self) -> Option<u16> {
         match *self {
             ValueType::Lane(l) => Some(l.number()),
             ValueType::Reference(r) => Some(r.number()),
             ValueType::Special(s) => Some(s.number()),
             ValueType::Vector(ref v) => Some(v.number()),
+            ValueType::DynamicVector(ref v) => Some(v.number()),
         }
     }
 
@@ -105,6 +110,7 @@ impl fmt::Display for ValueType {
             ValueType::Reference(r) => r.fmt(f),
             ValueType::Special(s) => s.fmt(f),
             ValueType::Vector(ref v) => v.fmt(f),
+            ValueType::DynamicVector(ref v) => v.fmt(f),
         }
     }
 }
@@ -137,6 +143,13 @@ impl From<VectorType> for ValueType {
     }
 }
 
+/// Create a ValueType from a given dynamic vector type.
+impl From<DynamicVectorType> for ValueType {
+    fn from(vector: DynamicVectorType) -> Self {
+        ValueType::DynamicVector(vector)
+    }
+}
+
 /// A concrete scalar type that can appear as a vector lane too.
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum LaneType {
@@ -150,6 +163,12 @@ impl LaneType {
     pub fn doc(self) -> String {
         match self {
             LaneType::Bool(_) => format!("A boolean type with {} bits.", self.lane_bits()),
+            LaneType::Float(shared_types::Float::F16) => String::from(
+                "A 16-bit floating point type represented in the IEEE 754-2008
+                *binary16* interchange format. Arithmetic on it is not supported; it can
+                only be loaded, stored, and converted to and from wider floating point
+                types.",
+            ),
             LaneType::Float(shared_types::Float::F32) => String::from(
                 "A 32-bit floating point type represented in the IEEE 754-2008
                 *binary32* interchange format. This corresponds to the :c:type:`float`
@@ -180,7 +199,7 @@ impl LaneType {
     }
 
     /// Find the unique number associated with this lane type.
-    pub fn number(self) -> u8 {
+    pub fn number(self) -> u16 {
         constants::LANE_BASE
             + match self {
                 LaneType::Bool(shared_types::Bool::B1) => 0,
@@ -196,6 +215,7 @@ impl LaneType {
                 LaneType::Int(shared_types::Int::I128) => 10,
                 LaneType::Float(shared_types::Float::F32) => 11,
                 LaneType::Float(shared_types::Float::F64) => 12,
+                LaneType::Float(shared_types::Float::F16) => 13,
             }
     }
 
@@ -224,6 +244,7 @@ impl LaneType {
 
     pub fn float_from_bits(num_bits: u16) -> LaneType {
         LaneType::Float(match num_bits {
+            16 => shared_types::Float::F16,
             32 => shared_types::Float::F32,
             64 => shared_types::Float::F64,
             _ => unreachable!("unxpected num bits for float"),
@@ -238,6 +259,10 @@ impl LaneType {
         }
     }
 
+    pub fn to_dynamic(self, lanes: u16) -> ValueType {
+        ValueType::DynamicVector(DynamicVectorType::new(self, lanes.into()))
+    }
+
     pub fn is_float(self) -> bool {
         match self {
             LaneType::Float(_) => true,
@@ -376,11 +401,11 @@ impl VectorType {
     ///
     /// Vector types are encoded with the lane type in the low 4 bits and
     /// log2(lanes) in the high 4 bits, giving a range of 2-256 lanes.
-    pub fn number(&self) -> u8 {
+    pub fn number(&self) -> u16 {
         let lanes_log_2: u32 = 63 - self.lane_count().leading_zeros();
         let base_num = u32::from(self.base.number());
         let num = (lanes_log_2 << 4) + base_num;
-        num as u8
+        num as u16
     }
 }
 
@@ -401,6 +426,75 @@ impl fmt::Debug for VectorType {
     }
 }
 
+/// A concrete dynamically-scaled SIMD vector type.
+///
+/// A dynamic vector type has a lane type and a minimum number of lanes; the actual number of
+/// lanes is the minimum multiplied by the target's `vscale`, which is only known at run time.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) struct DynamicVectorType {
+    base: LaneType,
+    unscaled_lanes: u64,
+}
+
+impl DynamicVectorType {
+    /// Initialize a new dynamic vector type with at least `unscaled_lanes` lanes.
+    pub fn new(base: LaneType, unscaled_lanes: u64) -> Self {
+        Self {
+            base,
+            unscaled_lanes,
+        }
+    }
+
+    /// Return a string containing the documentation comment for this dynamic vector type.
+    pub fn doc(&self) -> String {
+        format!(
+            "A dynamically-scaled SIMD vector with a minimum of {} lanes containing a `{}` each.",
+            self.unscaled_lanes, self.base
+        )
+    }
+
+    /// Return the number of bits in a lane.
+    pub fn lane_bits(&self) -> u64 {
+        self.base.lane_bits()
+    }
+
+    /// Return the minimum number of lanes.
+    pub fn minimum_lane_count(&self) -> u64 {
+        self.unscaled_lanes
+    }
+
+    /// Return the lane type.
+    pub fn lane_type(&self) -> LaneType {
+        self.base
+    }
+
+    /// Find the unique number associated with this dynamic vector type.
+    ///
+    /// Dynamic vector types are numbered like the vector types with their minimum number of
+    /// lanes, offset into the range starting at `DYNAMIC_VECTOR_BASE`.
+    pub fn number(&self) -> u16 {
+        VectorType::new(self.base, self.unscaled_lanes).number() + constants::DYNAMIC_VECTOR_BASE
+            - constants::VECTOR_BASE
+    }
+}
+
+impl fmt::Display for DynamicVectorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}xN", self.base, self.minimum_lane_count())
+    }
+}
+
+impl fmt::Debug for DynamicVectorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "DynamicVectorType(base={}, lanes={})",
+            self.base,
+            self.minimum_lane_count()
+        )
+    }
+}
+
 /// A concrete scalar type that is neither a vector nor a lane type.
 ///
 /// Special types cannot be used to form vectors.
@@ -438,7 +532,7 @@ impl SpecialType {
     }
 
     /// Find the unique number associated with this special type.
-    pub fn number(self) -> u8 {
+    pub fn number(self) -> u16 {
         match self {
             SpecialType::Flag(shared_types::Flag::IFlags) => 1,
             SpecialType::Flag(shared_types::Flag::FFlags) => 2,
@@ -525,7 +619,7 @@ impl ReferenceType {
     }
 
     /// Find the unique number associated with this reference type.
-    pub fn number(self) -> u8 {
+    pub fn number(self) -> u16 {
         constants::REFERENCE_BASE
             + match self {
                 ReferenceType(shared_types::Reference::R32) => 0,
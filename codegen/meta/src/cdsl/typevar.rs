@@ -3,7 +3,7 @@ use std::{
     collections::{BTreeSet, HashSet},
     fmt, hash,
     iter::FromIterator,
-    ops,
+    mem, ops,
     rc::Rc,
 };
 
@@ -57,7 +57,7 @@ impl TypeVar {
         let (name, doc) = (value_type.to_string(), value_type.doc());
         let mut builder = TypeSetBuilder::new();
 
-        let (scalar_type, num_lanes) = match value_type {
+        let (scalar_type, num_lanes, dynamic) = match value_type {
             ValueType::Special(special_type) => {
                 return TypeVar::new(name, doc, builder.specials(vec![special_type]).build());
             }
@@ -65,10 +65,17 @@ impl TypeVar {
                 let bits = reference_type as RangeBound;
                 return TypeVar::new(name, doc, builder.refs(bits..bits).build());
             }
-            ValueType::Lane(lane_type) => (lane_type, 1),
-            ValueType::Vector(vec_type) => {
-                (vec_type.lane_type(), vec_type.lane_count() as RangeBound)
-            }
+            ValueType::Lane(lane_type) => (lane_type, 1, false),
+            ValueType::Vector(vec_type) => (
+                vec_type.lane_type(),
+                vec_type.lane_count() as RangeBound,
+                false,
+            ),
+            ValueType::DynamicVector(vec_type) => (
+                vec_type.lane_type(),
+                vec_type.minimum_lane_count() as RangeBound,
+                true,
+            ),
         };
 
         builder = builder.simd_lanes(num_lanes..num_lanes);
@@ -87,7 +94,11 @@ impl TypeVar {
                 builder.bools(bits..bits)
             }
         };
-        TypeVar::new(name, doc, builder.build())
+        let mut type_set = builder.build();
+        if dynamic {
+            type_set.dynamic_lanes = mem::take(&mut type_set.lanes);
+        }
+        TypeVar::new(name, doc, type_set)
     }
 
     /// Get a fresh copy of self, named after `name`. Can only be called on non-derived typevars.
@@ -423,6 +434,7 @@ pub(crate) struct TypeVarParent {
 ///
 /// Parametrized type sets are specified in terms of ranges:
 /// - The permitted range of vector lanes, where 1 indicates a scalar type.
+/// - The permitted range of the minimum lanes of dynamic vectors.
 /// - The permitted range of integer types.
 /// - The permitted range of floating point types, and
 /// - The permitted range of boolean types.
@@ -445,6 +457,7 @@ macro_rules! num_set {
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub(crate) struct TypeSet {
     pub lanes: NumSet,
+    pub dynamic_lanes: NumSet,
     pub ints: NumSet,
     pub floats: NumSet,
     pub bools: NumSet,
@@ -455,6 +468,7 @@ pub(crate) struct TypeSet {
 impl TypeSet {
     fn new(
         lanes: NumSet,
+        dynamic_lanes: NumSet,
         ints: NumSet,
         floats: NumSet,
         bools: NumSet,
@@ -463,6 +477,7 @@ impl TypeSet {
     ) -> Self {
         Self {
             lanes,
+            dynamic_lanes,
             ints,
             floats,
             bools,
@@ -475,6 +490,7 @@ impl TypeSet {
     pub fn size(&self) -> usize {
         self.lanes.len()
             * (self.ints.len() + self.floats.len() + self.bools.len() + self.refs.len())
+            + self.dynamic_lanes.len() * (self.ints.len() + self.floats.len() + self.bools.len())
             + self.specials.len()
     }
 
@@ -496,6 +512,7 @@ impl TypeSet {
     fn lane_of(&self) -> TypeSet {
         let mut copy = self.clone();
         copy.lanes = num_set![1];
+        copy.dynamic_lanes = NumSet::new();
         copy
     }
 
@@ -505,7 +522,7 @@ impl TypeSet {
         copy.ints = NumSet::new();
         copy.floats = NumSet::new();
         copy.refs = NumSet::new();
-        if !(&self.lanes - &num_set![1]).is_empty() {
+        if !(&self.lanes - &num_set![1]).is_empty() || !self.dynamic_lanes.is_empty() {
             copy.bools = &self.ints | &self.floats;
             copy.bools = &copy.bools | &self.bools;
         }
@@ -519,7 +536,7 @@ impl TypeSet {
     fn half_width(&self) -> TypeSet {
         let mut copy = self.clone();
         copy.ints = NumSet::from_iter(self.ints.iter().filter(|&&x| x > 8).map(|&x| x / 2));
-        copy.floats = NumSet::from_iter(self.floats.iter().filter(|&&x| x > 32).map(|&x| x / 2));
+        copy.floats = NumSet::from_iter(self.floats.iter().filter(|&&x| x > 16).map(|&x| x / 2));
         copy.bools = NumSet::from_iter(self.bools.iter().filter(|&&x| x > 8).map(|&x| x / 2));
         copy.specials = Vec::new();
         copy
@@ -550,6 +567,12 @@ impl TypeSet {
     fn half_vector(&self) -> TypeSet {
         let mut copy = self.clone();
         copy.lanes = NumSet::from_iter(self.lanes.iter().filter(|&&x| x > 1).map(|&x| x / 2));
+        copy.dynamic_lanes = NumSet::from_iter(
+            self.dynamic_lanes
+                .iter()
+                .filter(|&&x| x > 2)
+                .map(|&x| x / 2),
+        );
         copy.specials = Vec::new();
         copy
     }
@@ -563,6 +586,12 @@ impl TypeSet {
                 .filter(|&&x| x < MAX_LANES)
                 .map(|&x| x * 2),
         );
+        copy.dynamic_lanes = NumSet::from_iter(
+            self.dynamic_lanes
+                .iter()
+                .filter(|&&x| x < MAX_LANES)
+                .map(|&x| x * 2),
+        );
         copy.specials = Vec::new();
         copy
     }
@@ -583,6 +612,17 @@ impl TypeSet {
                 ret.push(ReferenceType::ref_from_bits(bits).into());
             }
         }
+        for &num_lanes in &self.dynamic_lanes {
+            for &bits in &self.ints {
+                ret.push(LaneType::int_from_bits(bits).to_dynamic(num_lanes));
+            }
+            for &bits in &self.floats {
+                ret.push(LaneType::float_from_bits(bits).to_dynamic(num_lanes));
+            }
+            for &bits in &self.bools {
+                ret.push(LaneType::bool_from_bits(bits).to_dynamic(num_lanes));
+            }
+        }
         for &special in &self.specials {
             ret.push(special.into());
         }
@@ -635,6 +675,7 @@ impl TypeSet {
 
     pub fn inplace_intersect_with(&mut self, other: &TypeSet) {
         self.lanes = &self.lanes & &other.lanes;
+        self.dynamic_lanes = &self.dynamic_lanes & &other.dynamic_lanes;
         self.ints = &self.ints & &other.ints;
         self.floats = &self.floats & &other.floats;
         self.bools = &self.bools & &other.bools;
@@ -651,6 +692,7 @@ impl TypeSet {
 
     pub fn is_subset(&self, other: &TypeSet) -> bool {
         self.lanes.is_subset(&other.lanes)
+            && self.dynamic_lanes.is_subset(&other.dynamic_lanes)
             && self.ints.is_subset(&other.ints)
             && self.floats.is_subset(&other.floats)
             && self.bools.is_subset(&other.bools)
@@ -696,6 +738,12 @@ impl fmt::Debug for TypeSet {
                 Vec::from_iter(self.lanes.iter().map(|x| x.to_string())).join(", ")
             ));
         }
+        if !self.dynamic_lanes.is_empty() {
+            subsets.push(format!(
+                "dynamic_lanes={{{}}}",
+                Vec::from_iter(self.dynamic_lanes.iter().map(|x| x.to_string())).join(", ")
+            ));
+        }
         if !self.ints.is_empty() {
             subsets.push(format!(
                 "ints={{{}}}",
@@ -739,6 +787,7 @@ pub(crate) struct TypeSetBuilder {
     refs: Interval,
     includes_scalars: bool,
     simd_lanes: Interval,
+    dynamic_simd_lanes: Interval,
     specials: Vec<SpecialType>,
 }
 
@@ -751,6 +800,7 @@ impl TypeSetBuilder {
             refs: Interval::None,
             includes_scalars: true,
             simd_lanes: Interval::None,
+            dynamic_simd_lanes: Interval::None,
             specials: Vec::new(),
         }
     }
@@ -784,6 +834,11 @@ impl TypeSetBuilder {
         self.simd_lanes = interval.into();
         self
     }
+    pub fn dynamic_simd_lanes(mut self, interval: impl Into<Interval>) -> Self {
+        assert!(self.dynamic_simd_lanes == Interval::None);
+        self.dynamic_simd_lanes = interval.into();
+        self
+    }
     pub fn specials(mut self, specials: Vec<SpecialType>) -> Self {
         assert!(self.specials.is_empty());
         self.specials = specials;
@@ -800,8 +855,14 @@ impl TypeSetBuilder {
 
         TypeSet::new(
             range_to_set(self.simd_lanes.to_range(min_lanes..MAX_LANES, Some(1))),
+            range_to_set(self.dynamic_simd_lanes.to_range(2..MAX_LANES, None)),
             range_to_set(self.ints.to_range(8..MAX_BITS, None)),
-            range_to_set(self.floats.to_range(32..64, None)),
+            // `f16` is only a storage format, so it isn't part of `Interval::All` and has to be
+            // requested with an explicit range.
+            range_to_set(match self.floats {
+                Interval::All => Some(32..64),
+                ref floats => floats.to_range(16..64, None),
+            }),
             bools,
             range_to_set(self.refs.to_range(32..64, None)),
             self.specials,
@@ -950,6 +1011,37 @@ fn test_typevar_builder_inverted_bounds_panic() {
     TypeSetBuilder::new().ints(32..16).build();
 }
 
+#[test]
+fn test_dynamic_lanes() {
+    let type_set = TypeSetBuilder::new()
+        .ints(32..32)
+        .simd_lanes(4..4)
+        .dynamic_simd_lanes(2..4)
+        .build();
+    assert_eq!(type_set.lanes, num_set![4]);
+    assert_eq!(type_set.dynamic_lanes, num_set![2, 4]);
+    assert_eq!(type_set.size(), 3);
+    assert_eq!(
+        type_set.lane_of(),
+        TypeSetBuilder::new().ints(32..32).build()
+    );
+    assert_eq!(
+        type_set.half_vector(),
+        TypeSetBuilder::new()
+            .ints(32..32)
+            .simd_lanes(2..2)
+            .dynamic_simd_lanes(2..2)
+            .build()
+    );
+
+    let dynamic = LaneType::int_from_bits(32).to_dynamic(4);
+    assert_eq!(dynamic.to_string(), "i32x4xN");
+    let singleton = TypeVar::new_singleton(dynamic.clone());
+    assert!(singleton.get_typeset().lanes.is_empty());
+    assert_eq!(singleton.get_typeset().dynamic_lanes, num_set![4]);
+    assert!(type_set.concrete_types().contains(&dynamic));
+}
+
 #[test]
 fn test_as_bool() {
     let a = TypeSetBuilder::new()
@@ -1012,12 +1104,16 @@ fn test_forward_images() {
         TypeSetBuilder::new().ints(8..16).build()
     );
     assert_eq!(
-        TypeSetBuilder::new().floats(32..32).build().half_width(),
+        TypeSetBuilder::new().floats(16..16).build().half_width(),
         empty_set
     );
+    assert_eq!(
+        TypeSetBuilder::new().floats(32..32).build().half_width(),
+        TypeSetBuilder::new().floats(16..16).build()
+    );
     assert_eq!(
         TypeSetBuilder::new().floats(32..64).build().half_width(),
-        TypeSetBuilder::new().floats(32..32).build()
+        TypeSetBuilder::new().floats(16..32).build()
     );
     assert_eq!(
         TypeSetBuilder::new().bools(1..8).build().half_width(),
@@ -1163,7 +1259,7 @@ fn test_backward_images() {
     assert_eq!(
         TypeSetBuilder::new()
             .ints(8..8)
-            .floats(32..32)
+            .floats(16..16)
             .bools(1..8)
             .build()
             .preimage(DerivedFunc::DoubleWidth)
@@ -1180,7 +1276,7 @@ fn test_backward_images() {
         TypeSetBuilder::new()
             .simd_lanes(1..16)
             .ints(8..8)
-            .floats(32..32)
+            .floats(16..32)
             .build()
     );
 }
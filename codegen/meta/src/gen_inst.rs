@@ -679,6 +679,9 @@ fn iterable_to_string<I: fmt::Display, T: IntoIterator<Item = I>>(iterable: T) -
 
 fn typeset_to_string(ts: &TypeSet) -> String {
     let mut result = format!("TypeSet(lanes={}", iterable_to_string(&ts.lanes));
+    if !ts.dynamic_lanes.is_empty() {
+        result += &format!(", dynamic_lanes={}", iterable_to_string(&ts.dynamic_lanes));
+    }
     if !ts.ints.is_empty() {
         result += &format!(", ints={}", iterable_to_string(&ts.ints));
     }
@@ -717,6 +720,7 @@ pub(crate) fn gen_typesets_table(type_sets: &UniqueTable<TypeSet>, fmt: &mut For
             fmt.indent(|fmt| {
                 fmt.comment(typeset_to_string(ts));
                 gen_bitset(&ts.lanes, "lanes", 16, fmt);
+                gen_bitset(&ts.dynamic_lanes, "dynamic_lanes", 16, fmt);
                 gen_bitset(&ts.ints, "ints", 8, fmt);
                 gen_bitset(&ts.floats, "floats", 8, fmt);
                 gen_bitset(&ts.bools, "bools", 8, fmt);
@@ -2,7 +2,8 @@
 //!
 //! This generates a `types.rs` file which is included in
 //! `cranelift-codegen/ir/types.rs`. The file provides constant definitions for the
-//! most commonly used types, including all of the scalar types.
+//! most commonly used types, including all of the scalar types and the common
+//! vector and dynamic vector types.
 //!
 //! This ensures that the metaprogram and the generated program see the same
 //! type numbering.
@@ -40,6 +41,21 @@ fn emit_vectors(bits: u64, fmt: &mut srcgen::Formatter) -> Result<(), error::Err
     Ok(())
 }
 
+/// Emit definition for all dynamic vector types with a minimum of `bits` total size.
+fn emit_dynamic_vectors(bits: u64, fmt: &mut srcgen::Formatter) -> Result<(), error::Error> {
+    let vec_size: u64 = bits / 8;
+    for vec in cdsl_types::ValueType::all_lane_types()
+        .map(|ty| (ty, cdsl_types::ValueType::from(ty).membytes()))
+        .filter(|&(_, lane_size)| lane_size != 0 && lane_size < vec_size)
+        .map(|(ty, lane_size)| (ty, vec_size / lane_size))
+        .map(|(ty, lanes)| cdsl_types::DynamicVectorType::new(ty, lanes))
+    {
+        emit_type(&cdsl_types::ValueType::from(vec), fmt)?;
+    }
+
+    Ok(())
+}
+
 /// Emit types using the given formatter object.
 fn emit_types(fmt: &mut srcgen::Formatter) -> Result<(), error::Error> {
     // Emit all of the special types, such as types for CPU flags.
@@ -62,6 +78,11 @@ fn emit_types(fmt: &mut srcgen::Formatter) -> Result<(), error::Error> {
         emit_vectors(*vec_size, fmt)?;
     }
 
+    // Emit dynamic vector definitions for the same minimum sizes.
+    for vec_size in &[64_u64, 128, 256, 512] {
+        emit_dynamic_vectors(*vec_size, fmt)?;
+    }
+
     Ok(())
 }
 
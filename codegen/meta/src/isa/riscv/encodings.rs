@@ -112,6 +112,11 @@ fn lui_bits() -> u16 {
     0b01101
 }
 
+fn system_bits(funct3: u16) -> u16 {
+    assert!(funct3 <= 0b111);
+    0b11100 | (funct3 << 5)
+}
+
 pub(crate) fn define<'defs>(
     shared_defs: &'defs SharedDefinitions,
     isa_settings: &SettingGroup,
@@ -136,6 +141,7 @@ pub(crate) fn define<'defs>(
     let copy_to_ssa = shared.by_name("copy_to_ssa");
     let fill = shared.by_name("fill");
     let fill_nop = shared.by_name("fill_nop");
+    let get_cycle_counter = shared.by_name("get_cycle_counter");
     let iadd = shared.by_name("iadd");
     let iadd_imm = shared.by_name("iadd_imm");
     let iconst = shared.by_name("iconst");
@@ -162,6 +168,7 @@ pub(crate) fn define<'defs>(
     let r_ii = recipes.by_name("Ii");
     let r_iicmp = recipes.by_name("Iicmp");
     let r_iret = recipes.by_name("Iret");
+    let r_irdcycle = recipes.by_name("Irdcycle");
     let r_irmov = recipes.by_name("Irmov");
     let r_iz = recipes.by_name("Iz");
     let r_gp_sp = recipes.by_name("GPsp");
@@ -312,6 +319,9 @@ pub(crate) fn define<'defs>(
             .isa_predicate(use_m),
     );
 
+    // The cycle counter is only a single register on RV64; RV32 would need rdcycleh as well.
+    e.add64(e.enc(get_cycle_counter, r_irdcycle, system_bits(0b010)));
+
     // Control flow.
 
     // Unconditional branches.
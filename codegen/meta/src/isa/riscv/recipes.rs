@@ -167,6 +167,13 @@ pub(crate) fn define(shared_defs: &SharedDefinitions, regs: &IsaRegs) -> RecipeG
             .emit("put_i(bits, in_reg0, 0, out_reg0, sink);"),
     );
 
+    // rdcycle is csrrs rd, cycle, x0, with the CSR number in the immediate field.
+    recipes.push(
+        EncodingRecipeBuilder::new("Irdcycle", &formats.nullary, 4)
+            .operands_out(vec![gpr])
+            .emit("put_i(bits, 0, 0xc00, out_reg0, sink);"),
+    );
+
     // Same for a GPR regmove.
     recipes.push(
         EncodingRecipeBuilder::new("Irmov", &formats.reg_move, 4)
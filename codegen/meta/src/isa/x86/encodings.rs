@@ -17,7 +17,7 @@ use crate::{
     shared::{
         types::{
             Bool::{B1, B16, B32, B64, B8},
-            Float::{F32, F64},
+            Float::{F16, F32, F64},
             Int::{I16, I32, I64, I8},
             Reference::{R32, R64},
         },
@@ -1781,7 +1781,9 @@ fn define_simd(
     // legalize.rs for how this is done; once there, x86_pshuf* (below) is used for broadcasting the
     // value across the register.
 
-    let allowed_simd_type = |t: &LaneType| t.lane_bits() >= 8 && t.lane_bits() < 128;
+    // `f16` is only a storage format, which isn't supported in vectors.
+    let allowed_simd_type =
+        |t: &LaneType| t.lane_bits() >= 8 && t.lane_bits() < 128 && *t != LaneType::from(F16);
 
     // PSHUFB, 8-bit shuffle using two XMM registers.
     for ty in ValueType::all_lane_types().filter(allowed_simd_type) {
@@ -1812,7 +1814,7 @@ fn define_simd(
     }
 
     // PBLENDW, select lanes using a u8 immediate.
-    for ty in ValueType::all_lane_types().filter(|t| t.lane_bits() == 16) {
+    for ty in ValueType::all_lane_types().filter(|t| allowed_simd_type(t) && t.lane_bits() == 16) {
         let instruction = x86_pblendw.bind(vector(ty, sse_vector_size));
         let template = rec_fa_ib.opcodes(&PBLENDW);
         e.enc_both_inferred_maybe_isap(instruction, template, Some(use_sse41_simd));
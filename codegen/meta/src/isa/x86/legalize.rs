@@ -7,7 +7,7 @@ use crate::{
     },
     shared::{
         types::{
-            Float::{F32, F64},
+            Float::{F16, F32, F64},
             Int::{I16, I32, I64, I8},
         },
         Definitions as SharedDefinitions,
@@ -462,7 +462,9 @@ fn define_simd(
     // Limit the SIMD vector size: eventually multiple vector sizes may be supported
     // but for now only SSE-sized vectors are available.
     let sse_vector_size: u64 = 128;
-    let allowed_simd_type = |t: &LaneType| t.lane_bits() >= 8 && t.lane_bits() < 128;
+    // `f16` is only a storage format, which isn't supported in vectors.
+    let allowed_simd_type =
+        |t: &LaneType| t.lane_bits() >= 8 && t.lane_bits() < 128 && *t != LaneType::from(F16);
 
     // SIMD splat: 8-bits
     for ty in ValueType::all_lane_types().filter(|t| t.lane_bits() == 8) {
@@ -482,7 +484,7 @@ fn define_simd(
     }
 
     // SIMD splat: 16-bits
-    for ty in ValueType::all_lane_types().filter(|t| t.lane_bits() == 16) {
+    for ty in ValueType::all_lane_types().filter(|t| allowed_simd_type(t) && t.lane_bits() == 16) {
         let splat_x16x8 = splat.bind(vector(ty, sse_vector_size));
         let raw_bitcast_any16x8_to_i32x4 = raw_bitcast
             .bind(vector(I32, sse_vector_size))
@@ -28,6 +28,18 @@ pub(crate) fn define(shared: &SettingGroup) -> SettingGroup {
         "SSE4.2: CPUID.01H:ECX.SSE4_2[bit 20]",
         false,
     );
+    let has_cmpxchg16b = settings.add_bool(
+        "has_cmpxchg16b",
+        "Has support for CMPXCHG16B.",
+        "CMPXCHG16B: CPUID.01H:ECX.CMPXCHG16B[bit 13]",
+        false,
+    );
+    let has_movbe = settings.add_bool(
+        "has_movbe",
+        "Has support for MOVBE.",
+        "MOVBE: CPUID.01H:ECX.MOVBE[bit 22]",
+        false,
+    );
     let has_avx = settings.add_bool(
         "has_avx",
         "Has support for AVX.",
@@ -40,6 +52,12 @@ pub(crate) fn define(shared: &SettingGroup) -> SettingGroup {
         "AVX2: CPUID.07H:EBX.AVX2[bit 5]",
         false,
     );
+    let has_f16c = settings.add_bool(
+        "has_f16c",
+        "Has support for F16C.",
+        "F16C: CPUID.01H:ECX.F16C[bit 29]",
+        false,
+    );
     let has_avx512bitalg = settings.add_bool(
         "has_avx512bitalg",
         "Has support for AVX512BITALG.",
@@ -141,9 +159,12 @@ pub(crate) fn define(shared: &SettingGroup) -> SettingGroup {
         predicate!(shared_enable_simd && has_avx512f),
     );
 
+    settings.add_predicate("use_cmpxchg16b", predicate!(has_cmpxchg16b));
+    settings.add_predicate("use_movbe", predicate!(has_movbe));
     settings.add_predicate("use_popcnt", predicate!(has_popcnt && has_sse42));
     settings.add_predicate("use_bmi1", predicate!(has_bmi1));
     settings.add_predicate("use_lzcnt", predicate!(has_lzcnt));
+    settings.add_predicate("use_f16c", predicate!(has_avx && has_f16c));
 
     // Some shared boolean values are used in x86 instruction predicates, so we need to group them
     // in the same TargetIsa, for compatibility with code generated by meta-python.
@@ -173,12 +194,12 @@ pub(crate) fn define(shared: &SettingGroup) -> SettingGroup {
     let nehalem = settings.add_preset(
         "nehalem",
         "Nehalem microarchitecture.",
-        preset!(has_sse3 && has_ssse3 && has_sse41 && has_sse42 && has_popcnt),
+        preset!(has_sse3 && has_ssse3 && has_sse41 && has_sse42 && has_popcnt && has_cmpxchg16b),
     );
     let haswell = settings.add_preset(
         "haswell",
         "Haswell microarchitecture.",
-        preset!(nehalem && has_bmi1 && has_bmi2 && has_lzcnt),
+        preset!(nehalem && has_bmi1 && has_bmi2 && has_lzcnt && has_movbe),
     );
     let broadwell = settings.add_preset(
         "broadwell",
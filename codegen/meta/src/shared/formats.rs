@@ -20,6 +20,7 @@ pub(crate) struct Formats {
     pub(crate) call: Rc<InstructionFormat>,
     pub(crate) call_indirect: Rc<InstructionFormat>,
     pub(crate) cond_trap: Rc<InstructionFormat>,
+    pub(crate) fence: Rc<InstructionFormat>,
     pub(crate) copy_special: Rc<InstructionFormat>,
     pub(crate) copy_to_ssa: Rc<InstructionFormat>,
     pub(crate) float_compare: Rc<InstructionFormat>,
@@ -33,6 +34,8 @@ pub(crate) struct Formats {
     pub(crate) int_cond: Rc<InstructionFormat>,
     pub(crate) int_cond_trap: Rc<InstructionFormat>,
     pub(crate) int_select: Rc<InstructionFormat>,
+    pub(crate) invoke: Rc<InstructionFormat>,
+    pub(crate) invoke_indirect: Rc<InstructionFormat>,
     pub(crate) jump: Rc<InstructionFormat>,
     pub(crate) load: Rc<InstructionFormat>,
     pub(crate) load_complex: Rc<InstructionFormat>,
@@ -206,6 +209,19 @@ impl Formats {
                 .varargs()
                 .build(),
 
+            invoke: Builder::new("Invoke")
+                .imm(&entities.func_ref)
+                .imm(&entities.block)
+                .varargs()
+                .build(),
+
+            invoke_indirect: Builder::new("InvokeIndirect")
+                .imm(&entities.sig_ref)
+                .value()
+                .imm(&entities.block)
+                .varargs()
+                .build(),
+
             func_addr: Builder::new("FuncAddr").imm(&entities.func_ref).build(),
 
             atomic_rmw: Builder::new("AtomicRmw")
@@ -223,6 +239,8 @@ impl Formats {
                 .typevar_operand(2)
                 .build(),
 
+            fence: Builder::new("Fence").imm(&imm.memory_ordering).build(),
+
             load: Builder::new("Load")
                 .imm(&imm.memflags)
                 .value()
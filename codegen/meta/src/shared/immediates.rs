@@ -74,6 +74,9 @@ pub(crate) struct Immediates {
 
     /// A code indicating the arithmetic operation to perform in an atomic_rmw memory access.
     pub atomic_rmw_op: OperandKind,
+
+    /// The ordering constraints of a memory fence.
+    pub memory_ordering: OperandKind,
 }
 
 fn new_imm(format_field_name: &'static str, rust_type: &'static str) -> OperandKind {
@@ -175,6 +178,15 @@ impl Immediates {
                 new_enum("op", "ir::AtomicRmwOp", atomic_rmw_op_values)
                     .with_doc("Atomic Read-Modify-Write Ops")
             },
+            memory_ordering: {
+                let mut memory_ordering_values = HashMap::new();
+                memory_ordering_values.insert("acquire", "Acquire");
+                memory_ordering_values.insert("release", "Release");
+                memory_ordering_values.insert("acq_rel", "AcqRel");
+                memory_ordering_values.insert("seq_cst", "SeqCst");
+                new_enum("ordering", "ir::MemoryOrdering", memory_ordering_values)
+                    .with_doc("A memory fence ordering")
+            },
         }
     }
 }
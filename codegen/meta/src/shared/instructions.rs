@@ -475,6 +475,127 @@ fn define_control_flow(
         .is_call(true),
     );
 
+    let FN = &Operand::new("FN", &entities.func_ref)
+        .with_doc("function to call, declared by `function`");
+    let args = &Operand::new("args", &entities.varargs).with_doc("call arguments");
+    ig.push(
+        Inst::new(
+            "return_call",
+            r#"
+        Direct tail call.
+
+        Call a function which has been declared in the preamble and return its
+        results from the current function, reusing the current function's
+        stack frame. The argument types must match the function's signature,
+        the callee must return the same types as the current function, and
+        both must use the same calling convention, which must support tail
+        calls.
+
+        This instruction is a block terminator.
+        "#,
+            &formats.call,
+        )
+        .operands_in(vec![FN, args])
+        .is_call(true)
+        .is_terminator(true),
+    );
+
+    let SIG = &Operand::new("SIG", &entities.sig_ref).with_doc("function signature");
+    let callee = &Operand::new("callee", iAddr).with_doc("address of function to call");
+    let args = &Operand::new("args", &entities.varargs).with_doc("call arguments");
+    ig.push(
+        Inst::new(
+            "return_call_indirect",
+            r#"
+        Indirect tail call.
+
+        Call the function pointed to by `callee` with the given arguments and
+        return its results from the current function, like `return_call`.
+        The called function must match the specified signature.
+
+        This instruction is a block terminator.
+        "#,
+            &formats.call_indirect,
+        )
+        .operands_in(vec![SIG, callee, args])
+        .is_call(true)
+        .is_terminator(true),
+    );
+
+    let FN = &Operand::new("FN", &entities.func_ref)
+        .with_doc("function to call, declared by `function`");
+    let landing_pad = &Operand::new("landing_pad", &entities.block)
+        .with_doc("Block to continue at when the callee unwinds");
+    let args = &Operand::new("args", &entities.varargs)
+        .with_doc("call arguments followed by landing pad arguments");
+    let rvals = &Operand::new("rvals", &entities.varargs).with_doc("return values");
+    ig.push(
+        Inst::new(
+            "invoke",
+            r#"
+        Direct function call with an exceptional successor.
+
+        Call a function which has been declared in the preamble, like `call`.
+        When the callee returns, execution continues with the next
+        instruction, which must be a `jump` to the normal successor. When an
+        exception unwinds the callee instead, execution continues at the
+        `landing_pad` block, and the results of the call are not defined.
+
+        `args` holds the call arguments, whose types must match the function's
+        signature, followed by the arguments of the landing pad block.
+        "#,
+            &formats.invoke,
+        )
+        .operands_in(vec![FN, landing_pad, args])
+        .operands_out(vec![rvals])
+        .is_call(true)
+        .is_branch(true),
+    );
+
+    let SIG = &Operand::new("SIG", &entities.sig_ref).with_doc("function signature");
+    let callee = &Operand::new("callee", iAddr).with_doc("address of function to call");
+    let args = &Operand::new("args", &entities.varargs)
+        .with_doc("call arguments followed by landing pad arguments");
+    let rvals = &Operand::new("rvals", &entities.varargs).with_doc("return values");
+    ig.push(
+        Inst::new(
+            "invoke_indirect",
+            r#"
+        Indirect function call with an exceptional successor.
+
+        Call the function pointed to by `callee`, like `call_indirect`, and
+        continue at the `landing_pad` block if an exception unwinds it, like
+        `invoke`.
+        "#,
+            &formats.invoke_indirect,
+        )
+        .operands_in(vec![SIG, callee, landing_pad, args])
+        .operands_out(vec![rvals])
+        .is_call(true)
+        .is_branch(true),
+    );
+
+    let exn = &Operand::new("exn", iAddr).with_doc("exception pointer");
+    let selector = &Operand::new("selector", iAddr).with_doc("handler selector");
+    ig.push(
+        Inst::new(
+            "landing_pad",
+            r#"
+        Get the exception payload in a landing pad.
+
+        The personality routine that transfers control to a landing pad hands
+        it two pointer-sized values, conventionally the exception object and
+        a selector telling which handler matched. This instruction must be
+        the first one of a landing pad, i.e. of a block that is only reached
+        through the exceptional successors of `invoke` and `invoke_indirect`
+        instructions.
+        "#,
+            &formats.nullary,
+        )
+        .operands_out(vec![exn, selector])
+        .other_side_effects(true),
+    );
+
     let FN = &Operand::new("FN", &entities.func_ref)
         .with_doc("function to call, declared by `function`");
     let addr = &Operand::new("addr", iAddr);
@@ -516,8 +637,22 @@ fn define_simd_lane_access(
             .build(),
     );
 
-    let x = &Operand::new("x", &TxN.lane_of()).with_doc("Value to splat to all lanes");
-    let a = &Operand::new("a", TxN);
+    // Unlike the lane accessors below, `splat` can also produce dynamic vectors.
+    let TxNDyn = &TypeVar::new(
+        "TxN",
+        "A SIMD vector type or a dynamic vector type",
+        TypeSetBuilder::new()
+            .ints(Interval::All)
+            .floats(Interval::All)
+            .bools(Interval::All)
+            .simd_lanes(Interval::All)
+            .dynamic_simd_lanes(Interval::All)
+            .includes_scalars(false)
+            .build(),
+    );
+
+    let x = &Operand::new("x", &TxNDyn.lane_of()).with_doc("Value to splat to all lanes");
+    let a = &Operand::new("a", TxNDyn);
 
     ig.push(
         Inst::new(
@@ -525,7 +660,8 @@ fn define_simd_lane_access(
             r#"
         Vector splat.
 
-        Return a vector whose lanes are all ``x``.
+        Return a vector whose lanes are all ``x``. This includes dynamic vectors, whose number
+        of lanes is only known at run time.
         "#,
             &formats.unary,
         )
@@ -533,6 +669,8 @@ fn define_simd_lane_access(
         .operands_out(vec![a]),
     );
 
+    let a = &Operand::new("a", TxN);
+
     let I8x16 = &TypeVar::new(
         "I8x16",
         "A SIMD vector type consisting of 16 lanes of 8-bit integers",
@@ -783,6 +921,7 @@ pub(crate) fn define(
     let b1: &TypeVar = &ValueType::from(LaneType::from(types::Bool::B1)).into();
     let f32_: &TypeVar = &ValueType::from(LaneType::from(types::Float::F32)).into();
     let f64_: &TypeVar = &ValueType::from(LaneType::from(types::Float::F64)).into();
+    let i64_: &TypeVar = &ValueType::from(LaneType::from(types::Int::I64)).into();
 
     // Starting definitions.
     let Int = &TypeVar::new(
@@ -861,8 +1000,9 @@ pub(crate) fn define(
         "Any type that can be stored in memory",
         TypeSetBuilder::new()
             .ints(Interval::All)
-            .floats(Interval::All)
+            .floats(16..64)
             .simd_lanes(Interval::All)
+            .dynamic_simd_lanes(Interval::All)
             .refs(Interval::All)
             .build(),
     );
@@ -1490,6 +1630,26 @@ pub(crate) fn define(
         .operands_out(vec![addr]),
     );
 
+    {
+        let used = &Operand::new("used", iAddr).with_doc("The number of bytes of stack used");
+        ig.push(
+            Inst::new(
+                "stack_used",
+                r#"
+        Get the number of bytes of stack used by the current function.
+
+        This is the distance from the stack pointer of the caller, right before
+        the call, down to the current stack pointer. It includes the return
+        address and the saved frame pointer, the stack frame of the function
+        (see ``CodeInfo::frame_size``), and the outgoing arguments of a call
+        being set up. It is only supported on the x86_64 and aarch64 backends.
+        "#,
+                &formats.nullary,
+            )
+            .operands_out(vec![used]),
+        );
+    }
+
     let GV = &Operand::new("GV", &entities.global_value);
 
     ig.push(
@@ -1588,6 +1748,67 @@ pub(crate) fn define(
         .other_side_effects(true),
     );
 
+    {
+        let a = &Operand::new("a", i64_).with_doc("The value of the cycle counter");
+        ig.push(
+            Inst::new(
+                "get_cycle_counter",
+                r#"
+        Read the CPU's cycle counter.
+
+        This is ``rdtsc`` on x86_64, the virtual counter ``cntvct_el0`` on
+        aarch64 and ``rdcycle`` on 64-bit RISC-V. How fast the counter ticks
+        depends on the CPU, so only differences between readings are
+        meaningful. The ``enable_cycle_counter`` setting must be enabled to use
+        this instruction.
+        "#,
+                &formats.nullary,
+            )
+            .operands_out(vec![a])
+            .other_side_effects(true),
+        );
+    }
+
+    {
+        let rights =
+            &Operand::new("rights", i64_).with_doc("The access rights of all the protection keys");
+        ig.push(
+            Inst::new(
+                "get_pkey_rights",
+                r#"
+        Read the access rights of the memory protection keys.
+
+        This is the ``PKRU`` register of Intel's memory protection keys on
+        x86_64, with an access-disable bit and a write-disable bit for each
+        key; its upper 32 bits are always zero. On aarch64, this is the
+        ``POR_EL0`` register of the permission overlay extension, with four
+        bits of permissions for each key. The ``enable_protection_keys``
+        setting must be enabled to use this instruction.
+        "#,
+                &formats.nullary,
+            )
+            .operands_out(vec![rights])
+            .other_side_effects(true),
+        );
+
+        ig.push(
+            Inst::new(
+                "set_pkey_rights",
+                r#"
+        Set the access rights of the memory protection keys.
+
+        ``rights`` is in the format described for ``get_pkey_rights``; on
+        x86_64, its upper 32 bits are ignored. The new rights apply to all the
+        memory accesses that follow. The ``enable_protection_keys`` setting
+        must be enabled to use this instruction.
+        "#,
+                &formats.unary,
+            )
+            .operands_in(vec![rights])
+            .other_side_effects(true),
+        );
+    }
+
     let TableOffset = &TypeVar::new(
         "TableOffset",
         "An unsigned table offset",
@@ -1844,7 +2065,34 @@ pub(crate) fn define(
         .other_side_effects(true),
     );
 
+    let x = &Operand::new("x", iAddr).with_doc("Value to use when `c` is true");
+    let y = &Operand::new("y", iAddr).with_doc("Value to use when `c` is false");
+    let a = &Operand::new("a", iAddr);
+
+    ig.push(
+        Inst::new(
+            "select_spectre_guard",
+            r#"
+            Conditional select intended for Spectre guards.
+
+            This operation is semantically equivalent to a select instruction,
+            with the same guarantees as `selectif_spectre_guard`: it is never
+            removed or altered by an optimization pass, and every backend lowers
+            it to a conditional-move instruction rather than a branch. Only
+            integer and reference values can be selected, since those are the
+            values every target can move conditionally.
+            "#,
+            &formats.ternary,
+        )
+        .operands_in(vec![c, x, y])
+        .operands_out(vec![a])
+        .other_side_effects(true),
+    );
+
     let c = &Operand::new("c", Any).with_doc("Controlling value to test");
+    let x = &Operand::new("x", Any).with_doc("Value to use when `c` is true");
+    let y = &Operand::new("y", Any).with_doc("Value to use when `c` is false");
+    let a = &Operand::new("a", Any);
     ig.push(
         Inst::new(
             "bitselect",
@@ -2374,9 +2622,20 @@ pub(crate) fn define(
         .operands_out(vec![f]),
     );
 
-    let a = &Operand::new("a", Int);
-    let x = &Operand::new("x", Int);
-    let y = &Operand::new("y", Int);
+    // Addition is one of the few operations that is also defined on dynamic vectors.
+    let IntDyn = &TypeVar::new(
+        "Int",
+        "A scalar, vector or dynamic vector integer type",
+        TypeSetBuilder::new()
+            .ints(Interval::All)
+            .simd_lanes(Interval::All)
+            .dynamic_simd_lanes(Interval::All)
+            .build(),
+    );
+
+    let a = &Operand::new("a", IntDyn);
+    let x = &Operand::new("x", IntDyn);
+    let y = &Operand::new("y", IntDyn);
 
     ig.push(
         Inst::new(
@@ -2393,6 +2652,10 @@ pub(crate) fn define(
         .operands_out(vec![a]),
     );
 
+    let a = &Operand::new("a", Int);
+    let x = &Operand::new("x", Int);
+    let y = &Operand::new("y", Int);
+
     ig.push(
         Inst::new(
             "isub",
@@ -3268,6 +3531,26 @@ pub(crate) fn define(
         .operands_out(vec![a]),
     );
 
+    let x = &Operand::new("x", iExt8);
+    let a = &Operand::new("a", iExt8);
+
+    ig.push(
+        Inst::new(
+            "bswap",
+            r#"
+        Reverse the byte order of an integer.
+
+        Reverses the bytes in ``x``.
+        "#,
+            &formats.unary,
+        )
+        .operands_in(vec![x])
+        .operands_out(vec![a]),
+    );
+
+    let x = &Operand::new("x", iB);
+    let a = &Operand::new("a", iB);
+
     ig.push(
         Inst::new(
             "clz",
@@ -3438,6 +3721,19 @@ pub(crate) fn define(
     let z = &Operand::new("z", Float);
     let a = &Operand::new("a", Float).with_doc("Result of applying operator to each lane");
 
+    let FloatDyn = &TypeVar::new(
+        "Float",
+        "A scalar, vector or dynamic vector floating point number",
+        TypeSetBuilder::new()
+            .floats(Interval::All)
+            .simd_lanes(Interval::All)
+            .dynamic_simd_lanes(Interval::All)
+            .build(),
+    );
+    let xd = &Operand::new("x", FloatDyn);
+    let yd = &Operand::new("y", FloatDyn);
+    let ad = &Operand::new("a", FloatDyn).with_doc("Result of applying operator to each lane");
+
     ig.push(
         Inst::new(
             "fadd",
@@ -3446,8 +3742,8 @@ pub(crate) fn define(
         "#,
             &formats.binary,
         )
-        .operands_in(vec![x, y])
-        .operands_out(vec![a]),
+        .operands_in(vec![xd, yd])
+        .operands_out(vec![ad]),
     );
 
     ig.push(
@@ -4168,15 +4464,16 @@ pub(crate) fn define(
         .constraints(vec![WiderOrEq(IntTo.clone(), Int.clone())]),
     );
 
-    let FloatTo = &TypeVar::new(
-        "FloatTo",
-        "A scalar or vector floating point number",
+    let FloatConv = &TypeVar::new(
+        "FloatConv",
+        "A scalar or vector floating point number, including the storage-only `f16`",
         TypeSetBuilder::new()
-            .floats(Interval::All)
+            .floats(16..64)
             .simd_lanes(Interval::All)
             .build(),
     );
-    let x = &Operand::new("x", Float);
+    let FloatTo = &TypeVar::copy_from(FloatConv, "FloatTo".to_string());
+    let x = &Operand::new("x", FloatConv);
     let a = &Operand::new("a", FloatTo);
 
     ig.push(
@@ -4188,8 +4485,9 @@ pub(crate) fn define(
         Each lane in `x` is converted to the destination floating point format.
         This is an exact operation.
 
-        Cranelift currently only supports two floating point formats
-        - `f32` and `f64`. This may change in the future.
+        Cranelift supports three floating point formats: `f16`, `f32` and
+        `f64`. `f16` is only a storage format, which has to be promoted before
+        doing arithmetic on it.
 
         The result type must have the same number of vector lanes as the input,
         and the result lanes must not have fewer bits than the input lanes. If
@@ -4199,7 +4497,7 @@ pub(crate) fn define(
         )
         .operands_in(vec![x])
         .operands_out(vec![a])
-        .constraints(vec![WiderOrEq(FloatTo.clone(), Float.clone())]),
+        .constraints(vec![WiderOrEq(FloatTo.clone(), FloatConv.clone())]),
     );
 
     ig.push(
@@ -4211,8 +4509,8 @@ pub(crate) fn define(
         Each lane in `x` is converted to the destination floating point format
         by rounding to nearest, ties to even.
 
-        Cranelift currently only supports two floating point formats
-        - `f32` and `f64`. This may change in the future.
+        Cranelift supports three floating point formats: `f16`, `f32` and
+        `f64`.
 
         The result type must have the same number of vector lanes as the input,
         and the result lanes must not have more bits than the input lanes. If
@@ -4222,7 +4520,7 @@ pub(crate) fn define(
         )
         .operands_in(vec![x])
         .operands_out(vec![a])
-        .constraints(vec![WiderOrEq(Float.clone(), FloatTo.clone())]),
+        .constraints(vec![WiderOrEq(FloatConv.clone(), FloatTo.clone())]),
     );
 
     let F64x2 = &TypeVar::new(
@@ -4483,10 +4781,18 @@ pub(crate) fn define(
     );
     let x = &Operand::new("x", AtomicMem).with_doc("Value to be atomically stored");
     let a = &Operand::new("a", AtomicMem).with_doc("Value atomically loaded");
-    let e = &Operand::new("e", AtomicMem).with_doc("Expected value in CAS");
     let p = &Operand::new("p", iAddr);
+    let AtomicCasMem = &TypeVar::new(
+        "AtomicCasMem",
+        "Any type that can be used in an atomic compare-and-swap operation",
+        TypeSetBuilder::new().ints(8..128).build(),
+    );
+    let cas_x = &Operand::new("x", AtomicCasMem).with_doc("Value to be atomically stored");
+    let cas_a = &Operand::new("a", AtomicCasMem).with_doc("Value atomically loaded");
+    let cas_e = &Operand::new("e", AtomicCasMem).with_doc("Expected value in CAS");
     let MemFlags = &Operand::new("MemFlags", &imm.memflags);
     let AtomicRmwOp = &Operand::new("AtomicRmwOp", &imm.atomic_rmw_op);
+    let MemoryOrdering = &Operand::new("MemoryOrdering", &imm.memory_ordering);
 
     ig.push(
         Inst::new(
@@ -4515,15 +4821,16 @@ pub(crate) fn define(
         storing `x` if the value at `p` equals `e`.  The old value at `p` is returned,
         regardless of whether the operation succeeds or fails.  `p` has the type of the target
         word size, and `x` and `e` must have the same type and the same size, which may be an
-        integer type of 8, 16, 32 or 64 bits, even on a 32-bit target.  The type of the returned
-        value is the same as the type of `x` and `e`.  This operation is sequentially
-        consistent and creates happens-before edges that order normal (non-atomic) loads and
-        stores.
+        integer type of 8, 16, 32, 64 or 128 bits, even on a 32-bit target.  A 128-bit access
+        must be 16-byte aligned, and targets without a 128-bit compare-and-swap instruction
+        call the `AtomicCasI128` library routine instead.  The type of the returned value is
+        the same as the type of `x` and `e`.  This operation is sequentially consistent and
+        creates happens-before edges that order normal (non-atomic) loads and stores.
         "#,
             &formats.atomic_cas,
         )
-        .operands_in(vec![MemFlags, p, e, x])
-        .operands_out(vec![a])
+        .operands_in(vec![MemFlags, p, cas_e, cas_x])
+        .operands_out(vec![cas_a])
         .can_load(true)
         .can_store(true)
         .other_side_effects(true),
@@ -4570,14 +4877,69 @@ pub(crate) fn define(
         Inst::new(
             "fence",
             r#"
-        A memory fence.  This must provide ordering to ensure that, at a minimum, neither loads
-        nor stores of any kind may move forwards or backwards across the fence.  This operation
-        is sequentially consistent.
+        A memory fence, ordering the loads and stores around it as described by `MemoryOrdering`:
+
+        - `acquire`: no load or store after the fence may move before a load before it.
+        - `release`: no load or store before the fence may move after a store after it.
+        - `acq_rel`: both of the above.
+        - `seq_cst`: neither loads nor stores of any kind may move forwards or backwards across
+          the fence, and the fence is sequentially consistent.
+
+        Each target uses the cheapest barrier that provides the requested ordering, which may
+        be no instruction at all.
         "#,
-            &formats.nullary,
+            &formats.fence,
         )
+        .operands_in(vec![MemoryOrdering])
         .other_side_effects(true),
     );
 
+    // Annotations, which only convey facts about the program to the optimizer.
+    let x = &Operand::new("x", Testable);
+    let y = &Operand::new("y", Testable);
+
+    ig.push(
+        Inst::new(
+            "assert_eq",
+            r#"
+        Assert that `x` and `y` are equal.
+
+        This documents an invariant of the program for the benefit of readers
+        of the IR. Like `assume`, it is removed after legalization and compiles
+        to nothing. In debug builds, compilation fails if the value range
+        analysis proves that the operands differ.
+        "#,
+            &formats.binary,
+        )
+        .operands_in(vec![x, y])
+        .other_side_effects(true)
+        .is_ghost(true),
+    );
+
+    let Cond = &Operand::new("Cond", &imm.intcc);
+    let x = &Operand::new("x", iB);
+    let Y = &Operand::new("Y", &imm.imm64);
+    let a = &Operand::new("a", iB).with_doc("`x`, known to satisfy the condition");
+
+    ig.push(
+        Inst::new(
+            "assume",
+            r#"
+        Assume that the comparison `x Cond Y` holds.
+
+        Returns `x`, which the value range analysis then knows to satisfy the
+        comparison, so that the optimizations relying on it can make use of the
+        fact. The frontend is responsible for the fact to actually hold: the
+        instruction is removed after legalization and compiles to nothing. In
+        debug builds, compilation fails if the value range analysis proves that
+        the comparison can't hold.
+        "#,
+            &formats.int_compare_imm,
+        )
+        .operands_in(vec![Cond, x, Y])
+        .operands_out(vec![a])
+        .is_ghost(true),
+    );
+
     ig.build()
 }
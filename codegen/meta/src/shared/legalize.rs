@@ -53,6 +53,7 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
     let band_not = insts.by_name("band_not");
     let bint = insts.by_name("bint");
     let bitrev = insts.by_name("bitrev");
+    let bswap = insts.by_name("bswap");
     let bnot = insts.by_name("bnot");
     let bor = insts.by_name("bor");
     let bor_imm = insts.by_name("bor_imm");
@@ -135,7 +136,6 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
 
     // Custom expansions for memory objects.
     expand.custom_legalize(insts.by_name("global_value"), "expand_global_value");
-    expand.custom_legalize(insts.by_name("heap_addr"), "expand_heap_addr");
     expand.custom_legalize(insts.by_name("table_addr"), "expand_table_addr");
 
     // Custom expansions for calls.
@@ -149,6 +149,10 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
     expand.custom_legalize(br_table, "expand_br_table");
     expand.custom_legalize(select, "expand_select");
     widen.custom_legalize(select, "expand_select"); // small ints
+    expand.custom_legalize(
+        insts.by_name("select_spectre_guard"),
+        "expand_select_spectre_guard",
+    );
 
     // Custom expansions for floating point constants.
     // These expansions require bit-casting or creating constant pool entries.
@@ -408,14 +412,75 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
         );
     }
 
+    // Immediate operands of 128-bit instructions are materialized as a full constant, which
+    // `narrow_iconst` splits, and shift amounts as a 64-bit constant.
+    for &(inst_imm, inst) in &[
+        (iadd_imm, iadd),
+        (imul_imm, imul),
+        (sdiv_imm, sdiv),
+        (udiv_imm, udiv),
+        (srem_imm, srem),
+        (urem_imm, urem),
+        (band_imm, band),
+        (bor_imm, bor),
+        (bxor_imm, bxor),
+    ] {
+        narrow.legalize(
+            def!(a = inst_imm.I128(x, y)),
+            vec![def!(a1 = iconst.I128(y)), def!(a = inst(x, a1))],
+        );
+    }
+
+    for &(inst_imm, inst) in &[
+        (rotl_imm, rotl),
+        (rotr_imm, rotr),
+        (ishl_imm, ishl),
+        (sshr_imm, sshr),
+        (ushr_imm, ushr),
+    ] {
+        narrow.legalize(
+            def!(a = inst_imm.I128(x, y)),
+            vec![def!(a1 = iconst.I64(y)), def!(a = inst(x, a1))],
+        );
+    }
+
+    for &op in &[ishl, ushr, sshr] {
+        narrow.custom_legalize(op, "narrow_shift");
+    }
+    for &op in &[rotl, rotr] {
+        narrow.custom_legalize(op, "narrow_rotate");
+    }
+
+    narrow.legalize(
+        def!(a = bint.I128(b)),
+        vec![def!(x = bint.I64(b)), def!(a = uextend.I128(x))],
+    );
+
     let zero = Literal::constant(&imm.imm64, 0);
+    let sixty_four = Literal::constant(&imm.imm64, 64);
+    narrow.legalize(
+        def!(a = clz.I128(x)),
+        vec![
+            def!((xl, xh) = isplit(x)),
+            def!(e1 = clz(xl)),
+            def!(e2 = iadd_imm(e1, sixty_four)),
+            def!(e3 = clz(xh)),
+            def!(c = icmp_imm(intcc_eq, xh, zero)),
+            def!(e4 = select(c, e2, e3)),
+            def!(a = uextend(e4)),
+        ],
+    );
+
     narrow.legalize(
-        def!(a = iadd_imm.I128(x, c)),
+        def!(a = ctz.I128(x)),
         vec![
-            def!(yh = iconst.I64(zero)),
-            def!(yl = iconst.I64(c)),
-            def!(y = iconcat.I64(yh, yl)),
-            def!(a = iadd(x, y)),
+            def!((xl, xh) = isplit(x)),
+            def!(e1 = ctz(xh)),
+            def!(e2 = iadd_imm(e1, sixty_four)),
+            def!(e3 = ctz(xl)),
+            def!(c = icmp_imm(intcc_eq, xl, zero)),
+            def!(e4 = select(c, e2, e3)),
+            def!(a = uextend(e4)),
         ],
     );
 
@@ -936,6 +1001,66 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
         ],
     );
 
+    //# Expand bswap
+    widen.legalize(
+        def!(a = bswap.I16(x)),
+        vec![
+            def!(a1 = band_imm(x, Literal::constant(&imm.imm64, 0xff00))),
+            def!(a2 = ushr_imm(a1, imm64_8)),
+            def!(a3 = band_imm(x, Literal::constant(&imm.imm64, 0x00ff))),
+            def!(a4 = ishl_imm(a3, imm64_8)),
+            def!(a = bor(a2, a4)),
+        ],
+    );
+
+    expand.legalize(
+        def!(a = bswap.I32(x)),
+        vec![
+            def!(a1 = band_imm(x, Literal::constant(&imm.imm64, 0xff00_ff00))),
+            def!(a2 = ushr_imm(a1, imm64_8)),
+            def!(a3 = band_imm(x, Literal::constant(&imm.imm64, 0x00ff_00ff))),
+            def!(a4 = ishl_imm(a3, imm64_8)),
+            def!(b = bor(a2, a4)),
+            def!(b1 = ushr_imm(b, imm64_16)),
+            def!(b2 = ishl_imm(b, imm64_16)),
+            def!(a = bor(b1, b2)),
+        ],
+    );
+
+    expand.legalize(
+        def!(a = bswap.I64(x)),
+        vec![
+            def!(a1 = band_imm(x, imm64_0xff00ff00ff00ff00)),
+            def!(a2 = ushr_imm(a1, imm64_8)),
+            def!(a3 = band_imm(x, imm64_0x00ff00ff00ff00ff)),
+            def!(a4 = ishl_imm(a3, imm64_8)),
+            def!(b = bor(a2, a4)),
+            def!(b1 = band_imm(b, imm64_0xffff0000ffff0000)),
+            def!(b2 = ushr_imm(b1, imm64_16)),
+            def!(b3 = band_imm(b, imm64_0x0000ffff0000ffff)),
+            def!(b4 = ishl_imm(b3, imm64_16)),
+            def!(c = bor(b2, b4)),
+            def!(c1 = ushr_imm(c, imm64_32)),
+            def!(c2 = ishl_imm(c, imm64_32)),
+            def!(a = bor(c1, c2)),
+        ],
+    );
+
+    //# Expand cls
+    //# Xoring with the sign turns all the leading sign bits into zeros, including the sign bit
+    //# itself which isn't counted.
+    for &(int_ty, sign_shift) in &[(I32, 31), (I64, 63)] {
+        expand.legalize(
+            def!(a = cls.int_ty(x)),
+            vec![
+                def!(a1 = sshr_imm(x, Literal::constant(&imm.imm64, sign_shift))),
+                def!(a2 = bxor(x, a1)),
+                def!(a3 = clz(a2)),
+                def!(a = iadd_imm(a3, minus_one)),
+            ],
+        );
+    }
+
     // Floating-point sign manipulations.
     for &(ty, const_inst, minus_zero) in &[
         (F32, f32const, &Literal::bits(&imm.ieee32, 0x8000_0000)),
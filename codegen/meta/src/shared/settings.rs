@@ -59,6 +59,112 @@ pub(crate) fn define() -> SettingGroup {
         true,
     );
 
+    settings.add_bool(
+        "deterministic_regalloc",
+        "Make register allocation independent of the host and the toolchain.",
+        r#"
+            By default, the register allocator breaks some ties in ways that may differ between
+            the hosts and the versions of the standard library that Cranelift runs on, which
+            yields different, equally valid, machine code. Enable this for reproducible builds,
+            at the cost of slightly slower register allocation.
+        "#,
+        false,
+    );
+
+    settings.add_bool(
+        "preserve_block_order",
+        "Emit the blocks in the order of the function layout, with their branches as written.",
+        r#"
+            By default, the blocks are reordered to make fallthroughs more frequent, and
+            branches are removed, inverted or redirected to skip empty blocks. Enable this
+            for code that follows the IR, which makes single-stepping through generated code
+            and diffing it between builds of a frontend easier, at the cost of slower code.
+        "#,
+        false,
+    );
+
+    settings.add_enum(
+        "return_shape",
+        "Merge the returns of functions into a single exit block, or split them apart.",
+        r#"
+            This selects the shape of the return points of a function that the passes after
+            the first one see.
+
+            Possible values:
+
+            - `unchanged` keeps the returns as in the IR.
+            - `single_exit` replaces every `return` with a jump to a single exit block, which
+            gives instrumentation a single place to hook into and lets the epilogue be
+            shrink-wrapped.
+            - `direct_returns` replaces the jumps to blocks that do nothing but return with
+            returns of their own, which saves a jump on each path and usually some code size.
+
+            Functions with a `fallthrough_return` are left unchanged.
+        "#,
+        vec!["unchanged", "single_exit", "direct_returns"],
+    );
+
+    settings.add_num(
+        "regalloc_fuel_per_inst",
+        "Bound the effort of the backtracking register allocator, per instruction.",
+        r#"
+            The backtracking register allocator can take a long time on pathological
+            functions. When this is non-zero, it gives up after processing this many live
+            ranges per instruction of the function, and the function is allocated by the
+            linear scan allocator instead, which bounds the time register allocation takes.
+            0 means no bound.
+        "#,
+        0,
+    );
+
+    settings.add_num(
+        "regalloc_pressure_headroom",
+        "Number of registers per register class that spilling tries to keep free.",
+        r#"
+            Note: this only applies to the legacy register allocator, which lowers register
+            pressure by spilling before it colors the values.
+
+            When this is non-zero, the spilling pass keeps this many registers of each
+            top-level register class free of live values where it can, by spilling more
+            values. An instruction whose operands and results don't fit otherwise still uses
+            the free registers. The coloring pass then has more choices left when it resolves
+            register constraints, which makes it faster and needs fewer copies, at the cost of
+            more spills. At least one register of each class is always available.
+        "#,
+        0,
+    );
+
+    settings.add_enum(
+        "regalloc_spill_aggressiveness",
+        "How carefully the spilling pass picks the values to spill.",
+        r#"
+            Note: this only applies to the legacy register allocator.
+
+            Possible values:
+
+            - `eager` spills the value defined earliest, without looking at its uses. This is
+            fast, but the spilled value may be needed again right away.
+            - `conservative` looks ahead in the current block and spills the value whose next
+            use there is furthest away, preferring values not used in the block at all. This
+            takes longer on large blocks, but it usually needs fewer reloads, and targets with
+            few registers spill less often.
+        "#,
+        vec!["eager", "conservative"],
+    );
+
+    settings.add_bool(
+        "regalloc_large_functions",
+        "Let the register allocator accept very large functions.",
+        r#"
+            By default, the register allocator rejects functions with a million blocks or 16
+            million instructions, so as to keep a safety margin in the indices of its internal
+            structures. Enable this for very large machine-generated functions, which are then
+            allowed up to 256 million blocks and instructions, and take a lot of memory to
+            allocate.
+        "#,
+        false,
+    );
+
     // Note that Cranelift doesn't currently need an is_pie flag, because PIE is
     // just PIC where symbols can't be pre-empted, which can be expressed with the
     // `colocated` flag on external functions and global values.
@@ -139,6 +245,28 @@ pub(crate) fn define() -> SettingGroup {
         false,
     );
 
+    settings.add_bool(
+        "enable_cycle_counter",
+        "Enable the use of the get_cycle_counter instruction.",
+        r#"
+            This lets generated code read the CPU's cycle counter directly instead of calling
+            into the host. It is disabled by default because the operating system may deny user
+            code access to the counter, in which case reading it traps.
+        "#,
+        false,
+    );
+
+    settings.add_bool(
+        "enable_protection_keys",
+        "Enable the use of the get_pkey_rights and set_pkey_rights instructions.",
+        r#"
+            These instructions switch the access rights of memory protection keys, so that code
+            can be restricted to the memory tagged with some of the keys. Executing them on a CPU
+            without protection keys raises an illegal instruction exception.
+        "#,
+        false,
+    );
+
     settings.add_bool(
         "enable_simd",
         "Enable the use of SIMD instructions.",
@@ -153,6 +281,19 @@ pub(crate) fn define() -> SettingGroup {
         true,
     );
 
+    settings.add_bool(
+        "single_threaded",
+        "Assume that the generated code never runs concurrently with other threads.",
+        r#"
+            Embedders may set this for targets that only ever run a single thread, such as
+            many microcontrollers. Atomic memory operations are then turned into plain loads
+            and stores with the same memory flags, so they trap in the same way, and fences
+            are removed. This avoids atomic instruction encodings that the target may not
+            support or that are slow.
+        "#,
+        false,
+    );
+
     settings.add_bool(
         "enable_safepoints",
         "Enable safepoint instruction insertions.",
@@ -167,10 +308,36 @@ pub(crate) fn define() -> SettingGroup {
     settings.add_enum(
         "tls_model",
         "Defines the model used to perform TLS accesses.",
-        "",
+        r#"
+            Under `elf_gd`, symbols accessed through a `tls_symbol` global value may
+            instead request the initial-exec model, which avoids the call to
+            `__tls_get_addr` but requires the symbol to be resolvable at load time.
+        "#,
         vec!["none", "elf_gd", "macho", "coff"],
     );
 
+    settings.add_enum(
+        "trap_encoding",
+        "Defines the machine code emitted for trap instructions.",
+        r#"
+            Every trap is recorded in the trap table whatever its encoding, so this only matters
+            to how the trap is delivered to the embedder at run time. It applies to the MachInst
+            backends only.
+
+            Possible values:
+
+            - `undefined` emits an undefined instruction (`ud2` on x86_64, `udf` on aarch64),
+            which raises an illegal instruction signal.
+            - `breakpoint` emits a breakpoint instruction (`int3` on x86_64, `brk` on aarch64),
+            which stops in an attached debugger or raises a trap signal. On aarch64 the `brk`
+            immediate carries the trap code.
+            - `call_handler` emits a call to the `TrapHandler` libcall, for runtimes that don't
+            rely on signal handlers. The handler takes no arguments; the trap site is the
+            return address minus the size of the call instruction.
+        "#,
+        vec!["undefined", "breakpoint", "call_handler"],
+    );
+
     // Settings specific to the `baldrdash` calling convention.
 
     settings.add_enum(
@@ -290,13 +457,32 @@ pub(crate) fn define() -> SettingGroup {
         "The log2 of the size of the stack guard region.",
         r#"
             Stack frames larger than this size will have stack overflow checked
-            by calling the probestack function.
+            by probing each page of the frame, as selected by
+            `probestack_strategy`.
 
             The default is 12, which translates to a size of 4096.
         "#,
         12,
     );
 
+    settings.add_enum(
+        "probestack_strategy",
+        "Controls what kinds of stack probes are emitted.",
+        r#"
+            Supported strategies:
+
+            - `outline`: Always emits stack probes as calls to the probestack
+              libcall.
+            - `inline`: Always emits inline stack probes, touching each page of
+              the frame in turn. No runtime support is needed.
+
+            The `inline` strategy is only supported by the new x86-64 and
+            AArch64 backends; the others refuse to compile functions when stack
+            probes are enabled with it.
+        "#,
+        vec!["outline", "inline"],
+    );
+
     // Jump table options.
 
     settings.add_bool(
@@ -306,6 +492,47 @@ pub(crate) fn define() -> SettingGroup {
         true,
     );
 
+    settings.add_enum(
+        "jump_table_entries",
+        "Select the encoding of jump table entries.",
+        r#"
+            - `relative` stores each entry as a 32-bit offset from the start of
+              the table, so the table needs no relocations.
+            - `absolute` stores each entry as a 64-bit address and emits an
+              `Abs8` relocation against the function itself for it.
+
+            Absolute entries are only supported by the x64 backend; the others
+            refuse to compile functions containing jump tables with it.
+        "#,
+        vec!["relative", "absolute"],
+    );
+
+    // Memory operation options.
+
+    settings.add_num(
+        "max_inline_memory_ops",
+        "Maximum number of loads and stores a constant-size memory operation is expanded into.",
+        r#"
+            Frontends expand small constant-size copies and fills into a sequence of loads
+            and stores instead of calling `memcpy`, `memmove` or `memset`. Operations that
+            would need more accesses than this are turned into a libcall instead. Setting
+            this to 0 always calls the library function.
+        "#,
+        4,
+    );
+
+    settings.add_bool(
+        "allow_unaligned_memory_ops",
+        "Allow inline memory operations to use accesses wider than the pointer alignment.",
+        r#"
+            When enabled, an expanded memory operation uses the widest access that divides
+            its size, whatever the alignment declared for its pointers. Disable this for
+            targets that trap on unaligned accesses; accesses are then never wider than
+            the smallest declared alignment, which may need more of them.
+        "#,
+        true,
+    );
+
     // Spectre options.
 
     settings.add_bool(
@@ -8,18 +8,24 @@
 // 0x70-0x7d: Lane types
 // 0x7e-0x7f: Reference types
 // 0x80-0xff: Vector types
+// 0x100-0x17f: Dynamic vector types
 //
 // Vector types are encoded with the lane type in the low 4 bits and log2(lanes)
-// in the high 4 bits, giving a range of 2-256 lanes.
+// in the high 4 bits, giving a range of 2-256 lanes. Dynamic vector types are
+// numbered like the vector types of their minimum number of lanes, offset by
+// `DYNAMIC_VECTOR_BASE - VECTOR_BASE`.
 
 /// Start of the lane types.
-pub const LANE_BASE: u8 = 0x70;
+pub const LANE_BASE: u16 = 0x70;
 
 /// Base for reference types.
-pub const REFERENCE_BASE: u8 = 0x7E;
+pub const REFERENCE_BASE: u16 = 0x7E;
 
 /// Start of the 2-lane vector types.
-pub const VECTOR_BASE: u8 = 0x80;
+pub const VECTOR_BASE: u16 = 0x80;
+
+/// Start of the dynamic vector types, with a minimum of 2 lanes.
+pub const DYNAMIC_VECTOR_BASE: u16 = 0x100;
 
 // Some constants about register classes and types.
 
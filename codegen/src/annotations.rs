@@ -0,0 +1,157 @@
+//! Removal of the annotation instructions.
+//!
+//! The `assert_eq` and `assume` instructions let frontends convey facts about the program: the
+//! former documents an invariant, the latter narrows the range of a value for the value range
+//! analysis. Neither computes anything, so they are removed once the passes that may use the
+//! facts have run, and compile to nothing.
+//!
+//! In debug builds, the facts are checked against the value range analysis as the instructions
+//! are removed, and a fact that provably can't hold is reported as a verifier error.
+
+use crate::cursor::{Cursor, FuncCursor};
+use crate::ir::{Function, InstructionData};
+use crate::result::CodegenResult;
+use crate::timing;
+#[cfg(debug_assertions)]
+use crate::{
+    ir::condcodes::IntCC,
+    value_range::{ValueRange, ValueRanges},
+    verifier::VerifierErrors,
+};
+#[cfg(debug_assertions)]
+use alloc::string::ToString;
+
+/// Remove the annotations of `func`, replacing the results of `assume` instructions with their
+/// arguments.
+pub fn do_strip_annotations(func: &mut Function) -> CodegenResult<()> {
+    let _tt = timing::strip_annotations();
+
+    #[cfg(debug_assertions)]
+    check_annotations(func)?;
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(_block) = pos.next_block() {
+        while let Some(inst) = pos.next_inst() {
+            if !pos.func.dfg[inst].opcode().is_annotation() {
+                continue;
+            }
+            if let InstructionData::IntCompareImm { arg, .. } = pos.func.dfg[inst] {
+                let result = pos.func.dfg.first_result(inst);
+                pos.func.dfg.clear_results(inst);
+                pos.func.dfg.change_to_alias(result, arg);
+            }
+            pos.remove_inst_and_step_back();
+        }
+    }
+    Ok(())
+}
+
+/// Check that no annotation of `func` states a fact that the value range analysis disproves.
+#[cfg(debug_assertions)]
+fn check_annotations(func: &Function) -> CodegenResult<()> {
+    let mut ranges = ValueRanges::new();
+    let mut errors = VerifierErrors::new();
+    for block in func.layout.blocks() {
+        for inst in func.layout.block_insts(block) {
+            let (x, cond, y) = match func.dfg[inst] {
+                InstructionData::Binary { opcode, args } if opcode.is_annotation() => {
+                    match ranges.get(&func.dfg, args[1]) {
+                        Some(y) => (args[0], IntCC::Equal, y),
+                        None => continue,
+                    }
+                }
+                InstructionData::IntCompareImm {
+                    opcode,
+                    cond,
+                    arg,
+                    imm,
+                } if opcode.is_annotation() => {
+                    let bits = func.dfg.value_type(arg).bits() as u8;
+                    let imm: i64 = imm.into();
+                    (arg, cond, ValueRange::constant(bits, imm as u64))
+                }
+                _ => continue,
+            };
+            if let Some(x) = ranges.get(&func.dfg, x) {
+                if x.compare(cond, y) == Some(false) {
+                    errors.report((
+                        inst,
+                        func.dfg.display_inst(inst, None).to_string(),
+                        "the annotated fact can't hold",
+                    ));
+                }
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::do_strip_annotations;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::condcodes::IntCC;
+    use crate::ir::{types, Function, InstBuilder};
+    use alloc::string::ToString;
+
+    #[test]
+    fn strip() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+
+        let mut cur = FuncCursor::new(&mut func);
+        cur.insert_block(block0);
+        let small = cur.ins().assume(IntCC::UnsignedLessThan, x, 16);
+        let sum = cur.ins().iadd(small, x);
+        let copy = cur.ins().band_imm(x, 0xff);
+        cur.ins().assert_eq(copy, x);
+        cur.ins().return_(&[sum]);
+
+        do_strip_annotations(&mut func).unwrap();
+        assert_eq!(
+            func.display(None).to_string(),
+            "function u0:0() fast {\n\
+             block0(v0: i32):\n    \
+             v1 -> v0\n    \
+             v2 = iadd v1, v0\n    \
+             v3 = band_imm v0, 255\n    \
+             return v2\n\
+             }\n"
+        );
+        assert_eq!(func.dfg.resolve_aliases(small), x);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn disproved_annotations() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+
+        let mut cur = FuncCursor::new(&mut func);
+        cur.insert_block(block0);
+        let byte = cur.ins().band_imm(x, 0xff);
+        cur.ins().assume(IntCC::UnsignedGreaterThan, byte, 0x100);
+        cur.ins().assume(IntCC::UnsignedGreaterThan, x, 0x100);
+        let big = cur.ins().iconst(types::I32, 0x1000);
+        cur.ins().assert_eq(byte, big);
+        cur.ins().assert_eq(x, big);
+        cur.ins().return_(&[]);
+
+        match do_strip_annotations(&mut func) {
+            Err(crate::CodegenError::Verifier(errors)) => {
+                assert_eq!(errors.0.len(), 2);
+                assert_eq!(
+                    errors.0[0].to_string(),
+                    "inst1 (v2 = assume.i32 ugt v1, 256): the annotated fact can't hold"
+                );
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
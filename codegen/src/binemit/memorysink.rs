@@ -13,9 +13,10 @@
 //! that a `MemoryCodeSink` will always write binary machine code to raw memory. It forwards any
 //! relocations to a `RelocSink` trait object. Relocations are less frequent than the
 //! `CodeSink::put*` methods, so the performance impact of the virtual callbacks is less severe.
-use super::{Addend, CodeInfo, CodeOffset, CodeSink, Reloc};
+use super::{Addend, CodeInfo, CodeOffset, CodeSink, CodeStats, Reloc};
 use crate::{
     binemit::stack_map::StackMap,
+    budget::SkippedPasses,
     ir::{
         entities::Value, ConstantOffset, ExternalName, Function, JumpTable, Opcode, SourceLoc,
         TrapCode,
@@ -68,6 +69,9 @@ impl<'a> MemoryCodeSink<'a> {
                 jumptables_size: 0,
                 rodata_size: 0,
                 total_size: 0,
+                frame_size: 0,
+                stats: CodeStats::default(),
+                skipped_passes: SkippedPasses::default(),
             },
             relocs,
             traps,
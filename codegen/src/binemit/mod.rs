@@ -19,11 +19,12 @@ pub use self::{
 };
 pub use crate::regalloc::RegDiversions;
 use crate::{
+    budget::SkippedPasses,
     ir::{
         entities::Value, ConstantOffset, ExternalName, Function, Inst, JumpTable, Opcode,
         SourceLoc, TrapCode,
     },
-    isa::TargetIsa,
+    isa::{DataLayout, TargetIsa},
 };
 use core::fmt;
 #[cfg(feature = "enable-serde")]
@@ -70,8 +71,28 @@ pub enum Reloc {
     /// Elf x86_64 32 bit signed PC relative offset to two GOT entries for GD symbol.
     ElfX86_64TlsGd,
 
+    /// Elf x86_64 32 bit signed PC relative offset to the GOT entry holding the thread pointer
+    /// offset of an IE symbol.
+    ElfX86_64GotTpOff,
+
     /// Mach-O x86_64 32 bit signed PC relative offset to a `__thread_vars` entry.
     MachOX86_64Tlv,
+
+    /// AArch64 GD TLS: page of the GOT entries for a symbol, in an `adrp`
+    /// (R_AARCH64_TLSGD_ADR_PAGE21).
+    Aarch64TlsGdAdrPage21,
+
+    /// AArch64 GD TLS: low 12 bits of the address of the GOT entries for a symbol, in an `add`
+    /// (R_AARCH64_TLSGD_ADD_LO12_NC).
+    Aarch64TlsGdAddLo12Nc,
+
+    /// AArch64 IE TLS: page of the GOT entry holding the thread pointer offset of a symbol, in an
+    /// `adrp` (R_AARCH64_TLSIE_ADR_GOTTPREL_PAGE21).
+    Aarch64TlsIeAdrGotTpRelPage21,
+
+    /// AArch64 IE TLS: low 12 bits of the address of the GOT entry holding the thread pointer
+    /// offset of a symbol, in an `ldr` (R_AARCH64_TLSIE_LD64_GOTTPREL_LO12_NC).
+    Aarch64TlsIeLd64GotTpRelLo12Nc,
 }
 
 impl fmt::Display for Reloc {
@@ -90,7 +111,12 @@ impl fmt::Display for Reloc {
             Self::Arm32Call | Self::Arm64Call | Self::RiscvCall => write!(f, "Call"),
 
             Self::ElfX86_64TlsGd => write!(f, "ElfX86_64TlsGd"),
+            Self::ElfX86_64GotTpOff => write!(f, "ElfX86_64GotTpOff"),
             Self::MachOX86_64Tlv => write!(f, "MachOX86_64Tlv"),
+            Self::Aarch64TlsGdAdrPage21 => write!(f, "Aarch64TlsGdAdrPage21"),
+            Self::Aarch64TlsGdAddLo12Nc => write!(f, "Aarch64TlsGdAddLo12Nc"),
+            Self::Aarch64TlsIeAdrGotTpRelPage21 => write!(f, "Aarch64TlsIeAdrGotTpRelPage21"),
+            Self::Aarch64TlsIeLd64GotTpRelLo12Nc => write!(f, "Aarch64TlsIeLd64GotTpRelLo12Nc"),
         }
     }
 }
@@ -113,6 +139,36 @@ pub struct CodeInfo {
 
     /// Number of bytes in total.
     pub total_size: CodeOffset,
+
+    /// Number of bytes of the stack frame of the function: its stack slots, spill slots and
+    /// saved registers. This doesn't count the return address, the saved frame pointer, nor the
+    /// outgoing arguments of calls; the old x86 backend counts all of them, though.
+    pub frame_size: u32,
+
+    /// Statistics of the machine code, see `CodeStats`.
+    pub stats: CodeStats,
+
+    /// Optional passes that were skipped because the compilation budget was exhausted.
+    pub skipped_passes: SkippedPasses,
+}
+
+/// Statistics of the machine code of a function, to spot regressions in the quality of the
+/// generated code without disassembling it.
+///
+/// Only the `MachInst` backends compute them; they are all zero with the old backends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CodeStats {
+    /// Number of machine instructions, including the prologue and epilogues.
+    pub insts: u32,
+
+    /// Number of spills inserted by the register allocator.
+    pub spills: u32,
+
+    /// Number of reloads inserted by the register allocator.
+    pub reloads: u32,
+
+    /// Number of moves between registers inserted by the register allocator.
+    pub moves: u32,
 }
 
 impl CodeInfo {
@@ -208,13 +264,7 @@ where
     sink.begin_jumptables();
 
     // Output jump tables.
-    for (jt, jt_data) in func.jump_tables.iter() {
-        let jt_offset = func.jt_offsets[jt];
-        for block in jt_data.iter() {
-            let rel_offset: i32 = func.offsets[*block] as i32 - jt_offset as i32;
-            sink.put4(rel_offset as u32)
-        }
-    }
+    emit_jump_tables(func, isa.data_layout(), sink);
 
     sink.begin_rodata();
 
@@ -227,3 +277,66 @@ where
 
     sink.end_codegen();
 }
+
+/// Emit the jump tables of `func` to `sink`. Their entries are the offsets of the targets
+/// relative to the start of the table, stored in the byte order of the target rather than the
+/// one of the sink.
+fn emit_jump_tables<CS: CodeSink>(func: &Function, data_layout: DataLayout, sink: &mut CS) {
+    for (jt, jt_data) in func.jump_tables.iter() {
+        let jt_offset = func.jt_offsets[jt];
+        for block in jt_data.iter() {
+            let rel_offset: i32 = func.offsets[*block] as i32 - jt_offset as i32;
+            let mut entry = [0; 4];
+            data_layout.write_uint(u64::from(rel_offset as u32), &mut entry);
+            for byte in entry.iter() {
+                sink.put1(*byte)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Endianness, JumpTableData};
+    use target_lexicon::PointerWidth;
+
+    /// Emit the jump tables of a function whose single table follows two blocks of 8 bytes, to
+    /// a target of the given byte order.
+    fn jump_table_bytes(endianness: Endianness) -> [u8; 8] {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        let mut jt_data = JumpTableData::new();
+        jt_data.push_entry(block1);
+        jt_data.push_entry(block0);
+        let jt = func.create_jump_table(jt_data);
+        func.offsets[block0] = 0;
+        func.offsets[block1] = 8;
+        func.jt_offsets[jt] = 16;
+
+        let mut bytes = [0; 8];
+        let mut relocs = NullRelocSink {};
+        let mut traps = NullTrapSink {};
+        let mut stack_maps = NullStackMapSink {};
+        let mut sink = unsafe {
+            MemoryCodeSink::new(bytes.as_mut_ptr(), &mut relocs, &mut traps, &mut stack_maps)
+        };
+        let data_layout = DataLayout::new(PointerWidth::U64, endianness);
+        emit_jump_tables(&func, data_layout, &mut sink);
+        assert_eq!(sink.offset(), 8);
+        bytes
+    }
+
+    #[test]
+    fn jump_tables_in_target_byte_order() {
+        assert_eq!(
+            jump_table_bytes(Endianness::Little),
+            [0xf8, 0xff, 0xff, 0xff, 0xf0, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            jump_table_bytes(Endianness::Big),
+            [0xff, 0xff, 0xff, 0xf8, 0xff, 0xff, 0xff, 0xf0]
+        );
+    }
+}
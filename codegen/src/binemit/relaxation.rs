@@ -28,7 +28,8 @@
 //! ```
 
 use crate::{
-    binemit::{CodeInfo, CodeOffset},
+    binemit::{CodeInfo, CodeOffset, CodeStats},
+    budget::SkippedPasses,
     cursor::{Cursor, FuncCursor},
     dominator_tree::DominatorTree,
     flowgraph::ControlFlowGraph,
@@ -58,8 +59,10 @@ pub fn relax_branches(
     func.offsets.clear();
     func.offsets.resize(func.dfg.num_blocks());
 
-    // Start by removing redundant jumps.
-    fold_redundant_jumps(func, _cfg, _domtree);
+    // Start by removing redundant jumps, unless every jump is to be kept as written.
+    if !isa.flags().preserve_block_order() {
+        fold_redundant_jumps(func, _cfg, _domtree);
+    }
 
     // Convert jumps to fallthrough instructions where possible.
     fallthroughs(func);
@@ -146,6 +149,12 @@ pub fn relax_branches(
         jumptables_size,
         rodata_size,
         total_size: offset,
+        frame_size: func
+            .stack_slots
+            .layout_info
+            .map_or(0, |layout| layout.frame_size),
+        stats: CodeStats::default(),
+        skipped_passes: SkippedPasses::default(),
     })
 }
 
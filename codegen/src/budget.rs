@@ -0,0 +1,445 @@
+//! Compilation budgets.
+//!
+//! A [`CompileBudget`] installed on a [`Context`](crate::Context) is consulted around every pass
+//! that `Context::compile` runs. Once the budget reports that it is exhausted, the remaining
+//! optional passes are skipped and recorded in the returned
+//! [`CodeInfo`](crate::binemit::CodeInfo). Passes that are needed to produce correct code always
+//! run, so a function that blows its budget still compiles, just with less optimization.
+
+use crate::ir::Function;
+use crate::settings::{Flags, SettingKind};
+use core::fmt;
+
+/// A pass run by `Context::compile` that a budget is consulted about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompilePass {
+    /// Merging or splitting of the returns of the function.
+    CanonicalizeReturns,
+    /// Pre-legalization rewriting.
+    Preopt,
+    /// Narrowing of 64-bit arithmetic.
+    Narrow,
+    /// Rewriting of the induction variables of loops.
+    InductionVars,
+    /// Canonicalization of NaNs.
+    CanonicalizeNans,
+    /// Legalization.
+    Legalize,
+    /// Removal of the `assert_eq` and `assume` annotations.
+    StripAnnotations,
+    /// Post-legalization rewriting.
+    Postopt,
+    /// Loop invariant code motion.
+    Licm,
+    /// Global value numbering.
+    SimpleGvn,
+    /// Removal of unreachable blocks.
+    UnreachableCode,
+    /// Late control flow graph cleanup.
+    SimplifyBranches,
+    /// Dead code elimination.
+    Dce,
+    /// Removal of constant phi-nodes.
+    RemoveConstantPhis,
+    /// Lowering, register allocation and emission by a `MachBackend`.
+    MachBackend,
+    /// Register allocation.
+    Regalloc,
+    /// Prologue/epilogue insertion.
+    PrologueEpilogue,
+    /// Redundant reload removal.
+    RedundantReloadRemover,
+    /// Instruction encoding shrinking.
+    ShrinkInstructions,
+    /// Branch relaxation.
+    RelaxBranches,
+}
+
+/// A shared setting that must have one of the given values for a pass to run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SettingDependency {
+    /// The name of the setting.
+    pub name: &'static str,
+    /// The values of the setting that enable the pass, as accepted by `Configurable::set`.
+    pub values: &'static [&'static str],
+}
+
+/// The kind of ISA backend a pass runs with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PassBackend {
+    /// The pass runs with every ISA.
+    Any,
+    /// The pass only runs with ISAs that have a `MachBackend`.
+    MachInst,
+    /// The pass only runs with ISAs that use the legacy encoding-based backend.
+    Legacy,
+}
+
+const OPTIMIZING: &[SettingDependency] = &[SettingDependency {
+    name: "opt_level",
+    values: &["speed", "speed_and_size"],
+}];
+
+impl CompilePass {
+    /// All passes, in the order `Context::compile` runs them.
+    const ALL: [Self; 20] = [
+        Self::CanonicalizeReturns,
+        Self::Preopt,
+        Self::Narrow,
+        Self::InductionVars,
+        Self::CanonicalizeNans,
+        Self::Legalize,
+        Self::StripAnnotations,
+        Self::Postopt,
+        Self::Licm,
+        Self::SimpleGvn,
+        Self::UnreachableCode,
+        Self::SimplifyBranches,
+        Self::Dce,
+        Self::RemoveConstantPhis,
+        Self::MachBackend,
+        Self::Regalloc,
+        Self::PrologueEpilogue,
+        Self::RedundantReloadRemover,
+        Self::ShrinkInstructions,
+        Self::RelaxBranches,
+    ];
+
+    /// Iterate over all passes, in the order `Context::compile` runs them.
+    pub fn all() -> impl Iterator<Item = Self> {
+        Self::ALL.iter().copied()
+    }
+
+    /// A short description of the pass.
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::CanonicalizeReturns => "Return canonicalization",
+            Self::Preopt => "Pre-legalization rewriting",
+            Self::Narrow => "Narrowing of 64-bit arithmetic",
+            Self::InductionVars => "Induction variable rewriting",
+            Self::CanonicalizeNans => "NaN canonicalization",
+            Self::Legalize => "Legalization",
+            Self::StripAnnotations => "Removal of annotations",
+            Self::Postopt => "Post-legalization rewriting",
+            Self::Licm => "Loop invariant code motion",
+            Self::SimpleGvn => "Global value numbering",
+            Self::UnreachableCode => "Remove unreachable blocks",
+            Self::SimplifyBranches => "Branch simplification",
+            Self::Dce => "Dead code elimination",
+            Self::RemoveConstantPhis => "Remove constant phi-nodes",
+            Self::MachBackend => "Lowering, register allocation and emission",
+            Self::Regalloc => "Register allocation",
+            Self::PrologueEpilogue => "Prologue/epilogue insertion",
+            Self::RedundantReloadRemover => "Redundant reload removal",
+            Self::ShrinkInstructions => "Instruction encoding shrinking",
+            Self::RelaxBranches => "Branch relaxation",
+        }
+    }
+
+    /// The shared settings this pass depends on: it only runs if every one of them has one of
+    /// its listed values.
+    pub fn setting_dependencies(self) -> &'static [SettingDependency] {
+        match self {
+            Self::CanonicalizeReturns => &[SettingDependency {
+                name: "return_shape",
+                values: &["single_exit", "direct_returns"],
+            }],
+            Self::CanonicalizeNans => &[SettingDependency {
+                name: "enable_nan_canonicalization",
+                values: &["true"],
+            }],
+            Self::ShrinkInstructions => &[SettingDependency {
+                name: "opt_level",
+                values: &["speed_and_size"],
+            }],
+            pass if pass.is_optional() => OPTIMIZING,
+            _ => &[],
+        }
+    }
+
+    /// The kind of backend this pass runs with.
+    pub fn backend(self) -> PassBackend {
+        match self {
+            Self::MachBackend => PassBackend::MachInst,
+            Self::Regalloc
+            | Self::PrologueEpilogue
+            | Self::RedundantReloadRemover
+            | Self::ShrinkInstructions
+            | Self::RelaxBranches => PassBackend::Legacy,
+            _ => PassBackend::Any,
+        }
+    }
+
+    /// Does `Context::compile` run this pass with the given shared settings, assuming the ISA's
+    /// backend is one the pass runs with?
+    pub fn is_enabled(self, flags: &Flags) -> bool {
+        self.setting_dependencies().iter().all(|dep| {
+            flags.iter().any(|value| {
+                value.name == dep.name
+                    && dep.values.iter().any(|&expected| match value.kind() {
+                        SettingKind::Bool => value.as_bool() == Some(expected == "true"),
+                        SettingKind::Enum => value.as_enum() == Some(expected),
+                        SettingKind::Num => value.as_num() == expected.parse().ok(),
+                        SettingKind::Preset => false,
+                    })
+            })
+        })
+    }
+
+    /// Is this pass an optimization that may be skipped when the budget is exhausted?
+    ///
+    /// These are exactly the passes that aren't run at `opt_level=none`.
+    pub fn is_optional(self) -> bool {
+        match self {
+            Self::Preopt
+            | Self::Narrow
+            | Self::InductionVars
+            | Self::Postopt
+            | Self::Licm
+            | Self::SimpleGvn
+            | Self::SimplifyBranches
+            | Self::Dce
+            | Self::RedundantReloadRemover
+            | Self::ShrinkInstructions => true,
+            Self::CanonicalizeReturns
+            | Self::CanonicalizeNans
+            | Self::Legalize
+            | Self::StripAnnotations
+            | Self::UnreachableCode
+            | Self::RemoveConstantPhis
+            | Self::MachBackend
+            | Self::Regalloc
+            | Self::PrologueEpilogue
+            | Self::RelaxBranches => false,
+        }
+    }
+}
+
+impl fmt::Display for CompilePass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::CanonicalizeReturns => "canonicalize_returns",
+            Self::Preopt => "preopt",
+            Self::Narrow => "narrow",
+            Self::InductionVars => "induction_vars",
+            Self::CanonicalizeNans => "canonicalize_nans",
+            Self::Legalize => "legalize",
+            Self::StripAnnotations => "strip_annotations",
+            Self::Postopt => "postopt",
+            Self::Licm => "licm",
+            Self::SimpleGvn => "simple_gvn",
+            Self::UnreachableCode => "unreachable_code",
+            Self::SimplifyBranches => "simplify_branches",
+            Self::Dce => "dce",
+            Self::RemoveConstantPhis => "remove_constant_phis",
+            Self::MachBackend => "mach_backend",
+            Self::Regalloc => "regalloc",
+            Self::PrologueEpilogue => "prologue_epilogue",
+            Self::RedundantReloadRemover => "redundant_reload_remover",
+            Self::ShrinkInstructions => "shrink_instructions",
+            Self::RelaxBranches => "relax_branches",
+        })
+    }
+}
+
+/// The set of optional passes skipped while compiling a function because its budget was
+/// exhausted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SkippedPasses(u32);
+
+impl SkippedPasses {
+    /// Were no passes skipped?
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Was `pass` skipped?
+    pub fn contains(self, pass: CompilePass) -> bool {
+        self.0 & (1 << pass as u32) != 0
+    }
+
+    /// Iterate over the skipped passes, in the order they would have run.
+    pub fn iter(self) -> impl Iterator<Item = CompilePass> {
+        CompilePass::ALL
+            .iter()
+            .copied()
+            .filter(move |&pass| self.contains(pass))
+    }
+
+    pub(crate) fn insert(&mut self, pass: CompilePass) {
+        self.0 |= 1 << pass as u32;
+    }
+}
+
+/// Hooks through which an embedder bounds the work spent compiling a single function.
+///
+/// The budget is consulted by `Context::compile`: `start_compile` is called once per function,
+/// and `start_pass` and `end_pass` around every pass that runs. Skipped passes aren't reported.
+pub trait CompileBudget: Send {
+    /// Compilation of `func` is starting; reset any per-function state.
+    fn start_compile(&mut self, _func: &Function) {}
+
+    /// `pass` is about to run on `func`.
+    fn start_pass(&mut self, _pass: CompilePass, _func: &Function) {}
+
+    /// `pass` has finished running on `func`. Returns `true` if the budget is exhausted, in which
+    /// case all remaining optional passes are skipped for this function.
+    fn end_pass(&mut self, pass: CompilePass, func: &Function) -> bool;
+}
+
+/// A budget on the size of a function: it is exhausted as soon as the function has more than a
+/// given number of instructions after some pass.
+#[derive(Clone, Copy, Debug)]
+pub struct InstBudget {
+    max_insts: usize,
+}
+
+impl InstBudget {
+    /// Create a budget that is exhausted by functions with more than `max_insts` instructions.
+    pub fn new(max_insts: usize) -> Self {
+        Self { max_insts }
+    }
+}
+
+impl CompileBudget for InstBudget {
+    fn end_pass(&mut self, _pass: CompilePass, func: &Function) -> bool {
+        let num_insts: usize = func
+            .layout
+            .blocks()
+            .map(|block| func.layout.block_insts(block).count())
+            .sum();
+        num_insts > self.max_insts
+    }
+}
+
+#[cfg(feature = "std")]
+pub use self::time::TimeBudget;
+
+#[cfg(feature = "std")]
+mod time {
+    use super::{CompileBudget, CompilePass};
+    use crate::ir::Function;
+    use std::time::{Duration, Instant};
+
+    /// A budget on the time spent compiling a function.
+    ///
+    /// The budget is exhausted once the time since compilation started exceeds the total limit,
+    /// or a single pass exceeds the per-pass limit, if one is set.
+    #[derive(Clone, Copy, Debug)]
+    pub struct TimeBudget {
+        total: Duration,
+        per_pass: Option<Duration>,
+        compile_start: Instant,
+        pass_start: Instant,
+    }
+
+    impl TimeBudget {
+        /// Create a budget of `total` for compiling each function.
+        pub fn new(total: Duration) -> Self {
+            let now = Instant::now();
+            Self {
+                total,
+                per_pass: None,
+                compile_start: now,
+                pass_start: now,
+            }
+        }
+
+        /// Also limit the time spent in any single pass to `per_pass`.
+        pub fn with_pass_limit(mut self, per_pass: Duration) -> Self {
+            self.per_pass = Some(per_pass);
+            self
+        }
+    }
+
+    impl CompileBudget for TimeBudget {
+        fn start_compile(&mut self, _func: &Function) {
+            self.compile_start = Instant::now();
+        }
+
+        fn start_pass(&mut self, _pass: CompilePass, _func: &Function) {
+            self.pass_start = Instant::now();
+        }
+
+        fn end_pass(&mut self, _pass: CompilePass, _func: &Function) -> bool {
+            let now = Instant::now();
+            now - self.compile_start > self.total
+                || self
+                    .per_pass
+                    .map_or(false, |limit| now - self.pass_start > limit)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{self, Configurable};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn skipped_passes() {
+        let mut skipped = SkippedPasses::default();
+        assert!(skipped.is_empty());
+        skipped.insert(CompilePass::ShrinkInstructions);
+        skipped.insert(CompilePass::Licm);
+        assert!(!skipped.is_empty());
+        assert!(skipped.contains(CompilePass::Licm));
+        assert!(!skipped.contains(CompilePass::Dce));
+        assert_eq!(
+            skipped.iter().collect::<Vec<_>>(),
+            [CompilePass::Licm, CompilePass::ShrinkInstructions]
+        );
+    }
+
+    #[test]
+    fn all_passes() {
+        for (i, pass) in CompilePass::ALL.iter().enumerate() {
+            assert_eq!(*pass as usize, i);
+        }
+    }
+
+    #[test]
+    fn setting_dependencies_are_valid() {
+        let builder = settings::builder();
+        for pass in CompilePass::all() {
+            for dep in pass.setting_dependencies() {
+                for value in dep.values {
+                    assert_eq!(builder.validate(dep.name, value), Ok(()), "{}", pass);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn enabled_passes() {
+        let enabled = |flags: &Flags| {
+            CompilePass::all()
+                .filter(|pass| pass.backend() != PassBackend::Legacy && pass.is_enabled(flags))
+                .collect::<Vec<_>>()
+        };
+
+        let flags = Flags::new(settings::builder());
+        assert_eq!(
+            enabled(&flags),
+            [
+                CompilePass::Legalize,
+                CompilePass::StripAnnotations,
+                CompilePass::UnreachableCode,
+                CompilePass::RemoveConstantPhis,
+                CompilePass::MachBackend,
+            ]
+        );
+        assert!(!CompilePass::ShrinkInstructions.is_enabled(&flags));
+
+        let mut builder = settings::builder();
+        builder.set("opt_level", "speed").unwrap();
+        builder.enable("enable_nan_canonicalization").unwrap();
+        let flags = Flags::new(builder);
+        assert!(CompilePass::all()
+            .filter(|pass| pass.is_optional())
+            .all(|pass| pass.is_enabled(&flags) == (pass != CompilePass::ShrinkInstructions)));
+        assert!(CompilePass::CanonicalizeNans.is_enabled(&flags));
+        assert!(!CompilePass::CanonicalizeReturns.is_enabled(&flags));
+    }
+}
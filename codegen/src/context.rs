@@ -10,14 +10,17 @@
 //! single ISA instance.
 
 use crate::{
+    annotations::do_strip_annotations,
     binemit::{
         relax_branches, shrink_instructions, CodeInfo, MemoryCodeSink, RelocSink, StackMapSink,
         TrapSink,
     },
+    budget::{CompileBudget, CompilePass, SkippedPasses},
     dce::do_dce,
     dominator_tree::DominatorTree,
     flowgraph::ControlFlowGraph,
-    ir::Function,
+    induction_vars::do_induction_vars,
+    ir::{Block, Function, Inst, Value},
     isa::TargetIsa,
     legalize_function,
     legalizer::simple_legalize,
@@ -25,14 +28,17 @@ use crate::{
     loop_analysis::LoopAnalysis,
     machinst::{MachCompileResult, MachStackMap},
     nan_canonicalization::do_nan_canonicalization,
+    narrow::do_narrow,
     postopt::do_postopt,
     redundant_reload_remover::RedundantReloadRemover,
-    regalloc,
+    regalloc::{self, SafepointLocations},
     remove_constant_phis::do_remove_constant_phis,
-    result::CodegenResult,
-    settings::{FlagsOrIsa, OptLevel},
+    result::{CodegenError, CodegenResult},
+    returns::{do_merge_returns, do_split_returns},
+    settings::{FlagsOrIsa, JumpTableEntries, OptLevel, ReturnShape},
     simple_gvn::do_simple_gvn,
     simple_preopt::do_preopt,
+    simplify_branches::do_simplify_branches,
     timing,
     unreachable_code::eliminate_unreachable_code,
     value_label::{build_value_labels_ranges, ComparableSourceLoc, ValueLabelsRanges},
@@ -40,7 +46,7 @@ use crate::{
 };
 #[cfg(feature = "souper-harvest")]
 use alloc::string::String;
-use alloc::vec::Vec;
+use alloc::{boxed::Box, vec::Vec};
 use log::debug;
 
 #[cfg(feature = "souper-harvest")]
@@ -71,6 +77,16 @@ pub struct Context {
 
     /// Flag: do we want a disassembly with the MachCompileResult?
     pub want_disasm: bool,
+
+    /// Budget consulted around every pass of `compile`, if any. Unlike the other fields, it is
+    /// kept by `clear`, so that it applies to every function compiled with this context.
+    pub budget: Option<Box<dyn CompileBudget>>,
+
+    /// Has the budget been exhausted while compiling the current function?
+    budget_exhausted: bool,
+
+    /// Optional passes skipped while compiling the current function.
+    skipped_passes: SkippedPasses,
 }
 
 impl Context {
@@ -96,6 +112,9 @@ impl Context {
             redundant_reload_remover: RedundantReloadRemover::new(),
             mach_compile_result: None,
             want_disasm: false,
+            budget: None,
+            budget_exhausted: false,
+            skipped_passes: SkippedPasses::default(),
         }
     }
 
@@ -109,6 +128,8 @@ impl Context {
         self.redundant_reload_remover.clear();
         self.mach_compile_result = None;
         self.want_disasm = false;
+        self.budget_exhausted = false;
+        self.skipped_passes = SkippedPasses::default();
     }
 
     /// Set the flag to request a disassembly when compiling with a
@@ -117,6 +138,48 @@ impl Context {
         self.want_disasm = val;
     }
 
+    /// Enable or disable the splitting of branches before register allocation with the old
+    /// backends. It is enabled by default, and again after `clear`.
+    ///
+    /// Branch splitting inserts a block on the edges where the register allocator may need to
+    /// insert copies: those of the conditional branches followed by a jump, when either of them
+    /// goes to a block with parameters or with several predecessors. Frontends that never
+    /// produce such branches can skip it; the verifier then rejects functions that needed it.
+    pub fn set_branch_splitting(&mut self, val: bool) {
+        self.regalloc.set_branch_splitting(val);
+    }
+
+    /// The blocks inserted by branch splitting in the last function compiled with the old
+    /// backends, one per split edge. Branch relaxation removes the empty ones from the layout
+    /// again.
+    pub fn split_blocks(&self) -> &[Block] {
+        self.regalloc.split_blocks()
+    }
+
+    /// Request the locations of `values` on entry to `inst` when compiling with the old backends,
+    /// e.g. to deoptimize the function there or to take a snapshot of its state in a debugger.
+    ///
+    /// The locations are recorded after register allocation, for the values live on entry to
+    /// `inst`, in `value_locations`. `inst` must survive legalization, as calls do. The requests
+    /// are kept until `clear`.
+    pub fn request_value_locations(&mut self, inst: Inst, values: &[Value]) {
+        self.regalloc.request_value_locations(inst, values);
+    }
+
+    /// The locations recorded for the last function compiled, one entry per instruction given to
+    /// `request_value_locations` that was still in the function, in layout order.
+    pub fn value_locations(&self) -> &[SafepointLocations] {
+        self.regalloc.value_locations()
+    }
+
+    /// Set the budget consulted while compiling functions with this context.
+    ///
+    /// Once the budget is exhausted, `compile` skips the remaining optional passes and reports
+    /// them in `CodeInfo::skipped_passes`.
+    pub fn set_budget(&mut self, budget: Box<dyn CompileBudget>) {
+        self.budget = Some(budget);
+    }
+
     /// Compile the function, and emit machine code into a `Vec<u8>`.
     ///
     /// Run the function through all the passes necessary to generate code for the target ISA
@@ -170,52 +233,132 @@ impl Context {
             self.func.display(isa)
         );
 
+        self.budget_exhausted = false;
+        self.skipped_passes = SkippedPasses::default();
+        if let Some(budget) = &mut self.budget {
+            budget.start_compile(&self.func);
+        }
+
         self.compute_cfg();
+        if isa.flags().return_shape() != ReturnShape::Unchanged {
+            self.run_pass(CompilePass::CanonicalizeReturns, |ctx| {
+                ctx.canonicalize_returns(isa)
+            })?;
+        }
         if opt_level != OptLevel::None {
-            self.preopt(isa)?;
+            self.run_optional_pass(CompilePass::Preopt, |ctx| ctx.preopt(isa))?;
+            self.run_optional_pass(CompilePass::Narrow, |ctx| ctx.narrow(isa))?;
+            self.run_optional_pass(CompilePass::InductionVars, |ctx| {
+                ctx.compute_domtree();
+                ctx.compute_loop_analysis();
+                ctx.induction_vars(isa)
+            })?;
         }
         if isa.flags().enable_nan_canonicalization() {
-            self.canonicalize_nans(isa)?;
+            self.run_pass(CompilePass::CanonicalizeNans, |ctx| {
+                ctx.canonicalize_nans(isa)
+            })?;
         }
 
-        self.legalize(isa)?;
+        self.run_pass(CompilePass::Legalize, |ctx| ctx.legalize(isa))?;
+        self.run_pass(CompilePass::StripAnnotations, |ctx| {
+            ctx.strip_annotations(isa)
+        })?;
         if opt_level != OptLevel::None {
-            self.postopt(isa)?;
+            self.run_optional_pass(CompilePass::Postopt, |ctx| ctx.postopt(isa))?;
             self.compute_domtree();
-            self.compute_loop_analysis();
-            self.licm(isa)?;
-            self.simple_gvn(isa)?;
+            self.run_optional_pass(CompilePass::Licm, |ctx| {
+                ctx.compute_loop_analysis();
+                ctx.licm(isa)
+            })?;
+            self.run_optional_pass(CompilePass::SimpleGvn, |ctx| ctx.simple_gvn(isa))?;
         }
 
         self.compute_domtree();
-        self.eliminate_unreachable_code(isa)?;
+        self.run_pass(CompilePass::UnreachableCode, |ctx| {
+            ctx.eliminate_unreachable_code(isa)
+        })?;
         if opt_level != OptLevel::None {
-            self.dce(isa)?;
+            self.run_optional_pass(CompilePass::SimplifyBranches, |ctx| {
+                ctx.simplify_branches(isa)
+            })?;
+            self.run_optional_pass(CompilePass::Dce, |ctx| ctx.dce(isa))?;
         }
 
-        self.remove_constant_phis(isa)?;
+        self.run_pass(CompilePass::RemoveConstantPhis, |ctx| {
+            ctx.remove_constant_phis(isa)
+        })?;
 
         if let Some(backend) = isa.get_mach_backend() {
-            let result = backend.compile_function(&self.func, self.want_disasm)?;
-            let info = result.code_info();
+            let result = self.run_pass(CompilePass::MachBackend, |ctx| {
+                backend.compile_function(&ctx.func, ctx.want_disasm)
+            })?;
+            let mut info = result.code_info();
+            info.skipped_passes = self.skipped_passes;
             self.mach_compile_result = Some(result);
             Ok(info)
         } else {
-            self.regalloc(isa)?;
-            self.prologue_epilogue(isa)?;
+            self.run_pass(CompilePass::Regalloc, |ctx| ctx.regalloc(isa))?;
+            self.run_pass(CompilePass::PrologueEpilogue, |ctx| {
+                ctx.prologue_epilogue(isa)
+            })?;
             if opt_level == OptLevel::Speed || opt_level == OptLevel::SpeedAndSize {
-                self.redundant_reload_remover(isa)?;
+                self.run_optional_pass(CompilePass::RedundantReloadRemover, |ctx| {
+                    ctx.redundant_reload_remover(isa)
+                })?;
             }
             if opt_level == OptLevel::SpeedAndSize {
-                self.shrink_instructions(isa)?;
+                self.run_optional_pass(CompilePass::ShrinkInstructions, |ctx| {
+                    ctx.shrink_instructions(isa)
+                })?;
             }
-            let result = self.relax_branches(isa);
+            let result = self
+                .run_pass(CompilePass::RelaxBranches, |ctx| ctx.relax_branches(isa))
+                .map(|mut info| {
+                    info.skipped_passes = self.skipped_passes;
+                    info
+                });
 
             debug!("Compiled:\n{}", self.func.display(isa));
             result
         }
     }
 
+    /// Run `pass` by calling `f`, notifying the budget, if any, before and after.
+    fn run_pass<T>(
+        &mut self,
+        pass: CompilePass,
+        f: impl FnOnce(&mut Self) -> CodegenResult<T>,
+    ) -> CodegenResult<T> {
+        if let Some(budget) = &mut self.budget {
+            budget.start_pass(pass, &self.func);
+        }
+        let result = f(self)?;
+        if let Some(budget) = &mut self.budget {
+            if !self.budget_exhausted && budget.end_pass(pass, &self.func) {
+                debug!("Compilation budget exhausted after {}", pass);
+                self.budget_exhausted = true;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Run the optional `pass` like `run_pass`, unless the budget has been exhausted, in which
+    /// case the pass is skipped and recorded as such.
+    fn run_optional_pass(
+        &mut self,
+        pass: CompilePass,
+        f: impl FnOnce(&mut Self) -> CodegenResult<()>,
+    ) -> CodegenResult<()> {
+        debug_assert!(pass.is_optional());
+        if self.budget_exhausted {
+            debug!("Skipping {}: compilation budget exhausted", pass);
+            self.skipped_passes.insert(pass);
+            return Ok(());
+        }
+        self.run_pass(pass, f)
+    }
+
     /// Emit machine code directly into raw memory.
     ///
     /// Write all of the function's machine code to the memory at `mem`. The size of the machine
@@ -241,7 +384,10 @@ impl Context {
         let mut sink = MemoryCodeSink::new(mem, relocs, traps, stack_maps);
         if let Some(ref result) = &self.mach_compile_result {
             result.buffer.emit(&mut sink);
-            let info = sink.info;
+            let mut info = sink.info;
+            info.frame_size = result.frame_size;
+            info.stats = result.stats;
+            info.skipped_passes = self.skipped_passes;
             // New backends do not emit StackMaps through the `CodeSink` because its interface
             // requires `Value`s; instead, the `StackMap` objects are directly accessible via
             // `result.buffer.stack_maps()`.
@@ -256,7 +402,14 @@ impl Context {
             info
         } else {
             isa.emit_function_to_memory(&self.func, &mut sink);
-            sink.info
+            let mut info = sink.info;
+            info.frame_size = self
+                .func
+                .stack_slots
+                .layout_info
+                .map_or(0, |layout| layout.frame_size);
+            info.skipped_passes = self.skipped_passes;
+            info
         }
     }
 
@@ -354,6 +507,17 @@ impl Context {
         Ok(())
     }
 
+    /// Merge the returns of the function into a single exit block, or split such a block into
+    /// direct returns, as selected by the `return_shape` setting.
+    pub fn canonicalize_returns(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
+        match isa.flags().return_shape() {
+            ReturnShape::Unchanged => {}
+            ReturnShape::SingleExit => do_merge_returns(&mut self.func, &mut self.cfg),
+            ReturnShape::DirectReturns => do_split_returns(&mut self.func, &mut self.cfg),
+        }
+        self.verify_if(isa)
+    }
+
     /// Perform pre-legalization rewrites on the function.
     pub fn preopt(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
         do_preopt(&mut self.func, &mut self.cfg, isa);
@@ -361,6 +525,29 @@ impl Context {
         Ok(())
     }
 
+    /// Narrow 64-bit arithmetic whose upper half is unused to 32 bits, on targets where the
+    /// extension back to 64 bits is free.
+    pub fn narrow(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
+        if !isa.zero_extends_32bit_ops() {
+            return Ok(());
+        }
+        do_narrow(&mut self.func);
+        self.verify_if(isa)?;
+        Ok(())
+    }
+
+    /// Rewrite the induction variables of the loops of the function.
+    pub fn induction_vars(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
+        do_induction_vars(
+            &mut self.func,
+            &self.cfg,
+            &self.domtree,
+            &self.loop_analysis,
+            isa,
+        );
+        self.verify_if(isa)
+    }
+
     /// Perform NaN canonicalizing rewrites on the function.
     pub fn canonicalize_nans(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
         do_nan_canonicalization(&mut self.func);
@@ -378,12 +565,57 @@ impl Context {
             simple_legalize(&mut self.func, &mut self.cfg, isa);
             self.verify_if(isa)
         } else {
+            // The legacy backends can't reuse the frame of the current function for a call.
+            let has_tail_call = self.func.layout.blocks().any(|block| {
+                self.func
+                    .layout
+                    .last_inst(block)
+                    .map_or(false, |inst| self.func.dfg[inst].opcode().is_tail_call())
+            });
+            if has_tail_call {
+                return Err(CodegenError::Unsupported(format!(
+                    "tail calls on the legacy {} backend",
+                    isa.name()
+                )));
+            }
+            // Nor can they unwind to a landing pad.
+            let has_invoke = self.func.layout.blocks().any(|block| {
+                self.func
+                    .layout
+                    .block_likely_branches(block)
+                    .any(|inst| self.func.dfg[inst].opcode().is_invoke())
+            });
+            if has_invoke {
+                return Err(CodegenError::Unsupported(format!(
+                    "invokes on the legacy {} backend",
+                    isa.name()
+                )));
+            }
+            // Their jump tables always hold relative entries.
+            if isa.flags().enable_jump_tables()
+                && isa.flags().jump_table_entries() != JumpTableEntries::Relative
+                && !self.func.jump_tables.is_empty()
+            {
+                return Err(CodegenError::Unsupported(format!(
+                    "absolute jump table entries on the legacy {} backend",
+                    isa.name()
+                )));
+            }
             legalize_function(&mut self.func, &mut self.cfg, isa);
             debug!("Legalized:\n{}", self.func.display(isa));
             self.verify_if(isa)
         }
     }
 
+    /// Remove the `assert_eq` and `assume` annotations from the function.
+    pub fn strip_annotations<'a, FOI: Into<FlagsOrIsa<'a>>>(
+        &mut self,
+        fisa: FOI,
+    ) -> CodegenResult<()> {
+        do_strip_annotations(&mut self.func)?;
+        self.verify_if(fisa)
+    }
+
     /// Perform post-legalization rewrites on the function.
     pub fn postopt(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
         do_postopt(&mut self.func, isa);
@@ -440,6 +672,12 @@ impl Context {
         self.verify_if(fisa)
     }
 
+    /// Perform the late control flow graph cleanup.
+    pub fn simplify_branches(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
+        do_simplify_branches(&mut self.func, &mut self.cfg, &mut self.domtree, isa);
+        self.verify_if(isa)
+    }
+
     /// Run the register allocator.
     pub fn regalloc(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
         self.regalloc
@@ -0,0 +1,572 @@
+//! Rewriting of the induction variables of loops.
+//!
+//! Frontends lowering loops naively give each of them a counter compared against a bound on every
+//! iteration, and often further variables stepping in lockstep with the counter, e.g. an index
+//! and a byte offset. This pass looks at the loops with a single entry edge and a single back
+//! edge, and at their induction variables: the integer parameters of the loop header that the back
+//! edge passes incremented by a constant step.
+//!
+//! - A counter only used to decide whether to leave the loop, by comparing it against a
+//!   loop-invariant bound, is turned into the number of remaining iterations, counting down to
+//!   zero. The loop then compares with zero instead of keeping the bound live. `!=` tests are
+//!   rewritten for any step, and relational tests (`<` and `>`, signed or unsigned) for steps of 1
+//!   and -1 respectively, with the initial count clamped to zero if the first test fails. This is
+//!   only done if the target compares with an immediate no more expensively than with a register,
+//!   according to `TargetIsa::inst_cost`.
+//! - The other induction variables with the same type and step as a primary one are computed from
+//!   it by adding the difference of their initial values, so that only the primary one is carried
+//!   around the loop. This is only done for variables whose incremented value isn't used anywhere
+//!   but on the back edge, so that the loop doesn't get any longer.
+//!
+//! The control flow graph isn't changed.
+
+use crate::{
+    cursor::{Cursor, FuncCursor},
+    dominator_tree::DominatorTree,
+    entity::SecondaryMap,
+    flowgraph::{BlockPredecessor, ControlFlowGraph},
+    ir::{
+        condcodes::{CondCode, IntCC},
+        Block, DataFlowGraph, Function, Inst, InstBuilder, InstructionData, Opcode, Value,
+        ValueDef,
+    },
+    isa::TargetIsa,
+    loop_analysis::{Loop, LoopAnalysis},
+    timing,
+};
+use alloc::vec::Vec;
+use log::debug;
+
+/// The edges into the header of a loop whose induction variables can be rewritten.
+struct LoopEdges {
+    header: Block,
+    /// The branch to the header from outside the loop.
+    entry: Inst,
+    /// The branch to the header from within the loop.
+    latch: Inst,
+}
+
+/// An induction variable of a loop.
+struct InductionVar {
+    /// The index of the variable in the parameters of the loop header.
+    index: usize,
+    param: Value,
+    /// The value passed by the back edge, `iadd_imm param, step`.
+    next: Value,
+    step: i64,
+    /// The value passed by the entry edge.
+    init: Value,
+}
+
+/// The bound an induction variable is compared against.
+enum Bound {
+    Value(Value),
+    Imm(i64),
+}
+
+/// A test of an induction variable deciding whether to leave the loop.
+struct ExitTest {
+    /// The comparison, whose result is only used by the branch leaving the loop.
+    cmp: Inst,
+    /// The condition on the induction variable and the bound for the loop to go on.
+    cond: IntCC,
+    bound: Bound,
+    /// Does the loop go on when `cmp` is true, rather than when it is false?
+    continue_if_true: bool,
+    /// Does `cmp` test the incremented value of the variable, rather than its parameter?
+    tests_next: bool,
+}
+
+/// The number of uses of each value, with aliases resolved.
+fn count_uses(func: &Function) -> SecondaryMap<Value, u32> {
+    let mut uses = SecondaryMap::new();
+    for block in func.layout.blocks() {
+        for inst in func.layout.block_insts(block) {
+            for &arg in func.dfg.inst_args(inst) {
+                uses[func.dfg.resolve_aliases(arg)] += 1;
+            }
+        }
+    }
+    uses
+}
+
+/// The entry and back edges of `lp`, if it has a single one of each.
+fn loop_edges(
+    func: &Function,
+    cfg: &ControlFlowGraph,
+    domtree: &DominatorTree,
+    loop_analysis: &LoopAnalysis,
+    lp: Loop,
+) -> Option<LoopEdges> {
+    let header = loop_analysis.loop_header(lp);
+    let mut entry = None;
+    let mut latch = None;
+    for BlockPredecessor { inst, .. } in cfg.pred_iter(header) {
+        // Jump tables can't pass the header's parameters, and the variable arguments of an
+        // invoke start with its call arguments.
+        if func.dfg[inst].branch_destination() != Some(header) || func.dfg[inst].opcode().is_call()
+        {
+            return None;
+        }
+        let edge = if domtree.dominates(header, inst, &func.layout) {
+            &mut latch
+        } else {
+            &mut entry
+        };
+        if edge.is_some() {
+            return None;
+        }
+        *edge = Some(inst);
+    }
+    Some(LoopEdges {
+        header,
+        entry: entry?,
+        latch: latch?,
+    })
+}
+
+/// The induction variables of the loop with the given `edges`.
+fn induction_vars(dfg: &DataFlowGraph, edges: &LoopEdges) -> Vec<InductionVar> {
+    let entry_args = dfg.inst_variable_args(edges.entry);
+    let latch_args = dfg.inst_variable_args(edges.latch);
+    let mut vars = Vec::new();
+    for (index, &param) in dfg.block_params(edges.header).iter().enumerate() {
+        let ty = dfg.value_type(param);
+        if !ty.is_int() || ty.bits() > 64 {
+            continue;
+        }
+        let next = dfg.resolve_aliases(latch_args[index]);
+        let inst = match dfg.value_def(next) {
+            ValueDef::Result(inst, _) => inst,
+            ValueDef::Param(..) => continue,
+        };
+        if let InstructionData::BinaryImm64 {
+            opcode: Opcode::IaddImm,
+            arg,
+            imm,
+        } = dfg[inst]
+        {
+            // The immediate is truncated to the type of the variable.
+            let shift = 64 - ty.bits();
+            let step = (imm.bits() << shift) >> shift;
+            if dfg.resolve_aliases(arg) == param && step != 0 {
+                vars.push(InductionVar {
+                    index,
+                    param,
+                    next,
+                    step,
+                    init: dfg.resolve_aliases(entry_args[index]),
+                });
+            }
+        }
+    }
+    vars
+}
+
+/// Find a test of `var` that leaves `lp` unless it holds, and that is made on each iteration.
+fn exit_test(
+    func: &Function,
+    domtree: &DominatorTree,
+    loop_analysis: &LoopAnalysis,
+    lp: Loop,
+    edges: &LoopEdges,
+    var: &InductionVar,
+) -> Option<ExitTest> {
+    let dfg = &func.dfg;
+    let layout = &func.layout;
+
+    // The blocks executed on every iteration are the ones dominating the latch.
+    let mut block = layout.inst_block(edges.latch)?;
+    loop {
+        if let Some(test) = block_exit_test(func, loop_analysis, lp, block, var) {
+            // The bound must be available before entering the loop.
+            let invariant = match test.bound {
+                Bound::Value(bound) => {
+                    let def = dfg.value_def(bound);
+                    let def_block = match def {
+                        ValueDef::Result(inst, _) => layout.inst_block(inst)?,
+                        ValueDef::Param(block, _) => block,
+                    };
+                    !loop_analysis.is_in_loop(def_block, lp)
+                        && domtree.dominates(def, edges.entry, layout)
+                }
+                Bound::Imm(_) => true,
+            };
+            return if invariant { Some(test) } else { None };
+        }
+        if block == edges.header {
+            return None;
+        }
+        block = layout.inst_block(domtree.idom(block)?)?;
+    }
+}
+
+/// The test of `var` by the conditional branch ending `block` right before its final jump, if
+/// one of them leaves `lp` and the other one doesn't.
+fn block_exit_test(
+    func: &Function,
+    loop_analysis: &LoopAnalysis,
+    lp: Loop,
+    block: Block,
+    var: &InductionVar,
+) -> Option<ExitTest> {
+    let dfg = &func.dfg;
+    let jump = func.layout.last_inst(block)?;
+    let branch = func.layout.prev_inst(jump)?;
+    let opcode = dfg[branch].opcode();
+    if dfg[jump].opcode() != Opcode::Jump || (opcode != Opcode::Brz && opcode != Opcode::Brnz) {
+        return None;
+    }
+    let in_loop =
+        |inst: Inst| loop_analysis.is_in_loop(dfg[inst].branch_destination().unwrap(), lp);
+    let branch_stays = in_loop(branch);
+    if branch_stays == in_loop(jump) {
+        return None;
+    }
+
+    let cmp = match dfg.value_def(dfg.resolve_aliases(dfg.inst_args(branch)[0])) {
+        ValueDef::Result(inst, _) => inst,
+        ValueDef::Param(..) => return None,
+    };
+    let tested = |value: Value| {
+        let value = dfg.resolve_aliases(value);
+        if value == var.param {
+            Some(false)
+        } else if value == var.next {
+            Some(true)
+        } else {
+            None
+        }
+    };
+    let bound_value = |value: Value| Bound::Value(dfg.resolve_aliases(value));
+    let (cond, bound, tests_next) = match dfg[cmp] {
+        InstructionData::IntCompare {
+            opcode: Opcode::Icmp,
+            cond,
+            args,
+        } => match (tested(args[0]), tested(args[1])) {
+            (Some(tests_next), None) => (cond, bound_value(args[1]), tests_next),
+            (None, Some(tests_next)) => (cond.reverse(), bound_value(args[0]), tests_next),
+            _ => return None,
+        },
+        InstructionData::IntCompareImm {
+            opcode: Opcode::IcmpImm,
+            cond,
+            arg,
+            imm,
+        } => (cond, Bound::Imm(imm.bits()), tested(arg)?),
+        _ => return None,
+    };
+
+    // `brnz` goes on when the comparison is true, `brz` when it is false.
+    let continue_if_true = branch_stays == (opcode == Opcode::Brnz);
+    Some(ExitTest {
+        cmp,
+        cond: if continue_if_true {
+            cond
+        } else {
+            cond.inverse()
+        },
+        bound,
+        continue_if_true,
+        tests_next,
+    })
+}
+
+/// Turn `var`, which is only used by `test` and its own increment, into a count of the remaining
+/// iterations, if the condition of `test` allows it.
+///
+/// Returns true if the loop was changed.
+fn count_down(func: &mut Function, edges: &LoopEdges, var: &InductionVar, test: ExitTest) -> bool {
+    // The counter is decremented by this until the test fails, when it reaches zero.
+    let step = match test.cond {
+        IntCC::NotEqual => var.step.wrapping_neg(),
+        IntCC::UnsignedLessThan | IntCC::SignedLessThan if var.step == 1 => -1,
+        IntCC::UnsignedGreaterThan | IntCC::SignedGreaterThan if var.step == -1 => -1,
+        _ => return false,
+    };
+    debug!(
+        "Counting {} down in the loop headed by {}",
+        var.param, edges.header
+    );
+
+    // Compute the value of the counter at the first test, the distance from the first tested
+    // value to the bound, before entering the loop.
+    let ty = func.dfg.value_type(var.param);
+    let mut pos = FuncCursor::new(func).at_inst(edges.entry);
+    let bound = match test.bound {
+        Bound::Value(bound) => bound,
+        Bound::Imm(imm) => pos.ins().iconst(ty, imm),
+    };
+    let first = if test.tests_next {
+        pos.ins().iadd_imm(var.init, var.step)
+    } else {
+        var.init
+    };
+    let count = match test.cond {
+        IntCC::NotEqual => pos.ins().isub(bound, first),
+        cond => {
+            // The tested values step by one towards the bound, so the loop goes on until they
+            // reach it, provided that the first test passes.
+            let distance = if var.step == 1 {
+                pos.ins().isub(bound, first)
+            } else {
+                pos.ins().isub(first, bound)
+            };
+            let enters = pos.ins().icmp(cond, first, bound);
+            let zero = pos.ins().iconst(ty, 0);
+            pos.ins().select(enters, distance, zero)
+        }
+    };
+    let init = if test.tests_next {
+        pos.ins().iadd_imm(count, step.wrapping_neg())
+    } else {
+        count
+    };
+    pos.func.dfg.inst_variable_args_mut(edges.entry)[var.index] = init;
+
+    let increment = pos.func.dfg.value_def(var.next).unwrap_inst();
+    pos.func.dfg.replace(increment).iadd_imm(var.param, step);
+    let tested = if test.tests_next { var.next } else { var.param };
+    let cond = if test.continue_if_true {
+        IntCC::NotEqual
+    } else {
+        IntCC::Equal
+    };
+    pos.func.dfg.replace(test.cmp).icmp_imm(cond, tested, 0);
+    true
+}
+
+/// Compute the induction variables of the loop with the given `edges` that step like `primary`
+/// from it, except for the one at `skip`.
+///
+/// Returns true if the loop was changed.
+fn derive_from_primary(
+    func: &mut Function,
+    edges: &LoopEdges,
+    uses: &SecondaryMap<Value, u32>,
+    vars: &[InductionVar],
+    skip: Option<usize>,
+) -> bool {
+    let mut derived: Vec<(&InductionVar, &InductionVar)> = Vec::new();
+    let mut primaries: Vec<&InductionVar> = Vec::new();
+    for var in vars.iter().filter(|var| Some(var.index) != skip) {
+        let ty = func.dfg.value_type(var.param);
+        match primaries
+            .iter()
+            .find(|p| p.step == var.step && func.dfg.value_type(p.param) == ty)
+        {
+            Some(&primary) if uses[var.next] == 1 => derived.push((primary, var)),
+            Some(_) => {}
+            None => primaries.push(var),
+        }
+    }
+
+    // Remove the parameters from the last one, so that the indices of the others stay valid.
+    for &(primary, var) in derived.iter().rev() {
+        debug!(
+            "Computing {} from {} in the loop headed by {}",
+            var.param, primary.param, edges.header
+        );
+        let mut pos = FuncCursor::new(func).at_inst(edges.entry);
+        let offset = pos.ins().isub(var.init, primary.init);
+        pos.goto_first_insertion_point(edges.header);
+        let value = pos.ins().iadd(primary.param, offset);
+
+        pos.func.dfg.remove_block_param(var.param);
+        pos.func.dfg.change_to_alias(var.param, value);
+        for &branch in &[edges.entry, edges.latch] {
+            let num_fixed = pos.func.dfg[branch]
+                .opcode()
+                .constraints()
+                .num_fixed_value_arguments();
+            let mut args = pos.func.dfg[branch].take_value_list().unwrap();
+            args.remove(num_fixed + var.index, &mut pos.func.dfg.value_lists);
+            pos.func.dfg[branch].put_value_list(args);
+        }
+    }
+    !derived.is_empty()
+}
+
+/// The induction variable rewriting pass.
+pub fn do_induction_vars(
+    func: &mut Function,
+    cfg: &ControlFlowGraph,
+    domtree: &DominatorTree,
+    loop_analysis: &LoopAnalysis,
+    isa: &dyn TargetIsa,
+) {
+    let _tt = timing::induction_vars();
+    debug_assert!(cfg.is_valid());
+    debug_assert!(domtree.is_valid());
+    debug_assert!(loop_analysis.is_valid());
+
+    let mut uses = count_uses(func);
+    for lp in loop_analysis.loops() {
+        let edges = match loop_edges(func, cfg, domtree, loop_analysis, lp) {
+            Some(edges) => edges,
+            None => continue,
+        };
+        let vars = induction_vars(&func.dfg, &edges);
+        if vars.is_empty() {
+            continue;
+        }
+
+        let mut counter = None;
+        for var in &vars {
+            let test = match exit_test(func, domtree, loop_analysis, lp, &edges, var) {
+                Some(test) => test,
+                None => continue,
+            };
+            // The counter may only be used by the test and its increment.
+            let tests_param = !test.tests_next as u32;
+            if uses[func.dfg.first_result(test.cmp)] != 1
+                || uses[var.param] != 1 + tests_param
+                || uses[var.next] != 2 - tests_param
+            {
+                continue;
+            }
+            let ty = func.dfg.value_type(var.param);
+            let imm_cost = isa.inst_cost(Opcode::IcmpImm, ty);
+            let reg_cost = isa.inst_cost(Opcode::Icmp, ty);
+            if imm_cost.latency > reg_cost.latency || imm_cost.size > reg_cost.size {
+                continue;
+            }
+            if count_down(func, &edges, var, test) {
+                counter = Some(var.index);
+                break;
+            }
+        }
+
+        let changed = derive_from_primary(func, &edges, &uses, &vars, counter);
+        // Later loops may test values used by the new instructions.
+        if changed || counter.is_some() {
+            uses = count_uses(func);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "x86"))]
+mod tests {
+    use super::do_induction_vars;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::dominator_tree::DominatorTree;
+    use crate::flowgraph::ControlFlowGraph;
+    use crate::ir::{
+        condcodes::IntCC, types, AbiParam, Function, InstBuilder, InstructionData, MemFlags, Opcode,
+    };
+    use crate::isa;
+    use crate::loop_analysis::LoopAnalysis;
+    use crate::settings;
+    use crate::verifier::verify_function;
+    use target_lexicon::triple;
+
+    fn rewrite(func: &mut Function) {
+        let isa = isa::lookup(triple!("x86_64"))
+            .unwrap()
+            .finish(settings::Flags::new(settings::builder()));
+        let cfg = ControlFlowGraph::with_function(func);
+        let domtree = DominatorTree::with_function(func, &cfg);
+        let mut loop_analysis = LoopAnalysis::new();
+        loop_analysis.compute(func, &cfg, &domtree);
+        do_induction_vars(func, &cfg, &domtree, &loop_analysis, &*isa);
+        verify_function(&*func, &*isa).unwrap();
+    }
+
+    #[test]
+    fn count_down() {
+        // Calls `f` `n` times, counting from 0.
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        let block2 = func.dfg.make_block();
+        let block3 = func.dfg.make_block();
+        let n = func.dfg.append_block_param(block0, types::I32);
+        let i = func.dfg.append_block_param(block1, types::I32);
+        func.signature.params.push(AbiParam::new(types::I32));
+
+        let cmp = {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            let zero = cur.ins().iconst(types::I32, 0);
+            cur.ins().jump(block1, &[zero]);
+
+            cur.insert_block(block1);
+            let cmp = cur.ins().icmp(IntCC::SignedLessThan, i, n);
+            cur.ins().brz(cmp, block3, &[]);
+            cur.ins().jump(block2, &[]);
+
+            cur.insert_block(block2);
+            let next = cur.ins().iadd_imm(i, 1);
+            cur.ins().jump(block1, &[next]);
+
+            cur.insert_block(block3);
+            cur.ins().return_(&[]);
+            cmp
+        };
+
+        rewrite(&mut func);
+        // The count starts at `n`, or 0 if `n` is negative, and is decremented to 0.
+        match func.dfg[func.dfg.value_def(cmp).unwrap_inst()] {
+            InstructionData::IntCompareImm { cond, imm, .. } => {
+                assert_eq!(cond, IntCC::NotEqual);
+                assert_eq!(imm.bits(), 0);
+            }
+            ref data => panic!("unexpected comparison {:?}", data),
+        }
+        let entry = func.layout.last_inst(block0).unwrap();
+        let init = func.dfg.inst_variable_args(entry)[0];
+        let init = func.dfg.value_def(init).unwrap_inst();
+        assert_eq!(func.dfg[init].opcode(), Opcode::Select);
+        let latch = func.layout.last_inst(block2).unwrap();
+        let next = func.dfg.inst_variable_args(latch)[0];
+        match func.dfg[func.dfg.value_def(next).unwrap_inst()] {
+            InstructionData::BinaryImm64 { imm, .. } => assert_eq!(imm.bits(), -1),
+            ref data => panic!("unexpected increment {:?}", data),
+        }
+    }
+
+    #[test]
+    fn derive_from_primary() {
+        // Stores `i` to `p` for `i` from `x` on, with `p` advancing in lockstep, until `i` is 100.
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        let block2 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I64);
+        let base = func.dfg.append_block_param(block0, types::I64);
+        let i = func.dfg.append_block_param(block1, types::I64);
+        let p = func.dfg.append_block_param(block1, types::I64);
+        func.signature.params.push(AbiParam::new(types::I64));
+        func.signature.params.push(AbiParam::new(types::I64));
+
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            cur.ins().jump(block1, &[x, base]);
+
+            cur.insert_block(block1);
+            cur.ins().store(MemFlags::trusted(), i, p, 0);
+            let i_next = cur.ins().iadd_imm(i, 8);
+            let p_next = cur.ins().iadd_imm(p, 8);
+            let cmp = cur.ins().icmp_imm(IntCC::Equal, i_next, 800);
+            cur.ins().brnz(cmp, block2, &[]);
+            cur.ins().jump(block1, &[i_next, p_next]);
+
+            cur.insert_block(block2);
+            cur.ins().return_(&[]);
+        }
+
+        rewrite(&mut func);
+        // `i` is used by the store, so it can't count down, and `p` is computed from it.
+        assert_eq!(func.dfg.block_params(block1), [i]);
+        assert_eq!(
+            func.dfg
+                .inst_variable_args(func.layout.last_inst(block0).unwrap()),
+            [x]
+        );
+        let first = func.layout.first_inst(block1).unwrap();
+        assert_eq!(func.dfg[first].opcode(), Opcode::Iadd);
+        assert_eq!(func.dfg.resolve_aliases(p), func.dfg.first_result(first));
+    }
+}
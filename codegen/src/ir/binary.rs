@@ -0,0 +1,201 @@
+//! A compact binary encoding of Cranelift IR functions.
+//!
+//! The textual `.clif` form is meant to be read by people, and parsing it back requires the
+//! reader crate. The encoding defined here is much smaller and faster to produce and to decode,
+//! which makes it suitable as the key or value of a compilation cache, or for shipping functions
+//! between processes.
+//!
+//! An encoded function starts with the magic bytes `CLIF` and a format version, followed by the
+//! serialized function. The function itself records the version of Cranelift that encoded it, and
+//! is only decoded by the same version. Encoding is deterministic: equal functions always encode
+//! to the same bytes, and decoding then encoding a function gives back the same bytes.
+//!
+//! Decoding only checks that the bytes are well formed, not that they describe a valid function;
+//! run the verifier on functions decoded from untrusted sources before using them.
+
+use crate::ir::Function;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use bincode::Options;
+use core::fmt;
+
+/// The magic bytes at the start of every encoded function.
+const MAGIC: &[u8; 4] = b"CLIF";
+
+/// The version of the encoding, bumped whenever the layout of the header or the options of the
+/// serializer change.
+const FORMAT_VERSION: u8 = 1;
+
+/// The length of the header: the magic bytes and the format version.
+const HEADER_LEN: usize = MAGIC.len() + 1;
+
+/// An error found while decoding a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes don't start with the magic bytes of an encoded function.
+    BadMagic,
+
+    /// The function was encoded with an unsupported version of the format.
+    UnsupportedFormat(u8),
+
+    /// The encoded function is truncated, has trailing bytes, or is otherwise malformed. This
+    /// includes functions encoded by another version of Cranelift.
+    Malformed(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "Not an encoded clif ir function"),
+            DecodeError::UnsupportedFormat(version) => write!(
+                f,
+                "Unsupported format version {}, expected {}",
+                version, FORMAT_VERSION
+            ),
+            DecodeError::Malformed(msg) => write!(f, "Malformed clif ir function: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// The options of the serializer. Integers are encoded with a variable length, which keeps the
+/// many small entity indices of a function short.
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_varint_encoding()
+        .with_little_endian()
+        .reject_trailing_bytes()
+}
+
+/// Encode `func` to a compact binary form, which `decode` turns back into a function.
+pub fn encode(func: &Function) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + 64);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(FORMAT_VERSION);
+    options()
+        .serialize_into(&mut bytes, func)
+        .expect("serializing a function can't fail");
+    bytes
+}
+
+/// Decode a function encoded by `encode`.
+pub fn decode(bytes: &[u8]) -> Result<Function, DecodeError> {
+    if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    let version = bytes[MAGIC.len()];
+    if version != FORMAT_VERSION {
+        return Err(DecodeError::UnsupportedFormat(version));
+    }
+    let body = &bytes[HEADER_LEN..];
+    options()
+        .with_limit(body.len() as u64)
+        .deserialize(body)
+        .map_err(|err| DecodeError::Malformed(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::condcodes::IntCC;
+    use crate::ir::{types, AbiParam, ExternalName, InstBuilder, Signature};
+    use crate::isa::CallConv;
+    use alloc::vec;
+
+    fn sample() -> Function {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        let mut func = Function::with_name_signature(ExternalName::testcase("sample"), sig);
+
+        let block0 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+        let block1 = func.dfg.make_block();
+        let acc = func.dfg.append_block_param(block1, types::I32);
+        let n = func.dfg.append_block_param(block1, types::I32);
+        let block2 = func.dfg.make_block();
+
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(block0);
+        let zero = pos.ins().iconst(types::I32, 0);
+        pos.ins().jump(block1, &[zero, x]);
+
+        pos.insert_block(block1);
+        let mut sum = acc;
+        for i in 1..20 {
+            let k = pos.ins().iconst(types::I32, i);
+            let scaled = pos.ins().imul(n, k);
+            sum = pos.ins().iadd(sum, scaled);
+        }
+        let next = pos.ins().iadd_imm(n, -1);
+        let done = pos.ins().icmp_imm(IntCC::Equal, next, 0);
+        pos.ins().brz(done, block1, &[sum, next]);
+        pos.ins().jump(block2, &[]);
+
+        pos.insert_block(block2);
+        pos.ins().return_(&[sum]);
+        func
+    }
+
+    #[test]
+    fn round_trip() {
+        let func = sample();
+        let bytes = encode(&func);
+        let copy = decode(&bytes).expect("decoding failed");
+        assert_eq!(
+            copy.display(None).to_string(),
+            func.display(None).to_string()
+        );
+        assert_eq!(encode(&copy), bytes);
+    }
+
+    #[test]
+    fn smaller_than_text() {
+        let func = sample();
+        let text = func.display(None).to_string();
+        assert!(encode(&func).len() < text.len());
+    }
+
+    #[test]
+    fn deterministic() {
+        let mut func = sample();
+        for i in 0..16u8 {
+            func.dfg.constants.insert(vec![i; 16].into());
+        }
+        let copy = decode(&encode(&func)).expect("decoding failed");
+        assert_eq!(encode(&func), encode(&copy));
+        assert_eq!(encode(&func), encode(&func.clone()));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let bytes = encode(&sample());
+
+        assert_eq!(decode(b"CL").unwrap_err(), DecodeError::BadMagic);
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(decode(&bad_magic).unwrap_err(), DecodeError::BadMagic);
+
+        let mut bad_version = bytes.clone();
+        bad_version[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert_eq!(
+            decode(&bad_version).unwrap_err(),
+            DecodeError::UnsupportedFormat(FORMAT_VERSION + 1)
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            decode(&trailing).unwrap_err(),
+            DecodeError::Malformed(_)
+        ));
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(matches!(
+            decode(truncated).unwrap_err(),
+            DecodeError::Malformed(_)
+        ));
+    }
+}
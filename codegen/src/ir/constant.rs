@@ -25,7 +25,7 @@ use core::{
 use cranelift_entity::EntityRef;
 
 #[cfg(feature = "enable-serde")]
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 /// This type describes the actual constant data. Note that the bytes stored in this structure are
 /// expected to be in little-endian order; this is due to ease-of-use when interacting with
@@ -207,7 +207,6 @@ impl ConstantPoolEntry {
 /// Maintains the mapping between a constant handle (i.e.  [`Constant`](crate::ir::Constant)) and
 /// its constant data (i.e.  [`ConstantData`](crate::ir::ConstantData)).
 #[derive(Clone)]
-#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct ConstantPool {
     /// This mapping maintains the insertion order as long as Constants are created with
     /// sequentially increasing integers.
@@ -218,6 +217,30 @@ pub struct ConstantPool {
     values_to_handles: HashMap<ConstantData, Constant>,
 }
 
+/// Only the handles are serialized, in order, so that equal pools always serialize to the same
+/// bytes; the mapping from constant data back to handles is rebuilt when deserializing.
+#[cfg(feature = "enable-serde")]
+impl Serialize for ConstantPool {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.handles_to_values.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "enable-serde")]
+impl<'de> Deserialize<'de> for ConstantPool {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let handles_to_values = BTreeMap::<Constant, ConstantPoolEntry>::deserialize(deserializer)?;
+        let values_to_handles = handles_to_values
+            .iter()
+            .map(|(&handle, entry)| (entry.data.clone(), handle))
+            .collect();
+        Ok(Self {
+            handles_to_values,
+            values_to_handles,
+        })
+    }
+}
+
 impl ConstantPool {
     /// Create a new constant pool instance.
     pub fn new() -> Self {
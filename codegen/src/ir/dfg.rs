@@ -23,7 +23,9 @@ use core::{
 };
 
 #[cfg(feature = "enable-serde")]
-use serde::{Deserialize, Serialize};
+use alloc::collections::BTreeMap;
+#[cfg(feature = "enable-serde")]
+use serde::{Deserialize, Serialize, Serializer};
 
 /// A data flow graph defines all instructions and basic blocks in a function as well as
 /// the data flow dependencies between them. The DFG also tracks values which can be either
@@ -64,6 +66,11 @@ pub struct DataFlowGraph {
     /// Primary value table with entries for all values.
     values: PrimaryMap<Value, ValueData>,
 
+    /// Values explicitly marked as GC references, in addition to those of a reference type.
+    ///
+    /// See `is_gc_ref()`.
+    gc_refs: SecondaryMap<Value, bool>,
+
     /// Function signature table. These signatures are referenced by indirect call instructions as
     /// well as the external function references.
     pub signatures: PrimaryMap<SigRef, Signature>,
@@ -75,6 +82,10 @@ pub struct DataFlowGraph {
     pub ext_funcs: PrimaryMap<FuncRef, ExtFuncData>,
 
     /// Saves Value labels.
+    #[cfg_attr(
+        feature = "enable-serde",
+        serde(serialize_with = "serialize_values_labels")
+    )]
     pub values_labels: Option<HashMap<Value, ValueLabelAssignments>>,
 
     /// Constants used within the function
@@ -84,6 +95,19 @@ pub struct DataFlowGraph {
     pub immediates: PrimaryMap<Immediate, ConstantData>,
 }
 
+/// Serialize the value labels in the order of their values, so that equal functions always
+/// serialize to the same bytes.
+#[cfg(feature = "enable-serde")]
+fn serialize_values_labels<S: Serializer>(
+    values_labels: &Option<HashMap<Value, ValueLabelAssignments>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    values_labels
+        .as_ref()
+        .map(|labels| labels.iter().collect::<BTreeMap<_, _>>())
+        .serialize(serializer)
+}
+
 impl DataFlowGraph {
     /// Create a new empty `DataFlowGraph`.
     pub fn new() -> Self {
@@ -93,6 +117,7 @@ impl DataFlowGraph {
             blocks: PrimaryMap::new(),
             value_lists: ValueListPool::new(),
             values: PrimaryMap::new(),
+            gc_refs: SecondaryMap::new(),
             signatures: PrimaryMap::new(),
             old_signatures: SecondaryMap::new(),
             ext_funcs: PrimaryMap::new(),
@@ -109,6 +134,7 @@ impl DataFlowGraph {
         self.blocks.clear();
         self.value_lists.clear();
         self.values.clear();
+        self.gc_refs.clear();
         self.signatures.clear();
         self.old_signatures.clear();
         self.ext_funcs.clear();
@@ -242,6 +268,43 @@ impl DataFlowGraph {
         self.values[v].ty()
     }
 
+    /// Change the type of a value, leaving its definition and uses alone.
+    ///
+    /// The new type must be valid for all of them, which is the case when a legalization replaces
+    /// a type with another one everywhere in the function.
+    pub(crate) fn change_value_type(&mut self, v: Value, t: Type) {
+        match self.values[v] {
+            ValueData::Inst { ref mut ty, .. }
+            | ValueData::Param { ref mut ty, .. }
+            | ValueData::Alias { ref mut ty, .. } => *ty = t,
+        }
+    }
+
+    /// Is `v` a reference to a GC-managed object?
+    ///
+    /// Values of a reference type such as `r64` always are. Other values, e.g. pointer-sized
+    /// integers produced by a frontend that has no reference types, are only GC references if
+    /// they have been marked with `set_gc_ref()`. Only GC references are included in stack maps.
+    pub fn is_gc_ref(&self, v: Value) -> bool {
+        let v = self.resolve_aliases(v);
+        self.value_type(v).lane_type().is_ref() || self.gc_refs[v]
+    }
+
+    /// Mark `v` as being, or not being, a GC reference.
+    ///
+    /// This has no effect on values of a reference type, which are always GC references.
+    pub fn set_gc_ref(&mut self, v: Value, is_gc_ref: bool) {
+        let v = self.resolve_aliases(v);
+        self.gc_refs[v] = is_gc_ref;
+    }
+
+    /// Carry the GC reference mark of `old` over to `new`, which replaces it.
+    fn inherit_gc_ref(&mut self, old: Value, new: Value) {
+        if self.gc_refs[old] {
+            self.gc_refs[new] = true;
+        }
+    }
+
     /// Get the definition of a value.
     ///
     /// This is either the instruction that defined it or the Block that has the value as an
@@ -321,6 +384,7 @@ impl DataFlowGraph {
         );
         debug_assert_ne!(ty, types::INVALID);
 
+        self.inherit_gc_ref(dest, original);
         self.values[dest] = ValueData::Alias { ty, original };
     }
 
@@ -365,6 +429,9 @@ impl DataFlowGraph {
             );
             debug_assert_ne!(ty, types::INVALID);
 
+            if self.gc_refs[dest] {
+                self.gc_refs[original] = true;
+            }
             self.values[dest] = ValueData::Alias { ty, original };
         }
 
@@ -548,7 +615,7 @@ impl DataFlowGraph {
         self.results[inst].clear(&mut self.value_lists);
 
         // Get the call signature if this is a function call.
-        if let Some(sig) = self.call_signature(inst) {
+        if let Some(sig) = self.non_tail_call_signature(inst) {
             // Create result values corresponding to the call return types.
             debug_assert_eq!(
                 self.insts[inst].opcode().constraints().num_fixed_results(),
@@ -638,6 +705,7 @@ impl DataFlowGraph {
             num,
             inst,
         });
+        self.inherit_gc_ref(old_value, new_value);
         let num = num as usize;
         let attached = mem::replace(
             self.results[inst]
@@ -707,9 +775,64 @@ impl DataFlowGraph {
         }
     }
 
+    /// Get the call signature of a call instruction that returns to its caller, which is the
+    /// signature its results are created from.
+    /// Returns `None` if `inst` is not a call instruction or is a tail call.
+    pub fn non_tail_call_signature(&self, inst: Inst) -> Option<SigRef> {
+        if self.insts[inst].opcode().is_tail_call() {
+            None
+        } else {
+            self.call_signature(inst)
+        }
+    }
+
     /// Check if `inst` is a branch.
+    ///
+    /// Unlike `InstructionData::analyze_branch()`, this leaves the call arguments of an invoke
+    /// out of the arguments passed to its landing pad.
     pub fn analyze_branch(&self, inst: Inst) -> BranchInfo {
-        self.insts[inst].analyze_branch(&self.value_lists)
+        match self.insts[inst].analyze_branch(&self.value_lists) {
+            BranchInfo::SingleDest(dest, args) if self.insts[inst].opcode().is_invoke() => {
+                let num_call_args = self.num_invoke_call_args(inst, args.len());
+                BranchInfo::SingleDest(dest, &args[num_call_args..])
+            }
+            info => info,
+        }
+    }
+
+    /// Check if `inst` is a call.
+    ///
+    /// Unlike `InstructionData::analyze_call()`, this leaves the landing pad arguments of an
+    /// invoke out of the arguments passed to the callee.
+    pub fn analyze_call(&self, inst: Inst) -> CallInfo {
+        let is_invoke = self.insts[inst].opcode().is_invoke();
+        match self.insts[inst].analyze_call(&self.value_lists) {
+            CallInfo::Direct(func_ref, args) if is_invoke => CallInfo::Direct(
+                func_ref,
+                &args[..self.num_invoke_call_args(inst, args.len())],
+            ),
+            CallInfo::Indirect(sig_ref, args) if is_invoke => CallInfo::Indirect(
+                sig_ref,
+                &args[..self.num_invoke_call_args(inst, args.len())],
+            ),
+            info => info,
+        }
+    }
+
+    /// Get the number of the `num_args` variable arguments of the invoke `inst` that are passed
+    /// to the callee rather than the landing pad, as given by the callee signature.
+    ///
+    /// This doesn't trust the signature to be valid or to match the arguments, so that the
+    /// verifier can rely on it.
+    fn num_invoke_call_args(&self, inst: Inst, num_args: usize) -> usize {
+        let sig_ref = match self.insts[inst].analyze_call(&self.value_lists) {
+            CallInfo::Direct(func_ref, _) => self.ext_funcs.get(func_ref).map(|f| f.signature),
+            CallInfo::Indirect(sig_ref, _) => Some(sig_ref),
+            CallInfo::NotACall => None,
+        };
+        sig_ref
+            .and_then(|sig_ref| self.signatures.get(sig_ref))
+            .map_or(0, |sig| sig.params.len().min(num_args))
     }
 
     /// Compute the type of an instruction result from opcode constraints and call signatures.
@@ -733,7 +856,7 @@ impl DataFlowGraph {
         }
 
         // Not a fixed result, try to extract a return type from the call signature.
-        self.call_signature(inst).and_then(|sigref| {
+        self.non_tail_call_signature(inst).and_then(|sigref| {
             self.signatures[sigref]
                 .returns
                 .get(result_idx - num_fixed_results)
@@ -918,6 +1041,7 @@ impl DataFlowGraph {
             num,
             block,
         });
+        self.inherit_gc_ref(old_value, new_arg);
 
         self.blocks[block]
             .params
@@ -1011,7 +1135,7 @@ impl DataFlowGraph {
         reuse: &[Value],
     ) -> usize {
         // Get the call signature if this is a function call.
-        if let Some(sig) = self.call_signature(inst) {
+        if let Some(sig) = self.non_tail_call_signature(inst) {
             assert_eq!(
                 self.insts[inst].opcode().constraints().num_fixed_results(),
                 0
@@ -1319,4 +1443,37 @@ mod tests {
         // This does not see through copies.
         assert_eq!(pos.func.dfg.resolve_aliases(c3), c3);
     }
+
+    #[test]
+    fn gc_refs() {
+        use crate::ir::InstBuilder;
+
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(block0);
+
+        let r = pos.func.dfg.append_block_param(block0, types::R64);
+        let p = pos.func.dfg.append_block_param(block0, types::I64);
+        let v1 = pos.ins().iconst(types::I64, 8);
+        let v2 = pos.ins().iadd(p, v1);
+
+        // Reference types are always GC references; other values only when marked.
+        assert!(pos.func.dfg.is_gc_ref(r));
+        assert!(!pos.func.dfg.is_gc_ref(p));
+        pos.func.dfg.set_gc_ref(p, true);
+        assert!(pos.func.dfg.is_gc_ref(p));
+
+        // The mark survives replacing the value and turning it into an alias.
+        let p2 = pos.func.dfg.replace_block_param(p, types::I64);
+        assert!(pos.func.dfg.is_gc_ref(p2));
+        pos.func.dfg.set_gc_ref(v2, true);
+        let v3 = pos.ins().iconst(types::I64, 0);
+        let iadd = pos.func.dfg.value_def(v2).unwrap_inst();
+        pos.func.dfg.clear_results(iadd);
+        pos.func.dfg.change_to_alias(v2, v3);
+        assert!(pos.func.dfg.is_gc_ref(v3));
+        assert!(pos.func.dfg.is_gc_ref(v2));
+        assert!(!pos.func.dfg.is_gc_ref(v1));
+    }
 }
@@ -12,7 +12,7 @@ use crate::{
         FuncRef, GlobalValue, GlobalValueData, Heap, HeapData, Inst, InstEncodings,
         InstructionData, JumpTable, JumpTableData, JumpTableOffsets, JumpTables, Layout, Opcode,
         SigRef, Signature, SourceLocs, StackSlot, StackSlotData, StackSlots, Table, TableData,
-        ValueLocations,
+        UserTrapNames, ValueLocations,
     },
     isa::{CallConv, EncInfo, Encoding, Legalize, TargetIsa},
     regalloc::{EntryRegDiversions, RegDiversions},
@@ -432,6 +432,9 @@ pub struct DisplayFunctionAnnotations<'a> {
 
     /// Enable value labels annotations.
     pub value_ranges: Option<&'a ValueLabelsRanges>,
+
+    /// Enable annotations with the names of the user trap codes used.
+    pub user_trap_names: Option<&'a UserTrapNames>,
 }
 
 impl<'a> From<Option<&'a dyn TargetIsa>> for DisplayFunctionAnnotations<'a> {
@@ -439,6 +442,7 @@ impl<'a> From<Option<&'a dyn TargetIsa>> for DisplayFunctionAnnotations<'a> {
         DisplayFunctionAnnotations {
             isa,
             value_ranges: None,
+            user_trap_names: None,
         }
     }
 }
@@ -489,3 +493,91 @@ impl<'a> Iterator for InstOffsetIter<'a> {
         })
     }
 }
+
+#[cfg(all(test, feature = "enable-serde"))]
+mod tests {
+    use super::Function;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::{
+        condcodes::IntCC, immediates::Uimm64, types, AbiParam, ConstantData, ExternalName,
+        GlobalValueData, HeapData, HeapStyle, InstBuilder, JumpTableData, MemFlags, Signature,
+        StackSlotData, StackSlotKind, TableData, TrapCode,
+    };
+    use crate::isa::CallConv;
+    use alloc::string::ToString;
+
+    #[test]
+    fn serde_round_trip() {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I32));
+        sig.params.push(AbiParam::new(types::I64));
+        sig.returns.push(AbiParam::new(types::I32));
+        let mut func = Function::with_name_signature(ExternalName::testcase("round_trip"), sig);
+
+        let ss = func.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 16));
+        let vmctx = func.create_global_value(GlobalValueData::VMContext);
+        let base = func.create_global_value(GlobalValueData::Load {
+            base: vmctx,
+            offset: 8.into(),
+            global_type: types::I64,
+            readonly: true,
+        });
+        let heap = func.create_heap(HeapData {
+            base,
+            min_size: Uimm64::new(0x1000),
+            offset_guard_size: Uimm64::new(0x1000),
+            style: HeapStyle::Static {
+                bound: Uimm64::new(0x1_0000),
+            },
+            index_type: types::I32,
+        });
+        let table = func.create_table(TableData {
+            base_gv: base,
+            min_size: Uimm64::new(0),
+            bound_gv: vmctx,
+            element_size: Uimm64::new(8),
+            index_type: types::I32,
+        });
+        let constant = func
+            .dfg
+            .constants
+            .insert(ConstantData::from(&[1u8, 2, 3, 4][..]));
+
+        let block0 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+        let y = func.dfg.append_block_param(block0, types::I64);
+        let block1 = func.dfg.make_block();
+        let z = func.dfg.append_block_param(block1, types::I32);
+        let block2 = func.dfg.make_block();
+        let mut jt = JumpTableData::new();
+        jt.push_entry(block2);
+        let jt = func.create_jump_table(jt);
+
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(block0);
+        let addr = pos.ins().heap_addr(types::I64, heap, x, 4);
+        let loaded = pos.ins().load(types::I32, MemFlags::trusted(), addr, 0);
+        pos.ins().stack_store(loaded, ss, 4);
+        pos.ins().table_addr(types::I64, table, x, 0);
+        pos.ins().vconst(types::I32X4, constant);
+        let cond = pos.ins().icmp(IntCC::UnsignedLessThan, loaded, x);
+        pos.ins().trapnz(cond, TrapCode::HeapOutOfBounds);
+        let narrowed = pos.ins().ireduce(types::I32, y);
+        pos.ins().br_table(narrowed, block1, jt);
+
+        pos.insert_block(block2);
+        pos.ins().jump(block1, &[loaded]);
+
+        pos.insert_block(block1);
+        pos.ins().return_(&[z]);
+
+        let bytes = bincode::serialize(&func).expect("serialization failed");
+        let copy: Function = bincode::deserialize(&bytes).expect("deserialization failed");
+        assert_eq!(
+            copy.display(None).to_string(),
+            func.display(None).to_string()
+        );
+        assert_eq!(copy.layout.entry_block(), Some(block0));
+        assert_eq!(copy.dfg.value_type(z), types::I32);
+    }
+}
@@ -9,6 +9,7 @@ use crate::{
     machinst::RelocDistance,
 };
 use core::fmt;
+use core::str::FromStr;
 
 #[cfg(feature = "enable-serde")]
 use serde::{Deserialize, Serialize};
@@ -80,13 +81,36 @@ pub enum GlobalValueData {
         /// Does this symbol refer to a thread local storage value?
         tls: bool,
     },
+
+    /// Value is the address of a thread-local variable, named symbolically like `Symbol`.
+    ///
+    /// The address is computed for the current thread with the access sequence for `model` of
+    /// the TLS ABI selected by the `tls_model` setting. A `Symbol` with `tls` set leaves the
+    /// choice of model to the backend instead.
+    TlsSymbol {
+        /// The symbolic name.
+        name: ExternalName,
+
+        /// How the variable is accessed.
+        model: TlsAccessModel,
+    },
+
+    /// Value is the `vscale` of the target: the factor by which the minimum number of lanes of a
+    /// dynamic vector type is multiplied to give its actual number of lanes.
+    ///
+    /// The value doesn't change while the function executes. On targets without scalable vector
+    /// registers, dynamic vectors are the SIMD vectors of their minimum size, and it is 1.
+    VScale {
+        /// Type of the value.
+        global_type: Type,
+    },
 }
 
 impl GlobalValueData {
     /// Assume that `self` is an `GlobalValueData::Symbol` and return its name.
     pub fn symbol_name(&self) -> &ExternalName {
         match *self {
-            Self::Symbol { ref name, .. } => name,
+            Self::Symbol { ref name, .. } | Self::TlsSymbol { ref name, .. } => name,
             _ => panic!("only symbols have names"),
         }
     }
@@ -94,8 +118,12 @@ impl GlobalValueData {
     /// Return the type of this global.
     pub fn global_type(&self, isa: &dyn TargetIsa) -> Type {
         match *self {
-            Self::VMContext { .. } | Self::Symbol { .. } => isa.pointer_type(),
-            Self::IAddImm { global_type, .. } | Self::Load { global_type, .. } => global_type,
+            Self::VMContext { .. } | Self::Symbol { .. } | Self::TlsSymbol { .. } => {
+                isa.pointer_type()
+            }
+            Self::IAddImm { global_type, .. }
+            | Self::Load { global_type, .. }
+            | Self::VScale { global_type } => global_type,
         }
     }
 
@@ -158,6 +186,44 @@ impl fmt::Display for GlobalValueData {
                 }
                 Ok(())
             }
+            Self::TlsSymbol { ref name, model } => write!(f, "tls_symbol {} {}", model, name),
+            Self::VScale { global_type } => write!(f, "vscale.{}", global_type),
+        }
+    }
+}
+
+/// The TLS access model of a `GlobalValueData::TlsSymbol`.
+///
+/// The models trade generality for speed. A model that is more general than needed is always
+/// correct, so backends may fall back to one; on Mach-O, for example, there is only one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub enum TlsAccessModel {
+    /// The address is computed by calling into the runtime linker (`__tls_get_addr` on ELF
+    /// targets). This works for variables of any module, including ones loaded with `dlopen`.
+    GeneralDynamic,
+
+    /// The address is the thread pointer plus an offset loaded from the GOT. This only works for
+    /// variables of modules loaded at program startup, but doesn't need a call.
+    InitialExec,
+}
+
+impl fmt::Display for TlsAccessModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::GeneralDynamic => "general_dynamic",
+            Self::InitialExec => "initial_exec",
+        })
+    }
+}
+
+impl FromStr for TlsAccessModel {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "general_dynamic" => Ok(Self::GeneralDynamic),
+            "initial_exec" => Ok(Self::InitialExec),
+            _ => Err(()),
         }
     }
 }
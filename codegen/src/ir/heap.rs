@@ -37,6 +37,18 @@ pub enum HeapStyle {
         bound_gv: GlobalValue,
     },
 
+    /// A dynamically-bounded heap is a dynamic heap that doesn't rely on offset-guard pages or on
+    /// a faulting null page, for embedders that can't reserve such regions (32-bit hosts,
+    /// `no_std` environments).
+    ///
+    /// Every access that may exceed `min_size` is checked against the current bound, and the
+    /// index is masked to zero with a conditional move when the check fails, so that accesses
+    /// speculatively executed past the bounds check stay within the heap.
+    DynamicBounded {
+        /// Global value providing the current bound of the heap in bytes.
+        bound_gv: GlobalValue,
+    },
+
     /// A static heap has a fixed base address and a number of not-yet-allocated pages before the
     /// offset-guard pages.
     Static {
@@ -49,12 +61,15 @@ impl fmt::Display for HeapData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str(match self.style {
             HeapStyle::Dynamic { .. } => "dynamic",
+            HeapStyle::DynamicBounded { .. } => "dynamic_bounded",
             HeapStyle::Static { .. } => "static",
         })?;
 
         write!(f, " {}, min {}", self.base, self.min_size)?;
         match self.style {
-            HeapStyle::Dynamic { bound_gv } => write!(f, ", bound {}", bound_gv)?,
+            HeapStyle::Dynamic { bound_gv } | HeapStyle::DynamicBounded { bound_gv } => {
+                write!(f, ", bound {}", bound_gv)?
+            }
             HeapStyle::Static { bound } => write!(f, ", bound {}", bound)?,
         }
         write!(
@@ -76,6 +76,33 @@ impl Opcode {
             _ => false,
         }
     }
+
+    /// Returns true if the instruction is a tail call, which terminates its block and has no
+    /// results of its own.
+    pub fn is_tail_call(&self) -> bool {
+        match self {
+            Opcode::ReturnCall | Opcode::ReturnCallIndirect => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the instruction is a call with an exceptional successor, which also
+    /// makes it a branch to its landing pad.
+    pub fn is_invoke(&self) -> bool {
+        match self {
+            Opcode::Invoke | Opcode::InvokeIndirect => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the instruction is an annotation, which only conveys facts to the
+    /// optimizer and is removed before code generation.
+    pub fn is_annotation(&self) -> bool {
+        match self {
+            Opcode::AssertEq | Opcode::Assume => true,
+            _ => false,
+        }
+    }
 }
 
 impl TryFrom<NonZeroU32> for Opcode {
@@ -199,6 +226,10 @@ impl InstructionData {
     ///
     /// Any instruction that can transfer control to another block reveals its possible destinations
     /// here.
+    ///
+    /// The arguments of an invoke are its call arguments followed by its landing pad arguments,
+    /// which can only be told apart with the callee signature: use
+    /// `DataFlowGraph::analyze_branch` to get the landing pad arguments alone.
     pub fn analyze_branch<'a>(&'a self, pool: &'a ValueListPool) -> BranchInfo<'a> {
         match *self {
             Self::Jump {
@@ -226,6 +257,16 @@ impl InstructionData {
                 ref args,
                 ..
             } => BranchInfo::SingleDest(destination, &args.as_slice(pool)[2..]),
+            Self::Invoke {
+                destination,
+                ref args,
+                ..
+            } => BranchInfo::SingleDest(destination, args.as_slice(pool)),
+            Self::InvokeIndirect {
+                destination,
+                ref args,
+                ..
+            } => BranchInfo::SingleDest(destination, &args.as_slice(pool)[1..]),
             Self::BranchTable {
                 table, destination, ..
             } => BranchInfo::Table(table, Some(destination)),
@@ -247,7 +288,9 @@ impl InstructionData {
             | Self::Branch { destination, .. }
             | Self::BranchInt { destination, .. }
             | Self::BranchFloat { destination, .. }
-            | Self::BranchIcmp { destination, .. } => Some(destination),
+            | Self::BranchIcmp { destination, .. }
+            | Self::Invoke { destination, .. }
+            | Self::InvokeIndirect { destination, .. } => Some(destination),
             Self::BranchTable { .. } | Self::IndirectJump { .. } => None,
             _ => {
                 debug_assert!(!self.opcode().is_branch());
@@ -281,6 +324,14 @@ impl InstructionData {
             | Self::BranchIcmp {
                 ref mut destination,
                 ..
+            }
+            | Self::Invoke {
+                ref mut destination,
+                ..
+            }
+            | Self::InvokeIndirect {
+                ref mut destination,
+                ..
             } => Some(destination),
             Self::BranchTable { .. } | Self::IndirectJump { .. } => None,
             _ => {
@@ -384,6 +435,14 @@ impl InstructionData {
         }
     }
 
+    /// If this is a memory fence, return its ordering.
+    pub fn memory_ordering(&self) -> Option<ir::MemoryOrdering> {
+        match self {
+            &InstructionData::Fence { ordering, .. } => Some(ordering),
+            _ => None,
+        }
+    }
+
     /// If this is a load/store instruction, returns its immediate offset.
     pub fn load_store_offset(&self) -> Option<i32> {
         match self {
@@ -400,13 +459,22 @@ impl InstructionData {
     /// Return information about a call instruction.
     ///
     /// Any instruction that can call another function reveals its call signature here.
+    ///
+    /// The arguments of an invoke include its landing pad arguments, see `analyze_branch`; use
+    /// `DataFlowGraph::analyze_call` to get the call arguments alone.
     pub fn analyze_call<'a>(&'a self, pool: &'a ValueListPool) -> CallInfo<'a> {
         match *self {
             Self::Call {
                 func_ref, ref args, ..
+            }
+            | Self::Invoke {
+                func_ref, ref args, ..
             } => CallInfo::Direct(func_ref, args.as_slice(pool)),
             Self::CallIndirect {
                 sig_ref, ref args, ..
+            }
+            | Self::InvokeIndirect {
+                sig_ref, ref args, ..
             } => CallInfo::Indirect(sig_ref, &args.as_slice(pool)[1..]),
             _ => {
                 debug_assert!(!self.opcode().is_call());
@@ -439,7 +507,7 @@ impl InstructionData {
                 cond,
                 imm,
             } => {
-                debug_assert_eq!(*opcode, Opcode::IcmpImm);
+                debug_assert!(*opcode == Opcode::IcmpImm || *opcode == Opcode::Assume);
                 if cond.unsigned() != *cond {
                     imm.sign_extend_from_width(bit_width);
                 }
@@ -610,6 +678,8 @@ type BitSet16 = BitSet<u16>;
 pub struct ValueTypeSet {
     /// Allowed lane sizes
     pub lanes: BitSet16,
+    /// Allowed minimum lane sizes of dynamic vectors
+    pub dynamic_lanes: BitSet16,
     /// Allowed int widths
     pub ints: BitSet8,
     /// Allowed float widths
@@ -641,8 +711,13 @@ impl ValueTypeSet {
 
     /// Does `typ` belong to this set?
     pub fn contains(self, typ: Type) -> bool {
-        let l2l = typ.log2_lane_count();
-        self.lanes.contains(l2l) && self.is_base_type(typ.lane_type())
+        if typ.is_dynamic_vector() {
+            let l2l = typ.log2_min_lane_count();
+            self.dynamic_lanes.contains(l2l) && self.is_base_type(typ.lane_type())
+        } else {
+            let l2l = typ.log2_lane_count();
+            self.lanes.contains(l2l) && self.is_base_type(typ.lane_type())
+        }
     }
 
     /// Get an example member of this type set.
@@ -840,6 +915,7 @@ mod tests {
 
         let vts = ValueTypeSet {
             lanes: BitSet16::from_range(0, 8),
+            dynamic_lanes: BitSet16::from_range(0, 0),
             ints: BitSet8::from_range(4, 7),
             floats: BitSet8::from_range(0, 0),
             bools: BitSet8::from_range(3, 7),
@@ -859,6 +935,7 @@ mod tests {
 
         let vts = ValueTypeSet {
             lanes: BitSet16::from_range(0, 8),
+            dynamic_lanes: BitSet16::from_range(0, 0),
             ints: BitSet8::from_range(0, 0),
             floats: BitSet8::from_range(5, 7),
             bools: BitSet8::from_range(3, 7),
@@ -868,6 +945,7 @@ mod tests {
 
         let vts = ValueTypeSet {
             lanes: BitSet16::from_range(1, 8),
+            dynamic_lanes: BitSet16::from_range(0, 0),
             ints: BitSet8::from_range(0, 0),
             floats: BitSet8::from_range(5, 7),
             bools: BitSet8::from_range(3, 7),
@@ -877,6 +955,7 @@ mod tests {
 
         let vts = ValueTypeSet {
             lanes: BitSet16::from_range(2, 8),
+            dynamic_lanes: BitSet16::from_range(0, 0),
             ints: BitSet8::from_range(0, 0),
             floats: BitSet8::from_range(0, 0),
             bools: BitSet8::from_range(3, 7),
@@ -889,6 +968,7 @@ mod tests {
         let vts = ValueTypeSet {
             // TypeSet(lanes=(1, 256), ints=(8, 64))
             lanes: BitSet16::from_range(0, 9),
+            dynamic_lanes: BitSet16::from_range(0, 0),
             ints: BitSet8::from_range(3, 7),
             floats: BitSet8::from_range(0, 0),
             bools: BitSet8::from_range(0, 0),
@@ -898,5 +978,21 @@ mod tests {
         assert!(vts.contains(I32X4));
         assert!(!vts.contains(R32));
         assert!(!vts.contains(R64));
+        assert!(!vts.contains(I32X4XN));
+
+        let vts = ValueTypeSet {
+            // TypeSet(lanes=(1, 256), dynamic_lanes=(2, 16), ints=(8, 64))
+            lanes: BitSet16::from_range(0, 9),
+            dynamic_lanes: BitSet16::from_range(1, 5),
+            ints: BitSet8::from_range(3, 7),
+            floats: BitSet8::from_range(0, 0),
+            bools: BitSet8::from_range(0, 0),
+            refs: BitSet8::from_range(0, 0),
+        };
+        assert!(vts.contains(I32X4));
+        assert!(vts.contains(I32X4XN));
+        assert!(vts.contains(I8X16XN));
+        assert!(!vts.contains(I8X32XN));
+        assert!(!vts.contains(F32X4XN));
     }
 }
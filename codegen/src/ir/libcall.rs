@@ -23,7 +23,10 @@ use serde::{Deserialize, Serialize};
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub enum LibCall {
     /// probe for stack overflow. These are emitted for functions which need
-    /// when the `enable_probestack` setting is true.
+    /// when the `enable_probestack` setting is true, and always for large
+    /// frames of Windows fastcall functions. On x86-64, the function follows
+    /// the convention of Windows' `__chkstk`: the frame size is passed in rax
+    /// and the stack pointer is left unchanged.
     Probestack,
     /// udiv.i64
     UdivI64,
@@ -39,6 +42,14 @@ pub enum LibCall {
     UshrI64,
     /// sshr.i64
     SshrI64,
+    /// udiv.i128
+    UdivI128,
+    /// sdiv.i128
+    SdivI128,
+    /// urem.i128
+    UremI128,
+    /// srem.i128
+    SremI128,
     /// ceil.f32
     CeilF32,
     /// ceil.f64
@@ -55,15 +66,29 @@ pub enum LibCall {
     NearestF32,
     /// nearest.f64
     NearestF64,
+    /// fpromote.f32 of an f16
+    F16ToF32,
+    /// fdemote.f16 of an f32
+    F32ToF16,
+    /// fdemote.f16 of an f64
+    F64ToF16,
     /// libc.memcpy
     Memcpy,
     /// libc.memset
     Memset,
     /// libc.memmove
     Memmove,
+    /// atomic_cas.i128, for targets without a 128-bit compare-and-swap instruction. It takes
+    /// the address, the expected value and the replacement value, and returns the old value.
+    AtomicCasI128,
 
     /// Elf __tls_get_addr
     ElfTlsGetAddr,
+
+    /// The trap handler called by trap instructions when the `trap_encoding` setting is
+    /// `call_handler`. It takes no arguments, and the trap site is found from the return address.
+    /// It must preserve all registers if it returns, which it may only do for resumable traps.
+    TrapHandler,
     // When adding a new variant make sure to add it to `all_libcalls` too.
 }
 
@@ -86,6 +111,10 @@ impl FromStr for LibCall {
             "IshlI64" => Ok(Self::IshlI64),
             "UshrI64" => Ok(Self::UshrI64),
             "SshrI64" => Ok(Self::SshrI64),
+            "UdivI128" => Ok(Self::UdivI128),
+            "SdivI128" => Ok(Self::SdivI128),
+            "UremI128" => Ok(Self::UremI128),
+            "SremI128" => Ok(Self::SremI128),
             "CeilF32" => Ok(Self::CeilF32),
             "CeilF64" => Ok(Self::CeilF64),
             "FloorF32" => Ok(Self::FloorF32),
@@ -94,11 +123,16 @@ impl FromStr for LibCall {
             "TruncF64" => Ok(Self::TruncF64),
             "NearestF32" => Ok(Self::NearestF32),
             "NearestF64" => Ok(Self::NearestF64),
+            "F16ToF32" => Ok(Self::F16ToF32),
+            "F32ToF16" => Ok(Self::F32ToF16),
+            "F64ToF16" => Ok(Self::F64ToF16),
             "Memcpy" => Ok(Self::Memcpy),
             "Memset" => Ok(Self::Memset),
             "Memmove" => Ok(Self::Memmove),
+            "AtomicCasI128" => Ok(Self::AtomicCasI128),
 
             "ElfTlsGetAddr" => Ok(Self::ElfTlsGetAddr),
+            "TrapHandler" => Ok(Self::TrapHandler),
             _ => Err(()),
         }
     }
@@ -121,6 +155,14 @@ impl LibCall {
                 Opcode::Sshr => Self::SshrI64,
                 _ => return None,
             },
+            types::I128 => match opcode {
+                Opcode::Udiv => Self::UdivI128,
+                Opcode::Sdiv => Self::SdivI128,
+                Opcode::Urem => Self::UremI128,
+                Opcode::Srem => Self::SremI128,
+                Opcode::AtomicCas => Self::AtomicCasI128,
+                _ => return None,
+            },
             types::F32 => match opcode {
                 Opcode::Ceil => Self::CeilF32,
                 Opcode::Floor => Self::FloorF32,
@@ -151,6 +193,10 @@ impl LibCall {
             IshlI64,
             UshrI64,
             SshrI64,
+            UdivI128,
+            SdivI128,
+            UremI128,
+            SremI128,
             CeilF32,
             CeilF64,
             FloorF32,
@@ -159,10 +205,15 @@ impl LibCall {
             TruncF64,
             NearestF32,
             NearestF64,
+            F16ToF32,
+            F32ToF16,
+            F64ToF16,
             Memcpy,
             Memset,
             Memmove,
+            AtomicCasI128,
             ElfTlsGetAddr,
+            TrapHandler,
         ]
     }
 }
@@ -0,0 +1,46 @@
+/// Describes the ordering constraints of a memory fence.
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+#[cfg(feature = "enable-serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+/// Describes the ordering constraints of a memory fence, with the same meaning as the
+/// corresponding C++11 memory orders.
+pub enum MemoryOrdering {
+    /// No load or store after the fence may be reordered before any load before it.
+    Acquire,
+    /// No load or store before the fence may be reordered after any store after it.
+    Release,
+    /// Both `Acquire` and `Release`.
+    AcqRel,
+    /// `AcqRel`, and additionally part of a single total order of sequentially consistent
+    /// operations, so that no store before the fence may be reordered after a load after it.
+    SeqCst,
+}
+
+impl Display for MemoryOrdering {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let s = match self {
+            MemoryOrdering::Acquire => "acquire",
+            MemoryOrdering::Release => "release",
+            MemoryOrdering::AcqRel => "acq_rel",
+            MemoryOrdering::SeqCst => "seq_cst",
+        };
+        f.write_str(s)
+    }
+}
+
+impl FromStr for MemoryOrdering {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "acquire" => Ok(MemoryOrdering::Acquire),
+            "release" => Ok(MemoryOrdering::Release),
+            "acq_rel" => Ok(MemoryOrdering::AcqRel),
+            "seq_cst" => Ok(MemoryOrdering::SeqCst),
+            _ => Err(()),
+        }
+    }
+}
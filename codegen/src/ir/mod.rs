@@ -1,6 +1,8 @@
 //! Representation of Cranelift IR functions.
 
 mod atomic_rmw_op;
+#[cfg(feature = "binary-ir")]
+pub mod binary;
 mod builder;
 pub mod constant;
 pub mod dfg;
@@ -16,6 +18,7 @@ pub mod jumptable;
 pub mod layout;
 pub(crate) mod libcall;
 mod memflags;
+mod memory_ordering;
 mod progpoint;
 mod sourceloc;
 pub mod stackslot;
@@ -40,18 +43,19 @@ pub use crate::{
         extfunc::{AbiParam, ArgumentExtension, ArgumentPurpose, ExtFuncData, Signature},
         extname::ExternalName,
         function::{DisplayFunctionAnnotations, Function},
-        globalvalue::GlobalValueData,
+        globalvalue::{GlobalValueData, TlsAccessModel},
         heap::{HeapData, HeapStyle},
         instructions::{InstructionData, Opcode, ValueList, ValueListPool, VariableArgs},
         jumptable::JumpTableData,
         layout::Layout,
         libcall::{get_probestack_funcref, LibCall},
         memflags::{Endianness, MemFlags},
+        memory_ordering::MemoryOrdering,
         progpoint::{ExpandedProgramPoint, ProgramOrder, ProgramPoint},
         sourceloc::SourceLoc,
         stackslot::{StackLayoutInfo, StackSlotData, StackSlotKind, StackSlots},
         table::TableData,
-        trapcode::TrapCode,
+        trapcode::{DisplayTrapCode, TrapCode, UserTrapNames},
         types::Type,
         valueloc::{ArgumentLoc, ValueLoc},
     },
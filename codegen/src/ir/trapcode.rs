@@ -1,5 +1,7 @@
 //! Trap codes describing the reason for a trap.
 
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
 use core::{
     fmt::{self, Display, Formatter},
     str::FromStr,
@@ -99,6 +101,97 @@ impl FromStr for TrapCode {
     }
 }
 
+/// Human-readable names of `TrapCode::User` codes.
+///
+/// Language runtimes can encode their own causes of traps in user trap codes, instead of
+/// multiplexing them onto the generic codes. Naming those codes here lets printed functions and
+/// trap reports refer to the causes, and lets `parse` read the names back.
+#[derive(Clone, Debug, Default)]
+pub struct UserTrapNames {
+    names: BTreeMap<u16, String>,
+    codes: BTreeMap<String, u16>,
+}
+
+impl UserTrapNames {
+    /// No names.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the user trap code `code` `name`, replacing its previous name.
+    ///
+    /// Panics if `name` isn't made of ASCII alphanumeric characters and underscores, starting
+    /// with a letter or an underscore, if it is the name of a trap code already, e.g. `heap_oob`
+    /// or `user3`, or if it names another user trap code.
+    pub fn register(&mut self, code: u16, name: &str) {
+        let mut chars = name.chars();
+        assert!(
+            chars
+                .next()
+                .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            "invalid name for a user trap code: {:?}",
+            name
+        );
+        assert!(
+            name.parse::<TrapCode>().is_err(),
+            "{} is already the name of a trap code",
+            name
+        );
+        assert!(
+            self.codes.get(name).map_or(true, |&other| other == code),
+            "{} already names another user trap code",
+            name
+        );
+
+        if let Some(old) = self.names.insert(code, name.to_string()) {
+            self.codes.remove(&old);
+        }
+        self.codes.insert(name.to_string(), code);
+    }
+
+    /// The name of `code`, if it is a named user trap code.
+    pub fn name(&self, code: TrapCode) -> Option<&str> {
+        match code {
+            TrapCode::User(code) => self.names.get(&code).map(String::as_str),
+            _ => None,
+        }
+    }
+
+    /// The user trap codes with names, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter().map(|(&code, name)| (code, name.as_str()))
+    }
+
+    /// Parse a trap code, which may be the name of a user trap code.
+    pub fn parse(&self, s: &str) -> Result<TrapCode, ()> {
+        match self.codes.get(s) {
+            Some(&code) => Ok(TrapCode::User(code)),
+            None => s.parse(),
+        }
+    }
+
+    /// An object displaying `code` with its name, if it has one.
+    pub fn display(&self, code: TrapCode) -> DisplayTrapCode {
+        DisplayTrapCode { names: self, code }
+    }
+}
+
+/// Displays a trap code, with its name if it is a named user trap code.
+pub struct DisplayTrapCode<'a> {
+    names: &'a UserTrapNames,
+    code: TrapCode,
+}
+
+impl<'a> Display for DisplayTrapCode<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.names.name(self.code) {
+            Some(name) => f.write_str(name),
+            None => self.code.fmt(f),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,4 +226,38 @@ mod tests {
         assert_eq!("user-1".parse::<TrapCode>(), Err(()));
         assert_eq!("users".parse::<TrapCode>(), Err(()));
     }
+
+    #[test]
+    fn user_trap_names() {
+        let mut names = UserTrapNames::new();
+        names.register(3, "out_of_fuel");
+        names.register(7, "null_reference");
+        names.register(7, "null_deref");
+
+        assert_eq!(names.name(TrapCode::User(3)), Some("out_of_fuel"));
+        assert_eq!(names.name(TrapCode::User(4)), None);
+        assert_eq!(names.name(TrapCode::StackOverflow), None);
+        assert_eq!(
+            names.iter().collect::<alloc::vec::Vec<_>>(),
+            [(3, "out_of_fuel"), (7, "null_deref")]
+        );
+
+        assert_eq!(names.display(TrapCode::User(7)).to_string(), "null_deref");
+        assert_eq!(names.display(TrapCode::User(8)).to_string(), "user8");
+        assert_eq!(
+            names.display(TrapCode::HeapOutOfBounds).to_string(),
+            "heap_oob"
+        );
+
+        assert_eq!(names.parse("null_deref"), Ok(TrapCode::User(7)));
+        assert_eq!(names.parse("null_reference"), Err(()));
+        assert_eq!(names.parse("user7"), Ok(TrapCode::User(7)));
+        assert_eq!(names.parse("int_ovf"), Ok(TrapCode::IntegerOverflow));
+    }
+
+    #[test]
+    #[should_panic(expected = "already the name of a trap code")]
+    fn user_trap_names_shadowing_codes() {
+        UserTrapNames::new().register(3, "user4");
+    }
 }
@@ -17,16 +17,21 @@ use target_lexicon::{PointerWidth, Triple};
 ///
 /// Basic integer types: `I8`, `I16`, `I32`, `I64`, and `I128`. These types are sign-agnostic.
 ///
-/// Basic floating point types: `F32` and `F64`. IEEE single and double precision.
+/// Basic floating point types: `F16`, `F32` and `F64`. IEEE half, single and double precision.
+/// `F16` is a storage format: it can be loaded, stored and converted, but not computed with.
 ///
 /// Boolean types: `B1`, `B8`, `B16`, `B32`, `B64`, and `B128`. These all encode 'true' or 'false'. The
 /// larger types use redundant bits.
 ///
 /// SIMD vector types have power-of-two lanes, up to 256. Lanes can be any int/float/bool type.
 ///
+/// Dynamic vector types, such as `i32x4xN`, have a power-of-two minimum number of lanes, which
+/// is multiplied by the target's `vscale` to give the actual number of lanes. Their size is only
+/// known at run time, as on targets with scalable vector registers.
+///
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
-pub struct Type(u8);
+pub struct Type(u16);
 
 /// Not a valid type. Can't be loaded or stored. Can't be part of a SIMD vector.
 pub const INVALID: Type = Type(0);
@@ -60,7 +65,7 @@ impl Type {
         match self.lane_type() {
             B1 => 0,
             B8 | I8 => 3,
-            B16 | I16 => 4,
+            B16 | I16 | F16 => 4,
             B32 | I32 | F32 | R32 => 5,
             B64 | I64 | F64 | R64 => 6,
             B128 | I128 => 7,
@@ -73,7 +78,7 @@ impl Type {
         match self.lane_type() {
             B1 => 1,
             B8 | I8 => 8,
-            B16 | I16 => 16,
+            B16 | I16 | F16 => 16,
             B32 | I32 | F32 | R32 => 32,
             B64 | I64 | F64 | R64 => 64,
             B128 | I128 => 128,
@@ -96,7 +101,7 @@ impl Type {
     /// Get a type with the same number of lanes as `self`, but using `lane` as the lane type.
     fn replace_lanes(self, lane: Self) -> Self {
         debug_assert!(lane.is_lane() && !self.is_special());
-        Self((lane.0 & 0x0f) | (self.0 & 0xf0))
+        Self((lane.0 & 0x0f) | (self.0 & !0x0f))
     }
 
     /// Get a type with the same number of lanes as this type, but with the lanes replaced by
@@ -108,7 +113,7 @@ impl Type {
         // Replace the low 4 bits with the boolean version, preserve the high 4 bits.
         self.replace_lanes(match self.lane_type() {
             B8 | I8 => B8,
-            B16 | I16 => B16,
+            B16 | I16 | F16 => B16,
             B32 | I32 | F32 => B32,
             B64 | I64 | F64 => B64,
             R32 | R64 => panic!("Reference types should not convert to bool"),
@@ -122,7 +127,7 @@ impl Type {
     ///
     /// Scalar types are all converted to `b1` which is usually what you want.
     pub fn as_bool(self) -> Self {
-        if !self.is_vector() {
+        if !self.is_vector() && !self.is_dynamic_vector() {
             B1
         } else {
             self.as_bool_pedantic()
@@ -137,6 +142,7 @@ impl Type {
             I32 => I16,
             I64 => I32,
             I128 => I64,
+            F32 => F16,
             F64 => F32,
             B16 => B8,
             B32 => B16,
@@ -154,6 +160,7 @@ impl Type {
             I16 => I32,
             I32 => I64,
             I64 => I128,
+            F16 => F32,
             F32 => F64,
             B8 => B16,
             B16 => B32,
@@ -182,9 +189,16 @@ impl Type {
 
     /// Is this a SIMD vector type?
     ///
-    /// A vector type has 2 or more lanes.
+    /// A vector type has 2 or more lanes. Dynamic vectors aren't SIMD vector types.
     pub fn is_vector(self) -> bool {
-        self.0 >= constants::VECTOR_BASE
+        self.0 >= constants::VECTOR_BASE && !self.is_dynamic_vector()
+    }
+
+    /// Is this a dynamic vector type?
+    ///
+    /// A dynamic vector type has a minimum of 2 or more lanes, scaled by `vscale`.
+    pub fn is_dynamic_vector(self) -> bool {
+        self.0 >= constants::DYNAMIC_VECTOR_BASE
     }
 
     /// Is this a scalar boolean type?
@@ -206,7 +220,7 @@ impl Type {
     /// Is this a scalar floating point type?
     pub fn is_float(self) -> bool {
         match self {
-            F32 | F64 => true,
+            F16 | F32 | F64 => true,
             _ => false,
         }
     }
@@ -232,23 +246,56 @@ impl Type {
     /// All SIMD types have a lane count that is a power of two and no larger than 256, so this
     /// will be a number in the range 0-8.
     ///
-    /// A scalar type is the same as a SIMD vector type with one lane, so it returns 0.
+    /// A scalar type is the same as a SIMD vector type with one lane, so it returns 0. The number
+    /// of lanes of a dynamic vector type isn't known, so it also returns 0; see
+    /// `log2_min_lane_count`.
     pub fn log2_lane_count(self) -> u8 {
-        self.0.saturating_sub(constants::LANE_BASE) >> 4
+        if self.is_dynamic_vector() {
+            0
+        } else {
+            (self.0.saturating_sub(constants::LANE_BASE) >> 4) as u8
+        }
+    }
+
+    /// Get log_2 of the minimum number of lanes in this vector type, which is the same as
+    /// `log2_lane_count` for all but the dynamic vector types.
+    pub fn log2_min_lane_count(self) -> u8 {
+        if self.is_dynamic_vector() {
+            self.dynamic_to_vector().unwrap().log2_lane_count()
+        } else {
+            self.log2_lane_count()
+        }
     }
 
     /// Get the number of lanes in this SIMD vector type.
     ///
-    /// A scalar type is the same as a SIMD vector type with one lane, so it returns 1.
+    /// A scalar type is the same as a SIMD vector type with one lane, so it returns 1. Dynamic
+    /// vector types have no fixed number of lanes, so they return 0.
     pub fn lane_count(self) -> u16 {
-        1 << self.log2_lane_count()
+        if self.is_dynamic_vector() {
+            0
+        } else {
+            1 << self.log2_lane_count()
+        }
+    }
+
+    /// Get the minimum number of lanes in this vector type, which is the same as `lane_count`
+    /// for all but the dynamic vector types.
+    pub fn min_lane_count(self) -> u16 {
+        1 << self.log2_min_lane_count()
     }
 
-    /// Get the total number of bits used to represent this type.
+    /// Get the total number of bits used to represent this type, or 0 for dynamic vector types.
     pub fn bits(self) -> u16 {
         u16::from(self.lane_bits()) * self.lane_count()
     }
 
+    /// Get the minimum number of bits used to represent this type, which is the same as `bits`
+    /// for all but the dynamic vector types.
+    pub fn min_bits(self) -> u16 {
+        u16::from(self.lane_bits()) * self.min_lane_count()
+    }
+
     /// Get the number of bytes used to store this type in memory.
     pub fn bytes(self) -> u32 {
         (u32::from(self.bits()) + 7) / 8
@@ -260,14 +307,23 @@ impl Type {
     ///
     /// If this is already a SIMD vector type, this produces a SIMD vector type with `n *
     /// self.lane_count()` lanes.
+    ///
+    /// If this is a dynamic vector type, this produces a dynamic vector type with `n` times more
+    /// lanes at the minimum.
     pub fn by(self, n: u16) -> Option<Self> {
         if self.lane_bits() == 0 || !n.is_power_of_two() {
             return None;
         }
         let log2_lanes: u32 = n.trailing_zeros();
         let new_type = u32::from(self.0) + (log2_lanes << 4);
-        if new_type < 0x100 {
-            Some(Self(new_type as u8))
+        let limit = if self.is_dynamic_vector() {
+            constants::DYNAMIC_VECTOR_BASE
+                + (constants::DYNAMIC_VECTOR_BASE - constants::VECTOR_BASE)
+        } else {
+            constants::DYNAMIC_VECTOR_BASE
+        };
+        if new_type < u32::from(limit) {
+            Some(Self(new_type as u16))
         } else {
             None
         }
@@ -277,13 +333,35 @@ impl Type {
     ///
     /// There is no `double_vector()` method. Use `t.by(2)` instead.
     pub fn half_vector(self) -> Option<Self> {
-        if self.is_vector() {
+        if self.is_vector() || (self.is_dynamic_vector() && self.log2_min_lane_count() > 1) {
             Some(Self(self.0 - 0x10))
         } else {
             None
         }
     }
 
+    /// Get the dynamic vector type with the lanes of this SIMD vector type as its minimum.
+    pub fn vector_to_dynamic(self) -> Option<Self> {
+        if self.is_vector() {
+            Some(Self(
+                self.0 + constants::DYNAMIC_VECTOR_BASE - constants::VECTOR_BASE,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Get the SIMD vector type with the minimum lanes of this dynamic vector type.
+    pub fn dynamic_to_vector(self) -> Option<Self> {
+        if self.is_dynamic_vector() {
+            Some(Self(
+                self.0 - constants::DYNAMIC_VECTOR_BASE + constants::VECTOR_BASE,
+            ))
+        } else {
+            None
+        }
+    }
+
     /// Split the lane width in half and double the number of lanes to maintain the same bit-width.
     ///
     /// If this is a scalar type of `n` bits, it produces a SIMD vector type of `(n/2)x2`.
@@ -339,6 +417,8 @@ impl Display for Type {
             write!(f, "f{}", self.lane_bits())
         } else if self.is_vector() {
             write!(f, "{}x{}", self.lane_type(), self.lane_count())
+        } else if self.is_dynamic_vector() {
+            write!(f, "{}x{}xN", self.lane_type(), self.min_lane_count())
         } else if self.is_ref() {
             write!(f, "r{}", self.lane_bits())
         } else {
@@ -363,6 +443,8 @@ impl Debug for Type {
             write!(f, "types::F{}", self.lane_bits())
         } else if self.is_vector() {
             write!(f, "{:?}X{}", self.lane_type(), self.lane_count())
+        } else if self.is_dynamic_vector() {
+            write!(f, "{:?}X{}XN", self.lane_type(), self.min_lane_count())
         } else if self.is_ref() {
             write!(f, "types::R{}", self.lane_bits())
         } else {
@@ -406,6 +488,7 @@ mod tests {
         assert_eq!(I32, I32.lane_type());
         assert_eq!(I64, I64.lane_type());
         assert_eq!(I128, I128.lane_type());
+        assert_eq!(F16, F16.lane_type());
         assert_eq!(F32, F32.lane_type());
         assert_eq!(F64, F64.lane_type());
         assert_eq!(B1, B1.by(8).unwrap().lane_type());
@@ -428,6 +511,7 @@ mod tests {
         assert_eq!(I32.lane_bits(), 32);
         assert_eq!(I64.lane_bits(), 64);
         assert_eq!(I128.lane_bits(), 128);
+        assert_eq!(F16.lane_bits(), 16);
         assert_eq!(F32.lane_bits(), 32);
         assert_eq!(F64.lane_bits(), 64);
         assert_eq!(R32.lane_bits(), 32);
@@ -451,7 +535,8 @@ mod tests {
         assert_eq!(I32X4.half_width(), Some(I16X4));
         assert_eq!(I64.half_width(), Some(I32));
         assert_eq!(I128.half_width(), Some(I64));
-        assert_eq!(F32.half_width(), None);
+        assert_eq!(F16.half_width(), None);
+        assert_eq!(F32.half_width(), Some(F16));
         assert_eq!(F64.half_width(), Some(F32));
 
         assert_eq!(INVALID.double_width(), None);
@@ -469,6 +554,7 @@ mod tests {
         assert_eq!(I32X4.double_width(), Some(I64X4));
         assert_eq!(I64.double_width(), Some(I128));
         assert_eq!(I128.double_width(), None);
+        assert_eq!(F16.double_width(), Some(F32));
         assert_eq!(F32.double_width(), Some(F64));
         assert_eq!(F64.double_width(), None);
     }
@@ -505,6 +591,7 @@ mod tests {
         assert_eq!(I32.to_string(), "i32");
         assert_eq!(I64.to_string(), "i64");
         assert_eq!(I128.to_string(), "i128");
+        assert_eq!(F16.to_string(), "f16");
         assert_eq!(F32.to_string(), "f32");
         assert_eq!(F64.to_string(), "f64");
         assert_eq!(R32.to_string(), "r32");
@@ -520,16 +607,45 @@ mod tests {
         assert_eq!(B64.by(8).unwrap().to_string(), "b64x8");
         assert_eq!(I8.by(64).unwrap().to_string(), "i8x64");
         assert_eq!(F64.by(2).unwrap().to_string(), "f64x2");
+        assert_eq!(F16.by(8).unwrap().to_string(), "f16x8");
         assert_eq!(I8.by(3), None);
         assert_eq!(I8.by(512), None);
         assert_eq!(INVALID.by(4), None);
     }
 
+    #[test]
+    fn dynamic_vectors() {
+        assert!(I32X4XN.is_dynamic_vector());
+        assert!(!I32X4XN.is_vector());
+        assert!(!I32X4.is_dynamic_vector());
+        assert_eq!(I32X4XN.lane_type(), I32);
+        assert_eq!(I32X4XN.lane_count(), 0);
+        assert_eq!(I32X4XN.min_lane_count(), 4);
+        assert_eq!(I32X4XN.bits(), 0);
+        assert_eq!(I32X4XN.min_bits(), 128);
+        assert_eq!(I32X4XN.as_bool(), B32X4XN);
+
+        assert_eq!(I32X4.vector_to_dynamic(), Some(I32X4XN));
+        assert_eq!(I32X4XN.dynamic_to_vector(), Some(I32X4));
+        assert_eq!(I32.vector_to_dynamic(), None);
+        assert_eq!(I32X4.dynamic_to_vector(), None);
+
+        assert_eq!(I32X4XN.by(2), Some(I32X8XN));
+        assert_eq!(I32X4XN.half_vector(), Some(I32X2XN));
+        assert_eq!(I32X2XN.half_vector(), None);
+        assert_eq!(I8X16XN.by(32), None);
+
+        assert_eq!(I32X4XN.to_string(), "i32x4xN");
+        assert_eq!(F64X2XN.to_string(), "f64x2xN");
+        assert_eq!(alloc::format!("{:?}", I16X8XN), "types::I16X8XN");
+    }
+
     #[test]
     fn as_bool() {
         assert_eq!(I32X4.as_bool(), B32X4);
         assert_eq!(I32.as_bool(), B1);
         assert_eq!(I32X4.as_bool_pedantic(), B32X4);
         assert_eq!(I32.as_bool_pedantic(), B32);
+        assert_eq!(F16.as_bool_pedantic(), B16);
     }
 }
@@ -5,7 +5,10 @@ use crate::{
     ir::{types, types::*, ExternalName, LibCall, MemFlags, Opcode},
     isa,
     isa::{
-        aarch64::inst::{EmitState, *},
+        aarch64::{
+            inst::{EmitState, *},
+            lower::lower_condcode,
+        },
         unwind::UnwindInst,
     },
     machinst::*,
@@ -554,6 +557,46 @@ impl ABIMachineSpec for AArch64MachineDeps {
         Inst::EpiloguePlaceholder
     }
 
+    fn gen_cond_br_imm(
+        reg: Reg,
+        imm: u64,
+        cond: ir::condcodes::IntCC,
+        taken: MachLabel,
+        not_taken: MachLabel,
+        tmp: Writable<Reg>,
+    ) -> SmallInstVec<Inst> {
+        let mut insts = SmallVec::new();
+        if let Some(imm12) = Imm12::maybe_from_u64(imm) {
+            insts.push(Inst::AluRRImm12 {
+                alu_op: ALUOp::SubS64,
+                rd: writable_zero_reg(),
+                rn: reg,
+                imm12,
+            });
+        } else {
+            insts.extend(Inst::load_constant(tmp, imm));
+            insts.push(Inst::AluRRR {
+                alu_op: ALUOp::SubS64,
+                rd: writable_zero_reg(),
+                rn: reg,
+                rm: tmp.to_reg(),
+            });
+        }
+        insts.push(Inst::CondBr {
+            taken: BranchTarget::Label(taken),
+            not_taken: BranchTarget::Label(not_taken),
+            kind: CondBrKind::Cond(lower_condcode(cond)),
+        });
+        insts
+    }
+
+    fn gen_tail_jump(target: Reg) -> Inst {
+        Inst::IndirectBr {
+            rn: target,
+            targets: vec![],
+        }
+    }
+
     fn gen_get_stack_addr(mem: StackAMode, into_reg: Writable<Reg>, _ty: Type) -> Inst {
         let mem = mem.into();
         Inst::LoadAddr { rd: into_reg, mem }
@@ -687,6 +730,37 @@ impl ABIMachineSpec for AArch64MachineDeps {
         smallvec![]
     }
 
+    fn gen_inline_probestack(frame_size: u32, guard_size: u32) -> SmallInstVec<Self::I> {
+        // Small frames get one probe per page; larger ones use a loop so the
+        // prologue doesn't grow with the frame.
+        const PROBE_MAX_UNROLL: u32 = 3;
+        let probe_count = frame_size / guard_size;
+        let mut insts = SmallVec::new();
+        if probe_count == 0 {
+            // Nothing to probe.
+        } else if probe_count <= PROBE_MAX_UNROLL
+            || Imm12::maybe_from_u64(guard_size as u64).is_none()
+        {
+            // Move SP down a page at a time and store to it, so that no store
+            // lands below SP, then put SP back.
+            for _ in 0..probe_count {
+                insts.extend(Self::gen_sp_reg_adjust(-(guard_size as i32)));
+                insts.push(Inst::Store32 {
+                    rd: zero_reg(),
+                    mem: AMode::Unscaled(stack_reg(), SImm9::zero()),
+                    flags: MemFlags::trusted(),
+                });
+            }
+            insts.extend(Self::gen_sp_reg_adjust((guard_size * probe_count) as i32));
+        } else {
+            insts.push(Inst::StackProbeLoop {
+                frame_size: probe_count * guard_size,
+                guard_size,
+            });
+        }
+        insts
+    }
+
     // Returns stack bytes used as well as instructions. Does not adjust
     // nominal SP offset; abi_impl generic code will do that.
     fn gen_clobber_save(
@@ -1036,6 +1110,48 @@ impl ABIMachineSpec for AArch64MachineDeps {
         insts
     }
 
+    fn gen_tail_call(
+        dest: &CallDest,
+        uses: Vec<Reg>,
+        _callee_conv: isa::CallConv,
+    ) -> SmallVec<[Inst; 2]> {
+        // x9 is caller-saved and never holds an argument, and the epilogue only uses the spill
+        // temporaries, so the called address survives it.
+        let tmp = writable_xreg(9);
+        let mut insts = SmallVec::new();
+        match &dest {
+            &CallDest::ExtName(ref name, RelocDistance::Near) => insts.push(Inst::ReturnCall {
+                info: Box::new(ReturnCallInfo {
+                    dest: name.clone(),
+                    uses,
+                }),
+            }),
+            &CallDest::ExtName(ref name, RelocDistance::Far) => {
+                insts.push(Inst::LoadExtName {
+                    rd: tmp,
+                    name: Box::new(name.clone()),
+                    offset: 0,
+                });
+                insts.push(Inst::ReturnCallInd {
+                    info: Box::new(ReturnCallIndInfo {
+                        rn: tmp.to_reg(),
+                        uses,
+                    }),
+                });
+            }
+            &CallDest::Reg(reg) => {
+                insts.push(Inst::gen_move(tmp, *reg, I64));
+                insts.push(Inst::ReturnCallInd {
+                    info: Box::new(ReturnCallIndInfo {
+                        rn: tmp.to_reg(),
+                        uses,
+                    }),
+                });
+            }
+        }
+        insts
+    }
+
     fn gen_call(
         dest: &CallDest,
         uses: Vec<Reg>,
@@ -1133,7 +1249,7 @@ impl ABIMachineSpec for AArch64MachineDeps {
         // We allocate in terms of 8-byte slots.
         match (rc, ty) {
             (RegClass::I64, _) => 1,
-            (RegClass::V128, F32) | (RegClass::V128, F64) => 1,
+            (RegClass::V128, F16) | (RegClass::V128, F32) | (RegClass::V128, F64) => 1,
             (RegClass::V128, _) => 2,
             _ => panic!("Unexpected register class!"),
         }
@@ -1166,6 +1282,10 @@ impl ABIMachineSpec for AArch64MachineDeps {
         caller_saved
     }
 
+    fn get_exception_payload_regs() -> [Reg; 2] {
+        [xreg(0), xreg(1)]
+    }
+
     fn get_ext_mode(
         call_conv: isa::CallConv,
         specified: ir::ArgumentExtension,
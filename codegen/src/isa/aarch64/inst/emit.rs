@@ -2,9 +2,10 @@
 
 use crate::{
     binemit::{CodeOffset, Reloc, StackMap},
-    ir::{constant::ConstantData, types::*, MemFlags, TrapCode},
+    ir::{constant::ConstantData, types::*, LibCall, MemFlags, TrapCode},
     isa::aarch64::inst::*,
     machinst::ty_bits,
+    settings::TrapEncoding,
 };
 
 use regalloc::{Reg, RegClass, Writable};
@@ -151,6 +152,26 @@ fn enc_jump26(op_31_26: u32, off_26_0: u32) -> u32 {
     (op_31_26 << 26) | off_26_0
 }
 
+/// The immediate of the `brk` instruction emitted for a trap with the `breakpoint` trap encoding.
+/// User trap codes are used as is, and the built-in ones count down from `0xffff`; the trap table
+/// remains authoritative should the two collide.
+fn brk_imm(trap_code: TrapCode) -> u16 {
+    match trap_code {
+        TrapCode::StackOverflow => 0xffff,
+        TrapCode::HeapOutOfBounds => 0xfffe,
+        TrapCode::HeapMisaligned => 0xfffd,
+        TrapCode::TableOutOfBounds => 0xfffc,
+        TrapCode::IndirectCallToNull => 0xfffb,
+        TrapCode::BadSignature => 0xfffa,
+        TrapCode::IntegerOverflow => 0xfff9,
+        TrapCode::IntegerDivisionByZero => 0xfff8,
+        TrapCode::BadConversionToInteger => 0xfff7,
+        TrapCode::UnreachableCodeReached => 0xfff6,
+        TrapCode::Interrupt => 0xfff5,
+        TrapCode::User(code) => code,
+    }
+}
+
 fn enc_cmpbr(op_31_24: u32, off_18_0: u32, reg: Reg) -> u32 {
     assert!(off_18_0 < (1 << 19));
     (op_31_24 << 24) | (off_18_0 << 5) | machreg_to_gpr(reg)
@@ -346,6 +367,15 @@ fn enc_ccmp_imm(size: OperandSize, rn: Reg, imm: UImm5, nzcv: NZCV, cond: Cond)
         | nzcv.bits()
 }
 
+fn enc_ccmp(size: OperandSize, rn: Reg, rm: Reg, nzcv: NZCV, cond: Cond) -> u32 {
+    0b0_1_1_11010010_00000_0000_00_00000_0_0000
+        | size.sf_bit() << 31
+        | machreg_to_gpr(rm) << 16
+        | cond.bits() << 12
+        | machreg_to_gpr(rn) << 5
+        | nzcv.bits()
+}
+
 fn enc_bfm(opc: u8, size: OperandSize, rd: Writable<Reg>, rn: Reg, immr: u8, imms: u8) -> u32 {
     match size {
         OperandSize::Size64 => {
@@ -464,6 +494,10 @@ fn enc_dmb_ish() -> u32 {
     0xD5033BBF
 }
 
+fn enc_dmb_ishld() -> u32 {
+    0xD50339BF
+}
+
 fn enc_ldxr(ty: Type, rt: Writable<Reg>, rn: Reg) -> u32 {
     let sz = match ty {
         I64 => 0b11,
@@ -478,6 +512,21 @@ fn enc_ldxr(ty: Type, rt: Writable<Reg>, rn: Reg) -> u32 {
         | machreg_to_gpr(rt.to_reg())
 }
 
+fn enc_ldxp(rt: Writable<Reg>, rt2: Writable<Reg>, rn: Reg) -> u32 {
+    0b11001000_01111111_00000000_00000000
+        | (machreg_to_gpr(rt2.to_reg()) << 10)
+        | (machreg_to_gpr(rn) << 5)
+        | machreg_to_gpr(rt.to_reg())
+}
+
+fn enc_stxp(rs: Writable<Reg>, rt: Reg, rt2: Reg, rn: Reg) -> u32 {
+    0b11001000_00100000_00000000_00000000
+        | (machreg_to_gpr(rs.to_reg()) << 16)
+        | (machreg_to_gpr(rt2) << 10)
+        | (machreg_to_gpr(rn) << 5)
+        | machreg_to_gpr(rt)
+}
+
 fn enc_stxr(ty: Type, rs: Writable<Reg>, rt: Reg, rn: Reg) -> u32 {
     let sz = match ty {
         I64 => 0b11,
@@ -816,6 +865,9 @@ impl MachInstEmit for Inst {
                     BitOp::RBit32 | BitOp::RBit64 => (0b00000, 0b000000),
                     BitOp::Clz32 | BitOp::Clz64 => (0b00000, 0b000100),
                     BitOp::Cls32 | BitOp::Cls64 => (0b00000, 0b000101),
+                    BitOp::Rev16 => (0b00000, 0b000001),
+                    BitOp::Rev32 => (0b00000, 0b000010),
+                    BitOp::Rev64 => (0b00000, 0b000011),
                 };
                 sink.put4(enc_bit_rr(size, op1, op2, rn, rd))
             }
@@ -829,6 +881,7 @@ impl MachInstEmit for Inst {
             | &Inst::ULoad64 {
                 rd, ref mem, flags, ..
             }
+            | &Inst::FpuLoad16 { rd, ref mem, flags }
             | &Inst::FpuLoad32 { rd, ref mem, flags }
             | &Inst::FpuLoad64 { rd, ref mem, flags }
             | &Inst::FpuLoad128 { rd, ref mem, flags } => {
@@ -852,6 +905,7 @@ impl MachInstEmit for Inst {
                     &Inst::ULoad32 { .. } => (0b1011100001, 32),
                     &Inst::SLoad32 { .. } => (0b1011100010, 32),
                     &Inst::ULoad64 { .. } => (0b1111100001, 64),
+                    &Inst::FpuLoad16 { .. } => (0b0111110001, 16),
                     &Inst::FpuLoad32 { .. } => (0b1011110001, 32),
                     &Inst::FpuLoad64 { .. } => (0b1111110001, 64),
                     &Inst::FpuLoad128 { .. } => (0b0011110011, 128),
@@ -946,6 +1000,7 @@ impl MachInstEmit for Inst {
             | &Inst::Store16 { rd, ref mem, flags }
             | &Inst::Store32 { rd, ref mem, flags }
             | &Inst::Store64 { rd, ref mem, flags }
+            | &Inst::FpuStore16 { rd, ref mem, flags }
             | &Inst::FpuStore32 { rd, ref mem, flags }
             | &Inst::FpuStore64 { rd, ref mem, flags }
             | &Inst::FpuStore128 { rd, ref mem, flags } => {
@@ -960,6 +1015,7 @@ impl MachInstEmit for Inst {
                     &Inst::Store16 { .. } => (0b0111100000, 16),
                     &Inst::Store32 { .. } => (0b1011100000, 32),
                     &Inst::Store64 { .. } => (0b1111100000, 64),
+                    &Inst::FpuStore16 { .. } => (0b0111110000, 16),
                     &Inst::FpuStore32 { .. } => (0b1011110000, 32),
                     &Inst::FpuStore64 { .. } => (0b1111110000, 64),
                     &Inst::FpuStore128 { .. } => (0b0011110010, 128),
@@ -1433,6 +1489,116 @@ impl MachInstEmit for Inst {
                 sink.bind_label(out_label);
                 sink.put4(enc_dmb_ish()); // dmb ish
             }
+            &Inst::AtomicCAS128Loop => {
+                /* Emit this:
+                     dmb         ish
+                    again:
+                     ldxp        x20, x21, [x25]
+                     cmp         x20, x26
+                     ccmp        x21, x27, #0, eq
+                     b.ne        fail
+                     stxp        w24, x22, x23, [x25]
+                     cbnz        x24, again
+                     b           out
+                    fail:
+                     stxp        w24, x20, x21, [x25]
+                     cbnz        x24, again
+                    out:
+                     dmb         ish
+
+                  The pair loaded by `ldxp` is only guaranteed to be single-copy atomic if the
+                  following `stxp` succeeds, so the old value is stored back when the comparison
+                  fails.
+
+                  Operand conventions:
+                     IN:  x25 (addr), x27:x26 (expected value), x23:x22 (replacement value)
+                     OUT: x21:x20 (old value), x24 (trashed)
+                */
+                let x20 = xreg(20);
+                let x21 = xreg(21);
+                let x22 = xreg(22);
+                let x23 = xreg(23);
+                let x24 = xreg(24);
+                let x25 = xreg(25);
+                let x26 = xreg(26);
+                let x27 = xreg(27);
+                let xzrwr = writable_zero_reg();
+                let x20wr = writable_xreg(20);
+                let x21wr = writable_xreg(21);
+                let x24wr = writable_xreg(24);
+                let again_label = sink.get_label();
+                let fail_label = sink.get_label();
+                let out_label = sink.get_label();
+
+                sink.put4(enc_dmb_ish()); // dmb ish
+
+                // again:
+                sink.bind_label(again_label);
+                let srcloc = state.cur_srcloc();
+                if srcloc != SourceLoc::default() {
+                    sink.add_trap(srcloc, TrapCode::HeapOutOfBounds);
+                }
+                sink.put4(enc_ldxp(x20wr, x21wr, x25)); // ldxp x20, x21, [x25]
+
+                // cmp x20, x26 (== subs xzr, x20, x26)
+                sink.put4(enc_arith_rrr(0b111_01011_00_0, 0b000000, xzrwr, x20, x26));
+                // ccmp x21, x27, #0, eq
+                sink.put4(enc_ccmp(
+                    OperandSize::Size64,
+                    x21,
+                    x27,
+                    NZCV::new(false, false, false, false),
+                    Cond::Eq,
+                ));
+
+                // b.ne fail
+                let br_fail_offset = sink.cur_offset();
+                sink.put4(enc_conditional_br(
+                    BranchTarget::Label(fail_label),
+                    CondBrKind::Cond(Cond::Ne),
+                ));
+                sink.use_label_at_offset(br_fail_offset, fail_label, LabelUse::Branch19);
+
+                let srcloc = state.cur_srcloc();
+                if srcloc != SourceLoc::default() {
+                    sink.add_trap(srcloc, TrapCode::HeapOutOfBounds);
+                }
+                sink.put4(enc_stxp(x24wr, x22, x23, x25)); // stxp w24, x22, x23, [x25]
+
+                // cbnz w24, again
+                let br_again_offset = sink.cur_offset();
+                sink.put4(enc_conditional_br(
+                    BranchTarget::Label(again_label),
+                    CondBrKind::NotZero(x24),
+                ));
+                sink.use_label_at_offset(br_again_offset, again_label, LabelUse::Branch19);
+
+                // b out
+                let br_out_offset = sink.cur_offset();
+                sink.use_label_at_offset(br_out_offset, out_label, LabelUse::Branch26);
+                sink.add_uncond_branch(br_out_offset, br_out_offset + 4, out_label);
+                sink.put4(enc_jump26(0b000101, 0 /* will be fixed up later */));
+
+                // fail:
+                sink.bind_label(fail_label);
+                let srcloc = state.cur_srcloc();
+                if srcloc != SourceLoc::default() {
+                    sink.add_trap(srcloc, TrapCode::HeapOutOfBounds);
+                }
+                sink.put4(enc_stxp(x24wr, x20, x21, x25)); // stxp w24, x20, x21, [x25]
+
+                // cbnz w24, again
+                let br_again_offset = sink.cur_offset();
+                sink.put4(enc_conditional_br(
+                    BranchTarget::Label(again_label),
+                    CondBrKind::NotZero(x24),
+                ));
+                sink.use_label_at_offset(br_again_offset, again_label, LabelUse::Branch19);
+
+                // out:
+                sink.bind_label(out_label);
+                sink.put4(enc_dmb_ish()); // dmb ish
+            }
             &Inst::AtomicLoad { ty, r_data, r_addr } => {
                 let op = match ty {
                     I8 => 0b0011100001,
@@ -1475,6 +1641,90 @@ impl MachInstEmit for Inst {
             &Inst::Fence {} => {
                 sink.put4(enc_dmb_ish()); // dmb ish
             }
+            &Inst::LoadFence {} => {
+                sink.put4(enc_dmb_ishld()); // dmb ishld
+            }
+            &Inst::StackProbeLoop {
+                frame_size,
+                guard_size,
+            } => {
+                /* Emit this:
+                      mov   x16, #frame_size
+                      sub   x16, sp, x16, UXTX   // x16 = lowest address to probe
+                     again:
+                      sub   sp, sp, #guard_size
+                      stur  wzr, [sp]            // touch the page
+                      cmp   sp, x16
+                      b.ne  again
+                      mov   x16, #frame_size
+                      add   sp, sp, x16, UXTX
+
+                   Moving SP along with the probes keeps every store at or above the stack
+                   pointer.
+                */
+                debug_assert!(guard_size > 0 && frame_size % guard_size == 0);
+                let x16 = spilltmp_reg();
+                let x16wr = writable_spilltmp_reg();
+                let guard_imm = Imm12::maybe_from_u64(guard_size as u64)
+                    .expect("stack probe guard size must be encodable as an Imm12");
+                let again_label = sink.get_label();
+
+                for inst in Inst::load_constant(x16wr, frame_size as u64) {
+                    inst.emit(sink, emit_info, state);
+                }
+                Inst::AluRRRExtend {
+                    alu_op: ALUOp::Sub64,
+                    rd: x16wr,
+                    rn: stack_reg(),
+                    rm: x16,
+                    extendop: ExtendOp::UXTX,
+                }
+                .emit(sink, emit_info, state);
+
+                // again:
+                sink.bind_label(again_label);
+                Inst::AluRRImm12 {
+                    alu_op: ALUOp::Sub64,
+                    rd: writable_stack_reg(),
+                    rn: stack_reg(),
+                    imm12: guard_imm,
+                }
+                .emit(sink, emit_info, state);
+                Inst::Store32 {
+                    rd: zero_reg(),
+                    mem: AMode::Unscaled(stack_reg(), SImm9::zero()),
+                    flags: MemFlags::trusted(),
+                }
+                .emit(sink, emit_info, state);
+                Inst::AluRRRExtend {
+                    alu_op: ALUOp::SubS64,
+                    rd: writable_zero_reg(),
+                    rn: stack_reg(),
+                    rm: x16,
+                    extendop: ExtendOp::UXTX,
+                }
+                .emit(sink, emit_info, state);
+
+                // b.ne again
+                let br_offset = sink.cur_offset();
+                sink.put4(enc_conditional_br(
+                    BranchTarget::Label(again_label),
+                    CondBrKind::Cond(Cond::Ne),
+                ));
+                sink.use_label_at_offset(br_offset, again_label, LabelUse::Branch19);
+
+                for inst in Inst::load_constant(x16wr, frame_size as u64) {
+                    inst.emit(sink, emit_info, state);
+                }
+                Inst::AluRRRExtend {
+                    alu_op: ALUOp::Add64,
+                    rd: writable_stack_reg(),
+                    rn: stack_reg(),
+                    rm: x16,
+                    extendop: ExtendOp::UXTX,
+                }
+                .emit(sink, emit_info, state);
+            }
             &Inst::FpuMove64 { rd, rn } => {
                 sink.put4(enc_fpurr(0b000_11110_01_1_000000_10000, rd, rn));
             }
@@ -1513,6 +1763,10 @@ impl MachInstEmit for Inst {
                     FPUOp1::Sqrt64 => 0b000_11110_01_1_000011_10000,
                     FPUOp1::Cvt32To64 => 0b000_11110_00_1_000101_10000,
                     FPUOp1::Cvt64To32 => 0b000_11110_01_1_000100_10000,
+                    FPUOp1::Cvt16To32 => 0b000_11110_11_1_000100_10000,
+                    FPUOp1::Cvt16To64 => 0b000_11110_11_1_000101_10000,
+                    FPUOp1::Cvt32To16 => 0b000_11110_00_1_000111_10000,
+                    FPUOp1::Cvt64To16 => 0b000_11110_01_1_000111_10000,
                 };
                 sink.put4(enc_fpurr(top22, rd, rn));
             }
@@ -2226,6 +2480,17 @@ impl MachInstEmit for Inst {
             &Inst::MovFromNZCV { rd } => {
                 sink.put4(0xd53b4200 | machreg_to_gpr(rd.to_reg()));
             }
+            &Inst::MovFromCntvct { rd } => {
+                sink.put4(0xd53be040 | machreg_to_gpr(rd.to_reg()));
+            }
+            &Inst::MovFromPor { rd } => {
+                sink.put4(0xd53ba280 | machreg_to_gpr(rd.to_reg()));
+            }
+            &Inst::MovToPor { rn } => {
+                sink.put4(0xd51ba280 | machreg_to_gpr(rn));
+                // isb
+                sink.put4(0xd5033fdf);
+            }
             &Inst::Extend {
                 rd,
                 rn,
@@ -2281,9 +2546,31 @@ impl MachInstEmit for Inst {
                 // Emit the jump itself.
                 sink.put4(enc_jump26(0b000101, dest.as_offset26_or_zero()));
             }
+            &Inst::JumpInvoke {
+                ref dest,
+                landing_pad,
+            } => {
+                sink.add_exception_handler(landing_pad.as_label().unwrap());
+                let off = sink.cur_offset();
+                // Indicate that the jump uses a label, if so, so that a fixup can occur later.
+                if let Some(l) = dest.as_label() {
+                    sink.use_label_at_offset(off, l, LabelUse::Branch26);
+                    sink.add_uncond_branch(off, off + 4, l);
+                }
+                // Emit the jump itself.
+                sink.put4(enc_jump26(0b000101, dest.as_offset26_or_zero()));
+            }
             &Inst::Ret => {
                 sink.put4(0xd65f03c0);
             }
+            &Inst::ReturnCall { ref info } => {
+                let loc = state.cur_srcloc();
+                sink.add_reloc(loc, Reloc::Arm64Call, &info.dest, 0);
+                sink.put4(enc_jump26(0b000101, 0));
+            }
+            &Inst::ReturnCallInd { ref info } => {
+                sink.put4(enc_br(info.rn));
+            }
             &Inst::EpiloguePlaceholder => {
                 // Noop; this is just a placeholder for epilogues.
             }
@@ -2361,7 +2648,21 @@ impl MachInstEmit for Inst {
                 if let Some(s) = state.take_stack_map() {
                     sink.add_stack_map(StackMapExtent::UpcomingBytes(4), s);
                 }
-                sink.put4(0xd4a00000);
+                match emit_info.flags().trap_encoding() {
+                    TrapEncoding::Undefined => sink.put4(0xd4a00000),
+                    TrapEncoding::Breakpoint => {
+                        sink.put4(0xd4200000 | (u32::from(brk_imm(trap_code)) << 5))
+                    }
+                    TrapEncoding::CallHandler => {
+                        sink.add_reloc(
+                            srcloc,
+                            Reloc::Arm64Call,
+                            &ExternalName::LibCall(LibCall::TrapHandler),
+                            0,
+                        );
+                        sink.put4(enc_jump26(0b100101, 0));
+                    }
+                }
             }
             &Inst::Adr { rd, off } => {
                 assert!(off > -(1 << 20));
@@ -2474,6 +2775,47 @@ impl MachInstEmit for Inst {
                     sink.put8(0);
                 }
             }
+            &Inst::ElfTlsGetAddr { ref symbol } => {
+                // This is the general-dynamic TLS sequence from the AArch64 ELF
+                // ABI; the linker may relax it to a cheaper model:
+                //
+                //   adrp x0, :tlsgd:symbol
+                //   add x0, x0, :tlsgd_lo12:symbol
+                //   bl __tls_get_addr
+                //   nop
+                let srcloc = state.cur_srcloc();
+                sink.add_reloc(srcloc, Reloc::Aarch64TlsGdAdrPage21, &**symbol, 0);
+                sink.put4(0x90000000);
+                sink.add_reloc(srcloc, Reloc::Aarch64TlsGdAddLo12Nc, &**symbol, 0);
+                sink.put4(0x91000000);
+                sink.add_reloc(
+                    srcloc,
+                    Reloc::Arm64Call,
+                    &ExternalName::LibCall(LibCall::ElfTlsGetAddr),
+                    0,
+                );
+                sink.put4(enc_jump26(0b100101, 0));
+                sink.put4(0xd503201f);
+            }
+            &Inst::ElfTlsInitialExec {
+                ref symbol,
+                rd,
+                tmp,
+            } => {
+                // adrp rd, :gottprel:symbol
+                // ldr rd, [rd, :gottprel_lo12:symbol]
+                // mrs tmp, tpidr_el0
+                // add rd, tmp, rd
+                let srcloc = state.cur_srcloc();
+                let rd = machreg_to_gpr(rd.to_reg());
+                let tmp = machreg_to_gpr(tmp.to_reg());
+                sink.add_reloc(srcloc, Reloc::Aarch64TlsIeAdrGotTpRelPage21, &**symbol, 0);
+                sink.put4(0x90000000 | rd);
+                sink.add_reloc(srcloc, Reloc::Aarch64TlsIeLd64GotTpRelLo12Nc, &**symbol, 0);
+                sink.put4(0xf9400000 | (rd << 5) | rd);
+                sink.put4(0xd53bd040 | tmp);
+                sink.put4(0x8b000000 | (rd << 16) | (tmp << 5) | rd);
+            }
             &Inst::LoadAddr { rd, ref mem } => {
                 let (mem_insts, mem) = mem_finalize(sink.cur_offset(), mem, state);
                 for inst in mem_insts.into_iter() {
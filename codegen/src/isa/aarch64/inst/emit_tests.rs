@@ -1223,6 +1223,36 @@ fn test_aarch64_binemit() {
         "cls x21, x16",
     ));
 
+    insns.push((
+        Inst::BitRR {
+            op: BitOp::Rev16,
+            rd: writable_xreg(1),
+            rn: xreg(10),
+        },
+        "4105C05A",
+        "rev16 w1, w10",
+    ));
+
+    insns.push((
+        Inst::BitRR {
+            op: BitOp::Rev32,
+            rd: writable_xreg(1),
+            rn: xreg(10),
+        },
+        "4109C05A",
+        "rev w1, w10",
+    ));
+
+    insns.push((
+        Inst::BitRR {
+            op: BitOp::Rev64,
+            rd: writable_xreg(1),
+            rn: xreg(10),
+        },
+        "410DC0DA",
+        "rev x1, x10",
+    ));
+
     insns.push((
         Inst::ULoad8 {
             rd: writable_xreg(1),
@@ -2166,6 +2196,25 @@ fn test_aarch64_binemit() {
         "1B423BD5",
         "mrs x27, nzcv",
     ));
+    insns.push((
+        Inst::MovFromCntvct {
+            rd: writable_xreg(27),
+        },
+        "5BE03BD5",
+        "mrs x27, cntvct_el0",
+    ));
+    insns.push((
+        Inst::MovFromPor {
+            rd: writable_xreg(27),
+        },
+        "9BA23BD5",
+        "mrs x27, por_el0",
+    ));
+    insns.push((
+        Inst::MovToPor { rn: xreg(27) },
+        "9BA21BD5DF3F03D5",
+        "msr por_el0, x27 ; isb",
+    ));
     insns.push((
         Inst::VecDup {
             rd: writable_vreg(25),
@@ -4586,6 +4635,30 @@ fn test_aarch64_binemit() {
         "b.le 64 ; b 128",
     ));
 
+    insns.push((
+        Inst::ElfTlsGetAddr {
+            symbol: Box::new(ExternalName::User {
+                namespace: 0,
+                index: 0,
+            }),
+        },
+        "0000009000000091000000941F2003D5",
+        "elf_tls_get_addr User { namespace: 0, index: 0 }",
+    ));
+
+    insns.push((
+        Inst::ElfTlsInitialExec {
+            symbol: Box::new(ExternalName::User {
+                namespace: 0,
+                index: 0,
+            }),
+            rd: writable_xreg(1),
+            tmp: writable_xreg(2),
+        },
+        "01000090210040F942D03BD54100018B",
+        "elf_tls_initial_exec User { namespace: 0, index: 0 }, x1, x2",
+    ));
+
     insns.push((
         Inst::Call {
             info: Box::new(CallInfo {
@@ -4616,6 +4689,28 @@ fn test_aarch64_binemit() {
         "blr x10",
     ));
 
+    insns.push((
+        Inst::ReturnCall {
+            info: Box::new(ReturnCallInfo {
+                dest: ExternalName::testcase("test0"),
+                uses: Vec::new(),
+            }),
+        },
+        "00000014",
+        "b 0",
+    ));
+
+    insns.push((
+        Inst::ReturnCallInd {
+            info: Box::new(ReturnCallIndInfo {
+                rn: xreg(9),
+                uses: Vec::new(),
+            }),
+        },
+        "20011FD6",
+        "br x9",
+    ));
+
     insns.push((
         Inst::IndirectBr {
             rn: xreg(3),
@@ -4766,6 +4861,46 @@ fn test_aarch64_binemit() {
         "fcvt s15, d30",
     ));
 
+    insns.push((
+        Inst::FpuRR {
+            fpu_op: FPUOp1::Cvt16To32,
+            rd: writable_vreg(15),
+            rn: vreg(30),
+        },
+        "CF43E21E",
+        "fcvt s15, h30",
+    ));
+
+    insns.push((
+        Inst::FpuRR {
+            fpu_op: FPUOp1::Cvt16To64,
+            rd: writable_vreg(15),
+            rn: vreg(30),
+        },
+        "CFC3E21E",
+        "fcvt d15, h30",
+    ));
+
+    insns.push((
+        Inst::FpuRR {
+            fpu_op: FPUOp1::Cvt32To16,
+            rd: writable_vreg(15),
+            rn: vreg(30),
+        },
+        "CFC3231E",
+        "fcvt h15, s30",
+    ));
+
+    insns.push((
+        Inst::FpuRR {
+            fpu_op: FPUOp1::Cvt64To16,
+            rd: writable_vreg(15),
+            rn: vreg(30),
+        },
+        "CFC3631E",
+        "fcvt h15, d30",
+    ));
+
     insns.push((
         Inst::FpuRRR {
             fpu_op: FPUOp2::Add32,
@@ -5184,6 +5319,16 @@ fn test_aarch64_binemit() {
         "fcmp d23, d24",
     ));
 
+    insns.push((
+        Inst::FpuLoad16 {
+            rd: writable_vreg(16),
+            mem: AMode::RegScaled(xreg(8), xreg(9), F16),
+            flags: MemFlags::trusted(),
+        },
+        "1079697C",
+        "ldr h16, [x8, x9, LSL #1]",
+    ));
+
     insns.push((
         Inst::FpuLoad32 {
             rd: writable_vreg(16),
@@ -5244,6 +5389,16 @@ fn test_aarch64_binemit() {
         "ldr q16, pc+8",
     ));
 
+    insns.push((
+        Inst::FpuStore16 {
+            rd: vreg(16),
+            mem: AMode::RegScaled(xreg(8), xreg(9), F16),
+            flags: MemFlags::trusted(),
+        },
+        "1079297C",
+        "str h16, [x8, x9, LSL #1]",
+    ));
+
     insns.push((
         Inst::FpuStore32 {
             rd: vreg(16),
@@ -5622,6 +5777,21 @@ fn test_aarch64_binemit() {
         "atomically { compare-and-swap(64_bits_at_[x25], x26 -> x28), x27 = old_value_at_[x25]; x24 = trash }"
     ));
 
+    insns.push((
+        Inst::AtomicCAS128Loop,
+        "BF3B03D534577FC89F021AEBA0025BFA81000054365F38C878FFFFB503000014345738C818FFFFB5BF3B03D5",
+        "atomically { compare-and-swap(128_bits_at_[x25], x27:x26 -> x23:x22), x21:x20 = old_value_at_[x25]; x24 = trash }"
+    ));
+
+    insns.push((
+        Inst::StackProbeLoop {
+            frame_size: 0x5000,
+            guard_size: 0x1000,
+        },
+        "10008AD2F06330CBFF0740D1FF0300B8FF6330EBA1FFFF5410008AD2FF63308B",
+        "stack_probe_loop frame_size=20480, guard_size=4096; x16 = trash",
+    ));
+
     insns.push((
         Inst::AtomicLoad {
             ty: I8,
@@ -5663,14 +5833,17 @@ fn test_aarch64_binemit() {
     ));
 
     insns.push((Inst::Fence {}, "BF3B03D5", "dmb ish"));
+    insns.push((Inst::LoadFence {}, "BF3903D5", "dmb ishld"));
 
     let flags = settings::Flags::new(settings::builder());
     let rru = create_reg_universe(&flags);
     let emit_info = EmitInfo::new(flags);
     for (insn, expected_encoding, expected_printing) in insns {
-        println!(
+        log::debug!(
             "AArch64: {:?}, {}, {}",
-            insn, expected_encoding, expected_printing
+            insn,
+            expected_encoding,
+            expected_printing
         );
 
         // Check the printed text is as expected.
@@ -5687,6 +5860,217 @@ fn test_aarch64_binemit() {
     }
 }
 
+#[test]
+fn test_aarch64_binemit_trap_encoding() {
+    use crate::settings::Configurable;
+
+    for &(trap_encoding, trap_code, expected_encoding) in &[
+        ("undefined", TrapCode::UnreachableCodeReached, "0000A0D4"),
+        ("breakpoint", TrapCode::UnreachableCodeReached, "C0FE3FD4"),
+        ("breakpoint", TrapCode::User(42), "400520D4"),
+        ("call_handler", TrapCode::UnreachableCodeReached, "00000094"),
+    ] {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("trap_encoding", trap_encoding).unwrap();
+        let emit_info = EmitInfo::new(settings::Flags::new(flag_builder));
+
+        let mut sink = test_utils::TestCodeSink::new();
+        let mut buffer = MachBuffer::new();
+        Inst::Udf { trap_code }.emit(&mut buffer, &emit_info, &mut Default::default());
+        buffer.finish().emit(&mut sink);
+        assert_eq!(
+            expected_encoding,
+            sink.stringify(),
+            "{} {}",
+            trap_encoding,
+            trap_code
+        );
+    }
+}
+
+/// Emit random operand combinations of the common integer instructions and check them against
+/// the reference assembler; see `isa::crosscheck`.
+#[cfg(feature = "asm-crosscheck")]
+#[test]
+fn test_aarch64_binemit_crosscheck() {
+    use crate::isa::crosscheck::{crosscheck, Case, Rng};
+
+    let mut rng = Rng::new(0x5eed_c0de_cafe_f00d);
+    // Register 31 is either the zero register or the stack pointer depending on the instruction,
+    // and registers 29 and 30 are printed as `fp` and `lr` even in their 32-bit forms, so leave
+    // them out.
+    let reg = |rng: &mut Rng| xreg(rng.below(29) as u8);
+    let amode = |rng: &mut Rng, ty: Type| -> AMode {
+        let base = reg(rng);
+        match rng.below(4) {
+            0 => AMode::Unscaled(
+                base,
+                SImm9::maybe_from_i64(rng.below(512) as i64 - 256).unwrap(),
+            ),
+            1 => {
+                let offset = rng.below(4096) * u64::from(ty.bytes());
+                AMode::UnsignedOffset(
+                    base,
+                    UImm12Scaled::maybe_from_i64(offset as i64, ty).unwrap(),
+                )
+            }
+            2 => AMode::RegReg(base, reg(rng)),
+            _ => AMode::RegScaled(base, reg(rng), ty),
+        }
+    };
+
+    let mut insns = vec![];
+    for _ in 0..2000 {
+        let rd = Writable::from_reg(reg(&mut rng));
+        let rn = reg(&mut rng);
+        let rm = reg(&mut rng);
+        let flags = MemFlags::trusted();
+        let insn = match rng.below(9) {
+            0 => {
+                let alu_op = rng.choose(&[
+                    ALUOp::Add32,
+                    ALUOp::Add64,
+                    ALUOp::Sub32,
+                    ALUOp::Sub64,
+                    ALUOp::Orr32,
+                    ALUOp::Orr64,
+                    ALUOp::OrrNot32,
+                    ALUOp::OrrNot64,
+                    ALUOp::And32,
+                    ALUOp::And64,
+                    ALUOp::AndS32,
+                    ALUOp::AndS64,
+                    ALUOp::AndNot32,
+                    ALUOp::AndNot64,
+                    ALUOp::Eor32,
+                    ALUOp::Eor64,
+                    ALUOp::EorNot32,
+                    ALUOp::EorNot64,
+                    ALUOp::AddS32,
+                    ALUOp::AddS64,
+                    ALUOp::SubS32,
+                    ALUOp::SubS64,
+                    ALUOp::SMulH,
+                    ALUOp::UMulH,
+                    ALUOp::SDiv64,
+                    ALUOp::UDiv64,
+                    ALUOp::RotR32,
+                    ALUOp::RotR64,
+                    ALUOp::Lsr32,
+                    ALUOp::Lsr64,
+                    ALUOp::Asr32,
+                    ALUOp::Asr64,
+                    ALUOp::Lsl32,
+                    ALUOp::Lsl64,
+                    ALUOp::Adc32,
+                    ALUOp::Adc64,
+                    ALUOp::AdcS32,
+                    ALUOp::AdcS64,
+                    ALUOp::Sbc32,
+                    ALUOp::Sbc64,
+                    ALUOp::SbcS32,
+                    ALUOp::SbcS64,
+                ]);
+                Inst::AluRRR { alu_op, rd, rn, rm }
+            }
+            1 => {
+                let alu_op = rng.choose(&[
+                    ALUOp::Add32,
+                    ALUOp::Add64,
+                    ALUOp::Sub32,
+                    ALUOp::Sub64,
+                    ALUOp::AddS32,
+                    ALUOp::AddS64,
+                    ALUOp::SubS32,
+                    ALUOp::SubS64,
+                ]);
+                let imm12 = Imm12 {
+                    bits: rng.below(4096) as u16,
+                    shift12: rng.coin(),
+                };
+                Inst::AluRRImm12 {
+                    alu_op,
+                    rd,
+                    rn,
+                    imm12,
+                }
+            }
+            2 => {
+                let alu_op = rng.choose(&[
+                    ALUOp3::MAdd32,
+                    ALUOp3::MAdd64,
+                    ALUOp3::MSub32,
+                    ALUOp3::MSub64,
+                ]);
+                let ra = reg(&mut rng);
+                Inst::AluRRRR {
+                    alu_op,
+                    rd,
+                    rn,
+                    rm,
+                    ra,
+                }
+            }
+            3 => {
+                let imm = MoveWideConst::maybe_with_shift(
+                    rng.below(0x1_0000) as u16,
+                    16 * rng.below(4) as u8,
+                )
+                .unwrap();
+                Inst::MovZ {
+                    rd,
+                    imm,
+                    size: OperandSize::Size64,
+                }
+            }
+            4 => Inst::ULoad64 {
+                rd,
+                mem: amode(&mut rng, I64),
+                flags,
+            },
+            5 => Inst::ULoad32 {
+                rd,
+                mem: amode(&mut rng, I32),
+                flags,
+            },
+            6 => Inst::SLoad32 {
+                rd,
+                mem: amode(&mut rng, I32),
+                flags,
+            },
+            7 => Inst::Store64 {
+                rd: rn,
+                mem: amode(&mut rng, I64),
+                flags,
+            },
+            _ => Inst::Store32 {
+                rd: rn,
+                mem: amode(&mut rng, I32),
+                flags,
+            },
+        };
+        insns.push(insn);
+    }
+
+    let flags = settings::Flags::new(settings::builder());
+    let rru = create_reg_universe(&flags);
+    let emit_info = EmitInfo::new(flags);
+    let cases: Vec<Case> = insns
+        .into_iter()
+        .map(|insn| {
+            let mut sink = test_utils::TestCodeSink::new();
+            let mut buffer = MachBuffer::new();
+            insn.emit(&mut buffer, &emit_info, &mut Default::default());
+            buffer.finish().emit(&mut sink);
+            Case {
+                asm: insn.show_rru(Some(&rru)),
+                encoding: sink.stringify(),
+            }
+        })
+        .collect();
+    crosscheck("aarch64", &cases);
+}
+
 #[test]
 fn test_cond_invert() {
     for cond in vec![
@@ -7,12 +7,12 @@ use crate::{
     binemit::CodeOffset,
     ir::{
         types::{
-            B1, B128, B16, B32, B64, B8, F32, F64, FFLAGS, I128, I16, I32, I64, I8, I8X16, IFLAGS,
-            R32, R64,
+            B1, B128, B16, B32, B64, B8, F16, F32, F64, FFLAGS, I128, I16, I32, I64, I8, I8X16,
+            IFLAGS, R32, R64,
         },
         ExternalName, MemFlags, Opcode, SourceLoc, TrapCode, Type, ValueLabel,
     },
-    isa::{unwind::UnwindInst, CallConv},
+    isa::{aarch64::abi::AArch64MachineDeps, unwind::UnwindInst, CallConv},
     machinst::*,
     settings, CodegenError, CodegenResult,
 };
@@ -132,6 +132,10 @@ pub enum FPUOp1 {
     Sqrt64,
     Cvt32To64,
     Cvt64To32,
+    Cvt16To32,
+    Cvt16To64,
+    Cvt32To16,
+    Cvt64To16,
 }
 
 /// A floating-point unit (FPU) operation with two args.
@@ -410,13 +414,21 @@ pub enum BitOp {
     Clz64,
     Cls32,
     Cls64,
+    /// Reverse the bytes in each halfword
+    Rev16,
+    /// Byte reverse
+    Rev32,
+    /// Byte reverse
+    Rev64,
 }
 
 impl BitOp {
     /// What is the opcode's native width?
     pub fn operand_size(&self) -> OperandSize {
         match self {
-            BitOp::RBit32 | BitOp::Clz32 | BitOp::Cls32 => OperandSize::Size32,
+            BitOp::RBit32 | BitOp::Clz32 | BitOp::Cls32 | BitOp::Rev16 | BitOp::Rev32 => {
+                OperandSize::Size32
+            }
             _ => OperandSize::Size64,
         }
     }
@@ -427,6 +439,8 @@ impl BitOp {
             BitOp::RBit32 | BitOp::RBit64 => "rbit",
             BitOp::Clz32 | BitOp::Clz64 => "clz",
             BitOp::Cls32 | BitOp::Cls64 => "cls",
+            BitOp::Rev16 => "rev16",
+            BitOp::Rev32 | BitOp::Rev64 => "rev",
         }
     }
 }
@@ -441,6 +455,9 @@ impl From<(Opcode, Type)> for BitOp {
             (Opcode::Clz, I64) => BitOp::Clz64,
             (Opcode::Cls, I32) => BitOp::Cls32,
             (Opcode::Cls, I64) => BitOp::Cls64,
+            (Opcode::Bswap, I16) => BitOp::Rev16,
+            (Opcode::Bswap, I32) => BitOp::Rev32,
+            (Opcode::Bswap, I64) => BitOp::Rev64,
             _ => unreachable!("Called with non-bit op!: {:?}", op_ty),
         }
     }
@@ -470,6 +487,22 @@ pub struct CallIndInfo {
     pub callee_callconv: CallConv,
 }
 
+/// Additional information for (direct) ReturnCall instructions, left out of line to lower the size
+/// of the Inst enum.
+#[derive(Clone, Debug)]
+pub struct ReturnCallInfo {
+    pub dest: ExternalName,
+    pub uses: Vec<Reg>,
+}
+
+/// Additional information for ReturnCallInd instructions, left out of line to lower the size of
+/// the Inst enum.
+#[derive(Clone, Debug)]
+pub struct ReturnCallIndInfo {
+    pub rn: Reg,
+    pub uses: Vec<Reg>,
+}
+
 /// Additional information for JTSequence instructions, left out of line to lower the size of the Inst
 /// enum.
 #[derive(Clone, Debug)]
@@ -750,6 +783,17 @@ pub enum Inst {
         ty: Type, // I8, I16, I32 or I64
     },
 
+    /// A 128-bit compare-and-swap operation, implemented using an `ldxp`/`stxp` loop.  As with
+    /// `AtomicCASLoop`, the sequence is both preceded and followed by a fence and is sequentially
+    /// consistent.  The operand conventions are:
+    ///
+    /// x25       (rd) address
+    /// x27:x26   (rd) expected value
+    /// x23:x22   (rd) replacement value
+    /// x21:x20   (wr) old value
+    /// x24       (wr) scratch reg; value afterwards has no meaning
+    AtomicCAS128Loop,
+
     /// Read `ty` bits from address `r_addr`, zero extend the loaded value to 64 bits and put it
     /// in `r_data`.  The load instruction is preceded by a fence at least as comprehensive as
     /// that of the `Fence` instruction below.  This instruction is sequentially consistent.
@@ -773,6 +817,21 @@ pub enum Inst {
     /// ish".  This instruction is sequentially consistent.
     Fence,
 
+    /// A load memory fence: no load before the fence may move after any load or store after
+    /// it.  Emitted as "dmb ishld", this is enough for an acquire fence.
+    LoadFence,
+
+    /// An inline stack probe loop, emitted in the prologue: it moves SP down by `guard_size` at
+    /// a time, storing to each page on the way, until it has covered `frame_size` bytes, and then
+    /// restores SP. `frame_size` must be a multiple of `guard_size`, and `guard_size` must be
+    /// encodable as an `Imm12`.
+    ///
+    /// x16   (wr) scratch reg; value afterwards has no meaning
+    StackProbeLoop {
+        frame_size: u32,
+        guard_size: u32,
+    },
+
     /// FPU move. Note that this is distinct from a vector-register
     /// move; moving just 64 bits seems to be significantly faster.
     FpuMove64 {
@@ -843,6 +902,18 @@ pub enum Inst {
         rm: Reg,
     },
 
+    /// Floating-point load, half-precision (16 bit).
+    FpuLoad16 {
+        rd: Writable<Reg>,
+        mem: AMode,
+        flags: MemFlags,
+    },
+    /// Floating-point store, half-precision (16 bit).
+    FpuStore16 {
+        rd: Reg,
+        mem: AMode,
+        flags: MemFlags,
+    },
     /// Floating-point load, single-precision (32 bit).
     FpuLoad32 {
         rd: Writable<Reg>,
@@ -1148,6 +1219,22 @@ pub enum Inst {
         rd: Writable<Reg>,
     },
 
+    /// Read the virtual counter (actually a `MRS Xn, CNTVCT_EL0` insn).
+    MovFromCntvct {
+        rd: Writable<Reg>,
+    },
+
+    /// Read the permission overlay register (actually a `MRS Xn, POR_EL0` insn).
+    MovFromPor {
+        rd: Writable<Reg>,
+    },
+
+    /// Write the permission overlay register (actually a `MSR POR_EL0, Xn` insn), followed by an
+    /// `ISB` so that the new permissions apply to the accesses that follow.
+    MovToPor {
+        rn: Reg,
+    },
+
     /// A machine call instruction. N.B.: this allows only a +/- 128MB offset (it uses a relocation
     /// of type `Reloc::Arm64Call`); if the destination distance is not `RelocDistance::Near`, the
     /// code should use a `LoadExtName` / `CallInd` sequence instead, allowing an arbitrary 64-bit
@@ -1164,6 +1251,18 @@ pub enum Inst {
     /// A machine return instruction.
     Ret,
 
+    /// A tail call, after the epilogue: a branch to the callee. Like `Call`, this allows only a
+    /// +/- 128MB offset, and a `LoadExtName` / `ReturnCallInd` sequence is used for other
+    /// destinations.
+    ReturnCall {
+        info: Box<ReturnCallInfo>,
+    },
+
+    /// An indirect tail call, after the epilogue: a branch to the callee's address in a register.
+    ReturnCallInd {
+        info: Box<ReturnCallIndInfo>,
+    },
+
     /// A placeholder instruction, generating no code, meaning that a function epilogue must be
     /// inserted there.
     EpiloguePlaceholder,
@@ -1173,6 +1272,13 @@ pub enum Inst {
         dest: BranchTarget,
     },
 
+    /// An unconditional branch to the normal successor of an invoke, right after its call. The
+    /// call unwinds to `landing_pad` instead when it throws.
+    JumpInvoke {
+        dest: BranchTarget,
+        landing_pad: BranchTarget,
+    },
+
     /// A conditional branch. Contains two targets; at emission time, both are emitted, but
     /// the MachBuffer knows to truncate the trailing branch if fallthrough. We optimize the
     /// choice of taken/not_taken (inverting the branch polarity as needed) based on the
@@ -1247,6 +1353,21 @@ pub enum Inst {
         offset: i64,
     },
 
+    /// A call to the `ElfTlsGetAddr` libcall using the general-dynamic TLS
+    /// model. Returns address of TLS symbol in x0.
+    ElfTlsGetAddr {
+        symbol: Box<ExternalName>,
+    },
+
+    /// An ELF initial-exec TLS symbol access: the thread pointer plus the
+    /// symbol's offset from it, loaded from the GOT. Returns address of the
+    /// TLS symbol in `rd`.
+    ElfTlsInitialExec {
+        symbol: Box<ExternalName>,
+        rd: Writable<Reg>,
+        tmp: Writable<Reg>,
+    },
+
     /// Load address referenced by `mem` into `rd`.
     LoadAddr {
         rd: Writable<Reg>,
@@ -1609,6 +1730,11 @@ impl Inst {
                 mem,
                 flags,
             },
+            F16 => Inst::FpuLoad16 {
+                rd: into_reg,
+                mem,
+                flags,
+            },
             F32 => Inst::FpuLoad32 {
                 rd: into_reg,
                 mem,
@@ -1660,6 +1786,11 @@ impl Inst {
                 mem,
                 flags,
             },
+            F16 => Inst::FpuStore16 {
+                rd: from_reg,
+                mem,
+                flags,
+            },
             F32 => Inst::FpuStore32 {
                 rd: from_reg,
                 mem,
@@ -1856,6 +1987,16 @@ fn aarch64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             collector.add_def(writable_xreg(24));
             collector.add_def(writable_xreg(27));
         }
+        &Inst::AtomicCAS128Loop => {
+            collector.add_use(xreg(22));
+            collector.add_use(xreg(23));
+            collector.add_use(xreg(25));
+            collector.add_use(xreg(26));
+            collector.add_use(xreg(27));
+            collector.add_def(writable_xreg(20));
+            collector.add_def(writable_xreg(21));
+            collector.add_def(writable_xreg(24));
+        }
         &Inst::AtomicLoad { r_data, r_addr, .. } => {
             collector.add_use(r_addr);
             collector.add_def(r_data);
@@ -1864,7 +2005,10 @@ fn aarch64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             collector.add_use(r_addr);
             collector.add_use(r_data);
         }
-        &Inst::Fence {} => {}
+        &Inst::Fence {} | &Inst::LoadFence {} => {}
+        &Inst::StackProbeLoop { .. } => {
+            collector.add_def(writable_spilltmp_reg());
+        }
         &Inst::FpuMove64 { rd, rn } => {
             collector.add_def(rd);
             collector.add_use(rn);
@@ -1966,6 +2110,10 @@ fn aarch64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             collector.add_use(rn);
             collector.add_use(rm);
         }
+        &Inst::FpuLoad16 { rd, ref mem, .. } => {
+            collector.add_def(rd);
+            memarg_regs(mem, collector);
+        }
         &Inst::FpuLoad32 { rd, ref mem, .. } => {
             collector.add_def(rd);
             memarg_regs(mem, collector);
@@ -1978,6 +2126,10 @@ fn aarch64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             collector.add_def(rd);
             memarg_regs(mem, collector);
         }
+        &Inst::FpuStore16 { rd, ref mem, .. } => {
+            collector.add_use(rd);
+            memarg_regs(mem, collector);
+        }
         &Inst::FpuStore32 { rd, ref mem, .. } => {
             collector.add_use(rd);
             memarg_regs(mem, collector);
@@ -2098,17 +2250,18 @@ fn aarch64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             collector.add_use(rn);
             collector.add_use(rm);
         }
-        &Inst::MovToNZCV { rn } => {
+        &Inst::MovToNZCV { rn } | &Inst::MovToPor { rn } => {
             collector.add_use(rn);
         }
-        &Inst::MovFromNZCV { rd } => {
+        &Inst::MovFromNZCV { rd } | &Inst::MovFromCntvct { rd } | &Inst::MovFromPor { rd } => {
             collector.add_def(rd);
         }
         &Inst::Extend { rd, rn, .. } => {
             collector.add_def(rd);
             collector.add_use(rn);
         }
-        &Inst::Jump { .. } | &Inst::Ret | &Inst::EpiloguePlaceholder => {}
+        &Inst::Jump { .. } | &Inst::JumpInvoke { .. } | &Inst::Ret | &Inst::EpiloguePlaceholder => {
+        }
         &Inst::Call { ref info, .. } => {
             collector.add_uses(&*info.uses);
             collector.add_defs(&*info.defs);
@@ -2118,6 +2271,13 @@ fn aarch64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             collector.add_defs(&*info.defs);
             collector.add_use(info.rn);
         }
+        &Inst::ReturnCall { ref info } => {
+            collector.add_uses(&*info.uses);
+        }
+        &Inst::ReturnCallInd { ref info } => {
+            collector.add_uses(&*info.uses);
+            collector.add_use(info.rn);
+        }
         &Inst::CondBr { ref kind, .. } => match kind {
             CondBrKind::Zero(rt) | CondBrKind::NotZero(rt) => {
                 collector.add_use(*rt);
@@ -2150,6 +2310,17 @@ fn aarch64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
         &Inst::LoadExtName { rd, .. } => {
             collector.add_def(rd);
         }
+        &Inst::ElfTlsGetAddr { .. } => {
+            // All caller-saves are clobbered; the pseudoinstruction is specific
+            // to ELF systems, so the SysV calling convention applies.
+            for reg in AArch64MachineDeps::get_regs_clobbered_by_call(CallConv::SystemV) {
+                collector.add_def(reg);
+            }
+        }
+        &Inst::ElfTlsInitialExec { rd, tmp, .. } => {
+            collector.add_def(rd);
+            collector.add_def(tmp);
+        }
         &Inst::LoadAddr { rd, ref mem } => {
             collector.add_def(rd);
             memarg_regs(mem, collector);
@@ -2466,7 +2637,7 @@ fn aarch64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             map_use(mapper, rt);
             map_use(mapper, rn);
         }
-        &mut Inst::AtomicCASLoop { .. } => {
+        &mut Inst::AtomicCASLoop { .. } | &mut Inst::AtomicCAS128Loop => {
             // There are no vregs to map in this insn.
         }
         &mut Inst::AtomicLoad {
@@ -2485,7 +2656,10 @@ fn aarch64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             map_use(mapper, r_data);
             map_use(mapper, r_addr);
         }
-        &mut Inst::Fence {} => {}
+        &mut Inst::Fence {} | &mut Inst::LoadFence {} => {}
+        &mut Inst::StackProbeLoop { .. } => {
+            // There are no vregs to map in this insn.
+        }
         &mut Inst::FpuMove64 {
             ref mut rd,
             ref mut rn,
@@ -2656,6 +2830,14 @@ fn aarch64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             map_use(mapper, rn);
             map_use(mapper, rm);
         }
+        &mut Inst::FpuLoad16 {
+            ref mut rd,
+            ref mut mem,
+            ..
+        } => {
+            map_def(mapper, rd);
+            map_mem(mapper, mem);
+        }
         &mut Inst::FpuLoad32 {
             ref mut rd,
             ref mut mem,
@@ -2680,6 +2862,14 @@ fn aarch64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             map_def(mapper, rd);
             map_mem(mapper, mem);
         }
+        &mut Inst::FpuStore16 {
+            ref mut rd,
+            ref mut mem,
+            ..
+        } => {
+            map_use(mapper, rd);
+            map_mem(mapper, mem);
+        }
         &mut Inst::FpuStore32 {
             ref mut rd,
             ref mut mem,
@@ -2898,10 +3088,12 @@ fn aarch64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             map_use(mapper, rn);
             map_use(mapper, rm);
         }
-        &mut Inst::MovToNZCV { ref mut rn } => {
+        &mut Inst::MovToNZCV { ref mut rn } | &mut Inst::MovToPor { ref mut rn } => {
             map_use(mapper, rn);
         }
-        &mut Inst::MovFromNZCV { ref mut rd } => {
+        &mut Inst::MovFromNZCV { ref mut rd }
+        | &mut Inst::MovFromCntvct { ref mut rd }
+        | &mut Inst::MovFromPor { ref mut rd } => {
             map_def(mapper, rd);
         }
         &mut Inst::Extend {
@@ -2912,7 +3104,7 @@ fn aarch64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             map_def(mapper, rd);
             map_use(mapper, rn);
         }
-        &mut Inst::Jump { .. } => {}
+        &mut Inst::Jump { .. } | &mut Inst::JumpInvoke { .. } => {}
         &mut Inst::Call { ref mut info } => {
             for r in info.uses.iter_mut() {
                 map_use(mapper, r);
@@ -2931,6 +3123,17 @@ fn aarch64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             }
             map_use(mapper, &mut info.rn);
         }
+        &mut Inst::ReturnCall { ref mut info } => {
+            for r in info.uses.iter_mut() {
+                map_use(mapper, r);
+            }
+        }
+        &mut Inst::ReturnCallInd { ref mut info } => {
+            for r in info.uses.iter_mut() {
+                map_use(mapper, r);
+            }
+            map_use(mapper, &mut info.rn);
+        }
         &mut Inst::CondBr { ref mut kind, .. } => {
             map_br(mapper, kind);
         }
@@ -2958,6 +3161,15 @@ fn aarch64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
         &mut Inst::LoadExtName { ref mut rd, .. } => {
             map_def(mapper, rd);
         }
+        &mut Inst::ElfTlsGetAddr { .. } => {}
+        &mut Inst::ElfTlsInitialExec {
+            ref mut rd,
+            ref mut tmp,
+            ..
+        } => {
+            map_def(mapper, rd);
+            map_def(mapper, tmp);
+        }
         &mut Inst::LoadAddr {
             ref mut rd,
             ref mut mem,
@@ -3005,6 +3217,13 @@ impl MachInst for Inst {
         }
     }
 
+    fn is_call(&self) -> bool {
+        match self {
+            &Inst::Call { .. } | &Inst::CallInd { .. } => true,
+            _ => false,
+        }
+    }
+
     fn is_included_in_clobbers(&self) -> bool {
         // We exclude call instructions from the clobber-set when they are calls
         // from caller to callee with the same ABI. Such calls cannot possibly
@@ -3026,7 +3245,11 @@ impl MachInst for Inst {
     fn is_term<'a>(&'a self) -> MachTerminator<'a> {
         match self {
             &Inst::Ret | &Inst::EpiloguePlaceholder => MachTerminator::Ret,
+            &Inst::ReturnCall { .. } | &Inst::ReturnCallInd { .. } => MachTerminator::RetCall,
             &Inst::Jump { dest } => MachTerminator::Uncond(dest.as_label().unwrap()),
+            &Inst::JumpInvoke { dest, landing_pad } => {
+                MachTerminator::Invoke(dest.as_label().unwrap(), landing_pad.as_label().unwrap())
+            }
             &Inst::CondBr {
                 taken, not_taken, ..
             } => MachTerminator::Cond(taken.as_label().unwrap(), not_taken.as_label().unwrap()),
@@ -3110,6 +3333,7 @@ impl MachInst for Inst {
             B64 => Ok((&[RegClass::I64], &[B64])),
             R32 => panic!("32-bit reftype pointer should never be seen on AArch64"),
             R64 => Ok((&[RegClass::I64], &[R64])),
+            F16 => Ok((&[RegClass::V128], &[F16])),
             F32 => Ok((&[RegClass::V128], &[F32])),
             F64 => Ok((&[RegClass::V128], &[F64])),
             I128 => Ok((&[RegClass::I64, RegClass::I64], &[I64, I64])),
@@ -3500,6 +3724,9 @@ impl Inst {
                     "atomically {{ compare-and-swap({}_bits_at_[x25], x26 -> x28), x27 = old_value_at_[x25]; x24 = trash }}",
                     ty.bits())
             }
+            &Inst::AtomicCAS128Loop => {
+                "atomically { compare-and-swap(128_bits_at_[x25], x27:x26 -> x23:x22), x21:x20 = old_value_at_[x25]; x24 = trash }".to_string()
+            }
             &Inst::AtomicLoad {
                 ty, r_data, r_addr, ..
             } => {
@@ -3523,6 +3750,18 @@ impl Inst {
             &Inst::Fence {} => {
                 format!("dmb ish")
             }
+            &Inst::LoadFence {} => {
+                format!("dmb ishld")
+            }
+            &Inst::StackProbeLoop {
+                frame_size,
+                guard_size,
+            } => {
+                format!(
+                    "stack_probe_loop frame_size={}, guard_size={}; x16 = trash",
+                    frame_size, guard_size
+                )
+            }
             &Inst::FpuMove64 { rd, rn } => {
                 let rd = show_vreg_scalar(rd.to_reg(), mb_rru, ScalarSize::Size64);
                 let rn = show_vreg_scalar(rn, mb_rru, ScalarSize::Size64);
@@ -3554,6 +3793,10 @@ impl Inst {
                     FPUOp1::Sqrt64 => ("fsqrt", ScalarSize::Size64, ScalarSize::Size64),
                     FPUOp1::Cvt32To64 => ("fcvt", ScalarSize::Size32, ScalarSize::Size64),
                     FPUOp1::Cvt64To32 => ("fcvt", ScalarSize::Size64, ScalarSize::Size32),
+                    FPUOp1::Cvt16To32 => ("fcvt", ScalarSize::Size16, ScalarSize::Size32),
+                    FPUOp1::Cvt16To64 => ("fcvt", ScalarSize::Size16, ScalarSize::Size64),
+                    FPUOp1::Cvt32To16 => ("fcvt", ScalarSize::Size32, ScalarSize::Size16),
+                    FPUOp1::Cvt64To16 => ("fcvt", ScalarSize::Size64, ScalarSize::Size16),
                 };
                 let rd = show_vreg_scalar(rd.to_reg(), mb_rru, sizedest);
                 let rn = show_vreg_scalar(rn, mb_rru, sizesrc);
@@ -3627,6 +3870,12 @@ impl Inst {
                 let rm = show_vreg_scalar(rm, mb_rru, ScalarSize::Size64);
                 format!("fcmp {}, {}", rn, rm)
             }
+            &Inst::FpuLoad16 { rd, ref mem, .. } => {
+                let rd = show_vreg_scalar(rd.to_reg(), mb_rru, ScalarSize::Size16);
+                let (mem_str, mem) = mem_finalize_for_show(mem, mb_rru, state);
+                let mem = mem.show_rru(mb_rru);
+                format!("{}ldr {}, {}", mem_str, rd, mem)
+            }
             &Inst::FpuLoad32 { rd, ref mem, .. } => {
                 let rd = show_vreg_scalar(rd.to_reg(), mb_rru, ScalarSize::Size32);
                 let (mem_str, mem) = mem_finalize_for_show(mem, mb_rru, state);
@@ -3646,6 +3895,12 @@ impl Inst {
                 let mem = mem.show_rru(mb_rru);
                 format!("{}ldr {}, {}", mem_str, rd, mem)
             }
+            &Inst::FpuStore16 { rd, ref mem, .. } => {
+                let rd = show_vreg_scalar(rd, mb_rru, ScalarSize::Size16);
+                let (mem_str, mem) = mem_finalize_for_show(mem, mb_rru, state);
+                let mem = mem.show_rru(mb_rru);
+                format!("{}str {}, {}", mem_str, rd, mem)
+            }
             &Inst::FpuStore32 { rd, ref mem, .. } => {
                 let rd = show_vreg_scalar(rd, mb_rru, ScalarSize::Size32);
                 let (mem_str, mem) = mem_finalize_for_show(mem, mb_rru, state);
@@ -4113,6 +4368,18 @@ impl Inst {
                 let rd = rd.to_reg().show_rru(mb_rru);
                 format!("mrs {}, nzcv", rd)
             }
+            &Inst::MovFromCntvct { rd } => {
+                let rd = rd.to_reg().show_rru(mb_rru);
+                format!("mrs {}, cntvct_el0", rd)
+            }
+            &Inst::MovFromPor { rd } => {
+                let rd = rd.to_reg().show_rru(mb_rru);
+                format!("mrs {}, por_el0", rd)
+            }
+            &Inst::MovToPor { rn } => {
+                let rn = rn.show_rru(mb_rru);
+                format!("msr por_el0, {} ; isb", rn)
+            }
             &Inst::Extend {
                 rd,
                 rn,
@@ -4177,11 +4444,24 @@ impl Inst {
                 format!("blr {}", rn)
             }
             &Inst::Ret => "ret".to_string(),
+            &Inst::ReturnCall { .. } => format!("b 0"),
+            &Inst::ReturnCallInd { ref info } => {
+                let rn = info.rn.show_rru(mb_rru);
+                format!("br {}", rn)
+            }
             &Inst::EpiloguePlaceholder => "epilogue placeholder".to_string(),
             &Inst::Jump { ref dest } => {
                 let dest = dest.show_rru(mb_rru);
                 format!("b {}", dest)
             }
+            &Inst::JumpInvoke {
+                ref dest,
+                ref landing_pad,
+            } => {
+                let dest = dest.show_rru(mb_rru);
+                let landing_pad = landing_pad.show_rru(mb_rru);
+                format!("b {} ; unwind {}", dest, landing_pad)
+            }
             &Inst::CondBr {
                 ref taken,
                 ref not_taken,
@@ -4270,6 +4550,18 @@ impl Inst {
                 let rd = rd.show_rru(mb_rru);
                 format!("ldr {}, 8 ; b 12 ; data {:?} + {}", rd, name, offset)
             }
+            &Inst::ElfTlsGetAddr { ref symbol } => {
+                format!("elf_tls_get_addr {:?}", symbol)
+            }
+            &Inst::ElfTlsInitialExec {
+                ref symbol,
+                rd,
+                tmp,
+            } => {
+                let rd = rd.show_rru(mb_rru);
+                let tmp = tmp.show_rru(mb_rru);
+                format!("elf_tls_initial_exec {:?}, {}, {}", symbol, rd, tmp)
+            }
             &Inst::LoadAddr { rd, ref mem } => {
                 // TODO: we really should find a better way to avoid duplication of
                 // this logic between `emit()` and `show_rru()` -- a separate 1-to-N
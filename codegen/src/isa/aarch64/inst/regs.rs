@@ -5,9 +5,7 @@ use crate::{
     settings,
 };
 
-use regalloc::{
-    PrettyPrint, RealRegUniverse, Reg, RegClass, RegClassInfo, Writable, NUM_REG_CLASSES,
-};
+use regalloc::{PrettyPrint, RealRegUniverse, Reg, RegClass, RegClassInfo, Writable};
 
 use alloc::string::{String, ToString};
 
@@ -161,7 +159,7 @@ pub fn writable_tmp2_reg() -> Writable<Reg> {
 /// Create the register universe for AArch64.
 pub fn create_reg_universe(flags: &settings::Flags) -> RealRegUniverse {
     let mut regs = vec![];
-    let mut allocable_by_class = [None; NUM_REG_CLASSES];
+    let mut allocable_by_class = vec![None; RegClass::V128.rc_to_usize() + 1];
 
     // Numbering Scheme: we put V-regs first, then X-regs. The X-regs exclude several registers:
     // x18 (globally reserved for platform-specific purposes), x29 (frame pointer), x30 (link
@@ -210,11 +208,13 @@ pub fn create_reg_universe(flags: &settings::Flags) -> RealRegUniverse {
         first: x_reg_base as usize,
         last: x_reg_last as usize,
         suggested_scratch: Some(XREG_INDICES[19] as usize),
+        spill_class: None,
     });
     allocable_by_class[RegClass::V128.rc_to_usize()] = Some(RegClassInfo {
         first: v_reg_base as usize,
         last: v_reg_last as usize,
         suggested_scratch: Some(/* V31: */ 31),
+        spill_class: None,
     });
 
     // Other regs, not available to the allocator.
@@ -252,6 +252,7 @@ pub fn create_reg_universe(flags: &settings::Flags) -> RealRegUniverse {
         regs,
         allocable,
         allocable_by_class,
+        aliases: vec![],
     }
 }
 
@@ -1773,7 +1773,7 @@ impl LowerBackend for AArch64Backend {
         branches: &[IRInst],
         targets: &[MachLabel],
     ) -> CodegenResult<()> {
-        lower_inst::lower_branch(ctx, branches, targets)
+        lower_inst::lower_branch(ctx, branches, targets, &self.flags)
     }
 
     fn maybe_pinned_reg(&self) -> Option<Reg> {
@@ -2,10 +2,13 @@
 
 use crate::{
     binemit::CodeOffset,
-    ir::{condcodes::FloatCC, types::*, Inst as IRInst, InstructionData, Opcode, TrapCode},
+    ir::{
+        condcodes::FloatCC, types::*, Inst as IRInst, InstructionData, MemoryOrdering, Opcode,
+        TlsAccessModel, TrapCode,
+    },
     isa::aarch64::settings as aarch64_settings,
     machinst::{lower::*, *},
-    settings::Flags,
+    settings::{Flags, JumpTableEntries, TlsModel},
     CodegenError, CodegenResult,
 };
 
@@ -1214,22 +1217,29 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                 }
             } else {
                 let rd = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
-                let needs_zext = match op {
-                    Opcode::Bitrev | Opcode::Ctz => false,
-                    Opcode::Clz | Opcode::Cls => true,
+                let narrow_mode = match (op, ty_bits(ty)) {
+                    (Opcode::Bitrev, _) | (Opcode::Ctz, _) => NarrowValueMode::None,
+                    (Opcode::Clz, 64) => NarrowValueMode::ZeroExtend64,
+                    (Opcode::Clz, _) => NarrowValueMode::ZeroExtend32,
+                    (Opcode::Cls, 64) => NarrowValueMode::SignExtend64,
+                    (Opcode::Cls, _) => NarrowValueMode::SignExtend32,
                     _ => unreachable!(),
                 };
-                let narrow_mode = if needs_zext && ty_bits(ty) == 64 {
-                    NarrowValueMode::ZeroExtend64
-                } else if needs_zext {
-                    NarrowValueMode::ZeroExtend32
-                } else {
-                    NarrowValueMode::None
-                };
                 let rn = put_input_in_reg(ctx, inputs[0], narrow_mode);
 
                 ctx.emit(Inst::BitRR { rd, rn, op: bitop });
 
+                // Counting the leading bits of an extended n-bit value (n < 32) with a 32-bit
+                // instruction also counts the 32 - n bits added by the extension.
+                if (op == Opcode::Clz || op == Opcode::Cls) && ty_bits(ty) < 32 {
+                    ctx.emit(Inst::AluRRImm12 {
+                        alu_op: ALUOp::Sub32,
+                        rd,
+                        rn: rd.to_reg(),
+                        imm12: Imm12::maybe_from_u64(32 - ty_bits(ty) as u64).unwrap(),
+                    });
+                }
+
                 // Both bitrev and ctz use a bit-reverse (rbit) instruction; ctz to reduce the problem
                 // to a clz, and bitrev as the main operation.
                 if op == Opcode::Bitrev || op == Opcode::Ctz {
@@ -1263,6 +1273,17 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             }
         }
 
+        Opcode::Bswap => {
+            let ty = ty.unwrap();
+            let rd = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            let rn = put_input_in_reg(ctx, inputs[0], NarrowValueMode::None);
+            ctx.emit(Inst::BitRR {
+                rd,
+                rn,
+                op: BitOp::from((op, ty)),
+            });
+        }
+
         Opcode::Popcnt => {
             let ty = ty.unwrap();
 
@@ -1432,6 +1453,7 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                             (1, _, _) => Inst::ULoad8 { rd, mem, flags },
                             (8, false, _) => Inst::ULoad8 { rd, mem, flags },
                             (8, true, _) => Inst::SLoad8 { rd, mem, flags },
+                            (16, _, true) => Inst::FpuLoad16 { rd, mem, flags },
                             (16, false, _) => Inst::ULoad16 { rd, mem, flags },
                             (16, true, _) => Inst::SLoad16 { rd, mem, flags },
                             (32, false, false) => Inst::ULoad32 { rd, mem, flags },
@@ -1511,7 +1533,8 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                 let mem = lower_address(ctx, elem_ty, &inputs[1..], off);
                 ctx.emit(match (ty_bits(elem_ty), is_float) {
                     (1, _) | (8, _) => Inst::Store8 { rd, mem, flags },
-                    (16, _) => Inst::Store16 { rd, mem, flags },
+                    (16, true) => Inst::FpuStore16 { rd, mem, flags },
+                    (16, false) => Inst::Store16 { rd, mem, flags },
                     (32, false) => Inst::Store32 { rd, mem, flags },
                     (32, true) => Inst::FpuStore32 { rd, mem, flags },
                     (64, false) => Inst::Store64 { rd, mem, flags },
@@ -1561,6 +1584,47 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             // Also, x24 and x28 are trashed.  `fn aarch64_get_regs` must mention that.
         }
 
+        Opcode::AtomicCas if ty == Some(I128) => {
+            let r_dst = get_output_reg(ctx, outputs[0]);
+            let r_addr = put_input_in_reg(ctx, inputs[0], NarrowValueMode::None);
+            let r_expected = put_input_in_regs(ctx, inputs[1]);
+            let r_replacement = put_input_in_regs(ctx, inputs[2]);
+            // Make sure that all the args are in virtual regs.  See corresponding comment for
+            // `Opcode::AtomicRmw` above.
+            let r_addr = ctx.ensure_in_vreg(r_addr, I64);
+            let r_expected_lo = ctx.ensure_in_vreg(r_expected.regs()[0], I64);
+            let r_expected_hi = ctx.ensure_in_vreg(r_expected.regs()[1], I64);
+            let r_replacement_lo = ctx.ensure_in_vreg(r_replacement.regs()[0], I64);
+            let r_replacement_hi = ctx.ensure_in_vreg(r_replacement.regs()[1], I64);
+            // Move the args to the preordained AtomicCAS128Loop input regs
+            ctx.emit(Inst::gen_move(Writable::from_reg(xreg(25)), r_addr, I64));
+            ctx.emit(Inst::gen_move(
+                Writable::from_reg(xreg(26)),
+                r_expected_lo,
+                I64,
+            ));
+            ctx.emit(Inst::gen_move(
+                Writable::from_reg(xreg(27)),
+                r_expected_hi,
+                I64,
+            ));
+            ctx.emit(Inst::gen_move(
+                Writable::from_reg(xreg(22)),
+                r_replacement_lo,
+                I64,
+            ));
+            ctx.emit(Inst::gen_move(
+                Writable::from_reg(xreg(23)),
+                r_replacement_hi,
+                I64,
+            ));
+            ctx.emit(Inst::AtomicCAS128Loop);
+            // And finally, copy the preordained AtomicCAS128Loop output regs to their destination.
+            ctx.emit(Inst::gen_move(r_dst.regs()[0], xreg(20), I64));
+            ctx.emit(Inst::gen_move(r_dst.regs()[1], xreg(21), I64));
+            // Also, x24 is trashed.  `fn aarch64_get_regs` must mention that.
+        }
+
         Opcode::AtomicCas => {
             let r_dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
             let mut r_addr = put_input_in_reg(ctx, inputs[0], NarrowValueMode::None);
@@ -1630,9 +1694,14 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             });
         }
 
-        Opcode::Fence => {
-            ctx.emit(Inst::Fence {});
-        }
+        Opcode::Fence => match ctx.data(insn).memory_ordering().unwrap() {
+            MemoryOrdering::Acquire => ctx.emit(Inst::LoadFence {}),
+            // Loads as well as stores before a release fence have to be ordered against the
+            // stores after it, which takes a full barrier.
+            MemoryOrdering::Release | MemoryOrdering::AcqRel | MemoryOrdering::SeqCst => {
+                ctx.emit(Inst::Fence {})
+            }
+        },
 
         Opcode::StackLoad | Opcode::StackStore => {
             panic!("Direct stack memory access not supported; should not be used by Wasm");
@@ -1646,13 +1715,17 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             panic!("table_addr should have been removed by legalization!");
         }
 
+        Opcode::AssertEq | Opcode::Assume => {
+            panic!("annotations should have been removed after legalization!");
+        }
+
         Opcode::ConstAddr => unimplemented!(),
 
         Opcode::Nop => {
             // Nothing.
         }
 
-        Opcode::Select => {
+        Opcode::Select | Opcode::SelectSpectreGuard => {
             let flag_input = inputs[0];
             let cond = if let Some(icmp_insn) =
                 maybe_input_insn_via_conv(ctx, flag_input, Opcode::Icmp, Opcode::Bint)
@@ -2084,25 +2157,26 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             });
         }
 
-        Opcode::Call | Opcode::CallIndirect => {
+        Opcode::Call | Opcode::CallIndirect | Opcode::ReturnCall | Opcode::ReturnCallIndirect => {
             let caller_conv = ctx.abi().call_conv();
             let (mut abi, inputs) = match op {
-                Opcode::Call => {
+                Opcode::Call | Opcode::ReturnCall => {
                     let (extname, dist) = ctx.call_target(insn).unwrap();
                     let extname = extname.clone();
                     let sig = ctx.call_sig(insn).unwrap();
                     assert!(inputs.len() == sig.params.len());
-                    assert!(outputs.len() == sig.returns.len());
+                    // Tail calls have no results of their own.
+                    assert!(op.is_tail_call() || outputs.len() == sig.returns.len());
                     (
                         AArch64ABICaller::from_func(sig, &extname, dist, caller_conv, flags)?,
                         &inputs[..],
                     )
                 }
-                Opcode::CallIndirect => {
+                Opcode::CallIndirect | Opcode::ReturnCallIndirect => {
                     let ptr = put_input_in_reg(ctx, inputs[0], NarrowValueMode::ZeroExtend64);
                     let sig = ctx.call_sig(insn).unwrap();
                     assert!(inputs.len() - 1 == sig.params.len());
-                    assert!(outputs.len() == sig.returns.len());
+                    assert!(op.is_tail_call() || outputs.len() == sig.returns.len());
                     (
                         AArch64ABICaller::from_ptr(sig, ptr, op, caller_conv, flags)?,
                         &inputs[1..],
@@ -2111,6 +2185,16 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                 _ => unreachable!(),
             };
 
+            if op.is_tail_call() {
+                assert!(inputs.len() == abi.num_args());
+                for i in abi.get_copy_to_arg_order() {
+                    let arg_regs = put_input_in_regs(ctx, inputs[i]);
+                    abi.emit_copy_regs_to_arg(ctx, i, arg_regs);
+                }
+                abi.emit_tail_call(ctx)?;
+                return Ok(());
+            }
+
             abi.emit_stack_pre_adjust(ctx);
             assert!(inputs.len() == abi.num_args());
             for i in abi.get_copy_to_arg_order() {
@@ -2136,6 +2220,41 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             ctx.emit(Inst::gen_move(writable_xreg(PINNED_REG), rm, I64));
         }
 
+        Opcode::GetCycleCounter => {
+            let rd = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            ctx.emit(Inst::MovFromCntvct { rd });
+        }
+
+        Opcode::StackUsed => {
+            // The frame pointer points to the frame record, with the frame pointer and the link
+            // register of the caller, right below the stack pointer of the caller.
+            let rd = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            let sp = ctx.alloc_tmp(I64).only_reg().unwrap();
+            ctx.emit(Inst::gen_move(sp, stack_reg(), I64));
+            ctx.emit(Inst::AluRRR {
+                alu_op: ALUOp::Sub64,
+                rd,
+                rn: fp_reg(),
+                rm: sp.to_reg(),
+            });
+            ctx.emit(Inst::AluRRImm12 {
+                alu_op: ALUOp::Add64,
+                rd,
+                rn: rd.to_reg(),
+                imm12: Imm12::maybe_from_u64(16).unwrap(),
+            });
+        }
+
+        Opcode::GetPkeyRights => {
+            let rd = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            ctx.emit(Inst::MovFromPor { rd });
+        }
+
+        Opcode::SetPkeyRights => {
+            let rn = put_input_in_reg(ctx, inputs[0], NarrowValueMode::None);
+            ctx.emit(Inst::MovToPor { rn });
+        }
+
         Opcode::Spill
         | Opcode::Fill
         | Opcode::FillNop
@@ -2160,10 +2279,16 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
         | Opcode::Brif
         | Opcode::Brff
         | Opcode::IndirectJumpTableBr
-        | Opcode::BrTable => {
+        | Opcode::BrTable
+        | Opcode::Invoke
+        | Opcode::InvokeIndirect => {
             panic!("Branch opcode reached non-branch lowering logic!");
         }
 
+        Opcode::LandingPad => {
+            panic!("landing_pad is lowered where the unwinder enters the landing pad!");
+        }
+
         Opcode::Vconst => {
             let value = const_param_to_u128(ctx, insn).expect("Invalid immediate bytes");
             let rd = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
@@ -2858,10 +2983,17 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                     (Opcode::Fneg, 64) => FPUOp1::Neg64,
                     (Opcode::Fabs, 32) => FPUOp1::Abs32,
                     (Opcode::Fabs, 64) => FPUOp1::Abs64,
-                    (Opcode::Fpromote, 32) => panic!("Cannot promote to 32 bits"),
-                    (Opcode::Fpromote, 64) => FPUOp1::Cvt32To64,
-                    (Opcode::Fdemote, 32) => FPUOp1::Cvt64To32,
-                    (Opcode::Fdemote, 64) => panic!("Cannot demote to 64 bits"),
+                    (Opcode::Fpromote, _) | (Opcode::Fdemote, _) => {
+                        match (ty_bits(ctx.input_ty(insn, 0)), bits) {
+                            (16, 32) => FPUOp1::Cvt16To32,
+                            (16, 64) => FPUOp1::Cvt16To64,
+                            (32, 64) => FPUOp1::Cvt32To64,
+                            (32, 16) => FPUOp1::Cvt32To16,
+                            (64, 16) => FPUOp1::Cvt64To16,
+                            (64, 32) => FPUOp1::Cvt64To32,
+                            (from, to) => panic!("Cannot convert from {} to {} bits", from, to),
+                        }
+                    }
                     _ => panic!("Unknown op/bits combination"),
                 };
                 ctx.emit(Inst::FpuRR { fpu_op, rd, rn });
@@ -3485,7 +3617,28 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             });
         }
 
-        Opcode::TlsValue => unimplemented!("tls_value"),
+        Opcode::TlsValue => {
+            let rd = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            let (name, model) = ctx.tls_value(insn).unwrap();
+            let symbol = Box::new(name.clone());
+            match (flags.tls_model(), model) {
+                (TlsModel::ElfGd, Some(TlsAccessModel::InitialExec)) => {
+                    let tmp = ctx.alloc_tmp(I64).only_reg().unwrap();
+                    ctx.emit(Inst::ElfTlsInitialExec { symbol, rd, tmp });
+                }
+                (TlsModel::ElfGd, _) => {
+                    ctx.emit(Inst::ElfTlsGetAddr { symbol });
+                    ctx.emit(Inst::gen_move(rd, xreg(0), I64));
+                }
+                _ => {
+                    return Err(CodegenError::Unsupported(format!(
+                        "Unimplemented TLS model in AArch64 backend: {:?}",
+                        flags.tls_model()
+                    )));
+                }
+            }
+        }
+
         Opcode::FcvtLowFromSint => unimplemented!("FcvtLowFromSint"),
         Opcode::FvpromoteLow => unimplemented!("FvpromoteLow"),
         Opcode::Fvdemote => unimplemented!("Fvdemote"),
@@ -3494,10 +3647,69 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
     Ok(())
 }
 
+/// Lower the call of an `invoke` or `invoke_indirect`, ending the block with a jump to `normal`
+/// from which the call may unwind to `landing_pad` instead.
+fn lower_invoke<C: LowerCtx<I = Inst>>(
+    ctx: &mut C,
+    insn: IRInst,
+    normal: BranchTarget,
+    landing_pad: BranchTarget,
+    flags: &Flags,
+) -> CodegenResult<()> {
+    let op = ctx.data(insn).opcode();
+    let inputs = insn_inputs(ctx, insn);
+    let outputs = insn_outputs(ctx, insn);
+
+    let caller_conv = ctx.abi().call_conv();
+    let num_args = ctx.call_sig(insn).unwrap().params.len();
+    assert!(outputs.len() == ctx.call_sig(insn).unwrap().returns.len());
+    // The call arguments come first, followed by the landing pad arguments, which are passed on
+    // the exceptional edge.
+    let (mut abi, inputs) = match op {
+        Opcode::Invoke => {
+            let (extname, dist) = ctx.call_target(insn).unwrap();
+            let extname = extname.clone();
+            let sig = ctx.call_sig(insn).unwrap();
+            (
+                AArch64ABICaller::from_func(sig, &extname, dist, caller_conv, flags)?,
+                &inputs[..num_args],
+            )
+        }
+        Opcode::InvokeIndirect => {
+            let ptr = put_input_in_reg(ctx, inputs[0], NarrowValueMode::ZeroExtend64);
+            let sig = ctx.call_sig(insn).unwrap();
+            (
+                AArch64ABICaller::from_ptr(sig, ptr, op, caller_conv, flags)?,
+                &inputs[1..1 + num_args],
+            )
+        }
+        _ => unreachable!(),
+    };
+
+    assert!(inputs.len() == abi.num_args());
+    for i in abi.get_copy_to_arg_order() {
+        let arg_regs = put_input_in_regs(ctx, inputs[i]);
+        abi.emit_copy_regs_to_arg(ctx, i, arg_regs);
+    }
+    abi.emit_invoke(ctx)?;
+    // The return values are only copied out when the callee returns; an unwinding callee
+    // continues at the landing pad directly.
+    for (i, output) in outputs.iter().enumerate() {
+        let retval_regs = get_output_reg(ctx, *output);
+        abi.emit_copy_retval_to_regs(ctx, i, retval_regs);
+    }
+    ctx.emit(Inst::JumpInvoke {
+        dest: normal,
+        landing_pad,
+    });
+    Ok(())
+}
+
 pub(crate) fn lower_branch<C: LowerCtx<I = Inst>>(
     ctx: &mut C,
     branches: &[IRInst],
     targets: &[MachLabel],
+    flags: &Flags,
 ) -> CodegenResult<()> {
     // A block should end with at most two branches. The first may be a
     // conditional branch; a conditional branch can be followed only by an
@@ -3651,6 +3863,10 @@ pub(crate) fn lower_branch<C: LowerCtx<I = Inst>>(
                 }
             }
 
+            Opcode::Invoke | Opcode::InvokeIndirect => {
+                lower_invoke(ctx, branches[0], not_taken, taken, flags)?;
+            }
+
             _ => unimplemented!(),
         }
     } else {
@@ -3668,6 +3884,12 @@ pub(crate) fn lower_branch<C: LowerCtx<I = Inst>>(
             }
 
             Opcode::BrTable => {
+                if flags.jump_table_entries() != JumpTableEntries::Relative {
+                    return Err(CodegenError::Unsupported(
+                        "absolute jump table entries are only supported on x64".into(),
+                    ));
+                }
+
                 // Expand `br_table index, default, JT` to:
                 //
                 //   emit_island  // this forces an island at this point
@@ -1,9 +1,13 @@
 //! ARM 64-bit Instruction Set Architecture.
 
 use crate::{
-    ir::{condcodes::IntCC, Function},
-    isa::{aarch64::settings as aarch64_settings, Builder as IsaBuilder},
-    machinst::{compile, MachBackend, MachCompileResult, TargetIsaAdapter, VCode},
+    ir::{condcodes::IntCC, Function, Opcode, Type},
+    isa::{
+        aarch64::settings as aarch64_settings, generic_inst_cost, Builder as IsaBuilder, InstCost,
+    },
+    machinst::{
+        compile, stub, MachBackend, MachCompileResult, StubAssembler, TargetIsaAdapter, VCode,
+    },
     result::CodegenResult,
     settings as shared_settings,
 };
@@ -71,6 +75,7 @@ impl MachBackend for AArch64Backend {
 
         let (buffer, bb_starts, bb_edges) = vcode.emit();
         let frame_size = vcode.frame_size();
+        let stats = vcode.stats();
         let stackslot_offsets = vcode.stackslot_offsets().clone();
 
         let disasm = if want_disasm {
@@ -84,6 +89,7 @@ impl MachBackend for AArch64Backend {
         Ok(MachCompileResult {
             buffer,
             frame_size,
+            stats,
             disasm,
             value_labels_ranges: Default::default(),
             stackslot_offsets,
@@ -92,6 +98,21 @@ impl MachBackend for AArch64Backend {
         })
     }
 
+    fn compile_stub(
+        &self,
+        stub: &StubAssembler,
+        want_disasm: bool,
+    ) -> CodegenResult<MachCompileResult> {
+        let emit_info = EmitInfo::new(self.flags.clone());
+        stub::compile_stub::<abi::AArch64MachineDeps>(
+            stub,
+            &self.flags,
+            &emit_info,
+            &self.reg_universe,
+            want_disasm,
+        )
+    }
+
     fn name(&self) -> &'static str {
         "aarch64"
     }
@@ -117,6 +138,33 @@ impl MachBackend for AArch64Backend {
         &self.reg_universe
     }
 
+    fn zero_extends_32bit_ops(&self) -> bool {
+        true
+    }
+
+    fn inst_cost(&self, opcode: Opcode, ctrl_typevar: Type) -> InstCost {
+        // Numbers for recent Cortex-A and Neoverse cores.  Integer divisions are preceded by a
+        // check of the divisor, and remainders take a multiply-subtract after the division.
+        let wide = ctrl_typevar.lane_bits() > 32;
+        match opcode {
+            Opcode::Udiv | Opcode::Sdiv | Opcode::UdivImm | Opcode::SdivImm if wide => {
+                InstCost::new(20, 14, 8)
+            }
+            Opcode::Urem | Opcode::Srem | Opcode::UremImm | Opcode::SremImm if wide => {
+                InstCost::new(23, 14, 12)
+            }
+            Opcode::Udiv | Opcode::Sdiv | Opcode::UdivImm | Opcode::SdivImm => {
+                InstCost::new(12, 7, 8)
+            }
+            Opcode::Urem | Opcode::Srem | Opcode::UremImm | Opcode::SremImm => {
+                InstCost::new(15, 7, 12)
+            }
+            Opcode::Fdiv if wide => InstCost::new(15, 10, 4),
+            Opcode::Fdiv => InstCost::new(10, 7, 4),
+            _ => generic_inst_cost(opcode, ctrl_typevar),
+        }
+    }
+
     fn unsigned_add_overflow_condition(&self) -> IntCC {
         // Unsigned `>=`; this corresponds to the carry flag set on aarch64, which happens on
         // overflow of an add.
@@ -143,6 +191,7 @@ impl MachBackend for AArch64Backend {
                 Some(UnwindInfo::SystemV(
                     crate::isa::unwind::systemv::create_unwind_info_from_insts(
                         &result.buffer.unwind_info[..],
+                        result.buffer.exception_handlers(),
                         result.buffer.data.len(),
                         &mapper,
                     )?,
@@ -181,7 +230,7 @@ mod test {
     use super::*;
     use crate::{
         cursor::{Cursor, FuncCursor},
-        ir::{types::*, AbiParam, ExternalName, Function, InstBuilder, Signature},
+        ir::{types::*, AbiParam, ExtFuncData, ExternalName, Function, InstBuilder, Signature},
         isa::CallConv,
         settings,
         settings::Configurable,
@@ -302,4 +351,55 @@ mod test {
 
         assert_eq!(code, &golden[..]);
     }
+
+    #[test]
+    fn test_invoke_lowering() {
+        let name = ExternalName::testcase("test0");
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(I32));
+        sig.returns.push(AbiParam::new(I32));
+        let mut func = Function::with_name_signature(name, sig.clone());
+        let callee_sig = func.import_signature(sig);
+        let callee = func.import_function(ExtFuncData {
+            name: ExternalName::testcase("callee"),
+            signature: callee_sig,
+            colocated: false,
+        });
+
+        let bb0 = func.dfg.make_block();
+        let arg0 = func.dfg.append_block_param(bb0, I32);
+        let bb1 = func.dfg.make_block();
+        let bb2 = func.dfg.make_block();
+
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(bb0);
+        let invoke = pos.ins().invoke(callee, bb2, &[arg0]);
+        let v0 = pos.func.dfg.first_result(invoke);
+        pos.ins().jump(bb1, &[]);
+        pos.insert_block(bb1);
+        pos.ins().return_(&[v0]);
+        pos.insert_block(bb2);
+        pos.ins().landing_pad(I64);
+        let v1 = pos.ins().iconst(I32, -1);
+        pos.ins().return_(&[v1]);
+
+        let mut shared_flags_builder = settings::builder();
+        shared_flags_builder.set("opt_level", "none").unwrap();
+        let shared_flags = settings::Flags::new(shared_flags_builder);
+        let isa_flags = aarch64_settings::Flags::new(&shared_flags, aarch64_settings::builder());
+        let backend = AArch64Backend::new_with_flags(
+            Triple::from_str("aarch64").unwrap(),
+            shared_flags,
+            isa_flags,
+        );
+        let result = backend
+            .compile_function(&mut func, /* want_disasm = */ false)
+            .unwrap();
+
+        // The call unwinds to the landing pad, which is laid out after it.
+        let handlers = result.buffer.exception_handlers();
+        assert_eq!(handlers.len(), 1);
+        assert!(handlers[0].ret_addr < handlers[0].landing_pad);
+        assert!((handlers[0].landing_pad as usize) < result.buffer.data.len());
+    }
 }
@@ -1,7 +1,12 @@
 //! Implementation of the 32-bit ARM ABI.
 
 use crate::{
-    ir, ir::types::*, isa, isa::arm32::inst::*, machinst::*, settings, CodegenError, CodegenResult,
+    ir,
+    ir::types::*,
+    isa,
+    isa::arm32::{inst::*, lower::lower_condcode},
+    machinst::*,
+    settings, CodegenError, CodegenResult,
 };
 use alloc::{boxed::Box, vec::Vec};
 use regalloc::{RealReg, Reg, RegClass, Set, Writable};
@@ -220,6 +225,44 @@ impl ABIMachineSpec for Arm32MachineDeps {
         insts
     }
 
+    fn gen_cond_br_imm(
+        reg: Reg,
+        imm: u64,
+        cond: ir::condcodes::IntCC,
+        taken: MachLabel,
+        not_taken: MachLabel,
+        tmp: Writable<Reg>,
+    ) -> SmallInstVec<Inst> {
+        let mut insts = SmallVec::new();
+        // Words are 32 bits wide.
+        let imm = imm as u32;
+        if imm <= u8::MAX as u32 {
+            insts.push(Inst::CmpImm8 {
+                rn: reg,
+                imm8: imm as u8,
+            });
+        } else {
+            insts.extend(Inst::load_constant(tmp, imm));
+            insts.push(Inst::Cmp {
+                rn: reg,
+                rm: tmp.to_reg(),
+            });
+        }
+        insts.push(Inst::CondBr {
+            taken: BranchTarget::Label(taken),
+            not_taken: BranchTarget::Label(not_taken),
+            cond: lower_condcode(cond),
+        });
+        insts
+    }
+
+    fn gen_tail_jump(target: Reg) -> Inst {
+        Inst::IndirectBr {
+            rm: target,
+            targets: vec![],
+        }
+    }
+
     fn gen_get_stack_addr(mem: StackAMode, into_reg: Writable<Reg>, _ty: Type) -> Inst {
         let mem = mem.into();
         Inst::LoadAddr { rd: into_reg, mem }
@@ -307,6 +350,15 @@ impl ABIMachineSpec for Arm32MachineDeps {
         smallvec![]
     }
 
+    fn gen_inline_probestack(_: u32, _: u32) -> SmallInstVec<Self::I> {
+        unreachable!("inline stack probes are rejected on ARM32")
+    }
+
+    fn supports_inline_probestack() -> bool {
+        // TODO: implement along with `gen_probestack` above.
+        false
+    }
+
     /// Returns stack bytes used as well as instructions. Does not adjust
     /// nominal SP offset; caller will do that.
     fn gen_clobber_save(
@@ -363,6 +415,14 @@ impl ABIMachineSpec for Arm32MachineDeps {
         insts
     }
 
+    fn gen_tail_call(
+        _dest: &CallDest,
+        _uses: Vec<Reg>,
+        _callee_conv: isa::CallConv,
+    ) -> SmallVec<[Self::I; 2]> {
+        unreachable!("tail calls are rejected when lowering for ARM32");
+    }
+
     fn gen_call(
         dest: &CallDest,
         uses: Vec<Reg>,
@@ -457,6 +517,10 @@ impl ABIMachineSpec for Arm32MachineDeps {
         caller_saved
     }
 
+    fn get_exception_payload_regs() -> [Reg; 2] {
+        [rreg(0), rreg(1)]
+    }
+
     fn get_ext_mode(
         _call_conv: isa::CallConv,
         specified: ir::ArgumentExtension,
@@ -795,6 +795,13 @@ impl MachInst for Inst {
         }
     }
 
+    fn is_call(&self) -> bool {
+        match self {
+            &Inst::Call { .. } | &Inst::CallInd { .. } => true,
+            _ => false,
+        }
+    }
+
     fn is_term<'a>(&'a self) -> MachTerminator<'a> {
         match self {
             &Inst::Ret | &Inst::EpiloguePlaceholder => MachTerminator::Ret,
@@ -1310,20 +1317,36 @@ impl MachInstLabelUse for LabelUse {
         }
     }
 
+    /// Is a veneer supported for this label reference type?
     fn supports_veneer(self) -> bool {
-        false
+        match self {
+            LabelUse::Branch20 => true, // veneer is a Branch24
+            _ => false,
+        }
     }
 
+    /// How large is the veneer, if supported?
     fn veneer_size(self) -> CodeOffset {
-        0
+        4
     }
 
+    /// Generate a veneer into the buffer, given that this veneer is at `veneer_offset`, and return
+    /// an offset and label-use for the veneer's use of the original label.
     fn generate_veneer(
         self,
-        _buffer: &mut [u8],
-        _veneer_offset: CodeOffset,
+        buffer: &mut [u8],
+        veneer_offset: CodeOffset,
     ) -> (CodeOffset, LabelUse) {
-        panic!("Veneer not supported yet.")
+        match self {
+            LabelUse::Branch20 => {
+                // The veneer is an unconditional `b.w` to the next instruction, which is patched
+                // as a Branch24 once the label is known.
+                buffer[0..2].clone_from_slice(&u16::to_le_bytes(0xf000));
+                buffer[2..4].clone_from_slice(&u16::to_le_bytes(0xb800));
+                (veneer_offset, LabelUse::Branch24)
+            }
+            _ => panic!("Unsupported label-reference type for veneer generation!"),
+        }
     }
 }
 
@@ -1366,4 +1389,24 @@ mod tests {
         assert_eq!(u16::from_le_bytes([buffer[0], buffer[1]]), 0xf400);
         assert_eq!(u16::from_le_bytes([buffer[2], buffer[3]]), 0x9000);
     }
+
+    #[test]
+    fn branch20_veneer() {
+        let label_use = LabelUse::Branch20;
+        assert!(label_use.supports_veneer());
+
+        let mut buffer = [0; 4];
+        let (veneer_use_offset, veneer_use) = label_use.generate_veneer(&mut buffer, 0x100);
+        assert_eq!(veneer_use_offset, 0x100);
+        assert_eq!(veneer_use, LabelUse::Branch24);
+        // An unconditional `b.w` to the next instruction.
+        assert_eq!(u16::from_le_bytes([buffer[0], buffer[1]]), 0xf000);
+        assert_eq!(u16::from_le_bytes([buffer[2], buffer[3]]), 0xb800);
+
+        // The veneer reaches targets out of the range of the conditional branch.
+        let label_offset = 0x100 + label_use.max_pos_range() + 2;
+        veneer_use.patch(&mut buffer, 0x100, label_offset);
+        assert_eq!(u16::from_le_bytes([buffer[0], buffer[1]]), 0xf100);
+        assert_eq!(u16::from_le_bytes([buffer[2], buffer[3]]), 0xb800);
+    }
 }
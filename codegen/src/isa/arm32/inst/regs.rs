@@ -1,6 +1,6 @@
 //! 32-bit ARM ISA definitions: registers.
 
-use regalloc::{RealRegUniverse, Reg, RegClass, RegClassInfo, Writable, NUM_REG_CLASSES};
+use regalloc::{RealRegUniverse, Reg, RegClass, RegClassInfo, Writable};
 
 use alloc::string::ToString;
 
@@ -83,7 +83,7 @@ pub fn writable_tmp2_reg() -> Writable<Reg> {
 /// Use only GPR for now.
 pub fn create_reg_universe() -> RealRegUniverse {
     let mut regs = vec![];
-    let mut allocable_by_class = [None; NUM_REG_CLASSES];
+    let mut allocable_by_class = vec![None; RegClass::I32.rc_to_usize() + 1];
 
     let r_reg_base = 0u8;
     let r_reg_count = 10; // to exclude r10, fp, ip, sp, lr  and pc.
@@ -103,6 +103,7 @@ pub fn create_reg_universe() -> RealRegUniverse {
         first: r_reg_base as usize,
         last: r_reg_last as usize,
         suggested_scratch: None,
+        spill_class: None,
     });
 
     // Other regs, not available to the allocator.
@@ -124,5 +125,6 @@ pub fn create_reg_universe() -> RealRegUniverse {
         regs,
         allocable,
         allocable_by_class,
+        aliases: vec![],
     }
 }
@@ -4,7 +4,7 @@ use crate::{
     ir::{types::*, Inst as IRInst, Opcode},
     machinst::{lower::*, *},
     settings::Flags,
-    CodegenResult,
+    CodegenError, CodegenResult,
 };
 
 use crate::isa::arm32::{abi::*, inst::*};
@@ -285,6 +285,34 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                 _ => panic!("Unexpected output type {}", ty),
             }
         }
+        Opcode::Bswap => {
+            let rd = output_to_reg(ctx, outputs[0]);
+            let rm = input_to_reg(ctx, inputs[0], NarrowValueMode::None);
+            let ty = ctx.output_ty(insn, 0);
+            let bit_op = BitOp::Rev;
+
+            match ty.bits() {
+                32 => ctx.emit(Inst::BitOpRR { bit_op, rd, rm }),
+                16 => {
+                    let shift = ShiftOpAndAmt::new(
+                        ShiftOp::LSL,
+                        ShiftOpShiftImm::maybe_from_shift(16).unwrap(),
+                    );
+                    ctx.emit(Inst::AluRRShift {
+                        alu_op: ALUOp1::Mov,
+                        rd,
+                        rm,
+                        shift: Some(shift),
+                    });
+                    ctx.emit(Inst::BitOpRR {
+                        bit_op,
+                        rd,
+                        rm: rd.to_reg(),
+                    });
+                }
+                _ => panic!("Unexpected output type {}", ty),
+            }
+        }
         Opcode::Icmp | Opcode::Ifcmp => {
             let condcode = inst_condcode(ctx.data(insn)).unwrap();
             let cond = lower_condcode(condcode);
@@ -333,8 +361,8 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                 insts: it_insts,
             });
         }
-        Opcode::Select | Opcode::Selectif => {
-            let cond = if op == Opcode::Select {
+        Opcode::Select | Opcode::SelectSpectreGuard | Opcode::Selectif => {
+            let cond = if op != Opcode::Selectif {
                 let rn = input_to_reg(ctx, inputs[0], NarrowValueMode::ZeroExtend);
                 ctx.emit(Inst::CmpImm8 { rn, imm8: 0 });
                 Cond::Ne
@@ -527,6 +555,12 @@ pub(crate) fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                 abi.emit_copy_retval_to_regs(ctx, i, ValueRegs::one(retval_reg));
             }
         }
+        Opcode::ReturnCall | Opcode::ReturnCallIndirect => {
+            return Err(CodegenError::Unsupported(format!(
+                "{}: tail calls on the arm32 backend",
+                op
+            )));
+        }
         _ => panic!("lowering {} unimplemented!", op),
     }
 
@@ -3,7 +3,9 @@
 use crate::{
     ir::{condcodes::IntCC, Function},
     isa::Builder as IsaBuilder,
-    machinst::{compile, MachBackend, MachCompileResult, TargetIsaAdapter, VCode},
+    machinst::{
+        compile, stub, MachBackend, MachCompileResult, StubAssembler, TargetIsaAdapter, VCode,
+    },
     result::CodegenResult,
     settings,
 };
@@ -62,6 +64,7 @@ impl MachBackend for Arm32Backend {
         let vcode = self.compile_vcode(func, flags.clone())?;
         let (buffer, bb_starts, bb_edges) = vcode.emit();
         let frame_size = vcode.frame_size();
+        let stats = vcode.stats();
         let stackslot_offsets = vcode.stackslot_offsets().clone();
 
         let disasm = if want_disasm {
@@ -75,6 +78,7 @@ impl MachBackend for Arm32Backend {
         Ok(MachCompileResult {
             buffer,
             frame_size,
+            stats,
             disasm,
             value_labels_ranges: Default::default(),
             stackslot_offsets,
@@ -83,6 +87,21 @@ impl MachBackend for Arm32Backend {
         })
     }
 
+    fn compile_stub(
+        &self,
+        stub: &StubAssembler,
+        want_disasm: bool,
+    ) -> CodegenResult<MachCompileResult> {
+        let emit_info = EmitInfo::new(self.flags.clone());
+        stub::compile_stub::<abi::Arm32MachineDeps>(
+            stub,
+            &self.flags,
+            &emit_info,
+            &self.reg_universe,
+            want_disasm,
+        )
+    }
+
     fn name(&self) -> &'static str {
         "arm32"
     }
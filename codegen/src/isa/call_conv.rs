@@ -104,6 +104,15 @@ impl CallConv {
             _ => false,
         }
     }
+
+    /// Can functions using this calling convention make tail calls to each other?
+    ///
+    /// The Baldrdash conventions are excluded because their epilogues are generated by the
+    /// embedder, and the probestack convention because it is only used for a single runtime
+    /// function.
+    pub fn supports_tail_calls(self) -> bool {
+        !self.extends_baldrdash() && self != Self::Probestack
+    }
 }
 
 impl fmt::Display for CallConv {
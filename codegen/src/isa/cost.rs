@@ -0,0 +1,131 @@
+//! Cost model of instructions, for the heuristics of the optimization passes.
+//!
+//! Each ISA describes how expensive the lowering of an instruction is through
+//! `TargetIsa::inst_cost`, so that passes choosing between equivalent sequences of instructions
+//! can ask the target instead of guessing. The numbers are rough: they describe a typical
+//! implementation of the ISA, and the most common lowering of the instruction.
+
+use crate::ir::{Opcode, Type};
+
+/// The cost of the lowering of an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstCost {
+    /// The number of cycles until the result of the instruction is available.
+    pub latency: u8,
+    /// The number of cycles between the starts of independent instances of the instruction, i.e.
+    /// the reciprocal throughput.
+    pub throughput: u8,
+    /// The size of the machine code of the lowering, in bytes.
+    pub size: u8,
+}
+
+impl InstCost {
+    /// A cost with the given latency, reciprocal throughput and size.
+    pub const fn new(latency: u8, throughput: u8, size: u8) -> Self {
+        Self {
+            latency,
+            throughput,
+            size,
+        }
+    }
+}
+
+/// The cost of an instruction with opcode `opcode` and controlling type `ty`, on an unspecified
+/// 64-bit ISA with fixed-size 4-byte instructions.
+///
+/// ISAs override the entries that are significantly off for them, and use these otherwise.
+pub fn generic_inst_cost(opcode: Opcode, ty: Type) -> InstCost {
+    let wide = ty.lane_bits() > 32;
+    match opcode {
+        // Integer division is iterative, and takes longer for wider operands.
+        Opcode::Udiv
+        | Opcode::Sdiv
+        | Opcode::Urem
+        | Opcode::Srem
+        | Opcode::UdivImm
+        | Opcode::SdivImm
+        | Opcode::UremImm
+        | Opcode::SremImm => {
+            if wide {
+                InstCost::new(40, 20, 4)
+            } else {
+                InstCost::new(20, 10, 4)
+            }
+        }
+
+        Opcode::Imul | Opcode::ImulImm | Opcode::Umulhi | Opcode::Smulhi => InstCost::new(3, 1, 4),
+
+        Opcode::Fadd | Opcode::Fsub | Opcode::Fmul | Opcode::Fma => InstCost::new(4, 1, 4),
+        Opcode::Fdiv | Opcode::Sqrt => {
+            if wide {
+                InstCost::new(20, 8, 4)
+            } else {
+                InstCost::new(12, 4, 4)
+            }
+        }
+        Opcode::FcvtToUint
+        | Opcode::FcvtToSint
+        | Opcode::FcvtToUintSat
+        | Opcode::FcvtToSintSat
+        | Opcode::FcvtFromUint
+        | Opcode::FcvtFromSint => InstCost::new(5, 1, 4),
+
+        Opcode::Load
+        | Opcode::LoadComplex
+        | Opcode::Uload8
+        | Opcode::Sload8
+        | Opcode::Uload16
+        | Opcode::Sload16
+        | Opcode::Uload32
+        | Opcode::Sload32
+        | Opcode::AtomicLoad => InstCost::new(4, 1, 4),
+
+        Opcode::Call | Opcode::CallIndirect => InstCost::new(5, 2, 4),
+
+        // Everything else is assumed to be a single simple ALU operation, move or branch.
+        _ => InstCost::new(1, 1, 4),
+    }
+}
+
+#[cfg(all(test, any(feature = "x86", feature = "arm64")))]
+mod tests {
+    use super::*;
+    use crate::ir::types::{F64, I32, I64};
+    use crate::isa;
+    use crate::settings;
+    use core::str::FromStr;
+    use target_lexicon::triple;
+
+    #[cfg(feature = "x86")]
+    #[test]
+    fn x64_costs() {
+        let isa = isa::lookup_variant(triple!("x86_64"), isa::BackendVariant::MachInst)
+            .unwrap()
+            .finish(settings::Flags::new(settings::builder()));
+
+        assert_eq!(isa.inst_cost(Opcode::Udiv, I64), InstCost::new(40, 25, 12));
+        assert_eq!(isa.inst_cost(Opcode::Sdiv, I64), InstCost::new(45, 25, 24));
+        assert_eq!(isa.inst_cost(Opcode::Udiv, I32), InstCost::new(26, 6, 10));
+        assert_eq!(isa.inst_cost(Opcode::Fdiv, F64), InstCost::new(14, 4, 4));
+        // Other instructions keep the generic latencies, with shorter encodings.
+        assert_eq!(isa.inst_cost(Opcode::Iadd, I64), InstCost::new(1, 1, 3));
+        assert_eq!(isa.inst_cost(Opcode::Imul, I64), InstCost::new(3, 1, 3));
+    }
+
+    #[cfg(feature = "arm64")]
+    #[test]
+    fn aarch64_costs() {
+        let isa = isa::lookup(triple!("aarch64"))
+            .unwrap()
+            .finish(settings::Flags::new(settings::builder()));
+
+        assert_eq!(isa.inst_cost(Opcode::Udiv, I64), InstCost::new(20, 14, 8));
+        assert_eq!(isa.inst_cost(Opcode::Urem, I64), InstCost::new(23, 14, 12));
+        assert_eq!(isa.inst_cost(Opcode::Sdiv, I32), InstCost::new(12, 7, 8));
+        assert_eq!(isa.inst_cost(Opcode::Fdiv, F64), InstCost::new(15, 10, 4));
+        assert_eq!(
+            isa.inst_cost(Opcode::Iadd, I64),
+            generic_inst_cost(Opcode::Iadd, I64)
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Cross-checking of emitted encodings against a reference assembler.
+//!
+//! The emitter tests for each backend compare against hand-written expected encodings, which
+//! only cover the operand combinations somebody thought to write down. The tests in this module
+//! instead generate random operand combinations, emit them, and check the bytes against what a
+//! reference assembler (`llvm-mc` by default, or whatever `CRANELIFT_REFERENCE_ASSEMBLER` names)
+//! produces for the instruction's printed form.
+//!
+//! An encoding that differs from the assembler's is still accepted if both disassemble to the
+//! same instruction: x64 in particular has several valid encodings for many instructions.
+
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::string::{String, ToString};
+use std::vec::Vec;
+use std::{format, vec};
+
+/// A small, deterministic pseudo-random number generator (xorshift64*), so that failures are
+/// reproducible from the seed.
+pub struct Rng(u64);
+
+impl Rng {
+    /// Create a generator from a non-zero seed.
+    pub fn new(seed: u64) -> Self {
+        assert_ne!(seed, 0);
+        Self(seed)
+    }
+
+    /// Return the next random number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Return a random number in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Return a random element of `items`.
+    pub fn choose<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len() as u64) as usize]
+    }
+
+    /// Return a random boolean.
+    pub fn coin(&mut self) -> bool {
+        self.next_u64() & 1 != 0
+    }
+}
+
+/// An instruction to cross-check: its assembly text, and the encoding produced by the emitter as
+/// an uppercase hex string (as from `TestCodeSink::stringify`).
+pub struct Case {
+    pub asm: String,
+    pub encoding: String,
+}
+
+fn assembler() -> String {
+    env::var("CRANELIFT_REFERENCE_ASSEMBLER").unwrap_or_else(|_| "llvm-mc".to_string())
+}
+
+/// Run the reference assembler with `args`, feeding it `input`. Returns its stdout, or its
+/// stderr if it failed.
+fn run_assembler(args: &[String], input: &str) -> Result<String, String> {
+    let mut child = Command::new(assembler())
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| {
+            panic!(
+                "couldn't run the reference assembler `{}`: {}",
+                assembler(),
+                e
+            )
+        });
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}
+
+/// Parse the `encoding: [0x.., ..]` annotations printed by `llvm-mc -show-encoding` into
+/// uppercase hex strings.
+fn parse_encodings(output: &str) -> Vec<String> {
+    let mut encodings = vec![];
+    for line in output.lines() {
+        let start = match line.find("encoding: [") {
+            Some(start) => start + "encoding: [".len(),
+            None => continue,
+        };
+        let end = start + line[start..].find(']').expect("unterminated encoding");
+        let mut encoding = String::new();
+        for byte in line[start..end].split(',') {
+            encoding.push_str(&byte.trim().trim_start_matches("0x").to_uppercase());
+        }
+        encodings.push(encoding);
+    }
+    encodings
+}
+
+/// Disassemble an uppercase hex encoding into normalized instruction text.
+fn disassemble(triple: &str, encoding: &str) -> Result<String, String> {
+    let bytes: Vec<String> = (0..encoding.len())
+        .step_by(2)
+        .map(|i| format!("0x{}", &encoding[i..i + 2]))
+        .collect();
+    let output = run_assembler(
+        &[format!("-triple={}", triple), "--disassemble".to_string()],
+        &bytes.join(" "),
+    )?;
+    Ok(output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('.'))
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join("; "))
+}
+
+/// Assemble each case on its own, for when batch assembly didn't line up.
+fn assemble_each(triple: &str, cases: &[Case]) -> Vec<Result<String, String>> {
+    cases
+        .iter()
+        .map(|case| {
+            let output = run_assembler(
+                &[format!("-triple={}", triple), "-show-encoding".to_string()],
+                &case.asm,
+            )?;
+            let mut encodings = parse_encodings(&output);
+            if encodings.len() == 1 {
+                Ok(encodings.pop().unwrap())
+            } else {
+                Err(format!("expected one encoding, got: {}", output))
+            }
+        })
+        .collect()
+}
+
+/// Check the encoding of each of `cases` against the reference assembler for `triple`, and panic
+/// with a list of all mismatches if there are any.
+pub fn crosscheck(triple: &str, cases: &[Case]) {
+    let input: String = cases.iter().map(|case| format!("{}\n", case.asm)).collect();
+    let batch = run_assembler(
+        &[format!("-triple={}", triple), "-show-encoding".to_string()],
+        &input,
+    )
+    .map(|output| parse_encodings(&output));
+    let expected: Vec<Result<String, String>> = match batch {
+        Ok(encodings) if encodings.len() == cases.len() => encodings.into_iter().map(Ok).collect(),
+        _ => assemble_each(triple, cases),
+    };
+
+    let mut failures = vec![];
+    for (case, expected) in cases.iter().zip(expected) {
+        match expected {
+            Err(err) => failures.push(format!("{}: assembler error: {}", case.asm, err.trim())),
+            Ok(expected) if expected == case.encoding => {}
+            Ok(expected) => {
+                let ours = disassemble(triple, &case.encoding);
+                let theirs = disassemble(triple, &expected);
+                match (ours, theirs) {
+                    (Ok(ours), Ok(theirs)) if ours == theirs => {}
+                    (ours, _) => failures.push(format!(
+                        "{}: emitted {} (disassembles to {:?}), assembler produced {}",
+                        case.asm, case.encoding, ours, expected
+                    )),
+                }
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} encodings don't match the reference assembler:\n{}",
+        failures.len(),
+        cases.len(),
+        failures.join("\n")
+    );
+}
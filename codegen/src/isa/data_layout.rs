@@ -0,0 +1,153 @@
+//! The layout of data in memory on a target.
+//!
+//! Frontends and the data emitted next to the code (jump tables, constants) need to know how the
+//! target lays out its data, which need not match the host's when cross-compiling. `DataLayout`
+//! answers those questions from the target triple, so nothing has to assume the host's pointer
+//! width or byte order.
+
+use crate::ir::{self, Type};
+use target_lexicon::{PointerWidth, Triple};
+
+/// The pointer width, byte order and natural alignments of the data of a target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DataLayout {
+    pointer_width: PointerWidth,
+    endianness: ir::Endianness,
+}
+
+impl DataLayout {
+    /// Create a data layout with the given pointer width and byte order.
+    pub fn new(pointer_width: PointerWidth, endianness: ir::Endianness) -> Self {
+        Self {
+            pointer_width,
+            endianness,
+        }
+    }
+
+    /// Get the data layout of the target described by `triple`.
+    ///
+    /// Panics if the architecture of the triple has no known pointer width or byte order.
+    pub fn from_triple(triple: &Triple) -> Self {
+        let endianness = match triple.endianness().unwrap() {
+            target_lexicon::Endianness::Little => ir::Endianness::Little,
+            target_lexicon::Endianness::Big => ir::Endianness::Big,
+        };
+        Self::new(triple.pointer_width().unwrap(), endianness)
+    }
+
+    /// Get the width of pointers.
+    pub fn pointer_width(self) -> PointerWidth {
+        self.pointer_width
+    }
+
+    /// Get the integer type of pointers.
+    pub fn pointer_type(self) -> Type {
+        Type::int(u16::from(self.pointer_bits())).unwrap()
+    }
+
+    /// Get the width of pointers, in units of bits.
+    pub fn pointer_bits(self) -> u8 {
+        self.pointer_width.bits()
+    }
+
+    /// Get the width of pointers, in units of bytes.
+    pub fn pointer_bytes(self) -> u8 {
+        self.pointer_width.bytes()
+    }
+
+    /// Get the byte order of multi-byte values in memory.
+    pub fn endianness(self) -> ir::Endianness {
+        self.endianness
+    }
+
+    /// Get the natural alignment of values of type `ty`, in bytes: their size, but at most 16
+    /// bytes for the wide vector types.
+    pub fn natural_alignment(self, ty: Type) -> u8 {
+        assert!(ty.bytes() > 0, "{} has no size", ty);
+        ty.bytes().min(16) as u8
+    }
+
+    /// Write the low `bytes.len()` bytes of `value` into `bytes`, in the byte order of the
+    /// target.
+    pub fn write_uint(self, value: u64, bytes: &mut [u8]) {
+        assert!(bytes.len() <= 8, "{} bytes don't fit in a u64", bytes.len());
+        let len = bytes.len();
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let shift = match self.endianness {
+                ir::Endianness::Little => i,
+                ir::Endianness::Big => len - 1 - i,
+            };
+            *byte = (value >> (8 * shift)) as u8;
+        }
+    }
+
+    /// Read an unsigned integer of `bytes.len()` bytes from `bytes`, in the byte order of the
+    /// target.
+    pub fn read_uint(self, bytes: &[u8]) -> u64 {
+        assert!(bytes.len() <= 8, "{} bytes don't fit in a u64", bytes.len());
+        let len = bytes.len();
+        bytes.iter().enumerate().fold(0, |value, (i, &byte)| {
+            let shift = match self.endianness {
+                ir::Endianness::Little => i,
+                ir::Endianness::Big => len - 1 - i,
+            };
+            value | u64::from(byte) << (8 * shift)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::types;
+    use core::str::FromStr;
+
+    fn layout(name: &str) -> DataLayout {
+        DataLayout::from_triple(&Triple::from_str(name).unwrap())
+    }
+
+    #[test]
+    fn from_triple() {
+        let x86_64 = layout("x86_64-unknown-linux-gnu");
+        assert_eq!(x86_64.pointer_type(), types::I64);
+        assert_eq!(x86_64.endianness(), ir::Endianness::Little);
+
+        let i686 = layout("i686-unknown-linux-gnu");
+        assert_eq!(i686.pointer_type(), types::I32);
+        assert_eq!(i686.pointer_bytes(), 4);
+
+        let s390x = layout("s390x-unknown-linux-gnu");
+        assert_eq!(s390x.pointer_bits(), 64);
+        assert_eq!(s390x.endianness(), ir::Endianness::Big);
+    }
+
+    #[test]
+    fn natural_alignment() {
+        let x86_64 = layout("x86_64-unknown-linux-gnu");
+        assert_eq!(x86_64.natural_alignment(types::B1), 1);
+        assert_eq!(x86_64.natural_alignment(types::I16), 2);
+        assert_eq!(x86_64.natural_alignment(types::F64), 8);
+        assert_eq!(x86_64.natural_alignment(types::I128), 16);
+        assert_eq!(x86_64.natural_alignment(types::I64X4), 16);
+    }
+
+    #[test]
+    fn byte_order() {
+        let mut bytes = [0; 4];
+        let little = layout("x86_64-unknown-linux-gnu");
+        little.write_uint(0x1122_3344, &mut bytes);
+        assert_eq!(bytes, [0x44, 0x33, 0x22, 0x11]);
+        assert_eq!(little.read_uint(&bytes), 0x1122_3344);
+
+        let big = layout("s390x-unknown-linux-gnu");
+        big.write_uint(0x1122_3344, &mut bytes);
+        assert_eq!(bytes, [0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(big.read_uint(&bytes), 0x1122_3344);
+
+        // Only the low bytes are written.
+        let mut short = [0; 2];
+        big.write_uint(0xffff_fffe, &mut short);
+        assert_eq!(short, [0xff, 0xfe]);
+        assert_eq!(big.read_uint(&short), 0xfffe);
+    }
+}
@@ -46,10 +46,13 @@
 pub use crate::isa::{
     call_conv::CallConv,
     constraints::{BranchRange, ConstraintKind, OperandConstraint, RecipeConstraints},
+    cost::{generic_inst_cost, InstCost},
+    data_layout::DataLayout,
     enc_tables::Encodings,
     encoding::{base_size, EncInfo, Encoding},
     registers::{regs_overlap, RegClass, RegClassIndex, RegInfo, RegUnit},
     stack::{StackBase, StackBaseMask, StackRef},
+    target_defaults::shared_flags_builder,
 };
 
 #[cfg(feature = "unwind")]
@@ -70,7 +73,7 @@ use core::{
     fmt::{Debug, Formatter},
     hash::Hasher,
 };
-use target_lexicon::{triple, Architecture, PointerWidth, Triple};
+use target_lexicon::{Architecture, PointerWidth, Triple};
 
 #[cfg(feature = "riscv")]
 mod riscv;
@@ -101,14 +104,20 @@ pub mod unwind;
 
 mod call_conv;
 mod constraints;
+mod cost;
+mod data_layout;
 mod enc_tables;
 mod encoding;
 pub mod registers;
 mod stack;
+mod target_defaults;
 
 #[cfg(test)]
 mod test_utils;
 
+#[cfg(all(test, feature = "asm-crosscheck"))]
+mod crosscheck;
+
 /// Returns a builder that can create a corresponding `TargetIsa`
 /// or `Err(LookupError::SupportDisabled)` if not enabled.
 macro_rules! isa_builder {
@@ -177,16 +186,24 @@ pub fn lookup(triple: Triple) -> Result<Builder, LookupError> {
     lookup_variant(triple, BackendVariant::Any)
 }
 
-/// Look for a supported ISA with the given `name`.
+/// Look for a supported ISA with the given `name`, a target triple such as
+/// `armv7-unknown-linux-gnueabihf`.
 /// Return a builder that can create a corresponding `TargetIsa`.
 pub fn lookup_by_name(name: &str) -> Result<Builder, LookupError> {
     use alloc::str::FromStr;
-    lookup(triple!(name))
+    let triple = Triple::from_str(name).map_err(|err| {
+        log::debug!("invalid target triple {:?}: {}", name, err);
+        LookupError::InvalidTriple
+    })?;
+    lookup(triple)
 }
 
 /// Describes reason for target lookup failure
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum LookupError {
+    /// The name of the target is not a valid target triple.
+    InvalidTriple,
+
     /// Support for this target was disabled in the current build.
     SupportDisabled,
 
@@ -197,6 +214,7 @@ pub enum LookupError {
 impl fmt::Display for LookupError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
+            LookupError::InvalidTriple => write!(f, "Invalid target triple"),
             LookupError::SupportDisabled => write!(f, "Support for this target is disabled"),
             LookupError::Unsupported => {
                 write!(f, "Support for this target has not been implemented yet")
@@ -220,11 +238,23 @@ impl Builder {
         &self.triple
     }
 
+    /// A builder for the shared settings, with the defaults of the operating system and object
+    /// format of the triple; see `shared_flags_builder`.
+    pub fn shared_flags_builder(&self) -> settings::Builder {
+        shared_flags_builder(&self.triple)
+    }
+
     /// Iterates the available settings in the builder.
     pub fn iter(&self) -> impl Iterator<Item = settings::Setting> {
         self.setup.iter()
     }
 
+    /// Check that `value` is a valid value for the ISA-specific setting `name`, without changing
+    /// the builder.
+    pub fn validate(&self, name: &str, value: &str) -> SetResult<()> {
+        self.setup.validate(name, value)
+    }
+
     /// Combine the ISA-specific settings with the provided ISA-independent settings and allocate a
     /// fully configured `TargetIsa` trait object.
     pub fn finish(self, shared_flags: settings::Flags) -> Box<dyn TargetIsa> {
@@ -258,6 +288,17 @@ pub struct TargetFrontendConfig {
 
     /// The pointer width of the target.
     pub pointer_width: PointerWidth,
+
+    /// The byte order of the target.
+    pub endianness: ir::Endianness,
+
+    /// The maximum number of loads and stores a constant-size memory operation may be
+    /// expanded into before falling back to a libcall.
+    pub max_inline_memory_ops: u8,
+
+    /// Whether expanded memory operations may use accesses wider than the alignment
+    /// declared for their pointers.
+    pub allow_unaligned_memory_ops: bool,
 }
 
 impl TargetFrontendConfig {
@@ -275,6 +316,11 @@ impl TargetFrontendConfig {
     pub fn pointer_bytes(self) -> u8 {
         self.pointer_width.bytes()
     }
+
+    /// Get the layout of data on this target.
+    pub fn data_layout(self) -> DataLayout {
+        DataLayout::new(self.pointer_width, self.endianness)
+    }
 }
 
 /// Methods that are specialized to a target ISA. Implies a Display trait that shows the
@@ -306,12 +352,14 @@ pub trait TargetIsa: fmt::Display + Send + Sync {
         CallConv::triple_default(self.triple())
     }
 
+    /// Get the layout of data on this ISA: pointer width, byte order and natural alignments.
+    fn data_layout(&self) -> DataLayout {
+        DataLayout::from_triple(self.triple())
+    }
+
     /// Get the endianness of this ISA.
     fn endianness(&self) -> ir::Endianness {
-        match self.triple().endianness().unwrap() {
-            target_lexicon::Endianness::Little => ir::Endianness::Little,
-            target_lexicon::Endianness::Big => ir::Endianness::Big,
-        }
+        self.data_layout().endianness()
     }
 
     /// Get the pointer type of this ISA.
@@ -339,9 +387,22 @@ pub trait TargetIsa: fmt::Display + Send + Sync {
         TargetFrontendConfig {
             default_call_conv: self.default_call_conv(),
             pointer_width: self.pointer_width(),
+            endianness: self.endianness(),
+            max_inline_memory_ops: self.flags().max_inline_memory_ops(),
+            allow_unaligned_memory_ops: self.flags().allow_unaligned_memory_ops(),
         }
     }
 
+    /// The `vscale` of this target, if it is known at compile time: the factor by which the
+    /// minimum number of lanes of a dynamic vector type is multiplied to give its actual number
+    /// of lanes.
+    ///
+    /// None of the targets have scalable vector registers yet, so by default dynamic vectors are
+    /// lowered as the SIMD vectors of their minimum size.
+    fn vscale(&self) -> Option<u16> {
+        Some(1)
+    }
+
     /// Does the CPU implement scalar comparisons using a CPU flags register?
     fn uses_cpu_flags(&self) -> bool {
         false
@@ -352,6 +413,19 @@ pub trait TargetIsa: fmt::Display + Send + Sync {
         false
     }
 
+    /// Do 32-bit integer operations clear the upper half of their 64-bit destination register?
+    /// On such targets, narrowing 64-bit arithmetic to 32 bits makes the extension back to 64
+    /// bits free; see `Context::narrow`.
+    fn zero_extends_32bit_ops(&self) -> bool {
+        false
+    }
+
+    /// Get the cost of an instruction with opcode `opcode` and controlling type `ctrl_typevar`,
+    /// for heuristics choosing between equivalent sequences of instructions.
+    fn inst_cost(&self, opcode: ir::Opcode, ctrl_typevar: ir::Type) -> InstCost {
+        generic_inst_cost(opcode, ctrl_typevar)
+    }
+
     /// Get a data structure describing the registers in this ISA.
     fn register_info(&self) -> RegInfo;
 
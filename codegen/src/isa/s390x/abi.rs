@@ -61,7 +61,10 @@ use crate::{
     ir,
     ir::{condcodes::IntCC, types, MemFlags, Type},
     isa,
-    isa::{s390x::inst::*, unwind::UnwindInst},
+    isa::{
+        s390x::{inst::*, lower::condcode_is_signed},
+        unwind::UnwindInst,
+    },
     machinst::*,
     settings, CodegenError, CodegenResult,
 };
@@ -391,6 +394,59 @@ impl ABIMachineSpec for S390xMachineDeps {
         Inst::EpiloguePlaceholder
     }
 
+    fn gen_cond_br_imm(
+        reg: Reg,
+        imm: u64,
+        cond: IntCC,
+        taken: MachLabel,
+        not_taken: MachLabel,
+        tmp: Writable<Reg>,
+    ) -> SmallInstVec<Inst> {
+        let mut insts = SmallVec::new();
+        if condcode_is_signed(cond) {
+            if let Ok(imm) = i32::try_from(imm as i64) {
+                insts.push(Inst::CmpRSImm32 {
+                    op: CmpOp::CmpS64,
+                    rn: reg,
+                    imm,
+                });
+            } else {
+                insts.extend(Inst::load_constant64(tmp, imm));
+                insts.push(Inst::CmpRR {
+                    op: CmpOp::CmpS64,
+                    rn: reg,
+                    rm: tmp.to_reg(),
+                });
+            }
+        } else if let Ok(imm) = u32::try_from(imm) {
+            insts.push(Inst::CmpRUImm32 {
+                op: CmpOp::CmpL64,
+                rn: reg,
+                imm,
+            });
+        } else {
+            insts.extend(Inst::load_constant64(tmp, imm));
+            insts.push(Inst::CmpRR {
+                op: CmpOp::CmpL64,
+                rn: reg,
+                rm: tmp.to_reg(),
+            });
+        }
+        insts.push(Inst::CondBr {
+            taken: BranchTarget::Label(taken),
+            not_taken: BranchTarget::Label(not_taken),
+            cond: Cond::from_intcc(cond),
+        });
+        insts
+    }
+
+    fn gen_tail_jump(target: Reg) -> Inst {
+        Inst::IndirectBr {
+            rn: target,
+            targets: vec![],
+        }
+    }
+
     fn gen_get_stack_addr(mem: StackAMode, into_reg: Writable<Reg>, _ty: Type) -> Inst {
         let mem = mem.into();
         Inst::LoadAddr { rd: into_reg, mem }
@@ -452,6 +508,15 @@ impl ABIMachineSpec for S390xMachineDeps {
         smallvec![]
     }
 
+    fn gen_inline_probestack(_: u32, _: u32) -> SmallInstVec<Self::I> {
+        unreachable!("inline stack probes are rejected on s390x")
+    }
+
+    fn supports_inline_probestack() -> bool {
+        // TODO: implement along with `gen_probestack` above.
+        false
+    }
+
     // Returns stack bytes used as well as instructions. Does not adjust
     // nominal SP offset; abi_impl generic code will do that.
     fn gen_clobber_save(
@@ -601,6 +666,14 @@ impl ABIMachineSpec for S390xMachineDeps {
         insts
     }
 
+    fn gen_tail_call(
+        _dest: &CallDest,
+        _uses: Vec<Reg>,
+        _callee_conv: isa::CallConv,
+    ) -> SmallVec<[Self::I; 2]> {
+        unreachable!("tail calls are rejected when lowering for S390X");
+    }
+
     fn gen_call(
         dest: &CallDest,
         uses: Vec<Reg>,
@@ -708,6 +781,10 @@ impl ABIMachineSpec for S390xMachineDeps {
         caller_saved
     }
 
+    fn get_exception_payload_regs() -> [Reg; 2] {
+        [gpr(6), gpr(7)]
+    }
+
     fn get_ext_mode(
         _call_conv: isa::CallConv,
         specified: ir::ArgumentExtension,
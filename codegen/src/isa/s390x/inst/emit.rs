@@ -1222,6 +1222,14 @@ impl MachInstEmit for Inst {
                         let opcode = 0xb9e1; // POPCNT
                         put(sink, &enc_rrf_cde(opcode, rd.to_reg(), rn, 8, 0));
                     }
+                    UnaryOp::BSwap32 => {
+                        let opcode = 0xb91f; // LRVR
+                        put(sink, &enc_rre(opcode, rd.to_reg(), rn));
+                    }
+                    UnaryOp::BSwap64 => {
+                        let opcode = 0xb90f; // LRVGR
+                        put(sink, &enc_rre(opcode, rd.to_reg(), rn));
+                    }
                 }
             }
 
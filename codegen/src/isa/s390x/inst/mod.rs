@@ -109,6 +109,8 @@ pub enum UnaryOp {
     Neg64Ext32,
     PopcntByte,
     PopcntReg,
+    BSwap32,
+    BSwap64,
 }
 
 impl UnaryOp {
@@ -2381,6 +2383,13 @@ impl MachInst for Inst {
         }
     }
 
+    fn is_call(&self) -> bool {
+        match self {
+            &Inst::Call { .. } | &Inst::CallInd { .. } => true,
+            _ => false,
+        }
+    }
+
     fn is_term<'a>(&'a self) -> MachTerminator<'a> {
         match self {
             &Inst::Ret { .. } | &Inst::EpiloguePlaceholder => MachTerminator::Ret,
@@ -2844,6 +2853,8 @@ impl Inst {
                     UnaryOp::Neg64Ext32 => ("lcgfr", ""),
                     UnaryOp::PopcntByte => ("popcnt", ""),
                     UnaryOp::PopcntReg => ("popcnt", ", 8"),
+                    UnaryOp::BSwap32 => ("lrvr", ""),
+                    UnaryOp::BSwap64 => ("lrvgr", ""),
                 };
                 let rd = rd.to_reg().show_rru(mb_rru);
                 let rn = rn.show_rru(mb_rru);
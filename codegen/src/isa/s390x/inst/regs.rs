@@ -1,7 +1,7 @@
 //! S390x ISA definitions: registers.
 
 use crate::settings;
-use regalloc::{RealRegUniverse, Reg, RegClass, RegClassInfo, Writable, NUM_REG_CLASSES};
+use regalloc::{RealRegUniverse, Reg, RegClass, RegClassInfo, Writable};
 
 //=============================================================================
 // Registers, the Universe thereof, and printing
@@ -90,7 +90,7 @@ pub fn zero_reg() -> Reg {
 /// Create the register universe for AArch64.
 pub fn create_reg_universe(_flags: &settings::Flags) -> RealRegUniverse {
     let mut regs = vec![];
-    let mut allocable_by_class = [None; NUM_REG_CLASSES];
+    let mut allocable_by_class = vec![None; RegClass::F64.rc_to_usize() + 1];
 
     // Numbering Scheme: we put FPRs first, then GPRs. The GPRs exclude several registers:
     // r0 (we cannot use this for addressing // FIXME regalloc)
@@ -120,6 +120,7 @@ pub fn create_reg_universe(_flags: &settings::Flags) -> RealRegUniverse {
         first: base,
         last: regs.len() - 1,
         suggested_scratch: Some(fpr(1).get_index()),
+        spill_class: None,
     });
 
     // Caller-saved GPRs in the SystemV s390x ABI.
@@ -146,6 +147,7 @@ pub fn create_reg_universe(_flags: &settings::Flags) -> RealRegUniverse {
         first: base,
         last: regs.len() - 1,
         suggested_scratch: Some(gpr(13).get_index()),
+        spill_class: None,
     });
 
     // Other regs, not available to the allocator.
@@ -164,5 +166,6 @@ pub fn create_reg_universe(_flags: &settings::Flags) -> RealRegUniverse {
         regs,
         allocable,
         allocable_by_class,
+        aliases: vec![],
     }
 }
@@ -3,12 +3,13 @@
 use crate::{
     ir::{
         condcodes::{FloatCC, IntCC},
-        types, Endianness, Inst as IRInst, InstructionData, MemFlags, Opcode, TrapCode, Type,
+        types, Endianness, Inst as IRInst, InstructionData, MemFlags, MemoryOrdering, Opcode,
+        TrapCode, Type,
     },
     isa::s390x::{abi::*, inst::*, settings as s390x_settings, S390xBackend},
     machinst::{lower::*, *},
-    settings::Flags,
-    CodegenResult,
+    settings::{Flags, JumpTableEntries},
+    CodegenError, CodegenResult,
 };
 use alloc::{boxed::Box, vec::Vec};
 use core::convert::TryFrom;
@@ -1808,16 +1809,15 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                 rm: rn,
             });
 
-            ctx.emit(Inst::Flogr { rn });
+            ctx.emit(Inst::Flogr { rn: tmp.to_reg() });
             ctx.emit(Inst::gen_move(rd, gpr(0), ty));
 
-            if ty_bits_size < 64 {
-                ctx.emit(Inst::AluRSImm16 {
-                    alu_op: ALUOp::Add32,
-                    rd,
-                    imm: -(64 - ty_bits_size as i16),
-                });
-            }
+            // The sign bit itself is not counted, nor are the bits added by the extension.
+            ctx.emit(Inst::AluRSImm16 {
+                alu_op: ALUOp::Add32,
+                rd,
+                imm: -(65 - ty_bits_size as i16),
+            });
         }
 
         Opcode::Ctz => {
@@ -1876,7 +1876,89 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             });
         }
 
-        Opcode::Bitrev => unimplemented!(),
+        Opcode::Bitrev => {
+            // Swap adjacent bits, then bit pairs, then nibbles, and finally reverse the bytes.
+            let ty = ty.unwrap();
+            let rd = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            let rn = put_input_in_reg(ctx, inputs[0], NarrowValueMode::None);
+            let tmp = ctx.alloc_tmp(types::I64).only_reg().unwrap();
+            let mask = ctx.alloc_tmp(types::I64).only_reg().unwrap();
+            ctx.emit(Inst::gen_move(rd, rn, types::I64));
+            for &(shift, bits) in &[
+                (1, 0x5555_5555_5555_5555),
+                (2, 0x3333_3333_3333_3333),
+                (4, 0x0f0f_0f0f_0f0f_0f0f),
+            ] {
+                // rd = ((rd >> shift) & mask) | ((rd & mask) << shift)
+                lower_constant_u64(ctx, mask, bits);
+                ctx.emit(Inst::ShiftRR {
+                    shift_op: ShiftOp::LShR64,
+                    rd: tmp,
+                    rn: rd.to_reg(),
+                    shift_imm: SImm20::maybe_from_i64(shift).unwrap(),
+                    shift_reg: None,
+                });
+                ctx.emit(Inst::AluRR {
+                    alu_op: ALUOp::And64,
+                    rd: tmp,
+                    rm: mask.to_reg(),
+                });
+                ctx.emit(Inst::AluRR {
+                    alu_op: ALUOp::And64,
+                    rd,
+                    rm: mask.to_reg(),
+                });
+                ctx.emit(Inst::ShiftRR {
+                    shift_op: ShiftOp::LShL64,
+                    rd,
+                    rn: rd.to_reg(),
+                    shift_imm: SImm20::maybe_from_i64(shift).unwrap(),
+                    shift_reg: None,
+                });
+                ctx.emit(Inst::AluRR {
+                    alu_op: ALUOp::Orr64,
+                    rd,
+                    rm: tmp.to_reg(),
+                });
+            }
+            ctx.emit(Inst::UnaryRR {
+                op: UnaryOp::BSwap64,
+                rd,
+                rn: rd.to_reg(),
+            });
+
+            // Narrower values end up reversed into the most significant bits.
+            let ty_bits_size = ty_bits(ty);
+            if ty_bits_size < 64 {
+                ctx.emit(Inst::ShiftRR {
+                    shift_op: ShiftOp::LShR64,
+                    rd,
+                    rn: rd.to_reg(),
+                    shift_imm: SImm20::maybe_from_i64(64 - ty_bits_size as i64).unwrap(),
+                    shift_reg: None,
+                });
+            }
+        }
+
+        Opcode::Bswap => {
+            let ty = ty.unwrap();
+            let rd = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            let rn = put_input_in_reg(ctx, inputs[0], NarrowValueMode::None);
+            ctx.emit(Inst::UnaryRR {
+                op: choose_32_64(ty, UnaryOp::BSwap32, UnaryOp::BSwap64),
+                rd,
+                rn,
+            });
+            if ty == types::I16 {
+                ctx.emit(Inst::ShiftRR {
+                    shift_op: ShiftOp::LShR32,
+                    rd,
+                    rn: rd.to_reg(),
+                    shift_imm: SImm20::maybe_from_i64(16).unwrap(),
+                    shift_reg: None,
+                });
+            }
+        }
 
         Opcode::Popcnt => {
             let ty = ty.unwrap();
@@ -2442,6 +2524,10 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             panic!("global_value should have been removed by legalization!");
         }
 
+        Opcode::AssertEq | Opcode::Assume => {
+            panic!("annotations should have been removed after legalization!");
+        }
+
         Opcode::TlsValue => {
             panic!("Thread-local storage support not implemented!");
         }
@@ -2450,6 +2536,29 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             panic!("Pinned register support not implemented!");
         }
 
+        Opcode::GetCycleCounter => {
+            panic!("Cycle counter support not implemented!");
+        }
+
+        Opcode::StackUsed => {
+            panic!("Stack usage support not implemented!");
+        }
+
+        Opcode::ReturnCall | Opcode::ReturnCallIndirect => {
+            return Err(CodegenError::Unsupported(format!(
+                "{}: tail calls on the s390x backend",
+                op
+            )));
+        }
+
+        Opcode::LandingPad => {
+            panic!("Exception handling support not implemented!");
+        }
+
+        Opcode::GetPkeyRights | Opcode::SetPkeyRights => {
+            panic!("Protection key support not implemented!");
+        }
+
         Opcode::Icmp => {
             let condcode = ctx.data(insn).cond_code().unwrap();
             let cond = Cond::from_intcc(condcode);
@@ -2492,7 +2601,7 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             lower_flags_to_bool_result(ctx, cond, rd, ty);
         }
 
-        Opcode::Select => {
+        Opcode::Select | Opcode::SelectSpectreGuard => {
             let ty = ctx.output_ty(insn, 0);
             let rd = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
             let rn = put_input_in_reg(ctx, inputs[1], NarrowValueMode::None);
@@ -2785,7 +2894,12 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             ctx.emit(Inst::Fence);
         }
         Opcode::Fence => {
-            ctx.emit(Inst::Fence);
+            // Only a store followed by a load may be reordered on z/Architecture, so only a
+            // sequentially consistent fence needs a serializing instruction.
+            match ctx.data(insn).memory_ordering().unwrap() {
+                MemoryOrdering::Acquire | MemoryOrdering::Release | MemoryOrdering::AcqRel => {}
+                MemoryOrdering::SeqCst => ctx.emit(Inst::Fence),
+            }
         }
 
         Opcode::RawBitcast
@@ -2870,7 +2984,9 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
         | Opcode::Brif
         | Opcode::Brff
         | Opcode::IndirectJumpTableBr
-        | Opcode::BrTable => {
+        | Opcode::BrTable
+        | Opcode::Invoke
+        | Opcode::InvokeIndirect => {
             panic!("Branch opcode reached non-branch lowering logic!");
         }
 
@@ -2964,6 +3080,7 @@ fn lower_branch<C: LowerCtx<I = Inst>>(
     ctx: &mut C,
     branches: &[IRInst],
     targets: &[MachLabel],
+    flags: &Flags,
 ) -> CodegenResult<()> {
     // A block should end with at most two branches. The first may be a
     // conditional branch; a conditional branch can be followed only by an
@@ -3021,6 +3138,10 @@ fn lower_branch<C: LowerCtx<I = Inst>>(
 
             Opcode::Brff => unreachable!(),
 
+            Opcode::Invoke | Opcode::InvokeIndirect => {
+                panic!("Exception handling support not implemented!");
+            }
+
             _ => unimplemented!(),
         }
     } else {
@@ -3038,6 +3159,12 @@ fn lower_branch<C: LowerCtx<I = Inst>>(
             }
 
             Opcode::BrTable => {
+                if flags.jump_table_entries() != JumpTableEntries::Relative {
+                    return Err(CodegenError::Unsupported(
+                        "absolute jump table entries are only supported on x64".into(),
+                    ));
+                }
+
                 let jt_size = targets.len() - 1;
                 assert!(jt_size <= std::u32::MAX as usize);
 
@@ -3115,6 +3242,6 @@ impl LowerBackend for S390xBackend {
         branches: &[IRInst],
         targets: &[MachLabel],
     ) -> CodegenResult<()> {
-        lower_branch(ctx, branches, targets)
+        lower_branch(ctx, branches, targets, &self.flags)
     }
 }
@@ -5,7 +5,9 @@ use crate::isa::unwind::systemv::RegisterMappingError;
 use crate::{
     ir::{condcodes::IntCC, Function},
     isa::{s390x::settings as s390x_settings, Builder as IsaBuilder},
-    machinst::{compile, MachBackend, MachCompileResult, TargetIsaAdapter, VCode},
+    machinst::{
+        compile, stub, MachBackend, MachCompileResult, StubAssembler, TargetIsaAdapter, VCode,
+    },
     result::CodegenResult,
     settings as shared_settings,
 };
@@ -73,6 +75,7 @@ impl MachBackend for S390xBackend {
         let vcode = self.compile_vcode(func, flags.clone())?;
         let (buffer, bb_starts, bb_edges) = vcode.emit();
         let frame_size = vcode.frame_size();
+        let stats = vcode.stats();
         let value_labels_ranges = vcode.value_labels_ranges();
         let stackslot_offsets = vcode.stackslot_offsets().clone();
 
@@ -87,6 +90,7 @@ impl MachBackend for S390xBackend {
         Ok(MachCompileResult {
             buffer,
             frame_size,
+            stats,
             disasm,
             value_labels_ranges,
             stackslot_offsets,
@@ -95,6 +99,21 @@ impl MachBackend for S390xBackend {
         })
     }
 
+    fn compile_stub(
+        &self,
+        stub: &StubAssembler,
+        want_disasm: bool,
+    ) -> CodegenResult<MachCompileResult> {
+        let emit_info = EmitInfo::new(self.flags.clone(), self.isa_flags.clone());
+        stub::compile_stub::<abi::S390xMachineDeps>(
+            stub,
+            &self.flags,
+            &emit_info,
+            &self.reg_universe,
+            want_disasm,
+        )
+    }
+
     fn name(&self) -> &'static str {
         "s390x"
     }
@@ -141,6 +160,7 @@ impl MachBackend for S390xBackend {
                 Some(UnwindInfo::SystemV(
                     crate::isa::unwind::systemv::create_unwind_info_from_insts(
                         &result.buffer.unwind_info[..],
+                        result.buffer.exception_handlers(),
                         result.buffer.data.len(),
                         &mapper,
                     )?,
@@ -184,6 +204,7 @@ mod test {
         isa::CallConv,
         settings,
         settings::Configurable,
+        CodegenError,
     };
     use core::str::FromStr;
     use target_lexicon::Triple;
@@ -296,4 +317,35 @@ mod test {
 
         assert_eq!(code, &golden[..]);
     }
+
+    #[test]
+    fn test_inline_probestack_unsupported() {
+        let name = ExternalName::testcase("test0");
+        let sig = Signature::new(CallConv::SystemV);
+        let mut func = Function::with_name_signature(name, sig);
+
+        let bb0 = func.dfg.make_block();
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(bb0);
+        pos.ins().return_(&[]);
+
+        let mut shared_flags_builder = settings::builder();
+        shared_flags_builder.set("opt_level", "none").unwrap();
+        shared_flags_builder.enable("enable_probestack").unwrap();
+        shared_flags_builder
+            .set("probestack_strategy", "inline")
+            .unwrap();
+        let shared_flags = settings::Flags::new(shared_flags_builder);
+        let isa_flags = s390x_settings::Flags::new(&shared_flags, s390x_settings::builder());
+        let backend = S390xBackend::new_with_flags(
+            Triple::from_str("s390x").unwrap(),
+            shared_flags,
+            isa_flags,
+        );
+        match backend.compile_function(&mut func, /* want_disasm = */ false) {
+            Err(CodegenError::Unsupported(_)) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("compiled without the required stack probes"),
+        }
+    }
 }
@@ -0,0 +1,96 @@
+//! Default shared settings for the operating system and object format of a target triple.
+//!
+//! The shared settings describe the environment the code runs in as well as how it is compiled,
+//! so cross-compiling users would otherwise have to know which TLS model and relocation model the
+//! target OS expects. `shared_flags_builder` starts from the settings matching a triple, on top of
+//! which the user's own flags can be set.
+//!
+//! Some per-OS conventions need no setting: the calling convention and the ABI variant (e.g. hard
+//! float for `gnueabihf`) are derived from the triple by the backends, and the `MachInst`
+//! backends always keep a frame pointer, as the Apple and Windows ABIs require.
+
+use crate::settings::{self, Configurable};
+use target_lexicon::{BinaryFormat, OperatingSystem, Triple};
+
+/// A builder for the shared settings, with the defaults of the operating system and object
+/// format of `triple`.
+pub fn shared_flags_builder(triple: &Triple) -> settings::Builder {
+    let mut builder = settings::builder();
+
+    // Bare-metal targets have no loader or runtime to set up thread-local storage, whatever
+    // their object format.
+    let tls_model = match (triple.operating_system, triple.binary_format) {
+        (OperatingSystem::None_, _) | (OperatingSystem::Unknown, _) => "none",
+        (_, BinaryFormat::Elf) => "elf_gd",
+        (_, BinaryFormat::Macho) => "macho",
+        (_, BinaryFormat::Coff) => "coff",
+        _ => "none",
+    };
+    builder.set("tls_model", tls_model).unwrap();
+
+    // Apple platforms only support position-independent executables.
+    if is_apple(triple.operating_system) {
+        builder.enable("is_pic").unwrap();
+    }
+
+    builder
+}
+
+fn is_apple(os: OperatingSystem) -> bool {
+    match os {
+        OperatingSystem::Darwin
+        | OperatingSystem::Ios
+        | OperatingSystem::MacOSX { .. }
+        | OperatingSystem::Tvos => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::{lookup_by_name, LookupError};
+    use crate::settings::{Flags, TlsModel};
+    use core::str::FromStr;
+    use target_lexicon::Environment;
+
+    fn flags(name: &str) -> Flags {
+        Flags::new(shared_flags_builder(&Triple::from_str(name).unwrap()))
+    }
+
+    #[test]
+    fn os_defaults() {
+        let linux = flags("x86_64-unknown-linux-gnu");
+        assert_eq!(linux.tls_model(), TlsModel::ElfGd);
+        assert!(!linux.is_pic());
+
+        let musl = flags("aarch64-unknown-linux-musl");
+        assert_eq!(musl.tls_model(), TlsModel::ElfGd);
+
+        let macos = flags("aarch64-apple-darwin");
+        assert_eq!(macos.tls_model(), TlsModel::Macho);
+        assert!(macos.is_pic());
+
+        let windows = flags("x86_64-pc-windows-msvc");
+        assert_eq!(windows.tls_model(), TlsModel::Coff);
+
+        let bare = flags("riscv64gc-unknown-none-elf");
+        assert_eq!(bare.tls_model(), TlsModel::None);
+        assert!(!bare.is_pic());
+
+        let unknown = flags("x86_64-unknown-unknown");
+        assert_eq!(unknown.tls_model(), TlsModel::None);
+    }
+
+    #[test]
+    fn lookup_triples() {
+        let triple = Triple::from_str("armv7-unknown-linux-gnueabihf").unwrap();
+        assert_eq!(triple.environment, Environment::Gnueabihf);
+        assert_eq!(triple.binary_format, BinaryFormat::Elf);
+
+        match lookup_by_name("not-a-triple") {
+            Err(LookupError::InvalidTriple) => {}
+            _ => panic!("invalid triple accepted"),
+        }
+    }
+}
@@ -3,6 +3,7 @@
 use crate::{
     binemit::CodeOffset,
     isa::unwind::{input, UnwindInst},
+    machinst::MachExceptionHandler,
     result::{CodegenError, CodegenResult},
 };
 use alloc::vec::Vec;
@@ -163,10 +164,62 @@ pub(crate) trait RegisterMapper<Reg> {
 pub struct UnwindInfo {
     instructions: Vec<(u32, CallFrameInstruction)>,
     len: u32,
+    lsda: Option<Vec<u8>>,
+}
+
+/// Create the language-specific data area (LSDA) telling the personality routine where the calls
+/// of a function of `code_len` bytes unwind to, in the `.gcc_except_table` format.
+///
+/// Each call with an exception handler gets a call-site record for its return address, naming its
+/// landing pad with a zero action, i.e. as a cleanup: there is no action or type table, so it is
+/// up to the personality routine to decide whether to enter it. The gaps between these records are
+/// covered by records without landing pads, so that unwinding through any other call continues in
+/// the caller.
+fn create_lsda(handlers: &[MachExceptionHandler], code_len: u32) -> Vec<u8> {
+    // Start, length and landing pad of each call site, relative to the start of the function.
+    let mut call_sites = Vec::with_capacity(handlers.len() * 2 + 1);
+    let mut handlers = handlers.to_vec();
+    handlers.sort_by_key(|handler| handler.ret_addr);
+    let mut covered = 0;
+    for handler in &handlers {
+        // Personality routines look up the byte before the return address.
+        let start = handler.ret_addr - 1;
+        if start > covered {
+            call_sites.push((covered, start - covered, 0));
+        }
+        call_sites.push((start, 1, handler.landing_pad));
+        covered = handler.ret_addr;
+    }
+    if code_len > covered {
+        call_sites.push((covered, code_len - covered, 0));
+    }
+
+    const DW_EH_PE_OMIT: u8 = 0xff;
+    const DW_EH_PE_UDATA4: u8 = 0x03;
+    // Each record has three 4-byte fields and a one-byte ULEB128 action.
+    let mut table_len = call_sites.len() * 13;
+    let mut lsda = vec![DW_EH_PE_OMIT, DW_EH_PE_OMIT, DW_EH_PE_UDATA4];
+    loop {
+        let byte = (table_len & 0x7f) as u8;
+        table_len >>= 7;
+        if table_len == 0 {
+            lsda.push(byte);
+            break;
+        }
+        lsda.push(byte | 0x80);
+    }
+    for (start, len, landing_pad) in call_sites {
+        lsda.extend_from_slice(&start.to_le_bytes());
+        lsda.extend_from_slice(&len.to_le_bytes());
+        lsda.extend_from_slice(&landing_pad.to_le_bytes());
+        lsda.push(0);
+    }
+    lsda
 }
 
 pub(crate) fn create_unwind_info_from_insts<MR: RegisterMapper<regalloc::Reg>>(
     insts: &[(CodeOffset, UnwindInst)],
+    exception_handlers: &[MachExceptionHandler],
     code_len: usize,
     mr: &MR,
 ) -> CodegenResult<UnwindInfo> {
@@ -256,9 +309,16 @@ pub(crate) fn create_unwind_info_from_insts<MR: RegisterMapper<regalloc::Reg>>(
         }
     }
 
+    let lsda = if exception_handlers.is_empty() {
+        None
+    } else {
+        Some(create_lsda(exception_handlers, code_len as u32))
+    };
+
     Ok(UnwindInfo {
         instructions,
         len: code_len as u32,
+        lsda,
     })
 }
 
@@ -320,7 +380,21 @@ impl UnwindInfo {
         let instructions = builder.instructions;
         let len = unwind.function_size;
 
-        Ok(Self { instructions, len })
+        Ok(Self {
+            instructions,
+            len,
+            lsda: None,
+        })
+    }
+
+    /// Get the language-specific data area of the function, if it has any exception handlers.
+    ///
+    /// It is in the `.gcc_except_table` format, with offsets relative to the start of the
+    /// function. To unwind to the landing pads, the LSDA has to be placed in memory and the `lsda`
+    /// of the function's FDE set to its address, with a CIE naming the personality routine and the
+    /// LSDA pointer encoding.
+    pub fn lsda(&self) -> Option<&[u8]> {
+        self.lsda.as_deref()
     }
 
     /// Converts the unwind information into a `FrameDescriptionEntry`.
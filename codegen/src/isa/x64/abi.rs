@@ -18,6 +18,10 @@ use smallvec::{smallvec, SmallVec};
 /// with 32-bit arithmetic: for now, 128 MB.
 static STACK_ARG_RET_SIZE_LIMIT: u64 = 128 * 1024 * 1024;
 
+/// Size of the shadow space that a Windows fastcall caller reserves for the
+/// callee, right above the return address.
+static FASTCALL_SHADOW_SPACE_SIZE: u64 = 32;
+
 /// Offset in stack-arg area to callee-TLS slot in Baldrdash-2020 calling convention.
 static BALDRDASH_CALLEE_TLS_OFFSET: i64 = 0;
 /// Offset in stack-arg area to caller-TLS slot in Baldrdash-2020 calling convention.
@@ -116,7 +120,7 @@ impl ABIMachineSpec for X64ABIMachineSpec {
             //
             // (See:
             // https://docs.microsoft.com/en-us/cpp/build/x64-calling-convention?view=msvc-160)
-            next_stack = 32;
+            next_stack = FASTCALL_SHADOW_SPACE_SIZE;
         }
 
         if args_or_rets == ArgsOrRets::Args && has_baldrdash_tls {
@@ -244,6 +248,8 @@ impl ABIMachineSpec for X64ABIMachineSpec {
                     // Note that in all cases 16-byte stack alignment happens
                     // separately after all args.
                     let size = (reg_ty.bits() / 8) as u64;
+                    // `f16` values are moved to and from the stack as 32 bits; see `Inst::store`.
+                    let size = if *reg_ty == types::F16 { 4 } else { size };
                     let size = if args_or_rets == ArgsOrRets::Rets && call_conv.extends_wasmtime() {
                         size
                     } else {
@@ -388,6 +394,32 @@ impl ABIMachineSpec for X64ABIMachineSpec {
         ]
     }
 
+    fn gen_cond_br_imm(
+        reg: Reg,
+        imm: u64,
+        cond: ir::condcodes::IntCC,
+        taken: MachLabel,
+        not_taken: MachLabel,
+        tmp: Writable<Reg>,
+    ) -> SmallInstVec<Self::I> {
+        let mut insts = SmallVec::new();
+        // Comparisons sign-extend 32-bit immediates to 64 bits.
+        let imm = match i32::try_from(imm as i64) {
+            Ok(simm32) => RegMemImm::imm(simm32 as u32),
+            Err(_) => {
+                insts.push(Inst::imm(OperandSize::Size64, imm, tmp));
+                RegMemImm::reg(tmp.to_reg())
+            }
+        };
+        insts.push(Inst::cmp_rmi_r(OperandSize::Size64, imm, reg));
+        insts.push(Inst::jmp_cond(CC::from_intcc(cond), taken, not_taken));
+        insts
+    }
+
+    fn gen_tail_jump(target: Reg) -> Self::I {
+        Inst::jmp_unknown(RegMem::reg(target))
+    }
+
     fn gen_get_stack_addr(mem: StackAMode, into_reg: Writable<Reg>, _ty: Type) -> Self::I {
         let mem: SyntheticAmode = mem.into();
         Inst::lea(mem, into_reg)
@@ -405,11 +437,15 @@ impl ABIMachineSpec for X64ABIMachineSpec {
     }
 
     fn gen_load_base_offset(into_reg: Writable<Reg>, base: Reg, offset: i32, ty: Type) -> Self::I {
-        // Only ever used for I64s; if that changes, see if the ExtKind below needs to be changed.
-        assert_eq!(ty, I64);
+        // Narrower integers are zero-extended, like on the other targets.
+        let ext_kind = if ty == I64 {
+            ExtKind::None
+        } else {
+            ExtKind::ZeroExtend
+        };
         let simm32 = offset as u32;
         let mem = Amode::imm_reg(simm32, base);
-        Inst::load(ty, mem, into_reg, ExtKind::None)
+        Inst::load(ty, mem, into_reg, ext_kind)
     }
 
     fn gen_store_base_offset(base: Reg, offset: i32, from_reg: Reg, ty: Type) -> Self::I {
@@ -493,6 +529,36 @@ impl ABIMachineSpec for X64ABIMachineSpec {
         insts
     }
 
+    fn gen_inline_probestack(frame_size: u32, guard_size: u32) -> SmallInstVec<Self::I> {
+        // Small frames get one probe per page; larger ones use a loop so the
+        // prologue doesn't grow with the frame.
+        const PROBE_MAX_UNROLL: u32 = 3;
+        let probe_count = frame_size / guard_size;
+        let mut insts = SmallVec::new();
+        if probe_count == 0 {
+            // Nothing to probe.
+        } else if probe_count <= PROBE_MAX_UNROLL {
+            // Move %rsp down a page at a time and store to it, so that no
+            // store lands below %rsp, then put %rsp back. Any value will do,
+            // so store %rsp rather than materializing a zero.
+            for _ in 0..probe_count {
+                insts.extend(Self::gen_sp_reg_adjust(-(guard_size as i32)));
+                insts.push(Inst::store(
+                    types::I32,
+                    regs::rsp(),
+                    Amode::imm_reg(0, regs::rsp()),
+                ));
+            }
+            insts.extend(Self::gen_sp_reg_adjust((guard_size * probe_count) as i32));
+        } else {
+            insts.push(Inst::StackProbeLoop {
+                frame_size: probe_count * guard_size,
+                guard_size,
+            });
+        }
+        insts
+    }
+
     fn gen_clobber_save(
         call_conv: isa::CallConv,
         flags: &settings::Flags,
@@ -631,6 +697,36 @@ impl ABIMachineSpec for X64ABIMachineSpec {
         insts
     }
 
+    /// Generate a tail call instruction/sequence.
+    fn gen_tail_call(
+        dest: &CallDest,
+        uses: Vec<Reg>,
+        _callee_conv: isa::CallConv,
+    ) -> SmallVec<[Self::I; 2]> {
+        // %r11 is caller-saved and never holds an argument, so the epilogue leaves the called
+        // address alone.
+        let tmp = Writable::from_reg(regs::r11());
+        let mut insts = SmallVec::new();
+        match dest {
+            &CallDest::ExtName(ref name, RelocDistance::Near) => {
+                insts.push(Inst::return_call_known(name.clone(), uses));
+            }
+            &CallDest::ExtName(ref name, RelocDistance::Far) => {
+                insts.push(Inst::LoadExtName {
+                    dst: tmp,
+                    name: Box::new(name.clone()),
+                    offset: 0,
+                });
+                insts.push(Inst::return_call_unknown(tmp.to_reg(), uses));
+            }
+            &CallDest::Reg(reg) => {
+                insts.push(Inst::gen_move(tmp, reg, types::I64));
+                insts.push(Inst::return_call_unknown(tmp.to_reg(), uses));
+            }
+        }
+        insts
+    }
+
     /// Generate a call instruction/sequence.
     fn gen_call(
         dest: &CallDest,
@@ -709,12 +805,28 @@ impl ABIMachineSpec for X64ABIMachineSpec {
             name: Box::new(ExternalName::LibCall(LibCall::Memcpy)),
             offset: 0,
         });
+        // Fastcall callees may use the 32 bytes above the return address as
+        // shadow space. The area at SP belongs to the outgoing arguments of the
+        // call being set up, so reserve fresh shadow space for `memcpy()`.
+        let shadow_space = if call_conv.extends_windows_fastcall() {
+            FASTCALL_SHADOW_SPACE_SIZE as i32
+        } else {
+            0
+        };
+        if shadow_space > 0 {
+            insts.extend(Self::gen_sp_reg_adjust(-shadow_space));
+            insts.push(Self::gen_nominal_sp_adj(shadow_space));
+        }
         insts.push(Inst::call_unknown(
             RegMem::reg(memcpy_addr),
             /* uses = */ vec![arg0, arg1, arg2],
             /* defs = */ Self::get_regs_clobbered_by_call(call_conv),
             Opcode::Call,
         ));
+        if shadow_space > 0 {
+            insts.extend(Self::gen_sp_reg_adjust(shadow_space));
+            insts.push(Self::gen_nominal_sp_adj(-shadow_space));
+        }
         insts
     }
 
@@ -722,7 +834,9 @@ impl ABIMachineSpec for X64ABIMachineSpec {
         // We allocate in terms of 8-byte slots.
         match (rc, ty) {
             (RegClass::I64, _) => 1,
-            (RegClass::V128, types::F32) | (RegClass::V128, types::F64) => 1,
+            (RegClass::V128, types::F16)
+            | (RegClass::V128, types::F32)
+            | (RegClass::V128, types::F64) => 1,
             (RegClass::V128, _) => 2,
             _ => panic!("Unexpected register class!"),
         }
@@ -783,6 +897,10 @@ impl ABIMachineSpec for X64ABIMachineSpec {
         caller_saved
     }
 
+    fn get_exception_payload_regs() -> [Reg; 2] {
+        [regs::rax(), regs::rdx()]
+    }
+
     fn get_ext_mode(
         call_conv: isa::CallConv,
         specified: ir::ArgumentExtension,
@@ -460,11 +460,14 @@ pub(crate) enum InstructionSet {
     SSSE3,
     SSE41,
     SSE42,
+    CMPXCHG16b,
+    MOVBE,
     Popcnt,
     Lzcnt,
     BMI1,
     #[allow(dead_code)] // never constructed (yet).
     BMI2,
+    F16C,
     AVX512BITALG,
     AVX512DQ,
     AVX512F,
@@ -1011,6 +1014,41 @@ impl fmt::Display for SseOpcode {
     }
 }
 
+/// VEX-encoded operations on one XMM register, written to another.
+#[derive(Clone, PartialEq)]
+pub enum AvxOpcode {
+    /// Convert the four `f16` in the low 64 bits of the source to `f32`.
+    Vcvtph2ps,
+    /// Convert the four `f32` of the source to `f16`, in the low 64 bits of the destination,
+    /// rounding to nearest, ties to even.
+    Vcvtps2ph,
+}
+
+impl AvxOpcode {
+    /// Which `InstructionSet`s support the opcode?
+    pub(crate) fn available_from(&self) -> SmallVec<[InstructionSet; 2]> {
+        match self {
+            AvxOpcode::Vcvtph2ps | AvxOpcode::Vcvtps2ph => smallvec![InstructionSet::F16C],
+        }
+    }
+}
+
+impl fmt::Debug for AvxOpcode {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            AvxOpcode::Vcvtph2ps => "vcvtph2ps",
+            AvxOpcode::Vcvtps2ph => "vcvtps2ph",
+        };
+        write!(fmt, "{}", name)
+    }
+}
+
+impl fmt::Display for AvxOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub enum Avx512Opcode {
     Vcvtudq2ps,
@@ -12,10 +12,12 @@ use crate::{
                 int_reg_enc, low8_will_sign_extend_to_32, low8_will_sign_extend_to_64, reg_enc,
                 LegacyPrefixes, OpcodeMap, RexFlags,
             },
+            vex::VexInstruction,
         },
         inst::{args::*, *},
     },
     machinst::{inst_common, MachBuffer, MachInstEmit, MachLabel},
+    settings::TrapEncoding,
 };
 use core::convert::TryInto;
 use log::debug;
@@ -128,10 +130,13 @@ pub(crate) fn emit(
             InstructionSet::SSSE3 => info.isa_flags.use_ssse3(),
             InstructionSet::SSE41 => info.isa_flags.use_sse41(),
             InstructionSet::SSE42 => info.isa_flags.use_sse42(),
+            InstructionSet::CMPXCHG16b => info.isa_flags.use_cmpxchg16b(),
+            InstructionSet::MOVBE => info.isa_flags.use_movbe(),
             InstructionSet::Popcnt => info.isa_flags.use_popcnt(),
             InstructionSet::Lzcnt => info.isa_flags.use_lzcnt(),
             InstructionSet::BMI1 => info.isa_flags.use_bmi1(),
             InstructionSet::BMI2 => info.isa_flags.has_bmi2(),
+            InstructionSet::F16C => info.isa_flags.use_f16c(),
             InstructionSet::AVX512BITALG => info.isa_flags.has_avx512bitalg(),
             InstructionSet::AVX512DQ => info.isa_flags.has_avx512dq(),
             InstructionSet::AVX512F => info.isa_flags.has_avx512f(),
@@ -208,9 +213,9 @@ pub(crate) fn emit(
             } else {
                 let (opcode_r, opcode_m, subopcode_i, is_8bit) = match op {
                     AluRmiROpcode::Add => (0x01, 0x03, 0, false),
-                    AluRmiROpcode::Adc => (0x11, 0x03, 0, false),
+                    AluRmiROpcode::Adc => (0x11, 0x13, 2, false),
                     AluRmiROpcode::Sub => (0x29, 0x2B, 5, false),
-                    AluRmiROpcode::Sbb => (0x19, 0x2B, 5, false),
+                    AluRmiROpcode::Sbb => (0x19, 0x1B, 3, false),
                     AluRmiROpcode::And => (0x21, 0x23, 4, false),
                     AluRmiROpcode::Or => (0x09, 0x0B, 1, false),
                     AluRmiROpcode::Xor => (0x31, 0x33, 6, false),
@@ -358,6 +363,17 @@ pub(crate) fn emit(
             emit_std_enc_enc(sink, prefix, opcode, 1, subopcode, enc_src, rex_flags)
         }
 
+        Inst::Bswap { size, src } => {
+            let rex_flags = RexFlags::from(*size);
+            let enc_src = int_reg_enc(src.to_reg());
+
+            // BSWAP r32 is (REX.W==0) 0F C8+rd
+            // BSWAP r64 is (REX.W==1) 0F C8+rd
+            rex_flags.emit_two_op(sink, 0, enc_src);
+            sink.put1(0x0F);
+            sink.put1(0xC8 | (enc_src & 7));
+        }
+
         Inst::Div {
             size,
             signed,
@@ -693,6 +709,30 @@ pub(crate) fn emit(
             )
         }
 
+        Inst::MovbeMR { size, src, dst } => {
+            let src = &src.finalize(state, sink);
+
+            let prefix = match size {
+                OperandSize::Size16 => LegacyPrefixes::_66,
+                _ => LegacyPrefixes::None,
+            };
+
+            // 16-bit: MOVBE m16, r16 is 66 (REX.W==0) 0F 38 F0 /r
+            // 32-bit: MOVBE m32, r32 is (REX.W==0) 0F 38 F0 /r
+            // 64-bit: MOVBE m64, r64 is (REX.W==1) 0F 38 F0 /r
+            emit_std_reg_mem(
+                sink,
+                state,
+                info,
+                prefix,
+                0x0F38F0,
+                3,
+                dst.to_reg(),
+                src,
+                RexFlags::from(*size),
+            )
+        }
+
         Inst::LoadEffectiveAddress { addr, dst } => {
             let amode = addr.finalize(state, sink);
 
@@ -796,6 +836,30 @@ pub(crate) fn emit(
             emit_std_reg_mem(sink, state, info, prefix, opcode, 1, *src, dst, rex);
         }
 
+        Inst::MovbeRM { size, src, dst } => {
+            let dst = &dst.finalize(state, sink);
+
+            let prefix = match size {
+                OperandSize::Size16 => LegacyPrefixes::_66,
+                _ => LegacyPrefixes::None,
+            };
+
+            // 16-bit: MOVBE r16, m16 is 66 (REX.W==0) 0F 38 F1 /r
+            // 32-bit: MOVBE r32, m32 is (REX.W==0) 0F 38 F1 /r
+            // 64-bit: MOVBE r64, m64 is (REX.W==1) 0F 38 F1 /r
+            emit_std_reg_mem(
+                sink,
+                state,
+                info,
+                prefix,
+                0x0F38F1,
+                3,
+                *src,
+                dst,
+                RexFlags::from(*size),
+            );
+        }
+
         Inst::ShiftR {
             size,
             kind,
@@ -1163,6 +1227,27 @@ pub(crate) fn emit(
             }
         }
 
+        Inst::ReturnCallKnown { dest, .. } => {
+            sink.put1(0xE9);
+            // The addend adjusts for the difference between the end of the instruction and the
+            // beginning of the immediate field.
+            emit_reloc(sink, state, Reloc::X86CallPCRel4, &dest, -4);
+            sink.put4(0);
+        }
+
+        Inst::ReturnCallUnknown { dest, .. } => {
+            let reg_enc = int_reg_enc(*dest);
+            emit_std_enc_enc(
+                sink,
+                LegacyPrefixes::None,
+                0xFF,
+                1,
+                4, /*subopcode*/
+                reg_enc,
+                RexFlags::clear_w(),
+            );
+        }
+
         Inst::Ret {} => sink.put1(0xC3),
 
         Inst::JmpKnown { dst } => {
@@ -1178,6 +1263,21 @@ pub(crate) fn emit(
             sink.put4(0x0);
         }
 
+        Inst::JmpInvoke { dst, landing_pad } => {
+            sink.add_exception_handler(*landing_pad);
+
+            let br_start = sink.cur_offset();
+            let br_disp_off = br_start + 1;
+            let br_end = br_start + 5;
+
+            sink.use_label_at_offset(br_disp_off, *dst, LabelUse::JmpRel32);
+            sink.add_uncond_branch(br_start, br_end, *dst);
+
+            sink.put1(0xE9);
+            // Placeholder for the label value.
+            sink.put4(0x0);
+        }
+
         Inst::JmpIf { cc, taken } => {
             let cond_start = sink.cur_offset();
             let cond_disp_off = cond_start + 2;
@@ -1261,6 +1361,7 @@ pub(crate) fn emit(
             tmp2,
             ref targets,
             default_target,
+            ref abs_name,
             ..
         } => {
             // This sequence is *one* instruction in the vcode, and is expanded only here at
@@ -1284,6 +1385,9 @@ pub(crate) fn emit(
             // j *%tmp1
             // $start_of_jump_table:
             // -- jump table entries
+            //
+            // With absolute entries, the load and add are replaced by:
+            // movq [%tmp1, %tmp2, 8], %tmp1 ;; shift of 3, viz. multiply index by 8
             one_way_jmp(sink, CC::NB, *default_target); // idx unsigned >= jmp table size
 
             // Copy the index (and make sure to clear the high 32-bits lane of tmp2).
@@ -1295,40 +1399,61 @@ pub(crate) fn emit(
             let inst = Inst::lea(Amode::rip_relative(start_of_jumptable), *tmp1);
             inst.emit(sink, info, state);
 
-            // Load value out of the jump table. It's a relative offset to the target block, so it
-            // might be negative; use a sign-extension.
-            let inst = Inst::movsx_rm_r(
-                ExtMode::LQ,
-                RegMem::mem(Amode::imm_reg_reg_shift(0, tmp1.to_reg(), tmp2.to_reg(), 2)),
-                *tmp2,
-            );
-            inst.emit(sink, info, state);
+            if let Some(name) = abs_name {
+                // Load the target address straight out of the jump table.
+                let inst = Inst::mov64_m_r(
+                    Amode::imm_reg_reg_shift(0, tmp1.to_reg(), tmp2.to_reg(), 3),
+                    *tmp1,
+                );
+                inst.emit(sink, info, state);
 
-            // Add base of jump table to jump-table-sourced block offset.
-            let inst = Inst::alu_rmi_r(
-                OperandSize::Size64,
-                AluRmiROpcode::Add,
-                RegMemImm::reg(tmp2.to_reg()),
-                *tmp1,
-            );
-            inst.emit(sink, info, state);
+                // Branch to loaded address.
+                let inst = Inst::jmp_unknown(RegMem::reg(tmp1.to_reg()));
+                inst.emit(sink, info, state);
 
-            // Branch to computed address.
-            let inst = Inst::jmp_unknown(RegMem::reg(tmp1.to_reg()));
-            inst.emit(sink, info, state);
+                // Emit jump table (table of 64-bit addresses, relocated against the function).
+                sink.bind_label(start_of_jumptable);
+                let srcloc = state.cur_srcloc();
+                for &target in targets.iter() {
+                    sink.add_label_reloc(srcloc, Reloc::Abs8, name, target, 0);
+                    sink.put8(0);
+                }
+            } else {
+                // Load value out of the jump table. It's a relative offset to the target block,
+                // so it might be negative; use a sign-extension.
+                let inst = Inst::movsx_rm_r(
+                    ExtMode::LQ,
+                    RegMem::mem(Amode::imm_reg_reg_shift(0, tmp1.to_reg(), tmp2.to_reg(), 2)),
+                    *tmp2,
+                );
+                inst.emit(sink, info, state);
 
-            // Emit jump table (table of 32-bit offsets).
-            sink.bind_label(start_of_jumptable);
-            let jt_off = sink.cur_offset();
-            for &target in targets.iter() {
-                let word_off = sink.cur_offset();
-                // off_into_table is an addend here embedded in the label to be later patched at
-                // the end of codegen. The offset is initially relative to this jump table entry;
-                // with the extra addend, it'll be relative to the jump table's start, after
-                // patching.
-                let off_into_table = word_off - jt_off;
-                sink.use_label_at_offset(word_off, target, LabelUse::PCRel32);
-                sink.put4(off_into_table);
+                // Add base of jump table to jump-table-sourced block offset.
+                let inst = Inst::alu_rmi_r(
+                    OperandSize::Size64,
+                    AluRmiROpcode::Add,
+                    RegMemImm::reg(tmp2.to_reg()),
+                    *tmp1,
+                );
+                inst.emit(sink, info, state);
+
+                // Branch to computed address.
+                let inst = Inst::jmp_unknown(RegMem::reg(tmp1.to_reg()));
+                inst.emit(sink, info, state);
+
+                // Emit jump table (table of 32-bit offsets).
+                sink.bind_label(start_of_jumptable);
+                let jt_off = sink.cur_offset();
+                for &target in targets.iter() {
+                    let word_off = sink.cur_offset();
+                    // off_into_table is an addend here embedded in the label to be later patched
+                    // at the end of codegen. The offset is initially relative to this jump table
+                    // entry; with the extra addend, it'll be relative to the jump table's start,
+                    // after patching.
+                    let off_into_table = word_off - jt_off;
+                    sink.use_label_at_offset(word_off, target, LabelUse::PCRel32);
+                    sink.put4(off_into_table);
+                }
             }
         }
 
@@ -1438,6 +1563,24 @@ pub(crate) fn emit(
             };
         }
 
+        Inst::XmmUnaryRRVex { op, src, dst } => {
+            let src = src.get_hw_encoding();
+            let dst = dst.to_reg().get_hw_encoding();
+            let inst = VexInstruction::new().prefix(LegacyPrefixes::_66);
+            match op {
+                AvxOpcode::Vcvtph2ps => inst.map(OpcodeMap::_0F38).opcode(0x13).reg(dst).rm(src),
+                // The destination is the `rm` operand; the immediate selects rounding to nearest,
+                // ties to even.
+                AvxOpcode::Vcvtps2ph => inst
+                    .map(OpcodeMap::_0F3A)
+                    .opcode(0x1d)
+                    .reg(src)
+                    .rm(dst)
+                    .imm(0),
+            }
+            .encode(sink);
+        }
+
         Inst::XmmRmR {
             op,
             src: src_e,
@@ -2416,6 +2559,22 @@ pub(crate) fn emit(
             emit_std_reg_mem(sink, state, info, prefix, opcodes, 2, *src, &amode, rex);
         }
 
+        Inst::LockCmpxchg16b { dst } => {
+            // lock cmpxchg16b (dst)
+            let amode = dst.finalize(state, sink);
+            emit_std_enc_mem(
+                sink,
+                state,
+                info,
+                LegacyPrefixes::_F0,
+                0x0FC7,
+                2,
+                1, /*subopcode*/
+                &amode,
+                RexFlags::set_w(),
+            );
+        }
+
         Inst::AtomicRmwSeq { ty, op } => {
             // Emit this:
             //
@@ -2523,6 +2682,69 @@ pub(crate) fn emit(
             one_way_jmp(sink, CC::NZ, again_label);
         }
 
+        Inst::StackProbeLoop {
+            frame_size,
+            guard_size,
+        } => {
+            // Emit this:
+            //
+            //    movq  %rsp, %r11
+            //    subq  $frame_size, %r11   // r11 = lowest address to probe
+            //   again:
+            //    subq  $guard_size, %rsp
+            //    movl  %esp, (%rsp)        // touch the page; any value will do
+            //    cmpq  %r11, %rsp
+            //    jnz   again
+            //    addq  $frame_size, %rsp
+            //
+            // Moving %rsp along with the probes keeps every store at or above
+            // the stack pointer, as some tools and kernels expect.
+            debug_assert!(*guard_size > 0 && *frame_size % *guard_size == 0);
+            let rsp = regs::rsp();
+            let r11 = regs::r11();
+            let rsp_w = Writable::from_reg(rsp);
+            let r11_w = Writable::from_reg(r11);
+            let again_label = sink.get_label();
+
+            let i1 = Inst::mov_r_r(OperandSize::Size64, rsp, r11_w);
+            i1.emit(sink, info, state);
+
+            let i2 = Inst::alu_rmi_r(
+                OperandSize::Size64,
+                AluRmiROpcode::Sub,
+                RegMemImm::imm(*frame_size),
+                r11_w,
+            );
+            i2.emit(sink, info, state);
+
+            // again:
+            sink.bind_label(again_label);
+
+            let i3 = Inst::alu_rmi_r(
+                OperandSize::Size64,
+                AluRmiROpcode::Sub,
+                RegMemImm::imm(*guard_size),
+                rsp_w,
+            );
+            i3.emit(sink, info, state);
+
+            let i4 = Inst::store(types::I32, rsp, Amode::imm_reg(0, rsp));
+            i4.emit(sink, info, state);
+
+            let i5 = Inst::cmp_rmi_r(OperandSize::Size64, RegMemImm::reg(r11), rsp);
+            i5.emit(sink, info, state);
+
+            one_way_jmp(sink, CC::NZ, again_label);
+
+            let i6 = Inst::alu_rmi_r(
+                OperandSize::Size64,
+                AluRmiROpcode::Add,
+                RegMemImm::imm(*frame_size),
+                rsp_w,
+            );
+            i6.emit(sink, info, state);
+        }
+
         Inst::Fence { kind } => {
             sink.put1(0x0F);
             sink.put1(0xAE);
@@ -2533,6 +2755,26 @@ pub(crate) fn emit(
             }
         }
 
+        Inst::Rdtsc => {
+            // rdtsc = 0F 31
+            sink.put1(0x0F);
+            sink.put1(0x31);
+        }
+
+        Inst::Rdpkru => {
+            // rdpkru = 0F 01 EE
+            sink.put1(0x0F);
+            sink.put1(0x01);
+            sink.put1(0xEE);
+        }
+
+        Inst::Wrpkru => {
+            // wrpkru = 0F 01 EF
+            sink.put1(0x0F);
+            sink.put1(0x01);
+            sink.put1(0xEF);
+        }
+
         Inst::Hlt => {
             sink.put1(0xcc);
         }
@@ -2540,11 +2782,38 @@ pub(crate) fn emit(
         Inst::Ud2 { trap_code } => {
             let cur_srcloc = state.cur_srcloc();
             sink.add_trap(cur_srcloc, *trap_code);
+            let trap_encoding = info.flags().trap_encoding();
+            let len = match trap_encoding {
+                TrapEncoding::Undefined => 2,
+                TrapEncoding::Breakpoint => 1,
+                TrapEncoding::CallHandler => 5,
+            };
             if let Some(s) = state.take_stack_map() {
-                sink.add_stack_map(StackMapExtent::UpcomingBytes(2), s);
+                sink.add_stack_map(StackMapExtent::UpcomingBytes(len), s);
+            }
+            match trap_encoding {
+                TrapEncoding::Undefined => {
+                    // ud2
+                    sink.put1(0x0f);
+                    sink.put1(0x0b);
+                }
+                TrapEncoding::Breakpoint => {
+                    // int3
+                    sink.put1(0xcc);
+                }
+                TrapEncoding::CallHandler => {
+                    // call TrapHandler
+                    sink.put1(0xe8);
+                    emit_reloc(
+                        sink,
+                        state,
+                        Reloc::X86CallPCRel4,
+                        &ExternalName::LibCall(LibCall::TrapHandler),
+                        -4,
+                    );
+                    sink.put4(0);
+                }
             }
-            sink.put1(0x0f);
-            sink.put1(0x0b);
         }
 
         Inst::VirtualSPOffsetAdj { offset } => {
@@ -2670,6 +2939,28 @@ pub(crate) fn emit(
             sink.put4(0); // offset
         }
 
+        Inst::ElfTlsInitialExec { ref symbol, dst } => {
+            // N.B.: The linker may rewrite this sequence into a cheaper one
+            // (local-exec) when it knows the offset, so keep to the usual form.
+            let enc_dst = dst.to_reg().get_hw_encoding();
+            let rex = 0b0100_1000 | ((enc_dst >> 3) << 2); // REX.W, REX.R for %r8-%r15
+
+            // movq %fs:0, %dst
+            sink.put1(0x64); // FS segment override
+            sink.put1(rex);
+            sink.put1(0x8b); // MOV
+            sink.put1(0x04 | ((enc_dst & 7) << 3)); // ModRM byte: SIB follows
+            sink.put1(0x25); // SIB byte: disp32 only
+            sink.put4(0); // offset
+
+            // addq gv@gottpoff(%rip), %dst
+            sink.put1(rex);
+            sink.put1(0x03); // ADD
+            sink.put1(0x05 | ((enc_dst & 7) << 3)); // ModRM byte: RIP-relative
+            emit_reloc(sink, state, Reloc::ElfX86_64GotTpOff, symbol, -4);
+            sink.put4(0); // offset
+        }
+
         Inst::MachOTlsGetAddr { ref symbol } => {
             // movq gv@tlv(%rip), %rdi
             sink.put1(0x48); // REX.w
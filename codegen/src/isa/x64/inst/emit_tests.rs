@@ -63,7 +63,7 @@ fn test_x64_emit() {
     let _w_rbp = Writable::<Reg>::from_reg(rbp);
     let w_r8 = Writable::<Reg>::from_reg(r8);
     let w_r9 = Writable::<Reg>::from_reg(r9);
-    let _w_r10 = Writable::<Reg>::from_reg(r10);
+    let w_r10 = Writable::<Reg>::from_reg(r10);
     let w_r11 = Writable::<Reg>::from_reg(r11);
     let w_r12 = Writable::<Reg>::from_reg(r12);
     let w_r13 = Writable::<Reg>::from_reg(r13);
@@ -1033,6 +1033,66 @@ fn test_x64_emit() {
         "81C6EAF48F04",
         "addl    $76543210, %esi",
     ));
+    insns.push((
+        Inst::alu_rmi_r(
+            OperandSize::Size64,
+            AluRmiROpcode::Adc,
+            RegMemImm::reg(r15),
+            w_rdx,
+        ),
+        "4C11FA",
+        "adcq    %r15, %rdx",
+    ));
+    insns.push((
+        Inst::alu_rmi_r(
+            OperandSize::Size64,
+            AluRmiROpcode::Adc,
+            RegMemImm::mem(Amode::imm_reg(128, rax)),
+            w_rdx,
+        ),
+        "48139080000000",
+        "adcq    128(%rax), %rdx",
+    ));
+    insns.push((
+        Inst::alu_rmi_r(
+            OperandSize::Size32,
+            AluRmiROpcode::Adc,
+            RegMemImm::imm(1),
+            w_rsi,
+        ),
+        "83D601",
+        "adcl    $1, %esi",
+    ));
+    insns.push((
+        Inst::alu_rmi_r(
+            OperandSize::Size64,
+            AluRmiROpcode::Sbb,
+            RegMemImm::reg(r15),
+            w_rdx,
+        ),
+        "4C19FA",
+        "sbbq    %r15, %rdx",
+    ));
+    insns.push((
+        Inst::alu_rmi_r(
+            OperandSize::Size64,
+            AluRmiROpcode::Sbb,
+            RegMemImm::mem(Amode::imm_reg(128, rax)),
+            w_rdx,
+        ),
+        "481B9080000000",
+        "sbbq    128(%rax), %rdx",
+    ));
+    insns.push((
+        Inst::alu_rmi_r(
+            OperandSize::Size32,
+            AluRmiROpcode::Sbb,
+            RegMemImm::imm(76543210),
+            w_rsi,
+        ),
+        "81DEEAF48F04",
+        "sbbl    $76543210, %esi",
+    ));
     // This is pretty feeble
     insns.push((
         Inst::alu_rmi_r(
@@ -1396,6 +1456,29 @@ fn test_x64_emit() {
         "negb    %al",
     ));
 
+    // ========================================================
+    // Bswap
+    insns.push((
+        Inst::bswap(OperandSize::Size32, Writable::from_reg(regs::rsi())),
+        "0FCE",
+        "bswapl  %esi",
+    ));
+    insns.push((
+        Inst::bswap(OperandSize::Size64, Writable::from_reg(regs::r15())),
+        "490FCF",
+        "bswapq  %r15",
+    ));
+    insns.push((
+        Inst::bswap(OperandSize::Size32, Writable::from_reg(regs::r14())),
+        "410FCE",
+        "bswapl  %r14d",
+    ));
+    insns.push((
+        Inst::bswap(OperandSize::Size64, Writable::from_reg(regs::rax())),
+        "480FC8",
+        "bswapq  %rax",
+    ));
+
     // ========================================================
     // Div
     insns.push((
@@ -1880,6 +1963,42 @@ fn test_x64_emit() {
         "lea     label0(%rip), %rdi",
     ));
 
+    // ========================================================
+    // Movbe_M_R
+    insns.push((
+        Inst::movbe_m_r(OperandSize::Size32, Amode::imm_reg(99, rdi), w_rsi),
+        "0F38F07763",
+        "movbel  99(%rdi), %esi",
+    ));
+    insns.push((
+        Inst::movbe_m_r(OperandSize::Size64, Amode::imm_reg(99, r9), w_r8),
+        "4D0F38F04163",
+        "movbeq  99(%r9), %r8",
+    ));
+    insns.push((
+        Inst::movbe_m_r(OperandSize::Size16, Amode::imm_reg(99, rdi), w_rax),
+        "660F38F04763",
+        "movbew  99(%rdi), %ax",
+    ));
+
+    // ========================================================
+    // Movbe_R_M
+    insns.push((
+        Inst::movbe_r_m(OperandSize::Size64, rax, Amode::imm_reg(99, rdi)),
+        "480F38F14763",
+        "movbeq  %rax, 99(%rdi)",
+    ));
+    insns.push((
+        Inst::movbe_r_m(OperandSize::Size32, r12, Amode::imm_reg(99, rsi)),
+        "440F38F16663",
+        "movbel  %r12d, 99(%rsi)",
+    ));
+    insns.push((
+        Inst::movbe_r_m(OperandSize::Size16, rcx, Amode::imm_reg(99, r8)),
+        "66410F38F14863",
+        "movbew  %cx, 99(%r8)",
+    ));
+
     // ========================================================
     // MovSX_RM_R
     insns.push((
@@ -3225,6 +3344,30 @@ fn test_x64_emit() {
         "call    *321(%r10,%rdx,4)",
     ));
 
+    // ========================================================
+    // ReturnCallKnown / ReturnCallUnknown
+    insns.push((
+        Inst::return_call_known(
+            ExternalName::User {
+                namespace: 0,
+                index: 0,
+            },
+            Vec::new(),
+        ),
+        "E900000000",
+        "jmp     User { namespace: 0, index: 0 }",
+    ));
+    insns.push((
+        Inst::return_call_unknown(rax, Vec::new()),
+        "FFE0",
+        "jmp     *%rax",
+    ));
+    insns.push((
+        Inst::return_call_unknown(r11, Vec::new()),
+        "41FFE3",
+        "jmp     *%r11",
+    ));
+
     // ========================================================
     // LoadExtName
     // N.B.: test harness below sets is_pic.
@@ -3912,6 +4055,18 @@ fn test_x64_emit() {
         "vpopcntb %xmm2, %xmm8",
     ));
 
+    insns.push((
+        Inst::xmm_unary_r_r_vex(AvxOpcode::Vcvtph2ps, xmm2, w_xmm8),
+        "C4627913C2",
+        "vcvtph2ps %xmm2, %xmm8",
+    ));
+
+    insns.push((
+        Inst::xmm_unary_r_r_vex(AvxOpcode::Vcvtps2ph, xmm2, w_xmm8),
+        "C4C3791DD000",
+        "vcvtps2ph %xmm2, %xmm8",
+    ));
+
     insns.push((
         Inst::xmm_unary_rm_r(SseOpcode::Cvtpd2ps, RegMem::reg(xmm7), w_xmm7),
         "660F5AFF",
@@ -4245,6 +4400,20 @@ fn test_x64_emit() {
         "lock cmpxchgq %r10, -12345(%rcx,%rsi,8)",
     ));
 
+    // LockCmpxchg16b
+    insns.push((
+        Inst::LockCmpxchg16b {
+            dst: Amode::imm_reg_reg_shift(321, r10, rdx, 2).into(),
+        },
+        "F0490FC78C9241010000",
+        "lock cmpxchg16b 321(%r10,%rdx,4)",
+    ));
+    insns.push((
+        Inst::LockCmpxchg16b { dst: am2.clone() },
+        "F0480FC78CF1C7CFFFFF",
+        "lock cmpxchg16b -12345(%rcx,%rsi,8)",
+    ));
+
     // AtomicRmwSeq
     insns.push((
         Inst::AtomicRmwSeq { ty: types::I8, op: inst_common::AtomicRmwOp::Or, },
@@ -4272,6 +4441,16 @@ fn test_x64_emit() {
         "atomically { 64_bits_at_[%r9]) Add= %r10; %rax = old_value_at_[%r9]; %r11, %rflags = trash }"
     ));
 
+    // StackProbeLoop
+    insns.push((
+        Inst::StackProbeLoop {
+            frame_size: 0x5000,
+            guard_size: 0x1000,
+        },
+        "4989E34981EB005000004881EC001000008924244C39DC0F85EDFFFFFF4881C400500000",
+        "stack_probe_loop frame_size=20480, guard_size=4096; %r11, %rflags = trash",
+    ));
+
     // Fence
     insns.push((
         Inst::Fence {
@@ -4300,6 +4479,10 @@ fn test_x64_emit() {
 
     insns.push((Inst::Hlt, "CC", "hlt"));
 
+    insns.push((Inst::Rdtsc, "0F31", "rdtsc"));
+    insns.push((Inst::Rdpkru, "0F01EE", "rdpkru"));
+    insns.push((Inst::Wrpkru, "0F01EF", "wrpkru"));
+
     let trap_code = TrapCode::UnreachableCodeReached;
     insns.push((Inst::Ud2 { trap_code }, "0F0B", "ud2 unreachable"));
 
@@ -4314,6 +4497,30 @@ fn test_x64_emit() {
         "elf_tls_get_addr User { namespace: 0, index: 0 }",
     ));
 
+    insns.push((
+        Inst::ElfTlsInitialExec {
+            symbol: ExternalName::User {
+                namespace: 0,
+                index: 0,
+            },
+            dst: w_rax,
+        },
+        "64488B04250000000048030500000000",
+        "elf_tls_initial_exec User { namespace: 0, index: 0 }, %rax",
+    ));
+
+    insns.push((
+        Inst::ElfTlsInitialExec {
+            symbol: ExternalName::User {
+                namespace: 0,
+                index: 0,
+            },
+            dst: w_r10,
+        },
+        "644C8B1425000000004C031500000000",
+        "elf_tls_initial_exec User { namespace: 0, index: 0 }, %r10",
+    ));
+
     insns.push((
         Inst::MachOTlsGetAddr {
             symbol: ExternalName::User {
@@ -4335,6 +4542,10 @@ fn test_x64_emit() {
     let mut isa_flag_builder = x64::settings::builder();
     isa_flag_builder.enable("has_ssse3").unwrap();
     isa_flag_builder.enable("has_sse41").unwrap();
+    isa_flag_builder.enable("has_cmpxchg16b").unwrap();
+    isa_flag_builder.enable("has_movbe").unwrap();
+    isa_flag_builder.enable("has_avx").unwrap();
+    isa_flag_builder.enable("has_f16c").unwrap();
     isa_flag_builder.enable("has_avx512bitalg").unwrap();
     isa_flag_builder.enable("has_avx512dq").unwrap();
     isa_flag_builder.enable("has_avx512f").unwrap();
@@ -4363,3 +4574,224 @@ fn test_x64_emit() {
         assert_eq!(expected_encoding, actual_encoding, "{}", expected_printing);
     }
 }
+
+#[test]
+fn test_x64_emit_trap_encoding() {
+    use crate::settings::Configurable;
+
+    let trap_code = TrapCode::UnreachableCodeReached;
+    for &(trap_encoding, expected_encoding) in &[
+        ("undefined", "0F0B"),
+        ("breakpoint", "CC"),
+        ("call_handler", "E800000000"),
+    ] {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("trap_encoding", trap_encoding).unwrap();
+        let flags = settings::Flags::new(flag_builder);
+        let isa_flags = x64::settings::Flags::new(&flags, x64::settings::builder());
+        let emit_info = EmitInfo::new(flags, isa_flags);
+
+        let mut sink = test_utils::TestCodeSink::new();
+        let mut buffer = MachBuffer::new();
+        Inst::Ud2 { trap_code }.emit(&mut buffer, &emit_info, &mut Default::default());
+        buffer.finish().emit(&mut sink);
+        assert_eq!(expected_encoding, sink.stringify(), "{}", trap_encoding);
+    }
+}
+
+/// Emit random operand combinations of the common integer and SSE instructions and check them
+/// against the reference assembler; see `isa::crosscheck`.
+#[cfg(feature = "asm-crosscheck")]
+#[test]
+fn test_x64_emit_crosscheck() {
+    use crate::isa::crosscheck::{crosscheck, Case, Rng};
+
+    let gprs = [
+        regs::rax(),
+        regs::rbx(),
+        regs::rcx(),
+        regs::rdx(),
+        regs::rsi(),
+        regs::rdi(),
+        regs::rsp(),
+        regs::rbp(),
+        regs::r8(),
+        regs::r9(),
+        regs::r10(),
+        regs::r11(),
+        regs::r12(),
+        regs::r13(),
+        regs::r14(),
+        regs::r15(),
+    ];
+    let xmms = [
+        regs::xmm0(),
+        regs::xmm1(),
+        regs::xmm2(),
+        regs::xmm3(),
+        regs::xmm4(),
+        regs::xmm5(),
+        regs::xmm6(),
+        regs::xmm7(),
+        regs::xmm8(),
+        regs::xmm9(),
+        regs::xmm10(),
+        regs::xmm11(),
+        regs::xmm12(),
+        regs::xmm13(),
+        regs::xmm14(),
+        regs::xmm15(),
+    ];
+    // Immediates and displacements around the boundaries of the short encodings.
+    let imms: [u32; 10] = [
+        0,
+        1,
+        0x7f,
+        0x80,
+        0xffff_ff80,
+        0xffff_ff7f,
+        0xffff_ffff,
+        0x7fff_ffff,
+        0x8000_0000,
+        0x1234_5678,
+    ];
+
+    let mut rng = Rng::new(0x5eed_c0de_cafe_f00d);
+    let amode = |rng: &mut Rng| -> Amode {
+        let simm32 = rng.choose(&imms);
+        let base = rng.choose(&gprs);
+        if rng.coin() {
+            Amode::imm_reg(simm32, base)
+        } else {
+            let mut index = rng.choose(&gprs);
+            while index == regs::rsp() {
+                index = rng.choose(&gprs);
+            }
+            Amode::imm_reg_reg_shift(simm32, base, index, rng.below(4) as u8)
+        }
+    };
+
+    let mut insns = vec![];
+    for _ in 0..2000 {
+        let size = rng.choose(&[OperandSize::Size32, OperandSize::Size64]);
+        let dst = Writable::from_reg(rng.choose(&gprs));
+        let src = rng.choose(&gprs);
+        let rmi = match rng.below(3) {
+            0 => RegMemImm::reg(src),
+            1 => RegMemImm::mem(amode(&mut rng)),
+            _ => RegMemImm::imm(rng.choose(&imms)),
+        };
+        let rm = if rng.coin() {
+            RegMem::reg(src)
+        } else {
+            RegMem::mem(amode(&mut rng))
+        };
+        let insn = match rng.below(13) {
+            0 => {
+                let op = rng.choose(&[
+                    AluRmiROpcode::Add,
+                    AluRmiROpcode::Adc,
+                    AluRmiROpcode::Sub,
+                    AluRmiROpcode::Sbb,
+                    AluRmiROpcode::And,
+                    AluRmiROpcode::Or,
+                    AluRmiROpcode::Xor,
+                    AluRmiROpcode::Mul,
+                ]);
+                Inst::alu_rmi_r(size, op, rmi, dst)
+            }
+            1 => Inst::mov_r_r(size, src, dst),
+            2 => Inst::imm(size, rng.next_u64() >> rng.below(64), dst),
+            3 => {
+                let ext_mode = match rng.below(5) {
+                    0 => ExtMode::BL,
+                    1 => ExtMode::BQ,
+                    2 => ExtMode::WL,
+                    3 => ExtMode::WQ,
+                    _ => ExtMode::LQ,
+                };
+                if rng.coin() && ext_mode != ExtMode::LQ {
+                    Inst::movzx_rm_r(ext_mode, rm, dst)
+                } else {
+                    Inst::movsx_rm_r(ext_mode, rm, dst)
+                }
+            }
+            4 => Inst::mov64_m_r(amode(&mut rng), dst),
+            5 => {
+                let size = rng.choose(&[
+                    OperandSize::Size8,
+                    OperandSize::Size16,
+                    OperandSize::Size32,
+                    OperandSize::Size64,
+                ]);
+                Inst::mov_r_m(size, src, amode(&mut rng))
+            }
+            6 => Inst::lea(amode(&mut rng), dst),
+            7 => {
+                let kind = rng.choose(&[
+                    ShiftKind::ShiftLeft,
+                    ShiftKind::ShiftRightLogical,
+                    ShiftKind::ShiftRightArithmetic,
+                    ShiftKind::RotateLeft,
+                    ShiftKind::RotateRight,
+                ]);
+                // Skip shifts by one: the assembler prefers the dedicated shift-by-one opcode,
+                // which the emitter doesn't use.
+                let num_bits = if rng.coin() {
+                    Some(2 + rng.below(size.to_bits() as u64 - 2) as u8)
+                } else {
+                    None
+                };
+                Inst::shift_r(size, kind, num_bits, dst)
+            }
+            8 => Inst::cmp_rmi_r(size, rmi, dst.to_reg()),
+            9 => Inst::test_rmi_r(size, rmi, dst.to_reg()),
+            10 => Inst::push64(rmi),
+            11 => Inst::pop64(dst),
+            _ => {
+                let op = rng.choose(&[
+                    SseOpcode::Addps,
+                    SseOpcode::Addpd,
+                    SseOpcode::Subps,
+                    SseOpcode::Mulpd,
+                    SseOpcode::Andps,
+                    SseOpcode::Orpd,
+                    SseOpcode::Pxor,
+                    SseOpcode::Paddd,
+                    SseOpcode::Psubq,
+                    SseOpcode::Pmulld,
+                ]);
+                let src = if rng.coin() {
+                    RegMem::reg(rng.choose(&xmms))
+                } else {
+                    RegMem::mem(amode(&mut rng))
+                };
+                Inst::xmm_rm_r(op, src, Writable::from_reg(rng.choose(&xmms)))
+            }
+        };
+        insns.push(insn);
+    }
+
+    use crate::settings::Configurable;
+    let flags = settings::Flags::new(settings::builder());
+    let mut isa_flag_builder = x64::settings::builder();
+    isa_flag_builder.enable("has_ssse3").unwrap();
+    isa_flag_builder.enable("has_sse41").unwrap();
+    let isa_flags = x64::settings::Flags::new(&flags, isa_flag_builder);
+    let rru = regs::create_reg_universe_systemv(&flags);
+    let emit_info = EmitInfo::new(flags, isa_flags);
+    let cases: Vec<Case> = insns
+        .into_iter()
+        .map(|insn| {
+            let mut sink = test_utils::TestCodeSink::new();
+            let mut buffer = MachBuffer::new();
+            insn.emit(&mut buffer, &emit_info, &mut Default::default());
+            buffer.finish().emit(&mut sink);
+            Case {
+                asm: insn.show_rru(Some(&rru)),
+                encoding: sink.stringify(),
+            }
+        })
+        .collect();
+    crosscheck("x86_64", &cases);
+}
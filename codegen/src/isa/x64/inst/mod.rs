@@ -76,6 +76,12 @@ pub enum Inst {
         src: Writable<Reg>,
     },
 
+    /// Byte swap: bswap (l q) reg
+    Bswap {
+        size: OperandSize, // 4 or 8
+        src: Writable<Reg>,
+    },
+
     /// Integer quotient and remainder: (div idiv) $rax $rdx (reg addr)
     Div {
         size: OperandSize, // 1, 2, 4 or 8
@@ -146,6 +152,13 @@ pub enum Inst {
         dst: Writable<Reg>,
     },
 
+    /// A byte-swapping integer load: movbe (w l q) mem, reg
+    MovbeMR {
+        size: OperandSize, // 2, 4 or 8
+        src: SyntheticAmode,
+        dst: Writable<Reg>,
+    },
+
     /// Loads the memory address of addr into dst.
     LoadEffectiveAddress {
         addr: SyntheticAmode,
@@ -166,6 +179,13 @@ pub enum Inst {
         dst: SyntheticAmode,
     },
 
+    /// A byte-swapping integer store: movbe (w l q) reg, mem
+    MovbeRM {
+        size: OperandSize, // 2, 4 or 8
+        src: Reg,
+        dst: SyntheticAmode,
+    },
+
     /// Arithmetic shifts: (shl shr sar) (b w l q) imm reg.
     ShiftR {
         size: OperandSize, // 1, 2, 4 or 8
@@ -244,6 +264,13 @@ pub enum Inst {
         dst: Writable<Reg>,
     },
 
+    /// VEX-encoded unary op between XMM registers, writing all of the destination.
+    XmmUnaryRRVex {
+        op: AvxOpcode,
+        src: Reg,
+        dst: Writable<Reg>,
+    },
+
     /// XMM (scalar or vector) unary op (from xmm to reg/mem): stores, movd, movq
     XmmMovRM {
         op: SseOpcode,
@@ -368,6 +395,12 @@ pub enum Inst {
         opcode: Opcode,
     },
 
+    /// Direct tail call, after the epilogue: jmp simm32.
+    ReturnCallKnown { dest: ExternalName, uses: Vec<Reg> },
+
+    /// Indirect tail call, after the epilogue: jmpq *reg.
+    ReturnCallUnknown { dest: Reg, uses: Vec<Reg> },
+
     /// Return.
     Ret,
 
@@ -378,6 +411,13 @@ pub enum Inst {
     /// Jump to a known target: jmp simm32.
     JmpKnown { dst: MachLabel },
 
+    /// Jump to the normal successor of an invoke, right after its call: jmp simm32. The call
+    /// unwinds to `landing_pad` instead when it throws.
+    JmpInvoke {
+        dst: MachLabel,
+        landing_pad: MachLabel,
+    },
+
     /// One-way conditional branch: jcond cond target.
     ///
     /// This instruction is useful when we have conditional jumps depending on more than two
@@ -407,6 +447,9 @@ pub enum Inst {
         default_target: MachLabel,
         targets: Vec<MachLabel>,
         targets_for_term: Vec<MachLabel>,
+        /// When set, the table holds absolute 64-bit addresses, each relocated against this
+        /// name (the function being compiled), instead of 32-bit table-relative offsets.
+        abs_name: Option<Box<ExternalName>>,
     },
 
     /// Indirect jump: jmpq (reg mem).
@@ -448,6 +491,14 @@ pub enum Inst {
         dst: SyntheticAmode,
     },
 
+    /// A native `lock cmpxchg16b (amode)`, with register conventions:
+    ///
+    /// `dst`  (read) address, which must be 16-byte aligned
+    /// %rcx:%rbx  (read) replacement value
+    /// %rdx:%rax  (modified) in: expected value, out: value that was actually at `dst`
+    /// %rflags is written.  Do not assume anything about it after the instruction.
+    LockCmpxchg16b { dst: SyntheticAmode },
+
     /// A synthetic instruction, based on a loop around a native `lock cmpxchg` instruction.
     /// This atomically modifies a value in memory and returns the old value.  The sequence
     /// consists of an initial "normal" load from `dst`, followed by a loop which computes the
@@ -479,6 +530,22 @@ pub enum Inst {
     /// A memory fence (mfence, lfence or sfence).
     Fence { kind: FenceKind },
 
+    /// An inline stack probe loop, emitted in the prologue: it moves %rsp down by `guard_size`
+    /// at a time, storing to each page on the way, until it has covered `frame_size` bytes, and
+    /// then restores %rsp. `frame_size` must be a multiple of `guard_size`.
+    ///
+    /// %r11 is clobbered, as is %rflags.
+    StackProbeLoop { frame_size: u32, guard_size: u32 },
+
+    /// Read the time-stamp counter: EDX:EAX := TSC.
+    Rdtsc,
+
+    /// Read the protection key rights: EAX := PKRU, EDX := 0. ECX must be zero.
+    Rdpkru,
+
+    /// Write the protection key rights: PKRU := EAX. ECX and EDX must be zero.
+    Wrpkru,
+
     // =====================================
     // Meta-instructions generating no code.
     /// Marker, no-op in generated code: SP "virtual offset" is adjusted. This
@@ -503,6 +570,14 @@ pub enum Inst {
     /// of TLS symbol in rax.
     ElfTlsGetAddr { symbol: ExternalName },
 
+    /// An ELF initial-exec TLS symbol access: the thread pointer plus the
+    /// symbol's offset from it, loaded from the GOT. Returns address of the
+    /// TLS symbol in `dst`.
+    ElfTlsInitialExec {
+        symbol: ExternalName,
+        dst: Writable<Reg>,
+    },
+
     /// A Mach-O TLS symbol access. Returns address of the TLS
     /// symbol in rax.
     MachOTlsGetAddr { symbol: ExternalName },
@@ -533,6 +608,8 @@ impl Inst {
             | Inst::AtomicRmwSeq { .. }
             | Inst::CallKnown { .. }
             | Inst::CallUnknown { .. }
+            | Inst::ReturnCallKnown { .. }
+            | Inst::ReturnCallUnknown { .. }
             | Inst::CheckedDivOrRemSeq { .. }
             | Inst::Cmove { .. }
             | Inst::CmpRmiR { .. }
@@ -542,10 +619,12 @@ impl Inst {
             | Inst::Div { .. }
             | Inst::EpiloguePlaceholder
             | Inst::Fence { .. }
+            | Inst::StackProbeLoop { .. }
             | Inst::Hlt
             | Inst::Imm { .. }
             | Inst::JmpCond { .. }
             | Inst::JmpIf { .. }
+            | Inst::JmpInvoke { .. }
             | Inst::JmpKnown { .. }
             | Inst::JmpTableSeq { .. }
             | Inst::JmpUnknown { .. }
@@ -559,10 +638,13 @@ impl Inst {
             | Inst::MovzxRmR { .. }
             | Inst::MulHi { .. }
             | Inst::Neg { .. }
+            | Inst::Bswap { .. }
             | Inst::Not { .. }
             | Inst::Nop { .. }
             | Inst::Pop64 { .. }
             | Inst::Push64 { .. }
+            | Inst::Rdpkru
+            | Inst::Rdtsc
             | Inst::Ret
             | Inst::Setcc { .. }
             | Inst::ShiftR { .. }
@@ -570,16 +652,22 @@ impl Inst {
             | Inst::TrapIf { .. }
             | Inst::Ud2 { .. }
             | Inst::VirtualSPOffsetAdj { .. }
+            | Inst::Wrpkru
             | Inst::XmmCmove { .. }
             | Inst::XmmCmpRmR { .. }
             | Inst::XmmLoadConst { .. }
             | Inst::XmmMinMaxSeq { .. }
             | Inst::XmmUninitializedValue { .. }
             | Inst::ElfTlsGetAddr { .. }
+            | Inst::ElfTlsInitialExec { .. }
             | Inst::MachOTlsGetAddr { .. }
             | Inst::ValueLabelMarker { .. }
             | Inst::Unwind { .. } => smallvec![],
 
+            Inst::LockCmpxchg16b { .. } => smallvec![InstructionSet::CMPXCHG16b],
+
+            Inst::MovbeMR { .. } | Inst::MovbeRM { .. } => smallvec![InstructionSet::MOVBE],
+
             Inst::UnaryRmR { op, .. } => op.available_from(),
 
             // These use dynamic SSE opcodes.
@@ -592,6 +680,8 @@ impl Inst {
             | Inst::XmmUnaryRmR { op, .. } => smallvec![op.available_from()],
 
             Inst::XmmUnaryRmREvex { op, .. } | Inst::XmmRmREvex { op, .. } => op.available_from(),
+
+            Inst::XmmUnaryRRVex { op, .. } => op.available_from(),
         }
     }
 }
@@ -642,6 +732,12 @@ impl Inst {
         Inst::Neg { size, src }
     }
 
+    pub(crate) fn bswap(size: OperandSize, src: Writable<Reg>) -> Inst {
+        debug_assert!(size.is_one_of(&[OperandSize::Size32, OperandSize::Size64]));
+        debug_assert_eq!(src.to_reg().get_class(), RegClass::I64);
+        Inst::Bswap { size, src }
+    }
+
     pub(crate) fn div(size: OperandSize, signed: bool, divisor: RegMem) -> Inst {
         divisor.assert_regclass_is(RegClass::I64);
         Inst::Div {
@@ -732,6 +828,12 @@ impl Inst {
         Inst::XmmUnaryRmREvex { op, src, dst }
     }
 
+    pub(crate) fn xmm_unary_r_r_vex(op: AvxOpcode, src: Reg, dst: Writable<Reg>) -> Inst {
+        debug_assert!(src.get_class() == RegClass::V128);
+        debug_assert!(dst.to_reg().get_class() == RegClass::V128);
+        Inst::XmmUnaryRRVex { op, src, dst }
+    }
+
     pub(crate) fn xmm_rm_r(op: SseOpcode, src: RegMem, dst: Writable<Reg>) -> Self {
         src.assert_regclass_is(RegClass::V128);
         debug_assert!(dst.to_reg().get_class() == RegClass::V128);
@@ -942,6 +1044,24 @@ impl Inst {
         }
     }
 
+    pub(crate) fn movbe_m_r(
+        size: OperandSize,
+        src: impl Into<SyntheticAmode>,
+        dst: Writable<Reg>,
+    ) -> Inst {
+        debug_assert!(size.is_one_of(&[
+            OperandSize::Size16,
+            OperandSize::Size32,
+            OperandSize::Size64
+        ]));
+        debug_assert!(dst.to_reg().get_class() == RegClass::I64);
+        Inst::MovbeMR {
+            size,
+            src: src.into(),
+            dst,
+        }
+    }
+
     /// A convenience function to be able to use a RegMem as the source of a move.
     pub(crate) fn mov64_rm_r(src: RegMem, dst: Writable<Reg>) -> Inst {
         src.assert_regclass_is(RegClass::I64);
@@ -960,6 +1080,20 @@ impl Inst {
         }
     }
 
+    pub(crate) fn movbe_r_m(size: OperandSize, src: Reg, dst: impl Into<SyntheticAmode>) -> Inst {
+        debug_assert!(size.is_one_of(&[
+            OperandSize::Size16,
+            OperandSize::Size32,
+            OperandSize::Size64
+        ]));
+        debug_assert!(src.get_class() == RegClass::I64);
+        Inst::MovbeRM {
+            size,
+            src,
+            dst: dst.into(),
+        }
+    }
+
     pub(crate) fn lea(addr: impl Into<SyntheticAmode>, dst: Writable<Reg>) -> Inst {
         debug_assert!(dst.to_reg().get_class() == RegClass::I64);
         Inst::LoadEffectiveAddress {
@@ -1080,6 +1214,15 @@ impl Inst {
         }
     }
 
+    pub(crate) fn return_call_known(dest: ExternalName, uses: Vec<Reg>) -> Inst {
+        Inst::ReturnCallKnown { dest, uses }
+    }
+
+    pub(crate) fn return_call_unknown(dest: Reg, uses: Vec<Reg>) -> Inst {
+        debug_assert_eq!(dest.get_class(), RegClass::I64);
+        Inst::ReturnCallUnknown { dest, uses }
+    }
+
     pub(crate) fn ret() -> Inst {
         Inst::Ret
     }
@@ -1092,6 +1235,10 @@ impl Inst {
         Inst::JmpKnown { dst }
     }
 
+    pub(crate) fn jmp_invoke(dst: MachLabel, landing_pad: MachLabel) -> Inst {
+        Inst::JmpInvoke { dst, landing_pad }
+    }
+
     pub(crate) fn jmp_if(cc: CC, taken: MachLabel) -> Inst {
         Inst::JmpIf { cc, taken }
     }
@@ -1116,6 +1263,9 @@ impl Inst {
     /// Choose which instruction to use for loading a register value from memory. For loads smaller
     /// than 64 bits, this method expects a way to extend the value (i.e. [ExtKind::SignExtend],
     /// [ExtKind::ZeroExtend]); loads with no extension necessary will ignore this.
+    ///
+    /// `f16` values are loaded as 32 bits, since SSE2 has no 16-bit moves between XMM registers
+    /// and memory; this is only meant for stack slots, which are large enough.
     pub(crate) fn load(
         ty: Type,
         from_addr: impl Into<SyntheticAmode>,
@@ -1153,7 +1303,7 @@ impl Inst {
             }
             RegClass::V128 => {
                 let opcode = match ty {
-                    types::F32 => SseOpcode::Movss,
+                    types::F16 | types::F32 => SseOpcode::Movss,
                     types::F64 => SseOpcode::Movsd,
                     types::F32X4 => SseOpcode::Movups,
                     types::F64X2 => SseOpcode::Movupd,
@@ -1166,14 +1316,15 @@ impl Inst {
         }
     }
 
-    /// Choose which instruction to use for storing a register value to memory.
+    /// Choose which instruction to use for storing a register value to memory. Like loads, stores
+    /// of `f16` values write 32 bits.
     pub(crate) fn store(ty: Type, from_reg: Reg, to_addr: impl Into<SyntheticAmode>) -> Inst {
         let rc = from_reg.get_class();
         match rc {
             RegClass::I64 => Inst::mov_r_m(OperandSize::from_ty(ty), from_reg, to_addr),
             RegClass::V128 => {
                 let opcode = match ty {
-                    types::F32 => SseOpcode::Movss,
+                    types::F16 | types::F32 => SseOpcode::Movss,
                     types::F64 => SseOpcode::Movsd,
                     types::F32X4 => SseOpcode::Movups,
                     types::F64X2 => SseOpcode::Movupd,
@@ -1377,6 +1528,12 @@ impl PrettyPrint for Inst {
                 show_ireg_sized(src.to_reg(), mb_rru, size.to_bytes())
             ),
 
+            Inst::Bswap { size, src } => format!(
+                "{} {}",
+                ljustify2("bswap".to_string(), suffix_bwlq(*size)),
+                show_ireg_sized(src.to_reg(), mb_rru, size.to_bytes())
+            ),
+
             Inst::Div {
                 size,
                 signed,
@@ -1442,6 +1599,13 @@ impl PrettyPrint for Inst {
                 show_ireg_sized(dst.to_reg(), mb_rru, 8),
             ),
 
+            Inst::XmmUnaryRRVex { op, src, dst } => format!(
+                "{} {}, {}",
+                ljustify(op.to_string()),
+                show_ireg_sized(*src, mb_rru, 8),
+                show_ireg_sized(dst.to_reg(), mb_rru, 8),
+            ),
+
             Inst::XmmMovRM { op, src, dst, .. } => format!(
                 "{} {}, {}",
                 ljustify(op.to_string()),
@@ -1660,6 +1824,13 @@ impl PrettyPrint for Inst {
                 dst.show_rru(mb_rru)
             ),
 
+            Inst::MovbeMR { size, src, dst } => format!(
+                "{} {}, {}",
+                ljustify2("movbe".to_string(), suffix_bwlq(*size)),
+                src.show_rru(mb_rru),
+                show_ireg_sized(dst.to_reg(), mb_rru, size.to_bytes())
+            ),
+
             Inst::LoadEffectiveAddress { addr, dst } => format!(
                 "{} {}, {}",
                 ljustify("lea".to_string()),
@@ -1683,6 +1854,13 @@ impl PrettyPrint for Inst {
                 dst.show_rru(mb_rru)
             ),
 
+            Inst::MovbeRM { size, src, dst } => format!(
+                "{} {}, {}",
+                ljustify2("movbe".to_string(), suffix_bwlq(*size)),
+                show_ireg_sized(*src, mb_rru, size.to_bytes()),
+                dst.show_rru(mb_rru)
+            ),
+
             Inst::ShiftR {
                 size,
                 kind,
@@ -1771,6 +1949,16 @@ impl PrettyPrint for Inst {
                 dest.show_rru(mb_rru)
             ),
 
+            Inst::ReturnCallKnown { dest, .. } => {
+                format!("{} {:?}", ljustify("jmp".to_string()), dest)
+            }
+
+            Inst::ReturnCallUnknown { dest, .. } => format!(
+                "{} *{}",
+                ljustify("jmp".to_string()),
+                show_ireg_sized(*dest, mb_rru, 8)
+            ),
+
             Inst::Ret => "ret".to_string(),
 
             Inst::EpiloguePlaceholder => "epilogue placeholder".to_string(),
@@ -1779,6 +1967,13 @@ impl PrettyPrint for Inst {
                 format!("{} {}", ljustify("jmp".to_string()), dst.to_string())
             }
 
+            Inst::JmpInvoke { dst, landing_pad } => format!(
+                "{} {}; unwind {}",
+                ljustify("jmp".to_string()),
+                dst.to_string(),
+                landing_pad.to_string()
+            ),
+
             Inst::JmpIf { cc, taken } => format!(
                 "{} {}",
                 ljustify2("j".to_string(), cc.to_string()),
@@ -1830,6 +2025,10 @@ impl PrettyPrint for Inst {
                 )
             }
 
+            Inst::LockCmpxchg16b { dst } => {
+                format!("lock cmpxchg16b {}", dst.show_rru(mb_rru))
+            }
+
             Inst::AtomicRmwSeq { ty, op, .. } => {
                 format!(
                     "atomically {{ {}_bits_at_[%r9]) {:?}= %r10; %rax = old_value_at_[%r9]; %r11, %rflags = trash }}",
@@ -1842,8 +2041,22 @@ impl PrettyPrint for Inst {
                 FenceKind::SFence => "sfence".to_string(),
             },
 
+            Inst::StackProbeLoop {
+                frame_size,
+                guard_size,
+            } => format!(
+                "stack_probe_loop frame_size={}, guard_size={}; %r11, %rflags = trash",
+                frame_size, guard_size
+            ),
+
             Inst::VirtualSPOffsetAdj { offset } => format!("virtual_sp_offset_adjust {}", offset),
 
+            Inst::Rdtsc => "rdtsc".into(),
+
+            Inst::Rdpkru => "rdpkru".into(),
+
+            Inst::Wrpkru => "wrpkru".into(),
+
             Inst::Hlt => "hlt".into(),
 
             Inst::Ud2 { trap_code } => format!("ud2 {}", trap_code),
@@ -1852,6 +2065,14 @@ impl PrettyPrint for Inst {
                 format!("elf_tls_get_addr {:?}", symbol)
             }
 
+            Inst::ElfTlsInitialExec { ref symbol, dst } => {
+                format!(
+                    "elf_tls_initial_exec {:?}, {}",
+                    symbol,
+                    dst.to_reg().show_rru(mb_rru)
+                )
+            }
+
             Inst::MachOTlsGetAddr { ref symbol } => {
                 format!("macho_tls_get_addr {:?}", symbol)
             }
@@ -1893,7 +2114,7 @@ fn x64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
         Inst::Not { src, .. } => {
             collector.add_mod(*src);
         }
-        Inst::Neg { src, .. } => {
+        Inst::Neg { src, .. } | Inst::Bswap { src, .. } => {
             collector.add_mod(*src);
         }
         Inst::Div { size, divisor, .. } => {
@@ -1910,6 +2131,20 @@ fn x64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             collector.add_def(Writable::from_reg(regs::rdx()));
             rhs.get_regs_as_uses(collector);
         }
+        Inst::Rdtsc => {
+            collector.add_def(Writable::from_reg(regs::rax()));
+            collector.add_def(Writable::from_reg(regs::rdx()));
+        }
+        Inst::Rdpkru => {
+            collector.add_use(regs::rcx());
+            collector.add_def(Writable::from_reg(regs::rax()));
+            collector.add_def(Writable::from_reg(regs::rdx()));
+        }
+        Inst::Wrpkru => {
+            collector.add_use(regs::rax());
+            collector.add_use(regs::rcx());
+            collector.add_use(regs::rdx());
+        }
         Inst::CheckedDivOrRemSeq { divisor, tmp, .. } => {
             // Mark both fixed registers as mods, to avoid an early clobber problem in codegen
             // (i.e. the temporary is allocated one of the fixed registers). This requires writing
@@ -1934,6 +2169,10 @@ fn x64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             src.get_regs_as_uses(collector);
             collector.add_def(*dst);
         }
+        Inst::XmmUnaryRRVex { src, dst, .. } => {
+            collector.add_use(*src);
+            collector.add_def(*dst);
+        }
         Inst::XmmRmR { src, dst, op, .. } => {
             if inst.produces_const() {
                 // No need to account for src, since src == dst.
@@ -2048,7 +2287,9 @@ fn x64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             src.get_regs_as_uses(collector);
             collector.add_def(*dst);
         }
-        Inst::Mov64MR { src, dst, .. } | Inst::LoadEffectiveAddress { addr: src, dst } => {
+        Inst::Mov64MR { src, dst, .. }
+        | Inst::MovbeMR { src, dst, .. }
+        | Inst::LoadEffectiveAddress { addr: src, dst } => {
             src.get_regs_as_uses(collector);
             collector.add_def(*dst)
         }
@@ -2056,7 +2297,7 @@ fn x64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             src.get_regs_as_uses(collector);
             collector.add_def(*dst);
         }
-        Inst::MovRM { src, dst, .. } => {
+        Inst::MovRM { src, dst, .. } | Inst::MovbeRM { src, dst, .. } => {
             collector.add_use(*src);
             dst.get_regs_as_uses(collector);
         }
@@ -2103,6 +2344,15 @@ fn x64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             dest.get_regs_as_uses(collector);
         }
 
+        Inst::ReturnCallKnown { ref uses, .. } => {
+            collector.add_uses(uses);
+        }
+
+        Inst::ReturnCallUnknown { ref uses, dest } => {
+            collector.add_uses(uses);
+            collector.add_use(*dest);
+        }
+
         Inst::JmpTableSeq {
             ref idx,
             ref tmp1,
@@ -2128,6 +2378,14 @@ fn x64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             collector.add_mod(Writable::from_reg(regs::rax()));
         }
 
+        Inst::LockCmpxchg16b { dst } => {
+            dst.get_regs_as_uses(collector);
+            collector.add_use(regs::rbx());
+            collector.add_use(regs::rcx());
+            collector.add_mod(Writable::from_reg(regs::rax()));
+            collector.add_mod(Writable::from_reg(regs::rdx()));
+        }
+
         Inst::AtomicRmwSeq { .. } => {
             collector.add_use(regs::r9());
             collector.add_use(regs::r10());
@@ -2135,9 +2393,14 @@ fn x64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             collector.add_def(Writable::from_reg(regs::rax()));
         }
 
+        Inst::StackProbeLoop { .. } => {
+            collector.add_def(Writable::from_reg(regs::r11()));
+        }
+
         Inst::Ret
         | Inst::EpiloguePlaceholder
         | Inst::JmpKnown { .. }
+        | Inst::JmpInvoke { .. }
         | Inst::JmpIf { .. }
         | Inst::JmpCond { .. }
         | Inst::Nop { .. }
@@ -2161,6 +2424,10 @@ fn x64_get_regs(inst: &Inst, collector: &mut RegUsageCollector) {
             }
         }
 
+        Inst::ElfTlsInitialExec { dst, .. } => {
+            collector.add_def(*dst);
+        }
+
         Inst::ValueLabelMarker { reg, .. } => {
             collector.add_use(*reg);
         }
@@ -2283,7 +2550,9 @@ fn x64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
                 map_mod(mapper, dst);
             }
         }
-        Inst::Not { src, .. } | Inst::Neg { src, .. } => map_mod(mapper, src),
+        Inst::Not { src, .. } | Inst::Neg { src, .. } | Inst::Bswap { src, .. } => {
+            map_mod(mapper, src)
+        }
         Inst::Div { divisor, .. } => divisor.map_uses(mapper),
         Inst::MulHi { rhs, .. } => rhs.map_uses(mapper),
         Inst::CheckedDivOrRemSeq { divisor, tmp, .. } => {
@@ -2311,6 +2580,14 @@ fn x64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             src.map_uses(mapper);
             map_def(mapper, dst);
         }
+        Inst::XmmUnaryRRVex {
+            ref mut src,
+            ref mut dst,
+            ..
+        } => {
+            map_use(mapper, src);
+            map_def(mapper, dst);
+        }
         Inst::XmmRmRImm {
             ref op,
             ref mut src,
@@ -2462,7 +2739,9 @@ fn x64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             src.map_uses(mapper);
             map_def(mapper, dst);
         }
-        Inst::Mov64MR { src, dst, .. } | Inst::LoadEffectiveAddress { addr: src, dst } => {
+        Inst::Mov64MR { src, dst, .. }
+        | Inst::MovbeMR { src, dst, .. }
+        | Inst::LoadEffectiveAddress { addr: src, dst } => {
             src.map_uses(mapper);
             map_def(mapper, dst);
         }
@@ -2478,6 +2757,11 @@ fn x64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             ref mut src,
             ref mut dst,
             ..
+        }
+        | Inst::MovbeRM {
+            ref mut src,
+            ref mut dst,
+            ..
         } => {
             map_use(mapper, src);
             dst.map_uses(mapper);
@@ -2540,6 +2824,22 @@ fn x64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             dest.map_uses(mapper);
         }
 
+        Inst::ReturnCallKnown { ref mut uses, .. } => {
+            for r in uses.iter_mut() {
+                map_use(mapper, r);
+            }
+        }
+
+        Inst::ReturnCallUnknown {
+            ref mut uses,
+            ref mut dest,
+        } => {
+            for r in uses.iter_mut() {
+                map_use(mapper, r);
+            }
+            map_use(mapper, dest);
+        }
+
         Inst::JmpTableSeq {
             ref mut idx,
             ref mut tmp1,
@@ -2564,11 +2864,16 @@ fn x64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
             dst.map_uses(mapper);
         }
 
+        Inst::LockCmpxchg16b { ref mut dst } => dst.map_uses(mapper),
+
+        Inst::ElfTlsInitialExec { ref mut dst, .. } => map_def(mapper, dst),
+
         Inst::ValueLabelMarker { ref mut reg, .. } => map_use(mapper, reg),
 
         Inst::Ret
         | Inst::EpiloguePlaceholder
         | Inst::JmpKnown { .. }
+        | Inst::JmpInvoke { .. }
         | Inst::JmpCond { .. }
         | Inst::JmpIf { .. }
         | Inst::Nop { .. }
@@ -2577,9 +2882,13 @@ fn x64_map_regs<RUM: RegUsageMapper>(inst: &mut Inst, mapper: &RUM) {
         | Inst::Ud2 { .. }
         | Inst::Hlt
         | Inst::AtomicRmwSeq { .. }
+        | Inst::StackProbeLoop { .. }
         | Inst::ElfTlsGetAddr { .. }
         | Inst::MachOTlsGetAddr { .. }
         | Inst::Fence { .. }
+        | Inst::Rdtsc
+        | Inst::Rdpkru
+        | Inst::Wrpkru
         | Inst::Unwind { .. } => {
             // Instruction doesn't explicitly mention any regs, so it can't have any virtual
             // regs that we'd need to remap.  Hence no action required.
@@ -2640,11 +2949,22 @@ impl MachInst for Inst {
         }
     }
 
+    fn is_call(&self) -> bool {
+        match self {
+            Self::CallKnown { .. } | Self::CallUnknown { .. } => true,
+            _ => false,
+        }
+    }
+
     fn is_term<'a>(&'a self) -> MachTerminator<'a> {
         match self {
             // Interesting cases.
             &Self::Ret | &Self::EpiloguePlaceholder => MachTerminator::Ret,
+            &Self::ReturnCallKnown { .. } | &Self::ReturnCallUnknown { .. } => {
+                MachTerminator::RetCall
+            }
             &Self::JmpKnown { dst } => MachTerminator::Uncond(dst),
+            &Self::JmpInvoke { dst, landing_pad } => MachTerminator::Invoke(dst, landing_pad),
             &Self::JmpCond {
                 taken, not_taken, ..
             } => MachTerminator::Cond(taken, not_taken),
@@ -2689,7 +3009,7 @@ impl MachInst for Inst {
                 // those, which may write more lanes that we need, but are specified to have
                 // zero-latency.
                 let opcode = match ty {
-                    types::F32 | types::F64 | types::F32X4 => SseOpcode::Movaps,
+                    types::F16 | types::F32 | types::F64 | types::F32X4 => SseOpcode::Movaps,
                     types::F64X2 => SseOpcode::Movapd,
                     _ if ty.is_vector() && ty.bits() == 128 => SseOpcode::Movdqa,
                     _ => unimplemented!("unable to move type: {}", ty),
@@ -2721,6 +3041,7 @@ impl MachInst for Inst {
             types::B64 => Ok((&[RegClass::I64], &[types::B64])),
             types::R32 => panic!("32-bit reftype pointer should never be seen on x86-64"),
             types::R64 => Ok((&[RegClass::I64], &[types::R64])),
+            types::F16 => Ok((&[RegClass::V128], &[types::F16])),
             types::F32 => Ok((&[RegClass::V128], &[types::F32])),
             types::F64 => Ok((&[RegClass::V128], &[types::F64])),
             types::I128 => Ok((&[RegClass::I64, RegClass::I64], &[types::I64, types::I64])),
@@ -3013,3 +3334,81 @@ impl MachInstLabelUse for LabelUse {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::machinst::copyprop::find_redundant_moves;
+    use regalloc::InstIx;
+    use regs::*;
+
+    fn mov(dst: Reg, src: Reg) -> Inst {
+        Inst::mov_r_r(OperandSize::Size64, src, Writable::from_reg(dst))
+    }
+
+    fn add(dst: Reg, src: Reg) -> Inst {
+        Inst::alu_rmi_r(
+            OperandSize::Size64,
+            AluRmiROpcode::Add,
+            RegMemImm::reg(src),
+            Writable::from_reg(dst),
+        )
+    }
+
+    fn redundant(insns: &[Inst], block_starts: &[u32]) -> Vec<usize> {
+        let flags = settings::Flags::new(settings::builder());
+        let universe = create_reg_universe_systemv(&flags);
+        let block_starts: Vec<InstIx> = block_starts.iter().map(|&i| InstIx::new(i)).collect();
+        find_redundant_moves(insns, &block_starts, &universe)
+            .iter()
+            .enumerate()
+            .filter(|(_, &redundant)| redundant)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    #[test]
+    fn forwarded_copies() {
+        let insns = [
+            mov(rcx(), rax()),
+            mov(rdx(), rcx()),
+            // All of rax, rcx and rdx hold the same value.
+            mov(rax(), rdx()),
+            mov(rcx(), rcx()),
+            add(rsi(), rdx()),
+            // rcx is written by the add, so it no longer holds rdx's value.
+            add(rcx(), rsi()),
+            mov(rdx(), rcx()),
+            add(rsi(), rdx()),
+        ];
+        assert_eq!(redundant(&insns, &[0]), vec![2, 3]);
+    }
+
+    #[test]
+    fn overwritten_copies() {
+        let insns = [
+            mov(rcx(), rax()),
+            mov(rcx(), rdx()),
+            add(rsi(), rcx()),
+            mov(rdi(), rsi()),
+            // The add reads rdi before it writes it.
+            add(rdi(), rsi()),
+            mov(r8(), rsi()),
+            Inst::imm(OperandSize::Size64, 1, Writable::from_reg(r8())),
+            // A move at the end of a block may be read by its successors.
+            mov(r9(), rsi()),
+        ];
+        assert_eq!(redundant(&insns, &[0]), vec![0, 5]);
+    }
+
+    #[test]
+    fn block_boundaries() {
+        let insns = [
+            mov(rcx(), rax()),
+            // This is in another block, which may be entered from elsewhere.
+            mov(rax(), rcx()),
+            mov(rcx(), rax()),
+        ];
+        assert_eq!(redundant(&insns, &[0, 1]), vec![2]);
+    }
+}
@@ -18,9 +18,7 @@
 
 use crate::settings;
 use alloc::{string::String, vec::Vec};
-use regalloc::{
-    PrettyPrint, RealReg, RealRegUniverse, Reg, RegClass, RegClassInfo, NUM_REG_CLASSES,
-};
+use regalloc::{PrettyPrint, RealReg, RealRegUniverse, Reg, RegClass, RegClassInfo};
 
 // Hardware encodings (note the special rax, rcx, rdx, rbx order).
 
@@ -163,7 +161,7 @@ pub(crate) fn rbp() -> Reg {
 /// calling-convention is SystemV, at the moment.
 pub(crate) fn create_reg_universe_systemv(flags: &settings::Flags) -> RealRegUniverse {
     let mut regs = Vec::<(RealReg, String)>::new();
-    let mut allocable_by_class = [None; NUM_REG_CLASSES];
+    let mut allocable_by_class = vec![None; RegClass::V128.rc_to_usize() + 1];
 
     let use_pinned_reg = flags.enable_pinned_reg();
 
@@ -229,11 +227,13 @@ pub(crate) fn create_reg_universe_systemv(flags: &settings::Flags) -> RealRegUni
         first: first_gpr,
         last: last_gpr,
         suggested_scratch: Some(r12().get_index()),
+        spill_class: None,
     });
     allocable_by_class[RegClass::V128.rc_to_usize()] = Some(RegClassInfo {
         first: first_fpr,
         last: last_fpr,
         suggested_scratch: Some(xmm15().get_index()),
+        spill_class: None,
     });
 
     // Sanity-check: the index passed to the Reg ctor must match the order in the register list.
@@ -245,6 +245,7 @@ pub(crate) fn create_reg_universe_systemv(flags: &settings::Flags) -> RealRegUni
         regs,
         allocable,
         allocable_by_class,
+        aliases: vec![],
     }
 }
 
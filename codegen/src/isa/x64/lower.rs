@@ -5,7 +5,7 @@ use crate::{
     ir::{
         condcodes::{FloatCC, IntCC},
         types, AbiParam, ArgumentPurpose, ExternalName, Inst as IRInst, InstructionData, LibCall,
-        Opcode, Signature, Type,
+        MemoryOrdering, Opcode, Signature, TlsAccessModel, Type,
     },
     isa::{
         x64::{
@@ -16,8 +16,8 @@ use crate::{
         CallConv,
     },
     machinst::{lower::*, *},
-    result::CodegenResult,
-    settings::{Flags, TlsModel},
+    result::{CodegenError, CodegenResult},
+    settings::{Flags, JumpTableEntries, TlsModel},
 };
 use alloc::{boxed::Box, vec::Vec};
 use core::convert::TryFrom;
@@ -1234,8 +1234,8 @@ fn emit_vm_call<C: LowerCtx<I = Inst>>(
     assert_eq!(inputs.len() + vm_context, abi.num_args());
 
     for (i, input) in inputs.iter().enumerate() {
-        let arg_reg = put_input_in_reg(ctx, *input);
-        abi.emit_copy_regs_to_arg(ctx, i, ValueRegs::one(arg_reg));
+        let arg_regs = put_input_in_regs(ctx, *input);
+        abi.emit_copy_regs_to_arg(ctx, i, arg_regs);
     }
     if call_conv.extends_baldrdash() {
         let vm_context_vreg = ctx
@@ -1246,8 +1246,8 @@ fn emit_vm_call<C: LowerCtx<I = Inst>>(
 
     abi.emit_call(ctx);
     for (i, output) in outputs.iter().enumerate() {
-        let retval_reg = get_output_reg(ctx, *output).only_reg().unwrap();
-        abi.emit_copy_retval_to_regs(ctx, i, ValueRegs::one(retval_reg));
+        let retval_regs = get_output_reg(ctx, *output);
+        abi.emit_copy_retval_to_regs(ctx, i, retval_regs);
     }
     abi.emit_stack_post_adjust(ctx);
 
@@ -2522,9 +2522,33 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
         }
 
         Opcode::Ineg => {
-            let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
             let ty = ty.unwrap();
 
+            if ty == types::I128 {
+                // Subtract both halves from zero, propagating the borrow.
+                let src = put_input_in_regs(ctx, inputs[0]);
+                let dst = get_output_reg(ctx, outputs[0]);
+                assert_eq!(src.len(), 2);
+                assert_eq!(dst.len(), 2);
+
+                ctx.emit(Inst::imm(OperandSize::Size64, 0, dst.regs()[0]));
+                ctx.emit(Inst::imm(OperandSize::Size64, 0, dst.regs()[1]));
+                ctx.emit(Inst::alu_rmi_r(
+                    OperandSize::Size64,
+                    AluRmiROpcode::Sub,
+                    RegMemImm::reg(src.regs()[0]),
+                    dst.regs()[0],
+                ));
+                ctx.emit(Inst::alu_rmi_r(
+                    OperandSize::Size64,
+                    AluRmiROpcode::Sbb,
+                    RegMemImm::reg(src.regs()[1]),
+                    dst.regs()[1],
+                ));
+                return Ok(());
+            }
+
+            let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
             if ty.is_vector() {
                 // Zero's out a register and then does a packed subtraction
                 // of the input from the register.
@@ -2639,6 +2663,130 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             }
         }
 
+        Opcode::Cls => {
+            // The sign bit and every copy of it are turned into leading zeros by xoring the
+            // input with its arithmetic right shift, so:
+            // cls(x) = clz(x ^ (x >> (size_bits - 1))) - 1
+            let orig_ty = ty.unwrap();
+
+            if orig_ty == types::I128 {
+                // The sign comes from the upper half, and the two halves are then counted as for
+                // `clz`:
+                // mov upper, sign
+                // sar 63, sign
+                // xor sign, upper ; xor sign, lower
+                // clz upper, tmp1
+                // clz lower, dst
+                // add dst, 64
+                // cmp tmp1, 64
+                // cmovnz tmp1, dst
+                // sub dst, 1
+                let dsts = get_output_reg(ctx, outputs[0]);
+                let dst = dsts.regs()[0];
+                let srcs = put_input_in_regs(ctx, inputs[0]);
+                let sign = ctx.alloc_tmp(types::I64).only_reg().unwrap();
+                ctx.emit(Inst::gen_move(sign, srcs.regs()[1], types::I64));
+                ctx.emit(Inst::shift_r(
+                    OperandSize::Size64,
+                    ShiftKind::ShiftRightArithmetic,
+                    Some(63),
+                    sign,
+                ));
+                let lo = ctx.alloc_tmp(types::I64).only_reg().unwrap();
+                let hi = ctx.alloc_tmp(types::I64).only_reg().unwrap();
+                for &(half, src) in [(lo, srcs.regs()[0]), (hi, srcs.regs()[1])].iter() {
+                    ctx.emit(Inst::gen_move(half, src, types::I64));
+                    ctx.emit(Inst::alu_rmi_r(
+                        OperandSize::Size64,
+                        AluRmiROpcode::Xor,
+                        RegMemImm::reg(sign.to_reg()),
+                        half,
+                    ));
+                }
+
+                let tmp1 = ctx.alloc_tmp(types::I64).only_reg().unwrap();
+                emit_clz(ctx, types::I64, types::I64, hi.to_reg(), tmp1);
+                emit_clz(ctx, types::I64, types::I64, lo.to_reg(), dst);
+                ctx.emit(Inst::alu_rmi_r(
+                    OperandSize::Size64,
+                    AluRmiROpcode::Add,
+                    RegMemImm::imm(64),
+                    dst,
+                ));
+                ctx.emit(Inst::cmp_rmi_r(
+                    OperandSize::Size64,
+                    RegMemImm::imm(64),
+                    tmp1.to_reg(),
+                ));
+                ctx.emit(Inst::cmove(
+                    OperandSize::Size64,
+                    CC::NZ,
+                    RegMem::reg(tmp1.to_reg()),
+                    dst,
+                ));
+                ctx.emit(Inst::alu_rmi_r(
+                    OperandSize::Size64,
+                    AluRmiROpcode::Sub,
+                    RegMemImm::imm(1),
+                    dst,
+                ));
+                ctx.emit(Inst::alu_rmi_r(
+                    OperandSize::Size64,
+                    AluRmiROpcode::Xor,
+                    RegMemImm::reg(dsts.regs()[1].to_reg()),
+                    dsts.regs()[1],
+                ));
+                return Ok(());
+            }
+
+            let (ext_spec, ty) = match orig_ty {
+                types::I8 | types::I16 => (Some(ExtSpec::SignExtendTo32), types::I32),
+                a if a == types::I32 || a == types::I64 => (None, a),
+                _ => unreachable!(),
+            };
+            let src = if let Some(ext_spec) = ext_spec {
+                extend_input_to_reg(ctx, inputs[0], ext_spec)
+            } else {
+                put_input_in_reg(ctx, inputs[0])
+            };
+
+            let size = OperandSize::from_ty(ty);
+            let tmp = ctx.alloc_tmp(ty).only_reg().unwrap();
+            ctx.emit(Inst::gen_move(tmp, src, ty));
+            ctx.emit(Inst::shift_r(
+                size,
+                ShiftKind::ShiftRightArithmetic,
+                Some(ty.bits() as u8 - 1),
+                tmp,
+            ));
+            ctx.emit(Inst::alu_rmi_r(
+                size,
+                AluRmiROpcode::Xor,
+                RegMemImm::reg(src),
+                tmp,
+            ));
+
+            let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            if isa_flags.use_lzcnt() {
+                ctx.emit(Inst::unary_rm_r(
+                    size,
+                    UnaryRmROpcode::Lzcnt,
+                    RegMem::reg(tmp.to_reg()),
+                    dst,
+                ));
+            } else {
+                emit_clz(ctx, ty, ty, tmp.to_reg(), dst);
+            }
+
+            // Don't count the sign bit, nor the bits added by extending a narrow input.
+            ctx.emit(Inst::alu_rmi_r(
+                size,
+                AluRmiROpcode::Sub,
+                RegMemImm::imm(u32::from(ty.bits() - orig_ty.bits() + 1)),
+                dst,
+            ));
+        }
+
         Opcode::Ctz => {
             let orig_ty = ctx.input_ty(insn, 0);
 
@@ -3195,6 +3343,44 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             }
         }
 
+        Opcode::Bswap => {
+            let ty = ty.unwrap();
+            debug_assert!(ty == types::I16 || ty == types::I32 || ty == types::I64);
+
+            let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+
+            // With MOVBE, a byte swap of a loaded value can be done by the load itself.
+            let src = if isa_flags.use_movbe() {
+                input_to_reg_mem(ctx, inputs[0])
+            } else {
+                RegMem::reg(put_input_in_reg(ctx, inputs[0]))
+            };
+
+            match src {
+                RegMem::Mem { addr } => {
+                    ctx.emit(Inst::movbe_m_r(OperandSize::from_ty(ty), addr, dst));
+                }
+                RegMem::Reg { reg } => {
+                    ctx.emit(Inst::gen_move(dst, reg, ty));
+                    if ty == types::I64 {
+                        ctx.emit(Inst::bswap(OperandSize::Size64, dst));
+                    } else {
+                        ctx.emit(Inst::bswap(OperandSize::Size32, dst));
+                    }
+
+                    // There is no 16-bit bswap: swap the low 32 bits and shift the result down.
+                    if ty == types::I16 {
+                        ctx.emit(Inst::shift_r(
+                            OperandSize::Size32,
+                            ShiftKind::ShiftRightLogical,
+                            Some(16),
+                            dst,
+                        ));
+                    }
+                }
+            }
+        }
+
         Opcode::IsNull | Opcode::IsInvalid => {
             // Null references are represented by the constant value 0; invalid references are
             // represented by the constant value -1. See `define_reftypes()` in
@@ -3629,25 +3815,27 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             // N.B.: the Ret itself is generated by the ABI.
         }
 
-        Opcode::Call | Opcode::CallIndirect => {
+        Opcode::Call | Opcode::CallIndirect | Opcode::ReturnCall | Opcode::ReturnCallIndirect => {
             let caller_conv = ctx.abi().call_conv();
             let (mut abi, inputs) = match op {
-                Opcode::Call => {
+                Opcode::Call | Opcode::ReturnCall => {
                     let (extname, dist) = ctx.call_target(insn).unwrap();
                     let sig = ctx.call_sig(insn).unwrap();
                     assert_eq!(inputs.len(), sig.params.len());
-                    assert_eq!(outputs.len(), sig.returns.len());
+                    // Tail calls have no results of their own.
+                    assert!(op.is_tail_call() || outputs.len() == sig.returns.len());
                     (
                         X64ABICaller::from_func(sig, &extname, dist, caller_conv, flags)?,
                         &inputs[..],
                     )
                 }
 
-                Opcode::CallIndirect => {
+                Opcode::CallIndirect | Opcode::ReturnCallIndirect => {
                     let ptr = put_input_in_reg(ctx, inputs[0]);
                     let sig = ctx.call_sig(insn).unwrap();
                     assert_eq!(inputs.len() - 1, sig.params.len());
-                    assert_eq!(outputs.len(), sig.returns.len());
+                    // Tail calls have no results of their own.
+                    assert!(op.is_tail_call() || outputs.len() == sig.returns.len());
                     (
                         X64ABICaller::from_ptr(sig, ptr, op, caller_conv, flags)?,
                         &inputs[1..],
@@ -3657,6 +3845,16 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                 _ => unreachable!(),
             };
 
+            if op.is_tail_call() {
+                assert_eq!(inputs.len(), abi.num_args());
+                for i in abi.get_copy_to_arg_order() {
+                    let arg_regs = put_input_in_regs(ctx, inputs[i]);
+                    abi.emit_copy_regs_to_arg(ctx, i, arg_regs);
+                }
+                abi.emit_tail_call(ctx)?;
+                return Ok(());
+            }
+
             abi.emit_stack_pre_adjust(ctx);
             assert_eq!(inputs.len(), abi.num_args());
             for i in abi.get_copy_to_arg_order() {
@@ -4077,6 +4275,23 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             ctx.emit(Inst::xmm_unary_rm_r(sse_op, src, dst));
         }
 
+        Opcode::Fpromote if ctx.input_ty(insn, 0) == types::F16 => {
+            // Promote to f32, and then to f64 if needed; both steps are exact.
+            let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            let output_ty = ctx.output_ty(insn, 0);
+            if isa_flags.use_f16c() {
+                let src = put_input_in_reg(ctx, inputs[0]);
+                ctx.emit(Inst::xmm_unary_r_r_vex(AvxOpcode::Vcvtph2ps, src, dst));
+            } else {
+                // The libcall returns an f32 in the same register as an f64 would be.
+                emit_vm_call(ctx, flags, triple, LibCall::F16ToF32, insn, inputs, outputs)?;
+            }
+            if output_ty == types::F64 {
+                let src = RegMem::reg(dst.to_reg());
+                ctx.emit(Inst::xmm_unary_rm_r(SseOpcode::Cvtss2sd, src, dst));
+            }
+        }
+
         Opcode::Fpromote => {
             // We can't guarantee the RHS (if a load) is 128-bit aligned, so we
             // must avoid merging a load here.
@@ -4095,6 +4310,21 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             ));
         }
 
+        Opcode::Fdemote if ctx.output_ty(insn, 0) == types::F16 => {
+            if ctx.input_ty(insn, 0) == types::F32 && isa_flags.use_f16c() {
+                let src = put_input_in_reg(ctx, inputs[0]);
+                let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+                ctx.emit(Inst::xmm_unary_r_r_vex(AvxOpcode::Vcvtps2ph, src, dst));
+            } else {
+                // F16C can't convert from f64, and demoting through f32 would round twice.
+                let libcall = match ctx.input_ty(insn, 0) {
+                    types::F32 => LibCall::F32ToF16,
+                    _ => LibCall::F64ToF16,
+                };
+                emit_vm_call(ctx, flags, triple, libcall, insn, inputs, outputs)?;
+            }
+        }
+
         Opcode::Fdemote => {
             // We can't guarantee the RHS (if a load) is 128-bit aligned, so we
             // must avoid merging a load here.
@@ -4661,6 +4891,27 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                         dst,
                     ));
                 }
+                (types::F16, types::I16) => {
+                    let src = put_input_in_reg(ctx, inputs[0]);
+                    let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+                    ctx.emit(Inst::xmm_to_gpr(
+                        SseOpcode::Movd,
+                        src,
+                        dst,
+                        OperandSize::Size32,
+                    ));
+                }
+                (types::I16, types::F16) => {
+                    // Don't merge a load of the input, which would read 32 bits.
+                    let src = RegMem::reg(put_input_in_reg(ctx, inputs[0]));
+                    let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+                    ctx.emit(Inst::gpr_to_xmm(
+                        SseOpcode::Movd,
+                        src,
+                        OperandSize::Size32,
+                        dst,
+                    ));
+                }
                 _ => unreachable!("invalid bitcast from {:?} to {:?}", input_ty, output_ty),
             }
         }
@@ -4968,6 +5219,17 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                 let dsts = get_output_reg(ctx, outputs[0]);
                 ctx.emit(Inst::mov64_m_r(amode.clone(), dsts.regs()[0]));
                 ctx.emit(Inst::mov64_m_r(amode.offset(8), dsts.regs()[1]));
+            } else if elem_ty == types::F16 {
+                // SSE2 has no 16-bit loads into XMM registers, so go through a GPR.
+                let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+                let tmp = ctx.alloc_tmp(types::I32).only_reg().unwrap();
+                ctx.emit(Inst::movzx_rm_r(ExtMode::WL, RegMem::mem(amode), tmp));
+                ctx.emit(Inst::gpr_to_xmm(
+                    SseOpcode::Movd,
+                    RegMem::reg(tmp.to_reg()),
+                    OperandSize::Size32,
+                    dst,
+                ));
             } else {
                 let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
                 let is_xmm = elem_ty.is_float() || elem_ty.is_vector();
@@ -5073,6 +5335,31 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
                 let srcs = put_input_in_regs(ctx, inputs[0]);
                 ctx.emit(Inst::store(types::I64, srcs.regs()[0], addr.clone()));
                 ctx.emit(Inst::store(types::I64, srcs.regs()[1], addr.offset(8)));
+            } else if elem_ty == types::F16 {
+                // Like loads, go through a GPR to only write 16 bits.
+                let src = put_input_in_reg(ctx, inputs[0]);
+                let tmp = ctx.alloc_tmp(types::I32).only_reg().unwrap();
+                ctx.emit(Inst::xmm_to_gpr(
+                    SseOpcode::Movd,
+                    src,
+                    tmp,
+                    OperandSize::Size32,
+                ));
+                ctx.emit(Inst::store(types::I16, tmp.to_reg(), addr));
+            } else if let (Opcode::Store, true, Some(bswap)) = (
+                op,
+                isa_flags.use_movbe(),
+                matches_input(ctx, inputs[0], Opcode::Bswap),
+            ) {
+                // Store the byte-swapped value directly with MOVBE.
+                let src = put_input_in_reg(
+                    ctx,
+                    InsnInput {
+                        insn: bswap,
+                        input: 0,
+                    },
+                );
+                ctx.emit(Inst::movbe_r_m(OperandSize::from_ty(elem_ty), src, addr));
             } else {
                 let src = put_input_in_reg(ctx, inputs[0]);
                 ctx.emit(Inst::store(elem_ty, src, addr));
@@ -5123,6 +5410,59 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             ctx.emit(Inst::gen_move(dst, regs::rax(), types::I64));
         }
 
+        Opcode::AtomicCas if ty == Some(types::I128) => {
+            if !isa_flags.use_cmpxchg16b() {
+                return emit_vm_call(
+                    ctx,
+                    flags,
+                    triple,
+                    LibCall::AtomicCasI128,
+                    insn,
+                    inputs,
+                    outputs,
+                );
+            }
+
+            let dst = get_output_reg(ctx, outputs[0]);
+            let addr = lower_to_amode(ctx, inputs[0], 0);
+            let expected = put_input_in_regs(ctx, inputs[1]);
+            let replacement = put_input_in_regs(ctx, inputs[2]);
+
+            // Make sure that all four halves are in virtual regs, since in effect we have to do
+            // a parallel copy to get them safely to the `LockCmpxchg16b` input regs.  See the
+            // corresponding comment for `Opcode::AtomicRmw` above.
+            let expected_lo = ctx.ensure_in_vreg(expected.regs()[0], types::I64);
+            let expected_hi = ctx.ensure_in_vreg(expected.regs()[1], types::I64);
+            let replacement_lo = ctx.ensure_in_vreg(replacement.regs()[0], types::I64);
+            let replacement_hi = ctx.ensure_in_vreg(replacement.regs()[1], types::I64);
+
+            // Move the expected value into %rdx:%rax and the replacement into %rcx:%rbx.
+            ctx.emit(Inst::gen_move(
+                Writable::from_reg(regs::rax()),
+                expected_lo,
+                types::I64,
+            ));
+            ctx.emit(Inst::gen_move(
+                Writable::from_reg(regs::rdx()),
+                expected_hi,
+                types::I64,
+            ));
+            ctx.emit(Inst::gen_move(
+                Writable::from_reg(regs::rbx()),
+                replacement_lo,
+                types::I64,
+            ));
+            ctx.emit(Inst::gen_move(
+                Writable::from_reg(regs::rcx()),
+                replacement_hi,
+                types::I64,
+            ));
+            ctx.emit(Inst::LockCmpxchg16b { dst: addr.into() });
+            // And finally, copy the old value at the location to its destination regs.
+            ctx.emit(Inst::gen_move(dst.regs()[0], regs::rax(), types::I64));
+            ctx.emit(Inst::gen_move(dst.regs()[1], regs::rdx(), types::I64));
+        }
+
         Opcode::AtomicCas => {
             // This is very similar to, but not identical to, the `AtomicRmw` case.  As with
             // `AtomicRmw`, there's no need to zero-extend narrow values here.
@@ -5186,9 +5526,14 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
         }
 
         Opcode::Fence => {
-            ctx.emit(Inst::Fence {
-                kind: FenceKind::MFence,
-            });
+            // The x86-TSO memory model only allows a store to be reordered after a later load,
+            // so only a sequentially consistent fence needs an instruction.
+            match ctx.data(insn).memory_ordering().unwrap() {
+                MemoryOrdering::Acquire | MemoryOrdering::Release | MemoryOrdering::AcqRel => {}
+                MemoryOrdering::SeqCst => ctx.emit(Inst::Fence {
+                    kind: FenceKind::MFence,
+                }),
+            }
         }
 
         Opcode::FuncAddr => {
@@ -5230,7 +5575,10 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             ctx.emit(inst);
         }
 
-        Opcode::Select => {
+        Opcode::Select | Opcode::SelectSpectreGuard => {
+            // `select_spectre_guard` only accepts integer and reference values, so it never
+            // reaches the XMM paths below and is always lowered to `cmov`.
+            debug_assert!(op == Opcode::Select || is_int_or_ref_ty(ty.unwrap()));
             let flag_input = inputs[0];
             if let Some(fcmp) = matches_input(ctx, flag_input, Opcode::Fcmp) {
                 let cond_code = ctx.data(fcmp).fp_cond_code().unwrap();
@@ -5400,6 +5748,27 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             }
         }
 
+        Opcode::Udiv | Opcode::Urem | Opcode::Sdiv | Opcode::Srem
+            if ctx.input_ty(insn, 0) == types::I128 =>
+        {
+            // There's no 128-bit division instruction; call the runtime library instead, which
+            // takes and returns 128-bit values in pairs of registers.
+            if !flags.enable_llvm_abi_extensions() {
+                return Err(CodegenError::Unsupported(format!(
+                    "{} of 128-bit integers requires `enable_llvm_abi_extensions`",
+                    op
+                )));
+            }
+            let libcall = match op {
+                Opcode::Udiv => LibCall::UdivI128,
+                Opcode::Sdiv => LibCall::SdivI128,
+                Opcode::Urem => LibCall::UremI128,
+                Opcode::Srem => LibCall::SremI128,
+                _ => unreachable!(),
+            };
+            emit_vm_call(ctx, flags, triple, libcall, insn, inputs, outputs)?;
+        }
+
         Opcode::Udiv | Opcode::Urem | Opcode::Sdiv | Opcode::Srem => {
             let kind = match op {
                 Opcode::Udiv => DivOrRemKind::UnsignedDiv,
@@ -5526,6 +5895,47 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             ctx.emit(Inst::gen_move(dst, regs::pinned_reg(), types::I64));
         }
 
+        Opcode::GetCycleCounter => {
+            // rdtsc leaves the low half of the counter in %eax and the high half in %edx, both
+            // zero-extended to 64 bits.
+            let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            let hi = ctx.alloc_tmp(types::I64).only_reg().unwrap();
+            ctx.emit(Inst::Rdtsc);
+            ctx.emit(Inst::gen_move(hi, regs::rdx(), types::I64));
+            ctx.emit(Inst::gen_move(dst, regs::rax(), types::I64));
+            ctx.emit(Inst::shift_r(
+                OperandSize::Size64,
+                ShiftKind::ShiftLeft,
+                Some(32),
+                hi,
+            ));
+            ctx.emit(Inst::alu_rmi_r(
+                OperandSize::Size64,
+                AluRmiROpcode::Or,
+                RegMemImm::reg(hi.to_reg()),
+                dst,
+            ));
+        }
+
+        Opcode::StackUsed => {
+            // The return address and the frame pointer of the caller sit right above the frame
+            // pointer.
+            let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            ctx.emit(Inst::gen_move(dst, regs::rbp(), types::I64));
+            ctx.emit(Inst::alu_rmi_r(
+                OperandSize::Size64,
+                AluRmiROpcode::Sub,
+                RegMemImm::reg(regs::rsp()),
+                dst,
+            ));
+            ctx.emit(Inst::alu_rmi_r(
+                OperandSize::Size64,
+                AluRmiROpcode::Add,
+                RegMemImm::imm(16),
+                dst,
+            ));
+        }
+
         Opcode::SetPinnedReg => {
             let src = put_input_in_reg(ctx, inputs[0]);
             ctx.emit(Inst::gen_move(
@@ -5535,6 +5945,32 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             ));
         }
 
+        Opcode::GetPkeyRights => {
+            // rdpkru requires %ecx to be zero, and zero-extends PKRU into %rax.
+            let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            ctx.emit(Inst::imm(
+                OperandSize::Size32,
+                0,
+                Writable::from_reg(regs::rcx()),
+            ));
+            ctx.emit(Inst::Rdpkru);
+            ctx.emit(Inst::gen_move(dst, regs::rax(), types::I64));
+        }
+
+        Opcode::SetPkeyRights => {
+            // wrpkru takes the new rights in %eax, and requires %ecx and %edx to be zero.
+            let src = put_input_in_reg(ctx, inputs[0]);
+            ctx.emit(Inst::gen_move(
+                Writable::from_reg(regs::rax()),
+                src,
+                types::I64,
+            ));
+            for &reg in &[regs::rcx(), regs::rdx()] {
+                ctx.emit(Inst::imm(OperandSize::Size32, 0, Writable::from_reg(reg)));
+            }
+            ctx.emit(Inst::Wrpkru);
+        }
+
         Opcode::Vconst => {
             let used_constant = if let &InstructionData::UnaryConst {
                 constant_handle, ..
@@ -5953,28 +6389,31 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
             ctx.emit(Inst::gen_move(dst_hi, src.regs()[1], types::I64));
         }
 
-        Opcode::TlsValue => match flags.tls_model() {
-            TlsModel::ElfGd => {
-                let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
-                let (name, _, _) = ctx.symbol_value(insn).unwrap();
-                let symbol = name.clone();
-                ctx.emit(Inst::ElfTlsGetAddr { symbol });
-                ctx.emit(Inst::gen_move(dst, regs::rax(), types::I64));
-            }
-            TlsModel::Macho => {
-                let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
-                let (name, _, _) = ctx.symbol_value(insn).unwrap();
-                let symbol = name.clone();
-                ctx.emit(Inst::MachOTlsGetAddr { symbol });
-                ctx.emit(Inst::gen_move(dst, regs::rax(), types::I64));
-            }
-            _ => {
-                todo!(
-                    "Unimplemented TLS model in x64 backend: {:?}",
-                    flags.tls_model()
-                );
+        Opcode::TlsValue => {
+            let dst = get_output_reg(ctx, outputs[0]).only_reg().unwrap();
+            let (name, model) = ctx.tls_value(insn).unwrap();
+            let symbol = name.clone();
+            match (flags.tls_model(), model) {
+                (TlsModel::ElfGd, Some(TlsAccessModel::InitialExec)) => {
+                    ctx.emit(Inst::ElfTlsInitialExec { symbol, dst });
+                }
+                (TlsModel::ElfGd, _) => {
+                    ctx.emit(Inst::ElfTlsGetAddr { symbol });
+                    ctx.emit(Inst::gen_move(dst, regs::rax(), types::I64));
+                }
+                // Mach-O has a single TLS access model, which works for all symbols.
+                (TlsModel::Macho, _) => {
+                    ctx.emit(Inst::MachOTlsGetAddr { symbol });
+                    ctx.emit(Inst::gen_move(dst, regs::rax(), types::I64));
+                }
+                _ => {
+                    todo!(
+                        "Unimplemented TLS model in x64 backend: {:?}",
+                        flags.tls_model()
+                    );
+                }
             }
-        },
+        }
 
         Opcode::IaddImm
         | Opcode::ImulImm
@@ -6010,6 +6449,64 @@ fn lower_insn_to_regs<C: LowerCtx<I = Inst>>(
     Ok(())
 }
 
+/// Lower the call of an `invoke` or `invoke_indirect`, ending the block with a jump to `normal`
+/// from which the call may unwind to `landing_pad` instead.
+fn lower_invoke<C: LowerCtx<I = Inst>>(
+    ctx: &mut C,
+    insn: IRInst,
+    normal: MachLabel,
+    landing_pad: MachLabel,
+    flags: &Flags,
+) -> CodegenResult<()> {
+    let op = ctx.data(insn).opcode();
+    let inputs: SmallVec<[InsnInput; 4]> = (0..ctx.num_inputs(insn))
+        .map(|i| InsnInput { insn, input: i })
+        .collect();
+    let outputs: SmallVec<[InsnOutput; 2]> = (0..ctx.num_outputs(insn))
+        .map(|i| InsnOutput { insn, output: i })
+        .collect();
+
+    let caller_conv = ctx.abi().call_conv();
+    let num_args = ctx.call_sig(insn).unwrap().params.len();
+    assert_eq!(outputs.len(), ctx.call_sig(insn).unwrap().returns.len());
+    // The call arguments come first, followed by the landing pad arguments, which are passed on
+    // the exceptional edge.
+    let (mut abi, inputs) = match op {
+        Opcode::Invoke => {
+            let (extname, dist) = ctx.call_target(insn).unwrap();
+            let sig = ctx.call_sig(insn).unwrap();
+            (
+                X64ABICaller::from_func(sig, &extname, dist, caller_conv, flags)?,
+                &inputs[..num_args],
+            )
+        }
+        Opcode::InvokeIndirect => {
+            let ptr = put_input_in_reg(ctx, inputs[0]);
+            let sig = ctx.call_sig(insn).unwrap();
+            (
+                X64ABICaller::from_ptr(sig, ptr, op, caller_conv, flags)?,
+                &inputs[1..1 + num_args],
+            )
+        }
+        _ => unreachable!(),
+    };
+
+    assert_eq!(inputs.len(), abi.num_args());
+    for i in abi.get_copy_to_arg_order() {
+        let arg_regs = put_input_in_regs(ctx, inputs[i]);
+        abi.emit_copy_regs_to_arg(ctx, i, arg_regs);
+    }
+    abi.emit_invoke(ctx)?;
+    // The return values are only copied out when the callee returns; an unwinding callee
+    // continues at the landing pad directly.
+    for (i, output) in outputs.iter().enumerate() {
+        let retval_regs = get_output_reg(ctx, *output);
+        abi.emit_copy_retval_to_regs(ctx, i, retval_regs);
+    }
+    ctx.emit(Inst::jmp_invoke(normal, landing_pad));
+    Ok(())
+}
+
 //=============================================================================
 // Lowering-backend trait implementation.
 
@@ -6252,6 +6749,10 @@ impl LowerBackend for X64Backend {
                     }
                 }
 
+                Opcode::Invoke | Opcode::InvokeIndirect => {
+                    lower_invoke(ctx, branches[0], not_taken, taken, &self.flags)?;
+                }
+
                 _ => panic!("unexpected branch opcode: {:?}", op0),
             }
         } else {
@@ -6312,6 +6813,12 @@ impl LowerBackend for X64Backend {
 
                     let jt_targets: Vec<MachLabel> = targets.iter().skip(1).cloned().collect();
 
+                    // Absolute entries are relocated against the function's own symbol.
+                    let abs_name = match self.flags.jump_table_entries() {
+                        JumpTableEntries::Relative => None,
+                        JumpTableEntries::Absolute => Some(Box::new(ctx.func_name().clone())),
+                    };
+
                     ctx.emit(Inst::JmpTableSeq {
                         idx,
                         tmp1,
@@ -6319,6 +6826,7 @@ impl LowerBackend for X64Backend {
                         default_target,
                         targets: jt_targets,
                         targets_for_term,
+                        abs_name,
                     });
                 }
 
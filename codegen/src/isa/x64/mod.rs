@@ -4,12 +4,15 @@ use self::inst::EmitInfo;
 
 use super::TargetIsa;
 use crate::{
-    ir::{condcodes::IntCC, Function},
+    ir::{condcodes::IntCC, Function, Opcode, Type},
     isa::{
+        generic_inst_cost,
         x64::{inst::regs::create_reg_universe_systemv, settings as x64_settings},
-        Builder as IsaBuilder,
+        Builder as IsaBuilder, InstCost,
+    },
+    machinst::{
+        compile, stub, MachBackend, MachCompileResult, StubAssembler, TargetIsaAdapter, VCode,
     },
-    machinst::{compile, MachBackend, MachCompileResult, TargetIsaAdapter, VCode},
     result::CodegenResult,
     settings::{self as shared_settings, Flags},
 };
@@ -68,6 +71,7 @@ impl MachBackend for X64Backend {
         let (buffer, bb_starts, bb_edges) = vcode.emit();
         let buffer = buffer.finish();
         let frame_size = vcode.frame_size();
+        let stats = vcode.stats();
         let value_labels_ranges = vcode.value_labels_ranges();
         let stackslot_offsets = vcode.stackslot_offsets().clone();
 
@@ -80,6 +84,7 @@ impl MachBackend for X64Backend {
         Ok(MachCompileResult {
             buffer,
             frame_size,
+            stats,
             disasm,
             value_labels_ranges,
             stackslot_offsets,
@@ -88,6 +93,21 @@ impl MachBackend for X64Backend {
         })
     }
 
+    fn compile_stub(
+        &self,
+        stub: &StubAssembler,
+        want_disasm: bool,
+    ) -> CodegenResult<MachCompileResult> {
+        let emit_info = EmitInfo::new(self.flags.clone(), self.x64_flags.clone());
+        stub::compile_stub::<abi::X64ABIMachineSpec>(
+            stub,
+            &self.flags,
+            &emit_info,
+            &self.reg_universe,
+            want_disasm,
+        )
+    }
+
     fn flags(&self) -> &Flags {
         &self.flags
     }
@@ -113,6 +133,43 @@ impl MachBackend for X64Backend {
         &self.reg_universe
     }
 
+    fn zero_extends_32bit_ops(&self) -> bool {
+        true
+    }
+
+    fn inst_cost(&self, opcode: Opcode, ctrl_typevar: Type) -> InstCost {
+        // Numbers for recent Intel and AMD cores.  Integer divisions need their dividend in
+        // RDX:RAX and a check of the divisor, and 64-bit ones are much slower than 32-bit ones.
+        let wide = ctrl_typevar.lane_bits() > 32;
+        match opcode {
+            Opcode::Udiv | Opcode::Urem | Opcode::UdivImm | Opcode::UremImm if wide => {
+                InstCost::new(40, 25, 12)
+            }
+            Opcode::Sdiv | Opcode::Srem | Opcode::SdivImm | Opcode::SremImm if wide => {
+                InstCost::new(45, 25, 24)
+            }
+            Opcode::Udiv | Opcode::Urem | Opcode::UdivImm | Opcode::UremImm => {
+                InstCost::new(26, 6, 10)
+            }
+            Opcode::Sdiv | Opcode::Srem | Opcode::SdivImm | Opcode::SremImm => {
+                InstCost::new(26, 6, 22)
+            }
+            Opcode::Fdiv if wide => InstCost::new(14, 4, 4),
+            Opcode::Fdiv => InstCost::new(11, 3, 4),
+            Opcode::Sqrt if wide => InstCost::new(18, 6, 4),
+            Opcode::Sqrt => InstCost::new(12, 3, 4),
+            Opcode::Load | Opcode::LoadComplex => InstCost::new(5, 1, 4),
+            _ => {
+                // Variable-length encodings make most other instructions shorter than the
+                // generic 4 bytes.
+                InstCost {
+                    size: 3,
+                    ..generic_inst_cost(opcode, ctrl_typevar)
+                }
+            }
+        }
+    }
+
     fn unsigned_add_overflow_condition(&self) -> IntCC {
         // Unsigned `<`; this corresponds to the carry flag set on x86, which
         // indicates an add has overflowed.
@@ -138,6 +195,7 @@ impl MachBackend for X64Backend {
                 Some(UnwindInfo::SystemV(
                     crate::isa::unwind::systemv::create_unwind_info_from_insts(
                         &result.buffer.unwind_info[..],
+                        result.buffer.exception_handlers(),
                         result.buffer.data.len(),
                         &mapper,
                     )?,
@@ -17,7 +17,7 @@ use crate::{
     },
     isa::{CallConv, RegClass, RegUnit, TargetIsa},
     regalloc::RegisterSet,
-    result::CodegenResult,
+    result::{CodegenError, CodegenResult},
     stack_layout::layout_stack,
 };
 use alloc::borrow::Cow;
@@ -641,7 +641,7 @@ fn fastcall_prologue_epilogue(func: &mut ir::Function, isa: &dyn TargetIsa) -> C
         &csrs,
         sp_arg_index.is_some(),
         isa,
-    );
+    )?;
 
     // Reset the cursor and insert the epilogue
     let mut pos = pos.at_position(CursorPosition::Nowhere);
@@ -719,7 +719,7 @@ fn system_v_prologue_epilogue(func: &mut ir::Function, isa: &dyn TargetIsa) -> C
         &csrs,
         sp_arg_index.is_some(),
         isa,
-    );
+    )?;
 
     // Reset the cursor and insert the epilogue
     let mut pos = pos.at_position(CursorPosition::Nowhere);
@@ -737,7 +737,7 @@ fn insert_common_prologue(
     csrs: &RegisterSet,
     has_sp_param: bool,
     isa: &dyn TargetIsa,
-) {
+) -> CodegenResult<()> {
     let sp = if has_sp_param {
         let block = pos.current_block().expect("missing block under cursor");
         let sp = pos.func.dfg.append_block_param(block, reg_type);
@@ -802,6 +802,14 @@ fn insert_common_prologue(
     if stack_size > 0 {
         if isa.flags().enable_probestack() && stack_size > (1 << isa.flags().probestack_size_log2())
         {
+            // Only the new backends know how to emit an inline probe loop.
+            if isa.flags().probestack_strategy() == shared_settings::ProbestackStrategy::Inline {
+                return Err(CodegenError::Unsupported(format!(
+                    "inline stack probes on the legacy {} backend",
+                    isa.name()
+                )));
+            }
+
             // Emit a stack probe.
             let rax = RU::rax as RegUnit;
             let rax_val = ir::ValueLoc::Reg(rax);
@@ -871,6 +879,8 @@ fn insert_common_prologue(
             .or(last_csr_push)
             .unwrap_or(mov_sp_inst),
     );
+
+    Ok(())
 }
 
 /// Inserts code necessary to calculate `gv`.
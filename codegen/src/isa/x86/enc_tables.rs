@@ -18,7 +18,7 @@ use crate::{
         encoding::{base_size, Encoding, RecipeSizing},
         RegUnit, TargetIsa,
     },
-    legalizer::expand_as_libcall,
+    legalizer::{expand_as_libcall, narrow_shift},
     predicates,
     regalloc::RegDiversions,
 };
@@ -1666,7 +1666,7 @@ static USHR_MASKS: [u8; 128] = [
 fn convert_ushr(
     inst: ir::Inst,
     func: &mut ir::Function,
-    _cfg: &mut ControlFlowGraph,
+    cfg: &mut ControlFlowGraph,
     isa: &dyn TargetIsa,
 ) {
     let mut pos = FuncCursor::new(func).at_inst(inst);
@@ -1682,6 +1682,12 @@ fn convert_ushr(
         let arg1_type = pos.func.dfg.value_type(arg1);
         assert!(!arg1_type.is_vector() && arg1_type.is_int());
 
+        // 128-bit shifts are split into shifts of the two halves.
+        if arg0_type == I128 {
+            narrow_shift(inst, func, cfg, isa);
+            return;
+        }
+
         // TODO it may be more clear to use scalar_to_vector here; the current issue is that
         // scalar_to_vector has the restriction that the vector produced has a matching lane size
         // (e.g. i32 -> i32x4) whereas bitcast allows moving any-to-any conversions (e.g. i32 ->
@@ -1750,7 +1756,7 @@ static SHL_MASKS: [u8; 128] = [
 fn convert_ishl(
     inst: ir::Inst,
     func: &mut ir::Function,
-    _cfg: &mut ControlFlowGraph,
+    cfg: &mut ControlFlowGraph,
     isa: &dyn TargetIsa,
 ) {
     let mut pos = FuncCursor::new(func).at_inst(inst);
@@ -1766,6 +1772,12 @@ fn convert_ishl(
         let arg1_type = pos.func.dfg.value_type(arg1);
         assert!(!arg1_type.is_vector() && arg1_type.is_int());
 
+        // 128-bit shifts are split into shifts of the two halves.
+        if arg0_type == I128 {
+            narrow_shift(inst, func, cfg, isa);
+            return;
+        }
+
         // TODO it may be more clear to use scalar_to_vector here; the current issue is that
         // scalar_to_vector has the restriction that the vector produced has a matching lane size
         // (e.g. i32 -> i32x4) whereas bitcast allows moving any-to-any conversions (e.g. i32 ->
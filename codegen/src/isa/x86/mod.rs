@@ -100,6 +100,10 @@ impl TargetIsa for Isa {
         true
     }
 
+    fn zero_extends_32bit_ops(&self) -> bool {
+        self.pointer_bits() == 64
+    }
+
     fn register_info(&self) -> RegInfo {
         registers::INFO.clone()
     }
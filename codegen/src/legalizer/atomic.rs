@@ -0,0 +1,195 @@
+//! Legalization of atomic memory operations for single-threaded targets.
+//!
+//! This module exports the `expand_atomic` function which, when the `single_threaded` setting is
+//! enabled, transforms atomic instructions into plain loads and stores. The memory flags of the
+//! atomic instruction are kept, so that the expanded accesses trap in the same way.
+
+use crate::{
+    cursor::{Cursor, FuncCursor},
+    flowgraph::ControlFlowGraph,
+    ir::{self, condcodes::IntCC, AtomicRmwOp, InstBuilder},
+    isa::TargetIsa,
+};
+
+/// Is `opcode` one of the atomic instructions that `expand_atomic` handles?
+pub fn is_atomic(opcode: ir::Opcode) -> bool {
+    match opcode {
+        ir::Opcode::AtomicRmw
+        | ir::Opcode::AtomicCas
+        | ir::Opcode::AtomicLoad
+        | ir::Opcode::AtomicStore
+        | ir::Opcode::Fence => true,
+        _ => false,
+    }
+}
+
+/// Expand an atomic instruction into its non-atomic equivalent.
+pub fn expand_atomic(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &dyn TargetIsa,
+) {
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    match pos.func.dfg[inst] {
+        ir::InstructionData::AtomicRmw {
+            opcode: ir::Opcode::AtomicRmw,
+            args: [p, x],
+            flags,
+            op,
+        } => {
+            // The instruction becomes the load of the old value, which is its result.
+            let old = pos.func.dfg.first_result(inst);
+            let ty = pos.func.dfg.value_type(old);
+            pos.func.dfg.replace(inst).load(ty, flags, p, 0);
+            pos.goto_after_inst(inst);
+            let new = match op {
+                AtomicRmwOp::Add => pos.ins().iadd(old, x),
+                AtomicRmwOp::Sub => pos.ins().isub(old, x),
+                AtomicRmwOp::And => pos.ins().band(old, x),
+                AtomicRmwOp::Nand => {
+                    let and = pos.ins().band(old, x);
+                    pos.ins().bnot(and)
+                }
+                AtomicRmwOp::Or => pos.ins().bor(old, x),
+                AtomicRmwOp::Xor => pos.ins().bxor(old, x),
+                AtomicRmwOp::Xchg => x,
+                AtomicRmwOp::Umin => select_by(&mut pos, IntCC::UnsignedLessThan, old, x),
+                AtomicRmwOp::Umax => select_by(&mut pos, IntCC::UnsignedGreaterThan, old, x),
+                AtomicRmwOp::Smin => select_by(&mut pos, IntCC::SignedLessThan, old, x),
+                AtomicRmwOp::Smax => select_by(&mut pos, IntCC::SignedGreaterThan, old, x),
+            };
+            pos.ins().store(flags, new, p, 0);
+        }
+        ir::InstructionData::AtomicCas {
+            opcode: ir::Opcode::AtomicCas,
+            args: [p, expected, replacement],
+            flags,
+        } => {
+            // The location is always written, as with most hardware compare-and-swap
+            // instructions, so that a read-only location traps whatever the comparison yields.
+            let old = pos.func.dfg.first_result(inst);
+            let ty = pos.func.dfg.value_type(old);
+            pos.func.dfg.replace(inst).load(ty, flags, p, 0);
+            pos.goto_after_inst(inst);
+            let equal = pos.ins().icmp(IntCC::Equal, old, expected);
+            let new = pos.ins().select(equal, replacement, old);
+            pos.ins().store(flags, new, p, 0);
+        }
+        ir::InstructionData::LoadNoOffset {
+            opcode: ir::Opcode::AtomicLoad,
+            arg,
+            flags,
+        } => {
+            let ty = pos.func.dfg.value_type(pos.func.dfg.first_result(inst));
+            pos.func.dfg.replace(inst).load(ty, flags, arg, 0);
+        }
+        ir::InstructionData::StoreNoOffset {
+            opcode: ir::Opcode::AtomicStore,
+            args: [x, p],
+            flags,
+        } => {
+            pos.func.dfg.replace(inst).store(flags, x, p, 0);
+        }
+        ir::InstructionData::Fence {
+            opcode: ir::Opcode::Fence,
+            ..
+        } => {
+            pos.remove_inst();
+        }
+        _ => panic!(
+            "Expected an atomic instruction: {}",
+            pos.func.dfg.display_inst(inst, None)
+        ),
+    }
+}
+
+/// Select `a` if `a cond b` holds, and `b` otherwise.
+fn select_by(pos: &mut FuncCursor, cond: IntCC, a: ir::Value, b: ir::Value) -> ir::Value {
+    let take_a = pos.ins().icmp(cond, a, b);
+    pos.ins().select(take_a, a, b)
+}
+
+#[cfg(all(test, feature = "x86"))]
+mod tests {
+    use super::super::simple_legalize;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::flowgraph::ControlFlowGraph;
+    use crate::ir::{
+        types, AbiParam, AtomicRmwOp, Function, InstBuilder, MemFlags, MemoryOrdering, Opcode,
+    };
+    use crate::isa;
+    use crate::settings::{self, Configurable};
+    use crate::verifier::verify_function;
+    use alloc::vec::Vec;
+    use target_lexicon::triple;
+
+    #[test]
+    fn single_threaded_atomics() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let p = func.dfg.append_block_param(block0, types::I64);
+        let x = func.dfg.append_block_param(block0, types::I32);
+        func.signature.params.push(AbiParam::new(types::I64));
+        func.signature.params.push(AbiParam::new(types::I32));
+        func.signature.returns.push(AbiParam::new(types::I32));
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            let flags = MemFlags::trusted();
+            let a = cur.ins().atomic_load(types::I32, flags, p);
+            let b = cur
+                .ins()
+                .atomic_rmw(types::I32, flags, AtomicRmwOp::Umax, p, x);
+            let c = cur.ins().atomic_cas(flags, p, a, b);
+            cur.ins().fence(MemoryOrdering::SeqCst);
+            cur.ins().atomic_store(flags, c, p);
+            cur.ins().return_(&[c]);
+        }
+
+        let opcodes = |func: &Function| -> Vec<Opcode> {
+            let block = func.layout.entry_block().unwrap();
+            func.layout
+                .block_insts(block)
+                .map(|inst| func.dfg[inst].opcode())
+                .collect()
+        };
+        let legalize = |func: &mut Function, single_threaded: &str| {
+            let mut flag_builder = settings::builder();
+            flag_builder
+                .set("single_threaded", single_threaded)
+                .unwrap();
+            let isa = isa::lookup(triple!("x86_64"))
+                .unwrap()
+                .finish(settings::Flags::new(flag_builder));
+            let mut cfg = ControlFlowGraph::with_function(func);
+            simple_legalize(func, &mut cfg, &*isa);
+            verify_function(&*func, &*isa).unwrap();
+        };
+
+        // Atomics are left alone by default.
+        let mut atomic = func.clone();
+        legalize(&mut atomic, "false");
+        assert_eq!(opcodes(&atomic), opcodes(&func));
+
+        legalize(&mut func, "true");
+        assert_eq!(
+            opcodes(&func),
+            [
+                Opcode::Load,
+                Opcode::Load,
+                Opcode::Icmp,
+                Opcode::Select,
+                Opcode::Store,
+                Opcode::Load,
+                Opcode::Icmp,
+                Opcode::Select,
+                Opcode::Store,
+                Opcode::Store,
+                Opcode::Return,
+            ]
+        );
+    }
+}
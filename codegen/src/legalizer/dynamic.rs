@@ -0,0 +1,46 @@
+//! Legalization of dynamic vector types.
+//!
+//! This module exports the `fix_dynamic_vectors` function, which replaces the dynamic vector
+//! types of a function by the SIMD vector types of their size on the target, when the target's
+//! `vscale` is known at compile time.
+
+use crate::ir::{self, Type};
+use crate::isa::TargetIsa;
+use alloc::vec::Vec;
+
+/// Replace every dynamic vector type in `func` by the SIMD vector type with as many lanes on
+/// `isa`: the type of values, and the types of parameters and return values of signatures.
+///
+/// The operations on dynamic vectors are the same as on SIMD vectors of their size, so the
+/// instructions themselves don't change.
+pub(super) fn fix_dynamic_vectors(func: &mut ir::Function, isa: &dyn TargetIsa) {
+    let vscale = match isa.vscale() {
+        Some(vscale) => vscale,
+        None => return,
+    };
+    let fixed = |ty: Type| -> Option<Type> {
+        let vector = ty.dynamic_to_vector()?;
+        Some(vector.by(vscale).unwrap_or_else(|| {
+            panic!("{} is too large with a vscale of {}", ty, vscale);
+        }))
+    };
+
+    let values: Vec<ir::Value> = func.dfg.values().collect();
+    for value in values {
+        if let Some(ty) = fixed(func.dfg.value_type(value)) {
+            func.dfg.change_value_type(value, ty);
+        }
+    }
+
+    let fix_signature = |sig: &mut ir::Signature| {
+        for param in sig.params.iter_mut().chain(sig.returns.iter_mut()) {
+            if let Some(ty) = fixed(param.value_type) {
+                param.value_type = ty;
+            }
+        }
+    };
+    fix_signature(&mut func.signature);
+    for sig in func.dfg.signatures.values_mut() {
+        fix_signature(sig);
+    }
+}
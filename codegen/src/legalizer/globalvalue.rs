@@ -2,14 +2,69 @@
 //!
 //! This module exports the `expand_global_value` function which transforms a `global_value`
 //! instruction into code that depends on the kind of global value referenced.
+//!
+//! Chains of `iadd_imm` global values are folded into the offset of the global value they
+//! start from, so that e.g. a field of a structure pointed to by the VM context is a single
+//! load. The legalizers also keep a `GlobalValueCache`, so that a global value used several
+//! times in a block is only computed once there.
 
 use crate::{
     cursor::{Cursor, FuncCursor},
+    entity::{packed_option::PackedOption, SecondaryMap},
     flowgraph::ControlFlowGraph,
-    ir::{self, InstBuilder},
+    ir::{self, immediates::Imm64, InstBuilder},
     isa::TargetIsa,
 };
 
+/// The values of the `global_value` instructions legalized so far, for reuse by the later
+/// instructions of the same block.
+pub(super) struct GlobalValueCache {
+    values: SecondaryMap<ir::GlobalValue, PackedOption<ir::Value>>,
+}
+
+impl GlobalValueCache {
+    pub fn new() -> Self {
+        Self {
+            values: SecondaryMap::new(),
+        }
+    }
+
+    /// If `inst` is a `global_value` instruction, and an earlier one of its block computes the
+    /// same global value, replace its result with the value of the earlier one, remove it, and
+    /// return true. Otherwise remember the result of `inst` if it is a `global_value`
+    /// instruction, and return false.
+    ///
+    /// Global values don't change while the function executes, so this is always correct.
+    pub fn reuse(&mut self, inst: ir::Inst, func: &mut ir::Function) -> bool {
+        let gv = match func.dfg[inst] {
+            ir::InstructionData::UnaryGlobalValue {
+                opcode: ir::Opcode::GlobalValue,
+                global_value,
+            } => global_value,
+            _ => return false,
+        };
+        let result = func.dfg.first_result(inst);
+
+        if let Some(value) = self.values[gv].expand() {
+            let value = func.dfg.resolve_aliases(value);
+            let block = func.layout.inst_block(inst);
+            let def_block = match func.dfg.value_def(value) {
+                ir::ValueDef::Result(def, _) => func.layout.inst_block(def),
+                ir::ValueDef::Param(def, _) => Some(def),
+            };
+            if value != result && def_block == block {
+                func.dfg.clear_results(inst);
+                func.dfg.change_to_alias(result, value);
+                func.layout.remove_inst(inst);
+                return true;
+            }
+        }
+
+        self.values[gv] = result.into();
+        false
+    }
+}
+
 /// Expand a `global_value` instruction according to the definition of the global value.
 pub fn expand_global_value(
     inst: ir::Inst,
@@ -35,7 +90,7 @@ pub fn expand_global_value(
             base,
             offset,
             global_type,
-        } => iadd_imm_addr(inst, func, base, offset.into(), global_type),
+        } => iadd_imm_addr(inst, func, base, offset, global_type),
         ir::GlobalValueData::Load {
             base,
             offset,
@@ -43,9 +98,21 @@ pub fn expand_global_value(
             readonly,
         } => load_addr(inst, func, base, offset, global_type, readonly, isa),
         ir::GlobalValueData::Symbol { tls, .. } => symbol(inst, func, gv, isa, tls),
+        ir::GlobalValueData::TlsSymbol { .. } => symbol(inst, func, gv, isa, true),
+        ir::GlobalValueData::VScale { global_type } => vscale(inst, func, global_type, isa),
     }
 }
 
+/// Expand a `global_value` instruction for the vscale global.
+fn vscale(inst: ir::Inst, func: &mut ir::Function, global_type: ir::Type, isa: &dyn TargetIsa) {
+    let vscale = isa
+        .vscale()
+        .expect("vscale is only known at run time on this target");
+    func.dfg
+        .replace(inst)
+        .iconst(global_type, i64::from(vscale));
+}
+
 /// Expand a `global_value` instruction for a vmctx global.
 fn vmctx_addr(inst: ir::Inst, func: &mut ir::Function) {
     // Get the value representing the `vmctx` argument.
@@ -65,9 +132,39 @@ fn iadd_imm_addr(
     inst: ir::Inst,
     func: &mut ir::Function,
     base: ir::GlobalValue,
-    offset: i64,
+    offset: Imm64,
     global_type: ir::Type,
 ) {
+    // Fold the offsets of the chain of iadd_imm globals this one starts. The additions wrap
+    // around at the width of `global_type`, so their order doesn't matter.
+    let mut base = base;
+    let mut offset: i64 = offset.into();
+    while let ir::GlobalValueData::IAddImm {
+        base: next,
+        offset: next_offset,
+        ..
+    } = func.global_values[base]
+    {
+        offset = offset.wrapping_add(next_offset.into());
+        base = next;
+    }
+
+    // Fold the offset into the symbol the chain starts from, if any.
+    if let ir::GlobalValueData::Symbol {
+        ref name,
+        offset: symbol_offset,
+        colocated,
+        tls: false,
+    } = func.global_values[base]
+    {
+        let name = name.clone();
+        let symbol_offset: i64 = symbol_offset.into();
+        let offset = Imm64::new(symbol_offset.wrapping_add(offset));
+        let symbol = symbol_global_value(func, name, offset, colocated);
+        func.dfg.replace(inst).symbol_value(global_type, symbol);
+        return;
+    }
+
     let mut pos = FuncCursor::new(func).at_inst(inst);
 
     // Get the value for the lhs. For tidiness, expand VMContext here so that we avoid
@@ -84,6 +181,35 @@ fn iadd_imm_addr(
     pos.func.dfg.replace(inst).iadd_imm(lhs, offset);
 }
 
+/// The non-TLS symbol global value of `func` for `name` and `offset`, created if there is none.
+fn symbol_global_value(
+    func: &mut ir::Function,
+    name: ir::ExternalName,
+    offset: Imm64,
+    colocated: bool,
+) -> ir::GlobalValue {
+    let existing = func.global_values.iter().find(|(_, data)| match data {
+        ir::GlobalValueData::Symbol {
+            name: existing_name,
+            offset: existing_offset,
+            colocated: existing_colocated,
+            tls: false,
+        } => {
+            *existing_name == name && *existing_offset == offset && *existing_colocated == colocated
+        }
+        _ => false,
+    });
+    match existing {
+        Some((gv, _)) => gv,
+        None => func.create_global_value(ir::GlobalValueData::Symbol {
+            name,
+            offset,
+            colocated,
+            tls: false,
+        }),
+    }
+}
+
 /// Expand a `global_value` instruction for a load global.
 fn load_addr(
     inst: ir::Inst,
@@ -101,6 +227,23 @@ fn load_addr(
     let mut pos = FuncCursor::new(func).at_inst(inst);
     pos.use_srcloc(inst);
 
+    // Fold the offsets of a chain of iadd_imm globals into the offset of the load, as far as it
+    // fits.
+    let mut base = base;
+    let mut offset = offset;
+    while let ir::GlobalValueData::IAddImm {
+        base: next,
+        offset: next_offset,
+        ..
+    } = pos.func.global_values[base]
+    {
+        match offset.try_add_i64(next_offset.into()) {
+            Some(folded) => offset = folded,
+            None => break,
+        }
+        base = next;
+    }
+
     // Get the value for the base. For tidiness, expand VMContext here so that we avoid
     // `vmctx_addr` which creates an otherwise unneeded value alias.
     let base_addr = if let ir::GlobalValueData::VMContext = pos.func.global_values[base] {
@@ -140,3 +283,127 @@ fn symbol(
         func.dfg.replace(inst).symbol_value(ptr_ty, gv);
     }
 }
+
+#[cfg(all(test, feature = "x86"))]
+mod tests {
+    use super::super::simple_legalize;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::flowgraph::ControlFlowGraph;
+    use crate::ir::immediates::{Imm64, Offset32};
+    use crate::ir::{
+        types, AbiParam, ArgumentPurpose, ExternalName, Function, GlobalValueData, InstBuilder,
+        InstructionData, Opcode,
+    };
+    use crate::isa;
+    use crate::settings;
+    use crate::verifier::verify_function;
+    use alloc::vec::Vec;
+    use target_lexicon::triple;
+
+    #[test]
+    fn fold_and_reuse() {
+        let mut func = Function::new();
+        let vmctx = func.create_global_value(GlobalValueData::VMContext);
+        let base = func.create_global_value(GlobalValueData::Load {
+            base: vmctx,
+            offset: Offset32::new(8),
+            global_type: types::I64,
+            readonly: true,
+        });
+        let field = func.create_global_value(GlobalValueData::IAddImm {
+            base,
+            offset: Imm64::new(16),
+            global_type: types::I64,
+        });
+        let subfield = func.create_global_value(GlobalValueData::IAddImm {
+            base: field,
+            offset: Imm64::new(4),
+            global_type: types::I64,
+        });
+        let value = func.create_global_value(GlobalValueData::Load {
+            base: subfield,
+            offset: Offset32::new(8),
+            global_type: types::I32,
+            readonly: true,
+        });
+        let symbol = func.create_global_value(GlobalValueData::Symbol {
+            name: ExternalName::testcase("data"),
+            offset: Imm64::new(8),
+            colocated: false,
+            tls: false,
+        });
+        let symbol_field = func.create_global_value(GlobalValueData::IAddImm {
+            base: symbol,
+            offset: Imm64::new(8),
+            global_type: types::I64,
+        });
+
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        func.dfg.append_block_param(block0, types::I64);
+        func.signature
+            .params
+            .push(AbiParam::special(types::I64, ArgumentPurpose::VMContext));
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            cur.ins().global_value(types::I32, value);
+            cur.ins().global_value(types::I32, value);
+            cur.ins().global_value(types::I64, symbol_field);
+            cur.ins().global_value(types::I64, subfield);
+            cur.ins().jump(block1, &[]);
+            cur.insert_block(block1);
+            cur.ins().global_value(types::I32, value);
+            cur.ins().return_(&[]);
+        }
+
+        let isa = isa::lookup(triple!("x86_64"))
+            .unwrap()
+            .finish(settings::Flags::new(settings::builder()));
+        let mut cfg = ControlFlowGraph::with_function(&func);
+        simple_legalize(&mut func, &mut cfg, &*isa);
+        verify_function(&func, &*isa).unwrap();
+
+        let opcodes = |block| -> Vec<Opcode> {
+            func.layout
+                .block_insts(block)
+                .map(|inst| func.dfg[inst].opcode())
+                .collect()
+        };
+        // The load of the base is shared by the first three global values, and the offsets of
+        // the iadd_imm chains are folded into the loads and symbols.
+        assert_eq!(
+            opcodes(block0),
+            [
+                Opcode::Load,
+                Opcode::Load,
+                Opcode::SymbolValue,
+                Opcode::Iconst,
+                Opcode::Iadd,
+                Opcode::Jump,
+            ]
+        );
+        assert_eq!(
+            opcodes(block1),
+            [Opcode::Load, Opcode::Load, Opcode::Return]
+        );
+
+        for inst in func.layout.block_insts(block0) {
+            match func.dfg[inst] {
+                InstructionData::Load { offset, .. } => {
+                    assert!(offset == Offset32::new(8) || offset == Offset32::new(28))
+                }
+                InstructionData::UnaryGlobalValue { global_value, .. } => {
+                    match func.global_values[global_value] {
+                        GlobalValueData::Symbol { offset, .. } => {
+                            assert_eq!(offset, Imm64::new(16))
+                        }
+                        _ => panic!("symbol_value of a non-symbol"),
+                    }
+                }
+                InstructionData::UnaryImm { imm, .. } => assert_eq!(imm, Imm64::new(20)),
+                _ => {}
+            }
+        }
+    }
+}
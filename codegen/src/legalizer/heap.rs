@@ -8,14 +8,19 @@ use crate::{
     flowgraph::ControlFlowGraph,
     ir::{self, condcodes::IntCC, InstBuilder},
     isa::TargetIsa,
+    value_range::ValueRanges,
 };
 
 /// Expand a `heap_addr` instruction according to the definition of the heap.
+///
+/// `ranges` is shared by all the `heap_addr` instructions of the function, so that the ranges of
+/// the offsets are only computed once per legalization run.
 pub fn expand_heap_addr(
     inst: ir::Inst,
     func: &mut ir::Function,
     cfg: &mut ControlFlowGraph,
     isa: &dyn TargetIsa,
+    ranges: &mut ValueRanges,
 ) {
     // Unpack the instruction.
     let (heap, offset, access_size) = match func.dfg[inst] {
@@ -32,9 +37,28 @@ pub fn expand_heap_addr(
     };
 
     match func.heaps[heap].style {
-        ir::HeapStyle::Dynamic { bound_gv } => {
-            dynamic_addr(isa, inst, heap, offset, access_size, bound_gv, func)
-        }
+        ir::HeapStyle::Dynamic { bound_gv } => dynamic_addr(
+            isa,
+            inst,
+            heap,
+            offset,
+            access_size,
+            bound_gv,
+            false,
+            func,
+            ranges,
+        ),
+        ir::HeapStyle::DynamicBounded { bound_gv } => dynamic_addr(
+            isa,
+            inst,
+            heap,
+            offset,
+            access_size,
+            bound_gv,
+            true,
+            func,
+            ranges,
+        ),
         ir::HeapStyle::Static { bound } => static_addr(
             isa,
             inst,
@@ -44,11 +68,15 @@ pub fn expand_heap_addr(
             bound.into(),
             func,
             cfg,
+            ranges,
         ),
     }
 }
 
 /// Expand a `heap_addr` for a dynamic heap.
+///
+/// When `mask_index` is set, the offset is masked to zero on the out-of-bounds path instead of
+/// relying on the Spectre mitigation setting to select a null address.
 fn dynamic_addr(
     isa: &dyn TargetIsa,
     inst: ir::Inst,
@@ -56,15 +84,30 @@ fn dynamic_addr(
     offset: ir::Value,
     access_size: u32,
     bound_gv: ir::GlobalValue,
+    mask_index: bool,
     func: &mut ir::Function,
+    ranges: &mut ValueRanges,
 ) {
     let access_size = u64::from(access_size);
     let offset_ty = func.dfg.value_type(offset);
     let addr_ty = func.dfg.value_type(func.dfg.first_result(inst));
     let min_size = func.heaps[heap].min_size.into();
+    let offset_max = ranges
+        .get(&func.dfg, offset)
+        .map_or(u64::MAX, |range| range.umax());
     let mut pos = FuncCursor::new(func).at_inst(inst);
     pos.use_srcloc(inst);
 
+    // We know that `bound >= min_size`, so there is nothing to check if the whole access is
+    // within `min_size` for any possible value of the offset.
+    if offset_max
+        .checked_add(access_size)
+        .map_or(false, |end| end <= min_size)
+    {
+        compute_addr(isa, inst, heap, addr_ty, offset, offset_ty, pos.func, None);
+        return;
+    }
+
     // Start with the bounds check. Trap if `offset + access_size > bound`.
     let bound = pos.ins().global_value(offset_ty, bound_gv);
     let (cc, lhs, bound) = if access_size == 1 {
@@ -89,6 +132,24 @@ fn dynamic_addr(
     let oob = pos.ins().icmp(cc, lhs, bound);
     pos.ins().trapnz(oob, ir::TrapCode::HeapOutOfBounds);
 
+    if mask_index {
+        // The heap may have no guard pages and no faulting null page, so a speculatively
+        // executed access must not leave the heap: use index 0 instead of the out-of-bounds one.
+        let zero = pos.ins().iconst(offset_ty, 0);
+        let masked_offset = pos.ins().select_spectre_guard(oob, zero, offset);
+        compute_addr(
+            isa,
+            inst,
+            heap,
+            addr_ty,
+            masked_offset,
+            offset_ty,
+            pos.func,
+            None,
+        );
+        return;
+    }
+
     let spectre_oob_comparison = if isa.flags().enable_heap_access_spectre_mitigation() {
         Some((cc, lhs, bound))
     } else {
@@ -117,10 +178,14 @@ fn static_addr(
     bound: u64,
     func: &mut ir::Function,
     cfg: &mut ControlFlowGraph,
+    ranges: &mut ValueRanges,
 ) {
     let access_size = u64::from(access_size);
     let offset_ty = func.dfg.value_type(offset);
     let addr_ty = func.dfg.value_type(func.dfg.first_result(inst));
+    let offset_max = ranges
+        .get(&func.dfg, offset)
+        .map_or(u64::MAX, |range| range.umax());
     let mut pos = FuncCursor::new(func).at_inst(inst);
     pos.use_srcloc(inst);
 
@@ -145,19 +210,13 @@ fn static_addr(
     // if `offset > bound - access_size`. We know `bound - access_size` here is
     // non-negative from the above comparison.
     //
-    // If we can know `bound - access_size >= 4GB` then with a 32-bit offset
-    // we're guaranteed:
-    //
-    //      bound - access_size >= 4GB > offset
-    //
-    // or, in other words, `offset < bound - access_size`, meaning we can't trap
-    // for any value of `offset`.
-    //
-    // With that we have an optimization here where with 32-bit offsets and
-    // `bound - access_size >= 4GB` we can omit a bounds check.
+    // If the value range of `offset` tells us that `offset <= bound - access_size`
+    // for any value of `offset`, we can't trap and can omit the bounds check. This
+    // is notably the case of 32-bit offsets into heaps with
+    // `bound - access_size >= 4GB`.
     let limit = bound - access_size;
     let mut spectre_oob_comparison = None;
-    if offset_ty != ir::types::I32 || limit < 0xffff_ffff {
+    if offset_max > limit {
         let (cc, lhs, limit_imm) = if limit & 1 == 1 {
             // Prefer testing `offset >= limit - 1` when limit is odd because an even number is
             // likely to be a convenient constant on ARM and other RISC architectures.
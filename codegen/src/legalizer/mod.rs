@@ -20,10 +20,11 @@ use crate::{
     flowgraph::ControlFlowGraph,
     ir::{
         self,
-        types::{I32, I64},
+        types::{I128, I32, I64},
         InstBuilder, MemFlags,
     },
     isa::TargetIsa,
+    value_range::ValueRanges,
 };
 
 #[cfg(any(feature = "x86", feature = "riscv"))]
@@ -34,18 +35,29 @@ use alloc::vec::Vec;
 use crate::timing;
 use alloc::collections::BTreeSet;
 
+mod atomic;
 mod boundary;
 mod call;
+mod dynamic;
 mod globalvalue;
 mod heap;
 mod libcall;
+mod shift;
 mod split;
 mod table;
 
 #[cfg(any(feature = "x86", feature = "riscv"))]
 use self::call::expand_call;
 pub(crate) use self::libcall::expand_as_libcall;
-use self::{globalvalue::expand_global_value, heap::expand_heap_addr, table::expand_table_addr};
+pub(crate) use self::shift::narrow_shift;
+use self::{
+    atomic::expand_atomic,
+    dynamic::fix_dynamic_vectors,
+    globalvalue::{expand_global_value, GlobalValueCache},
+    heap::expand_heap_addr,
+    shift::narrow_rotate,
+    table::expand_table_addr,
+};
 
 enum LegalizeInstResult {
     Done,
@@ -59,6 +71,7 @@ fn legalize_inst(
     pos: &mut FuncCursor,
     cfg: &mut ControlFlowGraph,
     isa: &dyn TargetIsa,
+    ranges: &mut ValueRanges,
 ) -> LegalizeInstResult {
     let opcode = pos.func.dfg[inst].opcode();
 
@@ -73,6 +86,12 @@ fn legalize_inst(
         }
     } else if opcode.is_branch() {
         split::simplify_branch_arguments(&mut pos.func.dfg, inst);
+    } else if atomic::is_atomic(opcode) && isa.flags().single_threaded() {
+        expand_atomic(inst, pos.func, cfg, isa);
+        return LegalizeInstResult::Legalized;
+    } else if opcode == ir::Opcode::HeapAddr {
+        expand_heap_addr(inst, pos.func, cfg, isa, ranges);
+        return LegalizeInstResult::Legalized;
     } else if opcode == ir::Opcode::Isplit {
         pos.use_srcloc(inst);
 
@@ -150,6 +169,7 @@ pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, is
     let _tt = timing::legalize();
     debug_assert!(cfg.is_valid());
 
+    fix_dynamic_vectors(func, isa);
     boundary::legalize_signatures(func, isa);
 
     func.encodings.resize(func.dfg.num_insts());
@@ -168,6 +188,9 @@ pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, is
     // This must be a set to prevent trying to legalize `isplit` and `vsplit` twice in certain cases.
     let mut pending_splits = BTreeSet::new();
 
+    let mut global_values = GlobalValueCache::new();
+    let mut ranges = ValueRanges::new();
+
     // Process blocks in layout order. Some legalization actions may split the current block or append
     // new ones to the end. We need to make sure we visit those new blocks too.
     while let Some(_block) = pos.next_block() {
@@ -176,7 +199,13 @@ pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, is
         let mut prev_pos = pos.position();
 
         while let Some(inst) = pos.next_inst() {
-            match legalize_inst(inst, &mut pos, cfg, isa) {
+            // Reuse the value of an earlier `global_value` instruction of the block.
+            if global_values.reuse(inst, pos.func) {
+                pos.set_position(prev_pos);
+                continue;
+            }
+
+            match legalize_inst(inst, &mut pos, cfg, isa, &mut ranges) {
                 // Remember this position in case we need to double back.
                 LegalizeInstResult::Done => prev_pos = pos.position(),
 
@@ -196,7 +225,7 @@ pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, is
     // Try legalizing `isplit` and `vsplit` instructions, which could not previously be legalized.
     for inst in pending_splits {
         pos.goto_inst(inst);
-        legalize_inst(inst, &mut pos, cfg, isa);
+        legalize_inst(inst, &mut pos, cfg, isa, &mut ranges);
     }
 
     // Now that we've lowered all br_tables, we don't need the jump tables anymore.
@@ -243,12 +272,23 @@ pub fn simple_legalize(func: &mut ir::Function, cfg: &mut ControlFlowGraph, isa:
         }};
     }
 
+    fix_dynamic_vectors(func, isa);
+
+    let mut global_values = GlobalValueCache::new();
+    let mut ranges = ValueRanges::new();
+
     let mut pos = FuncCursor::new(func);
     let func_begin = pos.position();
     pos.set_position(func_begin);
     while let Some(_block) = pos.next_block() {
         let mut prev_pos = pos.position();
         while let Some(inst) = pos.next_inst() {
+            // Reuse the value of an earlier `global_value` instruction of the block.
+            if global_values.reuse(inst, pos.func) {
+                pos.set_position(prev_pos);
+                continue;
+            }
+
             match pos.func.dfg[inst].opcode() {
                 // control flow
                 ir::Opcode::BrIcmp => expand_br_icmp(inst, &mut pos.func, cfg, isa),
@@ -258,10 +298,15 @@ pub fn simple_legalize(func: &mut ir::Function, cfg: &mut ControlFlowGraph, isa:
 
                 // memory and constants
                 ir::Opcode::GlobalValue => expand_global_value(inst, &mut pos.func, cfg, isa),
-                ir::Opcode::HeapAddr => expand_heap_addr(inst, &mut pos.func, cfg, isa),
+                ir::Opcode::HeapAddr => {
+                    expand_heap_addr(inst, &mut pos.func, cfg, isa, &mut ranges)
+                }
                 ir::Opcode::StackLoad => expand_stack_load(inst, &mut pos.func, cfg, isa),
                 ir::Opcode::StackStore => expand_stack_store(inst, &mut pos.func, cfg, isa),
                 ir::Opcode::TableAddr => expand_table_addr(inst, &mut pos.func, cfg, isa),
+                opcode if atomic::is_atomic(opcode) && isa.flags().single_threaded() => {
+                    expand_atomic(inst, &mut pos.func, cfg, isa)
+                }
 
                 // bitops
                 ir::Opcode::BandImm => expand_imm_op!(pos, inst: band_imm => band),
@@ -598,6 +643,49 @@ fn expand_select(
     cfg.recompute_block(pos.func, old_block);
 }
 
+/// Expand the select_spectre_guard instruction.
+///
+/// Unlike `select`, this must never be turned into a branch: the controlling value is compared
+/// against zero instead, and the flags feed a `selectif_spectre_guard` which is encoded as a
+/// conditional move.
+fn expand_select_spectre_guard(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &dyn TargetIsa,
+) {
+    use crate::ir::condcodes::IntCC;
+
+    let (ctrl, tval, fval) = match func.dfg[inst] {
+        ir::InstructionData::Ternary {
+            opcode: ir::Opcode::SelectSpectreGuard,
+            args,
+        } => (args[0], args[1], args[2]),
+        _ => panic!(
+            "Expected select_spectre_guard: {}",
+            func.dfg.display_inst(inst, None)
+        ),
+    };
+    let ty = func.dfg.ctrl_typevar(inst);
+
+    // Replace `result = select_spectre_guard ctrl, tval, fval` with:
+    //
+    //   flags = ifcmp_imm ctrl, 0
+    //   result = selectif_spectre_guard ne flags, tval, fval
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+    let ctrl = if pos.func.dfg.value_type(ctrl).is_bool() {
+        pos.ins().bint(ir::types::I32, ctrl)
+    } else {
+        ctrl
+    };
+    let flags = pos.ins().ifcmp_imm(ctrl, 0);
+    pos.func
+        .dfg
+        .replace(inst)
+        .selectif_spectre_guard(ty, IntCC::NotEqual, flags, tval, fval);
+}
+
 fn expand_br_icmp(
     inst: ir::Inst,
     func: &mut ir::Function,
@@ -826,6 +914,13 @@ fn narrow_iconst(
         pos.func.dfg.replace(inst).iconcat(low, high);
         return;
     }
+    if isa.pointer_bits() == 64 && ty == I128 {
+        // The immediate is zero-extended, like the constants of the other backends.
+        let low = pos.ins().iconst(I64, imm);
+        let high = pos.ins().iconst(I64, 0);
+        pos.func.dfg.replace(inst).iconcat(low, high);
+        return;
+    }
 
     unimplemented!("missing encoding or legalization for iconst.{:?}", ty);
 }
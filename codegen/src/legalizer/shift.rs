@@ -0,0 +1,126 @@
+//! Narrowing of shifts and rotates.
+//!
+//! This module exports the `narrow_shift` and `narrow_rotate` functions, which legalize shifts and
+//! rotates of integers twice as wide as a register into operations on the two halves.
+
+use crate::{
+    cursor::{Cursor, FuncCursor},
+    flowgraph::ControlFlowGraph,
+    ir::{self, condcodes::IntCC, InstBuilder},
+    isa::TargetIsa,
+};
+
+/// Narrow an `ishl`, `ushr` or `sshr` by splitting the shifted value into halves.
+///
+/// The sequence is branch-free: the halves are shifted by the amount modulo the half width, and
+/// the results are selected depending on whether the amount crosses the middle of the value.
+pub fn narrow_shift(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &dyn TargetIsa,
+) {
+    let (opcode, arg, amount) = match func.dfg[inst] {
+        ir::InstructionData::Binary { opcode, args } => (opcode, args[0], args[1]),
+        _ => panic!("unexpected instruction in narrow_shift"),
+    };
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    let ty = pos.func.dfg.ctrl_typevar(inst);
+    let ty_half = ty.half_width().expect("Can't narrow shift");
+    let half_bits = i64::from(ty_half.bits());
+
+    let amount = half_width_amount(&mut pos, amount, ty_half);
+    let amount = pos.ins().band_imm(amount, 2 * half_bits - 1);
+    let crosses_half = pos
+        .ins()
+        .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, amount, half_bits);
+    // Shifting by `!amount` shifts by `half_bits - 1 - amount` modulo the half width; shifting
+    // one more bit beforehand completes the shift by `half_bits - amount`, without ever shifting
+    // by the full half width.
+    let inverse = pos.ins().bnot(amount);
+    let (xl, xh) = pos.ins().isplit(arg);
+
+    let (lo, hi) = match opcode {
+        ir::Opcode::Ishl => {
+            let lo = pos.ins().ishl(xl, amount);
+            let hi = pos.ins().ishl(xh, amount);
+            let carry = pos.ins().ushr_imm(xl, 1);
+            let carry = pos.ins().ushr(carry, inverse);
+            let hi = pos.ins().bor(hi, carry);
+            let zero = pos.ins().iconst(ty_half, 0);
+            (
+                pos.ins().select(crosses_half, zero, lo),
+                pos.ins().select(crosses_half, lo, hi),
+            )
+        }
+        ir::Opcode::Ushr | ir::Opcode::Sshr => {
+            let lo = pos.ins().ushr(xl, amount);
+            let (hi, fill) = if opcode == ir::Opcode::Ushr {
+                (pos.ins().ushr(xh, amount), pos.ins().iconst(ty_half, 0))
+            } else {
+                (
+                    pos.ins().sshr(xh, amount),
+                    pos.ins().sshr_imm(xh, half_bits - 1),
+                )
+            };
+            let carry = pos.ins().ishl_imm(xh, 1);
+            let carry = pos.ins().ishl(carry, inverse);
+            let lo = pos.ins().bor(lo, carry);
+            (
+                pos.ins().select(crosses_half, hi, lo),
+                pos.ins().select(crosses_half, fill, hi),
+            )
+        }
+        _ => panic!("unexpected opcode in narrow_shift: {}", opcode),
+    };
+    pos.func.dfg.replace(inst).iconcat(lo, hi);
+}
+
+/// Narrow a `rotl` or `rotr` into a pair of shifts of the full value, which are then narrowed by
+/// `narrow_shift`.
+pub fn narrow_rotate(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &dyn TargetIsa,
+) {
+    let (opcode, arg, amount) = match func.dfg[inst] {
+        ir::InstructionData::Binary { opcode, args } => (opcode, args[0], args[1]),
+        _ => panic!("unexpected instruction in narrow_rotate"),
+    };
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    let ty = pos.func.dfg.ctrl_typevar(inst);
+    let ty_half = ty.half_width().expect("Can't narrow rotate");
+
+    // Shift amounts are taken modulo the width of the value, so shifting by the negated amount
+    // shifts by the rest of the width, and rotating by zero ORs the value with itself.
+    let amount = half_width_amount(&mut pos, amount, ty_half);
+    let rest = pos.ins().ineg(amount);
+    let (left, right) = match opcode {
+        ir::Opcode::Rotl => (amount, rest),
+        ir::Opcode::Rotr => (rest, amount),
+        _ => panic!("unexpected opcode in narrow_rotate: {}", opcode),
+    };
+    let left = pos.ins().ishl(arg, left);
+    let right = pos.ins().ushr(arg, right);
+    pos.func.dfg.replace(inst).bor(left, right);
+}
+
+/// Convert a shift amount to the half-width type. Only the low bits of the amount matter, so
+/// wider amounts are truncated and narrower ones zero-extended.
+fn half_width_amount(pos: &mut FuncCursor, amount: ir::Value, ty_half: ir::Type) -> ir::Value {
+    let amount_ty = pos.func.dfg.value_type(amount);
+    if amount_ty.bits() > ty_half.bits() {
+        pos.ins().ireduce(ty_half, amount)
+    } else if amount_ty.bits() < ty_half.bits() {
+        pos.ins().uextend(ty_half, amount)
+    } else {
+        amount
+    }
+}
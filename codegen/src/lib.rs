@@ -49,11 +49,15 @@
 #[macro_use]
 extern crate alloc;
 
+#[cfg(feature = "std")]
+extern crate std;
+
 use hashbrown::{hash_map, HashMap, HashSet};
 
 pub use crate::{
     context::Context,
     legalizer::legalize_function,
+    regalloc::SafepointLocations,
     value_label::{ValueLabelsRanges, ValueLocRange},
     verifier::verify_function,
     write::write_function,
@@ -63,6 +67,7 @@ pub use cranelift_bforest as bforest;
 pub use cranelift_entity as entity;
 
 pub mod binemit;
+pub mod budget;
 pub mod cfg_printer;
 pub mod cursor;
 pub mod data_value;
@@ -76,24 +81,28 @@ pub mod machinst;
 pub mod print_errors;
 pub mod settings;
 pub mod timing;
+pub mod value_range;
 pub mod verifier;
 pub mod write;
 
 pub use crate::entity::packed_option;
 
 mod abi;
+mod annotations;
 mod bitset;
 mod constant_hash;
 mod context;
 mod dce;
 mod divconst_magic_numbers;
 mod fx;
+mod induction_vars;
 mod inst_predicates;
 mod iterators;
 mod legalizer;
 mod licm;
 mod log;
 mod nan_canonicalization;
+mod narrow;
 mod partition_slice;
 mod postopt;
 mod predicates;
@@ -101,9 +110,11 @@ mod redundant_reload_remover;
 mod regalloc;
 mod remove_constant_phis;
 mod result;
+mod returns;
 mod scoped_hash_map;
 mod simple_gvn;
 mod simple_preopt;
+mod simplify_branches;
 mod stack_layout;
 mod topo_order;
 mod unreachable_code;
@@ -71,6 +71,23 @@ impl LoopAnalysis {
         self.loops[lp].parent.expand()
     }
 
+    /// Return the innermost loop containing `block`, if any.
+    pub fn innermost_loop(&self, block: Block) -> Option<Loop> {
+        self.block_loop_map[block].expand()
+    }
+
+    /// Return the number of loops containing `block`, that is, 0 if `block` isn't in a loop, 1
+    /// if it is in a loop with no parent, and so on.
+    pub fn loop_depth(&self, block: Block) -> u32 {
+        let mut depth = 0;
+        let mut finger = self.innermost_loop(block);
+        while let Some(finger_loop) = finger {
+            depth += 1;
+            finger = self.loop_parent(finger_loop);
+        }
+        depth
+    }
+
     /// Determine if a Block belongs to a loop by running a finger along the loop tree.
     ///
     /// Returns `true` if `block` is in loop `lp`.
@@ -288,6 +305,12 @@ mod tests {
         assert_eq!(loop_analysis.is_in_loop(block2, loops[0]), true);
         assert_eq!(loop_analysis.is_in_loop(block3, loops[0]), true);
         assert_eq!(loop_analysis.is_in_loop(block0, loops[1]), false);
+        assert_eq!(loop_analysis.innermost_loop(block0), Some(loops[0]));
+        assert_eq!(loop_analysis.innermost_loop(block2), Some(loops[1]));
+        assert_eq!(loop_analysis.loop_depth(block0), 1);
+        assert_eq!(loop_analysis.loop_depth(block1), 2);
+        assert_eq!(loop_analysis.loop_depth(block2), 2);
+        assert_eq!(loop_analysis.loop_depth(block3), 1);
     }
 
     #[test]
@@ -5,6 +5,7 @@ use crate::{
     ir::{Signature, StackSlot},
     isa::CallConv,
     machinst::*,
+    result::CodegenResult,
     settings,
 };
 use regalloc::{Reg, Set, SpillSlot, Writable};
@@ -169,6 +170,15 @@ pub trait ABICallee {
     /// likely closely related.
     fn gen_epilogue(&self) -> SmallInstVec<Self::I>;
 
+    /// Generate the epilogue that precedes a tail call, post-regalloc. This is
+    /// the same as `gen_epilogue`, except that it leaves out the return
+    /// instruction: the tail call itself jumps to the callee instead.
+    fn gen_tail_call_epilogue(&self) -> SmallInstVec<Self::I>;
+
+    /// Get the registers holding the exception pointer and the handler
+    /// selector on entry to a landing pad.
+    fn exception_payload_regs(&self) -> [Reg; 2];
+
     /// Returns the full frame size for the given function, after prologue
     /// emission has run. This comprises the spill slots and stack-storage slots
     /// (but not storage for clobbered callee-save registers, arguments pushed
@@ -271,4 +281,24 @@ pub trait ABICaller {
     /// This function should only be called once, as it is allowed to re-use
     /// parts of the ABICaller object in emitting instructions.
     fn emit_call<C: LowerCtx<I = Self::I>>(&mut self, ctx: &mut C);
+
+    /// Emit a tail call, after the arguments have been copied with
+    /// `emit_copy_regs_to_arg`. The emitted instruction terminates the block;
+    /// the epilogue of the caller is inserted before it once registers are
+    /// allocated.
+    ///
+    /// The callee's arguments and return values must all be passed in
+    /// registers, since the tail call reuses the caller's frame; an error is
+    /// returned otherwise.
+    fn emit_tail_call<C: LowerCtx<I = Self::I>>(&mut self, ctx: &mut C) -> CodegenResult<()>;
+
+    /// Emit the call of an invoke, after the arguments have been copied with
+    /// `emit_copy_regs_to_arg`. Only the copies of the return values may come
+    /// between the call and the backend's invoke terminator: they run when the
+    /// callee returns, while an unwinding callee continues at the landing pad.
+    ///
+    /// No stack adjustment may come between the call and the terminator, so the
+    /// callee's arguments and return values must all be passed in registers; an
+    /// error is returned otherwise.
+    fn emit_invoke<C: LowerCtx<I = Self::I>>(&mut self, ctx: &mut C) -> CodegenResult<()>;
 }
@@ -127,10 +127,10 @@ use super::abi::*;
 use crate::{
     binemit::StackMap,
     ir,
-    ir::{types::*, ArgumentExtension, ArgumentPurpose, StackSlot},
+    ir::{condcodes::IntCC, types::*, ArgumentExtension, ArgumentPurpose, StackSlot},
     isa,
     machinst::*,
-    settings, CodegenResult,
+    settings, CodegenError, CodegenResult,
 };
 use alloc::vec::Vec;
 use core::{convert::TryFrom, marker::PhantomData, mem};
@@ -138,6 +138,10 @@ use log::{debug, trace};
 use regalloc::{RealReg, Reg, RegClass, Set, SpillSlot, Writable};
 use smallvec::{smallvec, SmallVec};
 
+/// Size of the guard page below the stack on Windows. Frames at least this
+/// large must be probed before they are used.
+const WINDOWS_GUARD_PAGE_SIZE: u32 = 4096;
+
 /// A location for (part of) an argument or return value. These "storage slots"
 /// are specified for each register-sized part of an argument.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -388,6 +392,24 @@ pub trait ABIMachineSpec {
     /// stack grows downward).
     fn gen_stack_lower_bound_trap(limit_reg: Reg) -> SmallInstVec<Self::I>;
 
+    /// Generate a sequence that compares the word-sized integer in `reg` with
+    /// `imm`, and branches to `taken` if `cond` holds and to `not_taken`
+    /// otherwise. `tmp` may be clobbered to materialize an immediate that the
+    /// comparison can't encode. Used to compile stubs; see `StubAssembler`.
+    fn gen_cond_br_imm(
+        reg: Reg,
+        imm: u64,
+        cond: IntCC,
+        taken: MachLabel,
+        not_taken: MachLabel,
+        tmp: Writable<Reg>,
+    ) -> SmallInstVec<Self::I>;
+
+    /// Generate an indirect jump to the address in `target` that leaves the
+    /// stack and the return address untouched, so that the target returns
+    /// directly to our caller. Used to compile stubs; see `StubAssembler`.
+    fn gen_tail_jump(target: Reg) -> Self::I;
+
     /// Generate an instruction to compute an address of a stack slot (FP- or
     /// SP-based offset).
     fn gen_get_stack_addr(mem: StackAMode, into_reg: Writable<Reg>, ty: Type) -> Self::I;
@@ -428,6 +450,20 @@ pub trait ABIMachineSpec {
     /// Generate a probestack call.
     fn gen_probestack(_frame_size: u32) -> SmallInstVec<Self::I>;
 
+    /// Generate an inline stack probe: a sequence touching each `guard_size`
+    /// page of a `frame_size`-byte frame below SP, from the top down, without
+    /// calling out to the probestack function. SP must hold its original value
+    /// again at the end, and only caller-saved registers that are not argument
+    /// registers may be clobbered.
+    fn gen_inline_probestack(_frame_size: u32, _guard_size: u32) -> SmallInstVec<Self::I>;
+
+    /// Returns whether `gen_inline_probestack` is implemented. If not,
+    /// functions are rejected when stack probes are enabled with
+    /// `probestack_strategy=inline`, rather than compiled without probes.
+    fn supports_inline_probestack() -> bool {
+        true
+    }
+
     /// Generate a clobber-save sequence. This takes the list of *all* registers
     /// written/modified by the function body. The implementation here is
     /// responsible for determining which of these are callee-saved according to
@@ -458,6 +494,16 @@ pub trait ABIMachineSpec {
         outgoing_args_size: u32,
     ) -> SmallVec<[Self::I; 16]>;
 
+    /// Generate a tail call instruction/sequence, which must return
+    /// `MachTerminator::RetCall` from `is_term()`. The called address must be
+    /// synthesized in a fixed register that is neither an argument register
+    /// nor restored by the epilogue, if needed.
+    fn gen_tail_call(
+        dest: &CallDest,
+        uses: Vec<Reg>,
+        callee_conv: isa::CallConv,
+    ) -> SmallVec<[Self::I; 2]>;
+
     /// Generate a call instruction/sequence. This method is provided one
     /// temporary register to use to synthesize the called address, if needed.
     fn gen_call(
@@ -494,6 +540,10 @@ pub trait ABIMachineSpec {
     /// not to be saved across a call to a callee with the given ABI.
     fn get_regs_clobbered_by_call(call_conv_of_callee: isa::CallConv) -> Vec<Writable<Reg>>;
 
+    /// Get the registers in which the unwinder passes the exception pointer and the handler
+    /// selector to a landing pad.
+    fn get_exception_payload_regs() -> [Reg; 2];
+
     /// Get the needed extension mode, given the mode attached to the argument
     /// in the signature and the calling convention. The input (the attribute in
     /// the signature) specifies what extension type should be done *if* the ABI
@@ -612,8 +662,9 @@ pub struct ABICalleeImpl<M: ABIMachineSpec> {
     /// manually register-allocated and carefully only use caller-saved
     /// registers and keep nothing live after this sequence of instructions.
     stack_limit: Option<(Reg, SmallInstVec<M::I>)>,
-    /// Are we to invoke the probestack function in the prologue? If so,
-    /// what is the minimum size at which we must invoke it?
+    /// Are we to probe the stack in the prologue? If so, what is the minimum
+    /// frame size at which we must probe it? This is also the size of the
+    /// guard region, i.e. the stride of inline probes.
     probestack_min_frame: Option<u32>,
 
     _mach: PhantomData<M>,
@@ -674,21 +725,38 @@ impl<M: ABIMachineSpec> ABICalleeImpl<M> {
         // argument or as a global value which often calculates the stack limit
         // from the arguments.
         let stack_limit =
-            get_special_purpose_param_register(f, &sig, ir::ArgumentPurpose::StackLimit)
-                .map(|reg| (reg, smallvec![]))
-                .or_else(|| f.stack_limit.map(|gv| gen_stack_limit::<M>(f, &sig, gv)));
-
-        // Determine whether a probestack call is required for large enough
-        // frames (and the minimum frame size if so).
+            match get_special_purpose_param_register(f, &sig, ir::ArgumentPurpose::StackLimit) {
+                Some(reg) => Some((reg, smallvec![])),
+                None => f
+                    .stack_limit
+                    .map(|gv| gen_stack_limit::<M>(f, &sig, gv))
+                    .transpose()?,
+            };
+
+        // Determine whether a stack probe is required for large enough
+        // frames (and the minimum frame size if so). Windows only commits stack
+        // memory when its single guard page is touched, so Windows fastcall
+        // functions must probe every frame that spans a page, whatever the
+        // settings say.
         let probestack_min_frame = if flags.enable_probestack() {
             assert!(
                 !flags.probestack_func_adjusts_sp(),
                 "SP-adjusting probestack not supported in new backends"
             );
             Some(1 << flags.probestack_size_log2())
+        } else if call_conv.extends_windows_fastcall() {
+            Some(WINDOWS_GUARD_PAGE_SIZE)
         } else {
             None
         };
+        if probestack_min_frame.is_some()
+            && flags.probestack_strategy() == settings::ProbestackStrategy::Inline
+            && !M::supports_inline_probestack()
+        {
+            return Err(CodegenError::Unsupported(
+                "inline stack probes on this target".into(),
+            ));
+        }
 
         Ok(Self {
             ir_sig,
@@ -791,10 +859,10 @@ fn gen_stack_limit<M: ABIMachineSpec>(
     f: &ir::Function,
     abi: &ABISig,
     gv: ir::GlobalValue,
-) -> (Reg, SmallInstVec<M::I>) {
+) -> CodegenResult<(Reg, SmallInstVec<M::I>)> {
     let mut insts = smallvec![];
-    let reg = generate_gv::<M>(f, abi, gv, &mut insts);
-    return (reg, insts);
+    let reg = generate_gv::<M>(f, abi, gv, &mut insts)?;
+    Ok((reg, insts))
 }
 
 fn generate_gv<M: ABIMachineSpec>(
@@ -802,12 +870,14 @@ fn generate_gv<M: ABIMachineSpec>(
     abi: &ABISig,
     gv: ir::GlobalValue,
     insts: &mut SmallInstVec<M::I>,
-) -> Reg {
+) -> CodegenResult<Reg> {
     match f.global_values[gv] {
         // Return the direct register the vmcontext is in
         ir::GlobalValueData::VMContext => {
-            get_special_purpose_param_register(f, abi, ir::ArgumentPurpose::VMContext)
-                .expect("no vmcontext parameter found")
+            Ok(
+                get_special_purpose_param_register(f, abi, ir::ArgumentPurpose::VMContext)
+                    .expect("no vmcontext parameter found"),
+            )
         }
         // Load our base value into a register, then load from that register
         // in to a temporary register.
@@ -817,7 +887,7 @@ fn generate_gv<M: ABIMachineSpec>(
             global_type: _,
             readonly: _,
         } => {
-            let base = generate_gv::<M>(f, abi, base, insts);
+            let base = generate_gv::<M>(f, abi, base, insts)?;
             let into_reg = Writable::from_reg(M::get_stacklimit_reg());
             insts.push(M::gen_load_base_offset(
                 into_reg,
@@ -825,9 +895,28 @@ fn generate_gv<M: ABIMachineSpec>(
                 offset.into(),
                 M::word_type(),
             ));
-            return into_reg.to_reg();
+            Ok(into_reg.to_reg())
+        }
+        // Compute the base value, then add the (non-negative) offset to it.
+        ir::GlobalValueData::IAddImm { base, offset, .. } => {
+            let imm = match i32::try_from(offset.bits()) {
+                Ok(imm) if imm >= 0 => imm as u32,
+                _ => {
+                    return Err(CodegenError::Unsupported(format!(
+                        "stack limit offset {} outside of 0..=i32::MAX",
+                        offset
+                    )))
+                }
+            };
+            let base = generate_gv::<M>(f, abi, base, insts)?;
+            let into_reg = Writable::from_reg(M::get_stacklimit_reg());
+            insts.extend(M::gen_add_imm(into_reg, base, imm));
+            Ok(into_reg.to_reg())
         }
-        ref other => panic!("global value for stack limit not supported: {}", other),
+        ref other => Err(CodegenError::Unsupported(format!(
+            "global value for stack limit: {}",
+            other
+        ))),
     }
 }
 
@@ -1298,6 +1387,20 @@ impl<M: ABIMachineSpec> ABICallee for ABICalleeImpl<M> {
         let mask = M::stack_align(self.call_conv) - 1;
         let total_stacksize = (total_stacksize + mask) & !mask; // 16-align the stack.
 
+        if !self.call_conv.extends_baldrdash() && total_stacksize > 0 {
+            self.fixed_frame_storage_size += total_stacksize;
+        }
+
+        // Save clobbered registers. The instructions are inserted below, after
+        // any stack check and probe.
+        let (clobber_size, clobber_insts) = M::gen_clobber_save(
+            self.call_conv,
+            &self.flags,
+            &self.clobbered,
+            self.fixed_frame_storage_size,
+            self.outgoing_args_size,
+        );
+
         if !self.call_conv.extends_baldrdash() {
             // Leaf functions with zero stack don't need a stack check if one's
             // specified, otherwise always insert the stack check.
@@ -1307,24 +1410,23 @@ impl<M: ABIMachineSpec> ABICallee for ABICalleeImpl<M> {
                     self.insert_stack_check(*reg, total_stacksize, &mut insts);
                 }
                 if let Some(min_frame) = &self.probestack_min_frame {
-                    if total_stacksize >= *min_frame {
-                        insts.extend(M::gen_probestack(total_stacksize));
+                    // The clobber save area is allocated right below the
+                    // fixed frame, so the probe has to cover it as well.
+                    let probe_size = total_stacksize + clobber_size as u32;
+                    if probe_size >= *min_frame {
+                        match self.flags.probestack_strategy() {
+                            settings::ProbestackStrategy::Outline => {
+                                insts.extend(M::gen_probestack(probe_size))
+                            }
+                            settings::ProbestackStrategy::Inline => {
+                                insts.extend(M::gen_inline_probestack(probe_size, *min_frame))
+                            }
+                        }
                     }
                 }
             }
-            if total_stacksize > 0 {
-                self.fixed_frame_storage_size += total_stacksize;
-            }
         }
 
-        // Save clobbered registers.
-        let (clobber_size, clobber_insts) = M::gen_clobber_save(
-            self.call_conv,
-            &self.flags,
-            &self.clobbered,
-            self.fixed_frame_storage_size,
-            self.outgoing_args_size,
-        );
         insts.extend(clobber_insts);
 
         // N.B.: "nominal SP", which we use to refer to stackslots and
@@ -1343,6 +1445,16 @@ impl<M: ABIMachineSpec> ABICallee for ABICalleeImpl<M> {
     }
 
     fn gen_epilogue(&self) -> SmallInstVec<M::I> {
+        let mut insts = self.gen_tail_call_epilogue();
+        if !self.call_conv.extends_baldrdash() {
+            insts.push(M::gen_ret());
+        }
+
+        debug!("Epilogue: {:?}", insts);
+        insts
+    }
+
+    fn gen_tail_call_epilogue(&self) -> SmallInstVec<M::I> {
         let mut insts = smallvec![];
 
         // Restore clobbered registers.
@@ -1362,13 +1474,14 @@ impl<M: ABIMachineSpec> ABICallee for ABICalleeImpl<M> {
 
         if !self.call_conv.extends_baldrdash() {
             insts.extend(M::gen_epilogue_frame_restore(&self.flags));
-            insts.push(M::gen_ret());
         }
-
-        debug!("Epilogue: {:?}", insts);
         insts
     }
 
+    fn exception_payload_regs(&self) -> [Reg; 2] {
+        M::get_exception_payload_regs()
+    }
+
     fn frame_size(&self) -> u32 {
         self.total_frame_size
             .expect("frame size not computed before prologue generation")
@@ -1753,4 +1866,29 @@ impl<M: ABIMachineSpec> ABICaller for ABICallerImpl<M> {
             }
         }
     }
+
+    fn emit_tail_call<C: LowerCtx<I = Self::I>>(&mut self, ctx: &mut C) -> CodegenResult<()> {
+        if self.sig.stack_arg_space != 0 || self.sig.stack_ret_space != 0 {
+            return Err(CodegenError::Unsupported(format!(
+                "tail call with arguments or return values on the stack: {}",
+                self.ir_sig
+            )));
+        }
+        let uses = mem::replace(&mut self.uses, Default::default());
+        for inst in M::gen_tail_call(&self.dest, uses, self.sig.call_conv) {
+            ctx.emit(inst);
+        }
+        Ok(())
+    }
+
+    fn emit_invoke<C: LowerCtx<I = Self::I>>(&mut self, ctx: &mut C) -> CodegenResult<()> {
+        if self.sig.stack_arg_space != 0 || self.sig.stack_ret_space != 0 {
+            return Err(CodegenError::Unsupported(format!(
+                "invoke with arguments or return values on the stack: {}",
+                self.ir_sig
+            )));
+        }
+        self.emit_call(ctx);
+        Ok(())
+    }
 }
@@ -2,7 +2,10 @@
 
 use crate::{
     binemit, ir,
-    isa::{BackendVariant, EncInfo, Encoding, Encodings, Legalize, RegClass, RegInfo, TargetIsa},
+    isa::{
+        BackendVariant, EncInfo, Encoding, Encodings, InstCost, Legalize, RegClass, RegInfo,
+        TargetIsa,
+    },
     machinst::*,
     regalloc::RegisterSet,
     settings::{self, Flags},
@@ -70,6 +73,14 @@ impl TargetIsa for TargetIsaAdapter {
         self.backend.hash_all_flags(hasher);
     }
 
+    fn inst_cost(&self, opcode: ir::Opcode, ctrl_typevar: ir::Type) -> InstCost {
+        self.backend.inst_cost(opcode, ctrl_typevar)
+    }
+
+    fn zero_extends_32bit_ops(&self) -> bool {
+        self.backend.zero_extends_32bit_ops()
+    }
+
     fn register_info(&self) -> RegInfo {
         // Called from function's Display impl, so we need a stub here.
         RegInfo {
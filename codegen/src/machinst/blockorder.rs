@@ -192,7 +192,10 @@ impl LoweredBlock {
 
 impl BlockLoweringOrder {
     /// Compute and return a lowered block order for `f`.
-    pub fn new(f: &Function) -> BlockLoweringOrder {
+    ///
+    /// With `preserve_layout`, the original blocks keep the order they have in the layout of
+    /// `f`, with the edge blocks following their predecessors, instead of being reordered.
+    pub fn new(f: &Function, preserve_layout: bool) -> BlockLoweringOrder {
         debug!("BlockLoweringOrder: function body {:?}", f);
 
         // Step 1: compute the in-edge and out-edge count of every block.
@@ -378,6 +381,17 @@ impl BlockLoweringOrder {
 
         postorder.reverse();
         let mut rpo = postorder;
+        if preserve_layout {
+            // The entry block is first in the layout too, so it stays first.
+            let mut layout_pos = SecondaryMap::with_default(0);
+            for (pos, block) in f.layout.blocks().enumerate() {
+                layout_pos[block] = pos;
+            }
+            rpo.sort_by_key(|&(block, _)| match block {
+                LoweredBlock::Edge { pred, .. } => (layout_pos[pred], 1),
+                _ => (layout_pos[block.orig_block().unwrap()], 0),
+            });
+        }
         if let Some(d) = deferred_last {
             rpo.push(d);
         }
@@ -496,7 +510,7 @@ mod test {
     #[test]
     fn test_blockorder_diamond() {
         let func = build_test_func(4, &[(0, 1), (0, 2), (1, 3), (2, 3)]);
-        let order = BlockLoweringOrder::new(&func);
+        let order = BlockLoweringOrder::new(&func, false);
 
         assert_eq!(order.lowered_order.len(), 6);
 
@@ -555,10 +569,10 @@ mod test {
                 (4, 6),
             ],
         );
-        let order = BlockLoweringOrder::new(&func);
+        let order = BlockLoweringOrder::new(&func, false);
 
         assert_eq!(order.lowered_order.len(), 11);
-        println!("ordered = {:?}", order.lowered_order);
+        debug!("ordered = {:?}", order.lowered_order);
 
         // block 0
         assert!(order.lowered_order[0].orig_block().unwrap().as_u32() == 0);
@@ -623,4 +637,51 @@ mod test {
         assert!(order.lowered_order[10].in_edge().is_none());
         assert!(order.lowered_order[10].out_edge().is_none());
     }
+
+    #[test]
+    fn test_blockorder_preserve_layout() {
+        // The same function as in `test_blockorder_critedge`.
+        let func = build_test_func(
+            7,
+            &[
+                (0, 1),
+                (0, 2),
+                (1, 3),
+                (1, 4),
+                (2, 5),
+                (3, 5),
+                (3, 6),
+                (4, 6),
+            ],
+        );
+        let order = BlockLoweringOrder::new(&func, true);
+
+        let blocks: Vec<_> = order
+            .lowered_order
+            .iter()
+            .map(|lb| match lb.orig_block() {
+                Some(block) => (block.as_u32(), None),
+                None => {
+                    let (pred, _, succ) = lb.out_edge().unwrap();
+                    (pred.as_u32(), Some(succ.as_u32()))
+                }
+            })
+            .collect();
+        assert_eq!(
+            blocks,
+            [
+                (0, None),
+                (1, None),
+                (2, None),
+                (2, Some(5)),
+                (3, None),
+                (3, Some(5)),
+                (3, Some(6)),
+                (4, None),
+                (4, Some(6)),
+                (5, None),
+                (6, None),
+            ]
+        );
+    }
 }
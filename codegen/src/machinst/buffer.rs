@@ -178,6 +178,11 @@ pub struct MachBuffer<I: VCodeInst> {
     stack_maps: SmallVec<[MachStackMap; 8]>,
     /// Any unwind info at a given location.
     unwind_info: SmallVec<[(CodeOffset, UnwindInst); 8]>,
+    /// Landing pads of the calls that may unwind, as `(return address, label)` pairs.
+    exception_handlers: SmallVec<[(CodeOffset, MachLabel); 4]>,
+    /// Relocations whose addend is the offset of a label, as `(index into relocs, label)`
+    /// pairs; the addends are filled in once all labels are bound.
+    label_relocs: SmallVec<[(usize, MachLabel); 4]>,
     /// The current source location in progress (after `start_srcloc()` and
     /// before `end_srcloc()`).  This is a (start_offset, src_loc) tuple.
     cur_srcloc: Option<(CodeOffset, SourceLoc)>,
@@ -226,6 +231,8 @@ pub struct MachBuffer<I: VCodeInst> {
     labels_at_tail_off: CodeOffset,
     /// Map used constants to their [MachLabel].
     constant_labels: SecondaryMap<VCodeConstant, MachLabel>,
+    /// Whether branches are edited at all; see `disable_branch_optimizations()`.
+    branch_optimizations: bool,
 }
 
 /// A `MachBuffer` once emission is completed: holds generated code and records,
@@ -247,6 +254,8 @@ pub struct MachBufferFinalized {
     stack_maps: SmallVec<[MachStackMap; 8]>,
     /// Any unwind info at a given location.
     pub unwind_info: SmallVec<[(CodeOffset, UnwindInst); 8]>,
+    /// Any exception handlers referring to this code.
+    exception_handlers: SmallVec<[MachExceptionHandler; 4]>,
 }
 
 static UNKNOWN_LABEL_OFFSET: CodeOffset = 0xffff_ffff;
@@ -307,6 +316,8 @@ impl<I: VCodeInst> MachBuffer<I> {
             srclocs: SmallVec::new(),
             stack_maps: SmallVec::new(),
             unwind_info: SmallVec::new(),
+            exception_handlers: SmallVec::new(),
+            label_relocs: SmallVec::new(),
             cur_srcloc: None,
             label_offsets: SmallVec::new(),
             label_aliases: SmallVec::new(),
@@ -318,9 +329,16 @@ impl<I: VCodeInst> MachBuffer<I> {
             labels_at_tail: SmallVec::new(),
             labels_at_tail_off: 0,
             constant_labels: SecondaryMap::new(),
+            branch_optimizations: true,
         }
     }
 
+    /// Emit every branch as given, without removing, inverting or redirecting any of them, so
+    /// that the code follows the order of the blocks.
+    pub fn disable_branch_optimizations(&mut self) {
+        self.branch_optimizations = false;
+    }
+
     /// Debug-only: check invariants of labels and branch-records described
     /// under "Branch-optimization Correctness" above.
     #[cfg(debug)]
@@ -763,6 +781,12 @@ impl<I: VCodeInst> MachBuffer<I> {
     }
 
     fn optimize_branches(&mut self) {
+        if !self.branch_optimizations {
+            // No invariant requires any branch to appear in `latest_branches`.
+            self.latest_branches.clear();
+            return;
+        }
+
         self.lazily_clear_labels_at_tail();
         // Invariants valid at this point.
 
@@ -1202,6 +1226,20 @@ impl<I: VCodeInst> MachBuffer<I> {
         // incorrect.
         assert!(self.fixup_records.is_empty());
 
+        let exception_handlers = self
+            .exception_handlers
+            .iter()
+            .map(|&(ret_addr, label)| MachExceptionHandler {
+                ret_addr,
+                landing_pad: self.resolve_label_offset(label),
+            })
+            .collect();
+
+        for &(idx, label) in self.label_relocs.iter() {
+            let offset = self.resolve_label_offset(label);
+            self.relocs[idx].addend += i64::from(offset);
+        }
+
         let mut srclocs = self.srclocs;
         srclocs.sort_by_key(|entry| entry.start);
 
@@ -1213,6 +1251,7 @@ impl<I: VCodeInst> MachBuffer<I> {
             srclocs,
             stack_maps: self.stack_maps,
             unwind_info: self.unwind_info,
+            exception_handlers,
         }
     }
 
@@ -1234,6 +1273,21 @@ impl<I: VCodeInst> MachBuffer<I> {
         });
     }
 
+    /// Add an external relocation at the current offset whose addend is the
+    /// offset of `label` within this buffer, plus `addend`. Used for references
+    /// to labels that must go through the symbol of the function itself.
+    pub fn add_label_reloc(
+        &mut self,
+        srcloc: SourceLoc,
+        kind: Reloc,
+        name: &ExternalName,
+        label: MachLabel,
+        addend: Addend,
+    ) {
+        self.label_relocs.push((self.relocs.len(), label));
+        self.add_reloc(srcloc, kind, name, addend);
+    }
+
     /// Add a trap record at the current offset.
     pub fn add_trap(&mut self, srcloc: SourceLoc, code: TrapCode) {
         self.traps.push(MachTrap {
@@ -1252,6 +1306,16 @@ impl<I: VCodeInst> MachBuffer<I> {
         });
     }
 
+    /// Make the most recently emitted call unwind to `landing_pad` when it throws.
+    pub fn add_exception_handler(&mut self, landing_pad: MachLabel) {
+        let ret_addr = self
+            .call_sites
+            .last()
+            .expect("exception handler without a call")
+            .ret_addr;
+        self.exception_handlers.push((ret_addr, landing_pad));
+    }
+
     /// Add an unwind record at the current offset.
     pub fn add_unwind(&mut self, unwind: UnwindInst) {
         self.unwind_info.push((self.cur_offset(), unwind));
@@ -1364,6 +1428,11 @@ impl MachBufferFinalized {
     pub fn stack_maps(&self) -> &[MachStackMap] {
         &self.stack_maps[..]
     }
+
+    /// Get the exception handlers of the calls in this code.
+    pub fn exception_handlers(&self) -> &[MachExceptionHandler] {
+        &self.exception_handlers[..]
+    }
 }
 
 /// A constant that is deferred to the next constant-pool opportunity.
@@ -1456,6 +1525,15 @@ pub struct MachStackMap {
     pub stack_map: StackMap,
 }
 
+/// Record of the landing pad a call unwinds to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MachExceptionHandler {
+    /// The offset of the call's return address.
+    pub ret_addr: CodeOffset,
+    /// The offset of the landing pad.
+    pub landing_pad: CodeOffset,
+}
+
 /// Record of branch instruction in the buffer, to facilitate editing.
 #[derive(Clone, Debug)]
 struct MachBranch {
@@ -1519,6 +1597,22 @@ mod test {
         assert_eq!(0, buf.total_size());
     }
 
+    #[test]
+    fn test_keep_jump_to_next() {
+        let info = EmitInfo::new(settings::Flags::new(settings::builder()));
+        let mut buf = MachBuffer::new();
+        buf.disable_branch_optimizations();
+        let mut state = Default::default();
+
+        buf.reserve_labels_for_blocks(2);
+        buf.bind_label(label(0));
+        let inst = Inst::Jump { dest: target(1) };
+        inst.emit(&mut buf, &info, &mut state);
+        buf.bind_label(label(1));
+        let buf = buf.finish();
+        assert_eq!(4, buf.total_size());
+    }
+
     #[test]
     fn test_elide_trivial_jump_blocks() {
         let info = EmitInfo::new(settings::Flags::new(settings::builder()));
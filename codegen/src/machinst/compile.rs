@@ -2,8 +2,12 @@
 
 use crate::{ir::Function, log::DeferredDisplay, machinst::*, settings, timing};
 
+use core::convert::TryInto;
 use log::debug;
-use regalloc::{allocate_registers_with_opts, Algorithm, Options, PrettyPrint};
+use regalloc::{
+    allocate_registers_with_opts, Algorithm, BacktrackingOptions, LinearScanOptions, Options,
+    PrettyPrint,
+};
 
 /// Compile the given function down to VCode with allocated registers, ready
 /// for binary emission.
@@ -17,7 +21,7 @@ where
     B::MInst: PrettyPrint,
 {
     // Compute lowered block order.
-    let block_order = BlockLoweringOrder::new(f);
+    let block_order = BlockLoweringOrder::new(f, b.flags().preserve_block_order());
     // Build the lowering context.
     let lower = Lower::new(f, abi, emit_info, block_order)?;
     // Lower the IR.
@@ -34,18 +38,34 @@ where
     );
 
     // Perform register allocation.
-    let (run_checker, algorithm) = match vcode.flags().regalloc() {
-        settings::Regalloc::Backtracking => (false, Algorithm::Backtracking(Default::default())),
-        settings::Regalloc::BacktrackingChecked => {
-            (true, Algorithm::Backtracking(Default::default()))
+    let bt_opts = || {
+        let fuel_per_inst = vcode.flags().regalloc_fuel_per_inst();
+        BacktrackingOptions {
+            fuel: if fuel_per_inst == 0 {
+                None
+            } else {
+                let fuel = usize::from(fuel_per_inst) * vcode.num_insts();
+                Some(fuel.try_into().unwrap_or(u32::max_value()))
+            },
+            ..Default::default()
         }
+    };
+    let linear_scan_opts = || LinearScanOptions {
+        split_around_calls: true,
+        ..Default::default()
+    };
+    let (run_checker, algorithm) = match vcode.flags().regalloc() {
+        settings::Regalloc::Backtracking => (false, Algorithm::Backtracking(bt_opts())),
+        settings::Regalloc::BacktrackingChecked => (true, Algorithm::Backtracking(bt_opts())),
         settings::Regalloc::ExperimentalLinearScan => {
-            (false, Algorithm::LinearScan(Default::default()))
+            (false, Algorithm::LinearScan(linear_scan_opts()))
         }
         settings::Regalloc::ExperimentalLinearScanChecked => {
-            (true, Algorithm::LinearScan(Default::default()))
+            (true, Algorithm::LinearScan(linear_scan_opts()))
         }
     };
+    let deterministic = vcode.flags().deterministic_regalloc();
+    let large_functions = vcode.flags().regalloc_large_functions();
 
     #[cfg(feature = "regalloc-snapshot")]
     {
@@ -79,6 +99,12 @@ where
             Options {
                 run_checker,
                 algorithm,
+                dump_allocation: false,
+                annotate: false,
+                deterministic,
+                report_moves: false,
+                split_critical_edges: false,
+                large_functions,
             },
         )
         .map_err(|err| {
@@ -93,10 +119,11 @@ where
     };
 
     // Reorder vcode into final order and copy out final instruction sequence
-    // all at once. This also inserts prologues/epilogues.
+    // all at once. This also inserts prologues/epilogues and removes redundant
+    // moves.
     {
         let _tt = timing::vcode_post_ra();
-        vcode.replace_insns_from_regalloc(result);
+        vcode.replace_insns_from_regalloc(result, b.reg_universe());
     }
 
     debug!(
@@ -0,0 +1,116 @@
+//! Redundant move elimination after register allocation.
+//!
+//! Register allocation leaves behind moves that have no effect: moves that shuffle a value back
+//! into a register that still holds it, typically at block edges where the allocator resolves
+//! differing locations, and moves whose destination is overwritten before being read. Once all
+//! registers are real, this pass finds such moves so that they are not emitted.
+//!
+//! The analysis works on each block separately. It numbers the values held by registers, starting
+//! with a fresh value for every register at the start of the block, and forwards value numbers
+//! through moves, so that after `mov r2, r1; mov r3, r2` all three registers are known to hold
+//! the same value for as long as none of them is written. It relies on the registers reported by
+//! `get_regs`, just like the register allocator does, and therefore only tracks the allocatable
+//! registers, minus the suggested scratch registers.
+
+use crate::machinst::MachInst;
+use alloc::vec;
+use alloc::vec::Vec;
+use regalloc::{InstIx, RealRegUniverse, Reg, RegUsageCollector};
+
+/// Find the moves in the allocated instruction sequence `insns` that can be removed, that is:
+///
+/// - moves whose destination already holds the value of their source, self-moves included;
+/// - moves whose destination is written by a later instruction of the same block before being
+///   read.
+///
+/// Blocks start at the indices in `block_starts`, in increasing order, as in the `target_map` of
+/// a `RegAllocResult`. Returns a flag for each instruction, set for the removable moves.
+pub(crate) fn find_redundant_moves<I: MachInst>(
+    insns: &[I],
+    block_starts: &[InstIx],
+    universe: &RealRegUniverse,
+) -> Vec<bool> {
+    let num_regs = universe.regs.len();
+    let mut tracked: Vec<bool> = (0..num_regs).map(|i| i < universe.allocable).collect();
+    for info in universe.allocable_by_class.iter().flatten() {
+        if let Some(scratch) = info.suggested_scratch {
+            tracked[scratch] = false;
+        }
+    }
+    let tracked_index = |reg: Reg| -> Option<usize> {
+        if reg.is_real() && tracked.get(reg.get_index()) == Some(&true) {
+            Some(reg.get_index())
+        } else {
+            None
+        }
+    };
+
+    let mut redundant = vec![false; insns.len()];
+    // The number of the value held by each register.
+    let mut values = vec![0u32; num_regs];
+    let mut next_value = 0u32;
+    // For each register, the last move that wrote it, if the register hasn't been read since.
+    let mut unread_move: Vec<Option<usize>> = vec![None; num_regs];
+    let mut reg_vecs = RegUsageCollector::get_empty_reg_vecs_test_framework_only(false);
+
+    for (block, start) in block_starts.iter().enumerate() {
+        let start = start.get() as usize;
+        let end = block_starts
+            .get(block + 1)
+            .map_or(insns.len(), |next| next.get() as usize);
+
+        // Nothing is known about the registers at the start of a block.
+        for value in values.iter_mut() {
+            *value = next_value;
+            next_value += 1;
+        }
+        for mov in unread_move.iter_mut() {
+            *mov = None;
+        }
+
+        for (i, insn) in insns.iter().enumerate().take(end).skip(start) {
+            if let Some((dst, src)) = insn.is_move() {
+                let dst = dst.to_reg();
+                if dst == src {
+                    redundant[i] = true;
+                    continue;
+                }
+                if let (Some(dst), Some(src)) = (tracked_index(dst), tracked_index(src)) {
+                    if values[dst] == values[src] {
+                        redundant[i] = true;
+                        continue;
+                    }
+                    unread_move[src] = None;
+                    if let Some(prev) = unread_move[dst].replace(i) {
+                        redundant[prev] = true;
+                    }
+                    values[dst] = values[src];
+                    continue;
+                }
+            }
+
+            reg_vecs.clear();
+            insn.get_regs(&mut RegUsageCollector::new(&mut reg_vecs));
+            for &reg in reg_vecs.uses.iter().chain(reg_vecs.mods.iter()) {
+                if let Some(reg) = tracked_index(reg) {
+                    unread_move[reg] = None;
+                }
+            }
+            for &reg in reg_vecs.defs.iter() {
+                if let Some(reg) = tracked_index(reg) {
+                    if let Some(prev) = unread_move[reg].take() {
+                        redundant[prev] = true;
+                    }
+                }
+            }
+            for &reg in reg_vecs.defs.iter().chain(reg_vecs.mods.iter()) {
+                if let Some(reg) = tracked_index(reg) {
+                    values[reg] = next_value;
+                    next_value += 1;
+                }
+            }
+        }
+    }
+
+    redundant
+}
@@ -13,7 +13,7 @@ use crate::{
     ir::{
         instructions::BranchInfo, ArgumentPurpose, Block, Constant, ConstantData, ExternalName,
         Function, GlobalValueData, Inst, InstructionData, MemFlags, Opcode, Signature, SourceLoc,
-        Type, Value, ValueDef, ValueLabelAssignments, ValueLabelStart,
+        TlsAccessModel, Type, Value, ValueDef, ValueLabelAssignments, ValueLabelStart,
     },
     machinst::{
         writable_value_regs, ABICallee, BlockIndex, BlockLoweringOrder, LoweredBlock, MachLabel,
@@ -73,6 +73,8 @@ pub trait LowerCtx {
     fn retval(&self, idx: usize) -> ValueRegs<Writable<Reg>>;
     /// Returns the vreg containing the VmContext parameter, if there's one.
     fn get_vm_context(&self) -> Option<Reg>;
+    /// Get the name of the function being lowered.
+    fn func_name(&self) -> &ExternalName;
 
     // General instruction queries:
 
@@ -90,6 +92,10 @@ pub trait LowerCtx {
     /// Get the symbol name, relocation distance estimate, and offset for a
     /// symbol_value instruction.
     fn symbol_value<'b>(&'b self, ir_inst: Inst) -> Option<(&'b ExternalName, RelocDistance, i64)>;
+    /// Get the symbol name and, if the global value asks for one, the TLS
+    /// access model for a tls_value instruction.
+    fn tls_value<'b>(&'b self, ir_inst: Inst)
+        -> Option<(&'b ExternalName, Option<TlsAccessModel>)>;
     /// Returns the memory flags of a given memory access.
     fn memflags(&self, ir_inst: Inst) -> Option<MemFlags>;
     /// Get the source location for a given instruction.
@@ -331,6 +337,13 @@ fn alloc_vregs<I: VCodeInst>(
     Ok(regs)
 }
 
+/// Include the vregs of a value that is a GC reference in stack maps.
+fn mark_gc_ref_vregs<I: VCodeInst>(regs: ValueRegs<Reg>, vcode: &mut VCodeBuilder<I>) {
+    for &reg in regs.regs() {
+        vcode.set_vreg_reftyped(reg.to_virtual_reg());
+    }
+}
+
 enum GenerateReturn {
     Yes,
     No,
@@ -357,6 +370,9 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
                 let ty = f.dfg.value_type(param);
                 if value_regs[param].is_invalid() {
                     let regs = alloc_vregs(ty, &mut next_vreg, &mut vcode)?;
+                    if f.dfg.is_gc_ref(param) {
+                        mark_gc_ref_vregs(regs, &mut vcode);
+                    }
                     value_regs[param] = regs;
                     debug!("bb {} param {}: regs {:?}", bb, param, regs);
                 }
@@ -366,6 +382,9 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
                     let ty = f.dfg.value_type(result);
                     if value_regs[result].is_invalid() {
                         let regs = alloc_vregs(ty, &mut next_vreg, &mut vcode)?;
+                        if f.dfg.is_gc_ref(result) {
+                            mark_gc_ref_vregs(regs, &mut vcode);
+                        }
                         value_regs[result] = regs;
                         debug!(
                             "bb {} inst {} ({:?}): result regs {:?}",
@@ -523,8 +542,13 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
     fn lower_edge(&mut self, pred: Block, inst: Inst, succ: Block) -> CodegenResult<()> {
         debug!("lower_edge: pred {} succ {}", pred, succ);
 
+        // The arguments of an invoke start with its call arguments.
+        let args = match self.f.dfg.analyze_branch(inst) {
+            BranchInfo::SingleDest(_, args) => args,
+            _ => &[],
+        };
         let num_args = self.f.dfg.block_params(succ).len();
-        debug_assert!(num_args == self.f.dfg.inst_variable_args(inst).len());
+        debug_assert!(num_args == args.len());
 
         // Most blocks have no params, so skip all the hoop-jumping below and make an early exit.
         if num_args == 0 {
@@ -544,13 +568,7 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
         let mut var_bundles: SmallVec<[_; 16]> = SmallVec::new();
 
         let mut i = 0;
-        for (dst_val, src_val) in self
-            .f
-            .dfg
-            .block_params(succ)
-            .iter()
-            .zip(self.f.dfg.inst_variable_args(inst).iter())
-        {
+        for (dst_val, src_val) in self.f.dfg.block_params(succ).iter().zip(args.iter()) {
             let src_val = self.f.dfg.resolve_aliases(*src_val);
             let ty = self.f.dfg.value_type(src_val);
 
@@ -729,6 +747,12 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
                 continue;
             }
 
+            // Skip landing pad payloads; these are defined where the unwinder
+            // enters the landing pad (see `gen_landing_pad_payload()` below).
+            if self.f.dfg[inst].opcode() == Opcode::LandingPad {
+                continue;
+            }
+
             // Normal instruction: codegen if the instruction is side-effecting
             // or any of its outputs its used.
             if has_side_effect || value_needed {
@@ -863,6 +887,45 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
         }
     }
 
+    /// Get the landing pad which the unwinder enters through the lowered
+    /// block `lb`, if any: this is where the exceptional edge of an invoke
+    /// starts.
+    fn landing_pad_entered_at(&self, lb: LoweredBlock) -> Option<Block> {
+        match lb {
+            LoweredBlock::EdgeAndOrig {
+                edge_inst, block, ..
+            }
+            | LoweredBlock::Edge {
+                edge_inst,
+                succ: block,
+                ..
+            } if self.f.dfg[edge_inst].opcode().is_invoke() => Some(block),
+            _ => None,
+        }
+    }
+
+    /// Copy the exception payload out of the registers the unwinder passes it
+    /// in, into the results of the `landing_pad` instruction of `block`. This
+    /// must come before any other instruction on the exceptional edge.
+    fn gen_landing_pad_payload(&mut self, block: Block) {
+        let inst = match self.f.layout.first_inst(block) {
+            Some(inst) if self.f.dfg[inst].opcode() == Opcode::LandingPad => inst,
+            _ => return,
+        };
+        let payload_regs = self.vcode.abi().exception_payload_regs();
+        for (&result, &from_reg) in self
+            .f
+            .dfg
+            .inst_results(inst)
+            .iter()
+            .zip(payload_regs.iter())
+        {
+            let ty = self.f.dfg.value_type(result);
+            let to_reg = self.value_regs[result].only_reg().unwrap();
+            self.emit(I::gen_move(Writable::from_reg(to_reg), from_reg, ty));
+        }
+    }
+
     fn lower_clif_branches<B: LowerBackend<MInst = I>>(
         &mut self,
         backend: &B,
@@ -979,6 +1042,12 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
                 self.finish_ir_inst(SourceLoc::default());
             }
 
+            // Exception payload, where the unwinder enters a landing pad.
+            if let Some(landing_pad) = self.landing_pad_entered_at(*lb) {
+                self.gen_landing_pad_payload(landing_pad);
+                self.finish_ir_inst(SourceLoc::default());
+            }
+
             if bindex == 0 {
                 // Set up the function with arg vreg inits.
                 self.gen_arg_setup();
@@ -1101,6 +1170,10 @@ impl<'func, I: VCodeInst> LowerCtx for Lower<'func, I> {
         self.vm_context
     }
 
+    fn func_name(&self) -> &ExternalName {
+        &self.f.name
+    }
+
     fn data(&self, ir_inst: Inst) -> &InstructionData {
         &self.f.dfg[ir_inst]
     }
@@ -1112,6 +1185,7 @@ impl<'func, I: VCodeInst> LowerCtx for Lower<'func, I> {
     fn call_target<'b>(&'b self, ir_inst: Inst) -> Option<(&'b ExternalName, RelocDistance)> {
         match &self.f.dfg[ir_inst] {
             &InstructionData::Call { func_ref, .. }
+            | &InstructionData::Invoke { func_ref, .. }
             | &InstructionData::FuncAddr { func_ref, .. } => {
                 let funcdata = &self.f.dfg.ext_funcs[func_ref];
                 let dist = funcdata.reloc_distance();
@@ -1123,11 +1197,14 @@ impl<'func, I: VCodeInst> LowerCtx for Lower<'func, I> {
 
     fn call_sig<'b>(&'b self, ir_inst: Inst) -> Option<&'b Signature> {
         match &self.f.dfg[ir_inst] {
-            &InstructionData::Call { func_ref, .. } => {
+            &InstructionData::Call { func_ref, .. } | &InstructionData::Invoke { func_ref, .. } => {
                 let funcdata = &self.f.dfg.ext_funcs[func_ref];
                 Some(&self.f.dfg.signatures[funcdata.signature])
             }
-            &InstructionData::CallIndirect { sig_ref, .. } => Some(&self.f.dfg.signatures[sig_ref]),
+            &InstructionData::CallIndirect { sig_ref, .. }
+            | &InstructionData::InvokeIndirect { sig_ref, .. } => {
+                Some(&self.f.dfg.signatures[sig_ref])
+            }
             _ => None,
         }
     }
@@ -1153,6 +1230,26 @@ impl<'func, I: VCodeInst> LowerCtx for Lower<'func, I> {
         }
     }
 
+    fn tls_value<'b>(
+        &'b self,
+        ir_inst: Inst,
+    ) -> Option<(&'b ExternalName, Option<TlsAccessModel>)> {
+        match &self.f.dfg[ir_inst] {
+            &InstructionData::UnaryGlobalValue { global_value, .. } => {
+                match &self.f.global_values[global_value] {
+                    &GlobalValueData::Symbol {
+                        ref name,
+                        tls: true,
+                        ..
+                    } => Some((name, None)),
+                    &GlobalValueData::TlsSymbol { ref name, model } => Some((name, Some(model))),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
     fn memflags(&self, ir_inst: Inst) -> Option<MemFlags> {
         match &self.f.dfg[ir_inst] {
             &InstructionData::AtomicCas { flags, .. } => Some(flags),
@@ -61,9 +61,11 @@
 //! ```
 
 use crate::{
-    binemit::{CodeInfo, CodeOffset, StackMap},
-    ir::{condcodes::IntCC, Function, SourceLoc, StackSlot, Type, ValueLabel},
-    result::CodegenResult,
+    binemit::{CodeInfo, CodeOffset, CodeStats, StackMap},
+    budget::SkippedPasses,
+    ir::{condcodes::IntCC, Function, Opcode, SourceLoc, StackSlot, Type, ValueLabel},
+    isa::{generic_inst_cost, InstCost},
+    result::{CodegenError, CodegenResult},
     settings::{self, Flags},
     value_label::ValueLabelsRanges,
 };
@@ -102,6 +104,9 @@ pub mod inst_common;
 pub use inst_common::*;
 pub mod valueregs;
 pub use valueregs::*;
+pub mod stub;
+pub use stub::*;
+pub(crate) mod copyprop;
 pub mod debug;
 
 /// A machine instruction.
@@ -129,6 +134,12 @@ pub trait MachInst: Clone + Debug {
         true
     }
 
+    /// Is this a call? The register allocator may split the live ranges crossing calls around
+    /// them.
+    fn is_call(&self) -> bool {
+        false
+    }
+
     /// If this is a load or store to the stack, return that info.
     fn stack_op_info(&self) -> Option<MachInstStackOpInfo> {
         None
@@ -256,12 +267,17 @@ pub enum MachTerminator<'a> {
     None,
     /// A return instruction.
     Ret,
+    /// A tail call, which leaves the function through the callee instead of returning.
+    RetCall,
     /// An unconditional branch to another block.
     Uncond(MachLabel),
     /// A conditional branch to one of two other blocks.
     Cond(MachLabel, MachLabel),
     /// An indirect branch with known possible targets.
     Indirect(&'a [MachLabel]),
+    /// The end of an invoke: a branch to the normal successor, placed right after a call which
+    /// may instead unwind to the landing pad.
+    Invoke(MachLabel, MachLabel),
 }
 
 impl<'a> MachTerminator<'a> {
@@ -272,7 +288,7 @@ impl<'a> MachTerminator<'a> {
             &MachTerminator::Uncond(l) => {
                 ret.push(l);
             }
-            &MachTerminator::Cond(l1, l2) => {
+            &MachTerminator::Cond(l1, l2) | &MachTerminator::Invoke(l1, l2) => {
                 ret.push(l1);
                 ret.push(l2);
             }
@@ -332,6 +348,8 @@ pub struct MachCompileResult {
     pub buffer: MachBufferFinalized,
     /// Size of stack frame, in bytes.
     pub frame_size: u32,
+    /// Statistics of the machine code.
+    pub stats: CodeStats,
     /// Disassembly, if requested.
     pub disasm: Option<String>,
     /// Debug info: value labels to registers/stackslots at code offsets.
@@ -361,6 +379,9 @@ impl MachCompileResult {
             jumptables_size: 0,
             rodata_size: 0,
             total_size: code_size,
+            frame_size: self.frame_size,
+            stats: self.stats,
+            skipped_passes: SkippedPasses::default(),
         }
     }
 }
@@ -375,6 +396,18 @@ pub trait MachBackend {
         want_disasm: bool,
     ) -> CodegenResult<MachCompileResult>;
 
+    /// Compile the given stub; see `StubAssembler`.
+    fn compile_stub(
+        &self,
+        _stub: &StubAssembler,
+        _want_disasm: bool,
+    ) -> CodegenResult<MachCompileResult> {
+        Err(CodegenError::Unsupported(format!(
+            "stubs on the {} backend",
+            self.name()
+        )))
+    }
+
     /// Return flags for this backend.
     fn flags(&self) -> &Flags;
 
@@ -393,6 +426,18 @@ pub trait MachBackend {
     /// Return the register universe for this backend.
     fn reg_universe(&self) -> &RealRegUniverse;
 
+    /// Return the cost of an instruction with the given opcode and controlling type; see
+    /// `TargetIsa::inst_cost`.
+    fn inst_cost(&self, opcode: Opcode, ctrl_typevar: Type) -> InstCost {
+        generic_inst_cost(opcode, ctrl_typevar)
+    }
+
+    /// Do 32-bit integer operations clear the upper half of their 64-bit destination register?
+    /// See `TargetIsa::zero_extends_32bit_ops`.
+    fn zero_extends_32bit_ops(&self) -> bool {
+        false
+    }
+
     /// Machine-specific condcode info needed by TargetIsa.
     /// Condition that will be true when an IaddIfcout overflows.
     fn unsigned_add_overflow_condition(&self) -> IntCC;
@@ -0,0 +1,571 @@
+//! Compilation of small stubs of machine code, without an `ir::Function`.
+//!
+//! Runtimes often generate thousands of tiny snippets of code: trampolines that shuffle their
+//! arguments before jumping to the real implementation, checks of an object's tag before
+//! dispatching to a specialized version, and so on. Building, lowering and register-allocating a
+//! whole `ir::Function` for each of them is wasteful, so a `StubAssembler` instead records a
+//! short sequence of operations on fixed registers -- those of the arguments and return values
+//! of a signature, and a few scratch registers -- which the backend then emits directly, with the
+//! usual branch resolution of the `MachBuffer`.
+//!
+//! Stubs have no stack frame: they run on the stack of their caller, and leave it as they found
+//! it when they return or jump to another function.
+
+use crate::ir::{condcodes::IntCC, Signature, Type};
+use crate::isa::TargetIsa;
+use crate::machinst::*;
+use crate::result::{CodegenError, CodegenResult};
+use crate::settings::Flags;
+use alloc::fmt::Write;
+use alloc::string::String;
+use alloc::vec::Vec;
+use cranelift_entity::PrimaryMap;
+use regalloc::{RealRegUniverse, Reg, Writable};
+
+/// A register that a stub operates on. All of them hold word-sized integers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StubReg {
+    /// The register of the parameter with the given index in the stub's signature. The
+    /// parameter must be passed in a single integer register.
+    Arg(usize),
+    /// The register of the return value with the given index in the stub's signature. The
+    /// return value must be passed in a single integer register.
+    Ret(usize),
+    /// A scratch register. These are caller-saved integer registers that don't hold any
+    /// argument or return value of the stub's signature; how many there are depends on the
+    /// target and the signature.
+    Temp(usize),
+}
+
+/// A position in a stub that can be branched to; see `StubAssembler::create_label`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StubLabel(u32);
+
+#[derive(Clone, Debug)]
+enum StubOp {
+    Move {
+        dst: StubReg,
+        src: StubReg,
+    },
+    Shuffle(Vec<(StubReg, StubReg)>),
+    Iconst {
+        dst: StubReg,
+        imm: u64,
+    },
+    Load {
+        ty: Type,
+        dst: StubReg,
+        base: StubReg,
+        offset: i32,
+    },
+    Store {
+        ty: Type,
+        src: StubReg,
+        base: StubReg,
+        offset: i32,
+    },
+    BrIcmpImm {
+        cond: IntCC,
+        reg: StubReg,
+        imm: u64,
+        target: StubLabel,
+    },
+    Jump(StubLabel),
+    TailJump(StubReg),
+    Return,
+    Bind(StubLabel),
+}
+
+/// A builder for a stub: a short straight-line sequence of operations on fixed registers, with
+/// forward and backward branches, that is compiled without constructing an `ir::Function`.
+///
+/// The same `StubAssembler` can be compiled for several targets, and cloned to stamp out
+/// variants of a stub that only differ in a few operations.
+#[derive(Clone, Debug)]
+pub struct StubAssembler {
+    sig: Signature,
+    ops: Vec<StubOp>,
+    num_labels: u32,
+}
+
+impl StubAssembler {
+    /// Start a stub that is called with the signature `sig`.
+    pub fn new(sig: Signature) -> Self {
+        Self {
+            sig,
+            ops: Vec::new(),
+            num_labels: 0,
+        }
+    }
+
+    /// The signature the stub is called with.
+    pub fn signature(&self) -> &Signature {
+        &self.sig
+    }
+
+    /// Create a label, which must be bound with `bind_label` before the stub is compiled if
+    /// anything branches to it.
+    pub fn create_label(&mut self) -> StubLabel {
+        let label = StubLabel(self.num_labels);
+        self.num_labels += 1;
+        label
+    }
+
+    /// Bind `label` to the current position in the stub.
+    pub fn bind_label(&mut self, label: StubLabel) {
+        self.ops.push(StubOp::Bind(label));
+    }
+
+    /// Copy `src` to `dst`.
+    pub fn mov(&mut self, dst: StubReg, src: StubReg) {
+        self.ops.push(StubOp::Move { dst, src });
+    }
+
+    /// Perform all the given `(dst, src)` copies in parallel, i.e. as if all the sources were
+    /// read before any destination is written, which is what permuting the arguments of a call
+    /// needs. Each destination may only appear once.
+    pub fn shuffle(&mut self, moves: &[(StubReg, StubReg)]) {
+        self.ops.push(StubOp::Shuffle(moves.to_vec()));
+    }
+
+    /// Set `dst` to the constant `imm`.
+    pub fn iconst(&mut self, dst: StubReg, imm: u64) {
+        self.ops.push(StubOp::Iconst { dst, imm });
+    }
+
+    /// Load an integer of type `ty` from `base + offset` into `dst`. Integers narrower than a
+    /// word are zero-extended.
+    pub fn load(&mut self, ty: Type, dst: StubReg, base: StubReg, offset: i32) {
+        self.ops.push(StubOp::Load {
+            ty,
+            dst,
+            base,
+            offset,
+        });
+    }
+
+    /// Store the low `ty` bits of `src` to `base + offset`.
+    pub fn store(&mut self, ty: Type, src: StubReg, base: StubReg, offset: i32) {
+        self.ops.push(StubOp::Store {
+            ty,
+            src,
+            base,
+            offset,
+        });
+    }
+
+    /// Branch to `target` if `reg` compares to `imm` according to `cond`, and fall through
+    /// otherwise.
+    pub fn br_icmp_imm(&mut self, cond: IntCC, reg: StubReg, imm: u64, target: StubLabel) {
+        self.ops.push(StubOp::BrIcmpImm {
+            cond,
+            reg,
+            imm,
+            target,
+        });
+    }
+
+    /// Branch to `target`.
+    pub fn jump(&mut self, target: StubLabel) {
+        self.ops.push(StubOp::Jump(target));
+    }
+
+    /// Jump to the address in `target`, with the stack and the return address as they were on
+    /// entry to the stub, so that the target returns directly to the stub's caller.
+    pub fn tail_jump(&mut self, target: StubReg) {
+        self.ops.push(StubOp::TailJump(target));
+    }
+
+    /// Return to the stub's caller.
+    pub fn ret(&mut self) {
+        self.ops.push(StubOp::Return);
+    }
+
+    /// Compile the stub for `isa`, which must use one of the `MachBackend`s.
+    ///
+    /// Compilation fails with `CodegenError::Unsupported` if the stub refers to a register that
+    /// doesn't exist, e.g. a parameter passed on the stack or more scratch registers than the
+    /// target has, if a label that is branched to is never bound or bound twice, or if the stub
+    /// can run past its last operation.
+    pub fn compile(
+        &self,
+        isa: &dyn TargetIsa,
+        want_disasm: bool,
+    ) -> CodegenResult<MachCompileResult> {
+        match isa.get_mach_backend() {
+            Some(backend) => backend.compile_stub(self, want_disasm),
+            None => Err(CodegenError::Unsupported(format!(
+                "stubs on the {} backend",
+                isa.name()
+            ))),
+        }
+    }
+
+    /// Check that the labels and the end of the stub are well-formed.
+    fn check_control_flow(&self) -> CodegenResult<()> {
+        let mut bound = vec![false; self.num_labels as usize];
+        let mut used = vec![false; self.num_labels as usize];
+        for op in &self.ops {
+            match *op {
+                StubOp::Bind(label) => {
+                    if bound[label.0 as usize] {
+                        return Err(CodegenError::Unsupported(format!(
+                            "stub label {} bound twice",
+                            label.0
+                        )));
+                    }
+                    bound[label.0 as usize] = true;
+                }
+                StubOp::BrIcmpImm { target, .. } | StubOp::Jump(target) => {
+                    used[target.0 as usize] = true;
+                }
+                _ => {}
+            }
+        }
+        if let Some(label) = (0..self.num_labels as usize).find(|&l| used[l] && !bound[l]) {
+            return Err(CodegenError::Unsupported(format!(
+                "stub label {} is never bound",
+                label
+            )));
+        }
+        match self.ops.last() {
+            Some(StubOp::Jump(_)) | Some(StubOp::TailJump(_)) | Some(StubOp::Return) => Ok(()),
+            _ => Err(CodegenError::Unsupported(
+                "stub doesn't end with a jump or return".into(),
+            )),
+        }
+    }
+}
+
+/// Get the registers of the given parameters or return values, or `None` for those that aren't
+/// passed in a single integer register.
+fn abi_regs<M: ABIMachineSpec>(
+    sig: &Signature,
+    flags: &Flags,
+    args_or_rets: ArgsOrRets,
+) -> CodegenResult<Vec<Option<Reg>>> {
+    let params = match args_or_rets {
+        ArgsOrRets::Args => &sig.params,
+        ArgsOrRets::Rets => &sig.returns,
+    };
+    let (locs, _, _) = M::compute_arg_locs(sig.call_conv, flags, params, args_or_rets, false)?;
+    Ok(locs
+        .iter()
+        .map(|loc| match loc {
+            ABIArg::Slots { slots, .. } if slots.len() == 1 => match slots[0] {
+                ABIArgSlot::Reg { reg, .. } if reg.get_class() == M::word_reg_class() => {
+                    Some(reg.to_reg())
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect())
+}
+
+/// Sequentialize the parallel copies `moves`, using `scratch` to break cycles.
+fn sequentialize_moves(mut moves: Vec<(Reg, Reg)>, scratch: Reg) -> Vec<(Reg, Reg)> {
+    let mut seq = Vec::with_capacity(moves.len() + 1);
+    moves.retain(|&(dst, src)| dst != src);
+    while !moves.is_empty() {
+        // Perform a copy whose destination isn't read by any other pending copy, if there is
+        // one.
+        if let Some(i) = (0..moves.len()).find(|&i| moves.iter().all(|&(_, s)| s != moves[i].0)) {
+            seq.push(moves.remove(i));
+            continue;
+        }
+        // Otherwise, all the pending copies form cycles: save the destination of the first one
+        // to the scratch register, which frees it up.
+        let dst = moves[0].0;
+        seq.push((scratch, dst));
+        for (_, src) in moves.iter_mut() {
+            if *src == dst {
+                *src = scratch;
+            }
+        }
+    }
+    seq
+}
+
+enum StubItem<I> {
+    Inst(I),
+    Bind(MachLabel),
+}
+
+/// Compile `stub` with the backend whose ABI is described by `M`. This is the implementation of
+/// `MachBackend::compile_stub` for all the backends.
+pub(crate) fn compile_stub<M: ABIMachineSpec>(
+    stub: &StubAssembler,
+    flags: &Flags,
+    emit_info: &<M::I as MachInstEmit>::Info,
+    universe: &RealRegUniverse,
+    want_disasm: bool,
+) -> CodegenResult<MachCompileResult> {
+    stub.check_control_flow()?;
+
+    let word_ty = M::word_type();
+    let args = abi_regs::<M>(&stub.sig, flags, ArgsOrRets::Args)?;
+    let rets = abi_regs::<M>(&stub.sig, flags, ArgsOrRets::Rets)?;
+    let clobbered = M::get_regs_clobbered_by_call(stub.sig.call_conv);
+    let mut temps: Vec<Reg> = match universe.class_info(M::word_reg_class()) {
+        Some(info) => universe.regs[info.first..=info.last]
+            .iter()
+            .map(|&(rreg, _)| rreg.to_reg())
+            .filter(|&reg| {
+                clobbered.contains(&Writable::from_reg(reg))
+                    && !args.contains(&Some(reg))
+                    && !rets.contains(&Some(reg))
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    // The last scratch register is kept for breaking copy cycles and materializing immediates.
+    let scratch = temps.pop().ok_or_else(|| {
+        CodegenError::Unsupported("no scratch register available for the stub".into())
+    })?;
+    let reg = |r: StubReg| {
+        let reg = match r {
+            StubReg::Arg(i) => args.get(i).cloned().flatten(),
+            StubReg::Ret(i) => rets.get(i).cloned().flatten(),
+            StubReg::Temp(i) => temps.get(i).cloned(),
+        };
+        reg.ok_or_else(|| CodegenError::Unsupported(format!("stub register {:?}", r)))
+    };
+    let check_ty = |ty: Type| {
+        if ty.is_int() && ty.bits() <= M::word_bits() as u16 {
+            Ok(())
+        } else {
+            Err(CodegenError::Unsupported(format!(
+                "{} loads and stores in stubs",
+                ty
+            )))
+        }
+    };
+
+    let mut buffer = MachBuffer::<M::I>::new();
+    let labels: Vec<MachLabel> = (0..stub.num_labels).map(|_| buffer.get_label()).collect();
+    let mut items = Vec::with_capacity(stub.ops.len());
+    let push_move = |items: &mut Vec<StubItem<M::I>>, dst: Reg, src: Reg| {
+        if dst != src {
+            items.push(StubItem::Inst(M::gen_move(
+                Writable::from_reg(dst),
+                src,
+                word_ty,
+            )));
+        }
+    };
+    for op in &stub.ops {
+        match *op {
+            StubOp::Move { dst, src } => push_move(&mut items, reg(dst)?, reg(src)?),
+            StubOp::Shuffle(ref moves) => {
+                let mut real_moves = Vec::with_capacity(moves.len());
+                for (i, &(dst, src)) in moves.iter().enumerate() {
+                    if moves[..i].iter().any(|&(d, _)| d == dst) {
+                        return Err(CodegenError::Unsupported(format!(
+                            "stub shuffle writes {:?} twice",
+                            dst
+                        )));
+                    }
+                    real_moves.push((reg(dst)?, reg(src)?));
+                }
+                for (dst, src) in sequentialize_moves(real_moves, scratch) {
+                    push_move(&mut items, dst, src);
+                }
+            }
+            StubOp::Iconst { dst, imm } => {
+                let dst = Writable::from_reg(reg(dst)?);
+                let insts = M::I::gen_constant(ValueRegs::one(dst), imm as u128, word_ty, |_| {
+                    Writable::from_reg(scratch)
+                });
+                items.extend(insts.into_iter().map(StubItem::Inst));
+            }
+            StubOp::Load {
+                ty,
+                dst,
+                base,
+                offset,
+            } => {
+                check_ty(ty)?;
+                let dst = Writable::from_reg(reg(dst)?);
+                items.push(StubItem::Inst(M::gen_load_base_offset(
+                    dst,
+                    reg(base)?,
+                    offset,
+                    ty,
+                )));
+            }
+            StubOp::Store {
+                ty,
+                src,
+                base,
+                offset,
+            } => {
+                check_ty(ty)?;
+                items.push(StubItem::Inst(M::gen_store_base_offset(
+                    reg(base)?,
+                    offset,
+                    reg(src)?,
+                    ty,
+                )));
+            }
+            StubOp::BrIcmpImm {
+                cond,
+                reg: r,
+                imm,
+                target,
+            } => {
+                let fallthrough = buffer.get_label();
+                let insts = M::gen_cond_br_imm(
+                    reg(r)?,
+                    imm,
+                    cond,
+                    labels[target.0 as usize],
+                    fallthrough,
+                    Writable::from_reg(scratch),
+                );
+                items.extend(insts.into_iter().map(StubItem::Inst));
+                items.push(StubItem::Bind(fallthrough));
+            }
+            StubOp::Jump(target) => {
+                items.push(StubItem::Inst(M::I::gen_jump(labels[target.0 as usize])));
+            }
+            StubOp::TailJump(target) => items.push(StubItem::Inst(M::gen_tail_jump(reg(target)?))),
+            StubOp::Return => items.push(StubItem::Inst(M::gen_ret())),
+            StubOp::Bind(label) => items.push(StubItem::Bind(labels[label.0 as usize])),
+        }
+    }
+
+    let mut state = <M::I as MachInstEmit>::State::default();
+    let mut disasm = String::new();
+    for item in &items {
+        match item {
+            StubItem::Inst(inst) => {
+                if want_disasm {
+                    let mut show_state = state.clone();
+                    writeln!(
+                        &mut disasm,
+                        "  {}",
+                        inst.pretty_print(Some(universe), &mut show_state)
+                    )
+                    .unwrap();
+                }
+                inst.emit(&mut buffer, emit_info, &mut state);
+            }
+            StubItem::Bind(label) => {
+                if want_disasm {
+                    writeln!(&mut disasm, "{}:", label.to_string()).unwrap();
+                }
+                buffer.bind_label(*label);
+            }
+        }
+    }
+
+    Ok(MachCompileResult {
+        buffer: buffer.finish(),
+        frame_size: 0,
+        stats: CodeStats::default(),
+        disasm: if want_disasm { Some(disasm) } else { None },
+        value_labels_ranges: Default::default(),
+        stackslot_offsets: PrimaryMap::new(),
+        bb_starts: Vec::new(),
+        bb_edges: Vec::new(),
+    })
+}
+
+#[cfg(all(test, feature = "x86"))]
+mod tests {
+    use super::*;
+    use crate::ir::{types, AbiParam};
+    use crate::isa::{lookup_variant, BackendVariant, CallConv};
+    use crate::settings;
+    use alloc::boxed::Box;
+    use core::str::FromStr;
+    use target_lexicon::Triple;
+
+    fn x64() -> Box<dyn TargetIsa> {
+        let triple = Triple::from_str("x86_64").unwrap();
+        lookup_variant(triple, BackendVariant::MachInst)
+            .unwrap()
+            .finish(settings::Flags::new(settings::builder()))
+    }
+
+    fn signature(params: usize, returns: usize) -> Signature {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params = vec![AbiParam::new(types::I64); params];
+        sig.returns = vec![AbiParam::new(types::I64); returns];
+        sig
+    }
+
+    #[test]
+    fn dispatch_stub() {
+        // Return 1 directly if the byte the first argument points to is 3, and otherwise swap the
+        // arguments and jump to a generic implementation.
+        let mut stub = StubAssembler::new(signature(2, 1));
+        let fast = stub.create_label();
+        stub.load(types::I8, StubReg::Temp(0), StubReg::Arg(0), 0);
+        stub.br_icmp_imm(IntCC::Equal, StubReg::Temp(0), 3, fast);
+        stub.shuffle(&[
+            (StubReg::Arg(0), StubReg::Arg(1)),
+            (StubReg::Arg(1), StubReg::Arg(0)),
+        ]);
+        stub.iconst(StubReg::Temp(1), 0x1234_5678_9abc);
+        stub.tail_jump(StubReg::Temp(1));
+        stub.bind_label(fast);
+        stub.iconst(StubReg::Ret(0), 1);
+        stub.ret();
+
+        let result = stub.compile(&*x64(), true).unwrap();
+        assert_eq!(
+            result.disasm.unwrap(),
+            "  movzbq  0(%rdi), %rcx\n\
+             \x20 cmpq    $3, %rcx\n\
+             \x20 jz      label0; j label1\n\
+             label1:\n\
+             \x20 movq    %rdi, %r11\n\
+             \x20 movq    %rsi, %rdi\n\
+             \x20 movq    %r11, %rsi\n\
+             \x20 movabsq $20015998343868, %rdx\n\
+             \x20 jmp     *%rdx\n\
+             label0:\n\
+             \x20 movl    $1, %eax\n\
+             \x20 ret\n"
+        );
+        assert_eq!(result.frame_size, 0);
+    }
+
+    #[test]
+    fn invalid_stubs() {
+        let isa = x64();
+        let unsupported = |stub: &StubAssembler| match stub.compile(&*isa, false) {
+            Err(CodegenError::Unsupported(_)) => true,
+            _ => false,
+        };
+
+        // Falls off its end.
+        let mut stub = StubAssembler::new(signature(1, 0));
+        stub.mov(StubReg::Temp(0), StubReg::Arg(0));
+        assert!(unsupported(&stub));
+
+        // Branches to a label that is never bound.
+        let mut stub = StubAssembler::new(signature(1, 0));
+        let label = stub.create_label();
+        stub.br_icmp_imm(IntCC::NotEqual, StubReg::Arg(0), 0, label);
+        stub.ret();
+        assert!(unsupported(&stub));
+
+        // Refers to a parameter that doesn't exist.
+        let mut stub = StubAssembler::new(signature(1, 0));
+        stub.tail_jump(StubReg::Arg(1));
+        assert!(unsupported(&stub));
+
+        // Uses more scratch registers than there are.
+        let mut stub = StubAssembler::new(signature(0, 0));
+        stub.iconst(StubReg::Temp(100), 0);
+        stub.ret();
+        assert!(unsupported(&stub));
+
+        let mut stub = StubAssembler::new(signature(1, 0));
+        stub.tail_jump(StubReg::Arg(0));
+        assert!(!unsupported(&stub));
+    }
+}
@@ -23,8 +23,9 @@ use crate::{
     settings, timing,
 };
 use regalloc::{
-    BlockIx, Function as RegallocFunction, InstIx, PrettyPrint, Range, RegAllocResult, RegClass,
-    RegUsageCollector, RegUsageMapper, Set as RegallocSet, SpillSlot, StackmapRequestInfo,
+    BlockIx, Function as RegallocFunction, InstIx, PrettyPrint, Range, RealRegUniverse,
+    RegAllocResult, RegClass, RegUsageCollector, RegUsageMapper, Set as RegallocSet, SpillSlot,
+    StackmapRequestInfo,
 };
 
 use alloc::{borrow::Cow, boxed::Box, fmt, string::String, vec::Vec};
@@ -114,6 +115,9 @@ pub struct VCode<I: VCodeInst> {
     /// Are any debug value-labels present? If not, we can skip the
     /// post-emission analysis.
     has_value_labels: bool,
+
+    /// Statistics of the final instructions. Filled in post-regalloc.
+    stats: CodeStats,
 }
 
 /// A builder for a VCode function body. This builder is designed for the
@@ -167,6 +171,8 @@ impl<I: VCodeInst> VCodeBuilder<I> {
             reftype_class,
             reftyped_vregs: vec![],
             safepoint_insns: vec![],
+            // Stack maps only describe stack slots.
+            report_reftyped_regs: false,
         };
 
         VCodeBuilder {
@@ -202,6 +208,15 @@ impl<I: VCodeInst> VCodeBuilder<I> {
         }
     }
 
+    /// Mark a VReg as holding a GC reference even though its type isn't a reference type, e.g.
+    /// because it holds an IR value marked with `DataFlowGraph::set_gc_ref()`.
+    pub fn set_vreg_reftyped(&mut self, vreg: VirtualReg) {
+        if !is_reftype(self.vcode.vreg_types[vreg.get_index()]) {
+            self.stack_map_info.reftyped_vregs.push(vreg);
+            self.vcode.have_ref_values = true;
+        }
+    }
+
     /// Are there any reference-typed values at all among the vregs?
     pub fn have_ref_values(&self) -> bool {
         self.vcode.have_ref_values()
@@ -231,7 +246,7 @@ impl<I: VCodeInst> VCodeBuilder<I> {
     /// Push an instruction for the current BB and current IR inst within the BB.
     pub fn push(&mut self, insn: I, is_safepoint: bool) {
         match insn.is_term() {
-            MachTerminator::None | MachTerminator::Ret => {}
+            MachTerminator::None | MachTerminator::Ret | MachTerminator::RetCall => {}
             MachTerminator::Uncond(target) => {
                 self.vcode.block_succs.push(BlockIx::new(target.get()));
             }
@@ -241,6 +256,10 @@ impl<I: VCodeInst> VCodeBuilder<I> {
                     .block_succs
                     .push(BlockIx::new(false_branch.get()));
             }
+            MachTerminator::Invoke(normal, landing_pad) => {
+                self.vcode.block_succs.push(BlockIx::new(normal.get()));
+                self.vcode.block_succs.push(BlockIx::new(landing_pad.get()));
+            }
             MachTerminator::Indirect(targets) => {
                 for target in targets {
                     self.vcode.block_succs.push(BlockIx::new(target.get()));
@@ -326,6 +345,7 @@ impl<I: VCodeInst> VCode<I> {
             insts_layout: RefCell::new((vec![], vec![], 0)),
             constants,
             has_value_labels: false,
+            stats: CodeStats::default(),
         }
     }
 
@@ -355,11 +375,21 @@ impl<I: VCodeInst> VCode<I> {
         self.block_ranges.len()
     }
 
+    /// Get the number of instructions.
+    pub fn num_insts(&self) -> usize {
+        self.insts.len()
+    }
+
     /// Stack frame size for the full function's body.
     pub fn frame_size(&self) -> u32 {
         self.abi.frame_size()
     }
 
+    /// Statistics of the instructions, see `CodeStats`. Only meaningful after regalloc.
+    pub fn stats(&self) -> CodeStats {
+        self.stats
+    }
+
     /// Inbound stack-args size.
     pub fn stack_args_size(&self) -> u32 {
         self.abi.stack_args_size()
@@ -373,19 +403,35 @@ impl<I: VCodeInst> VCode<I> {
 
     /// Take the results of register allocation, with a sequence of
     /// instructions including spliced fill/reload/move instructions, and replace
-    /// the VCode with them.
-    pub fn replace_insns_from_regalloc(&mut self, result: RegAllocResult<Self>) {
+    /// the VCode with them. Moves that turn out to be redundant are dropped;
+    /// unless optimizations are disabled, this includes moves found by the
+    /// analysis in `copyprop`, which needs the `reg_universe`.
+    pub fn replace_insns_from_regalloc(
+        &mut self,
+        result: RegAllocResult<Self>,
+        reg_universe: &RealRegUniverse,
+    ) {
         // Record the spillslot count and clobbered registers for the ABI/stack
         // setup code.
         self.abi.set_num_spillslots(result.num_spill_slots as usize);
         self.abi
             .set_clobbered(result.clobbered_registers.map(|r| Writable::from_reg(*r)));
 
+        // Find redundant moves at this point (we only know what is redundant
+        // once registers are allocated).
+        let redundant_moves = if self.flags().opt_level() == settings::OptLevel::None {
+            result.insns.iter().map(is_redundant_move).collect()
+        } else {
+            copyprop::find_redundant_moves(&result.insns, result.target_map.elems(), reg_universe)
+        };
+
         let mut final_insns = vec![];
         let mut final_block_ranges = vec![(0, 0); self.num_blocks()];
         let mut final_srclocs = vec![];
         let mut final_safepoint_insns = vec![];
         let mut safept_idx = 0;
+        let mut stats = CodeStats::default();
+        let mut reg_vecs = RegUsageCollector::get_empty_reg_vecs_test_framework_only(false);
 
         assert!(result.target_map.elems().len() == self.num_blocks());
         for block in 0..self.num_blocks() {
@@ -409,9 +455,7 @@ impl<I: VCodeInst> VCode<I> {
             for i in start..end {
                 let insn = &result.insns[i];
 
-                // Elide redundant moves at this point (we only know what is
-                // redundant once registers are allocated).
-                if is_redundant_move(insn) {
+                if redundant_moves[i] {
                     continue;
                 }
 
@@ -425,14 +469,37 @@ impl<I: VCodeInst> VCode<I> {
                     self.srclocs[orig_iix.get() as usize]
                 };
 
+                // Count the inserted instructions: reloads define a register, spills don't.
+                if orig_iix.is_invalid() {
+                    if insn.is_move().is_some() {
+                        stats.moves += 1;
+                    } else {
+                        reg_vecs.clear();
+                        insn.get_regs(&mut RegUsageCollector::new(&mut reg_vecs));
+                        if reg_vecs.defs.is_empty() {
+                            stats.spills += 1;
+                        } else {
+                            stats.reloads += 1;
+                        }
+                    }
+                }
+
                 // Whenever encountering a return instruction, replace it
-                // with the epilogue.
-                let is_ret = insn.is_term() == MachTerminator::Ret;
-                if is_ret {
+                // with the epilogue. A tail call is preceded by the epilogue,
+                // without its return.
+                let term = insn.is_term();
+                if term == MachTerminator::Ret {
                     let epilogue = self.abi.gen_epilogue();
                     let len = epilogue.len();
                     final_insns.extend(epilogue.into_iter());
                     final_srclocs.extend(iter::repeat(srcloc).take(len));
+                } else if term == MachTerminator::RetCall {
+                    let epilogue = self.abi.gen_tail_call_epilogue();
+                    let len = epilogue.len();
+                    final_insns.extend(epilogue.into_iter());
+                    final_srclocs.extend(iter::repeat(srcloc).take(len));
+                    final_insns.push(insn.clone());
+                    final_srclocs.push(srcloc);
                 } else {
                     final_insns.push(insn.clone());
                     final_srclocs.push(srcloc);
@@ -454,11 +521,13 @@ impl<I: VCodeInst> VCode<I> {
         }
 
         debug_assert!(final_insns.len() == final_srclocs.len());
+        stats.insts = final_insns.len() as u32;
 
         self.insts = final_insns;
         self.srclocs = final_srclocs;
         self.block_ranges = final_block_ranges;
         self.safepoint_insns = final_safepoint_insns;
+        self.stats = stats;
 
         // Save safepoint slot-lists. These will be passed to the `EmitState`
         // for the machine backend during emission so that it can do
@@ -480,6 +549,9 @@ impl<I: VCodeInst> VCode<I> {
     {
         let _tt = timing::vcode_emit();
         let mut buffer = MachBuffer::new();
+        if self.flags().preserve_block_order() {
+            buffer.disable_branch_optimizations();
+        }
         let mut state = I::State::new(&*self.abi);
         let cfg_metadata = self.flags().machine_code_cfg_info();
         let mut bb_starts: Vec<Option<CodeOffset>> = vec![];
@@ -694,6 +766,10 @@ impl<I: VCodeInst> RegallocFunction for VCode<I> {
         insn.is_included_in_clobbers()
     }
 
+    fn is_call(&self, insn: &I) -> bool {
+        insn.is_call()
+    }
+
     fn get_regs(insn: &I, collector: &mut RegUsageCollector) {
         insn.get_regs(collector)
     }
@@ -0,0 +1,431 @@
+//! Narrowing of 64-bit integer arithmetic to 32 bits.
+//!
+//! Frontends like Wasm produce a lot of 64-bit arithmetic whose upper half is never looked at,
+//! for instance because its result is only stored as 32 bits or truncated with `ireduce`. This
+//! pass rewrites such operations into their 32-bit form followed by a `uextend`, which is free on
+//! x86_64 and aarch64 since 32-bit operations clear the upper half of their destination, and
+//! which makes for shorter encodings and cheaper operations. Chains of narrowed operations use
+//! each other's 32-bit results directly, leaving the extensions dead for DCE to remove.
+//!
+//! An operation qualifies if the low 32 bits of its result only depend on the low 32 bits of its
+//! operands, and either no use observes the upper half of its result, as computed by a backward
+//! demanded-bits analysis, or the value range analysis proves the upper half to be zero.
+
+use crate::{
+    cursor::{Cursor, FuncCursor},
+    entity::SecondaryMap,
+    ir::{
+        immediates::Imm64,
+        instructions::BranchInfo,
+        types::{I32, I64},
+        DataFlowGraph, Function, Inst, InstBuilder, InstructionData, Opcode, Value, ValueDef,
+    },
+    packed_option::PackedOption,
+    simple_preopt::replace_single_result_with_alias,
+    timing,
+    value_range::ValueRanges,
+};
+use alloc::vec::Vec;
+
+const LOW_32_BITS: u64 = 0xffff_ffff;
+
+/// The mask of all the bits of values of `value`'s type.
+fn all_bits(dfg: &DataFlowGraph, value: Value) -> u64 {
+    let ty = dfg.value_type(value);
+    if ty.is_int() && ty.bits() < 64 {
+        (1 << ty.bits()) - 1
+    } else {
+        u64::MAX
+    }
+}
+
+/// The mask of all bits up to and including the highest bit in `demanded`. These are the operand
+/// bits that the `demanded` bits of a sum, difference or product depend on.
+fn low_bits_up_to(demanded: u64) -> u64 {
+    if demanded == 0 {
+        0
+    } else {
+        u64::MAX >> demanded.leading_zeros()
+    }
+}
+
+/// The bits of argument `index` of `inst` that are needed to compute the `demanded` bits of its
+/// result.
+fn arg_demanded_bits(dfg: &DataFlowGraph, inst: Inst, index: usize, demanded: u64) -> u64 {
+    match dfg[inst] {
+        InstructionData::Binary { opcode, .. } => match opcode {
+            Opcode::Iadd | Opcode::Isub | Opcode::Imul => low_bits_up_to(demanded),
+            Opcode::Band | Opcode::Bor | Opcode::Bxor => demanded,
+            _ => u64::MAX,
+        },
+        InstructionData::BinaryImm64 { opcode, imm, .. } => {
+            let imm = imm.bits() as u64;
+            let shift = imm % u64::from(dfg.ctrl_typevar(inst).bits());
+            match opcode {
+                Opcode::IaddImm | Opcode::ImulImm | Opcode::IrsubImm => low_bits_up_to(demanded),
+                Opcode::BandImm => demanded & imm,
+                Opcode::BorImm => demanded & !imm,
+                Opcode::BxorImm => demanded,
+                Opcode::IshlImm => demanded >> shift,
+                Opcode::UshrImm => demanded << shift,
+                _ => u64::MAX,
+            }
+        }
+        InstructionData::Unary { opcode, arg } => match opcode {
+            Opcode::Ineg => low_bits_up_to(demanded),
+            Opcode::Bnot | Opcode::Ireduce => demanded,
+            Opcode::Uextend => demanded & all_bits(dfg, arg),
+            Opcode::Sextend => {
+                let arg_bits = all_bits(dfg, arg);
+                if demanded & !arg_bits != 0 {
+                    // The upper bits are copies of the sign bit.
+                    (demanded & arg_bits) | (arg_bits ^ (arg_bits >> 1))
+                } else {
+                    demanded
+                }
+            }
+            _ => u64::MAX,
+        },
+        InstructionData::Ternary {
+            opcode: Opcode::Select,
+            ..
+        } if index > 0 => demanded,
+        InstructionData::Store { opcode, .. } if index == 0 => match opcode {
+            Opcode::Istore8 => 0xff,
+            Opcode::Istore16 => 0xffff,
+            Opcode::Istore32 => LOW_32_BITS,
+            _ => u64::MAX,
+        },
+        _ => u64::MAX,
+    }
+}
+
+/// Compute the bits of each value that are observed by its uses.
+fn demanded_bits(func: &Function) -> SecondaryMap<Value, u64> {
+    let dfg = &func.dfg;
+    let mut demanded = SecondaryMap::new();
+    let insts: Vec<Inst> = func
+        .layout
+        .blocks()
+        .flat_map(|block| func.layout.block_insts(block))
+        .collect();
+
+    // Demanded bits only ever grow, so this converges; block parameters of loop headers may need
+    // a few iterations.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut demand = |demanded: &mut SecondaryMap<Value, u64>, value: Value, bits: u64| {
+            let value = dfg.resolve_aliases(value);
+            let bits = demanded[value] | (bits & all_bits(dfg, value));
+            if bits != demanded[value] {
+                demanded[value] = bits;
+                changed = true;
+            }
+        };
+
+        for &inst in insts.iter().rev() {
+            let result_demanded = dfg
+                .inst_results(inst)
+                .first()
+                .map_or(0, |&result| demanded[result]);
+            let num_fixed_args = dfg.inst_fixed_args(inst).len();
+            for (index, &arg) in dfg.inst_fixed_args(inst).iter().enumerate() {
+                let bits = arg_demanded_bits(dfg, inst, index, result_demanded);
+                demand(&mut demanded, arg, bits);
+            }
+            match dfg.analyze_branch(inst) {
+                BranchInfo::SingleDest(dest, args) => {
+                    for (&arg, &param) in args.iter().zip(dfg.block_params(dest)) {
+                        let bits = demanded[param];
+                        demand(&mut demanded, arg, bits);
+                    }
+                }
+                BranchInfo::Table(..) | BranchInfo::NotABranch => {
+                    for &arg in &dfg.inst_args(inst)[num_fixed_args..] {
+                        demand(&mut demanded, arg, u64::MAX);
+                    }
+                }
+            }
+        }
+    }
+    demanded
+}
+
+/// Can `inst`, whose controlling type is `I64`, be computed with the same low 32 bits in 32 bits?
+fn is_narrowable(dfg: &DataFlowGraph, inst: Inst) -> bool {
+    match dfg[inst] {
+        InstructionData::Binary { opcode, .. } => match opcode {
+            Opcode::Iadd
+            | Opcode::Isub
+            | Opcode::Imul
+            | Opcode::Band
+            | Opcode::Bor
+            | Opcode::Bxor => true,
+            _ => false,
+        },
+        InstructionData::BinaryImm64 { opcode, imm, .. } => match opcode {
+            Opcode::IaddImm
+            | Opcode::ImulImm
+            | Opcode::IrsubImm
+            | Opcode::BandImm
+            | Opcode::BorImm
+            | Opcode::BxorImm => true,
+            // Larger shifts leave nothing but zeros in the low half.
+            Opcode::IshlImm => imm.bits() as u64 % 64 < 32,
+            _ => false,
+        },
+        InstructionData::Unary { opcode, .. } => opcode == Opcode::Ineg || opcode == Opcode::Bnot,
+        _ => false,
+    }
+}
+
+/// Get the low 32 bits of the 64-bit `value`, inserting instructions at the cursor if needed.
+fn low_half(
+    pos: &mut FuncCursor,
+    narrowed: &SecondaryMap<Value, PackedOption<Value>>,
+    value: Value,
+) -> Value {
+    let value = pos.func.dfg.resolve_aliases(value);
+    if let Some(narrow) = narrowed[value].expand() {
+        return narrow;
+    }
+    if let ValueDef::Result(def, _) = pos.func.dfg.value_def(value) {
+        match pos.func.dfg[def] {
+            InstructionData::Unary {
+                opcode: Opcode::Uextend,
+                arg,
+            }
+            | InstructionData::Unary {
+                opcode: Opcode::Sextend,
+                arg,
+            } if pos.func.dfg.value_type(arg) == I32 => return arg,
+            InstructionData::UnaryImm {
+                opcode: Opcode::Iconst,
+                imm,
+            } => return pos.ins().iconst(I32, i64::from(imm.bits() as i32)),
+            _ => {}
+        }
+    }
+    pos.ins().ireduce(I32, value)
+}
+
+/// Rewrite `inst` into its 32-bit form if possible. Returns the 32-bit result.
+fn narrow_inst(
+    pos: &mut FuncCursor,
+    demanded: &SecondaryMap<Value, u64>,
+    ranges: &mut ValueRanges,
+    narrowed: &SecondaryMap<Value, PackedOption<Value>>,
+    inst: Inst,
+) -> Option<Value> {
+    if pos.func.dfg.inst_results(inst).len() != 1
+        || pos.func.dfg.ctrl_typevar(inst) != I64
+        || !is_narrowable(&pos.func.dfg, inst)
+    {
+        return None;
+    }
+    let result = pos.func.dfg.first_result(inst);
+    if demanded[result] & !LOW_32_BITS != 0
+        && !ranges
+            .get(&pos.func.dfg, result)
+            .map_or(false, |range| range.umax() <= LOW_32_BITS)
+    {
+        return None;
+    }
+
+    pos.use_srcloc(inst);
+    let narrow_inst = match pos.func.dfg[inst] {
+        InstructionData::Binary { opcode, args } => {
+            let x = low_half(pos, narrowed, args[0]);
+            let y = low_half(pos, narrowed, args[1]);
+            pos.ins().Binary(opcode, I32, x, y).0
+        }
+        InstructionData::BinaryImm64 { opcode, arg, imm } => {
+            let x = low_half(pos, narrowed, arg);
+            let imm = Imm64::new(i64::from(imm.bits() as i32));
+            pos.ins().BinaryImm64(opcode, I32, imm, x).0
+        }
+        InstructionData::Unary { opcode, arg } => {
+            let x = low_half(pos, narrowed, arg);
+            pos.ins().Unary(opcode, I32, x).0
+        }
+        _ => unreachable!(),
+    };
+    let narrow = pos.func.dfg.first_result(narrow_inst);
+    pos.func.dfg.replace(inst).uextend(I64, narrow);
+    Some(narrow)
+}
+
+/// Make the truncations and narrow stores of a narrowed value use its 32-bit form directly.
+fn use_low_half(
+    pos: &mut FuncCursor,
+    narrowed: &SecondaryMap<Value, PackedOption<Value>>,
+    inst: Inst,
+) {
+    let arg = match pos.func.dfg.inst_fixed_args(inst).first() {
+        Some(&arg) => pos.func.dfg.resolve_aliases(arg),
+        None => return,
+    };
+    let narrow = match narrowed[arg].expand() {
+        Some(narrow) => narrow,
+        None => return,
+    };
+    match pos.func.dfg[inst] {
+        InstructionData::Unary {
+            opcode: Opcode::Ireduce,
+            ..
+        } => {
+            if pos.func.dfg.ctrl_typevar(inst) == I32 {
+                replace_single_result_with_alias(&mut pos.func.dfg, inst, narrow);
+            } else {
+                pos.func.dfg.inst_args_mut(inst)[0] = narrow;
+            }
+        }
+        InstructionData::Store {
+            opcode: Opcode::Istore32,
+            args,
+            flags,
+            offset,
+        } => {
+            pos.func
+                .dfg
+                .replace(inst)
+                .store(flags, narrow, args[1], offset);
+        }
+        InstructionData::Store {
+            opcode: Opcode::Istore8,
+            ..
+        }
+        | InstructionData::Store {
+            opcode: Opcode::Istore16,
+            ..
+        } => {
+            pos.func.dfg.inst_args_mut(inst)[0] = narrow;
+        }
+        _ => {}
+    }
+}
+
+/// The narrowing pass.
+pub fn do_narrow(func: &mut Function) {
+    let _tt = timing::narrow();
+    let demanded = demanded_bits(func);
+    let mut ranges = ValueRanges::new();
+    let mut narrowed = SecondaryMap::<Value, PackedOption<Value>>::new();
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(_block) = pos.next_block() {
+        while let Some(inst) = pos.next_inst() {
+            if let Some(narrow) = narrow_inst(&mut pos, &demanded, &mut ranges, &narrowed, inst) {
+                let result = pos.func.dfg.first_result(inst);
+                narrowed[result] = narrow.into();
+            } else {
+                use_low_half(&mut pos, &narrowed, inst);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::do_narrow;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::{types, AbiParam, Function, InstBuilder, MemFlags, Opcode};
+    use crate::settings;
+    use crate::verifier::verify_function;
+    use crate::{dce::do_dce, dominator_tree::DominatorTree, flowgraph::ControlFlowGraph};
+    use alloc::vec::Vec;
+
+    fn opcodes_and_types(func: &Function) -> Vec<(Opcode, types::Type)> {
+        let block = func.layout.entry_block().unwrap();
+        func.layout
+            .block_insts(block)
+            .map(|inst| (func.dfg[inst].opcode(), func.dfg.ctrl_typevar(inst)))
+            .collect()
+    }
+
+    #[test]
+    fn narrow_truncated_arithmetic() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I64);
+        let y = func.dfg.append_block_param(block0, types::I64);
+        let p = func.dfg.append_block_param(block0, types::I64);
+        for _ in 0..3 {
+            func.signature.params.push(AbiParam::new(types::I64));
+        }
+
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            let sum = cur.ins().iadd(x, y);
+            let scaled = cur.ins().imul_imm(sum, 3);
+            let masked = cur.ins().band_imm(scaled, 0x7fff_ffff);
+            cur.ins().istore32(MemFlags::trusted(), masked, p, 0);
+            // The upper half of `wide` is observed, so it stays 64-bit.
+            let wide = cur.ins().iadd(x, y);
+            cur.ins().store(MemFlags::trusted(), wide, p, 8);
+            cur.ins().return_(&[]);
+        }
+
+        do_narrow(&mut func);
+        verify_function(&func, &settings::Flags::new(settings::builder())).unwrap();
+        let cfg = ControlFlowGraph::with_function(&func);
+        let mut domtree = DominatorTree::with_function(&func, &cfg);
+        do_dce(&mut func, &mut domtree);
+
+        assert_eq!(
+            opcodes_and_types(&func),
+            [
+                (Opcode::Ireduce, types::I32),
+                (Opcode::Ireduce, types::I32),
+                (Opcode::Iadd, types::I32),
+                (Opcode::ImulImm, types::I32),
+                (Opcode::BandImm, types::I32),
+                (Opcode::Store, types::I32),
+                (Opcode::Iadd, types::I64),
+                (Opcode::Store, types::I64),
+                (Opcode::Return, types::INVALID),
+            ]
+        );
+    }
+
+    #[test]
+    fn narrow_known_small_values() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+        let p = func.dfg.append_block_param(block0, types::I64);
+        func.signature.params.push(AbiParam::new(types::I32));
+        func.signature.params.push(AbiParam::new(types::I64));
+
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            let byte = cur.ins().band_imm(x, 0xff);
+            let wide = cur.ins().uextend(types::I64, byte);
+            // The sum fits in 32 bits, so it can be computed in 32 bits even though all of its
+            // bits are stored.
+            let sum = cur.ins().iadd_imm(wide, 1000);
+            cur.ins().store(MemFlags::trusted(), sum, p, 0);
+            cur.ins().return_(&[]);
+        }
+
+        do_narrow(&mut func);
+        verify_function(&func, &settings::Flags::new(settings::builder())).unwrap();
+        let cfg = ControlFlowGraph::with_function(&func);
+        let mut domtree = DominatorTree::with_function(&func, &cfg);
+        do_dce(&mut func, &mut domtree);
+
+        assert_eq!(
+            opcodes_and_types(&func),
+            [
+                (Opcode::BandImm, types::I32),
+                (Opcode::IaddImm, types::I32),
+                (Opcode::Uextend, types::I64),
+                (Opcode::Store, types::I64),
+                (Opcode::Return, types::INVALID),
+            ]
+        );
+    }
+}
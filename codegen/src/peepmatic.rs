@@ -11,10 +11,11 @@ use crate::{
     },
     isa::TargetIsa,
 };
-use alloc::borrow::Cow;
+use alloc::{borrow::Cow, boxed::Box};
 use core::{
     convert::{TryFrom, TryInto},
-    iter,
+    iter, ptr,
+    sync::atomic::{AtomicPtr, Ordering},
 };
 use cranelift_codegen_shared::condcodes::IntCC;
 use peepmatic_runtime::{
@@ -24,11 +25,6 @@ use peepmatic_runtime::{
     r#type::{BitWidth, Kind, Type},
     PeepholeOptimizations, PeepholeOptimizer,
 };
-use std::{
-    boxed::Box,
-    ptr,
-    sync::atomic::{AtomicPtr, Ordering},
-};
 
 peepmatic_traits::define_parse_and_typing_rules_for_operator! {
     Opcode {
@@ -2,6 +2,9 @@
 //!
 //! One of the reason for splitting edges is to be able to insert `copy` and `regmove` instructions
 //! between a conditional branch and the following terminator.
+//!
+//! Frontends whose functions never need splitting can disable this pass with
+//! `Context::set_branch_splitting`; the verifier then checks that no edge needed splitting.
 use alloc::vec::Vec;
 
 use crate::{
@@ -13,15 +16,20 @@ use crate::{
     topo_order::TopoOrder,
 };
 
+/// Split the edges of `func` that need it, and push the blocks created for them to `new_blocks`,
+/// one per split edge.
 pub fn run(
     isa: &dyn TargetIsa,
     func: &mut Function,
     cfg: &mut ControlFlowGraph,
     domtree: &mut DominatorTree,
     topo: &mut TopoOrder,
+    new_blocks: &mut Vec<Block>,
 ) {
     let mut ctx = Context {
-        has_new_blocks: false,
+        split: true,
+        unsplit_branch: None,
+        new_blocks,
         cur: EncCursor::new(func, isa),
         domtree,
         topo,
@@ -30,9 +38,37 @@ pub fn run(
     ctx.run()
 }
 
+/// Find a branch of `func` whose edge would be split by `run`, without changing `func`.
+pub fn find_unsplit_branch(
+    isa: &dyn TargetIsa,
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    domtree: &mut DominatorTree,
+    topo: &mut TopoOrder,
+) -> Option<Inst> {
+    let mut new_blocks = Vec::new();
+    let mut ctx = Context {
+        split: false,
+        unsplit_branch: None,
+        new_blocks: &mut new_blocks,
+        cur: EncCursor::new(func, isa),
+        domtree,
+        topo,
+        cfg,
+    };
+    ctx.run();
+    ctx.unsplit_branch
+}
+
 struct Context<'a> {
-    /// True if new blocks were inserted.
-    has_new_blocks: bool,
+    /// Split the edges, or only look for an edge that needs splitting.
+    split: bool,
+
+    /// The first branch found whose edge needs splitting, when not splitting.
+    unsplit_branch: Option<Inst>,
+
+    /// The blocks inserted to split edges.
+    new_blocks: &'a mut Vec<Block>,
 
     /// Current instruction as well as reference to function and ISA.
     cur: EncCursor<'a>,
@@ -48,6 +84,10 @@ impl<'a> Context<'a> {
         // Any block order will do.
         self.topo.reset(self.cur.func.layout.blocks());
         while let Some(block) = self.topo.next(&self.cur.func.layout, self.domtree) {
+            if self.unsplit_branch.is_some() {
+                return;
+            }
+
             // Branches can only be at the last or second to last position in an extended basic
             // block.
             self.cur.goto_last_inst(block);
@@ -63,7 +103,7 @@ impl<'a> Context<'a> {
         }
 
         // If blocks were added the cfg and domtree are inconsistent and must be recomputed.
-        if self.has_new_blocks {
+        if !self.new_blocks.is_empty() {
             self.cfg.compute(&self.cur.func);
             self.domtree.compute(&self.cur.func, self.cfg);
         }
@@ -81,6 +121,11 @@ impl<'a> Context<'a> {
 
         // If there are any parameters, split the edge.
         if self.should_split_edge(target) {
+            if !self.split {
+                self.unsplit_branch = Some(branch);
+                return;
+            }
+
             // Create the block the branch will jump to.
             let new_block = self.cur.func.dfg.make_block();
 
@@ -88,7 +133,7 @@ impl<'a> Context<'a> {
             // target block.
             assert_ne!(Some(target), self.cur.layout().entry_block());
             self.cur.layout_mut().insert_block(new_block, target);
-            self.has_new_blocks = true;
+            self.new_blocks.push(new_block);
 
             // Extract the arguments of the branch instruction, split the Block parameters and the
             // branch arguments
@@ -142,9 +187,14 @@ impl<'a> Context<'a> {
 
         // If there are any parameters, split the edge.
         if self.should_split_edge(*target) {
+            if !self.split {
+                self.unsplit_branch = Some(inst);
+                return;
+            }
+
             // Create the block the branch will jump to.
             let new_block = self.cur.func.dfg.make_block();
-            self.has_new_blocks = true;
+            self.new_blocks.push(new_block);
 
             // Split the current block before its terminator, and insert a new jump instruction to
             // jump to it.
@@ -169,3 +219,98 @@ impl<'a> Context<'a> {
         self.cfg.pred_iter(target).nth(1).is_some()
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "x86")]
+mod tests {
+    use crate::{
+        cursor::{Cursor, FuncCursor},
+        ir::{types, AbiParam, ExternalName, Function, InstBuilder, Signature},
+        isa::{lookup_variant, BackendVariant, CallConv, TargetIsa},
+        result::CodegenError,
+        settings::{builder, Flags},
+        Context,
+    };
+    use alloc::boxed::Box;
+    use std::str::FromStr;
+    use target_lexicon::triple;
+
+    fn isa() -> Box<dyn TargetIsa> {
+        lookup_variant(triple!("x86_64"), BackendVariant::Legacy)
+            .expect("expect x86 ISA")
+            .finish(Flags::new(builder()))
+    }
+
+    // A function whose conditional branch passes an argument to its destination, which has
+    // several predecessors, if `branch_args` is set. Otherwise, no edge needs splitting.
+    fn function(branch_args: bool) -> Function {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        let mut func = Function::with_name_signature(ExternalName::user(0, 0), sig);
+
+        let block0 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+        let block1 = func.dfg.make_block();
+        let block2 = func.dfg.make_block();
+        let y = func.dfg.append_block_param(block2, types::I32);
+        let block3 = func.dfg.make_block();
+
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(block0);
+        if branch_args {
+            pos.ins().brnz(x, block2, &[x]);
+        } else {
+            pos.ins().brnz(x, block3, &[]);
+        }
+        pos.ins().jump(block1, &[]);
+
+        pos.insert_block(block1);
+        let one = pos.ins().iconst(types::I32, 1);
+        pos.ins().jump(block2, &[one]);
+
+        pos.insert_block(block2);
+        pos.ins().return_(&[y]);
+
+        pos.insert_block(block3);
+        pos.ins().jump(block2, &[x]);
+
+        func
+    }
+
+    #[test]
+    fn report_split_blocks() {
+        let isa = isa();
+        let mut ctx = Context::for_function(function(true));
+        ctx.compile(&*isa).expect("expected compilation");
+        assert_eq!(ctx.split_blocks().len(), 1);
+
+        ctx.clear();
+        ctx.func = function(false);
+        ctx.compile(&*isa).expect("expected compilation");
+        assert!(ctx.split_blocks().is_empty());
+    }
+
+    #[test]
+    fn disable_branch_splitting() {
+        let isa = isa();
+        let mut ctx = Context::for_function(function(false));
+        ctx.set_branch_splitting(false);
+        ctx.compile(&*isa).expect("expected compilation");
+        assert!(ctx.split_blocks().is_empty());
+
+        ctx.clear();
+        ctx.func = function(true);
+        ctx.set_branch_splitting(false);
+        match ctx.compile(&*isa) {
+            Err(CodegenError::Verifier(errors)) => assert_eq!(errors.0.len(), 1),
+            _ => panic!("expected a verifier error"),
+        }
+
+        // `clear` enables branch splitting again.
+        ctx.clear();
+        ctx.func = function(true);
+        ctx.compile(&*isa).expect("expected compilation");
+        assert_eq!(ctx.split_blocks().len(), 1);
+    }
+}
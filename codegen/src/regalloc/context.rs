@@ -7,18 +7,26 @@
 use crate::{
     dominator_tree::DominatorTree,
     flowgraph::ControlFlowGraph,
-    ir::Function,
+    ir::{Block, Function, Inst, Value},
     isa::TargetIsa,
+    loop_analysis::LoopAnalysis,
     regalloc::{
-        branch_splitting, coalescing::Coalescing, coloring::Coloring,
-        live_value_tracker::LiveValueTracker, liveness::Liveness, reload::Reload,
-        safepoint::emit_stack_maps, spilling::Spilling, virtregs::VirtRegs,
+        branch_splitting,
+        coalescing::Coalescing,
+        coloring::Coloring,
+        live_value_tracker::LiveValueTracker,
+        liveness::Liveness,
+        reload::Reload,
+        safepoint::{emit_stack_maps, LocationRequests, SafepointLocations},
+        spilling::Spilling,
+        virtregs::VirtRegs,
     },
     result::CodegenResult,
     timing,
     topo_order::TopoOrder,
     verifier::{verify_context, verify_cssa, verify_liveness, verify_locations, VerifierErrors},
 };
+use alloc::vec::Vec;
 
 /// Persistent memory allocations for register allocation.
 pub struct Context {
@@ -30,6 +38,10 @@ pub struct Context {
     spilling: Spilling,
     reload: Reload,
     coloring: Coloring,
+    loop_analysis: LoopAnalysis,
+    split_branches: bool,
+    split_blocks: Vec<Block>,
+    locations: LocationRequests,
 }
 
 impl Context {
@@ -47,6 +59,10 @@ impl Context {
             spilling: Spilling::new(),
             reload: Reload::new(),
             coloring: Coloring::new(),
+            loop_analysis: LoopAnalysis::new(),
+            split_branches: true,
+            split_blocks: Vec::new(),
+            locations: LocationRequests::new(),
         }
     }
 
@@ -60,6 +76,34 @@ impl Context {
         self.spilling.clear();
         self.reload.clear();
         self.coloring.clear();
+        self.loop_analysis.clear();
+        self.split_branches = true;
+        self.split_blocks.clear();
+        self.locations.clear();
+    }
+
+    /// Enable or disable branch splitting for the next function; see
+    /// `crate::Context::set_branch_splitting`.
+    pub fn set_branch_splitting(&mut self, enable: bool) {
+        self.split_branches = enable;
+    }
+
+    /// The blocks inserted by branch splitting in the last function allocated, one per split
+    /// edge.
+    pub fn split_blocks(&self) -> &[Block] {
+        &self.split_blocks
+    }
+
+    /// Request the locations of `values` on entry to `inst`; see
+    /// `crate::Context::request_value_locations`.
+    pub fn request_value_locations(&mut self, inst: Inst, values: &[Value]) {
+        self.locations.request(inst, values);
+    }
+
+    /// The locations of the values requested with `request_value_locations`, recorded when
+    /// allocating registers for the last function.
+    pub fn value_locations(&self) -> &[SafepointLocations] {
+        self.locations.table()
     }
 
     /// Current values liveness state.
@@ -91,7 +135,31 @@ impl Context {
         self.tracker.clear();
 
         // Pass: Split branches, add space where to add copy & regmove instructions.
-        branch_splitting::run(isa, func, cfg, domtree, &mut self.topo);
+        self.split_blocks.clear();
+        if self.split_branches {
+            branch_splitting::run(
+                isa,
+                func,
+                cfg,
+                domtree,
+                &mut self.topo,
+                &mut self.split_blocks,
+            );
+        } else if isa.flags().enable_verifier() {
+            if let Some(branch) =
+                branch_splitting::find_unsplit_branch(isa, func, cfg, domtree, &mut self.topo)
+            {
+                errors.report((
+                    branch,
+                    "edge needs splitting but branch splitting is disabled",
+                ));
+                return Err(errors.into());
+            }
+        }
+
+        // The loops of the function, for the spilling heuristics. Branch splitting may have added
+        // blocks, so a loop analysis from before register allocation can't be reused.
+        self.loop_analysis.compute(func, cfg, domtree);
 
         // Pass: Liveness analysis.
         self.liveness.compute(isa, func, cfg);
@@ -137,6 +205,7 @@ impl Context {
             isa,
             func,
             domtree,
+            &self.loop_analysis,
             &mut self.liveness,
             &self.virtregs,
             &mut self.topo,
@@ -200,22 +269,28 @@ impl Context {
         );
 
         // If there are any reference types used, encode safepoints and emit
-        // stack maps.
+        // stack maps. Record the value locations requested at the same time.
         //
         // This function runs after register allocation has taken place, meaning
         // values have locations assigned already, which is necessary for
         // creating the stack maps.
-        let safepoints_enabled = isa.flags().enable_safepoints();
-        for val in func.dfg.values() {
-            let ty = func.dfg.value_type(val);
-            if ty.lane_type().is_ref() {
-                assert!(
-                    safepoints_enabled,
-                    "reference types were found but safepoints were not enabled"
-                );
-                emit_stack_maps(func, domtree, &self.liveness, &mut self.tracker, isa);
-                break;
-            }
+        let stack_maps = func.dfg.values().any(|val| func.dfg.is_gc_ref(val));
+        if stack_maps {
+            assert!(
+                isa.flags().enable_safepoints(),
+                "reference types were found but safepoints were not enabled"
+            );
+        }
+        if stack_maps || !self.locations.is_empty() {
+            emit_stack_maps(
+                func,
+                domtree,
+                &self.liveness,
+                &mut self.tracker,
+                isa,
+                stack_maps,
+                &mut self.locations,
+            );
         }
 
         if isa.flags().enable_verifier() {
@@ -24,5 +24,5 @@ pub use self::{
     context::Context,
     diversion::{EntryRegDiversions, RegDiversions},
     register_set::RegisterSet,
-    safepoint::emit_stack_maps,
+    safepoint::SafepointLocations,
 };
@@ -32,6 +32,13 @@
 //! We maintain two separate register counts per top-level register class: base counts and
 //! transient counts. The base counts are adjusted with the `take` and `free` functions. The
 //! transient counts are adjusted with `take_transient` and `free_transient`.
+//!
+//! # Headroom
+//!
+//! A number of registers in each top-level register class can be set aside as *headroom*.
+//! `take_transient` fails when a register would have to come from the headroom, so that the
+//! spilling pass lowers register pressure further; `take_transient_from_headroom` then lets the
+//! registers that can't be freed by spilling use the headroom.
 
 // Remove once we're using the pressure tracker.
 #![allow(dead_code)]
@@ -55,6 +62,9 @@ struct TopRC {
     /// Max number of registers that can be allocated.
     limit: u32,
 
+    /// Number of registers out of `limit` that are only allocated from the headroom.
+    headroom: u32,
+
     /// Register units per register.
     width: u8,
 
@@ -69,6 +79,15 @@ impl TopRC {
     fn total_count(&self) -> u32 {
         self.base_count + self.transient_count
     }
+
+    /// Max number of registers that can be allocated, using the headroom or not.
+    fn limit(&self, from_headroom: bool) -> u32 {
+        if from_headroom {
+            self.limit
+        } else {
+            self.limit - self.headroom
+        }
+    }
 }
 
 pub struct Pressure {
@@ -82,7 +101,10 @@ pub struct Pressure {
 
 impl Pressure {
     /// Create a new register pressure tracker.
-    pub fn new(reginfo: &RegInfo, usable: &RegisterSet) -> Self {
+    ///
+    /// Up to `headroom` registers of each top-level register class are set aside as headroom,
+    /// leaving at least one register outside of it.
+    pub fn new(reginfo: &RegInfo, usable: &RegisterSet, headroom: u32) -> Self {
         let mut p = Self {
             aliased: 0,
             toprc: Default::default(),
@@ -122,6 +144,7 @@ impl Pressure {
             .zip(reginfo.classes)
         {
             toprc.limit = usable.iter(rc).len() as u32;
+            toprc.headroom = min(headroom, toprc.limit.saturating_sub(1));
             toprc.width = rc.width;
         }
 
@@ -137,6 +160,12 @@ impl Pressure {
     /// pressure should be eased in one of the returned top-level register classes before calling
     /// `can_take()` to check again.
     fn check_avail(&self, rc: RegClass) -> RegClassMask {
+        self.check_avail_within(rc, false)
+    }
+
+    /// Check for an available register in the register class `rc`, which may come from the
+    /// headroom if `from_headroom` is set.
+    fn check_avail_within(&self, rc: RegClass, from_headroom: bool) -> RegClassMask {
         let entry = match self.toprc.get(rc.toprc as usize) {
             None => return 0, // Not a pressure tracked bank.
             Some(e) => e,
@@ -144,25 +173,25 @@ impl Pressure {
         let mask = 1 << rc.toprc;
         if (self.aliased & mask) == 0 {
             // This is a simple unaliased top-level register class.
-            if entry.total_count() < entry.limit {
+            if entry.total_count() < entry.limit(from_headroom) {
                 0
             } else {
                 mask
             }
         } else {
             // This is the more complicated case. The top-level register class has aliases.
-            self.check_avail_aliased(entry)
+            self.check_avail_aliased(entry, from_headroom)
         }
     }
 
     /// Check for an available register in a top-level register class that may have aliases.
     ///
     /// This is the out-of-line slow path for `check_avail()`.
-    fn check_avail_aliased(&self, entry: &TopRC) -> RegClassMask {
+    fn check_avail_aliased(&self, entry: &TopRC, from_headroom: bool) -> RegClassMask {
         let first = usize::from(entry.first_toprc);
         let num = usize::from(entry.num_toprcs);
         let width = u32::from(entry.width);
-        let ulimit = entry.limit * width;
+        let ulimit = entry.limit(from_headroom) * width;
 
         // Count up the number of available register units.
         let mut units = 0;
@@ -228,9 +257,24 @@ impl Pressure {
 
     /// Try to increment a transient counter.
     ///
-    /// This will fail if there are not enough registers available.
+    /// This will fail if there are not enough registers available outside of the headroom.
     pub fn take_transient(&mut self, rc: RegClass) -> Result<(), RegClassMask> {
-        let mask = self.check_avail(rc);
+        self.take_transient_within(rc, false)
+    }
+
+    /// Try to increment a transient counter, using the headroom if needed.
+    ///
+    /// This will fail if there are not enough registers available at all.
+    pub fn take_transient_from_headroom(&mut self, rc: RegClass) -> Result<(), RegClassMask> {
+        self.take_transient_within(rc, true)
+    }
+
+    fn take_transient_within(
+        &mut self,
+        rc: RegClass,
+        from_headroom: bool,
+    ) -> Result<(), RegClassMask> {
+        let mask = self.check_avail_within(rc, from_headroom);
         if mask == 0 {
             if let Some(t) = self.toprc.get_mut(rc.toprc as usize) {
                 t.transient_count += 1;
@@ -304,7 +348,7 @@ mod tests {
 
         let regs = RegisterSet::new();
 
-        let mut pressure = Pressure::new(&reginfo, &regs);
+        let mut pressure = Pressure::new(&reginfo, &regs, 0);
         let mut count = 0;
         while pressure.check_avail(gpr) == 0 {
             pressure.take(gpr);
@@ -323,6 +367,36 @@ mod tests {
         assert_eq!(pressure.check_avail(s), 0);
     }
 
+    #[test]
+    fn headroom() {
+        let reginfo = INFO.borrow();
+        let gpr = rc_by_name(&reginfo, "GPR");
+        let s = rc_by_name(&reginfo, "S");
+
+        let regs = RegisterSet::new();
+
+        let mut pressure = Pressure::new(&reginfo, &regs, 4);
+        let mut count = 0;
+        while pressure.take_transient(gpr).is_ok() {
+            count += 1;
+        }
+        assert_eq!(count, 12);
+        assert_eq!(pressure.take_transient(s), Ok(()));
+        while pressure.take_transient_from_headroom(gpr).is_ok() {
+            count += 1;
+        }
+        assert_eq!(count, 16);
+        assert_eq!(pressure.check_avail(gpr), 1 << gpr.toprc);
+        pressure.reset_transient();
+        assert_eq!(pressure.check_avail(gpr), 0);
+
+        // At least one register is left outside of the headroom.
+        let mut pressure = Pressure::new(&reginfo, &regs, 100);
+        assert_eq!(pressure.take_transient(gpr), Ok(()));
+        assert_eq!(pressure.take_transient(gpr), Err(1 << gpr.toprc));
+        assert_eq!(pressure.take_transient_from_headroom(gpr), Ok(()));
+    }
+
     #[test]
     fn arm_float_bank() {
         let reginfo = INFO.borrow();
@@ -331,7 +405,7 @@ mod tests {
         let q = rc_by_name(&reginfo, "Q");
         let regs = RegisterSet::new();
 
-        let mut pressure = Pressure::new(&reginfo, &regs);
+        let mut pressure = Pressure::new(&reginfo, &regs, 0);
         assert_eq!(pressure.check_avail(s), 0);
         assert_eq!(pressure.check_avail(d), 0);
         assert_eq!(pressure.check_avail(q), 0);
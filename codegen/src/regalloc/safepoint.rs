@@ -2,12 +2,87 @@ use crate::{
     cursor::{Cursor, FuncCursor},
     dominator_tree::DominatorTree,
     inst_predicates::is_safepoint,
-    ir::{Function, InstBuilder},
+    ir::{Function, Inst, InstBuilder, Value, ValueLoc},
     isa::TargetIsa,
-    regalloc::{live_value_tracker::LiveValueTracker, liveness::Liveness},
+    regalloc::{
+        diversion::RegDiversions, live_value_tracker::LiveValueTracker, liveness::Liveness,
+    },
+    HashMap,
 };
 use alloc::vec::Vec;
 
+/// The locations of some values on entry to an instruction, after register allocation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SafepointLocations {
+    /// The instruction.
+    pub inst: Inst,
+    /// The requested values that are live on entry to `inst`, with their locations there. A
+    /// `ValueLoc::Stack` location is a spill slot or an incoming argument slot, whose offset is
+    /// known once the stack frame is laid out.
+    pub locations: Vec<(Value, ValueLoc)>,
+}
+
+/// Values whose locations are recorded at chosen instructions by `emit_stack_maps`, e.g. to
+/// deoptimize code or to take a snapshot of a function's state in a debugger.
+pub struct LocationRequests {
+    values: HashMap<Inst, Vec<Value>>,
+    table: Vec<SafepointLocations>,
+}
+
+impl LocationRequests {
+    /// Create a new, empty, set of requests.
+    pub fn new() -> Self {
+        Self {
+            values: HashMap::new(),
+            table: Vec::new(),
+        }
+    }
+
+    /// Clear the requests and the recorded locations.
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.table.clear();
+    }
+
+    /// Are there no requests?
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Request the locations of `values` on entry to `inst`.
+    pub fn request(&mut self, inst: Inst, values: &[Value]) {
+        self.values
+            .entry(inst)
+            .or_insert_with(Vec::new)
+            .extend_from_slice(values);
+    }
+
+    /// The locations recorded by the last call to `emit_stack_maps`, for the requested
+    /// instructions in layout order.
+    pub fn table(&self) -> &[SafepointLocations] {
+        &self.table
+    }
+
+    // Record the locations of the values requested at `inst`, if any, that are live there.
+    fn record(
+        &mut self,
+        func: &Function,
+        inst: Inst,
+        tracker: &LiveValueTracker,
+        divert: &RegDiversions,
+    ) {
+        if let Some(values) = self.values.get(&inst) {
+            let locations = values
+                .iter()
+                .map(|&value| func.dfg.resolve_aliases(value))
+                .filter(|&value| tracker.live().iter().any(|lv| lv.value == value))
+                .map(|value| (value, divert.get(value, &func.locations)))
+                .collect();
+            self.table.push(SafepointLocations { inst, locations });
+        }
+    }
+}
+
 fn insert_and_encode_safepoint<'f>(
     pos: &mut FuncCursor<'f>,
     tracker: &LiveValueTracker,
@@ -17,7 +92,7 @@ fn insert_and_encode_safepoint<'f>(
     let live_ref_values = tracker
         .live()
         .iter()
-        .filter(|live_value| pos.func.dfg.value_type(live_value.value).is_ref())
+        .filter(|live_value| pos.func.dfg.is_gc_ref(live_value.value))
         .map(|live_val| live_val.value)
         .collect::<Vec<_>>();
 
@@ -35,25 +110,36 @@ fn insert_and_encode_safepoint<'f>(
 
 // The emit_stack_maps() function analyzes each instruction to retrieve the liveness of
 // the defs and operands by traversing a function's blocks in layout order.
+//
+// It inserts the safepoints for the stack maps if `stack_maps` is set, and records the
+// locations requested in `locations`, replacing those recorded before.
 pub fn emit_stack_maps(
     func: &mut Function,
     domtree: &DominatorTree,
     liveness: &Liveness,
     tracker: &mut LiveValueTracker,
     isa: &dyn TargetIsa,
+    stack_maps: bool,
+    locations: &mut LocationRequests,
 ) {
     let mut curr = func.layout.entry_block();
+    let mut divert = RegDiversions::new();
+    locations.table.clear();
 
     while let Some(block) = curr {
         tracker.block_top(block, &func.dfg, liveness, &func.layout, domtree);
         tracker.drop_dead_params();
+        divert.at_block(&func.entry_diversions, block);
         let mut pos = FuncCursor::new(func);
 
         // From the top of the block, step through the instructions.
         pos.goto_top(block);
 
         while let Some(inst) = pos.next_inst() {
-            if is_safepoint(&pos.func, inst) {
+            locations.record(&pos.func, inst, tracker, &divert);
+            divert.apply(&pos.func.dfg[inst]);
+
+            if stack_maps && is_safepoint(&pos.func, inst) {
                 insert_and_encode_safepoint(&mut pos, tracker, isa);
             }
 
@@ -64,3 +150,73 @@ pub fn emit_stack_maps(
         curr = func.layout.next_block(block);
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "x86")]
+mod tests {
+    use crate::{
+        cursor::{Cursor, FuncCursor},
+        ir::{
+            types, AbiParam, ExtFuncData, ExternalName, Function, InstBuilder, Signature, ValueLoc,
+        },
+        isa::{lookup_variant, BackendVariant, CallConv},
+        settings::{builder, Flags},
+        Context,
+    };
+    use std::str::FromStr;
+    use target_lexicon::triple;
+
+    #[test]
+    fn value_locations() {
+        let isa = lookup_variant(triple!("x86_64"), BackendVariant::Legacy)
+            .expect("expect x86 ISA")
+            .finish(Flags::new(builder()));
+
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I64));
+        sig.params.push(AbiParam::new(types::I64));
+        sig.returns.push(AbiParam::new(types::I64));
+        let mut func = Function::with_name_signature(ExternalName::user(0, 0), sig.clone());
+        let callee_sig = func.import_signature(sig);
+        let callee = func.import_function(ExtFuncData {
+            name: ExternalName::user(0, 1),
+            signature: callee_sig,
+            colocated: false,
+        });
+
+        let block0 = func.dfg.make_block();
+        let a = func.dfg.append_block_param(block0, types::I64);
+        let b = func.dfg.append_block_param(block0, types::I64);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(block0);
+        let c = pos.ins().iadd(a, b);
+        let call = pos.ins().call(callee, &[a, a]);
+        let r = pos.func.dfg.first_result(call);
+        let d = pos.ins().iadd(c, r);
+        let e = pos.ins().iadd(d, b);
+        let add = pos.func.dfg.value_def(e).unwrap_inst();
+        pos.ins().return_(&[e]);
+
+        let mut ctx = Context::for_function(func);
+        ctx.request_value_locations(call, &[b, c, r]);
+        ctx.request_value_locations(add, &[c, d]);
+        ctx.compile(&*isa).expect("expected compilation");
+
+        let table = ctx.value_locations();
+        assert_eq!(table.len(), 2);
+
+        // The values live across the call are spilled, and its result isn't live before it.
+        assert_eq!(table[0].inst, call);
+        assert_eq!(table[0].locations.len(), 2);
+        for &(value, loc) in &table[0].locations {
+            assert!([b, c].contains(&value));
+            assert_eq!(loc, ctx.func.locations[value]);
+            assert!(matches!(loc, ValueLoc::Stack(_)));
+        }
+
+        // `c` is dead by then, and `d` is in a register.
+        assert_eq!(table[1].inst, add);
+        assert_eq!(table[1].locations, [(d, ctx.func.locations[d])]);
+        assert!(matches!(table[1].locations[0].1, ValueLoc::Reg(_)));
+    }
+}
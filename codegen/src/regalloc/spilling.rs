@@ -18,11 +18,13 @@
 use crate::{
     cursor::{Cursor, EncCursor},
     dominator_tree::DominatorTree,
+    entity::SecondaryMap,
     ir::{ArgumentLoc, Block, Function, Inst, InstBuilder, SigRef, Value, ValueLoc},
     isa::{
         registers::{RegClass, RegClassIndex, RegClassMask, RegUnit},
         ConstraintKind, EncInfo, RecipeConstraints, RegInfo, TargetIsa,
     },
+    loop_analysis::LoopAnalysis,
     regalloc::{
         affinity::Affinity,
         live_value_tracker::{LiveValue, LiveValueTracker},
@@ -30,11 +32,12 @@ use crate::{
         pressure::Pressure,
         virtregs::VirtRegs,
     },
+    settings::RegallocSpillAggressiveness,
     timing,
     topo_order::TopoOrder,
 };
 use alloc::vec::Vec;
-use core::fmt;
+use core::{cmp::max, fmt};
 use log::debug;
 
 /// Return a top-level register class which contains `unit`.
@@ -50,6 +53,7 @@ fn toprc_containing_regunit(unit: RegUnit, reginfo: &RegInfo) -> RegClass {
 pub struct Spilling {
     spills: Vec<Value>,
     reg_uses: Vec<RegUse>,
+    use_depths: SecondaryMap<Value, u32>,
 }
 
 /// Context data structure that gets instantiated once per pass.
@@ -63,6 +67,7 @@ struct Context<'a> {
 
     // References to contextual data structures we need.
     domtree: &'a DominatorTree,
+    loop_analysis: &'a LoopAnalysis,
     liveness: &'a mut Liveness,
     virtregs: &'a VirtRegs,
     topo: &'a mut TopoOrder,
@@ -70,6 +75,9 @@ struct Context<'a> {
     // Current register pressure.
     pressure: Pressure,
 
+    // Look ahead in the current block when picking spill candidates.
+    conservative: bool,
+
     // Values spilled for the current instruction. These values have already been removed from the
     // pressure tracker, but they are still present in the live value tracker and their affinity
     // hasn't been changed yet.
@@ -77,6 +85,9 @@ struct Context<'a> {
 
     // Uses of register values in the current instruction.
     reg_uses: &'a mut Vec<RegUse>,
+
+    // Depth of the deepest loop using each value, see `loop_depth`.
+    use_depths: &'a SecondaryMap<Value, u32>,
 }
 
 impl Spilling {
@@ -85,6 +96,7 @@ impl Spilling {
         Self {
             spills: Vec::new(),
             reg_uses: Vec::new(),
+            use_depths: SecondaryMap::new(),
         }
     }
 
@@ -92,6 +104,7 @@ impl Spilling {
     pub fn clear(&mut self) {
         self.spills.clear();
         self.reg_uses.clear();
+        self.use_depths.clear();
     }
 
     /// Run the spilling algorithm over `func`.
@@ -100,6 +113,7 @@ impl Spilling {
         isa: &dyn TargetIsa,
         func: &mut Function,
         domtree: &DominatorTree,
+        loop_analysis: &LoopAnalysis,
         liveness: &mut Liveness,
         virtregs: &VirtRegs,
         topo: &mut TopoOrder,
@@ -107,6 +121,20 @@ impl Spilling {
     ) {
         let _tt = timing::ra_spilling();
         debug!("Spilling for:\n{}", func.display(isa));
+
+        self.use_depths.clear();
+        for block in func.layout.blocks() {
+            let depth = loop_analysis.loop_depth(block);
+            if depth == 0 {
+                continue;
+            }
+            for inst in func.layout.block_insts(block) {
+                for &arg in func.dfg.inst_args(inst) {
+                    self.use_depths[arg] = max(self.use_depths[arg], depth);
+                }
+            }
+        }
+
         let reginfo = isa.register_info();
         let usable_regs = isa.allocatable_registers(func);
         let mut ctx = Context {
@@ -114,12 +142,20 @@ impl Spilling {
             reginfo: isa.register_info(),
             encinfo: isa.encoding_info(),
             domtree,
+            loop_analysis,
             liveness,
             virtregs,
             topo,
-            pressure: Pressure::new(&reginfo, &usable_regs),
+            pressure: Pressure::new(
+                &reginfo,
+                &usable_regs,
+                u32::from(isa.flags().regalloc_pressure_headroom()),
+            ),
+            conservative: isa.flags().regalloc_spill_aggressiveness()
+                == RegallocSpillAggressiveness::Conservative,
             spills: &mut self.spills,
             reg_uses: &mut self.reg_uses,
+            use_depths: &self.use_depths,
         };
         ctx.run(tracker)
     }
@@ -221,6 +257,10 @@ impl<'a> Context<'a> {
                             self.spill_reg(cand);
                         }
                         None => {
+                            if self.pressure.take_transient_from_headroom(rc).is_ok() {
+                                break 'try_take;
+                            }
+
                             // We can't spill any of the live-in registers, so we have to spill an
                             // block argument. Since the current spill metric would consider all the
                             // block arguments equal, just spill the present register.
@@ -293,11 +333,20 @@ impl<'a> Context<'a> {
                         debug!("Need {} reg from {} throughs", op.regclass, throughs.len());
                         match self.spill_candidate(mask, throughs) {
                             Some(cand) => self.spill_reg(cand),
-                            None => panic!(
-                                "Ran out of {} registers for {}",
-                                op.regclass,
-                                self.cur.display_inst(inst)
-                            ),
+                            None => {
+                                if self
+                                    .pressure
+                                    .take_transient_from_headroom(op.regclass)
+                                    .is_ok()
+                                {
+                                    break;
+                                }
+                                panic!(
+                                    "Ran out of {} registers for {}",
+                                    op.regclass,
+                                    self.cur.display_inst(inst)
+                                )
+                            }
                         }
                     }
                 }
@@ -478,11 +527,16 @@ impl<'a> Context<'a> {
                         )
                     } {
                         Some(cand) => self.spill_reg(cand),
-                        None => panic!(
-                            "Ran out of {} registers when inserting copy before {}",
-                            rc,
-                            self.cur.display_inst(inst)
-                        ),
+                        None => {
+                            if self.pressure.take_transient_from_headroom(rc).is_ok() {
+                                break;
+                            }
+                            panic!(
+                                "Ran out of {} registers when inserting copy before {}",
+                                rc,
+                                self.cur.display_inst(inst)
+                            )
+                        }
                     }
                 }
             }
@@ -504,28 +558,83 @@ impl<'a> Context<'a> {
         //
         // We know that all candidate defs dominate the current instruction, so one of them will
         // dominate the others. That is the earliest def.
-        candidates
-            .into_iter()
-            .filter_map(|lv| {
-                // Viable candidates are registers in one of the `mask` classes, and not already in
-                // the spill set.
-                if let Affinity::Reg(rci) = lv.affinity {
-                    let rc = self.reginfo.rc(rci);
-                    if (mask & (1 << rc.toprc)) != 0 && !self.spills.contains(&lv.value) {
-                        // Here, `lv` is a viable spill candidate.
-                        return Some(lv.value);
-                    }
+        //
+        // Spilling a value costs a reload at each of its uses, so values used in deeper loops
+        // than the others are only spilled as a last resort; see `loop_depth`. When spilling
+        // conservatively, the candidate whose next use in the current block is furthest away is
+        // spilled next, and the earliest def only breaks the remaining ties.
+        let viable = candidates.into_iter().filter_map(|lv| {
+            // Viable candidates are registers in one of the `mask` classes, and not already in
+            // the spill set.
+            if let Affinity::Reg(rci) = lv.affinity {
+                let rc = self.reginfo.rc(rci);
+                if (mask & (1 << rc.toprc)) != 0 && !self.spills.contains(&lv.value) {
+                    // Here, `lv` is a viable spill candidate.
+                    return Some(lv.value);
                 }
-                None
+            }
+            None
+        });
+        let rpo_cmp = |&a: &Value, &b: &Value| {
+            // Compare candidates according to the RPO of their defs.
+            self.domtree.rpo_cmp(
+                self.cur.func.dfg.value_def(a),
+                self.cur.func.dfg.value_def(b),
+                &self.cur.func.layout,
+            )
+        };
+        viable
+            .map(|v| {
+                let distance = if self.conservative {
+                    self.next_use_distance(v)
+                } else {
+                    0
+                };
+                (v, self.loop_depth(v), distance)
             })
-            .min_by(|&a, &b| {
-                // Find the minimum candidate according to the RPO of their defs.
-                self.domtree.rpo_cmp(
-                    self.cur.func.dfg.value_def(a),
-                    self.cur.func.dfg.value_def(b),
-                    &self.cur.func.layout,
-                )
+            .min_by(|(a, da, na), (b, db, nb)| {
+                da.cmp(db)
+                    .then_with(|| nb.cmp(na))
+                    .then_with(|| rpo_cmp(a, b))
             })
+            .map(|(v, _, _)| v)
+    }
+
+    // Get the depth of the deepest loop where the virtual register of `value` is defined or used,
+    // that is, where spilling `value` would insert a spill or a reload.
+    fn loop_depth(&self, value: Value) -> u32 {
+        let func = &self.cur.func;
+        self.virtregs
+            .congruence_class(&value)
+            .iter()
+            .map(|&v| {
+                let def_block = func.layout.pp_block(func.dfg.value_def(v));
+                max(self.use_depths[v], self.loop_analysis.loop_depth(def_block))
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Count the instructions after the current one in the current block up to the next use of
+    // `value`, or return `u32::MAX` if `value` isn't used again in the block.
+    fn next_use_distance(&self, value: Value) -> u32 {
+        let layout = &self.cur.func.layout;
+        let mut next = match self.cur.current_inst() {
+            Some(inst) => layout.next_inst(inst),
+            None => self
+                .cur
+                .current_block()
+                .and_then(|block| layout.first_inst(block)),
+        };
+        let mut distance = 0;
+        while let Some(inst) = next {
+            if self.cur.func.dfg.inst_args(inst).contains(&value) {
+                return distance;
+            }
+            distance += 1;
+            next = layout.next_inst(inst);
+        }
+        u32::MAX
     }
 
     /// Spill `value` immediately by
@@ -641,3 +750,86 @@ impl fmt::Display for RegUse {
         Ok(())
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "x86")]
+mod tests {
+    use crate::{
+        cursor::{Cursor, FuncCursor},
+        ir::{types, AbiParam, ExternalName, Function, InstBuilder, Opcode, Signature, Value},
+        isa::{lookup_variant, BackendVariant, CallConv, TargetIsa},
+        loop_analysis::LoopAnalysis,
+        settings::{builder, Flags},
+        Context,
+    };
+    use alloc::{boxed::Box, vec::Vec};
+    use std::str::FromStr;
+    use target_lexicon::triple;
+
+    fn isa() -> Box<dyn TargetIsa> {
+        lookup_variant(triple!("x86_64"), BackendVariant::Legacy)
+            .expect("expect x86 ISA")
+            .finish(Flags::new(builder()))
+    }
+
+    // Count the `fill` instructions inserted in loops by the reload pass.
+    fn fills_in_loops(ctx: &Context) -> usize {
+        let mut loop_analysis = LoopAnalysis::new();
+        loop_analysis.compute(&ctx.func, &ctx.cfg, &ctx.domtree);
+        ctx.func
+            .layout
+            .blocks()
+            .filter(|&block| loop_analysis.loop_depth(block) > 0)
+            .flat_map(|block| ctx.func.layout.block_insts(block))
+            .filter(|&inst| ctx.func.dfg[inst].opcode() == Opcode::Fill)
+            .count()
+    }
+
+    // A function where more values are live across a loop than there are registers, of which only
+    // `used_in_loop`, the first ones defined, are used in the loop.
+    fn loop_function(live_across: usize, used_in_loop: usize) -> Function {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I64));
+        sig.returns.push(AbiParam::new(types::I64));
+        let mut func = Function::with_name_signature(ExternalName::user(0, 0), sig);
+
+        let block0 = func.dfg.make_block();
+        let n = func.dfg.append_block_param(block0, types::I64);
+        let header = func.dfg.make_block();
+        let i = func.dfg.append_block_param(header, types::I64);
+        let exit = func.dfg.make_block();
+
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(block0);
+        let values: Vec<Value> = (0..live_across as i64)
+            .map(|k| pos.ins().iadd_imm(n, k))
+            .collect();
+        pos.ins().jump(header, &[n]);
+
+        pos.insert_block(header);
+        let mut next = i;
+        for &v in &values[..used_in_loop] {
+            next = pos.ins().isub(next, v);
+        }
+        pos.ins().brnz(next, header, &[next]);
+        pos.ins().jump(exit, &[]);
+
+        pos.insert_block(exit);
+        let mut sum = next;
+        for &v in &values {
+            sum = pos.ins().iadd(sum, v);
+        }
+        pos.ins().return_(&[sum]);
+
+        func
+    }
+
+    #[test]
+    fn keep_values_used_in_loops() {
+        let isa = isa();
+        let mut ctx = Context::for_function(loop_function(20, 3));
+        ctx.compile(&*isa).expect("expected compilation");
+        // Spilling the earliest defs would reload the values used in the loop at each iteration.
+        assert_eq!(fills_in_loops(&ctx), 0);
+    }
+}
@@ -183,13 +183,11 @@ pub fn do_remove_constant_phis(func: &mut Function, domtree: &mut DominatorTree)
         let mut summary = BlockSummary::new(SmallVec::from(formals));
 
         for inst in func.layout.block_insts(*b) {
-            let idetails = &func.dfg[inst];
             // Note that multi-dest transfers (i.e., branch tables) don't
             // carry parameters in our IR, so we only have to care about
-            // `SingleDest` here.
-            if let BranchInfo::SingleDest(dest, _) = idetails.analyze_branch(&func.dfg.value_lists)
-            {
-                let inst_var_args = func.dfg.inst_variable_args(inst);
+            // `SingleDest` here.  For invokes, the block arguments follow
+            // the call arguments.
+            if let BranchInfo::SingleDest(dest, inst_var_args) = func.dfg.analyze_branch(inst) {
                 // Skip branches/jumps that carry no params.
                 if inst_var_args.len() > 0 {
                     let mut actuals = SmallVec::<[Value; 4]>::new();
@@ -340,17 +338,16 @@ pub fn do_remove_constant_phis(func: &mut Function, domtree: &mut DominatorTree)
     // formals changed, change the actuals accordingly.  Don't scan all insns,
     // rather just visit those as listed in the summaries we prepared earlier.
     for (_src_block, summary) in &summaries {
-        for (inst, dst_block, _src_actuals) in &summary.dests {
+        for (inst, dst_block, src_actuals) in &summary.dests {
             if !need_editing.contains(dst_block) {
                 continue;
             }
 
             let old_actuals = func.dfg[*inst].take_value_list().unwrap();
             let num_old_actuals = old_actuals.len(&func.dfg.value_lists);
-            let num_fixed_actuals = func.dfg[*inst]
-                .opcode()
-                .constraints()
-                .num_fixed_value_arguments();
+            // Everything before the block arguments is kept as is; for
+            // invokes this includes the call arguments.
+            let num_fixed_actuals = num_old_actuals - src_actuals.len();
             let dst_summary = summaries.get(&dst_block).unwrap();
 
             // Check that the numbers of arguments make sense.
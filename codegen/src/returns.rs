@@ -0,0 +1,257 @@
+//! Canonicalization of the return points of a function.
+//!
+//! A function may return from any number of blocks. Depending on the embedder, a different shape
+//! is preferable for the passes that follow, which the `return_shape` setting selects:
+//!
+//! - a single exit block, which all the paths out of the function jump to, gives instrumentation
+//!   a single place to hook into and lets the epilogue be shrink-wrapped;
+//! - direct returns, where every path returns on its own, save the jump to the exit block on each
+//!   of them, and usually some code size.
+//!
+//! Functions with a `fallthrough_return` are left alone, since that instruction must remain the
+//! last one of the function.
+
+use crate::{
+    cursor::{Cursor, FuncCursor},
+    flowgraph::ControlFlowGraph,
+    ir::{Block, Function, Inst, InstBuilder, Opcode, Type, Value},
+    timing,
+};
+use alloc::vec::Vec;
+use log::debug;
+
+/// Merge the returns of the function into a single exit block, which takes the returned values
+/// as parameters and is appended to the layout. Every `return` is replaced with a jump to it.
+///
+/// Functions that return from at most one place are left unchanged.
+pub fn do_merge_returns(func: &mut Function, cfg: &mut ControlFlowGraph) {
+    let _tt = timing::canonicalize_returns();
+
+    let mut returns = Vec::new();
+    for block in func.layout.blocks() {
+        if let Some(inst) = func.layout.last_inst(block) {
+            match func.dfg[inst].opcode() {
+                Opcode::Return => returns.push(inst),
+                Opcode::FallthroughReturn => return,
+                _ => {}
+            }
+        }
+    }
+    if returns.len() < 2 {
+        return;
+    }
+
+    let exit = func.dfg.make_block();
+    let types: Vec<Type> = func
+        .dfg
+        .inst_args(returns[0])
+        .iter()
+        .map(|&arg| func.dfg.value_type(arg))
+        .collect();
+    let params: Vec<Value> = types
+        .into_iter()
+        .map(|ty| func.dfg.append_block_param(exit, ty))
+        .collect();
+
+    let mut pos = FuncCursor::new(func);
+    pos.insert_block(exit);
+    pos.ins().return_(&params);
+    cfg.recompute_block(pos.func, exit);
+
+    for inst in returns {
+        let block = pos.func.layout.inst_block(inst).unwrap();
+        let args = pos.func.dfg.inst_args(inst).to_vec();
+        pos.func.dfg.replace(inst).jump(exit, &args);
+        cfg.recompute_block(pos.func, block);
+    }
+    debug!("Merged the returns of {} into {}", pos.func.name, exit);
+}
+
+/// Replace the jumps to blocks that do nothing but return with returns of the same values. Such
+/// a block is removed once no branch targets it anymore; conditional branches and jump tables
+/// still need it.
+pub fn do_split_returns(func: &mut Function, cfg: &mut ControlFlowGraph) {
+    let _tt = timing::canonicalize_returns();
+
+    let entry = func.layout.entry_block();
+    let mut exits = Vec::new();
+    for block in func.layout.blocks() {
+        if let Some(inst) = func.layout.first_inst(block) {
+            match func.dfg[inst].opcode() {
+                Opcode::Return if Some(block) != entry => exits.push((block, inst)),
+                Opcode::FallthroughReturn => return,
+                _ => {}
+            }
+        }
+    }
+
+    for (exit, ret) in exits {
+        let params = func.dfg.block_params(exit).to_vec();
+        let ret_args: Vec<Value> = func
+            .dfg
+            .inst_args(ret)
+            .iter()
+            .map(|&arg| func.dfg.resolve_aliases(arg))
+            .collect();
+
+        let preds: Vec<(Block, Inst)> = cfg
+            .pred_iter(exit)
+            .map(|pred| (pred.block, pred.inst))
+            .collect();
+        let mut still_targeted = false;
+        for (block, inst) in preds {
+            if func.dfg[inst].opcode() != Opcode::Jump {
+                still_targeted = true;
+                continue;
+            }
+            // The returned values that aren't parameters of the exit block are defined in a block
+            // dominating it, which dominates the jump too.
+            let jump_args = func.dfg.inst_variable_args(inst);
+            let args: Vec<Value> = ret_args
+                .iter()
+                .map(|&arg| match params.iter().position(|&param| param == arg) {
+                    Some(i) => jump_args[i],
+                    None => arg,
+                })
+                .collect();
+            func.dfg.replace(inst).return_(&args);
+            cfg.recompute_block(func, block);
+        }
+
+        if !still_targeted {
+            debug!("Split the returns of {} out of {}", func.name, exit);
+            func.layout.remove_inst(ret);
+            cfg.recompute_block(func, exit);
+            func.layout.remove_block(exit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{do_merge_returns, do_split_returns};
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::flowgraph::ControlFlowGraph;
+    use crate::ir::{types, AbiParam, Function, InstBuilder, Opcode};
+    use crate::settings;
+    use crate::verifier::verify_function;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn opcodes(func: &Function) -> Vec<Vec<Opcode>> {
+        func.layout
+            .blocks()
+            .map(|block| {
+                func.layout
+                    .block_insts(block)
+                    .map(|inst| func.dfg[inst].opcode())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn check(func: &Function, cfg: &ControlFlowGraph) {
+        verify_function(func, &settings::Flags::new(settings::builder())).unwrap();
+        let expected = ControlFlowGraph::with_function(func);
+        for block in func.layout.blocks() {
+            let mut preds: Vec<_> = cfg.pred_iter(block).map(|pred| pred.inst).collect();
+            let mut expected_preds: Vec<_> =
+                expected.pred_iter(block).map(|pred| pred.inst).collect();
+            preds.sort();
+            expected_preds.sort();
+            assert_eq!(preds, expected_preds);
+        }
+    }
+
+    /// A function returning `x + 1` if `x` is zero and `x` otherwise, from two blocks.
+    fn two_returns() -> Function {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        let block2 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+        func.signature.params.push(AbiParam::new(types::I32));
+        func.signature.returns.push(AbiParam::new(types::I32));
+
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            cur.ins().brz(x, block1, &[]);
+            cur.ins().jump(block2, &[]);
+
+            cur.insert_block(block1);
+            let y = cur.ins().iadd_imm(x, 1);
+            cur.ins().return_(&[y]);
+
+            cur.insert_block(block2);
+            cur.ins().return_(&[x]);
+        }
+        func
+    }
+
+    #[test]
+    fn merge_and_split() {
+        let mut func = two_returns();
+        let mut cfg = ControlFlowGraph::with_function(&func);
+
+        do_merge_returns(&mut func, &mut cfg);
+        check(&func, &cfg);
+        assert_eq!(
+            opcodes(&func),
+            [
+                vec![Opcode::Brz, Opcode::Jump],
+                vec![Opcode::IaddImm, Opcode::Jump],
+                vec![Opcode::Jump],
+                vec![Opcode::Return]
+            ]
+        );
+
+        // Splitting the exit block gives back the original function.
+        do_split_returns(&mut func, &mut cfg);
+        check(&func, &cfg);
+        assert_eq!(opcodes(&func), opcodes(&two_returns()));
+        let block2 = func.layout.last_block().unwrap();
+        let ret = func.layout.first_inst(block2).unwrap();
+        let block0 = func.layout.entry_block().unwrap();
+        assert_eq!(func.dfg.inst_args(ret), func.dfg.block_params(block0));
+    }
+
+    #[test]
+    fn split_keeps_branch_targets() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        let block2 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+        let p = func.dfg.append_block_param(block2, types::I32);
+        func.signature.params.push(AbiParam::new(types::I32));
+        func.signature.returns.push(AbiParam::new(types::I32));
+
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            cur.ins().brz(x, block2, &[x]);
+            cur.ins().jump(block1, &[]);
+
+            cur.insert_block(block1);
+            let y = cur.ins().iadd_imm(x, 1);
+            cur.ins().jump(block2, &[y]);
+
+            cur.insert_block(block2);
+            cur.ins().return_(&[p]);
+        }
+
+        // The conditional branch still needs the exit block.
+        let mut cfg = ControlFlowGraph::with_function(&func);
+        do_split_returns(&mut func, &mut cfg);
+        check(&func, &cfg);
+        assert_eq!(
+            opcodes(&func),
+            [
+                vec![Opcode::Brz, Opcode::Jump],
+                vec![Opcode::IaddImm, Opcode::Return],
+                vec![Opcode::Return]
+            ]
+        );
+    }
+}
@@ -72,11 +72,14 @@ pub struct Setting {
     pub kind: SettingKind,
     /// The supported values of the setting (for enum values).
     pub values: Option<&'static [&'static str]>,
+    /// The default value of the setting, or `None` for presets.
+    pub default: Option<Value>,
 }
 
 /// Represents a setting value.
 ///
 /// This is used for iterating values in `Flags`.
+#[derive(Clone, Copy, Debug)]
 pub struct Value {
     /// The name of the setting associated with this value.
     pub name: &'static str,
@@ -154,16 +157,33 @@ impl Builder {
                 detail::Detail::Bool { .. } => (SettingKind::Bool, None),
                 detail::Detail::Preset => (SettingKind::Preset, None),
             };
+            let default = match kind {
+                SettingKind::Preset => None,
+                _ => Some(Value {
+                    name: d.name,
+                    detail: d.detail,
+                    values,
+                    value: template.defaults[d.offset as usize],
+                }),
+            };
 
             Setting {
                 name: d.name,
                 description: d.description,
                 kind,
                 values,
+                default,
             }
         })
     }
 
+    /// Check that `value` is a valid value for the setting `name`, without changing the builder.
+    ///
+    /// This reports the same errors as `Configurable::set`.
+    pub fn validate(&self, name: &str, value: &str) -> SetResult<()> {
+        self.clone().set(name, value)
+    }
+
     /// Set the value of a single bit.
     fn set_bit(&mut self, offset: usize, bit: u8, value: bool) {
         let byte = &mut self.bytes[offset];
@@ -407,7 +427,7 @@ pub mod detail {
     }
 
     /// The different kind of settings along with descriptor bits that depend on the kind.
-    #[derive(Clone, Copy, Hash)]
+    #[derive(Clone, Copy, Debug, Hash)]
     pub enum Detail {
         /// A boolean setting only uses one bit, numbered from LSB.
         Bool {
@@ -480,7 +500,7 @@ impl<'a> From<&'a dyn TargetIsa> for FlagsOrIsa<'a> {
 
 #[cfg(test)]
 mod tests {
-    use super::{builder, Configurable, Flags, SetError::*};
+    use super::{builder, Configurable, Flags, SetError::*, SettingKind};
     use alloc::string::ToString;
 
     #[test]
@@ -492,11 +512,22 @@ mod tests {
             r#"[shared]
 regalloc = "backtracking"
 opt_level = "none"
+return_shape = "unchanged"
+regalloc_fuel_per_inst = 0
+regalloc_pressure_headroom = 0
+regalloc_spill_aggressiveness = "eager"
 tls_model = "none"
+trap_encoding = "undefined"
 libcall_call_conv = "isa_default"
 baldrdash_prologue_words = 0
 probestack_size_log2 = 12
+probestack_strategy = "outline"
+jump_table_entries = "relative"
+max_inline_memory_ops = 4
 enable_verifier = true
+deterministic_regalloc = false
+preserve_block_order = false
+regalloc_large_functions = false
 is_pic = false
 use_colocated_libcalls = false
 avoid_div_traps = false
@@ -504,8 +535,11 @@ enable_float = true
 enable_nan_canonicalization = false
 enable_pinned_reg = false
 use_pinned_reg_as_heap_base = false
+enable_cycle_counter = false
+enable_protection_keys = false
 enable_simd = false
 enable_atomics = true
+single_threaded = false
 enable_safepoints = false
 enable_llvm_abi_extensions = false
 unwind_info = true
@@ -514,6 +548,7 @@ emit_all_ones_funcaddrs = false
 enable_probestack = true
 probestack_func_adjusts_sp = false
 enable_jump_tables = true
+allow_unaligned_memory_ops = true
 enable_heap_access_spectre_mitigation = true
 "#
         );
@@ -522,6 +557,55 @@ enable_heap_access_spectre_mitigation = true
         assert_eq!(f.baldrdash_prologue_words(), 0);
     }
 
+    #[test]
+    fn iter_defaults() {
+        let b = builder();
+        let setting = |name| b.iter().find(|s| s.name == name).unwrap();
+
+        let opt_level = setting("opt_level");
+        assert_eq!(opt_level.kind, SettingKind::Enum);
+        assert_eq!(
+            opt_level.values,
+            Some(&["none", "speed", "speed_and_size"][..])
+        );
+        assert_eq!(opt_level.default.unwrap().as_enum(), Some("none"));
+        assert_eq!(
+            setting("enable_verifier").default.unwrap().as_bool(),
+            Some(true)
+        );
+        assert_eq!(
+            setting("probestack_size_log2").default.unwrap().as_num(),
+            Some(12)
+        );
+        assert!(b
+            .iter()
+            .all(|s| s.default.is_none() == (s.kind == SettingKind::Preset)));
+    }
+
+    #[test]
+    fn validate() {
+        let mut b = builder();
+        assert_eq!(b.validate("opt_level", "speed"), Ok(()));
+        assert_eq!(
+            b.validate("not_there", "true"),
+            Err(BadName("not_there".to_string()))
+        );
+        assert_eq!(
+            b.validate("enable_simd", "best"),
+            Err(BadValue("bool".to_string()))
+        );
+        assert_eq!(
+            b.validate("probestack_size_log2", "4096"),
+            Err(BadValue("number".to_string()))
+        );
+
+        // Validating doesn't change the builder.
+        b.set("opt_level", "speed_and_size").unwrap();
+        assert_eq!(b.validate("opt_level", "none"), Ok(()));
+        let f = Flags::new(b);
+        assert_eq!(f.opt_level(), super::OptLevel::SpeedAndSize);
+    }
+
     #[test]
     fn modify_bool() {
         let mut b = builder();
@@ -12,17 +12,18 @@ use crate::{
         condcodes::{CondCode, IntCC},
         instructions::Opcode,
         types::{I32, I64},
-        Block, DataFlowGraph, Function, Inst, InstBuilder, InstructionData, Type, Value,
+        Block, DataFlowGraph, Function, Inst, InstBuilder, InstructionData, Type, Value, ValueDef,
     },
     isa::TargetIsa,
     timing,
+    value_range::ValueRanges,
 };
 
 #[inline]
 /// Replaces the unique result of the instruction inst to an alias of the given value, and
 /// replaces the instruction with a nop. Can be used only on instructions producing one unique
 /// result, otherwise will assert.
-fn replace_single_result_with_alias(dfg: &mut DataFlowGraph, inst: Inst, value: Value) {
+pub(crate) fn replace_single_result_with_alias(dfg: &mut DataFlowGraph, inst: Inst, value: Value) {
     // Replace the result value by an alias.
     let results = dfg.detach_results(inst);
     debug_assert!(results.len(&dfg.value_lists) == 1);
@@ -609,6 +610,130 @@ fn branch_order(pos: &mut FuncCursor, cfg: &mut ControlFlowGraph, block: Block,
     cfg.recompute_block(pos.func, block);
 }
 
+/// Fold away a conditional branch or trap whose condition is known from the value ranges.
+///
+/// Returns true if `inst` was removed, leaving the cursor at the preceding instruction.
+fn fold_known_condition(
+    pos: &mut FuncCursor,
+    cfg: &mut ControlFlowGraph,
+    ranges: &mut ValueRanges,
+    block: Block,
+    inst: Inst,
+) -> bool {
+    let (opcode, cond) = match pos.func.dfg[inst] {
+        InstructionData::Branch { opcode, .. } | InstructionData::CondTrap { opcode, .. } => {
+            (opcode, pos.func.dfg.inst_args(inst)[0])
+        }
+        _ => return false,
+    };
+    let cond_is_true = match ranges.get(&pos.func.dfg, cond) {
+        Some(range) if range.is_nonzero() => true,
+        Some(range) if range.is_zero() => false,
+        _ => return false,
+    };
+
+    match opcode {
+        Opcode::Brz | Opcode::Brnz if cond_is_true == (opcode == Opcode::Brnz) => {
+            // The branch is always taken: turn it into a jump, and drop the rest of the block.
+            let dest = pos.func.dfg[inst].branch_destination().unwrap();
+            let args = pos.func.dfg.inst_variable_args(inst).to_vec();
+            pos.func.dfg.replace(inst).jump(dest, &args);
+            while let Some(next) = pos.func.layout.next_inst(inst) {
+                pos.func.layout.remove_inst(next);
+            }
+            cfg.recompute_block(pos.func, block);
+            false
+        }
+        Opcode::Brz | Opcode::Brnz => {
+            pos.remove_inst_and_step_back();
+            cfg.recompute_block(pos.func, block);
+            true
+        }
+        Opcode::Trapz | Opcode::Trapnz if cond_is_true != (opcode == Opcode::Trapnz) => {
+            pos.remove_inst_and_step_back();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Narrow integer operations whose operands are known from the value ranges to fit in fewer
+/// bits.
+fn narrow_by_range(
+    pos: &mut FuncCursor,
+    ranges: &mut ValueRanges,
+    isa: &dyn TargetIsa,
+    inst: Inst,
+) {
+    match pos.func.dfg[inst] {
+        // 32-bit divisions are cheaper than 64-bit ones on most targets, and don't need a libcall
+        // on 32-bit ones.  The extension of the result is on the critical path too.
+        InstructionData::Binary { opcode, args }
+            if (opcode == Opcode::Udiv || opcode == Opcode::Urem)
+                && pos.func.dfg.ctrl_typevar(inst) == I64 =>
+        {
+            let narrow_latency =
+                isa.inst_cost(opcode, I32).latency + isa.inst_cost(Opcode::Uextend, I64).latency;
+            if narrow_latency >= isa.inst_cost(opcode, I64).latency {
+                return;
+            }
+
+            let fits_in_32_bits = |ranges: &mut ValueRanges, dfg: &DataFlowGraph, value| {
+                ranges
+                    .get(dfg, value)
+                    .map_or(false, |range| range.umax() <= u64::from(u32::MAX))
+            };
+            if !fits_in_32_bits(ranges, &pos.func.dfg, args[0])
+                || !fits_in_32_bits(ranges, &pos.func.dfg, args[1])
+            {
+                return;
+            }
+            // The narrow division traps on the same zero divisor as the original one.
+            let srcloc = pos.srcloc();
+            pos.use_srcloc(inst);
+            let x = pos.ins().ireduce(I32, args[0]);
+            let y = pos.ins().ireduce(I32, args[1]);
+            let result = if opcode == Opcode::Udiv {
+                pos.ins().udiv(x, y)
+            } else {
+                pos.ins().urem(x, y)
+            };
+            pos.set_srcloc(srcloc);
+            pos.func.dfg.replace(inst).uextend(I64, result);
+        }
+
+        // `uextend(ireduce(x))` is `x` itself if `x` fits in the narrow type.
+        InstructionData::Unary {
+            opcode: Opcode::Uextend,
+            arg,
+        } => {
+            let dfg = &pos.func.dfg;
+            let wide = match dfg.value_def(arg) {
+                ValueDef::Result(arg_inst, _) => match dfg[arg_inst] {
+                    InstructionData::Unary {
+                        opcode: Opcode::Ireduce,
+                        arg: wide,
+                    } => wide,
+                    _ => return,
+                },
+                _ => return,
+            };
+            if dfg.value_type(wide) != dfg.ctrl_typevar(inst) {
+                return;
+            }
+            let narrow_max = u64::MAX >> (64 - dfg.value_type(arg).bits());
+            if ranges
+                .get(dfg, wide)
+                .map_or(false, |range| range.umax() <= narrow_max)
+            {
+                replace_single_result_with_alias(&mut pos.func.dfg, inst, wide);
+            }
+        }
+
+        _ => {}
+    }
+}
+
 #[cfg(feature = "enable-peepmatic")]
 mod simplify {
     use super::*;
@@ -1089,6 +1214,7 @@ pub fn do_preopt(func: &mut Function, cfg: &mut ControlFlowGraph, isa: &dyn Targ
     let mut pos = FuncCursor::new(func);
     let native_word_width = isa.pointer_bytes() as u32;
     let mut optimizer = simplify::peephole_optimizer(isa);
+    let mut ranges = ValueRanges::new();
 
     while let Some(block) = pos.next_block() {
         while let Some(inst) = pos.next_inst() {
@@ -1100,7 +1226,80 @@ pub fn do_preopt(func: &mut Function, cfg: &mut ControlFlowGraph, isa: &dyn Targ
                 continue;
             }
 
+            if fold_known_condition(&mut pos, cfg, &mut ranges, block, inst) {
+                continue;
+            }
+            narrow_by_range(&mut pos, &mut ranges, isa, inst);
+
             branch_order(&mut pos, cfg, block, inst);
         }
     }
 }
+
+#[cfg(all(test, feature = "x86"))]
+mod tests {
+    use super::do_preopt;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::flowgraph::ControlFlowGraph;
+    use crate::ir::{condcodes::IntCC, types, AbiParam, Function, InstBuilder, Opcode, TrapCode};
+    use crate::isa;
+    use crate::settings;
+    use crate::verifier::verify_function;
+    use alloc::vec::Vec;
+    use core::str::FromStr;
+    use target_lexicon::triple;
+
+    #[test]
+    fn range_based_rewrites() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        let block2 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I64);
+        let y = func.dfg.append_block_param(block0, types::I32);
+        let q = func.dfg.append_block_param(block2, types::I64);
+        func.signature.params.push(AbiParam::new(types::I64));
+        func.signature.params.push(AbiParam::new(types::I32));
+        func.signature.returns.push(AbiParam::new(types::I64));
+
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            let low = cur.ins().band_imm(x, 0xffff);
+            let in_bounds = cur.ins().icmp_imm(IntCC::UnsignedLessThan, low, 0x1_0000);
+            cur.ins().trapz(in_bounds, TrapCode::HeapOutOfBounds);
+            let divisor = cur.ins().uextend(types::I64, y);
+            let quotient = cur.ins().udiv(low, divisor);
+            cur.ins().brz(in_bounds, block1, &[]);
+            cur.ins().jump(block2, &[quotient]);
+
+            cur.insert_block(block1);
+            cur.ins().return_(&[x]);
+
+            cur.insert_block(block2);
+            cur.ins().return_(&[q]);
+        }
+
+        let isa = isa::lookup(triple!("x86_64"))
+            .unwrap()
+            .finish(settings::Flags::new(settings::builder()));
+        let mut cfg = ControlFlowGraph::with_function(&func);
+        do_preopt(&mut func, &mut cfg, &*isa);
+        verify_function(&func, &*isa).unwrap();
+
+        let opcodes: Vec<Opcode> = func
+            .layout
+            .block_insts(block0)
+            .map(|inst| func.dfg[inst].opcode())
+            .collect();
+        assert!(!opcodes.contains(&Opcode::Trapz));
+        assert!(!opcodes.contains(&Opcode::Brz));
+        assert_eq!(cfg.pred_iter(block1).count(), 0);
+        let udiv = func
+            .layout
+            .block_insts(block0)
+            .find(|&inst| func.dfg[inst].opcode() == Opcode::Udiv)
+            .unwrap();
+        assert_eq!(func.dfg.ctrl_typevar(udiv), types::I32);
+    }
+}
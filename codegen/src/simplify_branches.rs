@@ -0,0 +1,448 @@
+//! Late control flow graph cleanup.
+//!
+//! The optimizations that run after legalization leave branches behind that don't do anything
+//! useful anymore: conditional branches on conditions that have become known, blocks that only
+//! jump somewhere else, and chains of blocks that could just as well be a single one. This pass
+//! cleans them up so that they don't reach the backends:
+//!
+//! - conditional branches whose outcome is known from the value ranges are turned into jumps, or
+//!   removed if they are never taken;
+//! - branches to a block containing nothing but a `jump` are retargeted to that jump's
+//!   destination;
+//! - conditional branches to the same block, with the same arguments, as the jump that follows
+//!   them are removed;
+//! - the blocks made unreachable are removed;
+//! - a block ending with a jump to a block that has no other predecessor is merged with it.
+
+use crate::{
+    cursor::{Cursor, FuncCursor},
+    dominator_tree::DominatorTree,
+    flowgraph::ControlFlowGraph,
+    ir::{Block, Function, Inst, InstBuilder, InstructionData, Opcode, Value},
+    isa::TargetIsa,
+    timing,
+    unreachable_code::eliminate_unreachable_code,
+    value_range::ValueRanges,
+};
+use alloc::vec::Vec;
+use log::debug;
+
+/// Fold the branch `inst` if its outcome is known: a branch that is always taken becomes a jump,
+/// and the rest of its block is removed, while a branch that is never taken is removed.
+///
+/// Returns true if `inst` was removed, leaving the cursor at the preceding instruction.
+fn fold_known_branch(
+    pos: &mut FuncCursor,
+    ranges: &mut ValueRanges,
+    isa: &dyn TargetIsa,
+    inst: Inst,
+) -> bool {
+    let (dest, args) = match pos.func.dfg[inst] {
+        InstructionData::Branch {
+            opcode,
+            destination,
+            ..
+        } if opcode == Opcode::Brz || opcode == Opcode::Brnz => {
+            let cond = pos.func.dfg.inst_args(inst)[0];
+            let cond_is_true = match ranges.get(&pos.func.dfg, cond) {
+                Some(range) if range.is_nonzero() => true,
+                Some(range) if range.is_zero() => false,
+                _ => return false,
+            };
+            if cond_is_true != (opcode == Opcode::Brnz) {
+                pos.remove_inst_and_step_back();
+                return true;
+            }
+            (destination, pos.func.dfg.inst_variable_args(inst).to_vec())
+        }
+        InstructionData::BranchIcmp {
+            cond, destination, ..
+        } => {
+            let (x, y) = (
+                pos.func.dfg.inst_args(inst)[0],
+                pos.func.dfg.inst_args(inst)[1],
+            );
+            let taken = match (ranges.get(&pos.func.dfg, x), ranges.get(&pos.func.dfg, y)) {
+                (Some(x), Some(y)) => x.compare(cond, y),
+                _ => None,
+            };
+            match taken {
+                Some(true) => (destination, pos.func.dfg.inst_variable_args(inst).to_vec()),
+                Some(false) => {
+                    pos.remove_inst_and_step_back();
+                    return true;
+                }
+                None => return false,
+            }
+        }
+        InstructionData::BranchTable {
+            arg,
+            destination,
+            table,
+            ..
+        } => {
+            let index = match ranges.get(&pos.func.dfg, arg).and_then(|r| r.as_constant()) {
+                Some(index) => index,
+                None => return false,
+            };
+            let entries = pos.func.jump_tables[table].as_slice();
+            let dest = if index < entries.len() as u64 {
+                entries[index as usize]
+            } else {
+                destination
+            };
+            (dest, Vec::new())
+        }
+        _ => return false,
+    };
+
+    debug!(
+        "Folding {} into a jump to {}",
+        pos.func.dfg.display_inst(inst, None),
+        dest
+    );
+    pos.func.dfg.replace(inst).jump(dest, &args);
+    let ok = pos.func.update_encoding(inst, isa).is_ok();
+    debug_assert!(ok);
+    while let Some(next) = pos.func.layout.next_inst(inst) {
+        pos.func.layout.remove_inst(next);
+    }
+    false
+}
+
+/// If `block` contains nothing but a jump, return that jump.
+fn forwarding_jump(func: &Function, block: Block) -> Option<Inst> {
+    let inst = func.layout.first_inst(block)?;
+    if func.dfg[inst].opcode() == Opcode::Jump && func.layout.entry_block() != Some(block) {
+        Some(inst)
+    } else {
+        None
+    }
+}
+
+/// Retarget the single destination branch `inst` past the blocks that only jump to another block.
+fn retarget_branch(func: &mut Function, inst: Inst) {
+    let orig_dest = match func.dfg[inst].branch_destination() {
+        Some(dest) => dest,
+        None => return,
+    };
+    let mut dest = orig_dest;
+    let mut args = func.dfg.inst_variable_args(inst).to_vec();
+    let mut visited = Vec::new();
+    while let Some(jump) = forwarding_jump(func, dest) {
+        // Cycles of forwarding blocks are infinite loops; stop anywhere on them.
+        if visited.contains(&dest) {
+            break;
+        }
+        visited.push(dest);
+
+        // The arguments of the jump are either parameters of its block, which take the value of
+        // our arguments, or values defined in a block dominating it and thus dominating `inst`.
+        let params = func.dfg.block_params(dest);
+        args = func
+            .dfg
+            .inst_args(jump)
+            .iter()
+            .map(|&arg| {
+                let arg = func.dfg.resolve_aliases(arg);
+                match params.iter().position(|&param| param == arg) {
+                    Some(n) => args[n],
+                    None => arg,
+                }
+            })
+            .collect();
+        dest = func.dfg[jump].branch_destination().unwrap();
+    }
+    if dest == orig_dest {
+        return;
+    }
+
+    debug!(
+        "Retargeting {} to {}",
+        func.dfg.display_inst(inst, None),
+        dest
+    );
+    let num_fixed = func.dfg[inst]
+        .opcode()
+        .constraints()
+        .num_fixed_value_arguments();
+    let mut list = func.dfg[inst]
+        .take_value_list()
+        .expect("Branches must have value lists.");
+    list.truncate(num_fixed, &mut func.dfg.value_lists);
+    list.extend(args, &mut func.dfg.value_lists);
+    func.dfg[inst].put_value_list(list);
+    func.change_branch_destination(inst, dest);
+}
+
+/// The destination and arguments of the single destination branch `inst`, with aliases resolved.
+fn branch_target(func: &Function, inst: Inst) -> Option<(Block, Vec<Value>)> {
+    let dest = func.dfg[inst].branch_destination()?;
+    let args = func
+        .dfg
+        .inst_variable_args(inst)
+        .iter()
+        .map(|&arg| func.dfg.resolve_aliases(arg))
+        .collect();
+    Some((dest, args))
+}
+
+/// Remove the conditional branches right before the final jump of `block` that go to the same
+/// block, with the same arguments, as the jump.
+fn merge_identical_successors(func: &mut Function, block: Block) {
+    let jump = match func.layout.last_inst(block) {
+        Some(inst) if func.dfg[inst].opcode() == Opcode::Jump => inst,
+        _ => return,
+    };
+    let target = branch_target(func, jump);
+    while let Some(branch) = func.layout.prev_inst(jump) {
+        if !func.dfg[branch].opcode().is_branch() || branch_target(func, branch) != target {
+            break;
+        }
+        debug!(
+            "Removing {}, which has the same target as the following jump",
+            func.dfg.display_inst(branch, None)
+        );
+        func.layout.remove_inst(branch);
+    }
+}
+
+/// Merge the block that `block` ends by jumping to into `block`, if `block` is its only
+/// predecessor and the jump is its only branch.
+///
+/// Returns true if the blocks were merged.
+fn merge_with_successor(func: &mut Function, cfg: &mut ControlFlowGraph, block: Block) -> bool {
+    let jump = match func.layout.last_inst(block) {
+        Some(inst) if func.dfg[inst].opcode() == Opcode::Jump => inst,
+        _ => return false,
+    };
+    // Only the final branch of a block may be followed by other instructions.
+    if let Some(prev) = func.layout.prev_inst(jump) {
+        if func.dfg[prev].opcode().is_branch() {
+            return false;
+        }
+    }
+    let dest = func.dfg[jump].branch_destination().unwrap();
+    if dest == block || func.layout.entry_block() == Some(dest) || cfg.pred_iter(dest).count() != 1
+    {
+        return false;
+    }
+
+    debug!("Merging {} into {}", dest, block);
+    let args = func.dfg.inst_variable_args(jump).to_vec();
+    func.layout.remove_inst(jump);
+    let params = func.dfg.detach_block_params(dest);
+    for (i, &param) in params
+        .as_slice(&func.dfg.value_lists)
+        .to_vec()
+        .iter()
+        .enumerate()
+    {
+        func.dfg.change_to_alias(param, args[i]);
+    }
+    while let Some(inst) = func.layout.first_inst(dest) {
+        func.layout.remove_inst(inst);
+        func.layout.append_inst(inst, block);
+    }
+
+    cfg.recompute_block(func, dest);
+    cfg.recompute_block(func, block);
+    func.layout.remove_block(dest);
+    true
+}
+
+/// The branch simplification pass.
+///
+/// This leaves `cfg` and `domtree` up to date.
+pub fn do_simplify_branches(
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    domtree: &mut DominatorTree,
+    isa: &dyn TargetIsa,
+) {
+    let _tt = timing::simplify_branches();
+    let mut ranges = ValueRanges::new();
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(block) = pos.next_block() {
+        while let Some(inst) = pos.next_inst() {
+            if fold_known_branch(&mut pos, &mut ranges, isa, inst) {
+                continue;
+            }
+            retarget_branch(pos.func, inst);
+        }
+        merge_identical_successors(pos.func, block);
+    }
+
+    cfg.compute(func);
+    domtree.compute(func, cfg);
+    eliminate_unreachable_code(func, cfg, domtree);
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(block) = pos.next_block() {
+        while merge_with_successor(pos.func, cfg, block) {}
+    }
+    domtree.compute(func, cfg);
+}
+
+#[cfg(all(test, feature = "x86"))]
+mod tests {
+    use super::do_simplify_branches;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::dominator_tree::DominatorTree;
+    use crate::flowgraph::ControlFlowGraph;
+    use crate::ir::{condcodes::IntCC, types, AbiParam, Function, InstBuilder, Opcode};
+    use crate::isa::{self, TargetIsa};
+    use crate::settings;
+    use crate::verifier::verify_function;
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use target_lexicon::triple;
+
+    fn simplify(func: &mut Function) -> Box<dyn TargetIsa> {
+        let isa = isa::lookup(triple!("x86_64"))
+            .unwrap()
+            .finish(settings::Flags::new(settings::builder()));
+        let mut cfg = ControlFlowGraph::with_function(func);
+        let mut domtree = DominatorTree::with_function(func, &cfg);
+        do_simplify_branches(func, &mut cfg, &mut domtree, &*isa);
+        verify_function(&*func, &*isa).unwrap();
+        assert!(cfg.is_valid() && domtree.is_valid());
+        isa
+    }
+
+    fn opcodes(func: &Function) -> Vec<Vec<Opcode>> {
+        func.layout
+            .blocks()
+            .map(|block| {
+                func.layout
+                    .block_insts(block)
+                    .map(|inst| func.dfg[inst].opcode())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fold_known_branches() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        let block2 = func.dfg.make_block();
+        let block3 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+        let y = func.dfg.append_block_param(block3, types::I32);
+        func.signature.params.push(AbiParam::new(types::I32));
+        func.signature.returns.push(AbiParam::new(types::I32));
+
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            let one = cur.ins().iconst(types::I32, 1);
+            cur.ins().brz(one, block1, &[]);
+            cur.ins().jump(block2, &[]);
+
+            cur.insert_block(block1);
+            cur.ins().return_(&[one]);
+
+            cur.insert_block(block2);
+            let masked = cur.ins().band_imm(x, 0xff);
+            let limit = cur.ins().iconst(types::I32, 0x100);
+            cur.ins()
+                .br_icmp(IntCC::UnsignedLessThan, masked, limit, block3, &[masked]);
+            cur.ins().jump(block1, &[]);
+
+            cur.insert_block(block3);
+            cur.ins().return_(&[y]);
+        }
+
+        simplify(&mut func);
+        assert_eq!(
+            opcodes(&func),
+            [[
+                Opcode::Iconst,
+                Opcode::BandImm,
+                Opcode::Iconst,
+                Opcode::Return
+            ]]
+        );
+    }
+
+    #[test]
+    fn merge_forwarded_successors() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        let block2 = func.dfg.make_block();
+        let block3 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+        let p = func.dfg.append_block_param(block1, types::I32);
+        let y = func.dfg.append_block_param(block3, types::I32);
+        func.signature.params.push(AbiParam::new(types::I32));
+        func.signature.returns.push(AbiParam::new(types::I32));
+
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            cur.ins().brnz(x, block1, &[x]);
+            cur.ins().jump(block2, &[]);
+
+            // Both successors of `block0` end up in `block3` with the same argument.
+            cur.insert_block(block1);
+            cur.ins().jump(block3, &[p]);
+
+            cur.insert_block(block2);
+            cur.ins().jump(block3, &[x]);
+
+            cur.insert_block(block3);
+            cur.ins().return_(&[y]);
+        }
+
+        simplify(&mut func);
+        assert_eq!(opcodes(&func), [[Opcode::Return]]);
+        let ret = func.layout.first_inst(block0).unwrap();
+        assert_eq!(func.dfg.resolve_aliases(func.dfg.inst_args(ret)[0]), x);
+    }
+
+    #[test]
+    fn forwarding_cycles() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        let block2 = func.dfg.make_block();
+        let block3 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I32);
+        func.signature.params.push(AbiParam::new(types::I32));
+
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_block(block0);
+            cur.ins().brz(x, block1, &[]);
+            cur.ins().jump(block3, &[]);
+
+            // An infinite loop made of forwarding blocks.
+            cur.insert_block(block1);
+            cur.ins().jump(block2, &[]);
+
+            cur.insert_block(block2);
+            cur.ins().jump(block1, &[]);
+
+            cur.insert_block(block3);
+            cur.ins().return_(&[]);
+        }
+
+        // The loop can't be retargeted past, but its two blocks are merged.
+        simplify(&mut func);
+        assert_eq!(
+            opcodes(&func),
+            [
+                vec![Opcode::Brz, Opcode::Jump],
+                vec![Opcode::Jump],
+                vec![Opcode::Return]
+            ]
+        );
+        let jump = func.layout.first_inst(block1).unwrap();
+        assert_eq!(func.dfg[jump].branch_destination(), Some(block1));
+    }
+}
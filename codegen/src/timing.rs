@@ -55,14 +55,20 @@ define_passes! {
     flowgraph: "Control flow graph",
     domtree: "Dominator tree",
     loop_analysis: "Loop analysis",
+    value_ranges: "Value range analysis",
     postopt: "Post-legalization rewriting",
     preopt: "Pre-legalization rewriting",
+    narrow: "Narrowing of 64-bit arithmetic",
+    induction_vars: "Induction variable rewriting",
     dce: "Dead code elimination",
     legalize: "Legalization",
+    strip_annotations: "Removal of annotations",
     gvn: "Global value numbering",
     licm: "Loop invariant code motion",
     unreachable_code: "Remove unreachable blocks",
     remove_constant_phis: "Remove constant phi-nodes",
+    simplify_branches: "Branch simplification",
+    canonicalize_returns: "Return canonicalization",
 
     vcode_lower: "VCode lowering",
     vcode_post_ra: "VCode post-register allocation finalization",
@@ -106,13 +112,13 @@ impl fmt::Display for Pass {
 /// performance-sensitive builds or restricted environments. The dummy implementation must provide
 /// `TimingToken` and `PassTimes` types and `take_current`, `add_to_current`, and `start_pass` funcs
 #[cfg(feature = "std")]
-/*
 mod details {
     use super::{Pass, DESCRIPTIONS, NUM_PASSES};
-    use log::debug;
     use core::cell::{Cell, RefCell};
-    use alloc::fmt;
+    use core::fmt;
     use core::mem;
+    use log::debug;
+    use std::thread_local;
     use std::time::{Duration, Instant};
 
     /// A timing token is responsible for timing the currently running pass. Timing starts when it
@@ -233,23 +239,36 @@ mod details {
             }
         })
     }
-}*/
+}
 
-/// Dummy `debug` implementation
+/// Dummy implementation for builds without libstd, which offer no clock to time passes with.
+/// Nothing is timed and the reported pass times are always empty.
+#[cfg(not(feature = "std"))]
 mod details {
     use super::Pass;
-    /// Dummy `TimingToken`
+    use core::fmt;
+
+    /// A dummy timing token.
     pub struct TimingToken;
-    /// Dummy `PassTimes`
+
+    /// Accumulated timing for all passes, which is always empty.
+    #[derive(Default)]
     pub struct PassTimes;
-    /// Returns dummy `PassTimes`
+
+    impl fmt::Display for PassTimes {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f, "pass timing is not available without std")
+        }
+    }
+
+    /// Returns empty `PassTimes`.
     pub fn take_current() -> PassTimes {
         PassTimes
     }
-    /// does nothing
-    pub fn add_to_current(_times: PassTimes) {}
 
-    /// does nothing
+    /// Does nothing.
+    pub fn add_to_current(_times: &PassTimes) {}
+
     pub(super) fn start_pass(_pass: Pass) -> TimingToken {
         TimingToken
     }
@@ -0,0 +1,764 @@
+//! Conservative integer range and known-bits analysis.
+//!
+//! For every scalar integer value of at most 64 bits, and every `b1` value, `ValueRanges` tracks
+//! an unsigned range and the bits known to be zero or one, derived from the instructions that
+//! define the value and its arguments. Block parameters are unconstrained, so the analysis needs
+//! no fixpoint iteration: the range of a value can be computed on demand by walking its
+//! definitions, and is cached for later queries.
+//!
+//! A range describes what the value is whenever it is computed, so it stays valid across any
+//! rewrite that preserves the meaning of the values it leaves in place. Passes can therefore keep
+//! using a `ValueRanges` while they transform the function, as long as they don't redefine the
+//! existing values.
+
+use crate::entity::SecondaryMap;
+use crate::ir::condcodes::IntCC;
+use crate::ir::{types, DataFlowGraph, Function, InstructionData, Opcode, Type, Value, ValueDef};
+use crate::timing;
+use alloc::vec::Vec;
+
+/// What is known about the bits of an integer value, interpreted as unsigned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValueRange {
+    bits: u8,
+    umin: u64,
+    umax: u64,
+    zeros: u64,
+    ones: u64,
+}
+
+/// The mask of the low `bits` bits.
+fn mask(bits: u8) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// The mask of all bits up to and including the highest set bit of `x`.
+fn up_to_highest_bit(x: u64) -> u64 {
+    if x == 0 {
+        0
+    } else {
+        u64::MAX >> x.leading_zeros()
+    }
+}
+
+/// The mask of the low `n` bits, for `n` possibly larger than 64.
+fn low_mask(n: u32) -> u64 {
+    mask(n.min(64) as u8)
+}
+
+/// The number of bits tracked for values of type `ty`, or `None` if they aren't tracked.
+fn tracked_bits(ty: Type) -> Option<u8> {
+    if ty.is_int() && !ty.is_vector() && ty.bits() <= 64 {
+        Some(ty.bits() as u8)
+    } else if ty == types::B1 {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+impl ValueRange {
+    /// Nothing is known about a value of `bits` bits.
+    pub fn unknown(bits: u8) -> Self {
+        Self {
+            bits,
+            umin: 0,
+            umax: mask(bits),
+            zeros: 0,
+            ones: 0,
+        }
+    }
+
+    /// The value of `bits` bits is the constant `value`, truncated to `bits` bits.
+    pub fn constant(bits: u8, value: u64) -> Self {
+        let value = value & mask(bits);
+        Self {
+            bits,
+            umin: value,
+            umax: value,
+            zeros: !value & mask(bits),
+            ones: value,
+        }
+    }
+
+    /// Combine a range and known bits, tightening each with what the other implies.
+    fn new(bits: u8, umin: u64, umax: u64, zeros: u64, ones: u64) -> Self {
+        let mask = mask(bits);
+        let mut zeros = zeros & mask;
+        let mut ones = ones & mask;
+        let umin = umin.max(ones);
+        let umax = umax.min(mask & !zeros);
+        if umin > umax || zeros & ones != 0 {
+            // Contradictory facts can only come up in unreachable code.
+            return Self::unknown(bits);
+        }
+        // All the values in the range share the bits above the highest bit in which its bounds
+        // differ.
+        let common = mask & !up_to_highest_bit(umin ^ umax);
+        zeros |= common & !umin;
+        ones |= common & umin;
+        if zeros & ones != 0 {
+            return Self::unknown(bits);
+        }
+        Self {
+            bits,
+            umin,
+            umax,
+            zeros,
+            ones,
+        }
+    }
+
+    /// The width of the value in bits.
+    pub fn bits(self) -> u8 {
+        self.bits
+    }
+
+    /// The smallest value the value can take, as unsigned.
+    pub fn umin(self) -> u64 {
+        self.umin
+    }
+
+    /// The largest value the value can take, as unsigned.
+    pub fn umax(self) -> u64 {
+        self.umax
+    }
+
+    /// The mask of the bits known to be zero.
+    pub fn known_zeros(self) -> u64 {
+        self.zeros
+    }
+
+    /// The mask of the bits known to be one.
+    pub fn known_ones(self) -> u64 {
+        self.ones
+    }
+
+    /// The value, if it is known exactly.
+    pub fn as_constant(self) -> Option<u64> {
+        if self.umin == self.umax {
+            Some(self.umin)
+        } else {
+            None
+        }
+    }
+
+    /// Is the value known to be zero?
+    pub fn is_zero(self) -> bool {
+        self.umax == 0
+    }
+
+    /// Is the value known to be non-zero?
+    pub fn is_nonzero(self) -> bool {
+        self.umin != 0
+    }
+
+    /// Is the sign bit known to be clear, so that signed and unsigned interpretations agree?
+    fn is_non_negative(self) -> bool {
+        self.umax <= mask(self.bits) >> 1
+    }
+
+    /// The number of low bits known to be zero.
+    fn trailing_zeros(self) -> u32 {
+        self.zeros.trailing_ones().min(u32::from(self.bits))
+    }
+
+    /// A value that is either `self` or `other`.
+    fn union(self, other: Self) -> Self {
+        Self::new(
+            self.bits,
+            self.umin.min(other.umin),
+            self.umax.max(other.umax),
+            self.zeros & other.zeros,
+            self.ones & other.ones,
+        )
+    }
+
+    /// The same value, zero-extended to `bits` bits.
+    fn uextend(self, bits: u8) -> Self {
+        Self::new(
+            bits,
+            self.umin,
+            self.umax,
+            self.zeros | (mask(bits) & !mask(self.bits)),
+            self.ones,
+        )
+    }
+
+    /// The same value, sign-extended to `bits` bits.
+    fn sextend(self, bits: u8) -> Self {
+        let high = mask(bits) & !mask(self.bits);
+        if self.is_non_negative() {
+            self.uextend(bits)
+        } else if self.umin > mask(self.bits) >> 1 {
+            Self::new(
+                bits,
+                self.umin | high,
+                self.umax | high,
+                self.zeros,
+                self.ones | high,
+            )
+        } else {
+            Self::new(bits, 0, u64::MAX, self.zeros, self.ones)
+        }
+    }
+
+    /// The same value, truncated to `bits` bits.
+    fn ireduce(self, bits: u8) -> Self {
+        if self.umax <= mask(bits) {
+            Self::new(bits, self.umin, self.umax, self.zeros, self.ones)
+        } else {
+            Self::new(bits, 0, u64::MAX, self.zeros, self.ones)
+        }
+    }
+
+    fn iadd(self, other: Self) -> Self {
+        let zeros = low_mask(self.trailing_zeros().min(other.trailing_zeros()));
+        match self.umax.checked_add(other.umax) {
+            Some(umax) if umax <= mask(self.bits) => {
+                Self::new(self.bits, self.umin + other.umin, umax, zeros, 0)
+            }
+            _ => Self::new(self.bits, 0, u64::MAX, zeros, 0),
+        }
+    }
+
+    fn isub(self, other: Self) -> Self {
+        let zeros = low_mask(self.trailing_zeros().min(other.trailing_zeros()));
+        if self.umin >= other.umax {
+            Self::new(
+                self.bits,
+                self.umin - other.umax,
+                self.umax - other.umin,
+                zeros,
+                0,
+            )
+        } else {
+            Self::new(self.bits, 0, u64::MAX, zeros, 0)
+        }
+    }
+
+    fn imul(self, other: Self) -> Self {
+        let zeros = low_mask(self.trailing_zeros() + other.trailing_zeros());
+        match self.umax.checked_mul(other.umax) {
+            Some(umax) if umax <= mask(self.bits) => {
+                Self::new(self.bits, self.umin * other.umin, umax, zeros, 0)
+            }
+            _ => Self::new(self.bits, 0, u64::MAX, zeros, 0),
+        }
+    }
+
+    fn udiv(self, other: Self) -> Self {
+        // A zero divisor traps, so the result only exists for non-zero ones.
+        Self::new(
+            self.bits,
+            self.umin / other.umax.max(1),
+            self.umax / other.umin.max(1),
+            0,
+            0,
+        )
+    }
+
+    fn urem(self, other: Self) -> Self {
+        let umax = match other.umax {
+            0 => self.umax,
+            divisor => self.umax.min(divisor - 1),
+        };
+        Self::new(self.bits, 0, umax, 0, 0)
+    }
+
+    fn band(self, other: Self) -> Self {
+        Self::new(
+            self.bits,
+            0,
+            self.umax.min(other.umax),
+            self.zeros | other.zeros,
+            self.ones & other.ones,
+        )
+    }
+
+    fn bor(self, other: Self) -> Self {
+        Self::new(
+            self.bits,
+            self.umin.max(other.umin),
+            u64::MAX,
+            self.zeros & other.zeros,
+            self.ones | other.ones,
+        )
+    }
+
+    fn bxor(self, other: Self) -> Self {
+        Self::new(
+            self.bits,
+            0,
+            u64::MAX,
+            (self.zeros & other.zeros) | (self.ones & other.ones),
+            (self.zeros & other.ones) | (self.ones & other.zeros),
+        )
+    }
+
+    fn ishl(self, amount: Self) -> Self {
+        let shift = match amount.as_constant() {
+            Some(amount) => (amount % u64::from(self.bits)) as u8,
+            None => return Self::unknown(self.bits),
+        };
+        let zeros = (self.zeros << shift) | mask(shift);
+        let ones = self.ones << shift;
+        if self.umax <= mask(self.bits) >> shift {
+            Self::new(
+                self.bits,
+                self.umin << shift,
+                self.umax << shift,
+                zeros,
+                ones,
+            )
+        } else {
+            Self::new(self.bits, 0, u64::MAX, zeros, ones)
+        }
+    }
+
+    fn ushr(self, amount: Self) -> Self {
+        let shift = match amount.as_constant() {
+            Some(amount) => (amount % u64::from(self.bits)) as u8,
+            // Shifting right never makes a value larger.
+            None => return Self::new(self.bits, 0, self.umax, 0, 0),
+        };
+        let mask = mask(self.bits);
+        Self::new(
+            self.bits,
+            self.umin >> shift,
+            self.umax >> shift,
+            (self.zeros >> shift) | (mask & !(mask >> shift)),
+            self.ones >> shift,
+        )
+    }
+
+    fn sshr(self, amount: Self) -> Self {
+        if self.is_non_negative() {
+            self.ushr(amount)
+        } else {
+            Self::unknown(self.bits)
+        }
+    }
+
+    /// Evaluate the comparison `self cond other`, if its outcome is known.
+    pub fn compare(self, cond: IntCC, other: Self) -> Option<bool> {
+        let (a, b) = (self, other);
+        match cond {
+            IntCC::Equal => {
+                if a.as_constant().is_some() && a.as_constant() == b.as_constant() {
+                    Some(true)
+                } else if a.umax < b.umin
+                    || b.umax < a.umin
+                    || a.zeros & b.ones != 0
+                    || a.ones & b.zeros != 0
+                {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            IntCC::NotEqual => a.compare(IntCC::Equal, b).map(|eq| !eq),
+            IntCC::UnsignedLessThan => {
+                if a.umax < b.umin {
+                    Some(true)
+                } else if a.umin >= b.umax {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            IntCC::UnsignedLessThanOrEqual => {
+                if a.umax <= b.umin {
+                    Some(true)
+                } else if a.umin > b.umax {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            IntCC::UnsignedGreaterThan => b.compare(IntCC::UnsignedLessThan, a),
+            IntCC::UnsignedGreaterThanOrEqual => b.compare(IntCC::UnsignedLessThanOrEqual, a),
+            IntCC::SignedLessThan
+            | IntCC::SignedLessThanOrEqual
+            | IntCC::SignedGreaterThan
+            | IntCC::SignedGreaterThanOrEqual => {
+                if a.is_non_negative() && b.is_non_negative() {
+                    a.compare(cond.unsigned(), b)
+                } else {
+                    None
+                }
+            }
+            IntCC::Overflow | IntCC::NotOverflow => None,
+        }
+    }
+
+    /// Narrow the range with the fact that the comparison `self cond other` holds.
+    ///
+    /// If the fact contradicts the range, which can only happen in unreachable code, the range
+    /// is returned unchanged.
+    fn assume(self, cond: IntCC, other: Self) -> Self {
+        let (a, b) = (self, other);
+        let (umin, umax, zeros, ones) = match cond {
+            IntCC::Equal => (b.umin, b.umax, b.zeros, b.ones),
+            IntCC::NotEqual => match b.as_constant() {
+                Some(c) if c == a.umin && c < a.umax => (c + 1, a.umax, 0, 0),
+                Some(c) if c == a.umax && c > a.umin => (a.umin, c - 1, 0, 0),
+                _ => return a,
+            },
+            IntCC::UnsignedLessThan if b.umax == 0 => return a,
+            IntCC::UnsignedLessThan => (0, b.umax - 1, 0, 0),
+            IntCC::UnsignedLessThanOrEqual => (0, b.umax, 0, 0),
+            IntCC::UnsignedGreaterThan if b.umin == mask(a.bits) => return a,
+            IntCC::UnsignedGreaterThan => (b.umin + 1, u64::MAX, 0, 0),
+            IntCC::UnsignedGreaterThanOrEqual => (b.umin, u64::MAX, 0, 0),
+            IntCC::SignedGreaterThan | IntCC::SignedGreaterThanOrEqual if b.is_non_negative() => {
+                // `self` is at least a non-negative value, so it is non-negative itself.
+                let a = a.assume(
+                    IntCC::UnsignedLessThanOrEqual,
+                    Self::constant(a.bits, mask(a.bits) >> 1),
+                );
+                return a.assume(cond.unsigned(), b);
+            }
+            IntCC::SignedLessThan
+            | IntCC::SignedLessThanOrEqual
+            | IntCC::SignedGreaterThan
+            | IntCC::SignedGreaterThanOrEqual => {
+                if a.is_non_negative() && b.is_non_negative() {
+                    return a.assume(cond.unsigned(), b);
+                }
+                return a;
+            }
+            IntCC::Overflow | IntCC::NotOverflow => return a,
+        };
+        let umin = umin.max(a.umin);
+        let umax = umax.min(a.umax);
+        let zeros = zeros | a.zeros;
+        let ones = ones | a.ones;
+        if umin > umax || zeros & ones != 0 {
+            return a;
+        }
+        let r = Self::new(a.bits, umin, umax, zeros, ones);
+        // `new` only gives up when the combined facts are contradictory.
+        if r == Self::unknown(a.bits) {
+            a
+        } else {
+            r
+        }
+    }
+}
+
+/// The ranges of the values of a function, computed on demand.
+pub struct ValueRanges {
+    ranges: SecondaryMap<Value, Option<ValueRange>>,
+
+    /// Scratch memory used by `get()`.
+    stack: Vec<Value>,
+}
+
+impl ValueRanges {
+    /// Allocate a new blank value range analysis.
+    pub fn new() -> Self {
+        Self {
+            ranges: SecondaryMap::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Allocate and compute the ranges of all the values of `func`.
+    pub fn with_function(func: &Function) -> Self {
+        let mut ranges = Self::new();
+        ranges.compute(func);
+        ranges
+    }
+
+    /// Compute the ranges of all the values of `func`, so that later queries are cheap.
+    pub fn compute(&mut self, func: &Function) {
+        let _tt = timing::value_ranges();
+        self.clear();
+        for block in func.layout.blocks() {
+            for inst in func.layout.block_insts(block) {
+                for &result in func.dfg.inst_results(inst) {
+                    self.get(&func.dfg, result);
+                }
+            }
+        }
+    }
+
+    /// Clear all the computed ranges.
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+        self.stack.clear();
+    }
+
+    /// Get the range of `value`, or `None` if values of its type aren't tracked.
+    pub fn get(&mut self, dfg: &DataFlowGraph, value: Value) -> Option<ValueRange> {
+        let value = dfg.resolve_aliases(value);
+        tracked_bits(dfg.value_type(value))?;
+
+        // Compute the ranges of the arguments of the defining instructions before the ranges of
+        // their results, depth first.
+        self.stack.push(value);
+        while let Some(&v) = self.stack.last() {
+            if self.ranges[v].is_some() {
+                self.stack.pop();
+                continue;
+            }
+            let mut pending = false;
+            if let ValueDef::Result(inst, _) = dfg.value_def(v) {
+                for &arg in dfg.inst_args(inst) {
+                    let arg = dfg.resolve_aliases(arg);
+                    if self.ranges[arg].is_none() && tracked_bits(dfg.value_type(arg)).is_some() {
+                        self.stack.push(arg);
+                        pending = true;
+                    }
+                }
+            }
+            if !pending {
+                self.ranges[v] = Some(self.eval(dfg, v));
+                self.stack.pop();
+            }
+        }
+        self.ranges[value]
+    }
+
+    /// The range of `value`, whose arguments' ranges have all been computed.
+    fn eval(&self, dfg: &DataFlowGraph, value: Value) -> ValueRange {
+        let bits = tracked_bits(dfg.value_type(value)).unwrap();
+        let unknown = ValueRange::unknown(bits);
+        let inst = match dfg.value_def(value) {
+            ValueDef::Result(inst, 0) => inst,
+            _ => return unknown,
+        };
+        if dfg.inst_results(inst).len() != 1 {
+            return unknown;
+        }
+
+        let arg = |n: usize| {
+            let arg = dfg.resolve_aliases(dfg.inst_args(inst)[n]);
+            self.ranges[arg].unwrap_or_else(|| {
+                ValueRange::unknown(tracked_bits(dfg.value_type(arg)).unwrap_or(64))
+            })
+        };
+        let constant = |imm: i64| ValueRange::constant(bits, imm as u64);
+
+        let data = &dfg[inst];
+        match *data {
+            InstructionData::UnaryImm {
+                opcode: Opcode::Iconst,
+                imm,
+            } => constant(imm.into()),
+            InstructionData::UnaryBool {
+                opcode: Opcode::Bconst,
+                imm,
+            } => constant(imm.into()),
+            InstructionData::BinaryImm64 { opcode, imm, .. } => {
+                let imm: i64 = imm.into();
+                let x = arg(0);
+                match opcode {
+                    // Negative immediates are more precisely handled as a subtraction.
+                    Opcode::IaddImm if imm < 0 => x.isub(constant(imm.wrapping_neg())),
+                    Opcode::IaddImm => x.iadd(constant(imm)),
+                    Opcode::IrsubImm => constant(imm).isub(x),
+                    Opcode::ImulImm => x.imul(constant(imm)),
+                    Opcode::UdivImm => x.udiv(constant(imm)),
+                    Opcode::UremImm => x.urem(constant(imm)),
+                    Opcode::BandImm => x.band(constant(imm)),
+                    Opcode::BorImm => x.bor(constant(imm)),
+                    Opcode::BxorImm => x.bxor(constant(imm)),
+                    Opcode::IshlImm => x.ishl(constant(imm)),
+                    Opcode::UshrImm => x.ushr(constant(imm)),
+                    Opcode::SshrImm => x.sshr(constant(imm)),
+                    _ => unknown,
+                }
+            }
+            InstructionData::Binary { opcode, .. } => {
+                let (x, y) = (arg(0), arg(1));
+                match opcode {
+                    Opcode::Iadd => x.iadd(y),
+                    Opcode::Isub => x.isub(y),
+                    Opcode::Imul => x.imul(y),
+                    Opcode::Udiv => x.udiv(y),
+                    Opcode::Urem => x.urem(y),
+                    Opcode::Band => x.band(y),
+                    Opcode::Bor => x.bor(y),
+                    Opcode::Bxor => x.bxor(y),
+                    Opcode::Ishl => x.ishl(y),
+                    Opcode::Ushr => x.ushr(y),
+                    Opcode::Sshr => x.sshr(y),
+                    _ => unknown,
+                }
+            }
+            InstructionData::Unary { opcode, .. } => match opcode {
+                Opcode::Uextend => arg(0).uextend(bits),
+                Opcode::Sextend => arg(0).sextend(bits),
+                Opcode::Ireduce => arg(0).ireduce(bits),
+                Opcode::Bint => {
+                    let b = arg(0);
+                    if b.bits() == 1 {
+                        b.uextend(bits)
+                    } else {
+                        ValueRange::new(bits, 0, 1, 0, 0)
+                    }
+                }
+                Opcode::Clz | Opcode::Ctz | Opcode::Popcnt => {
+                    ValueRange::new(bits, 0, u64::from(arg(0).bits()), 0, 0)
+                }
+                _ => unknown,
+            },
+            InstructionData::IntCompare { cond, .. } => match arg(0).compare(cond, arg(1)) {
+                Some(result) => ValueRange::constant(bits, result as u64),
+                None => unknown,
+            },
+            InstructionData::IntCompareImm {
+                opcode: Opcode::Assume,
+                cond,
+                imm,
+                ..
+            } => {
+                let imm: i64 = imm.into();
+                arg(0).assume(cond, constant(imm))
+            }
+            InstructionData::IntCompareImm { cond, imm, .. } => {
+                let x = arg(0);
+                let imm: i64 = imm.into();
+                match x.compare(cond, ValueRange::constant(x.bits(), imm as u64)) {
+                    Some(result) => ValueRange::constant(bits, result as u64),
+                    None => unknown,
+                }
+            }
+            InstructionData::Ternary {
+                opcode: Opcode::Select,
+                ..
+            } => {
+                let cond = arg(0);
+                if cond.is_nonzero() {
+                    arg(1)
+                } else if cond.is_zero() {
+                    arg(2)
+                } else {
+                    arg(1).union(arg(2))
+                }
+            }
+            InstructionData::Load { opcode, .. } => match opcode {
+                Opcode::Uload8 => ValueRange::new(bits, 0, mask(8), 0, 0),
+                Opcode::Uload16 => ValueRange::new(bits, 0, mask(16), 0, 0),
+                Opcode::Uload32 => ValueRange::new(bits, 0, mask(32), 0, 0),
+                _ => unknown,
+            },
+            _ => unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ValueRange, ValueRanges};
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::condcodes::IntCC;
+    use crate::ir::{types, Function, InstBuilder, MemFlags};
+
+    #[test]
+    fn known_bits() {
+        let r = ValueRange::new(32, 0x10, 0x1f, 0, 0);
+        assert_eq!(r.known_ones(), 0x10);
+        assert_eq!(r.known_zeros(), 0xffff_ffe0);
+
+        let r = ValueRange::new(8, 0, 0xff, 0x0f, 0);
+        assert_eq!(r.umax(), 0xf0);
+        assert_eq!(ValueRange::constant(8, 0x1ff).as_constant(), Some(0xff));
+
+        let aligned = ValueRange::unknown(64).ishl(ValueRange::constant(64, 3));
+        let r = aligned.iadd(ValueRange::constant(64, 16));
+        assert_eq!(r.known_zeros() & 7, 7);
+    }
+
+    #[test]
+    fn compare() {
+        let small = ValueRange::new(32, 0, 100, 0, 0);
+        let big = ValueRange::new(32, 200, 300, 0, 0);
+        assert_eq!(small.compare(IntCC::UnsignedLessThan, big), Some(true));
+        assert_eq!(small.compare(IntCC::SignedGreaterThan, big), Some(false));
+        assert_eq!(small.compare(IntCC::Equal, big), Some(false));
+        assert_eq!(small.compare(IntCC::Equal, small), None);
+
+        let negative = ValueRange::new(32, 0x8000_0000, 0xffff_ffff, 0, 0);
+        assert_eq!(small.compare(IntCC::SignedLessThan, negative), None);
+        assert_eq!(small.compare(IntCC::UnsignedLessThan, negative), Some(true));
+    }
+
+    #[test]
+    fn ranges_of_values() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I64);
+        let y = func.dfg.append_block_param(block0, types::I32);
+
+        let mut cur = FuncCursor::new(&mut func);
+        cur.insert_block(block0);
+        let byte = cur.ins().uload8(types::I32, MemFlags::trusted(), x, 0);
+        let idx = cur.ins().uextend(types::I64, byte);
+        let scaled = cur.ins().imul_imm(idx, 8);
+        let offset = cur.ins().iadd_imm(scaled, 16);
+        let masked = cur.ins().band_imm(y, 0xf0);
+        let shifted = cur.ins().ushr_imm(masked, 4);
+        let cmp = cur.ins().icmp_imm(IntCC::UnsignedLessThan, shifted, 16);
+        let unknown = cur.ins().icmp_imm(IntCC::UnsignedLessThan, y, 16);
+        let wide = cur.ins().uextend(types::I64, y);
+        let narrow = cur.ins().ireduce(types::I32, wide);
+        cur.ins().return_(&[]);
+
+        let func = cur.func;
+        let mut ranges = ValueRanges::new();
+        let r = ranges.get(&func.dfg, offset).unwrap();
+        assert_eq!((r.umin(), r.umax()), (16, 255 * 8 + 16));
+        assert_eq!(r.known_zeros() & 7, 7);
+
+        let r = ranges.get(&func.dfg, shifted).unwrap();
+        assert_eq!((r.umin(), r.umax()), (0, 15));
+        assert_eq!(ranges.get(&func.dfg, cmp).unwrap().as_constant(), Some(1));
+        assert_eq!(ranges.get(&func.dfg, unknown).unwrap().as_constant(), None);
+        assert_eq!(ranges.get(&func.dfg, x).unwrap(), ValueRange::unknown(64));
+        assert_eq!(
+            ranges.get(&func.dfg, narrow).unwrap(),
+            ValueRange::unknown(32)
+        );
+
+        let r = ranges.get(&func.dfg, wide).unwrap();
+        assert_eq!(r.umax(), 0xffff_ffff);
+    }
+
+    #[test]
+    fn assumed_ranges() {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let x = func.dfg.append_block_param(block0, types::I64);
+        let y = func.dfg.append_block_param(block0, types::I32);
+
+        let mut cur = FuncCursor::new(&mut func);
+        cur.insert_block(block0);
+        let small = cur.ins().assume(IntCC::UnsignedLessThan, x, 100);
+        let positive = cur.ins().assume(IntCC::SignedGreaterThan, y, 0);
+        let cmp = cur.ins().icmp_imm(IntCC::SignedGreaterThan, positive, 0);
+        let other = cur.ins().assume(IntCC::NotEqual, y, 0);
+        let loaded = cur.ins().uload8(types::I32, MemFlags::trusted(), small, 0);
+        let at_least = cur
+            .ins()
+            .assume(IntCC::UnsignedGreaterThanOrEqual, loaded, 0x10);
+        cur.ins().return_(&[]);
+
+        let func = cur.func;
+        let mut ranges = ValueRanges::new();
+        let r = ranges.get(&func.dfg, small).unwrap();
+        assert_eq!((r.umin(), r.umax()), (0, 99));
+        let r = ranges.get(&func.dfg, positive).unwrap();
+        assert_eq!((r.umin(), r.umax()), (1, 0x7fff_ffff));
+        assert_eq!(ranges.get(&func.dfg, cmp).unwrap().as_constant(), Some(1));
+        assert!(ranges.get(&func.dfg, other).unwrap().is_nonzero());
+        let r = ranges.get(&func.dfg, at_least).unwrap();
+        assert_eq!((r.umin(), r.umax()), (0x10, 0xff));
+    }
+}
@@ -28,6 +28,12 @@
 //! - All branches to a block must be present in the CFG.
 //! - A recomputed dominator tree is identical to the existing one.
 //!
+//! Exceptional control flow
+//!
+//! - Landing pads, i.e. the blocks invokes unwind to, can only be reached by unwinding.
+//! - A `landing_pad` instruction must be the first instruction of a landing pad.
+//! - The results of an invoke can't be used where it may have unwound.
+//!
 //! Type checking
 //!
 //! - Compare input and output values against the opcode's type constraints.
@@ -387,6 +393,14 @@ impl<'a> Verifier<'a> {
                         }
                     }
                 }
+                ir::GlobalValueData::VScale { global_type } => {
+                    if !global_type.is_int() {
+                        errors.report((
+                            gv,
+                            format!("vscale global value with non-int type {}", global_type),
+                        ));
+                    }
+                }
                 ir::GlobalValueData::Load { base, .. } => {
                     if let Some(isa) = self.isa {
                         let base_type = self.func.global_values[base].global_type(isa);
@@ -410,6 +424,47 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
+    fn verify_stack_limit(&self, errors: &mut VerifierErrors) -> VerifierStepResult<()> {
+        let gv = match self.func.stack_limit {
+            Some(gv) => gv,
+            None => return Ok(()),
+        };
+        if !self.func.global_values.is_valid(gv) {
+            return errors.nonfatal((gv, format!("invalid stack limit global value {}", gv)));
+        }
+
+        if self
+            .func
+            .signature
+            .special_param_index(ir::ArgumentPurpose::StackLimit)
+            .is_some()
+        {
+            errors.report((
+                gv,
+                format!(
+                    "stack limit {} given as both a global value and a parameter",
+                    gv
+                ),
+            ));
+        }
+
+        if let Some(isa) = self.isa {
+            let limit_type = self.func.global_values[gv].global_type(isa);
+            let pointer_type = isa.pointer_type();
+            if limit_type != pointer_type {
+                errors.report((
+                    gv,
+                    format!(
+                        "stack limit {} has type {}, which is not the pointer type {}",
+                        gv, limit_type, pointer_type
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     fn verify_heaps(&self, errors: &mut VerifierErrors) -> VerifierStepResult<()> {
         if let Some(isa) = self.isa {
             for (heap, heap_data) in &self.func.heaps {
@@ -430,7 +485,9 @@ impl<'a> Verifier<'a> {
                     ));
                 }
 
-                if let ir::HeapStyle::Dynamic { bound_gv, .. } = heap_data.style {
+                if let ir::HeapStyle::Dynamic { bound_gv, .. }
+                | ir::HeapStyle::DynamicBounded { bound_gv, .. } = heap_data.style
+                {
                     if !self.func.global_values.is_valid(bound_gv) {
                         return errors
                             .nonfatal((heap, format!("invalid bound global value {}", bound_gv)));
@@ -454,6 +511,30 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
+    fn verify_gc_refs(&self, errors: &mut VerifierErrors) -> VerifierStepResult<()> {
+        for value in self.func.dfg.values() {
+            let ty = self.func.dfg.value_type(value);
+            if ty.is_ref() || !self.func.dfg.is_gc_ref(value) {
+                continue;
+            }
+            let ok = match self.isa {
+                Some(isa) => ty == isa.pointer_type(),
+                None => ty.is_int() && !ty.is_vector(),
+            };
+            if !ok {
+                errors.report((
+                    value,
+                    format!(
+                        "{} is marked as a GC reference, but has non-pointer type {}",
+                        value, ty
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     fn verify_tables(&self, errors: &mut VerifierErrors) -> VerifierStepResult<()> {
         if let Some(isa) = self.isa {
             for (table, table_data) in &self.func.tables {
@@ -586,7 +667,7 @@ impl<'a> Verifier<'a> {
         let num_fixed_results = inst_data.opcode().constraints().num_fixed_results();
         // var_results is 0 if we aren't a call instruction
         let var_results = dfg
-            .call_signature(inst)
+            .non_tail_call_signature(inst)
             .map_or(0, |sig| dfg.signatures[sig].returns.len());
         let total_results = num_fixed_results + var_results;
 
@@ -686,6 +767,26 @@ impl<'a> Verifier<'a> {
                 self.verify_sig_ref(inst, sig_ref, errors)?;
                 self.verify_value_list(inst, args, errors)?;
             }
+            Invoke {
+                func_ref,
+                destination,
+                ref args,
+                ..
+            } => {
+                self.verify_func_ref(inst, func_ref, errors)?;
+                self.verify_block(inst, destination, errors)?;
+                self.verify_value_list(inst, args, errors)?;
+            }
+            InvokeIndirect {
+                sig_ref,
+                destination,
+                ref args,
+                ..
+            } => {
+                self.verify_sig_ref(inst, sig_ref, errors)?;
+                self.verify_block(inst, destination, errors)?;
+                self.verify_value_list(inst, args, errors)?;
+            }
             FuncAddr { func_ref, .. } => {
                 self.verify_func_ref(inst, func_ref, errors)?;
             }
@@ -737,6 +838,48 @@ impl<'a> Verifier<'a> {
                     ));
                 }
             }
+            NullAry {
+                opcode: Opcode::GetCycleCounter,
+            } => {
+                if let Some(isa) = &self.isa {
+                    if !isa.flags().enable_cycle_counter() {
+                        return errors.fatal((
+                            inst,
+                            self.context(inst),
+                            "GetCycleCounter cannot be used without enable_cycle_counter",
+                        ));
+                    }
+                } else {
+                    return errors.fatal((
+                        inst,
+                        self.context(inst),
+                        "GetCycleCounter needs an ISA!",
+                    ));
+                }
+            }
+            NullAry {
+                opcode: Opcode::GetPkeyRights,
+            }
+            | Unary {
+                opcode: Opcode::SetPkeyRights,
+                ..
+            } => {
+                if let Some(isa) = &self.isa {
+                    if !isa.flags().enable_protection_keys() {
+                        return errors.fatal((
+                            inst,
+                            self.context(inst),
+                            "GetPkeyRights/SetPkeyRights cannot be used without enable_protection_keys",
+                        ));
+                    }
+                } else {
+                    return errors.fatal((
+                        inst,
+                        self.context(inst),
+                        "GetPkeyRights/SetPkeyRights need an ISA!",
+                    ));
+                }
+            }
             Unary {
                 opcode: Opcode::Bitcast,
                 arg,
@@ -754,6 +897,7 @@ impl<'a> Verifier<'a> {
             // Exhaustive list so we can't forget to add new formats
             AtomicCas { .. }
             | AtomicRmw { .. }
+            | Fence { .. }
             | LoadNoOffset { .. }
             | StoreNoOffset { .. }
             | Unary { .. }
@@ -1002,6 +1146,21 @@ impl<'a> Verifier<'a> {
                             format!("uses value {} from itself", v),
                         ));
                     }
+                    // The results of an invoke are only defined when the callee returns, i.e.
+                    // after the jump to its normal successor.
+                    if dfg[def_inst].opcode().is_invoke() {
+                        let defined = self.func.layout.next_inst(def_inst).map_or(false, |jump| {
+                            self.expected_domtree
+                                .dominates(jump, loc_inst, &self.func.layout)
+                        });
+                        if !defined {
+                            return errors.fatal((
+                                loc_inst,
+                                self.context(loc_inst),
+                                format!("uses value {} where {} may have unwound", v, def_inst),
+                            ));
+                        }
+                    }
                 }
             }
             ValueDef::Param(block, _) => {
@@ -1234,6 +1393,7 @@ impl<'a> Verifier<'a> {
         let _ = self.typecheck_fixed_args(inst, ctrl_type, errors);
         let _ = self.typecheck_variable_args(inst, errors);
         let _ = self.typecheck_return(inst, errors);
+        let _ = self.typecheck_tail_call(inst, errors);
         let _ = self.typecheck_special(inst, ctrl_type, errors);
 
         // Misuses of copy_nop instructions are fatal
@@ -1330,14 +1490,14 @@ impl<'a> Verifier<'a> {
         errors: &mut VerifierErrors,
     ) -> VerifierStepResult<()> {
         match self.func.dfg.analyze_branch(inst) {
-            BranchInfo::SingleDest(block, _) => {
+            BranchInfo::SingleDest(block, args) => {
                 let iter = self
                     .func
                     .dfg
                     .block_params(block)
                     .iter()
                     .map(|&v| self.func.dfg.value_type(v));
-                self.typecheck_variable_args_iterator(inst, iter, errors)?;
+                self.typecheck_variable_args_iterator(inst, args, iter, errors)?;
             }
             BranchInfo::Table(table, block) => {
                 if let Some(block) = block {
@@ -1370,22 +1530,22 @@ impl<'a> Verifier<'a> {
             BranchInfo::NotABranch => {}
         }
 
-        match self.func.dfg[inst].analyze_call(&self.func.dfg.value_lists) {
-            CallInfo::Direct(func_ref, _) => {
+        match self.func.dfg.analyze_call(inst) {
+            CallInfo::Direct(func_ref, args) => {
                 let sig_ref = self.func.dfg.ext_funcs[func_ref].signature;
                 let arg_types = self.func.dfg.signatures[sig_ref]
                     .params
                     .iter()
                     .map(|a| a.value_type);
-                self.typecheck_variable_args_iterator(inst, arg_types, errors)?;
+                self.typecheck_variable_args_iterator(inst, args, arg_types, errors)?;
                 self.check_outgoing_args(inst, sig_ref, errors)?;
             }
-            CallInfo::Indirect(sig_ref, _) => {
+            CallInfo::Indirect(sig_ref, args) => {
                 let arg_types = self.func.dfg.signatures[sig_ref]
                     .params
                     .iter()
                     .map(|a| a.value_type);
-                self.typecheck_variable_args_iterator(inst, arg_types, errors)?;
+                self.typecheck_variable_args_iterator(inst, args, arg_types, errors)?;
                 self.check_outgoing_args(inst, sig_ref, errors)?;
             }
             CallInfo::NotACall => {}
@@ -1396,10 +1556,10 @@ impl<'a> Verifier<'a> {
     fn typecheck_variable_args_iterator<I: Iterator<Item = Type>>(
         &self,
         inst: Inst,
+        variable_args: &[Value],
         iter: I,
         errors: &mut VerifierErrors,
     ) -> VerifierStepResult<()> {
-        let variable_args = self.func.dfg.inst_variable_args(inst);
         let mut i = 0;
 
         for expected_type in iter {
@@ -1536,6 +1696,111 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
+    fn typecheck_tail_call(
+        &self,
+        inst: Inst,
+        errors: &mut VerifierErrors,
+    ) -> VerifierStepResult<()> {
+        if !self.func.dfg[inst].opcode().is_tail_call() {
+            return Ok(());
+        }
+        let sig_ref = self.func.dfg.call_signature(inst).unwrap();
+        let callee = &self.func.dfg.signatures[sig_ref];
+        let caller = &self.func.signature;
+
+        if callee.call_conv != caller.call_conv {
+            return errors.nonfatal((
+                inst,
+                self.context(inst),
+                format!(
+                    "tail call from {} to {} must not change calling convention",
+                    caller.call_conv, callee.call_conv
+                ),
+            ));
+        }
+        if !caller.call_conv.supports_tail_calls() {
+            return errors.nonfatal((
+                inst,
+                self.context(inst),
+                format!("{} doesn't support tail calls", caller.call_conv),
+            ));
+        }
+
+        let matches = callee.returns.len() == caller.returns.len()
+            && callee
+                .returns
+                .iter()
+                .zip(caller.returns.iter())
+                .all(|(a, b)| {
+                    a.value_type == b.value_type
+                        && a.extension == b.extension
+                        && a.purpose == b.purpose
+                });
+        if !matches {
+            return errors.nonfatal((
+                inst,
+                self.context(inst),
+                "return values of tail call must match function signature",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Is `block` a landing pad, i.e. the exceptional successor of an invoke?
+    fn is_landing_pad(&self, block: Block) -> bool {
+        self.expected_cfg
+            .pred_iter(block)
+            .any(|BlockPredecessor { inst, .. }| self.func.dfg[inst].opcode().is_invoke())
+    }
+
+    fn verify_landing_pad_inst(
+        &self,
+        block: Block,
+        inst: Inst,
+        errors: &mut VerifierErrors,
+    ) -> VerifierStepResult<()> {
+        if self.func.dfg[inst].opcode() != Opcode::LandingPad {
+            return Ok(());
+        }
+        if self.func.layout.first_inst(block) != Some(inst) || !self.is_landing_pad(block) {
+            return errors.fatal((
+                inst,
+                self.context(inst),
+                "landing_pad must be the first instruction of a landing pad",
+            ));
+        }
+        Ok(())
+    }
+
+    fn verify_landing_pad_preds(
+        &self,
+        block: Block,
+        errors: &mut VerifierErrors,
+    ) -> VerifierStepResult<()> {
+        if !self.is_landing_pad(block) {
+            return Ok(());
+        }
+        if self.func.layout.entry_block() == Some(block) {
+            return errors.fatal((
+                block,
+                format!("entry block {} can't be a landing pad", block),
+            ));
+        }
+        for BlockPredecessor { inst, .. } in self.expected_cfg.pred_iter(block) {
+            if !self.func.dfg[inst].opcode().is_invoke() {
+                return errors.fatal((
+                    inst,
+                    self.context(inst),
+                    format!(
+                        "{} is a landing pad and can only be reached by unwinding",
+                        block
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
     // Check special-purpose type constraints that can't be expressed in the normal opcode
     // constraints.
     fn typecheck_special(
@@ -1848,10 +2113,11 @@ impl<'a> Verifier<'a> {
         let opcode = self.func.dfg[inst].opcode();
 
         // The `fallthrough`, `fallthrough_return`, and `safepoint` instructions are not required
-        // to have an encoding.
+        // to have an encoding, and neither are annotations, which are removed after legalization.
         if opcode == Opcode::Fallthrough
             || opcode == Opcode::FallthroughReturn
             || opcode == Opcode::Safepoint
+            || opcode.is_annotation()
         {
             return Ok(());
         }
@@ -2017,7 +2283,9 @@ impl<'a> Verifier<'a> {
 
     pub fn run(&self, errors: &mut VerifierErrors) -> VerifierStepResult<()> {
         self.verify_global_values(errors)?;
+        self.verify_stack_limit(errors)?;
         self.verify_heaps(errors)?;
+        self.verify_gc_refs(errors)?;
         self.verify_tables(errors)?;
         self.verify_jump_tables(errors)?;
         self.typecheck_entry_block_params(errors)?;
@@ -2029,6 +2297,7 @@ impl<'a> Verifier<'a> {
             }
             for inst in self.func.layout.block_insts(block) {
                 self.block_integrity(block, inst, errors)?;
+                self.verify_landing_pad_inst(block, inst, errors)?;
                 self.instruction_integrity(inst, errors)?;
                 self.verify_safepoint_unused(inst, errors)?;
                 self.typecheck(inst, errors)?;
@@ -2037,6 +2306,7 @@ impl<'a> Verifier<'a> {
             }
 
             self.encodable_as_bb(block, errors)?;
+            self.verify_landing_pad_preds(block, errors)?;
         }
 
         verify_flags(self.func, &self.expected_cfg, self.isa, errors)?;
@@ -2059,7 +2329,7 @@ mod tests {
         entity::EntityList,
         ir::{
             instructions::{InstructionData, Opcode},
-            types, AbiParam, Function,
+            types, AbiParam, ArgumentPurpose, Function, GlobalValueData,
         },
         settings,
     };
@@ -2144,6 +2414,23 @@ mod tests {
         assert_err_with_msg!(errors, "Return value at position 0 has an invalid type");
     }
 
+    #[test]
+    fn test_stack_limit_global_value_and_param() {
+        let mut func = Function::new();
+        func.signature
+            .params
+            .push(AbiParam::special(types::I64, ArgumentPurpose::StackLimit));
+        let gv = func.create_global_value(GlobalValueData::VMContext);
+        func.stack_limit = Some(gv);
+
+        let mut errors = VerifierErrors::default();
+        let flags = &settings::Flags::new(settings::builder());
+        let verifier = Verifier::new(&func, flags.into());
+
+        let _ = verifier.verify_stack_limit(&mut errors);
+        assert_err_with_msg!(errors, "given as both a global value and a parameter");
+    }
+
     #[test]
     fn test_printing_contextual_errors() {
         // Build function.
@@ -2187,4 +2474,97 @@ mod tests {
 
         assert_err_with_msg!(errors, "block0 cannot be empty");
     }
+
+    #[test]
+    fn test_tail_call() {
+        use crate::cursor::{Cursor, FuncCursor};
+        use crate::ir::{ExtFuncData, ExternalName, InstBuilder, Signature};
+        use crate::isa::CallConv;
+
+        // Verify a function returning an `i32` with `system_v`, which tail calls a function with
+        // the given return type and calling convention.
+        let verify = |returns: types::Type, call_conv: CallConv| {
+            let mut func = Function::new();
+            func.signature = Signature::new(CallConv::SystemV);
+            func.signature.returns.push(AbiParam::new(types::I32));
+            let mut sig = Signature::new(call_conv);
+            sig.returns.push(AbiParam::new(returns));
+            let signature = func.import_signature(sig);
+            let callee = func.import_function(ExtFuncData {
+                name: ExternalName::testcase("callee"),
+                signature,
+                colocated: false,
+            });
+            let block0 = func.dfg.make_block();
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_block(block0);
+            pos.ins().return_call(callee, &[]);
+
+            let flags = &settings::Flags::new(settings::builder());
+            let verifier = Verifier::new(&func, flags.into());
+            let mut errors = VerifierErrors::default();
+            let _ = verifier.run(&mut errors);
+            errors
+        };
+
+        assert!(verify(types::I32, CallConv::SystemV).0.is_empty());
+        assert_err_with_msg!(
+            verify(types::I64, CallConv::SystemV),
+            "return values of tail call must match function signature"
+        );
+        assert_err_with_msg!(
+            verify(types::I32, CallConv::WindowsFastcall),
+            "must not change calling convention"
+        );
+    }
+
+    #[test]
+    fn test_landing_pad() {
+        use crate::cursor::{Cursor, FuncCursor};
+        use crate::ir::{ExtFuncData, ExternalName, InstBuilder, Signature};
+        use crate::isa::CallConv;
+
+        // Verify a function invoking a callee that unwinds to `block2`, optionally also
+        // reaching `block2` through a normal jump.
+        let verify = |jump_to_landing_pad: bool| {
+            let mut func = Function::new();
+            func.signature = Signature::new(CallConv::SystemV);
+            let mut sig = Signature::new(CallConv::SystemV);
+            sig.returns.push(AbiParam::new(types::I32));
+            let signature = func.import_signature(sig);
+            let callee = func.import_function(ExtFuncData {
+                name: ExternalName::testcase("callee"),
+                signature,
+                colocated: false,
+            });
+            let block0 = func.dfg.make_block();
+            let block1 = func.dfg.make_block();
+            let block2 = func.dfg.make_block();
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_block(block0);
+            pos.ins().invoke(callee, block2, &[]);
+            if jump_to_landing_pad {
+                pos.ins().jump(block2, &[]);
+            } else {
+                pos.ins().jump(block1, &[]);
+            }
+            pos.insert_block(block1);
+            pos.ins().return_(&[]);
+            pos.insert_block(block2);
+            pos.ins().landing_pad(types::I64);
+            pos.ins().return_(&[]);
+
+            let flags = &settings::Flags::new(settings::builder());
+            let verifier = Verifier::new(&func, flags.into());
+            let mut errors = VerifierErrors::default();
+            let _ = verifier.run(&mut errors);
+            errors
+        };
+
+        assert!(verify(false).0.is_empty());
+        assert_err_with_msg!(
+            verify(true),
+            "block2 is a landing pad and can only be reached by unwinding"
+        );
+    }
 }
@@ -6,8 +6,9 @@
 use crate::{
     entity::SecondaryMap,
     ir::{
-        entities::AnyEntity, Block, DataFlowGraph, DisplayFunctionAnnotations, Function, Inst,
-        SigRef, Type, Value, ValueDef, ValueLoc,
+        entities::AnyEntity, instructions::BranchInfo, Block, DataFlowGraph,
+        DisplayFunctionAnnotations, Function, Inst, SigRef, TrapCode, Type, UserTrapNames, Value,
+        ValueDef, ValueLoc,
     },
     isa::{RegInfo, TargetIsa},
     packed_option::ReservedValue,
@@ -206,6 +207,9 @@ pub fn decorate_function<FW: FuncWriter>(
     writeln!(w, " {{")?;
     let aliases = alias_map(func);
     let mut any = func_w.write_preamble(w, func, regs)?;
+    if let Some(names) = annotations.user_trap_names {
+        any |= write_user_trap_names(w, func, names)?;
+    }
     for block in &func.layout {
         if any {
             writeln!(w)?;
@@ -365,6 +369,35 @@ fn decorate_block<FW: FuncWriter>(
     Ok(())
 }
 
+/// Write the names of the user trap codes used in `func` as comments.
+fn write_user_trap_names(
+    w: &mut dyn Write,
+    func: &Function,
+    names: &UserTrapNames,
+) -> Result<bool, fmt::Error> {
+    let mut codes: Vec<u16> = func
+        .layout
+        .blocks()
+        .flat_map(|block| func.layout.block_insts(block))
+        .filter_map(|inst| match func.dfg[inst].trap_code() {
+            Some(TrapCode::User(code)) => Some(code),
+            _ => None,
+        })
+        .collect();
+    codes.sort_unstable();
+    codes.dedup();
+
+    let mut any = false;
+    for code in codes {
+        let code = TrapCode::User(code);
+        if let Some(name) = names.name(code) {
+            any = true;
+            writeln!(w, "    ; {} = {}", code, name)?;
+        }
+    }
+    Ok(any)
+}
+
 //----------------------------------------------------------------------
 //
 // Instructions
@@ -502,6 +535,7 @@ pub fn write_operands(
     match dfg[inst] {
         AtomicRmw { op, args, .. } => write!(w, " {}, {}, {}", op, args[0], args[1]),
         AtomicCas { args, .. } => write!(w, " {}, {}, {}", args[0], args[1], args[2]),
+        Fence { ordering, .. } => write!(w, " {}", ordering),
         LoadNoOffset { flags, arg, .. } => write!(w, "{} {}", flags, arg),
         StoreNoOffset { flags, args, .. } => write!(w, "{} {}, {}", flags, args[0], args[1]),
         Unary { arg, .. } => write!(w, " {}", arg),
@@ -613,6 +647,38 @@ pub fn write_operands(
                 DisplayValues(&args[1..])
             )
         }
+        Invoke {
+            func_ref,
+            destination,
+            ..
+        } => {
+            let (call_args, landing_pad_args) = split_invoke_args(dfg, inst);
+            write!(
+                w,
+                " {}({}), {}",
+                func_ref,
+                DisplayValues(call_args),
+                destination
+            )?;
+            write_block_args(w, landing_pad_args)
+        }
+        InvokeIndirect {
+            sig_ref,
+            destination,
+            ref args,
+            ..
+        } => {
+            let (call_args, landing_pad_args) = split_invoke_args(dfg, inst);
+            write!(
+                w,
+                " {}, {}({}), {}",
+                sig_ref,
+                args.as_slice(pool)[0],
+                DisplayValues(call_args),
+                destination
+            )?;
+            write_block_args(w, landing_pad_args)
+        }
         FuncAddr { func_ref, .. } => write!(w, " {}", func_ref),
         StackLoad {
             stack_slot, offset, ..
@@ -727,6 +793,16 @@ pub fn write_operands(
     }
 }
 
+/// Split the variable arguments of an invoke into its call and landing pad arguments.
+fn split_invoke_args(dfg: &DataFlowGraph, inst: Inst) -> (&[Value], &[Value]) {
+    let args = dfg.inst_variable_args(inst);
+    let num_landing_pad_args = match dfg.analyze_branch(inst) {
+        BranchInfo::SingleDest(_, landing_pad_args) => landing_pad_args.len(),
+        _ => 0,
+    };
+    args.split_at(args.len() - num_landing_pad_args)
+}
+
 /// Write block args using optional parantheses.
 fn write_block_args(w: &mut dyn Write, args: &[Value]) -> fmt::Result {
     if args.is_empty() {
@@ -854,4 +930,33 @@ mod tests {
             "function u0:0() fast {\nblock0(v3: i32):\n    v0 -> v3\n    v2 -> v0\n    v4 = iconst.i32 42\n    v5 = iadd v0, v0\n    v1 -> v5\n    v6 = iconst.i32 23\n    v7 = iadd v1, v1\n}\n"
         );
     }
+
+    #[test]
+    fn user_trap_names() {
+        use crate::ir::{DisplayFunctionAnnotations, TrapCode, UserTrapNames};
+
+        let mut func = Function::new();
+        {
+            let block0 = func.dfg.make_block();
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_block(block0);
+            let v0 = pos.func.dfg.append_block_param(block0, types::I32);
+            pos.ins().trapz(v0, TrapCode::User(3));
+            pos.ins().trapnz(v0, TrapCode::User(1));
+            pos.ins().trap(TrapCode::User(3));
+        }
+
+        let mut names = UserTrapNames::new();
+        names.register(1, "null_deref");
+        names.register(2, "unused");
+        names.register(3, "out_of_fuel");
+        let annotations = DisplayFunctionAnnotations {
+            user_trap_names: Some(&names),
+            ..Default::default()
+        };
+        assert_eq!(
+            func.display_with(annotations).to_string(),
+            "function u0:0() fast {\n    ; user1 = null_deref\n    ; user3 = out_of_fuel\n\nblock0(v0: i32):\n    trapz v0, user3\n    trapnz v0, user1\n    trap user3\n}\n"
+        );
+    }
 }
@@ -295,6 +295,15 @@ impl<'a> FunctionBuilder<'a> {
         self.func_ctx.types[var] = ty;
     }
 
+    /// Declares that the variable `var` holds references to GC-managed objects, so that its
+    /// values are included in stack maps even though its type isn't a reference type.
+    ///
+    /// The variable must have the pointer type. Values passed to `def_var` are marked with
+    /// `ir::DataFlowGraph::set_gc_ref`, as are the block parameters created for the variable.
+    pub fn declare_var_gc_ref(&mut self, var: Variable) {
+        self.func_ctx.ssa.declare_gc_ref(var);
+    }
+
     /// Returns the Cranelift IR value corresponding to the utilization at the current program
     /// position of a previously defined user variable.
     pub fn use_var(&mut self, var: Variable) -> Value {
@@ -333,6 +342,9 @@ impl<'a> FunctionBuilder<'a> {
             val
         );
 
+        if self.func_ctx.ssa.is_gc_ref(var) {
+            self.func.dfg.set_gc_ref(val, true);
+        }
         self.func_ctx.ssa.def_var(var, val, self.position.unwrap());
     }
 
@@ -629,6 +641,10 @@ impl<'a> FunctionBuilder<'a> {
 
     /// Optimised memcpy or memmove for small copies.
     ///
+    /// Copies that need more loads and stores than `config.max_inline_memory_ops` call
+    /// `memcpy` or `memmove` instead. Unless `config.allow_unaligned_memory_ops` is set, the
+    /// accesses are never wider than the smaller of `dest_align` and `src_align`.
+    ///
     /// # Codegen safety
     ///
     /// The following properties must hold to prevent UB:
@@ -646,32 +662,15 @@ impl<'a> FunctionBuilder<'a> {
         non_overlapping: bool,
         mut flags: MemFlags,
     ) {
-        // Currently the result of guess work, not actual profiling.
-        const THRESHOLD: u64 = 4;
-
         if size == 0 {
             return;
         }
 
-        let access_size = greatest_divisible_power_of_two(size);
-        assert!(
-            access_size.is_power_of_two(),
-            "`size` is not a power of two"
-        );
-        assert!(
-            access_size >= u64::from(::core::cmp::min(src_align, dest_align)),
-            "`size` is smaller than `dest` and `src`'s alignment value."
-        );
-
-        let (access_size, int_type) = if access_size <= 8 {
-            (access_size, Type::int((access_size * 8) as u16).unwrap())
-        } else {
-            (8, types::I64)
-        };
-
+        let align = ::core::cmp::min(src_align, dest_align);
+        let (access_size, int_type) = memory_op_access(config, size, align);
         let load_and_store_amount = size / access_size;
 
-        if load_and_store_amount > THRESHOLD {
+        if load_and_store_amount > u64::from(config.max_inline_memory_ops) {
             let size_value = self.ins().iconst(config.pointer_type(), size as i64);
             if non_overlapping {
                 self.call_memcpy(config, dest, src, size_value);
@@ -681,11 +680,13 @@ impl<'a> FunctionBuilder<'a> {
             return;
         }
 
-        flags.set_aligned();
+        if access_size <= u64::from(align) {
+            flags.set_aligned();
+        }
 
         // Load all of the memory first. This is necessary in case `dest` overlaps.
         // It can also improve performance a bit.
-        let registers: smallvec::SmallVec<[_; THRESHOLD as usize]> = (0..load_and_store_amount)
+        let registers: smallvec::SmallVec<[_; 4]> = (0..load_and_store_amount)
             .map(|i| {
                 let offset = (access_size * i) as i32;
                 (self.ins().load(int_type, flags, src, offset), offset)
@@ -728,7 +729,8 @@ impl<'a> FunctionBuilder<'a> {
 
     /// Calls libc.memset
     ///
-    /// Writes `size` bytes of value `ch` to memory starting at `buffer`.
+    /// Writes `size` bytes of value `ch` to memory starting at `buffer`. Fills are expanded
+    /// inline under the same limits as `emit_small_memory_copy`.
     pub fn emit_small_memset(
         &mut self,
         config: TargetFrontendConfig,
@@ -738,37 +740,21 @@ impl<'a> FunctionBuilder<'a> {
         buffer_align: u8,
         mut flags: MemFlags,
     ) {
-        // Currently the result of guess work, not actual profiling.
-        const THRESHOLD: u64 = 4;
-
         if size == 0 {
             return;
         }
 
-        let access_size = greatest_divisible_power_of_two(size);
-        assert!(
-            access_size.is_power_of_two(),
-            "`size` is not a power of two"
-        );
-        assert!(
-            access_size >= u64::from(buffer_align),
-            "`size` is smaller than `dest` and `src`'s alignment value."
-        );
-
-        let (access_size, int_type) = if access_size <= 8 {
-            (access_size, Type::int((access_size * 8) as u16).unwrap())
-        } else {
-            (8, types::I64)
-        };
-
+        let (access_size, int_type) = memory_op_access(config, size, buffer_align);
         let load_and_store_amount = size / access_size;
 
-        if load_and_store_amount > THRESHOLD {
+        if load_and_store_amount > u64::from(config.max_inline_memory_ops) {
             let ch = self.ins().iconst(types::I8, i64::from(ch));
             let size = self.ins().iconst(config.pointer_type(), size as i64);
             self.call_memset(config, buffer, ch, size);
         } else {
-            flags.set_aligned();
+            if access_size <= u64::from(buffer_align) {
+                flags.set_aligned();
+            }
 
             let ch = u64::from(ch);
             let raw_value = if int_type == types::I64 {
@@ -824,6 +810,33 @@ fn greatest_divisible_power_of_two(size: u64) -> u64 {
     (size as i64 & -(size as i64)) as u64
 }
 
+/// Picks the size and integer type of the accesses used to expand a memory operation of `size`
+/// bytes whose pointers are aligned to `align` bytes. The accesses are never wider than the
+/// pointers of the target.
+fn memory_op_access(config: TargetFrontendConfig, size: u64, align: u8) -> (u64, Type) {
+    let mut access_size = greatest_divisible_power_of_two(size);
+    assert!(
+        access_size.is_power_of_two(),
+        "`size` is not a power of two"
+    );
+    assert!(
+        access_size >= u64::from(align),
+        "`size` is smaller than the pointers' alignment value."
+    );
+
+    if !config.allow_unaligned_memory_ops {
+        access_size = ::core::cmp::min(access_size, u64::from(align.max(1)));
+    }
+
+    let data_layout = config.data_layout();
+    let max_access_size = u64::from(data_layout.pointer_bytes());
+    if access_size <= max_access_size {
+        (access_size, Type::int((access_size * 8) as u16).unwrap())
+    } else {
+        (max_access_size, data_layout.pointer_type())
+    }
+}
+
 // Helper functions
 impl<'a> FunctionBuilder<'a> {
     /// A Block is 'filled' when a terminator instruction is present.
@@ -857,7 +870,10 @@ mod tests {
     use alloc::string::ToString;
     use cranelift_codegen::{
         entity::EntityRef,
-        ir::{types::*, AbiParam, ExternalName, Function, InstBuilder, MemFlags, Signature},
+        ir::{
+            types::*, AbiParam, Endianness, ExternalName, Function, InstBuilder, MemFlags,
+            Signature,
+        },
         isa::{CallConv, TargetFrontendConfig},
         settings,
         verifier::verify_function,
@@ -979,6 +995,9 @@ mod tests {
         TargetFrontendConfig {
             default_call_conv: CallConv::SystemV,
             pointer_width: PointerWidth::U64,
+            endianness: Endianness::Little,
+            max_inline_memory_ops: 4,
+            allow_unaligned_memory_ops: true,
         }
     }
 
@@ -1085,6 +1104,80 @@ block0:
         );
     }
 
+    #[test]
+    fn small_memcpy_without_unaligned_accesses() {
+        let frontend_config = TargetFrontendConfig {
+            allow_unaligned_memory_ops: false,
+            ..systemv_frontend_config()
+        };
+        let mut sig = Signature::new(frontend_config.default_call_conv);
+        sig.returns.push(AbiParam::new(I32));
+
+        let mut fn_ctx = FunctionBuilderContext::new();
+        let mut func = Function::with_name_signature(ExternalName::testcase("sample"), sig);
+        {
+            let mut builder = FunctionBuilder::new(&mut func, &mut fn_ctx);
+
+            let block0 = builder.create_block();
+            let x = Variable::new(0);
+            let y = Variable::new(16);
+            builder.declare_var(x, frontend_config.pointer_type());
+            builder.declare_var(y, frontend_config.pointer_type());
+            builder.append_block_params_for_function_params(block0);
+            builder.switch_to_block(block0);
+
+            let src = builder.use_var(x);
+            let dest = builder.use_var(y);
+            builder.emit_small_memory_copy(
+                frontend_config,
+                dest,
+                src,
+                8,
+                4,
+                8,
+                true,
+                MemFlags::new(),
+            );
+            // Too many two-byte accesses: this one becomes a libcall.
+            builder.emit_small_memory_copy(
+                frontend_config,
+                dest,
+                src,
+                16,
+                2,
+                2,
+                true,
+                MemFlags::new(),
+            );
+            builder.ins().return_(&[dest]);
+
+            builder.seal_all_blocks();
+            builder.finalize();
+        }
+
+        assert_eq!(
+            func.display(None).to_string(),
+            "function %sample() -> i32 system_v {
+    sig0 = (i64, i64, i64) system_v
+    fn0 = %Memcpy sig0
+
+block0:
+    v6 = iconst.i64 0
+    v1 -> v6
+    v5 = iconst.i64 0
+    v0 -> v5
+    v2 = load.i32 aligned v0
+    v3 = load.i32 aligned v0+4
+    store aligned v2, v1
+    store aligned v3, v1+4
+    v4 = iconst.i64 16
+    call fn0(v1, v0, v4)
+    return v1
+}
+"
+        );
+    }
+
     #[test]
     fn not_so_small_memcpy() {
         let frontend_config = systemv_frontend_config();
@@ -1182,6 +1275,51 @@ block0:
         );
     }
 
+    #[test]
+    fn small_memset_on_32_bit_target() {
+        let frontend_config = TargetFrontendConfig {
+            pointer_width: PointerWidth::U32,
+            ..systemv_frontend_config()
+        };
+        let mut sig = Signature::new(frontend_config.default_call_conv);
+        sig.returns.push(AbiParam::new(I32));
+
+        let mut fn_ctx = FunctionBuilderContext::new();
+        let mut func = Function::with_name_signature(ExternalName::testcase("sample"), sig);
+        {
+            let mut builder = FunctionBuilder::new(&mut func, &mut fn_ctx);
+
+            let block0 = builder.create_block();
+            let y = Variable::new(16);
+            builder.declare_var(y, frontend_config.pointer_type());
+            builder.append_block_params_for_function_params(block0);
+            builder.switch_to_block(block0);
+
+            // The stores are no wider than the pointers.
+            let dest = builder.use_var(y);
+            let size = 8;
+            builder.emit_small_memset(frontend_config, dest, 1, size, 8, MemFlags::new());
+            builder.ins().return_(&[dest]);
+
+            builder.seal_all_blocks();
+            builder.finalize();
+        }
+
+        assert_eq!(
+            func.display(None).to_string(),
+            "function %sample() -> i32 system_v {
+block0:
+    v2 = iconst.i32 0
+    v0 -> v2
+    v1 = iconst.i32 0x0101_0101
+    store aligned v1, v0
+    store aligned v1, v0+4
+    return v0
+}
+"
+        );
+    }
+
     #[test]
     fn not_so_small_memset() {
         let frontend_config = systemv_frontend_config();
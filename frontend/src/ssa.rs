@@ -46,6 +46,9 @@ pub struct SSABuilder {
     /// block.
     ssa_blocks: SecondaryMap<Block, SSABlockData>,
 
+    /// Variables holding GC references. Block parameters created for them are marked as such.
+    gc_ref_vars: SecondaryMap<Variable, bool>,
+
     /// Call stack for use in the `use_var`/`predecessors_lookup` state machine.
     calls: Vec<Call>,
     /// Result stack for use in the `use_var`/`predecessors_lookup` state machine.
@@ -126,6 +129,7 @@ impl SSABuilder {
         Self {
             variables: SecondaryMap::with_default(SecondaryMap::new()),
             ssa_blocks: SecondaryMap::new(),
+            gc_ref_vars: SecondaryMap::new(),
             calls: Vec::new(),
             results: Vec::new(),
             side_effects: SideEffects::new(),
@@ -137,6 +141,7 @@ impl SSABuilder {
     pub fn clear(&mut self) {
         self.variables.clear();
         self.ssa_blocks.clear();
+        self.gc_ref_vars.clear();
         debug_assert!(self.calls.is_empty());
         debug_assert!(self.results.is_empty());
         debug_assert!(self.side_effects.is_empty());
@@ -205,6 +210,14 @@ fn emit_zero(ty: Type, mut cur: FuncCursor) -> Value {
         } else {
             panic!("unimplemented scalar type: {:?}", ty)
         }
+    } else if ty.is_dynamic_vector() {
+        // There are no dynamic vector constants, so splat a zero lane instead.
+        let position = cur.position();
+        let scalar = emit_zero(
+            ty.lane_type(),
+            FuncCursor::new(&mut *cur.func).at_position(position),
+        );
+        cur.ins().splat(ty, scalar)
     } else {
         panic!("unimplemented type: {:?}", ty)
     }
@@ -230,6 +243,17 @@ fn emit_zero(ty: Type, mut cur: FuncCursor) -> Value {
 /// Phi functions.
 ///
 impl SSABuilder {
+    /// Declares that `var` holds GC references, so that the values the SSA builder creates for
+    /// it are marked with `ir::DataFlowGraph::set_gc_ref`.
+    pub fn declare_gc_ref(&mut self, var: Variable) {
+        self.gc_ref_vars[var] = true;
+    }
+
+    /// Does `var` hold GC references?
+    pub fn is_gc_ref(&self, var: Variable) -> bool {
+        self.gc_ref_vars[var]
+    }
+
     /// Declares a new definition of a variable in a given basic block.
     /// The SSA value is passed as an argument because it should be created with
     /// `ir::DataFlowGraph::append_result`.
@@ -290,10 +314,12 @@ impl SSABuilder {
             } else {
                 // Break potential cycles by eagerly adding an operandless param.
                 let val = func.dfg.append_block_param(block, ty);
+                func.dfg.set_gc_ref(val, self.gc_ref_vars[var]);
                 UseVarCases::SealedMultiplePredecessors(val, block)
             }
         } else {
             let val = func.dfg.append_block_param(block, ty);
+            func.dfg.set_gc_ref(val, self.gc_ref_vars[var]);
             data.undef_variables.push((var, val));
             UseVarCases::Unsealed(val)
         };
@@ -1,7 +1,13 @@
 //! Defines `JITModule`.
 
-use crate::{compiled_blob::CompiledBlob, memory::Memory};
-use alloc::{borrow::ToOwned, boxed::Box, format, string::String, vec::Vec};
+use crate::{
+    code_hash::{self, FunctionHash},
+    compiled_blob::CompiledBlob,
+    finalized::{FinalizedBatch, FinalizedFunction, FinalizedFunctions},
+    memory::Memory,
+    traps, GlobalManager, MemoryManager, Mutex, QuotaManager, SharedManager,
+};
+use alloc::{borrow::ToOwned, boxed::Box, format, string::String, sync::Arc, vec::Vec};
 use core::{
     convert::{TryFrom, TryInto},
     mem, ptr,
@@ -10,17 +16,21 @@ use core::{
 };
 use cranelift_codegen::{
     self,
-    binemit::{Addend, CodeInfo, CodeOffset, Reloc, RelocSink, StackMapSink, TrapSink},
-    ir,
+    binemit::{
+        Addend, CodeInfo, CodeOffset, NullStackMapSink, NullTrapSink, Reloc, RelocSink, StackMap,
+        StackMapSink, TrapSink,
+    },
+    cursor::{Cursor, FuncCursor},
+    ir::{self, InstBuilder},
     isa::TargetIsa,
     settings,
     settings::Configurable,
-    CodegenError,
+    CodegenError, Context,
 };
 use cranelift_entity::SecondaryMap;
 use cranelift_module::{
     DataContext, DataDescription, DataId, FuncId, Init, Linkage, Module, ModuleCompiledFunction,
-    ModuleDeclarations, ModuleError, ModuleResult, RelocRecord,
+    ModuleDeclarations, ModuleError, ModuleResult, ModuleSink, RelocRecord, TrapSite,
 };
 use cranelift_native;
 use hashbrown::HashMap;
@@ -37,6 +47,8 @@ pub struct JITBuilder {
     symbols: HashMap<String, *const u8>,
     libcall_names: Box<dyn Fn(ir::LibCall) -> String + Send + Sync>,
     hotswap_enabled: bool,
+    user_trap_names: ir::UserTrapNames,
+    memory_manager: Option<Box<dyn MemoryManager + Send>>,
 }
 
 impl JITBuilder {
@@ -80,6 +92,8 @@ impl JITBuilder {
             symbols,
             libcall_names,
             hotswap_enabled: false,
+            user_trap_names: ir::UserTrapNames::new(),
+            memory_manager: None,
         }
     }
 
@@ -127,6 +141,31 @@ impl JITBuilder {
         self.hotswap_enabled = enabled;
         self
     }
+
+    /// Name the user trap code `code`, for [`JITModule::user_trap_names`] and the functions
+    /// logged by the module. See `ir::UserTrapNames::register` for the valid names.
+    pub fn user_trap_name(&mut self, code: u16, name: &str) -> &mut Self {
+        self.user_trap_names.register(code, name);
+        self
+    }
+
+    /// Allocate the code and data memory of the module through `manager`, instead of the
+    /// process-wide memory manager.
+    pub fn memory_manager(&mut self, manager: Box<dyn MemoryManager + Send>) -> &mut Self {
+        self.memory_manager = Some(manager);
+        self
+    }
+
+    /// Limit the code and data memory of the module to `max_bytes` at any point in time, by
+    /// wrapping its memory manager in a [`QuotaManager`]. Other modules are unaffected.
+    pub fn memory_quota(&mut self, max_bytes: usize) -> &mut Self {
+        let inner = self
+            .memory_manager
+            .take()
+            .unwrap_or_else(|| Box::new(GlobalManager));
+        self.memory_manager = Some(Box::new(QuotaManager::new(inner, max_bytes)));
+        self
+    }
 }
 
 /// A pending update to the GOT.
@@ -146,7 +185,7 @@ pub struct JITModule {
     isa: Box<dyn TargetIsa>,
     hotswap_enabled: bool,
     symbols: HashMap<String, *const u8>,
-    libcall_names: Box<dyn Fn(ir::LibCall) -> String>,
+    libcall_names: Box<dyn Fn(ir::LibCall) -> String + Send + Sync>,
     memory: MemoryHandle,
     declarations: ModuleDeclarations,
     function_got_entries: SecondaryMap<FuncId, Option<NonNull<AtomicPtr<u8>>>>,
@@ -161,8 +200,22 @@ pub struct JITModule {
 
     /// Updates to the GOT awaiting relocations to be made and region protections to be set
     pending_got_updates: Vec<GotUpdate>,
+
+    /// The addresses of finalized functions, for lock-free lookups from other threads.
+    finalized_functions: FinalizedFunctions,
+
+    /// The hashes of finalized functions, computed once they are relocated.
+    function_hashes: SecondaryMap<FuncId, Option<FunctionHash>>,
+
+    user_trap_names: ir::UserTrapNames,
 }
 
+// The raw pointers in a `JITModule` point either to host symbols or to memory owned by the
+// module, which is only ever written through `&mut self`. Finalized code is published to other
+// threads through `FinalizedFunctions`, which synchronizes on its own.
+unsafe impl Send for JITModule {}
+unsafe impl Sync for JITModule {}
+
 /// A handle to allow freeing memory allocated by the `Module`.
 struct MemoryHandle {
     code: Memory,
@@ -180,6 +233,11 @@ impl JITModule {
     /// from that module are currently executing and none of the `fn` pointers
     /// are called afterwards.
     pub unsafe fn free_memory(mut self) {
+        for (_, blob) in self.compiled_functions.iter() {
+            if let Some(blob) = blob {
+                traps::unregister(blob.ptr);
+            }
+        }
         self.memory.code.free_memory();
         self.memory.readonly.free_memory();
         self.memory.writable.free_memory();
@@ -290,6 +348,7 @@ impl JITModule {
             ir::ExternalName::LibCall(ref libcall) => {
                 let sym = (self.libcall_names)(*libcall);
                 self.lookup_symbol(&sym)
+                    .or_else(|| lookup_host_libcall(*libcall))
                     .unwrap_or_else(|| panic!("can't resolve libcall {}", sym))
             }
             _ => panic!("invalid ExternalName {}", name),
@@ -378,32 +437,77 @@ impl JITModule {
         (compiled.ptr, compiled.size)
     }
 
+    /// Returns the hashes of a finalized function.
+    pub fn function_hash(&self, func_id: FuncId) -> FunctionHash {
+        assert!(
+            !self.functions_to_finalize.iter().any(|x| *x == func_id),
+            "function not yet finalized"
+        );
+        self.function_hashes[func_id].expect("function must be finalized to be hashed")
+    }
+
+    /// Checks that the code of a finalized function in memory still has the hash it had when it
+    /// was finalized, i.e. that it hasn't been tampered with since.
+    pub fn verify_function_hash(&self, func_id: FuncId) -> bool {
+        let hash = self.function_hash(func_id);
+        let blob = self.compiled_functions[func_id].as_ref().unwrap();
+        code_hash::hash_code(unsafe { blob.bytes() }) == hash.code
+    }
+
+    /// The names of user trap codes given through [`JITBuilder::user_trap_name`], e.g. to report
+    /// the [`Trap`](crate::Trap)s of JIT code with the causes they stand for.
+    pub fn user_trap_names(&self) -> &ir::UserTrapNames {
+        &self.user_trap_names
+    }
+
+    /// The name of the symbol `name` refers to, as hashed in relocations.
+    fn symbol_name(&self, name: &ir::ExternalName) -> String {
+        match *name {
+            ir::ExternalName::User { .. } => {
+                if ModuleDeclarations::is_function(name) {
+                    let func_id = FuncId::from_name(name);
+                    self.declarations.get_function_decl(func_id).name.clone()
+                } else {
+                    let data_id = DataId::from_name(name);
+                    self.declarations.get_data_decl(data_id).name.clone()
+                }
+            }
+            ir::ExternalName::LibCall(ref libcall) => (self.libcall_names)(*libcall),
+            _ => panic!("invalid ExternalName {}", name),
+        }
+    }
+
     fn record_function_for_perf(&self, _ptr: *mut u8, _size: usize, _name: &str) {}
 
     /// Finalize all functions and data objects that are defined but not yet finalized.
     /// All symbols referenced in their bodies that are declared as needing a definition
     /// must be defined by this point.
     ///
-    /// Use `get_finalized_function` and `get_finalized_data` to obtain the final
-    /// artifacts.
-    pub fn finalize_definitions(&mut self) {
-        for func in mem::take(&mut self.functions_to_finalize) {
+    /// Returns the functions and data objects finalized by this call, with their addresses and
+    /// sizes. `get_finalized_function` and `get_finalized_data` can also be used to obtain the
+    /// final artifacts later on.
+    pub fn finalize_definitions(&mut self) -> FinalizedBatch {
+        let mut batch = FinalizedBatch::default();
+        let functions = mem::take(&mut self.functions_to_finalize);
+        for &func in &functions {
             let decl = self.declarations.get_function_decl(func);
             assert!(decl.linkage.is_definable());
-            let func = self.compiled_functions[func]
+            let blob = self.compiled_functions[func]
                 .as_ref()
                 .expect("function must be compiled before it can be finalized");
-            func.perform_relocations(
+            blob.perform_relocations(
                 |name| self.get_address(name),
                 |name| self.get_got_address(name).as_ptr().cast(),
                 |name| self.get_plt_address(name),
             );
+            let hash = FunctionHash::new(blob, |name| self.symbol_name(name));
+            self.function_hashes[func] = Some(hash);
         }
 
-        for data in mem::take(&mut self.data_objects_to_finalize) {
-            let decl = self.declarations.get_data_decl(data);
+        for data_id in mem::take(&mut self.data_objects_to_finalize) {
+            let decl = self.declarations.get_data_decl(data_id);
             assert!(decl.linkage.is_definable());
-            let data = self.compiled_data_objects[data]
+            let data = self.compiled_data_objects[data_id]
                 .as_ref()
                 .expect("data object must be compiled before it can be finalized");
             data.perform_relocations(
@@ -411,6 +515,7 @@ impl JITModule {
                 |name| self.get_got_address(name).as_ptr().cast(),
                 |name| self.get_plt_address(name),
             );
+            batch.add_data_object(data_id, data.ptr, data.size);
         }
 
         // Now that we're done patching, prepare the memory for execution!
@@ -420,6 +525,38 @@ impl JITModule {
         for update in self.pending_got_updates.drain(..) {
             unsafe { update.entry.as_ref() }.store(update.ptr as *mut _, Ordering::SeqCst);
         }
+
+        for func in functions {
+            let blob = self.compiled_functions[func].as_ref().unwrap();
+            self.finalized_functions.publish(func, blob.ptr);
+            let mut data_refs = Vec::new();
+            for reloc in &blob.relocs {
+                if let ir::ExternalName::User { .. } = reloc.name {
+                    if !ModuleDeclarations::is_function(&reloc.name) {
+                        let data_id = DataId::from_name(&reloc.name);
+                        if !data_refs.contains(&data_id) {
+                            data_refs.push(data_id);
+                        }
+                    }
+                }
+            }
+            batch.add_function(
+                func,
+                FinalizedFunction {
+                    ptr: blob.ptr,
+                    size: blob.size,
+                    data_refs,
+                },
+            );
+        }
+
+        batch
+    }
+
+    /// Returns a handle through which other threads can look up finalized functions without
+    /// locking, while this module keeps defining and finalizing functions.
+    pub fn finalized_functions(&self) -> FinalizedFunctions {
+        self.finalized_functions.clone()
     }
 
     /// Create a new `JITModule`.
@@ -431,15 +568,20 @@ impl JITModule {
             );
         }
 
+        let manager: SharedManager = Arc::new(Mutex::new(
+            builder
+                .memory_manager
+                .unwrap_or_else(|| Box::new(GlobalManager)),
+        ));
         let mut module = Self {
             isa: builder.isa,
             hotswap_enabled: builder.hotswap_enabled,
             symbols: builder.symbols,
             libcall_names: builder.libcall_names,
             memory: MemoryHandle {
-                code: Memory::new(),
-                readonly: Memory::new(),
-                writable: Memory::new(),
+                code: Memory::new(manager.clone()),
+                readonly: Memory::new(manager.clone()),
+                writable: Memory::new(manager),
             },
             declarations: ModuleDeclarations::default(),
             function_got_entries: SecondaryMap::new(),
@@ -452,6 +594,9 @@ impl JITModule {
             functions_to_finalize: Vec::new(),
             data_objects_to_finalize: Vec::new(),
             pending_got_updates: Vec::new(),
+            finalized_functions: FinalizedFunctions::new(),
+            function_hashes: SecondaryMap::new(),
+            user_trap_names: builder.user_trap_names,
         };
 
         // Pre-create a GOT and PLT entry for each libcall.
@@ -499,12 +644,57 @@ impl JITModule {
             )));
         }
 
-        self.compiled_functions[func_id] = None;
+        if let Some(blob) = self.compiled_functions[func_id].take() {
+            traps::unregister(blob.ptr);
+        }
+        self.function_hashes[func_id] = None;
 
         // FIXME return some kind of handle that allows for deallocating the function
 
         Ok(())
     }
+
+    /// Define a trampoline with the signature of `callee`, which calls it with the protection key
+    /// rights set to `rights` and restores the previous rights once it returns.
+    ///
+    /// Such trampolines confine sandboxed code to the memory of some protection keys: entering
+    /// the sandbox goes through a trampoline with the rights to the keys of its heaps, and every
+    /// call out of the sandbox through one with the rights of the host. This requires the
+    /// `enable_protection_keys` setting.
+    pub fn define_pkey_trampoline(&mut self, callee: FuncId, rights: u64) -> ModuleResult<FuncId> {
+        if !self.isa.flags().enable_protection_keys() {
+            return Err(ModuleError::Backend(anyhow::anyhow!(
+                "protection key trampolines require the enable_protection_keys setting"
+            )));
+        }
+
+        let decl = self.declarations.get_function_decl(callee);
+        let sig = decl.signature.clone();
+        let params: Vec<_> = sig.params.iter().map(|param| param.value_type).collect();
+        let id = self.declare_anonymous_function(&sig)?;
+        let mut ctx = Context::new();
+        ctx.func = ir::Function::with_name_signature(ir::ExternalName::user(0, id.as_u32()), sig);
+        let callee = self.declare_func_in_func(callee, &mut ctx.func);
+
+        let mut pos = FuncCursor::new(&mut ctx.func);
+        let block = pos.func.dfg.make_block();
+        pos.insert_block(block);
+        let args: Vec<_> = params
+            .into_iter()
+            .map(|ty| pos.func.dfg.append_block_param(block, ty))
+            .collect();
+
+        let saved = pos.ins().get_pkey_rights();
+        let rights = pos.ins().iconst(ir::types::I64, rights as i64);
+        pos.ins().set_pkey_rights(rights);
+        let call = pos.ins().call(callee, &args);
+        let results = pos.func.dfg.inst_results(call).to_vec();
+        pos.ins().set_pkey_rights(saved);
+        pos.ins().return_(&results);
+
+        self.define_function(id, &mut ctx, &mut NullTrapSink {}, &mut NullStackMapSink {})?;
+        Ok(id)
+    }
 }
 
 impl Module for JITModule {
@@ -616,14 +806,21 @@ impl Module for JITModule {
         ctx.import_global_value(ir::ExternalName::user(1, data.as_u32()))
     }
 
-    fn define_function(
+    fn define_function_with_sink(
         &mut self,
         id: FuncId,
         ctx: &mut cranelift_codegen::Context,
-        trap_sink: &mut dyn TrapSink,
-        stack_map_sink: &mut dyn StackMapSink,
+        sink: &mut dyn ModuleSink,
     ) -> ModuleResult<ModuleCompiledFunction> {
-        info!("defining function {}: {}", id, ctx.func.display(self.isa()));
+        info!(
+            "defining function {}: {}",
+            id,
+            ctx.func.display_with(ir::DisplayFunctionAnnotations {
+                isa: Some(self.isa()),
+                value_ranges: None,
+                user_trap_names: Some(&self.user_trap_names),
+            })
+        );
         let CodeInfo {
             total_size: code_size,
             ..
@@ -643,10 +840,30 @@ impl Module for JITModule {
             .memory
             .code
             .allocate(size, EXECUTABLE_DATA_ALIGNMENT)
-            .expect("TODO: handle OOM etc.");
+            .map_err(|()| out_of_memory(&decl.name, size))?;
 
         let mut reloc_sink = JITRelocSink::default();
-        unsafe { ctx.emit_to_memory(&*self.isa, ptr, &mut reloc_sink, trap_sink, stack_map_sink) };
+        let mut trap_sink = JITTrapSink::default();
+        let mut stack_map_sink = JITStackMapSink::default();
+        unsafe {
+            ctx.emit_to_memory(
+                &*self.isa,
+                ptr,
+                &mut reloc_sink,
+                &mut trap_sink,
+                &mut stack_map_sink,
+            )
+        };
+        for reloc in &reloc_sink.relocs {
+            sink.reloc(reloc);
+        }
+        for site in &trap_sink.sites {
+            sink.trap(site);
+        }
+        for (offset, stack_map) in stack_map_sink.stack_maps {
+            sink.stack_map(offset, stack_map);
+        }
+        traps::register(ptr, size, id, trap_sink.sites);
 
         self.record_function_for_perf(ptr, size, &decl.name);
         self.compiled_functions[id] = Some(CompiledBlob {
@@ -715,7 +932,7 @@ impl Module for JITModule {
             .memory
             .code
             .allocate(size, EXECUTABLE_DATA_ALIGNMENT)
-            .expect("TODO: handle OOM etc.");
+            .map_err(|()| out_of_memory(&decl.name, size))?;
 
         unsafe {
             ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, size);
@@ -778,12 +995,12 @@ impl Module for JITModule {
             self.memory
                 .writable
                 .allocate(size, align.unwrap_or(WRITABLE_DATA_ALIGNMENT))
-                .expect("TODO: handle OOM etc.")
+                .map_err(|()| out_of_memory(&decl.name, size))?
         } else {
             self.memory
                 .readonly
                 .allocate(size, align.unwrap_or(READONLY_DATA_ALIGNMENT))
-                .expect("TODO: handle OOM etc.")
+                .map_err(|()| out_of_memory(&decl.name, size))?
         };
 
         match *init {
@@ -826,6 +1043,73 @@ fn lookup_with_dlsym(_name: &str) -> Option<*const u8> {
     None
 }
 
+/// Look up the host's own implementation of `libcall`, for libcalls that aren't defined as
+/// symbols of the JIT.
+#[cfg(all(windows, target_arch = "x86_64", feature = "std"))]
+fn lookup_host_libcall(libcall: ir::LibCall) -> Option<*const u8> {
+    use winapi::um::libloaderapi::{GetModuleHandleA, GetProcAddress};
+
+    match libcall {
+        // Windows stack probes are done by `__chkstk`, which ntdll exports.
+        ir::LibCall::Probestack => unsafe {
+            let ntdll = GetModuleHandleA(b"ntdll.dll\0".as_ptr().cast());
+            if ntdll.is_null() {
+                return None;
+            }
+            let chkstk = GetProcAddress(ntdll, b"__chkstk\0".as_ptr().cast());
+            if chkstk.is_null() {
+                None
+            } else {
+                Some(chkstk as *const u8)
+            }
+        },
+        _ => None,
+    }
+}
+
+#[cfg(not(all(windows, target_arch = "x86_64", feature = "std")))]
+fn lookup_host_libcall(_libcall: ir::LibCall) -> Option<*const u8> {
+    None
+}
+
+/// The error reported when the memory manager can't provide memory for `name`.
+fn out_of_memory(name: &str, size: usize) -> ModuleError {
+    ModuleError::Backend(anyhow::anyhow!(
+        "Failed to allocate {} bytes of JIT memory for {}",
+        size,
+        name
+    ))
+}
+
+/// A `TrapSink` that records trap sites, for `traps::lookup_trap` and the user's `ModuleSink`.
+#[derive(Default)]
+struct JITTrapSink {
+    sites: Vec<TrapSite>,
+}
+
+impl TrapSink for JITTrapSink {
+    fn trap(&mut self, offset: CodeOffset, srcloc: ir::SourceLoc, code: ir::TrapCode) {
+        self.sites.push(TrapSite {
+            offset,
+            srcloc,
+            code,
+        });
+    }
+}
+
+/// A `StackMapSink` that records stack maps until they can be passed on to the user's
+/// `ModuleSink`.
+#[derive(Default)]
+struct JITStackMapSink {
+    stack_maps: Vec<(CodeOffset, StackMap)>,
+}
+
+impl StackMapSink for JITStackMapSink {
+    fn add_stack_map(&mut self, offset: CodeOffset, stack_map: StackMap) {
+        self.stack_maps.push((offset, stack_map));
+    }
+}
+
 #[derive(Default)]
 struct JITRelocSink {
     relocs: Vec<RelocRecord>,
@@ -0,0 +1,70 @@
+//! Walking stacks that contain JIT frames.
+//!
+//! Functions compiled by cranelift always set up a frame pointer, so a stack can be walked by
+//! following the chain of saved frame pointers. Every frame is matched against the functions
+//! registered by `JITModule`s, which yields a function id and offset for JIT frames. Native
+//! frames can be walked through as long as the native code keeps frame pointers too (for Rust,
+//! `-C force-frame-pointers=yes`); the walk stops at the first frame that breaks the chain.
+
+use crate::traps::lookup_function;
+use cranelift_codegen::binemit::CodeOffset;
+use cranelift_module::FuncId;
+
+/// One frame of a walked stack.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    /// The PC in this frame: the faulting or current PC for the innermost frame, and the
+    /// return address for all other frames.
+    pub pc: usize,
+    /// The frame pointer of this frame.
+    pub fp: usize,
+    /// The JIT function `pc` belongs to, and the offset of `pc` from its start, or `None` for
+    /// native frames.
+    pub func: Option<(FuncId, CodeOffset)>,
+}
+
+/// Walk the stack starting from the frame with program counter `pc` and frame pointer `fp`,
+/// calling `f` for every frame from the innermost outwards until it returns `false` or the
+/// end of the frame pointer chain is reached.
+///
+/// `pc` and `fp` typically come from the register state of an interrupted thread (e.g. in a
+/// trap handler) or from the frame pointer of a native function called by JIT code. Like
+/// [`lookup_trap`](crate::lookup_trap), this never blocks; JIT frames of functions that are
+/// being defined concurrently may be reported as native frames.
+///
+/// # Safety
+///
+/// `fp` must be null or a valid frame pointer of the current thread's stack, and every frame
+/// reachable from it must have a frame pointer.
+pub unsafe fn walk_frames(pc: usize, fp: usize, mut f: impl FnMut(&Frame) -> bool) {
+    let mut frame = Frame {
+        pc,
+        fp,
+        func: lookup_function(pc),
+    };
+    loop {
+        if !f(&frame) {
+            return;
+        }
+        if frame.fp == 0 || frame.fp % core::mem::align_of::<usize>() != 0 {
+            return;
+        }
+        // On both x86-64 and AArch64 the saved frame pointer of the caller is at `[fp]`, and
+        // the return address right above it.
+        let words = frame.fp as *const usize;
+        let caller_fp = *words;
+        let return_address = *words.add(1);
+        // The stack grows downwards, so caller frames are at higher addresses. Anything else
+        // means the chain is broken or has ended.
+        if caller_fp <= frame.fp || return_address == 0 {
+            return;
+        }
+        // A call may be the last instruction of a function, so look up the call instruction
+        // rather than the return address itself.
+        frame = Frame {
+            pc: return_address,
+            fp: caller_fp,
+            func: lookup_function(return_address - 1).map(|(id, offset)| (id, offset + 1)),
+        };
+    }
+}
@@ -0,0 +1,80 @@
+//! Hashes of finalized functions, for attestation and deduplication of JIT code.
+//!
+//! Each function gets two SHA-256 hashes when it is finalized. The hash of its code covers the
+//! bytes as they sit in memory after relocation, and is what `JITModule::verify_function_hash`
+//! checks the memory against to detect tampering. The relocated bytes depend on where the
+//! function and its callees ended up, so the normalized hash instead covers the bytes with every
+//! relocation site zeroed, followed by the relocations themselves with the names of the symbols
+//! they refer to. It is the same for the same function compiled for the same target on any
+//! machine, or in any module.
+
+use crate::compiled_blob::CompiledBlob;
+use alloc::{format, string::String};
+use core::convert::TryFrom;
+use cranelift_codegen::binemit::Reloc;
+use cranelift_codegen::ir::ExternalName;
+use cranelift_module::RelocRecord;
+use sha2::{Digest, Sha256};
+
+/// The hashes of a finalized function, from `JITModule::function_hash`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FunctionHash {
+    /// The SHA-256 hash of the bytes of the function, as relocated in memory.
+    pub code: [u8; 32],
+    /// The SHA-256 hash of the bytes of the function with the relocation sites zeroed, and of its
+    /// relocations, which doesn't depend on where the function or its callees were put.
+    pub normalized: [u8; 32],
+}
+
+impl FunctionHash {
+    /// Hash `blob`, which must have been relocated already. Relocations are against the symbols
+    /// named by `symbol_name`.
+    pub(crate) fn new(blob: &CompiledBlob, symbol_name: impl Fn(&ExternalName) -> String) -> Self {
+        let bytes = unsafe { blob.bytes() };
+
+        let mut normalized_bytes = bytes.to_vec();
+        for record in &blob.relocs {
+            let start = usize::try_from(record.offset).unwrap();
+            let end = (start + reloc_size(record.reloc)).min(normalized_bytes.len());
+            for byte in &mut normalized_bytes[start..end] {
+                *byte = 0;
+            }
+        }
+
+        let mut normalized = Sha256::new();
+        normalized.update((normalized_bytes.len() as u64).to_le_bytes());
+        normalized.update(&normalized_bytes);
+        for &RelocRecord {
+            reloc,
+            offset,
+            ref name,
+            addend,
+        } in &blob.relocs
+        {
+            let name = symbol_name(name);
+            normalized.update(format!("{:?}", reloc).as_bytes());
+            normalized.update(offset.to_le_bytes());
+            normalized.update((name.len() as u64).to_le_bytes());
+            normalized.update(name.as_bytes());
+            normalized.update(addend.to_le_bytes());
+        }
+
+        Self {
+            code: hash_code(bytes),
+            normalized: normalized.finalize().into(),
+        }
+    }
+}
+
+/// The SHA-256 hash of the code of a function.
+pub(crate) fn hash_code(bytes: &[u8]) -> [u8; 32] {
+    Sha256::digest(bytes).into()
+}
+
+/// The number of bytes a relocation of kind `reloc` patches.
+fn reloc_size(reloc: Reloc) -> usize {
+    match reloc {
+        Reloc::Abs8 => 8,
+        _ => 4,
+    }
+}
@@ -11,6 +11,15 @@ pub(crate) struct CompiledBlob {
 }
 
 impl CompiledBlob {
+    /// The bytes of this blob.
+    ///
+    /// # Safety
+    ///
+    /// The memory of the blob must be readable and not be written to while the slice lives.
+    pub(crate) unsafe fn bytes(&self) -> &[u8] {
+        core::slice::from_raw_parts(self.ptr, self.size)
+    }
+
     pub(crate) fn perform_relocations(
         &self,
         get_address: impl Fn(&ExternalName) -> *const u8,
@@ -0,0 +1,236 @@
+//! Lock-free lookup of finalized functions.
+//!
+//! A `JITModule` publishes the address of every function it finalizes in a table that other
+//! threads can read through a [`FinalizedFunctions`] handle without ever taking a lock, even
+//! while the module goes on defining and finalizing more functions.
+//!
+//! [`JITModule::finalize_definitions`](crate::JITModule::finalize_definitions) also returns
+//! everything it finalized in one go as a [`FinalizedBatch`], which can be handed to other threads
+//! as a whole.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use cranelift_entity::EntityRef;
+use cranelift_module::{DataId, FuncId};
+use hashbrown::HashMap;
+
+/// Number of entries in the first chunk of the table. Every further chunk is twice as large as
+/// the one before it.
+const FIRST_CHUNK_LEN: usize = 64;
+
+/// Number of chunks, enough to cover every possible `FuncId`.
+const NUM_CHUNKS: usize = 27;
+
+/// An append-only table of function addresses, indexed by `FuncId`.
+///
+/// The table consists of chunks of doubling size that are allocated on first use and never
+/// moved, so readers only ever follow two atomic pointers.
+struct AddressTable {
+    chunks: [AtomicPtr<AtomicPtr<u8>>; NUM_CHUNKS],
+}
+
+impl AddressTable {
+    fn new() -> Self {
+        const NULL: AtomicPtr<AtomicPtr<u8>> = AtomicPtr::new(ptr::null_mut());
+        Self {
+            chunks: [NULL; NUM_CHUNKS],
+        }
+    }
+
+    /// The length of chunk `chunk`.
+    fn chunk_len(chunk: usize) -> usize {
+        FIRST_CHUNK_LEN << chunk
+    }
+
+    /// The chunk holding entry `index`, and the position of the entry in it.
+    fn locate(index: usize) -> (usize, usize) {
+        let n = index / FIRST_CHUNK_LEN + 1;
+        let chunk = (usize::BITS - 1 - n.leading_zeros()) as usize;
+        let chunk_start = FIRST_CHUNK_LEN * ((1 << chunk) - 1);
+        (chunk, index - chunk_start)
+    }
+
+    fn get(&self, index: usize) -> Option<*const u8> {
+        let (chunk, offset) = Self::locate(index);
+        let entries = self.chunks[chunk].load(Ordering::Acquire);
+        if entries.is_null() {
+            return None;
+        }
+        let ptr = unsafe { &*entries.add(offset) }.load(Ordering::Acquire);
+        if ptr.is_null() {
+            None
+        } else {
+            Some(ptr)
+        }
+    }
+
+    fn set(&self, index: usize, ptr: *const u8) {
+        let (chunk, offset) = Self::locate(index);
+        let mut entries = self.chunks[chunk].load(Ordering::Acquire);
+        if entries.is_null() {
+            let new: Box<[AtomicPtr<u8>]> = (0..Self::chunk_len(chunk))
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect::<Vec<_>>()
+                .into_boxed_slice();
+            let new = Box::into_raw(new) as *mut AtomicPtr<u8>;
+            match self.chunks[chunk].compare_exchange(
+                ptr::null_mut(),
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => entries = new,
+                Err(existing) => {
+                    unsafe { drop(Self::chunk_from_raw(chunk, new)) };
+                    entries = existing;
+                }
+            }
+        }
+        unsafe { &*entries.add(offset) }.store(ptr as *mut u8, Ordering::Release);
+    }
+
+    unsafe fn chunk_from_raw(chunk: usize, entries: *mut AtomicPtr<u8>) -> Box<[AtomicPtr<u8>]> {
+        Box::from_raw(ptr::slice_from_raw_parts_mut(
+            entries,
+            Self::chunk_len(chunk),
+        ))
+    }
+}
+
+impl Drop for AddressTable {
+    fn drop(&mut self) {
+        for (chunk, entries) in self.chunks.iter_mut().enumerate() {
+            let entries = *entries.get_mut();
+            if !entries.is_null() {
+                unsafe { drop(Self::chunk_from_raw(chunk, entries)) };
+            }
+        }
+    }
+}
+
+/// A handle to the finalized functions of a `JITModule`, obtained from
+/// [`JITModule::finalized_functions`](crate::JITModule::finalized_functions).
+///
+/// The handle is cheap to clone and can be sent to other threads, which can then look up
+/// finalized functions without locking while the module keeps defining and finalizing
+/// functions.
+#[derive(Clone)]
+pub struct FinalizedFunctions {
+    table: Arc<AddressTable>,
+}
+
+impl FinalizedFunctions {
+    pub(crate) fn new() -> Self {
+        Self {
+            table: Arc::new(AddressTable::new()),
+        }
+    }
+
+    /// Returns the address of the function `func_id`, or `None` if it hasn't been finalized yet.
+    ///
+    /// After a function is redefined with hotswap support enabled, this keeps returning the
+    /// previous definition until the new one is finalized. The pointer remains valid until
+    /// [`JITModule::free_memory`](crate::JITModule::free_memory) is called.
+    pub fn get(&self, func_id: FuncId) -> Option<*const u8> {
+        self.table.get(func_id.index())
+    }
+
+    /// Publish the address of the finalized function `func_id`.
+    pub(crate) fn publish(&self, func_id: FuncId, ptr: *const u8) {
+        self.table.set(func_id.index(), ptr);
+    }
+}
+
+/// A function finalized by a call to
+/// [`JITModule::finalize_definitions`](crate::JITModule::finalize_definitions).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FinalizedFunction {
+    /// The entry point of the function.
+    pub ptr: *const u8,
+    /// The size of the code of the function, in bytes.
+    pub size: usize,
+    /// The data objects whose addresses were resolved in the code of the function, in the order
+    /// they are first referenced.
+    pub data_refs: Vec<DataId>,
+}
+
+/// The functions and data objects finalized by one call to
+/// [`JITModule::finalize_definitions`](crate::JITModule::finalize_definitions).
+///
+/// Every function of the batch is already published to the
+/// [`FinalizedFunctions`] handles by the time the batch is returned, so passing the batch to
+/// another thread hands over all of its functions at once. The pointers remain valid until
+/// [`JITModule::free_memory`](crate::JITModule::free_memory) is called.
+#[derive(Clone, Debug, Default)]
+pub struct FinalizedBatch {
+    functions: HashMap<FuncId, FinalizedFunction>,
+    data_objects: HashMap<DataId, (*const u8, usize)>,
+}
+
+// The pointers of a batch point to finalized code and data, which are never written again.
+unsafe impl Send for FinalizedBatch {}
+unsafe impl Sync for FinalizedBatch {}
+
+impl FinalizedBatch {
+    /// The function `func_id`, if it was finalized in this batch.
+    pub fn function(&self, func_id: FuncId) -> Option<&FinalizedFunction> {
+        self.functions.get(&func_id)
+    }
+
+    /// The address and size of the data object `data_id`, if it was finalized in this batch.
+    pub fn data_object(&self, data_id: DataId) -> Option<(*const u8, usize)> {
+        self.data_objects.get(&data_id).copied()
+    }
+
+    /// The functions finalized in this batch, in no particular order.
+    pub fn functions(&self) -> impl Iterator<Item = (FuncId, &FinalizedFunction)> {
+        self.functions.iter().map(|(&id, func)| (id, func))
+    }
+
+    /// The data objects finalized in this batch with their addresses and sizes, in no particular
+    /// order.
+    pub fn data_objects(&self) -> impl Iterator<Item = (DataId, (*const u8, usize))> + '_ {
+        self.data_objects.iter().map(|(&id, &object)| (id, object))
+    }
+
+    /// Whether nothing was finalized in this batch.
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty() && self.data_objects.is_empty()
+    }
+
+    pub(crate) fn add_function(&mut self, func_id: FuncId, func: FinalizedFunction) {
+        self.functions.insert(func_id, func);
+    }
+
+    pub(crate) fn add_data_object(&mut self, data_id: DataId, ptr: *const u8, size: usize) {
+        self.data_objects.insert(data_id, (ptr, size));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        assert_eq!(AddressTable::locate(0), (0, 0));
+        assert_eq!(AddressTable::locate(63), (0, 63));
+        assert_eq!(AddressTable::locate(64), (1, 0));
+        assert_eq!(AddressTable::locate(191), (1, 127));
+        assert_eq!(AddressTable::locate(192), (2, 0));
+        assert_eq!(AddressTable::locate(u32::MAX as usize).0, NUM_CHUNKS - 1);
+    }
+
+    #[test]
+    fn test_get_and_publish() {
+        let functions = FinalizedFunctions::new();
+        let id = FuncId::new(1000);
+        assert_eq!(functions.get(id), None);
+        functions.publish(id, 0x1000 as *const u8);
+        assert_eq!(functions.get(id), Some(0x1000 as *const u8));
+        assert_eq!(functions.get(FuncId::new(1001)), None);
+    }
+}
@@ -25,8 +25,19 @@
 #![no_std]
 
 mod backend;
+mod backtrace;
+mod code_hash;
 mod compiled_blob;
+mod finalized;
 mod memory;
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod pkey;
+mod quota;
+mod traps;
 
 extern crate alloc;
 
@@ -39,14 +50,30 @@ use spin::{Mutex, MutexGuard};
 use std::sync::{Mutex, MutexGuard};
 
 pub use crate::backend::{JITBuilder, JITModule};
+pub use crate::backtrace::{walk_frames, Frame};
+pub use crate::code_hash::FunctionHash;
+pub use crate::finalized::{FinalizedBatch, FinalizedFunction, FinalizedFunctions};
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub use crate::pkey::ProtectionKey;
+pub use crate::quota::QuotaManager;
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub use crate::traps::install_trap_handler;
+pub use crate::traps::{lookup_trap, Trap};
 use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use hashbrown::HashMap;
 use lazy_static::lazy_static;
 
 /// Version number of this crate.
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 lazy_static! {
-    static ref MANAGER: Mutex<Box<dyn MemoryManager + Send>> = Mutex::new(Box::new(DefaultManager));
+    static ref MANAGER: Mutex<Box<dyn MemoryManager + Send>> =
+        Mutex::new(Box::new(DefaultManager::new()));
 }
 
 #[cfg(not(feature = "std"))]
@@ -58,6 +85,18 @@ fn mem_manage() -> MutexGuard<'static, Box<dyn MemoryManager + Send>> {
     MANAGER.lock().unwrap()
 }
 
+/// A memory manager shared by the memories of one `JITModule`.
+type SharedManager = alloc::sync::Arc<Mutex<Box<dyn MemoryManager + Send>>>;
+
+#[cfg(not(feature = "std"))]
+fn lock_manager(manager: &SharedManager) -> MutexGuard<'_, Box<dyn MemoryManager + Send>> {
+    manager.lock()
+}
+#[cfg(feature = "std")]
+fn lock_manager(manager: &SharedManager) -> MutexGuard<'_, Box<dyn MemoryManager + Send>> {
+    manager.lock().unwrap()
+}
+
 /// Set the memory manager. See below.
 /// Only call once or expect unsoundness bugs.
 /// Not needed with feature std as default allocator is used.
@@ -77,14 +116,62 @@ pub trait MemoryManager {
     fn set_rx(&mut self, ptr: *mut u8, size: usize);
     /// Sets the pointer obtained from `alloc_page_aligned` as RW
     fn set_rw(&mut self, ptr: *mut u8, size: usize);
-    /// Allocates a new page-aligned pointer of `size`, which should be a multiple of page size
+    /// Allocates a new page-aligned pointer of `size`, which should be a multiple of page size.
+    /// Returns a null pointer if the allocation can't be satisfied.
     fn alloc_page_aligned(&mut self, size: usize) -> *mut u8;
     /// Deallocates pointer obtained from `alloc_page_aligned`
     /// `size` must be the same as passed to `alloc_page_aligned`.
     fn dealloc(&mut self, ptr: *mut u8, size: usize);
 }
 
-struct DefaultManager;
+/// The memory manager of modules that weren't given their own, which forwards to the
+/// process-wide one.
+struct GlobalManager;
+
+impl MemoryManager for GlobalManager {
+    fn page_size(&self) -> usize {
+        mem_manage().page_size()
+    }
+
+    fn set_r(&mut self, ptr: *mut u8, size: usize) {
+        mem_manage().set_r(ptr, size)
+    }
+
+    fn set_rx(&mut self, ptr: *mut u8, size: usize) {
+        mem_manage().set_rx(ptr, size)
+    }
+
+    fn set_rw(&mut self, ptr: *mut u8, size: usize) {
+        mem_manage().set_rw(ptr, size)
+    }
+
+    fn alloc_page_aligned(&mut self, size: usize) -> *mut u8 {
+        mem_manage().alloc_page_aligned(size)
+    }
+
+    fn dealloc(&mut self, ptr: *mut u8, size: usize) {
+        mem_manage().dealloc(ptr, size)
+    }
+}
+
+/// The default memory manager.
+///
+/// With feature std, this hands out anonymous memory mappings (`mmap` on Unix,
+/// `VirtualAlloc` on Windows) and keeps track of each region's size so that
+/// `dealloc` can return them to the OS again.
+struct DefaultManager {
+    #[cfg(feature = "std")]
+    regions: HashMap<usize, usize>,
+}
+
+impl DefaultManager {
+    fn new() -> Self {
+        Self {
+            #[cfg(feature = "std")]
+            regions: HashMap::new(),
+        }
+    }
+}
 
 #[cfg(feature = "std")]
 impl MemoryManager for DefaultManager {
@@ -106,12 +193,21 @@ impl MemoryManager for DefaultManager {
 
     #[cfg(not(target_os = "windows"))]
     fn alloc_page_aligned(&mut self, size: usize) -> *mut u8 {
-        let mut ptr = core::ptr::null_mut();
-        unsafe {
-            let err = libc::posix_memalign(&mut ptr, self.page_size(), size);
-            assert_eq!(err, 0);
-            ptr as *mut u8
+        let ptr = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return core::ptr::null_mut();
         }
+        self.regions.insert(ptr as usize, size);
+        ptr as *mut u8
     }
 
     #[cfg(target_os = "windows")]
@@ -121,18 +217,44 @@ impl MemoryManager for DefaultManager {
             winnt::{MEM_COMMIT, MEM_RESERVE, PAGE_READWRITE},
         };
 
-        unsafe {
+        let ptr = unsafe {
             VirtualAlloc(
                 core::ptr::null_mut(),
                 size,
                 MEM_COMMIT | MEM_RESERVE,
                 PAGE_READWRITE,
-            ) as *mut u8
+            )
+        };
+        if ptr.is_null() {
+            return core::ptr::null_mut();
         }
+        self.regions.insert(ptr as usize, size);
+        ptr as *mut u8
     }
 
-    fn dealloc(&mut self, ptr: *mut u8, _size: usize) {
-        unsafe { libc::free(ptr as _); }
+    #[cfg(not(target_os = "windows"))]
+    fn dealloc(&mut self, ptr: *mut u8, size: usize) {
+        let len = self
+            .regions
+            .remove(&(ptr as usize))
+            .expect("dealloc of a region not obtained from alloc_page_aligned");
+        debug_assert_eq!(len, size);
+        let err = unsafe { libc::munmap(ptr as *mut libc::c_void, len) };
+        assert_eq!(err, 0, "munmap of {} bytes failed", len);
+    }
+
+    #[cfg(target_os = "windows")]
+    fn dealloc(&mut self, ptr: *mut u8, size: usize) {
+        use winapi::um::{memoryapi::VirtualFree, winnt::MEM_RELEASE};
+
+        let len = self
+            .regions
+            .remove(&(ptr as usize))
+            .expect("dealloc of a region not obtained from alloc_page_aligned");
+        debug_assert_eq!(len, size);
+        // `MEM_RELEASE` requires a size of zero and frees the whole reservation.
+        let ok = unsafe { VirtualFree(ptr as _, 0, MEM_RELEASE) };
+        assert_ne!(ok, 0, "VirtualFree of {} bytes failed", len);
     }
 }
 
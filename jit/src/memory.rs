@@ -1,4 +1,4 @@
-use crate::mem_manage;
+use crate::{lock_manager, SharedManager};
 use alloc::vec::Vec;
 use core::{convert::TryFrom, mem, ptr};
 
@@ -7,10 +7,12 @@ fn round_up_to_page_size(size: usize, page_size: usize) -> usize {
     (size + (page_size - 1)) & !(page_size - 1)
 }
 
-/// A simple struct consisting of a pointer and length.
+/// A simple struct consisting of a pointer and length, and the memory manager the pointer was
+/// obtained from.
 struct PtrLen {
     ptr: *mut u8,
     len: usize,
+    manager: Option<SharedManager>,
 }
 
 impl PtrLen {
@@ -19,25 +21,31 @@ impl PtrLen {
         Self {
             ptr: ptr::null_mut(),
             len: 0,
+            manager: None,
         }
     }
 
-    fn with_size(size: usize) -> Result<Self, ()> {
-        let page_size = mem_manage().page_size();
-        let alloc_size = round_up_to_page_size(size, page_size);
-        let ptr = mem_manage().alloc_page_aligned(alloc_size);
+    fn with_size(manager: &SharedManager, size: usize) -> Result<Self, ()> {
+        let mut mgr = lock_manager(manager);
+        let alloc_size = round_up_to_page_size(size, mgr.page_size());
+        let ptr = mgr.alloc_page_aligned(alloc_size);
+        if ptr.is_null() {
+            return Err(());
+        }
         Ok(Self {
             ptr,
             len: alloc_size,
+            manager: Some(manager.clone()),
         })
     }
 }
 
 impl Drop for PtrLen {
     fn drop(&mut self) {
-        if !self.ptr.is_null() {
-            mem_manage().set_rw(self.ptr, self.len);
-            mem_manage().dealloc(self.ptr, self.len);
+        if let Some(manager) = &self.manager {
+            let mut mgr = lock_manager(manager);
+            mgr.set_rw(self.ptr, self.len);
+            mgr.dealloc(self.ptr, self.len);
         }
     }
 }
@@ -47,6 +55,7 @@ impl Drop for PtrLen {
 /// function pointers remain valid for the remainder of the
 /// program's life.
 pub(crate) struct Memory {
+    manager: SharedManager,
     allocations: Vec<PtrLen>,
     executable: usize,
     current: PtrLen,
@@ -54,8 +63,9 @@ pub(crate) struct Memory {
 }
 
 impl Memory {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(manager: SharedManager) -> Self {
         Self {
+            manager,
             allocations: Vec::new(),
             executable: 0,
             current: PtrLen::new(),
@@ -86,7 +96,7 @@ impl Memory {
         self.finish_current();
 
         // TODO: Allocate more at a time.
-        self.current = PtrLen::with_size(size)?;
+        self.current = PtrLen::with_size(&self.manager, size)?;
         self.position = size;
         Ok(self.current.ptr)
     }
@@ -96,9 +106,10 @@ impl Memory {
         self.finish_current();
 
         {
-            for &PtrLen { ptr, len } in &self.allocations[self.executable..] {
+            let mut mgr = lock_manager(&self.manager);
+            for &PtrLen { ptr, len, .. } in &self.allocations[self.executable..] {
                 if len != 0 {
-                    mem_manage().set_rx(ptr, len);
+                    mgr.set_rx(ptr, len);
                 }
             }
         }
@@ -109,9 +120,10 @@ impl Memory {
         self.finish_current();
 
         {
-            for &PtrLen { ptr, len } in &self.allocations[self.executable..] {
+            let mut mgr = lock_manager(&self.manager);
+            for &PtrLen { ptr, len, .. } in &self.allocations[self.executable..] {
                 if len != 0 {
-                    mem_manage().set_r(ptr, len);
+                    mgr.set_r(ptr, len);
                 }
             }
         }
@@ -120,6 +132,7 @@ impl Memory {
     /// Frees all allocated memory regions that would be leaked otherwise.
     /// Likely to invalidate existing function pointers, causing unsafety.
     pub(crate) unsafe fn free_memory(&mut self) {
+        self.finish_current();
         self.allocations.clear();
     }
 }
@@ -127,6 +140,7 @@ impl Memory {
 impl Drop for Memory {
     fn drop(&mut self) {
         // leak memory to guarantee validity of function pointers
+        self.finish_current();
         mem::replace(&mut self.allocations, Vec::new())
             .into_iter()
             .for_each(mem::forget);
@@ -136,6 +150,8 @@ impl Drop for Memory {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{DefaultManager, GlobalManager, MemoryManager, Mutex};
+    use alloc::{boxed::Box, sync::Arc};
 
     #[test]
     fn test_round_up_to_page_size() {
@@ -144,4 +160,31 @@ mod tests {
         assert_eq!(round_up_to_page_size(4096, 4096), 4096);
         assert_eq!(round_up_to_page_size(4097, 4096), 8192);
     }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_free_memory() {
+        let manager = Arc::new(Mutex::new(Box::new(GlobalManager) as Box<_>));
+        let mut memory = Memory::new(manager);
+        let ptr = memory.allocate(100, 16).unwrap();
+        assert!(!ptr.is_null());
+        unsafe { ptr.write_bytes(0xcc, 100) };
+        memory.set_readonly();
+        unsafe { memory.free_memory() };
+        assert!(memory.allocations.is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_failed_allocation() {
+        let mut mgr = DefaultManager::new();
+        let size = round_up_to_page_size(isize::MAX as usize / 2, mgr.page_size());
+        assert!(mgr.alloc_page_aligned(size).is_null());
+        assert!(mgr.regions.is_empty());
+
+        let manager = Arc::new(Mutex::new(Box::new(mgr) as Box<_>));
+        let mut memory = Memory::new(manager);
+        assert!(memory.allocate(size, 16).is_err());
+        assert!(!memory.allocate(100, 16).unwrap().is_null());
+    }
 }
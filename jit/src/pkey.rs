@@ -0,0 +1,85 @@
+//! Memory protection keys, to confine JIT code to some of the memory of the process.
+//!
+//! With protection keys (Intel MPK on x86-64, the permission overlays of POE on aarch64), each
+//! page is tagged with one of 16 keys, and a per-thread register holds the access rights to the
+//! pages of each key. Changing the rights only takes an unprivileged instruction, which makes it
+//! cheap to confine sandboxed code to its own heaps: tag each heap with a `ProtectionKey`, and
+//! call into the sandbox through a trampoline from `JITModule::define_pkey_trampoline` which only
+//! allows access to the keys of its heaps while it runs.
+
+use std::io;
+
+/// A memory protection key, allocated from the operating system.
+///
+/// The key is freed when this is dropped; pages tagged with it must be tagged with another key
+/// or unmapped first.
+#[derive(Debug)]
+pub struct ProtectionKey {
+    key: u32,
+}
+
+impl ProtectionKey {
+    /// Allocate a protection key, to which the current thread initially has full access.
+    ///
+    /// Returns `None` if the CPU or the kernel doesn't support protection keys, or if all the
+    /// keys are in use.
+    pub fn new() -> Option<Self> {
+        let key = unsafe { libc::syscall(libc::SYS_pkey_alloc, 0, 0) };
+        if key < 0 {
+            None
+        } else {
+            Some(Self { key: key as u32 })
+        }
+    }
+
+    /// The number of this key, between 1 and 15.
+    pub fn key(&self) -> u32 {
+        self.key
+    }
+
+    /// Make the pages of `ptr..ptr + len` readable and writable, and tag them with this key.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be page-aligned, and the pages must be mapped and not in use by code that
+    /// doesn't have access to this key.
+    pub unsafe fn protect(&self, ptr: *mut u8, len: usize) -> io::Result<()> {
+        let err = libc::syscall(
+            libc::SYS_pkey_mprotect,
+            ptr,
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            self.key,
+        );
+        if err != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// The protection key rights, as read and written by the `get_pkey_rights` and
+    /// `set_pkey_rights` instructions, that allow full access to the memory tagged with `keys`
+    /// and deny access to the memory tagged with any other key, except the default key 0, with
+    /// which the stacks and the rest of the memory of the process are tagged.
+    pub fn rights_allowing(keys: &[&Self]) -> u64 {
+        let allowed = keys.iter().map(|key| key.key).chain(Some(0));
+
+        // PKRU has an access-disable bit followed by a write-disable bit for each key.
+        #[cfg(target_arch = "x86_64")]
+        let rights = allowed.fold(u32::max_value(), |rights, key| {
+            rights & !(0b11 << (2 * key))
+        });
+
+        // POR_EL0 has four bits of permissions for each key, of which 0b0111 grants all of them.
+        #[cfg(target_arch = "aarch64")]
+        let rights = allowed.fold(0u64, |rights, key| rights | (0b0111 << (4 * key)));
+
+        u64::from(rights)
+    }
+}
+
+impl Drop for ProtectionKey {
+    fn drop(&mut self) {
+        unsafe { libc::syscall(libc::SYS_pkey_free, self.key) };
+    }
+}
@@ -0,0 +1,114 @@
+//! A memory manager that puts an upper bound on the memory handed out to the JIT.
+
+use crate::MemoryManager;
+use alloc::boxed::Box;
+
+/// A `MemoryManager` wrapper which enforces a quota on the total amount of code and data
+/// memory allocated through it.
+///
+/// Once the quota would be exceeded, `alloc_page_aligned` returns a null pointer instead of
+/// forwarding the request, which makes the affected `define_function`/`define_data` call fail
+/// with a `ModuleError` instead of growing without bound. This is meant for embedders that JIT
+/// untrusted input and want to contain how much memory a single module can consume: give each
+/// module its own `QuotaManager` through `JITBuilder::memory_manager`, or use
+/// `JITBuilder::memory_quota`. Memory is credited back to the quota when it is freed.
+pub struct QuotaManager {
+    inner: Box<dyn MemoryManager + Send>,
+    max_bytes: usize,
+    used_bytes: usize,
+}
+
+impl QuotaManager {
+    /// Wrap `inner`, allowing at most `max_bytes` to be allocated at any point in time.
+    pub fn new(inner: Box<dyn MemoryManager + Send>, max_bytes: usize) -> Self {
+        Self {
+            inner,
+            max_bytes,
+            used_bytes: 0,
+        }
+    }
+
+    /// The maximum number of bytes that may be allocated at once.
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// The number of bytes currently allocated through this manager.
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+}
+
+impl MemoryManager for QuotaManager {
+    fn page_size(&self) -> usize {
+        self.inner.page_size()
+    }
+
+    fn set_r(&mut self, ptr: *mut u8, size: usize) {
+        self.inner.set_r(ptr, size)
+    }
+
+    fn set_rx(&mut self, ptr: *mut u8, size: usize) {
+        self.inner.set_rx(ptr, size)
+    }
+
+    fn set_rw(&mut self, ptr: *mut u8, size: usize) {
+        self.inner.set_rw(ptr, size)
+    }
+
+    fn alloc_page_aligned(&mut self, size: usize) -> *mut u8 {
+        match self.used_bytes.checked_add(size) {
+            Some(total) if total <= self.max_bytes => {}
+            _ => return core::ptr::null_mut(),
+        }
+        let ptr = self.inner.alloc_page_aligned(size);
+        if !ptr.is_null() {
+            self.used_bytes += size;
+        }
+        ptr
+    }
+
+    fn dealloc(&mut self, ptr: *mut u8, size: usize) {
+        self.inner.dealloc(ptr, size);
+        let used_bytes = self.used_bytes.checked_sub(size);
+        debug_assert!(
+            used_bytes.is_some(),
+            "dealloc of {} bytes that weren't allocated through this QuotaManager",
+            size
+        );
+        self.used_bytes = used_bytes.unwrap_or(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fake;
+
+    impl MemoryManager for Fake {
+        fn page_size(&self) -> usize {
+            4096
+        }
+        fn set_r(&mut self, _ptr: *mut u8, _size: usize) {}
+        fn set_rx(&mut self, _ptr: *mut u8, _size: usize) {}
+        fn set_rw(&mut self, _ptr: *mut u8, _size: usize) {}
+        fn alloc_page_aligned(&mut self, _size: usize) -> *mut u8 {
+            4096 as *mut u8
+        }
+        fn dealloc(&mut self, _ptr: *mut u8, _size: usize) {}
+    }
+
+    #[test]
+    fn test_quota() {
+        let mut mgr = QuotaManager::new(Box::new(Fake), 8192);
+        let a = mgr.alloc_page_aligned(4096);
+        assert!(!a.is_null());
+        assert!(!mgr.alloc_page_aligned(4096).is_null());
+        assert!(mgr.alloc_page_aligned(4096).is_null());
+        assert_eq!(mgr.used_bytes(), 8192);
+        mgr.dealloc(a, 4096);
+        assert_eq!(mgr.used_bytes(), 4096);
+        assert!(!mgr.alloc_page_aligned(4096).is_null());
+    }
+}
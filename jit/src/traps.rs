@@ -0,0 +1,375 @@
+//! Mapping of faulting PCs in JIT code back to the trap information recorded at compile time.
+//!
+//! Every function defined through a `JITModule` registers its code range together with its
+//! trap sites here. Embedders with their own signal handling can use [`lookup_trap`] to turn a
+//! faulting PC into a [`Trap`]; with feature std, [`install_trap_handler`] installs a process
+//! wide handler that does this for them.
+
+use crate::Mutex;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use cranelift_codegen::{binemit::CodeOffset, ir};
+use cranelift_module::{FuncId, TrapSite};
+use lazy_static::lazy_static;
+
+/// A trap raised by JIT code, as delivered to the embedder.
+#[derive(Clone, Debug)]
+pub struct Trap {
+    /// The PC of the faulting instruction.
+    pub pc: usize,
+    /// The function the faulting instruction belongs to.
+    pub func_id: FuncId,
+    /// Offset of the faulting instruction from the start of the function.
+    pub offset: CodeOffset,
+    /// Trap code, as determined by cranelift.
+    pub code: ir::TrapCode,
+    /// Source location given to cranelift.
+    pub srcloc: ir::SourceLoc,
+}
+
+/// The code range and trap sites of one function.
+struct RegisteredFunction {
+    end: usize,
+    func_id: FuncId,
+    traps: Vec<TrapSite>,
+}
+
+/// An immutable copy of the registered functions, sorted by start address, that lookups read
+/// without locking.
+type Snapshot = Vec<(usize, Arc<RegisteredFunction>)>;
+
+/// The registered functions, as maintained by `register` and `unregister`.
+struct Registry {
+    /// All registered functions, keyed by their start address.
+    functions: BTreeMap<usize, Arc<RegisteredFunction>>,
+    /// Replaced snapshots that lookups may still be reading.
+    retired: Vec<Box<Snapshot>>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+        functions: BTreeMap::new(),
+        retired: Vec::new(),
+    });
+}
+
+/// The current snapshot of the registry, or null if nothing was ever registered.
+static SNAPSHOT: AtomicPtr<Snapshot> = AtomicPtr::new(ptr::null_mut());
+
+/// The number of lookups currently reading a snapshot.
+static READERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Record the trap sites of the function `func_id`, whose code lives at `ptr..ptr + size`.
+pub(crate) fn register(ptr: *const u8, size: usize, func_id: FuncId, traps: Vec<TrapSite>) {
+    let start = ptr as usize;
+    let function = RegisteredFunction {
+        end: start + size,
+        func_id,
+        traps,
+    };
+    let mut registry = lock_registry();
+    registry.functions.insert(start, Arc::new(function));
+    publish(&mut registry);
+}
+
+/// Forget the function whose code starts at `ptr`, e.g. because its memory is being freed.
+pub(crate) fn unregister(ptr: *const u8) {
+    let mut registry = lock_registry();
+    if registry.functions.remove(&(ptr as usize)).is_some() {
+        publish(&mut registry);
+    }
+}
+
+/// Replace the snapshot read by lookups with the current contents of `registry`.
+fn publish(registry: &mut Registry) {
+    let snapshot: Snapshot = registry
+        .functions
+        .iter()
+        .map(|(&start, function)| (start, function.clone()))
+        .collect();
+    let old = SNAPSHOT.swap(Box::into_raw(Box::new(snapshot)), Ordering::SeqCst);
+    if !old.is_null() {
+        registry.retired.push(unsafe { Box::from_raw(old) });
+    }
+    // A lookup that starts after this point reads the new snapshot, so the retired ones can go
+    // once no lookup is in progress. Otherwise they are freed by a later update.
+    if READERS.load(Ordering::SeqCst) == 0 {
+        registry.retired.clear();
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn lock_registry() -> spin::MutexGuard<'static, Registry> {
+    REGISTRY.lock()
+}
+#[cfg(feature = "std")]
+fn lock_registry() -> std::sync::MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap()
+}
+
+/// Call `f` with the start address and registration of the function containing `pc`, if any.
+///
+/// This neither blocks nor allocates, so it may be called from a signal handler, including one
+/// that interrupted an update of the registry.
+fn with_function<T>(
+    pc: usize,
+    f: impl FnOnce(usize, &RegisteredFunction) -> Option<T>,
+) -> Option<T> {
+    READERS.fetch_add(1, Ordering::SeqCst);
+    let snapshot = SNAPSHOT.load(Ordering::SeqCst);
+    let result = if snapshot.is_null() {
+        None
+    } else {
+        let snapshot = unsafe { &*snapshot };
+        let index = snapshot.partition_point(|&(start, _)| start <= pc);
+        index
+            .checked_sub(1)
+            .map(|i| &snapshot[i])
+            .filter(|(_, function)| pc < function.end)
+            .and_then(|(start, function)| f(*start, function))
+    };
+    READERS.fetch_sub(1, Ordering::SeqCst);
+    result
+}
+
+/// Look up the JIT function containing `pc`, returning its id and the offset of `pc` from its
+/// start. Like [`lookup_trap`], this never blocks.
+pub(crate) fn lookup_function(pc: usize) -> Option<(FuncId, CodeOffset)> {
+    with_function(pc, |start, function| {
+        Some((function.func_id, (pc - start) as CodeOffset))
+    })
+}
+
+/// Look up the trap recorded for the instruction at `pc`, if `pc` is a trapping instruction in
+/// JIT code.
+///
+/// This neither blocks nor allocates, so it may be called from a signal handler.
+pub fn lookup_trap(pc: usize) -> Option<Trap> {
+    with_function(pc, |start, function| {
+        let offset = (pc - start) as CodeOffset;
+        function
+            .traps
+            .iter()
+            .find(|site| site.offset == offset)
+            .map(|site| Trap {
+                pc,
+                func_id: function.func_id,
+                offset,
+                code: site.code,
+                srcloc: site.srcloc,
+            })
+    })
+}
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub use self::handler::install_trap_handler;
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+mod handler {
+    use super::{lookup_trap, Trap};
+    use alloc::boxed::Box;
+    use core::ptr;
+    use core::sync::atomic::{AtomicPtr, Ordering};
+
+    type Handler = Box<dyn Fn(&Trap) + Send + Sync>;
+
+    /// The embedder's handler, double-boxed so that it fits in an `AtomicPtr`.
+    static HANDLER: AtomicPtr<Handler> = AtomicPtr::new(ptr::null_mut());
+
+    /// Install a process-wide handler for hardware traps raised by JIT code.
+    ///
+    /// When JIT code faults at one of its trap sites (an out-of-bounds heap access, a `ud2`, an
+    /// integer division by zero, ...), `handler` is called on the faulting thread with the
+    /// corresponding [`Trap`]. It runs in signal (or vectored exception) context and should
+    /// only do async-signal-safe work before transferring control elsewhere, e.g. by unwinding
+    /// to a point set up by the embedder. If it returns, or if the fault isn't at a trap site
+    /// in JIT code, the fault is passed on to whatever handler was installed before, which by
+    /// default terminates the process.
+    ///
+    /// Calling this again replaces the previous handler.
+    pub fn install_trap_handler(handler: Box<dyn Fn(&Trap) + Send + Sync>) {
+        let new = Box::into_raw(Box::new(handler));
+        let old = HANDLER.swap(new, Ordering::SeqCst);
+        if old.is_null() {
+            unsafe { platform::install() };
+        }
+        // A replaced handler is leaked, since another thread may still be running it.
+    }
+
+    /// Deliver the fault at `pc` to the embedder's handler, if it is a trap in JIT code.
+    fn deliver(pc: usize) {
+        let handler = HANDLER.load(Ordering::SeqCst);
+        if handler.is_null() {
+            return;
+        }
+        if let Some(trap) = lookup_trap(pc) {
+            unsafe { (*handler)(&trap) };
+        }
+    }
+
+    #[cfg(unix)]
+    mod platform {
+        use core::mem;
+        use core::ptr;
+        use libc::{c_int, c_void, sigaction, siginfo_t};
+
+        /// The signals hardware traps can arrive as.
+        const SIGNALS: [c_int; 4] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGILL, libc::SIGFPE];
+
+        /// The handlers installed before ours, in the order of `SIGNALS`.
+        static mut PREVIOUS: [mem::MaybeUninit<sigaction>; 4] = [mem::MaybeUninit::uninit(); 4];
+
+        pub(super) unsafe fn install() {
+            for (i, &signum) in SIGNALS.iter().enumerate() {
+                let mut handler: sigaction = mem::zeroed();
+                handler.sa_sigaction = trap_handler as *const () as usize;
+                handler.sa_flags = libc::SA_SIGINFO | libc::SA_NODEFER | libc::SA_ONSTACK;
+                libc::sigemptyset(&mut handler.sa_mask);
+                let previous = (*ptr::addr_of_mut!(PREVIOUS))[i].as_mut_ptr();
+                if libc::sigaction(signum, &handler, previous) != 0 {
+                    panic!(
+                        "unable to install the JIT trap handler for signal {}",
+                        signum
+                    );
+                }
+            }
+        }
+
+        unsafe extern "C" fn trap_handler(
+            signum: c_int,
+            info: *mut siginfo_t,
+            context: *mut c_void,
+        ) {
+            if let Some(pc) = get_pc(context) {
+                super::deliver(pc);
+            }
+            // Either this isn't a JIT trap, or the embedder's handler returned: pass the fault on
+            // to the handler installed before ours, leaving ours in place for later traps.
+            let i = match SIGNALS.iter().position(|&s| s == signum) {
+                Some(i) => i,
+                None => return,
+            };
+            let previous = &*(*ptr::addr_of!(PREVIOUS))[i].as_ptr();
+            if previous.sa_flags & libc::SA_SIGINFO != 0 {
+                let handler: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) =
+                    mem::transmute(previous.sa_sigaction);
+                handler(signum, info, context);
+            } else if previous.sa_sigaction == libc::SIG_DFL
+                || previous.sa_sigaction == libc::SIG_IGN
+            {
+                // The default action terminates the process, and a hardware fault can't be
+                // ignored, so reinstate that disposition and return: the faulting instruction
+                // then runs again and the process dies of the signal as it would have without us.
+                libc::sigaction(signum, previous, ptr::null_mut());
+            } else {
+                let handler: extern "C" fn(c_int) = mem::transmute(previous.sa_sigaction);
+                handler(signum);
+            }
+        }
+
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        unsafe fn get_pc(context: *mut c_void) -> Option<usize> {
+            let context = &*(context as *const libc::ucontext_t);
+            Some(context.uc_mcontext.gregs[libc::REG_RIP as usize] as usize)
+        }
+
+        #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+        unsafe fn get_pc(context: *mut c_void) -> Option<usize> {
+            let context = &*(context as *const libc::ucontext_t);
+            Some(context.uc_mcontext.pc as usize)
+        }
+
+        #[cfg(all(target_os = "macos", target_arch = "x86_64"))]
+        unsafe fn get_pc(context: *mut c_void) -> Option<usize> {
+            let context = &*(context as *const libc::ucontext_t);
+            Some((*context.uc_mcontext).__ss.__rip as usize)
+        }
+
+        #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+        unsafe fn get_pc(context: *mut c_void) -> Option<usize> {
+            let context = &*(context as *const libc::ucontext_t);
+            Some((*context.uc_mcontext).__ss.__pc as usize)
+        }
+
+        #[cfg(not(any(
+            all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ),
+            all(
+                target_os = "macos",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ),
+        )))]
+        unsafe fn get_pc(_context: *mut c_void) -> Option<usize> {
+            None
+        }
+    }
+
+    #[cfg(windows)]
+    mod platform {
+        use winapi::um::errhandlingapi::AddVectoredExceptionHandler;
+        use winapi::um::winnt::{EXCEPTION_POINTERS, LONG};
+        use winapi::vc::excpt::EXCEPTION_CONTINUE_SEARCH;
+
+        pub(super) unsafe fn install() {
+            if AddVectoredExceptionHandler(1, Some(trap_handler)).is_null() {
+                panic!("unable to install the JIT trap handler");
+            }
+        }
+
+        unsafe extern "system" fn trap_handler(info: *mut EXCEPTION_POINTERS) -> LONG {
+            if let Some(pc) = get_pc(info) {
+                super::deliver(pc);
+            }
+            EXCEPTION_CONTINUE_SEARCH
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        unsafe fn get_pc(info: *mut EXCEPTION_POINTERS) -> Option<usize> {
+            Some((*(*info).ContextRecord).Rip as usize)
+        }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        unsafe fn get_pc(_info: *mut EXCEPTION_POINTERS) -> Option<usize> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use cranelift_entity::EntityRef;
+
+    #[test]
+    fn test_register_and_lookup() {
+        let site = TrapSite {
+            offset: 8,
+            srcloc: ir::SourceLoc::new(3),
+            code: ir::TrapCode::HeapOutOfBounds,
+        };
+        register(0x1000 as *const u8, 0x10, FuncId::new(1), vec![site]);
+        register(0x1010 as *const u8, 0x10, FuncId::new(2), vec![]);
+
+        let trap = lookup_trap(0x1008).unwrap();
+        assert_eq!(trap.func_id, FuncId::new(1));
+        assert_eq!(trap.offset, 8);
+        assert_eq!(trap.code, ir::TrapCode::HeapOutOfBounds);
+        assert!(lookup_trap(0x1004).is_none());
+        assert_eq!(lookup_function(0x1018), Some((FuncId::new(2), 8)));
+        assert_eq!(lookup_function(0x1020), None);
+        assert_eq!(lookup_function(0xfff), None);
+
+        unregister(0x1000 as *const u8);
+        assert!(lookup_trap(0x1008).is_none());
+        assert_eq!(lookup_function(0x1018), Some((FuncId::new(2), 8)));
+        unregister(0x1010 as *const u8);
+        assert_eq!(lookup_function(0x1018), None);
+    }
+}
@@ -1,5 +1,5 @@
 use cranelift_codegen::{
-    binemit::{NullStackMapSink, NullTrapSink},
+    binemit::{self, NullStackMapSink, NullTrapSink},
     ir::{types::I16, *},
     isa::CallConv,
     settings::{self, Configurable},
@@ -157,6 +157,38 @@ fn switch_error() {
     }
 }
 
+#[test]
+fn memory_quota_is_per_module() {
+    fn new_module(max_bytes: usize) -> JITModule {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("use_colocated_libcalls", "false").unwrap();
+        flag_builder.set("is_pic", "false").unwrap();
+        let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+            panic!("host machine is not supported: {}", msg);
+        });
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.memory_quota(max_bytes);
+        JITModule::new(builder)
+    }
+
+    fn define_zeroed(module: &mut JITModule, name: &str, size: usize) -> ModuleResult<()> {
+        let data_id = module.declare_data(name, Linkage::Local, true, false)?;
+        let mut data_ctx = DataContext::new();
+        data_ctx.define_zeroinit(size);
+        module.define_data(data_id, &data_ctx)
+    }
+
+    let mut small = new_module(0x10000);
+    let mut large = new_module(0x40000);
+
+    assert!(define_zeroed(&mut small, "too_big", 0x20000).is_err());
+    define_zeroed(&mut large, "big", 0x20000).unwrap();
+    // Neither the failed allocation nor the other module's memory counts against the quota.
+    define_zeroed(&mut small, "small", 0x1000).unwrap();
+    define_zeroed(&mut large, "big_too", 0x10000).unwrap();
+}
+
 #[test]
 fn libcall_function() {
     let mut flag_builder = settings::builder();
@@ -215,3 +247,2671 @@ fn libcall_function() {
 
     module.finalize_definitions();
 }
+
+#[test]
+fn lookup_trap_in_jit_code() {
+    struct RecordingTrapSink(Vec<(binemit::CodeOffset, TrapCode)>);
+
+    impl binemit::TrapSink for RecordingTrapSink {
+        fn trap(&mut self, offset: binemit::CodeOffset, _: SourceLoc, code: TrapCode) {
+            self.0.push((offset, code));
+        }
+    }
+
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    // FIXME set back to true once the x64 backend supports it.
+    flag_builder.set("is_pic", "false").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+    let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+    builder.user_trap_name(42, "out_of_fuel");
+    let mut module = JITModule::new(builder);
+
+    let sig = Signature {
+        params: vec![],
+        returns: vec![],
+        call_conv: CallConv::SystemV,
+    };
+
+    let func_id = module
+        .declare_function("trapping", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        bcx.ins().trap(TrapCode::User(42));
+    }
+
+    let mut trap_sink = RecordingTrapSink(vec![]);
+    let mut stack_map_sink = NullStackMapSink {};
+    module
+        .define_function(func_id, &mut ctx, &mut trap_sink, &mut stack_map_sink)
+        .unwrap();
+    module.finalize_definitions();
+
+    let code = module.get_finalized_function(func_id) as usize;
+    let &(offset, _) = trap_sink
+        .0
+        .iter()
+        .find(|&&(_, code)| code == TrapCode::User(42))
+        .unwrap();
+
+    let trap = lookup_trap(code + offset as usize).unwrap();
+    assert_eq!(trap.func_id, func_id);
+    assert_eq!(trap.offset, offset);
+    assert_eq!(trap.code, TrapCode::User(42));
+    assert_eq!(
+        module.user_trap_names().name(trap.code),
+        Some("out_of_fuel")
+    );
+    assert!(lookup_trap(code + offset as usize + 1).is_none());
+}
+
+#[test]
+fn walk_frames_through_jit_code() {
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(settings::builder()));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let sig = Signature {
+        params: vec![],
+        returns: vec![],
+        call_conv: CallConv::SystemV,
+    };
+
+    let func_id = module
+        .declare_function("returning", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        bcx.ins().return_(&[]);
+    }
+
+    module
+        .define_function(
+            func_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap();
+    module.finalize_definitions();
+
+    let code = module.get_finalized_function(func_id) as usize;
+
+    // A fake stack of three frames: native code, called by the JIT function, called by native
+    // code at the outermost frame.
+    let mut stack = [0usize; 6];
+    let base = stack.as_ptr() as usize;
+    let word = core::mem::size_of::<usize>();
+    stack[0] = base + 2 * word;
+    stack[1] = code + 4;
+    stack[2] = base + 4 * word;
+    stack[3] = 0x10;
+
+    let mut frames = vec![];
+    unsafe {
+        walk_frames(0x1, stack.as_ptr() as usize, |frame| {
+            frames.push(*frame);
+            true
+        })
+    };
+    assert_eq!(frames.len(), 3);
+    assert!(frames[0].func.is_none());
+    assert_eq!(frames[1].pc, code + 4);
+    assert_eq!(frames[1].func, Some((func_id, 4)));
+    assert_eq!(frames[2].pc, 0x10);
+    assert!(frames[2].func.is_none());
+}
+
+#[test]
+fn define_function_with_sink() {
+    #[derive(Default)]
+    struct RecordingSink {
+        relocs: Vec<RelocRecord>,
+        traps: Vec<TrapSite>,
+    }
+
+    impl ModuleSink for RecordingSink {
+        fn reloc(&mut self, reloc: &RelocRecord) {
+            self.relocs.push(reloc.clone());
+        }
+
+        fn trap(&mut self, site: &TrapSite) {
+            self.traps.push(site.clone());
+        }
+    }
+
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    // FIXME set back to true once the x64 backend supports it.
+    flag_builder.set("is_pic", "false").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let sig = Signature {
+        params: vec![],
+        returns: vec![],
+        call_conv: CallConv::SystemV,
+    };
+
+    let func_id = module
+        .declare_function("caller", Linkage::Local, &sig)
+        .unwrap();
+    let callee = module
+        .declare_function("callee", Linkage::Import, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let local_callee = module.declare_func_in_func(callee, bcx.func);
+        bcx.ins().call(local_callee, &[]);
+        bcx.ins().trap(TrapCode::User(7));
+    }
+
+    let mut sink = RecordingSink::default();
+    module
+        .define_function_with_sink(func_id, &mut ctx, &mut sink)
+        .unwrap();
+
+    assert_eq!(sink.relocs.len(), 1);
+    assert_eq!(sink.relocs[0].name, ExternalName::from(callee));
+    assert!(sink.traps.iter().any(|site| site.code == TrapCode::User(7)));
+}
+
+/// Builds a function returning `10 * index` for indices 0 to 3 through a jump table, and -1
+/// otherwise.
+fn build_switch(func: &mut Function) {
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx: FunctionBuilder = FunctionBuilder::new(func, &mut func_ctx);
+    let start = bcx.create_block();
+    let index = bcx.append_block_param(start, types::I32);
+    let default = bcx.create_block();
+    let targets: Vec<Block> = (0..4).map(|_| bcx.create_block()).collect();
+
+    let mut jt_data = JumpTableData::new();
+    for &target in &targets {
+        jt_data.push_entry(target);
+    }
+    let jt = bcx.create_jump_table(jt_data);
+
+    bcx.switch_to_block(start);
+    bcx.ins().br_table(index, default, jt);
+
+    bcx.switch_to_block(default);
+    let result = bcx.ins().iconst(types::I32, -1);
+    bcx.ins().return_(&[result]);
+
+    for (i, &target) in targets.iter().enumerate() {
+        bcx.switch_to_block(target);
+        let result = bcx.ins().iconst(types::I32, 10 * i as i64);
+        bcx.ins().return_(&[result]);
+    }
+    bcx.seal_all_blocks();
+}
+
+#[test]
+fn jump_tables_need_no_relocations() {
+    #[derive(Default)]
+    struct RecordingSink(Vec<RelocRecord>);
+
+    impl ModuleSink for RecordingSink {
+        fn reloc(&mut self, reloc: &RelocRecord) {
+            self.0.push(reloc.clone());
+        }
+    }
+
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    // FIXME set back to true once the x64 backend supports it.
+    flag_builder.set("is_pic", "false").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I32)],
+        returns: vec![AbiParam::new(types::I32)],
+        call_conv: CallConv::SystemV,
+    };
+
+    let func_id = module
+        .declare_function("switch", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+    build_switch(&mut ctx.func);
+
+    let mut sink = RecordingSink::default();
+    module
+        .define_function_with_sink(func_id, &mut ctx, &mut sink)
+        .unwrap();
+    // Jump table entries are offsets from the table, so they need no relocations.
+    assert!(sink.0.is_empty());
+    module.finalize_definitions();
+
+    let code = module.get_finalized_function(func_id);
+    let switch = unsafe { core::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(code) };
+    assert_eq!(switch(0), 0);
+    assert_eq!(switch(3), 30);
+    assert_eq!(switch(4), -1);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn absolute_jump_table_entries() {
+    use cranelift_codegen::{isa::BackendVariant, CodegenError};
+
+    #[derive(Default)]
+    struct RecordingSink(Vec<RelocRecord>);
+
+    impl ModuleSink for RecordingSink {
+        fn reloc(&mut self, reloc: &RelocRecord) {
+            self.0.push(reloc.clone());
+        }
+    }
+
+    for &variant in [BackendVariant::Legacy, BackendVariant::MachInst].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("use_colocated_libcalls", "false").unwrap();
+        flag_builder.set("is_pic", "false").unwrap();
+        flag_builder.set("jump_table_entries", "absolute").unwrap();
+        let isa_builder =
+            cranelift_native::builder_with_options(variant, true).unwrap_or_else(|msg| {
+                panic!("host machine is not supported: {}", msg);
+            });
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let sig = Signature {
+            params: vec![AbiParam::new(types::I32)],
+            returns: vec![AbiParam::new(types::I32)],
+            call_conv: CallConv::SystemV,
+        };
+
+        let func_id = module
+            .declare_function("switch", Linkage::Local, &sig)
+            .unwrap();
+        let name = ExternalName::user(0, func_id.as_u32());
+
+        let mut ctx = Context::new();
+        ctx.func = Function::with_name_signature(name.clone(), sig);
+        build_switch(&mut ctx.func);
+
+        let mut sink = RecordingSink::default();
+        let res = module.define_function_with_sink(func_id, &mut ctx, &mut sink);
+        if let BackendVariant::Legacy = variant {
+            // The legacy backend only emits relative entries.
+            match res {
+                Err(ModuleError::Compilation(CodegenError::Unsupported(_))) => {}
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+            continue;
+        }
+        res.unwrap();
+
+        // Every entry is an absolute address relocated against the function itself.
+        assert_eq!(sink.0.len(), 4);
+        for pair in sink.0.windows(2) {
+            assert_eq!(pair[1].offset, pair[0].offset + 8);
+        }
+        for reloc in &sink.0 {
+            assert_eq!(reloc.reloc, binemit::Reloc::Abs8);
+            assert_eq!(reloc.name, name);
+            assert!(reloc.addend > 0);
+        }
+        module.finalize_definitions();
+
+        let code = module.get_finalized_function(func_id);
+        let switch = unsafe { core::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(code) };
+        assert_eq!(switch(0), 0);
+        assert_eq!(switch(3), 30);
+        assert_eq!(switch(4), -1);
+    }
+}
+
+#[test]
+fn finalized_functions_from_other_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<JITModule>();
+    assert_send_sync::<FinalizedFunctions>();
+
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(settings::builder()));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+    let finalized = module.finalized_functions();
+
+    let sig = Signature {
+        params: vec![],
+        returns: vec![AbiParam::new(types::I32)],
+        call_conv: CallConv::SystemV,
+    };
+
+    let func_id = module
+        .declare_function("answer", Linkage::Local, &sig)
+        .unwrap();
+    assert!(finalized.get(func_id).is_none());
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let answer = bcx.ins().iconst(types::I32, 42);
+        bcx.ins().return_(&[answer]);
+    }
+
+    module
+        .define_function(
+            func_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap();
+    assert!(finalized.get(func_id).is_none());
+    module.finalize_definitions();
+
+    let code = std::thread::spawn(move || finalized.get(func_id).unwrap() as usize)
+        .join()
+        .unwrap();
+    assert_eq!(code, module.get_finalized_function(func_id) as usize);
+    let answer = unsafe { core::mem::transmute::<usize, extern "C" fn() -> i32>(code) };
+    assert_eq!(answer(), 42);
+}
+
+#[test]
+fn finalize_definitions_returns_batch() {
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(settings::builder()));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+    let pointer_type = module.target_config().pointer_type();
+
+    let data_id = module
+        .declare_data("answer_data", Linkage::Local, false, false)
+        .unwrap();
+    let mut data_ctx = DataContext::new();
+    data_ctx.define(42i32.to_ne_bytes().to_vec().into_boxed_slice());
+    module.define_data(data_id, &data_ctx).unwrap();
+
+    let sig = Signature {
+        params: vec![],
+        returns: vec![AbiParam::new(types::I32)],
+        call_conv: CallConv::SystemV,
+    };
+    let mut ctx = Context::new();
+    let mut func_ctx = FunctionBuilderContext::new();
+
+    let load_id = module
+        .declare_function("load_answer", Linkage::Local, &sig)
+        .unwrap();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, load_id.as_u32()), sig.clone());
+    {
+        let gv = module.declare_data_in_func(data_id, &mut ctx.func);
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let addr = bcx.ins().global_value(pointer_type, gv);
+        let answer = bcx.ins().load(types::I32, MemFlags::trusted(), addr, 0);
+        bcx.ins().return_(&[answer]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module
+        .define_function(
+            load_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap();
+    module.clear_context(&mut ctx);
+
+    let const_id = module
+        .declare_function("const_answer", Linkage::Local, &sig)
+        .unwrap();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, const_id.as_u32()), sig);
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let answer = bcx.ins().iconst(types::I32, 42);
+        bcx.ins().return_(&[answer]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module
+        .define_function(
+            const_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap();
+
+    let batch = module.finalize_definitions();
+    assert_eq!(batch.functions().count(), 2);
+    assert_eq!(
+        batch.data_object(data_id),
+        Some(module.get_finalized_data(data_id))
+    );
+
+    let load = batch.function(load_id).unwrap();
+    assert_eq!(load.ptr, module.get_finalized_function(load_id));
+    assert!(load.size > 0);
+    assert_eq!(load.data_refs, [data_id]);
+    let konst = batch.function(const_id).unwrap();
+    assert_eq!(konst.ptr, module.get_finalized_function(const_id));
+    assert!(konst.data_refs.is_empty());
+
+    let answers = std::thread::spawn(move || {
+        batch
+            .functions()
+            .map(|(_, func)| {
+                let func = unsafe { core::mem::transmute::<_, extern "C" fn() -> i32>(func.ptr) };
+                func()
+            })
+            .collect::<Vec<_>>()
+    })
+    .join()
+    .unwrap();
+    assert_eq!(answers, [42, 42]);
+
+    assert!(module.finalize_definitions().is_empty());
+}
+
+#[test]
+fn writable_data_outlives_module() {
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(settings::builder()));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+    let pointer_type = module.target_config().pointer_type();
+
+    let data_id = module
+        .declare_data("counter", Linkage::Local, true, false)
+        .unwrap();
+    let mut data_ctx = DataContext::new();
+    data_ctx.define(vec![0x2a; 8].into_boxed_slice());
+    module.define_data(data_id, &data_ctx).unwrap();
+
+    let sig = Signature {
+        params: vec![],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+    let func_id = module
+        .declare_function("load_counter", Linkage::Local, &sig)
+        .unwrap();
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let gv = module.declare_data_in_func(data_id, &mut ctx.func);
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let addr = bcx.ins().global_value(pointer_type, gv);
+        let value = bcx.ins().load(types::I64, MemFlags::trusted(), addr, 0);
+        bcx.ins().return_(&[value]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module
+        .define_function(
+            func_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap();
+    module.finalize_definitions();
+    let code = module.get_finalized_function(func_id);
+
+    // Dropping the module leaks all of its memory, including the writable
+    // data the finalized code still refers to.
+    drop(module);
+
+    let load = unsafe { core::mem::transmute::<_, extern "C" fn() -> u64>(code) };
+    assert_eq!(load(), 0x2a2a_2a2a_2a2a_2a2a);
+}
+
+#[test]
+fn compile_budget_skips_optional_passes() {
+    use cranelift_codegen::budget::{CompilePass, InstBudget};
+
+    let mut flag_builder = settings::builder();
+    flag_builder.set("opt_level", "speed").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I32)],
+        returns: vec![AbiParam::new(types::I32)],
+        call_conv: CallConv::SystemV,
+    };
+    let mut func = Function::with_name_signature(ExternalName::user(0, 0), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let x = bcx.block_params(block)[0];
+        let y = bcx.ins().iadd_imm(x, 1);
+        let z = bcx.ins().imul(y, x);
+        bcx.ins().return_(&[z]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+
+    let mut ctx = Context::for_function(func.clone());
+    let info = ctx.compile(&*isa).unwrap();
+    assert!(info.skipped_passes.is_empty());
+
+    // The budget is exhausted by the first pass, so all later optional passes are skipped.
+    let mut ctx = Context::for_function(func.clone());
+    ctx.set_budget(Box::new(InstBudget::new(0)));
+    let skipped = [
+        CompilePass::Narrow,
+        CompilePass::InductionVars,
+        CompilePass::Postopt,
+        CompilePass::Licm,
+        CompilePass::SimpleGvn,
+        CompilePass::SimplifyBranches,
+        CompilePass::Dce,
+    ];
+    let info = ctx.compile(&*isa).unwrap();
+    assert_eq!(info.skipped_passes.iter().collect::<Vec<_>>(), skipped);
+
+    // The budget is kept by `clear`.
+    ctx.clear();
+    ctx.func = func;
+    let info = ctx.compile(&*isa).unwrap();
+    assert_eq!(info.skipped_passes.iter().collect::<Vec<_>>(), skipped);
+}
+
+#[test]
+fn windows_fastcall_probes_large_frames() {
+    #[derive(Default)]
+    struct LibcallSink {
+        libcalls: Vec<LibCall>,
+    }
+
+    impl binemit::RelocSink for LibcallSink {
+        fn reloc_external(
+            &mut self,
+            _offset: binemit::CodeOffset,
+            _srcloc: SourceLoc,
+            _reloc: binemit::Reloc,
+            name: &ExternalName,
+            _addend: binemit::Addend,
+        ) {
+            if let ExternalName::LibCall(libcall) = name {
+                self.libcalls.push(*libcall);
+            }
+        }
+
+        fn reloc_constant(&mut self, _: binemit::CodeOffset, _: binemit::Reloc, _: u32) {}
+
+        fn reloc_jt(&mut self, _: binemit::CodeOffset, _: binemit::Reloc, _: JumpTable) {}
+    }
+
+    let mut flag_builder = settings::builder();
+    flag_builder.set("enable_probestack", "false").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+
+    let probes = |call_conv: CallConv, frame_size: u32| {
+        let sig = Signature::new(call_conv);
+        let mut ctx =
+            Context::for_function(Function::with_name_signature(ExternalName::user(0, 0), sig));
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let slot =
+                bcx.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, frame_size));
+            let block = bcx.create_block();
+            bcx.switch_to_block(block);
+            let zero = bcx.ins().iconst(types::I64, 0);
+            bcx.ins().stack_store(zero, slot, 0);
+            bcx.ins().return_(&[]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+
+        let mut code = vec![];
+        let mut sink = LibcallSink::default();
+        ctx.compile_and_emit(
+            &*isa,
+            &mut code,
+            &mut sink,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap();
+        sink.libcalls.contains(&LibCall::Probestack)
+    };
+
+    assert!(probes(CallConv::WindowsFastcall, 8192));
+    assert!(!probes(CallConv::WindowsFastcall, 64));
+    assert!(!probes(CallConv::SystemV, 8192));
+}
+
+#[test]
+fn read_cycle_counter() {
+    let mut flag_builder = settings::builder();
+    flag_builder.enable("enable_cycle_counter").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let sig = Signature {
+        params: vec![],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+    let func_id = module
+        .declare_function("cycles", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let cycles = bcx.ins().get_cycle_counter();
+        bcx.ins().return_(&[cycles]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+
+    module
+        .define_function(
+            func_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap();
+    module.finalize_definitions();
+
+    let code = module.get_finalized_function(func_id);
+    let cycles = unsafe { core::mem::transmute::<_, extern "C" fn() -> i64>(code) };
+    let first = cycles() as u64;
+    let second = cycles() as u64;
+    assert!(first != 0);
+    assert!(second >= first);
+}
+
+#[test]
+fn stack_used() {
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(settings::builder()));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let sig = Signature {
+        params: vec![],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+    let func_id = module
+        .declare_function("stack_used", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let slot = bcx.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 100));
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let zero = bcx.ins().iconst(types::I64, 0);
+        bcx.ins().stack_store(zero, slot, 0);
+        let used = bcx.ins().stack_used(types::I64);
+        bcx.ins().return_(&[used]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+
+    module
+        .define_function(
+            func_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap();
+    let frame_size = ctx
+        .mach_compile_result
+        .as_ref()
+        .unwrap()
+        .code_info()
+        .frame_size;
+    assert!(frame_size >= 100);
+    module.finalize_definitions();
+
+    // The return address and the frame pointer of the caller sit on top of the frame.
+    let code = module.get_finalized_function(func_id);
+    let stack_used = unsafe { core::mem::transmute::<_, extern "C" fn() -> i64>(code) };
+    assert_eq!(stack_used(), i64::from(frame_size) + 16);
+}
+
+#[test]
+fn code_stats() {
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(settings::builder()));
+
+    // Sum 40 values loaded from `p`, in the reverse order of the loads, so that they are all live
+    // at once and some of them have to be spilled.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64)],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, 0), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let p = bcx.block_params(block)[0];
+        let values: Vec<Value> = (0..40)
+            .map(|i| bcx.ins().load(types::I64, MemFlags::trusted(), p, i * 8))
+            .collect();
+        let sum = values
+            .into_iter()
+            .rev()
+            .fold(p, |sum, value| bcx.ins().iadd(sum, value));
+        bcx.ins().return_(&[sum]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+
+    let stats = ctx.compile(&*isa).unwrap().stats;
+    assert!(stats.spills > 0);
+    assert!(stats.reloads > 0);
+    assert!(stats.insts >= 80 + stats.spills + stats.reloads + stats.moves);
+}
+
+#[test]
+fn count_down_loops() {
+    use condcodes::IntCC;
+
+    let mut flag_builder = settings::builder();
+    flag_builder.set("opt_level", "speed").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    // Counts the iterations of a loop stepping `i` by one from `init` as long as `i cond n`, with
+    // the test at the top of the loop or, if `test_next`, after incrementing `i`. The counter is
+    // only used by the test, so it can be turned into a count-down.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I32), AbiParam::new(types::I32)],
+        returns: vec![AbiParam::new(types::I32)],
+        call_conv: CallConv::SystemV,
+    };
+    let mut define = |name: &str, cond: IntCC, test_next: bool| {
+        let func_id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+        let mut ctx = Context::new();
+        ctx.func =
+            Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig.clone());
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let entry = bcx.create_block();
+            let header = bcx.create_block();
+            let body = bcx.create_block();
+            let exit = bcx.create_block();
+            bcx.append_block_params_for_function_params(entry);
+            let i = bcx.append_block_param(header, types::I32);
+            let count = bcx.append_block_param(header, types::I32);
+            let result = bcx.append_block_param(exit, types::I32);
+
+            bcx.switch_to_block(entry);
+            let init = bcx.block_params(entry)[0];
+            let n = bcx.block_params(entry)[1];
+            let zero = bcx.ins().iconst(types::I32, 0);
+            bcx.ins().jump(header, &[init, zero]);
+
+            bcx.switch_to_block(header);
+            let count_next = bcx.ins().iadd_imm(count, 1);
+            let i_next = bcx.ins().iadd_imm(i, 1);
+            if test_next {
+                bcx.ins().jump(body, &[]);
+            } else {
+                let cmp = bcx.ins().icmp(cond, i, n);
+                bcx.ins().brz(cmp, exit, &[count]);
+                bcx.ins().jump(body, &[]);
+            }
+
+            bcx.switch_to_block(body);
+            if test_next {
+                let cmp = bcx.ins().icmp(cond, i_next, n);
+                bcx.ins().brnz(cmp, header, &[i_next, count_next]);
+                bcx.ins().jump(exit, &[count_next]);
+            } else {
+                bcx.ins().jump(header, &[i_next, count_next]);
+            }
+
+            bcx.switch_to_block(exit);
+            bcx.ins().return_(&[result]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module
+            .define_function(
+                func_id,
+                &mut ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap();
+        func_id
+    };
+
+    let loops: Vec<_> = [
+        ("slt", IntCC::SignedLessThan, false),
+        ("ult", IntCC::UnsignedLessThan, false),
+        ("ne", IntCC::NotEqual, false),
+        ("slt_next", IntCC::SignedLessThan, true),
+        ("ne_next", IntCC::NotEqual, true),
+    ]
+    .iter()
+    .map(|&(name, cond, test_next)| (define(name, cond, test_next), cond, test_next))
+    .collect();
+    module.finalize_definitions();
+
+    for &(func_id, cond, test_next) in loops.iter() {
+        let code = module.get_finalized_function(func_id);
+        let count = unsafe { core::mem::transmute::<_, extern "C" fn(i32, i32) -> i32>(code) };
+        for &(init, n) in [(0, 10), (3, 4), (5, 5), (7, 2), (-3, 2), (2, -3)].iter() {
+            let holds = |i: i32| match cond {
+                IntCC::SignedLessThan => i < n,
+                IntCC::UnsignedLessThan => (i as u32) < n as u32,
+                _ => i != n,
+            };
+            // Skip the loops that would wrap around.
+            let first = if test_next { init + 1 } else { init };
+            if (cond == IntCC::NotEqual && first > n) || (cond == IntCC::UnsignedLessThan && n < 0)
+            {
+                continue;
+            }
+            let mut expected = 0;
+            let mut i = init;
+            if test_next {
+                expected += 1;
+                i += 1;
+            }
+            while holds(i) {
+                expected += 1;
+                i += 1;
+            }
+            assert_eq!(count(init, n), expected, "{} {} {}", cond, init, n);
+        }
+    }
+}
+
+#[test]
+fn return_shapes() {
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I32)],
+        returns: vec![AbiParam::new(types::I32)],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &shape in ["unchanged", "single_exit", "direct_returns"].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("return_shape", shape).unwrap();
+        flag_builder.set("enable_verifier", "true").unwrap();
+        let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+            panic!("host machine is not supported: {}", msg);
+        });
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        // Returns `x + 1` if `x` is zero and `2 * x` otherwise, from two returns or from a
+        // single exit block.
+        let mut define = |name: &str, single_exit: bool| {
+            let func_id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+            let mut ctx = Context::new();
+            ctx.func =
+                Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig.clone());
+            let mut func_ctx = FunctionBuilderContext::new();
+            {
+                let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                let entry = bcx.create_block();
+                let zero = bcx.create_block();
+                let nonzero = bcx.create_block();
+                let exit = bcx.create_block();
+                bcx.append_block_params_for_function_params(entry);
+
+                bcx.switch_to_block(entry);
+                let x = bcx.block_params(entry)[0];
+                bcx.ins().brz(x, zero, &[]);
+                bcx.ins().jump(nonzero, &[]);
+
+                bcx.switch_to_block(zero);
+                let one = bcx.ins().iadd_imm(x, 1);
+                if single_exit {
+                    bcx.ins().jump(exit, &[one]);
+                } else {
+                    bcx.ins().return_(&[one]);
+                }
+
+                bcx.switch_to_block(nonzero);
+                let twice = bcx.ins().iadd(x, x);
+                if single_exit {
+                    bcx.ins().jump(exit, &[twice]);
+                } else {
+                    bcx.ins().return_(&[twice]);
+                }
+
+                if single_exit {
+                    bcx.switch_to_block(exit);
+                    let result = bcx.append_block_param(exit, types::I32);
+                    bcx.ins().return_(&[result]);
+                }
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            module
+                .define_function(
+                    func_id,
+                    &mut ctx,
+                    &mut NullTrapSink {},
+                    &mut NullStackMapSink {},
+                )
+                .unwrap();
+            func_id
+        };
+
+        let funcs = [define("two_returns", false), define("single_exit", true)];
+        module.finalize_definitions();
+
+        for &func_id in funcs.iter() {
+            let code = module.get_finalized_function(func_id);
+            let f = unsafe { core::mem::transmute::<_, extern "C" fn(i32) -> i32>(code) };
+            assert_eq!(f(0), 1, "{}", shape);
+            assert_eq!(f(3), 6, "{}", shape);
+            assert_eq!(f(-5), -10, "{}", shape);
+        }
+    }
+}
+
+#[test]
+fn single_threaded_atomics() {
+    let mut flag_builder = settings::builder();
+    flag_builder.enable("single_threaded").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    // Adds `x` to `*p` and returns the old value, or 0 if the sum doesn't exceed 10.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64), AbiParam::new(types::I32)],
+        returns: vec![AbiParam::new(types::I32)],
+        call_conv: CallConv::SystemV,
+    };
+    let func_id = module
+        .declare_function("fetch_add", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let p = bcx.block_params(block)[0];
+        let x = bcx.block_params(block)[1];
+        let flags = MemFlags::trusted();
+        let old = bcx
+            .ins()
+            .atomic_rmw(types::I32, flags, AtomicRmwOp::Add, p, x);
+        bcx.ins().fence(MemoryOrdering::AcqRel);
+        let sum = bcx.ins().atomic_load(types::I32, flags, p);
+        let ten = bcx.ins().iconst(types::I32, 10);
+        let small = bcx
+            .ins()
+            .icmp(condcodes::IntCC::SignedLessThanOrEqual, sum, ten);
+        let zero = bcx.ins().iconst(types::I32, 0);
+        let result = bcx.ins().select(small, zero, old);
+        bcx.ins().return_(&[result]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+
+    module
+        .define_function(
+            func_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap();
+    module.finalize_definitions();
+
+    let code = module.get_finalized_function(func_id);
+    let fetch_add = unsafe { core::mem::transmute::<_, extern "C" fn(*mut i32, i32) -> i32>(code) };
+    let mut counter = 4;
+    assert_eq!(fetch_add(&mut counter, 5), 0);
+    assert_eq!(counter, 9);
+    assert_eq!(fetch_add(&mut counter, 5), 9);
+    assert_eq!(counter, 14);
+}
+
+#[cfg(all(feature = "std", target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn pkey_trampoline() {
+    let key = match ProtectionKey::new() {
+        Some(key) => key,
+        // The CPU or the kernel doesn't support protection keys.
+        None => return,
+    };
+
+    let mut flag_builder = settings::builder();
+    flag_builder.enable("enable_protection_keys").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let mut define = |name: &str, sig: Signature, body: &dyn Fn(&mut FunctionBuilder, &[Value])| {
+        let func_id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+        let mut ctx = Context::new();
+        ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let params = bcx.block_params(block).to_vec();
+            body(&mut bcx, &params);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module
+            .define_function(
+                func_id,
+                &mut ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap();
+        func_id
+    };
+
+    // Returns the protection key rights it runs with.
+    let rights_id = define(
+        "rights",
+        Signature {
+            params: vec![],
+            returns: vec![AbiParam::new(types::I64)],
+            call_conv: CallConv::SystemV,
+        },
+        &|bcx, _| {
+            let rights = bcx.ins().get_pkey_rights();
+            bcx.ins().return_(&[rights]);
+        },
+    );
+    // Loads an i32 from a heap.
+    let load_id = define(
+        "load",
+        Signature {
+            params: vec![AbiParam::new(types::I64)],
+            returns: vec![AbiParam::new(types::I32)],
+            call_conv: CallConv::SystemV,
+        },
+        &|bcx, params| {
+            let x = bcx
+                .ins()
+                .load(types::I32, MemFlags::trusted(), params[0], 0);
+            bcx.ins().return_(&[x]);
+        },
+    );
+
+    let allowed = ProtectionKey::rights_allowing(&[&key]);
+    let sandboxed_rights_id = module.define_pkey_trampoline(rights_id, allowed).unwrap();
+    let sandboxed_load_id = module.define_pkey_trampoline(load_id, allowed).unwrap();
+    module.finalize_definitions();
+
+    let rights = unsafe {
+        core::mem::transmute::<*const u8, extern "C" fn() -> u64>(
+            module.get_finalized_function(rights_id),
+        )
+    };
+    let sandboxed_rights = unsafe {
+        core::mem::transmute::<*const u8, extern "C" fn() -> u64>(
+            module.get_finalized_function(sandboxed_rights_id),
+        )
+    };
+    let sandboxed_load = unsafe {
+        core::mem::transmute::<*const u8, extern "C" fn(*const i32) -> i32>(
+            module.get_finalized_function(sandboxed_load_id),
+        )
+    };
+
+    // The key and the default key are accessible within the trampolines, and no other key is.
+    let other_key = ProtectionKey::new().unwrap();
+    assert_eq!(allowed & 0b11, 0);
+    assert_eq!((allowed >> (2 * key.key())) & 0b11, 0);
+    assert_eq!((allowed >> (2 * other_key.key())) & 0b11, 0b11);
+
+    // The page of the heap is leaked, as it keeps the key's tag.
+    let layout = std::alloc::Layout::from_size_align(4096, 4096).unwrap();
+    let heap = unsafe { std::alloc::alloc_zeroed(layout) };
+    unsafe {
+        (heap as *mut i32).write(42);
+        key.protect(heap, 4096).unwrap();
+    }
+
+    let host_rights = rights();
+    assert_eq!(sandboxed_rights(), allowed);
+    assert_eq!(rights(), host_rights);
+    assert_eq!(sandboxed_load(heap as *const i32), 42);
+    assert_eq!(rights(), host_rights);
+}
+
+#[test]
+fn function_hashes() {
+    let hash_caller = |padding: bool, value: i64| {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("use_colocated_libcalls", "false").unwrap();
+        flag_builder.set("is_pic", "false").unwrap();
+        let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+            panic!("host machine is not supported: {}", msg);
+        });
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let sig = Signature {
+            params: vec![],
+            returns: vec![AbiParam::new(types::I64)],
+            call_conv: CallConv::SystemV,
+        };
+        let callee_id = module
+            .declare_function("callee", Linkage::Local, &sig)
+            .unwrap();
+        let caller_id = module
+            .declare_function("caller", Linkage::Local, &sig)
+            .unwrap();
+
+        let mut ctx = Context::new();
+        let mut func_ctx = FunctionBuilderContext::new();
+        for &func_id in &[callee_id, caller_id] {
+            ctx.func =
+                Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig.clone());
+            {
+                let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                let block = bcx.create_block();
+                bcx.switch_to_block(block);
+                let result = if func_id == callee_id {
+                    bcx.ins().iconst(types::I64, value)
+                } else {
+                    let callee = module.declare_func_in_func(callee_id, bcx.func);
+                    let call = bcx.ins().call(callee, &[]);
+                    bcx.inst_results(call)[0]
+                };
+                bcx.ins().return_(&[result]);
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            module
+                .define_function(
+                    func_id,
+                    &mut ctx,
+                    &mut NullTrapSink {},
+                    &mut NullStackMapSink {},
+                )
+                .unwrap();
+            ctx.clear();
+
+            // Moves the caller away from the callee.
+            if padding && func_id == callee_id {
+                define_simple_function(&mut module);
+            }
+        }
+        module.finalize_definitions();
+
+        assert!(module.verify_function_hash(callee_id));
+        assert!(module.verify_function_hash(caller_id));
+        (
+            module.function_hash(callee_id),
+            module.function_hash(caller_id),
+        )
+    };
+
+    let (callee, caller) = hash_caller(false, 42);
+    let (moved_callee, moved_caller) = hash_caller(true, 42);
+    let (other_callee, other_caller) = hash_caller(false, 43);
+
+    // The callee has no relocations, so both of its hashes stay the same wherever it is.
+    assert_eq!(callee, moved_callee);
+    // The caller refers to the callee by its offset, so only the normalized hash stays the same.
+    assert_ne!(caller.code, moved_caller.code);
+    assert_eq!(caller.normalized, moved_caller.normalized);
+
+    assert_ne!(callee.normalized, other_callee.normalized);
+    assert_eq!(caller.normalized, other_caller.normalized);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn i128_arithmetic() {
+    use condcodes::IntCC;
+    use cranelift_codegen::isa::BackendVariant;
+
+    // The runtime library routines for 128-bit division.
+    extern "C" fn udivti3(x: u128, y: u128) -> u128 {
+        x / y
+    }
+    extern "C" fn divti3(x: i128, y: i128) -> i128 {
+        x / y
+    }
+    extern "C" fn umodti3(x: u128, y: u128) -> u128 {
+        x % y
+    }
+    extern "C" fn modti3(x: i128, y: i128) -> i128 {
+        x % y
+    }
+
+    type Op = fn(&mut FunctionBuilder, Value, Value) -> Value;
+    type Check = fn(u128, u128) -> u128;
+    let ops: [(&str, Op, Check); 18] = [
+        (
+            "iadd",
+            |b, x, y| b.ins().iadd(x, y),
+            |x, y| x.wrapping_add(y),
+        ),
+        (
+            "isub",
+            |b, x, y| b.ins().isub(x, y),
+            |x, y| x.wrapping_sub(y),
+        ),
+        (
+            "imul",
+            |b, x, y| b.ins().imul(x, y),
+            |x, y| x.wrapping_mul(y),
+        ),
+        ("udiv", |b, x, y| b.ins().udiv(x, y), |x, y| x / y),
+        (
+            "sdiv",
+            |b, x, y| b.ins().sdiv(x, y),
+            |x, y| (x as i128).wrapping_div(y as i128) as u128,
+        ),
+        ("urem", |b, x, y| b.ins().urem(x, y), |x, y| x % y),
+        (
+            "srem",
+            |b, x, y| b.ins().srem(x, y),
+            |x, y| (x as i128).wrapping_rem(y as i128) as u128,
+        ),
+        ("ishl", |b, x, y| b.ins().ishl(x, y), |x, y| x << (y % 128)),
+        ("ushr", |b, x, y| b.ins().ushr(x, y), |x, y| x >> (y % 128)),
+        (
+            "sshr",
+            |b, x, y| b.ins().sshr(x, y),
+            |x, y| ((x as i128) >> (y % 128)) as u128,
+        ),
+        (
+            "rotl",
+            |b, x, y| b.ins().rotl(x, y),
+            |x, y| x.rotate_left((y % 128) as u32),
+        ),
+        (
+            "rotr",
+            |b, x, y| b.ins().rotr(x, y),
+            |x, y| x.rotate_right((y % 128) as u32),
+        ),
+        ("ineg", |b, x, _| b.ins().ineg(x), |x, _| x.wrapping_neg()),
+        (
+            "clz",
+            |b, x, _| b.ins().clz(x),
+            |x, _| x.leading_zeros().into(),
+        ),
+        (
+            "ctz",
+            |b, x, _| b.ins().ctz(x),
+            |x, _| x.trailing_zeros().into(),
+        ),
+        (
+            "ushr_imm",
+            |b, x, _| b.ins().ushr_imm(x, 65),
+            |x, _| x >> 65,
+        ),
+        (
+            "ult",
+            |b, x, y| {
+                let c = b.ins().icmp(IntCC::UnsignedLessThan, x, y);
+                b.ins().bint(types::I128, c)
+            },
+            |x, y| (x < y).into(),
+        ),
+        (
+            "sge",
+            |b, x, y| {
+                let c = b.ins().icmp(IntCC::SignedGreaterThanOrEqual, x, y);
+                b.ins().bint(types::I128, c)
+            },
+            |x, y| (x as i128 >= y as i128).into(),
+        ),
+    ];
+    let values = [
+        0,
+        1,
+        3,
+        63,
+        64,
+        65,
+        127,
+        131,
+        u64::MAX as u128,
+        1 << 64,
+        0x0123_4567_89ab_cdef_fedc_ba98_7654_3210,
+        i128::MAX as u128,
+        i128::MIN as u128,
+        u128::MAX - 6,
+    ];
+
+    // Each function loads its operands from the first two pointers and stores its result to the
+    // third.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64); 3],
+        returns: vec![],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &variant in [BackendVariant::Legacy, BackendVariant::MachInst].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        flag_builder.enable("enable_llvm_abi_extensions").unwrap();
+        let isa_builder = cranelift_native::builder_with_options(variant, true)
+            .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("__udivti3", udivti3 as *const u8);
+        builder.symbol("__divti3", divti3 as *const u8);
+        builder.symbol("__umodti3", umodti3 as *const u8);
+        builder.symbol("__modti3", modti3 as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let mut funcs = Vec::new();
+        for &(name, op, _) in ops.iter() {
+            let func_id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+            let mut ctx = Context::new();
+            ctx.func =
+                Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig.clone());
+            let mut func_ctx = FunctionBuilderContext::new();
+            {
+                let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                let block = bcx.create_block();
+                bcx.append_block_params_for_function_params(block);
+                bcx.switch_to_block(block);
+                let params = bcx.block_params(block).to_vec();
+                let flags = MemFlags::trusted();
+                let x = bcx.ins().load(types::I128, flags, params[0], 0);
+                let y = bcx.ins().load(types::I128, flags, params[1], 0);
+                let result = op(&mut bcx, x, y);
+                bcx.ins().store(flags, result, params[2], 0);
+                bcx.ins().return_(&[]);
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            module
+                .define_function(
+                    func_id,
+                    &mut ctx,
+                    &mut NullTrapSink {},
+                    &mut NullStackMapSink {},
+                )
+                .unwrap_or_else(|err| panic!("{:?} {}: {:?}", variant, name, err));
+            funcs.push(func_id);
+        }
+        module.finalize_definitions();
+
+        for (&(name, _, check), &func_id) in ops.iter().zip(funcs.iter()) {
+            let code = module.get_finalized_function(func_id);
+            let f = unsafe {
+                core::mem::transmute::<_, extern "C" fn(*const u128, *const u128, *mut u128)>(code)
+            };
+            for &x in values.iter() {
+                for &y in values.iter() {
+                    let divides = matches!(name, "udiv" | "sdiv" | "urem" | "srem");
+                    if divides
+                        && (y == 0 || (name == "sdiv" && x == i128::MIN as u128 && y == u128::MAX))
+                    {
+                        continue;
+                    }
+                    let mut result = 0;
+                    f(&x, &y, &mut result);
+                    assert_eq!(
+                        result,
+                        check(x, y),
+                        "{:?} {}({:#x}, {:#x})",
+                        variant,
+                        name,
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn f16_conversions() {
+    use cranelift_codegen::isa::BackendVariant;
+
+    // The runtime library routines for `f16` conversions, which only need to handle the normal
+    // numbers, zeros and infinities used below. The `f16` values are passed in the low 16 bits of
+    // a floating point register.
+    fn extend(h: u16) -> f32 {
+        let sign = u32::from(h >> 15) << 31;
+        let exp = u32::from(h >> 10) & 0x1f;
+        let mantissa = u32::from(h) & 0x3ff;
+        let bits = match exp {
+            0 => sign,
+            0x1f => sign | 0x7f80_0000 | mantissa << 13,
+            _ => sign | (exp + 127 - 15) << 23 | mantissa << 13,
+        };
+        f32::from_bits(bits)
+    }
+    fn truncate(x: f32) -> u16 {
+        let bits = x.to_bits();
+        let sign = (bits >> 16) as u16 & 0x8000;
+        let exp = (bits >> 23) & 0xff;
+        let half_exp = match exp {
+            0 => 0,
+            0xff => 0x1f,
+            _ => exp + 15 - 127,
+        };
+        sign | (half_exp << 10) as u16 | ((bits >> 13) & 0x3ff) as u16
+    }
+    extern "C" fn extendhfsf2(x: f32) -> f32 {
+        extend(x.to_bits() as u16)
+    }
+    extern "C" fn truncsfhf2(x: f32) -> f32 {
+        f32::from_bits(truncate(x).into())
+    }
+    extern "C" fn truncdfhf2(x: f64) -> f32 {
+        f32::from_bits(truncate(x as f32).into())
+    }
+
+    type Op = fn(&mut FunctionBuilder, Value) -> Value;
+    let ops: [(&str, Type, Op); 5] = [
+        ("promote32", types::F16, |b, x| {
+            b.ins().fpromote(types::F32, x)
+        }),
+        ("promote64", types::F16, |b, x| {
+            b.ins().fpromote(types::F64, x)
+        }),
+        ("demote32", types::F32, |b, x| {
+            b.ins().fdemote(types::F16, x)
+        }),
+        ("demote64", types::F64, |b, x| {
+            b.ins().fdemote(types::F16, x)
+        }),
+        ("bitcast", types::I16, |b, x| {
+            let x = b.ins().bitcast(types::F16, x);
+            let x = b.ins().fpromote(types::F32, x);
+            let x = b.ins().fdemote(types::F16, x);
+            b.ins().bitcast(types::I16, x)
+        }),
+    ];
+    // Values which are exactly representable as `f16`.
+    let values: [u16; 10] = [
+        0x0000, 0x8000, 0x3c00, 0xc100, 0x3800, 0x7bff, 0xfbff, 0x0400, 0x7c00, 0xfc00,
+    ];
+
+    // Each function loads its operand from the first pointer and stores its result to the second.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64); 2],
+        returns: vec![],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &f16c in [true, false].iter() {
+        if f16c && !(is_x86_feature_detected!("avx") && is_x86_feature_detected!("f16c")) {
+            continue;
+        }
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        let mut isa_builder =
+            cranelift_native::builder_with_options(BackendVariant::MachInst, true)
+                .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        if f16c {
+            isa_builder.enable("has_avx").unwrap();
+            isa_builder.enable("has_f16c").unwrap();
+        }
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("__extendhfsf2", extendhfsf2 as *const u8);
+        builder.symbol("__truncsfhf2", truncsfhf2 as *const u8);
+        builder.symbol("__truncdfhf2", truncdfhf2 as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let mut funcs = Vec::new();
+        for &(name, ty, op) in ops.iter() {
+            let func_id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+            let mut ctx = Context::new();
+            ctx.func =
+                Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig.clone());
+            let mut func_ctx = FunctionBuilderContext::new();
+            {
+                let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                let block = bcx.create_block();
+                bcx.append_block_params_for_function_params(block);
+                bcx.switch_to_block(block);
+                let params = bcx.block_params(block).to_vec();
+                let flags = MemFlags::trusted();
+                let x = bcx.ins().load(ty, flags, params[0], 0);
+                let result = op(&mut bcx, x);
+                bcx.ins().store(flags, result, params[1], 0);
+                bcx.ins().return_(&[]);
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            module
+                .define_function(
+                    func_id,
+                    &mut ctx,
+                    &mut NullTrapSink {},
+                    &mut NullStackMapSink {},
+                )
+                .unwrap_or_else(|err| panic!("f16c={} {}: {:?}", f16c, name, err));
+            funcs.push(func_id);
+        }
+        module.finalize_definitions();
+
+        for (&(name, _, _), &func_id) in ops.iter().zip(funcs.iter()) {
+            let code = module.get_finalized_function(func_id);
+            let f = unsafe { core::mem::transmute::<_, extern "C" fn(*const u64, *mut u64)>(code) };
+            for &h in values.iter() {
+                let (input, expected) = match name {
+                    "promote32" => (u64::from(h), u64::from(extend(h).to_bits())),
+                    "promote64" => (u64::from(h), f64::from(extend(h)).to_bits()),
+                    "demote32" => (u64::from(extend(h).to_bits()), u64::from(h)),
+                    "demote64" => (f64::from(extend(h)).to_bits(), u64::from(h)),
+                    _ => (u64::from(h), u64::from(h)),
+                };
+                // The bits above the stored result must be left alone.
+                let upper = match name {
+                    "promote32" => !0 << 32,
+                    "promote64" => 0,
+                    _ => !0 << 16,
+                };
+                let mut result = upper;
+                f(&input, &mut result);
+                assert_eq!(
+                    result,
+                    upper | expected,
+                    "f16c={} {}({:#x})",
+                    f16c,
+                    name,
+                    input
+                );
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn dynamic_vectors() {
+    use cranelift_codegen::isa::BackendVariant;
+
+    // Computes `c = a + b + splat(1)` on `i32x4xN` and `z = x + splat(0.5)` on `f32x4xN`, and
+    // returns the `vscale` the dynamic vectors were lowered with.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64); 5],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+
+    if !is_x86_feature_detected!("sse4.1") {
+        return;
+    }
+    for &opt_level in ["none", "speed"].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        flag_builder.set("opt_level", opt_level).unwrap();
+        let mut isa_builder =
+            cranelift_native::builder_with_options(BackendVariant::MachInst, true)
+                .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        // The `i32` splat is lowered with `pinsrd`.
+        isa_builder.enable("has_sse3").unwrap();
+        isa_builder.enable("has_ssse3").unwrap();
+        isa_builder.enable("has_sse41").unwrap();
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let vscale = isa.vscale().unwrap();
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let func_id = module
+            .declare_function("dynamic", Linkage::Local, &sig)
+            .unwrap();
+        let mut ctx = Context::new();
+        ctx.func =
+            Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig.clone());
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let vscale_gv = bcx.create_global_value(GlobalValueData::VScale {
+                global_type: types::I64,
+            });
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let params = bcx.block_params(block).to_vec();
+            let flags = MemFlags::trusted();
+
+            let a = bcx.ins().load(types::I32X4XN, flags, params[0], 0);
+            let b = bcx.ins().load(types::I32X4XN, flags, params[1], 0);
+            let one = bcx.ins().iconst(types::I32, 1);
+            let ones = bcx.ins().splat(types::I32X4XN, one);
+            let sum = bcx.ins().iadd(a, b);
+            let sum = bcx.ins().iadd(sum, ones);
+            bcx.ins().store(flags, sum, params[2], 0);
+
+            let x = bcx.ins().load(types::F32X4XN, flags, params[3], 0);
+            let half = bcx.ins().f32const(0.5);
+            let halves = bcx.ins().splat(types::F32X4XN, half);
+            let z = bcx.ins().fadd(x, halves);
+            bcx.ins().store(flags, z, params[4], 0);
+
+            let vscale = bcx.ins().global_value(types::I64, vscale_gv);
+            bcx.ins().return_(&[vscale]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module
+            .define_function(
+                func_id,
+                &mut ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap_or_else(|err| panic!("opt_level={}: {:?}", opt_level, err));
+        module.finalize_definitions();
+
+        let code = module.get_finalized_function(func_id);
+        let f = unsafe {
+            core::mem::transmute::<
+                _,
+                extern "C" fn(*const i32, *const i32, *mut i32, *const f32, *mut f32) -> i64,
+            >(code)
+        };
+        let lanes = 4 * usize::from(vscale);
+        let a: Vec<i32> = (0..lanes as i32).collect();
+        let b: Vec<i32> = (0..lanes as i32).map(|i| 100 * i).collect();
+        let x: Vec<f32> = (0..lanes).map(|i| i as f32).collect();
+        let mut c = vec![0; lanes];
+        let mut z = vec![0.0; lanes];
+        assert_eq!(
+            f(
+                a.as_ptr(),
+                b.as_ptr(),
+                c.as_mut_ptr(),
+                x.as_ptr(),
+                z.as_mut_ptr()
+            ),
+            i64::from(vscale)
+        );
+        for i in 0..lanes {
+            assert_eq!(c[i], a[i] + b[i] + 1, "opt_level={}", opt_level);
+            assert_eq!(z[i], x[i] + 0.5, "opt_level={}", opt_level);
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn tail_calls() {
+    use cranelift_codegen::{isa::BackendVariant, CodegenError};
+
+    // `even(n)` and `odd(n)` tail call each other until `n` reaches zero, far deeper than the
+    // stack would allow for ordinary calls. `even` calls `odd` directly, and `odd` calls `even`
+    // through a function pointer after keeping `n` live across a call to `id`.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64)],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &opt_level in ["none", "speed"].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        flag_builder.set("opt_level", opt_level).unwrap();
+        let isa_builder = cranelift_native::builder_with_options(BackendVariant::MachInst, true)
+            .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let even_id = module
+            .declare_function("even", Linkage::Local, &sig)
+            .unwrap();
+        // Not final, so that the direct tail call needs the address of `odd` in a register.
+        let odd_id = module
+            .declare_function("odd", Linkage::Preemptible, &sig)
+            .unwrap();
+        let id_id = module.declare_function("id", Linkage::Local, &sig).unwrap();
+
+        let mut ctx = module.make_context();
+        let mut func_ctx = FunctionBuilderContext::new();
+
+        // id(n) = n
+        ctx.func.signature = sig.clone();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let n = bcx.block_params(block)[0];
+            bcx.ins().return_(&[n]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module
+            .define_function(
+                id_id,
+                &mut ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap();
+        module.clear_context(&mut ctx);
+
+        // Build `n == 0 ? base : tail(n - 1)`.
+        fn define(
+            module: &mut JITModule,
+            ctx: &mut Context,
+            func_ctx: &mut FunctionBuilderContext,
+            sig: &Signature,
+            func_id: FuncId,
+            base: i64,
+            tail: &dyn Fn(&mut JITModule, &mut FunctionBuilder, Value),
+        ) {
+            ctx.func.signature = sig.clone();
+            {
+                let mut bcx = FunctionBuilder::new(&mut ctx.func, func_ctx);
+                let block = bcx.create_block();
+                let done = bcx.create_block();
+                let recurse = bcx.create_block();
+                bcx.append_block_params_for_function_params(block);
+                bcx.switch_to_block(block);
+                let n = bcx.block_params(block)[0];
+                bcx.ins().brz(n, done, &[]);
+                bcx.ins().jump(recurse, &[]);
+                bcx.switch_to_block(done);
+                let base = bcx.ins().iconst(types::I64, base);
+                bcx.ins().return_(&[base]);
+                bcx.switch_to_block(recurse);
+                let n = bcx.ins().iadd_imm(n, -1);
+                tail(module, &mut bcx, n);
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            module
+                .define_function(func_id, ctx, &mut NullTrapSink {}, &mut NullStackMapSink {})
+                .unwrap_or_else(|err| panic!("{:?}", err));
+            module.clear_context(ctx);
+        }
+
+        define(
+            &mut module,
+            &mut ctx,
+            &mut func_ctx,
+            &sig,
+            even_id,
+            1,
+            &|module, bcx, n| {
+                let odd = module.declare_func_in_func(odd_id, bcx.func);
+                bcx.ins().return_call(odd, &[n]);
+            },
+        );
+        define(
+            &mut module,
+            &mut ctx,
+            &mut func_ctx,
+            &sig,
+            odd_id,
+            0,
+            &|module, bcx, n| {
+                let id = module.declare_func_in_func(id_id, bcx.func);
+                let call = bcx.ins().call(id, &[n]);
+                let copy = bcx.inst_results(call)[0];
+                // `n` is live across the call, so it is kept in a callee-saved register, which
+                // has to be restored before the tail call.
+                let n = bcx.ins().iadd(n, copy);
+                let n = bcx.ins().ushr_imm(n, 1);
+                let even = module.declare_func_in_func(even_id, bcx.func);
+                let ptr = bcx.ins().func_addr(types::I64, even);
+                let sig_ref = bcx.import_signature(sig.clone());
+                bcx.ins().return_call_indirect(sig_ref, ptr, &[n]);
+            },
+        );
+        module.finalize_definitions();
+
+        let code = module.get_finalized_function(even_id);
+        let even = unsafe { core::mem::transmute::<_, extern "C" fn(i64) -> i64>(code) };
+        assert_eq!(even(10_000_000), 1, "opt_level={}", opt_level);
+        assert_eq!(even(10_000_001), 0, "opt_level={}", opt_level);
+
+        // A tail call can't pass arguments on the stack, since it reuses the caller's frame.
+        let many = Signature {
+            params: vec![AbiParam::new(types::I64); 8],
+            returns: vec![AbiParam::new(types::I64)],
+            call_conv: CallConv::SystemV,
+        };
+        let many_id = module
+            .declare_function("many", Linkage::Local, &many)
+            .unwrap();
+        ctx.func.signature = many.clone();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let args = bcx.block_params(block).to_vec();
+            let callee = module.declare_func_in_func(many_id, bcx.func);
+            bcx.ins().return_call(callee, &args);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        match module.define_function(
+            many_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        ) {
+            Err(ModuleError::Compilation(CodegenError::Unsupported(_))) => {}
+            Err(err) => panic!("expected an unsupported feature error, got {:?}", err),
+            Ok(_) => panic!("expected an unsupported feature error"),
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn invokes() {
+    use cranelift_codegen::isa::BackendVariant;
+
+    // `caller(n)` invokes `inc(n)`, which returns normally, so the landing pad receiving `n` is
+    // never entered.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64)],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &opt_level in ["none", "speed"].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        flag_builder.set("opt_level", opt_level).unwrap();
+        let isa_builder = cranelift_native::builder_with_options(BackendVariant::MachInst, true)
+            .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let inc_id = module
+            .declare_function("inc", Linkage::Local, &sig)
+            .unwrap();
+        let caller_id = module
+            .declare_function("caller", Linkage::Local, &sig)
+            .unwrap();
+
+        let mut ctx = module.make_context();
+        let mut func_ctx = FunctionBuilderContext::new();
+
+        // inc(n) = n + 1
+        ctx.func.signature = sig.clone();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let n = bcx.block_params(block)[0];
+            let n = bcx.ins().iadd_imm(n, 1);
+            bcx.ins().return_(&[n]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module
+            .define_function(
+                inc_id,
+                &mut ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap();
+        module.clear_context(&mut ctx);
+
+        ctx.func.signature = sig.clone();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let block = bcx.create_block();
+            let normal = bcx.create_block();
+            let landing_pad = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.append_block_param(landing_pad, types::I64);
+            bcx.switch_to_block(block);
+            let n = bcx.block_params(block)[0];
+            let inc = module.declare_func_in_func(inc_id, bcx.func);
+            let invoke = bcx.ins().invoke(inc, landing_pad, &[n, n]);
+            let result = bcx.inst_results(invoke)[0];
+            bcx.ins().jump(normal, &[]);
+            bcx.switch_to_block(normal);
+            bcx.ins().return_(&[result]);
+            bcx.switch_to_block(landing_pad);
+            bcx.ins().landing_pad(types::I64);
+            let n = bcx.block_params(landing_pad)[0];
+            bcx.ins().return_(&[n]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module
+            .define_function(
+                caller_id,
+                &mut ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap_or_else(|err| panic!("{:?}", err));
+        module.clear_context(&mut ctx);
+        module.finalize_definitions();
+
+        let code = module.get_finalized_function(caller_id);
+        let caller = unsafe { core::mem::transmute::<_, extern "C" fn(i64) -> i64>(code) };
+        assert_eq!(caller(41), 42, "opt_level={}", opt_level);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn inline_probestack() {
+    use cranelift_codegen::isa::BackendVariant;
+
+    // Large frames are probed without calling out to the probestack function, so no symbol needs
+    // to be provided for it. The frames below cover the unrolled probes as well as the loop.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64)],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &opt_level in ["none", "speed"].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        flag_builder.enable("enable_probestack").unwrap();
+        flag_builder.set("probestack_strategy", "inline").unwrap();
+        flag_builder.set("opt_level", opt_level).unwrap();
+        let isa_builder = cranelift_native::builder_with_options(BackendVariant::MachInst, true)
+            .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let names = default_libcall_names();
+        let libcall_names = Box::new(move |libcall: LibCall| {
+            assert_ne!(libcall, LibCall::Probestack);
+            names(libcall)
+        });
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, libcall_names));
+
+        let mut ctx = module.make_context();
+        let mut func_ctx = FunctionBuilderContext::new();
+        let mut funcs = Vec::new();
+        for &frame_size in [3 * 4096, 256 * 1024].iter() {
+            let func_id = module
+                .declare_function(&format!("frame_{}", frame_size), Linkage::Local, &sig)
+                .unwrap();
+
+            // Store `n` at both ends of the frame and add the two back up.
+            ctx.func.signature = sig.clone();
+            {
+                let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                let slot = bcx
+                    .create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, frame_size));
+                let block = bcx.create_block();
+                bcx.append_block_params_for_function_params(block);
+                bcx.switch_to_block(block);
+                let n = bcx.block_params(block)[0];
+                bcx.ins().stack_store(n, slot, 0);
+                bcx.ins().stack_store(n, slot, frame_size as i32 - 8);
+                let low = bcx.ins().stack_load(types::I64, slot, 0);
+                let high = bcx
+                    .ins()
+                    .stack_load(types::I64, slot, frame_size as i32 - 8);
+                let sum = bcx.ins().iadd(low, high);
+                bcx.ins().return_(&[sum]);
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            module
+                .define_function(
+                    func_id,
+                    &mut ctx,
+                    &mut NullTrapSink {},
+                    &mut NullStackMapSink {},
+                )
+                .unwrap_or_else(|err| panic!("{:?}", err));
+            module.clear_context(&mut ctx);
+            funcs.push(func_id);
+        }
+        module.finalize_definitions();
+
+        for &func_id in funcs.iter() {
+            let code = module.get_finalized_function(func_id);
+            let func = unsafe { core::mem::transmute::<_, extern "C" fn(i64) -> i64>(code) };
+            assert_eq!(func(21), 42, "opt_level={}", opt_level);
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn stack_limit_global_value() {
+    use cranelift_codegen::isa::BackendVariant;
+
+    // The stack limit is loaded from the word `vmctx` points to, plus a page of slack. With a
+    // limit of a page above zero the check has to pass and the function returns normally.
+    let sig = Signature {
+        params: vec![AbiParam::special(types::I64, ArgumentPurpose::VMContext)],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &opt_level in ["none", "speed"].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        flag_builder.set("opt_level", opt_level).unwrap();
+        let isa_builder = cranelift_native::builder_with_options(BackendVariant::MachInst, true)
+            .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let func_id = module
+            .declare_function("checked", Linkage::Local, &sig)
+            .unwrap();
+        let mut ctx = module.make_context();
+        let mut func_ctx = FunctionBuilderContext::new();
+        ctx.func.signature = sig.clone();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let vmctx = bcx.create_global_value(GlobalValueData::VMContext);
+            let base = bcx.create_global_value(GlobalValueData::Load {
+                base: vmctx,
+                offset: 0.into(),
+                global_type: types::I64,
+                readonly: true,
+            });
+            let limit = bcx.create_global_value(GlobalValueData::IAddImm {
+                base,
+                offset: 4096.into(),
+                global_type: types::I64,
+            });
+            bcx.func.stack_limit = Some(limit);
+            let slot = bcx.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 64));
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let seven = bcx.ins().iconst(types::I64, 7);
+            bcx.ins().stack_store(seven, slot, 0);
+            let seven = bcx.ins().stack_load(types::I64, slot, 0);
+            bcx.ins().return_(&[seven]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module
+            .define_function(
+                func_id,
+                &mut ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap_or_else(|err| panic!("{:?}", err));
+        module.clear_context(&mut ctx);
+        module.finalize_definitions();
+
+        let code = module.get_finalized_function(func_id);
+        let checked = unsafe { core::mem::transmute::<_, extern "C" fn(*const u64) -> i64>(code) };
+        let limit = 0u64;
+        assert_eq!(checked(&limit), 7, "opt_level={}", opt_level);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn atomic_cas_i128() {
+    use cranelift_codegen::isa::BackendVariant;
+
+    if !std::is_x86_feature_detected!("cmpxchg16b") {
+        return;
+    }
+
+    // Swaps `*p` from `e` to `x`, passing every 128-bit value as two halves, and stores the old
+    // value to `old`.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64); 6],
+        returns: vec![],
+        call_conv: CallConv::SystemV,
+    };
+
+    let mut flag_builder = settings::builder();
+    flag_builder.enable("enable_verifier").unwrap();
+    let mut isa_builder = cranelift_native::builder_with_options(BackendVariant::MachInst, true)
+        .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+    isa_builder.enable("has_cmpxchg16b").unwrap();
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let func_id = module
+        .declare_function("cas128", Linkage::Local, &sig)
+        .unwrap();
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig;
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        let flags = MemFlags::trusted();
+        let e = bcx.ins().iconcat(params[2], params[3]);
+        let x = bcx.ins().iconcat(params[4], params[5]);
+        let old = bcx.ins().atomic_cas(flags, params[0], e, x);
+        let (old_lo, old_hi) = bcx.ins().isplit(old);
+        bcx.ins().store(flags, old_lo, params[1], 0);
+        bcx.ins().store(flags, old_hi, params[1], 8);
+        bcx.ins().return_(&[]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module
+        .define_function(
+            func_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap_or_else(|err| panic!("{:?}", err));
+    module.finalize_definitions();
+
+    #[repr(C, align(16))]
+    struct Cell([u64; 2]);
+
+    type Cas = extern "C" fn(*mut Cell, *mut [u64; 2], u64, u64, u64, u64);
+
+    let code = module.get_finalized_function(func_id);
+    let cas = unsafe { core::mem::transmute::<_, Cas>(code) };
+    let mut cell = Cell([1, 2]);
+    let mut old = [0; 2];
+
+    // A mismatch in either half leaves the location alone.
+    cas(&mut cell, &mut old, 1, 3, 5, 6);
+    assert_eq!((cell.0, old), ([1, 2], [1, 2]));
+    cas(&mut cell, &mut old, 3, 2, 5, 6);
+    assert_eq!((cell.0, old), ([1, 2], [1, 2]));
+
+    cas(&mut cell, &mut old, 1, 2, 5, 6);
+    assert_eq!((cell.0, old), ([5, 6], [1, 2]));
+}
+
+#[test]
+fn bswap() {
+    use cranelift_codegen::isa::BackendVariant;
+
+    // Byte-swaps an i16, an i32 and an i64 loaded from `src` into `dst`, plus the i32 incremented
+    // so that the swap is also done on a value that doesn't come straight from memory.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64); 2],
+        returns: vec![],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &variant in [BackendVariant::Legacy, BackendVariant::MachInst].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        #[allow(unused_mut)]
+        let mut isa_builder = cranelift_native::builder_with_options(variant, true)
+            .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        #[cfg(target_arch = "x86_64")]
+        {
+            if std::is_x86_feature_detected!("movbe") {
+                isa_builder.enable("has_movbe").unwrap();
+            }
+        }
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let func_id = module
+            .declare_function("bswap", Linkage::Local, &sig)
+            .unwrap();
+        let mut ctx = module.make_context();
+        ctx.func.signature = sig.clone();
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let (src, dst) = (bcx.block_params(block)[0], bcx.block_params(block)[1]);
+            let flags = MemFlags::trusted();
+            // The offsets are those of the fields of the `#[repr(C)]` `Values` struct below.
+            for &(ty, offset) in [(types::I16, 0), (types::I32, 4), (types::I64, 8)].iter() {
+                let x = bcx.ins().load(ty, flags, src, offset);
+                let y = bcx.ins().bswap(x);
+                bcx.ins().store(flags, y, dst, offset);
+            }
+            let x = bcx.ins().load(types::I32, flags, src, 4);
+            let x = bcx.ins().iadd_imm(x, 1);
+            let y = bcx.ins().bswap(x);
+            bcx.ins().store(flags, y, dst, 16);
+            bcx.ins().return_(&[]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module
+            .define_function(
+                func_id,
+                &mut ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap_or_else(|err| panic!("{:?}", err));
+        module.finalize_definitions();
+
+        #[repr(C)]
+        #[derive(Debug, Default, PartialEq)]
+        struct Values(u16, u32, u64, u32);
+
+        type Swap = extern "C" fn(*const Values, *mut Values);
+
+        let code = module.get_finalized_function(func_id);
+        let f = unsafe { core::mem::transmute::<_, Swap>(code) };
+        let src = Values(0x0102, 0x0102_0304, 0x0102_0304_0506_0708, 0);
+        let mut dst = Values::default();
+        f(&src, &mut dst);
+        assert_eq!(
+            dst,
+            Values(0x0201, 0x0403_0201, 0x0807_0605_0403_0201, 0x0503_0201),
+            "{:?}",
+            variant
+        );
+    }
+}
+
+#[test]
+fn bitrev_and_cls() {
+    use cranelift_codegen::isa::BackendVariant;
+
+    type Op = fn(&mut FunctionBuilder, Value) -> Value;
+    type Check = fn(u64, u32) -> u64;
+    let ops: [(&str, Op, Check); 2] = [
+        (
+            "bitrev",
+            |b, x| b.ins().bitrev(x),
+            |x, bits| x.reverse_bits() >> (64 - bits),
+        ),
+        (
+            "cls",
+            |b, x| b.ins().cls(x),
+            |x, bits| {
+                let x = (x << (64 - bits)) as i64 >> (64 - bits);
+                u64::from((x ^ (x >> 63)).leading_zeros() - 1 - (64 - bits))
+            },
+        ),
+    ];
+    let tys = [types::I8, types::I16, types::I32, types::I64];
+
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64)],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &variant in [BackendVariant::Legacy, BackendVariant::MachInst].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        let isa_builder = cranelift_native::builder_with_options(variant, true)
+            .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let mut funcs = Vec::new();
+        for &(name, op, check) in ops.iter() {
+            for &ty in tys.iter() {
+                let func_id = module
+                    .declare_function(&format!("{}_{}", name, ty), Linkage::Local, &sig)
+                    .unwrap();
+                let mut ctx = module.make_context();
+                ctx.func.signature = sig.clone();
+                let mut func_ctx = FunctionBuilderContext::new();
+                {
+                    let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                    let block = bcx.create_block();
+                    bcx.append_block_params_for_function_params(block);
+                    bcx.switch_to_block(block);
+                    let mut x = bcx.block_params(block)[0];
+                    if ty != types::I64 {
+                        x = bcx.ins().ireduce(ty, x);
+                    }
+                    let mut y = op(&mut bcx, x);
+                    if ty != types::I64 {
+                        y = bcx.ins().uextend(types::I64, y);
+                    }
+                    bcx.ins().return_(&[y]);
+                    bcx.seal_all_blocks();
+                    bcx.finalize();
+                }
+                module
+                    .define_function(
+                        func_id,
+                        &mut ctx,
+                        &mut NullTrapSink {},
+                        &mut NullStackMapSink {},
+                    )
+                    .unwrap_or_else(|err| panic!("{} {}: {:?}", name, ty, err));
+                funcs.push((name, ty, check, func_id));
+            }
+        }
+        module.finalize_definitions();
+
+        for &(name, ty, check, func_id) in funcs.iter() {
+            let code = module.get_finalized_function(func_id);
+            let f = unsafe { core::mem::transmute::<_, extern "C" fn(u64) -> u64>(code) };
+            for &x in [0, 1, 0x7f, 0x80, 0x1234_5678_9abc_def0, u64::max_value()].iter() {
+                let bits = u32::from(ty.bits());
+                assert_eq!(
+                    f(x),
+                    check(x, bits),
+                    "{:?} {}.{}({:#x})",
+                    variant,
+                    name,
+                    ty,
+                    x
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn cls_i128() {
+    use cranelift_codegen::isa::BackendVariant;
+
+    // Counts the leading sign bits of the i128 made of the two i64 halves passed in. Only the new
+    // backend is tested, as the legacy one can't `iconcat` function arguments.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64); 2],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+
+    let mut flag_builder = settings::builder();
+    flag_builder.enable("enable_verifier").unwrap();
+    let isa_builder = cranelift_native::builder_with_options(BackendVariant::MachInst, true)
+        .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+    let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let func_id = module
+        .declare_function("cls_i128", Linkage::Local, &sig)
+        .unwrap();
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig.clone();
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let (lo, hi) = (bcx.block_params(block)[0], bcx.block_params(block)[1]);
+        let x = bcx.ins().iconcat(lo, hi);
+        let y = bcx.ins().cls(x);
+        let y = bcx.ins().ireduce(types::I64, y);
+        bcx.ins().return_(&[y]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module
+        .define_function(
+            func_id,
+            &mut ctx,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )
+        .unwrap_or_else(|err| panic!("{:?}", err));
+    module.finalize_definitions();
+
+    let code = module.get_finalized_function(func_id);
+    let f = unsafe { core::mem::transmute::<_, extern "C" fn(u64, u64) -> u64>(code) };
+    for &x in [
+        0,
+        1,
+        -1,
+        0x7f,
+        1 << 64,
+        -(1 << 64),
+        0x1234_5678_9abc_def0,
+        i128::from(i64::min_value()),
+        i128::max_value(),
+        i128::min_value(),
+    ]
+    .iter()
+    {
+        let x: i128 = x;
+        let expected = u64::from((x ^ (x >> 127)).leading_zeros() - 1);
+        assert_eq!(f(x as u64, (x >> 64) as u64), expected, "cls({:#x})", x);
+    }
+}
+
+#[test]
+fn select_spectre_guard() {
+    use condcodes::IntCC;
+    use cranelift_codegen::isa::BackendVariant;
+
+    // Clamps an out-of-bounds index to zero, the way a sandboxing frontend would guard a
+    // bounds-checked access against speculation.
+    let sig = Signature {
+        params: vec![AbiParam::new(types::I64); 2],
+        returns: vec![AbiParam::new(types::I64)],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &variant in [BackendVariant::Legacy, BackendVariant::MachInst].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        let isa_builder = cranelift_native::builder_with_options(variant, true)
+            .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let func_id = module
+            .declare_function("clamp_index", Linkage::Local, &sig)
+            .unwrap();
+        let mut ctx = module.make_context();
+        ctx.func.signature = sig.clone();
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let (index, bound) = (bcx.block_params(block)[0], bcx.block_params(block)[1]);
+            let in_bounds = bcx.ins().icmp(IntCC::UnsignedLessThan, index, bound);
+            let zero = bcx.ins().iconst(types::I64, 0);
+            let clamped = bcx.ins().select_spectre_guard(in_bounds, index, zero);
+            bcx.ins().return_(&[clamped]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module
+            .define_function(
+                func_id,
+                &mut ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap_or_else(|err| panic!("{:?}", err));
+        module.finalize_definitions();
+
+        type Clamp = extern "C" fn(u64, u64) -> u64;
+
+        let code = module.get_finalized_function(func_id);
+        let f = unsafe { core::mem::transmute::<_, Clamp>(code) };
+        let cases = [(0, 1, 0), (3, 4, 3), (4, 4, 0), (u64::max_value(), 4, 0)];
+        for &(index, bound, expected) in cases.iter() {
+            assert_eq!(f(index, bound), expected, "{:?} {}", variant, index);
+        }
+    }
+}
+
+#[test]
+fn dynamic_bounded_heap() {
+    use cranelift_codegen::ir::immediates::Uimm64;
+    use cranelift_codegen::isa::BackendVariant;
+
+    // Loads an i32 from a heap described by `vmctx`, which has no offset-guard pages.
+    let sig = Signature {
+        params: vec![
+            AbiParam::special(types::I64, ArgumentPurpose::VMContext),
+            AbiParam::new(types::I64),
+        ],
+        returns: vec![AbiParam::new(types::I32)],
+        call_conv: CallConv::SystemV,
+    };
+
+    for &variant in [BackendVariant::Legacy, BackendVariant::MachInst].iter() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("enable_verifier").unwrap();
+        let isa_builder = cranelift_native::builder_with_options(variant, true)
+            .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let func_id = module
+            .declare_function("heap_load", Linkage::Local, &sig)
+            .unwrap();
+        let mut ctx = module.make_context();
+        ctx.func.signature = sig.clone();
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let vmctx = bcx.create_global_value(GlobalValueData::VMContext);
+            let base = bcx.create_global_value(GlobalValueData::Load {
+                base: vmctx,
+                offset: 0.into(),
+                global_type: types::I64,
+                readonly: true,
+            });
+            let bound_gv = bcx.create_global_value(GlobalValueData::Load {
+                base: vmctx,
+                offset: 8.into(),
+                global_type: types::I64,
+                readonly: false,
+            });
+            let heap = bcx.create_heap(HeapData {
+                base,
+                min_size: Uimm64::new(0),
+                offset_guard_size: Uimm64::new(0),
+                style: HeapStyle::DynamicBounded { bound_gv },
+                index_type: types::I64,
+            });
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let index = bcx.block_params(block)[1];
+            let addr = bcx.ins().heap_addr(types::I64, heap, index, 4);
+            let value = bcx.ins().load(types::I32, MemFlags::trusted(), addr, 0);
+            bcx.ins().return_(&[value]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module
+            .define_function(
+                func_id,
+                &mut ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap_or_else(|err| panic!("{:?}", err));
+        module.finalize_definitions();
+
+        #[repr(C)]
+        struct VMContext {
+            base: *const u8,
+            bound: u64,
+        }
+
+        type Load = extern "C" fn(*const VMContext, u64) -> u32;
+
+        let memory = [1u32, 2, 3, 4];
+        let vmctx = VMContext {
+            base: memory.as_ptr() as *const u8,
+            bound: 16,
+        };
+        let code = module.get_finalized_function(func_id);
+        let f = unsafe { core::mem::transmute::<_, Load>(code) };
+        for (i, &expected) in memory.iter().enumerate() {
+            assert_eq!(f(&vmctx, 4 * i as u64), expected, "{:?}", variant);
+        }
+    }
+}
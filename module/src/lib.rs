@@ -22,6 +22,9 @@
 #[cfg(not(feature = "std"))]
 #[macro_use]
 extern crate alloc as std;
+#[cfg(feature = "std")]
+#[macro_use]
+extern crate std;
 
 use hashbrown::{hash_map, HashMap};
 use std::{borrow::ToOwned, boxed::Box, string::String};
@@ -30,6 +33,7 @@ use cranelift_codegen::ir;
 
 mod data_context;
 mod module;
+mod sink;
 mod traps;
 
 pub use crate::{
@@ -38,6 +42,7 @@ pub use crate::{
         DataId, FuncId, FuncOrDataId, Linkage, Module, ModuleCompiledFunction, ModuleDeclarations,
         ModuleError, ModuleResult, RelocRecord,
     },
+    sink::{ModuleSink, NullModuleSink},
     traps::TrapSite,
 };
 
@@ -56,6 +61,10 @@ pub fn default_libcall_names() -> Box<dyn Fn(ir::LibCall) -> String + Send + Syn
         ir::LibCall::IshlI64 => "__ashldi3".to_owned(),
         ir::LibCall::UshrI64 => "__lshrdi3".to_owned(),
         ir::LibCall::SshrI64 => "__ashrdi3".to_owned(),
+        ir::LibCall::UdivI128 => "__udivti3".to_owned(),
+        ir::LibCall::SdivI128 => "__divti3".to_owned(),
+        ir::LibCall::UremI128 => "__umodti3".to_owned(),
+        ir::LibCall::SremI128 => "__modti3".to_owned(),
         ir::LibCall::CeilF32 => "ceilf".to_owned(),
         ir::LibCall::CeilF64 => "ceil".to_owned(),
         ir::LibCall::FloorF32 => "floorf".to_owned(),
@@ -64,10 +73,15 @@ pub fn default_libcall_names() -> Box<dyn Fn(ir::LibCall) -> String + Send + Syn
         ir::LibCall::TruncF64 => "trunc".to_owned(),
         ir::LibCall::NearestF32 => "nearbyintf".to_owned(),
         ir::LibCall::NearestF64 => "nearbyint".to_owned(),
+        ir::LibCall::F16ToF32 => "__extendhfsf2".to_owned(),
+        ir::LibCall::F32ToF16 => "__truncsfhf2".to_owned(),
+        ir::LibCall::F64ToF16 => "__truncdfhf2".to_owned(),
         ir::LibCall::Memcpy => "memcpy".to_owned(),
         ir::LibCall::Memset => "memset".to_owned(),
         ir::LibCall::Memmove => "memmove".to_owned(),
+        ir::LibCall::AtomicCasI128 => "__sync_val_compare_and_swap_16".to_owned(),
 
         ir::LibCall::ElfTlsGetAddr => "__tls_get_addr".to_owned(),
+        ir::LibCall::TrapHandler => "__cranelift_trap_handler".to_owned(),
     })
 }
@@ -7,6 +7,7 @@
 
 use super::HashMap;
 use crate::data_context::DataContext;
+use crate::sink::{BinemitSinks, ModuleSink};
 use cranelift_codegen::{
     binemit,
     entity::{entity_impl, PrimaryMap},
@@ -193,6 +194,16 @@ pub enum ModuleError {
     Backend(anyhow::Error),
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for ModuleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Backend(err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
+
 impl std::fmt::Display for ModuleError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -541,6 +552,24 @@ pub trait Module {
         ctx: &mut Context,
         trap_sink: &mut dyn binemit::TrapSink,
         stack_map_sink: &mut dyn binemit::StackMapSink,
+    ) -> ModuleResult<ModuleCompiledFunction> {
+        self.define_function_with_sink(
+            func,
+            ctx,
+            &mut BinemitSinks {
+                trap_sink,
+                stack_map_sink,
+            },
+        )
+    }
+
+    /// Define a function like `define_function`, passing the relocations, trap sites and stack
+    /// maps of the compiled function to `sink`.
+    fn define_function_with_sink(
+        &mut self,
+        func: FuncId,
+        ctx: &mut Context,
+        sink: &mut dyn ModuleSink,
     ) -> ModuleResult<ModuleCompiledFunction>;
 
     /// Define a function, taking the function body from the given `bytes`.
@@ -647,6 +676,15 @@ impl<M: Module> Module for &mut M {
         (**self).define_function(func, ctx, trap_sink, stack_map_sink)
     }
 
+    fn define_function_with_sink(
+        &mut self,
+        func: FuncId,
+        ctx: &mut Context,
+        sink: &mut dyn ModuleSink,
+    ) -> ModuleResult<ModuleCompiledFunction> {
+        (**self).define_function_with_sink(func, ctx, sink)
+    }
+
     fn define_function_bytes(
         &mut self,
         func: FuncId,
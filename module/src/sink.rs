@@ -0,0 +1,42 @@
+//! Defines `ModuleSink`.
+
+use crate::{RelocRecord, TrapSite};
+use cranelift_codegen::binemit::{self, CodeOffset, StackMap};
+
+/// Receives the relocations, trap sites and stack maps of a function while a `Module` defines
+/// it. This lets users of a `Module` keep whatever metadata they need, e.g. to write it to their
+/// own image format, without the backend having to expose its internal bookkeeping.
+///
+/// Every method does nothing by default.
+pub trait ModuleSink {
+    /// A relocation to apply to the function's code.
+    fn reloc(&mut self, _reloc: &RelocRecord) {}
+
+    /// A trapping instruction in the function.
+    fn trap(&mut self, _site: &TrapSite) {}
+
+    /// The stack map of the safepoint at `offset` in the function.
+    fn stack_map(&mut self, _offset: CodeOffset, _stack_map: StackMap) {}
+}
+
+/// A `ModuleSink` that ignores everything.
+pub struct NullModuleSink {}
+
+impl ModuleSink for NullModuleSink {}
+
+/// A `ModuleSink` that forwards trap sites and stack maps to the sinks expected by
+/// `binemit`, ignoring relocations.
+pub(crate) struct BinemitSinks<'a> {
+    pub(crate) trap_sink: &'a mut dyn binemit::TrapSink,
+    pub(crate) stack_map_sink: &'a mut dyn binemit::StackMapSink,
+}
+
+impl<'a> ModuleSink for BinemitSinks<'a> {
+    fn trap(&mut self, site: &TrapSite) {
+        self.trap_sink.trap(site.offset, site.srcloc, site.code);
+    }
+
+    fn stack_map(&mut self, offset: CodeOffset, stack_map: StackMap) {
+        self.stack_map_sink.add_stack_map(offset, stack_map);
+    }
+}
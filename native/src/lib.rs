@@ -51,6 +51,7 @@ pub fn builder_with_options(
                 "support for architecture disabled at compile time"
             }
             isa::LookupError::Unsupported => "unsupported architecture",
+            isa::LookupError::InvalidTriple => "invalid host triple",
         })?;
 
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
@@ -79,6 +80,12 @@ pub fn builder_with_options(
         if std::is_x86_feature_detected!("sse4.2") {
             isa_builder.enable("has_sse42").unwrap();
         }
+        if std::is_x86_feature_detected!("cmpxchg16b") {
+            isa_builder.enable("has_cmpxchg16b").unwrap();
+        }
+        if std::is_x86_feature_detected!("movbe") {
+            isa_builder.enable("has_movbe").unwrap();
+        }
         if std::is_x86_feature_detected!("popcnt") {
             isa_builder.enable("has_popcnt").unwrap();
         }
@@ -88,6 +95,9 @@ pub fn builder_with_options(
         if std::is_x86_feature_detected!("avx2") {
             isa_builder.enable("has_avx2").unwrap();
         }
+        if std::is_x86_feature_detected!("f16c") {
+            isa_builder.enable("has_f16c").unwrap();
+        }
         if std::is_x86_feature_detected!("bmi1") {
             isa_builder.enable("has_bmi1").unwrap();
         }
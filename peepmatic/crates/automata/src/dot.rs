@@ -222,6 +222,8 @@ where
 mod tests {
     use super::*;
     use crate::Builder;
+    use alloc::{string::String, vec};
+    use std::eprintln;
 
     #[test]
     fn test_write_dot() {
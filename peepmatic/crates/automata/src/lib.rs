@@ -13,7 +13,7 @@
 //!
 //! If you're looking for a general-purpose transducers crate in Rust you're
 //! probably looking for [the `fst` crate][fst-crate]. While this implementation
-//! is fully generic and has no dependencies, its feature set is specific to
+//! is fully generic and only needs `alloc`, its feature set is specific to
 //! `peepmatic`'s needs:
 //!
 //! * We need to associate extra data with each state: the match operation to
@@ -46,6 +46,12 @@
 
 #![deny(missing_debug_implementations)]
 #![deny(missing_docs)]
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(any(test, feature = "dot"))]
+extern crate std;
 
 mod output_impls;
 
@@ -55,12 +61,9 @@ mod serde_impls;
 #[cfg(feature = "dot")]
 pub mod dot;
 
-use std::{
-    collections::{BTreeMap, HashMap, HashSet},
-    convert::TryInto,
-    hash::Hash,
-    iter, mem,
-};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::{convert::TryInto, hash::Hash, iter, mem};
+use hashbrown::{HashMap, HashSet};
 
 /// An output type for a transducer automata.
 ///
@@ -321,7 +324,7 @@ where
             .collect::<Vec<_>>();
         let mut final_states = BTreeMap::new();
 
-        assert!((inner.frozen.len() as u64) < (std::u32::MAX as u64));
+        assert!((inner.frozen.len() as u64) < (u32::MAX as u64));
         for (i, state) in inner.frozen.into_iter().enumerate() {
             assert!(state_data[i].is_none());
             assert!(transitions[i].is_empty());
@@ -1,5 +1,6 @@
 use crate::Output;
-use std::{cmp, hash::Hash};
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::{cmp, hash::Hash};
 
 impl Output for u64 {
     fn empty() -> Self {
@@ -96,7 +97,8 @@ where
 #[cfg(test)]
 mod tests {
     use crate::Output;
-    use std::fmt::Debug;
+    use alloc::vec;
+    use core::fmt::Debug;
 
     // Assert the laws that `Output` requires for correctness. `a` and `b`
     // should be two different instances of an `Output` type.
@@ -8,12 +8,13 @@
 //! make sure to bump `SERIALIZATION_VERSION`.
 
 use crate::{Automaton, Output, State};
+use alloc::{collections::BTreeMap, format, vec::Vec};
+use core::{fmt, hash::Hash, marker::PhantomData};
 use serde::{
     de::{self, Deserializer, SeqAccess, Visitor},
     ser::SerializeTupleStruct,
     Deserialize, Serialize, Serializer,
 };
-use std::{collections::BTreeMap, fmt, hash::Hash, marker::PhantomData};
 
 const SERIALIZATION_VERSION: u32 = 1;
 
@@ -62,7 +63,6 @@ impl<'de> Visitor<'de> for U32Visitor {
     where
         E: de::Error,
     {
-        use std::u32;
         if value <= u64::from(u32::MAX) {
             Ok(value as u32)
         } else {
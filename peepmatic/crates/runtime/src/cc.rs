@@ -1,7 +1,7 @@
 //! Condition codes.
 
+use core::{convert::TryFrom, fmt};
 use serde::{Deserialize, Serialize};
-use std::{convert::TryFrom, fmt};
 
 /// A condition code.
 ///
@@ -0,0 +1,466 @@
+//! A `no_std` deserializer for the `bincode` encoding of peephole
+//! optimizations.
+//!
+//! The `bincode` crate itself requires `std`, but `no_std` users of this crate
+//! still need to load the optimizations that `peepmatic` serialized with
+//! `bincode::serialize`. This implements the decoding half of that format:
+//! fixed-width little-endian integers, `u64` lengths for sequences, maps and
+//! strings, `u32` enum variant indices, and a `u8` tag for options. Structs and
+//! tuples are just their fields in order.
+
+use alloc::string::{String, ToString};
+use core::{convert::TryInto, fmt, str};
+use serde::de::{
+    self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
+
+/// An error that occurred while decoding serialized peephole optimizations.
+#[derive(Debug)]
+pub struct DecodeError {
+    message: String,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl de::StdError for DecodeError {}
+
+impl de::Error for DecodeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DecodeError {
+            message: msg.to_string(),
+        }
+    }
+}
+
+/// Deserialize a `T` from the bincode-encoded `bytes`.
+pub fn from_bytes<'de, T>(bytes: &'de [u8]) -> Result<T, DecodeError>
+where
+    T: de::Deserialize<'de>,
+{
+    T::deserialize(&mut Deserializer { bytes })
+}
+
+struct Deserializer<'de> {
+    bytes: &'de [u8],
+}
+
+impl<'de> Deserializer<'de> {
+    fn take(&mut self, len: usize) -> Result<&'de [u8], DecodeError> {
+        if len > self.bytes.len() {
+            return Err(de::Error::custom("unexpected end of input"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take_array()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take_array()?))
+    }
+
+    fn read_len(&mut self) -> Result<usize, DecodeError> {
+        let len = self.read_u64()?;
+        len.try_into()
+            .map_err(|_| de::Error::custom("length does not fit in a `usize`"))
+    }
+
+    fn read_str(&mut self) -> Result<&'de str, DecodeError> {
+        let len = self.read_len()?;
+        str::from_utf8(self.take(len)?).map_err(de::Error::custom)
+    }
+}
+
+macro_rules! deserialize_ints {
+    ( $( $method:ident => $visit:ident ( $ty:ty ); )* ) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+            where
+                V: Visitor<'de>,
+            {
+                visitor.$visit(<$ty>::from_le_bytes(self.take_array()?))
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+    type Error = DecodeError;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        Err(de::Error::custom(
+            "the bincode format is not self-describing",
+        ))
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.read_u8()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            b => Err(de::Error::custom(format_args!("invalid bool: {}", b))),
+        }
+    }
+
+    deserialize_ints! {
+        deserialize_i8 => visit_i8(i8);
+        deserialize_i16 => visit_i16(i16);
+        deserialize_i32 => visit_i32(i32);
+        deserialize_i64 => visit_i64(i64);
+        deserialize_i128 => visit_i128(i128);
+        deserialize_u8 => visit_u8(u8);
+        deserialize_u16 => visit_u16(u16);
+        deserialize_u32 => visit_u32(u32);
+        deserialize_u64 => visit_u64(u64);
+        deserialize_u128 => visit_u128(u128);
+        deserialize_f32 => visit_f32(f32);
+        deserialize_f64 => visit_f64(f64);
+    }
+
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        // Chars are encoded as their UTF-8 bytes, without a length prefix.
+        let first = self.read_u8()?;
+        let width = match first {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Err(de::Error::custom("invalid char encoding")),
+        };
+        let mut buf = [first, 0, 0, 0];
+        buf[1..width].copy_from_slice(self.take(width - 1)?);
+        let s = str::from_utf8(&buf[..width]).map_err(de::Error::custom)?;
+        visitor.visit_char(s.chars().next().unwrap())
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_borrowed_str(self.read_str()?)
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        let len = self.read_len()?;
+        visitor.visit_borrowed_bytes(self.take(len)?)
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.read_u8()? {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            tag => Err(de::Error::custom(format_args!(
+                "invalid option tag: {}",
+                tag
+            ))),
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        let len = self.read_len()?;
+        visitor.visit_seq(Access { de: self, len })
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(Access { de: self, len })
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        let len = self.read_len()?;
+        visitor.visit_map(Access { de: self, len })
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        Err(de::Error::custom(
+            "the bincode format does not encode identifiers",
+        ))
+    }
+
+    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        Err(de::Error::custom(
+            "the bincode format cannot skip unknown values",
+        ))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Access to the `len` elements of a sequence, or entries of a map, or fields
+/// of a tuple or struct.
+struct Access<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    len: usize,
+}
+
+impl<'de, 'a> SeqAccess<'de> for Access<'a, 'de> {
+    type Error = DecodeError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, DecodeError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de, 'a> MapAccess<'de> for Access<'a, 'de> {
+    type Error = DecodeError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, DecodeError>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, DecodeError>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de, 'a> EnumAccess<'de> for &'a mut Deserializer<'de> {
+    type Error = DecodeError;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self), DecodeError>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let index = self.read_u32()?;
+        let variant =
+            seed.deserialize(IntoDeserializer::<DecodeError>::into_deserializer(index))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a> VariantAccess<'de> for &'a mut Deserializer<'de> {
+    type Error = DecodeError;
+
+    fn unit_variant(self) -> Result<(), DecodeError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, DecodeError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DecodeError>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Kind {
+        Unit,
+        Newtype(u16),
+        Tuple(i8, char),
+        Struct { flag: bool, name: String },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Everything {
+        kinds: Vec<Kind>,
+        map: BTreeMap<u64, Option<i32>>,
+        boxed: Box<[u128]>,
+        pair: (f32, f64),
+        unit: (),
+    }
+
+    #[test]
+    fn decode_bincode_encoding() {
+        let mut map = BTreeMap::new();
+        map.insert(1, Some(-7));
+        map.insert(u64::MAX, None);
+        let value = Everything {
+            kinds: vec![
+                Kind::Unit,
+                Kind::Newtype(0xbeef),
+                Kind::Tuple(-1, 'λ'),
+                Kind::Struct {
+                    flag: true,
+                    name: "peepmatic".into(),
+                },
+            ],
+            map,
+            boxed: vec![0, u128::MAX].into_boxed_slice(),
+            pair: (1.5, -0.25),
+            unit: (),
+        };
+
+        let bytes = bincode::serialize(&value).unwrap();
+        assert_eq!(from_bytes::<Everything>(&bytes).unwrap(), value);
+
+        // Truncated input is an error, not a panic.
+        for len in 0..bytes.len() {
+            assert!(from_bytes::<Everything>(&bytes[..len]).is_err());
+        }
+    }
+}
@@ -1,44 +1,78 @@
 //! `Error` and `Result` types for this crate.
 
+use crate::decode::DecodeError;
+use alloc::boxed::Box;
+use core::fmt;
+
+#[cfg(feature = "std")]
 use std::io;
-use thiserror::Error;
 
 /// A result type containing `Ok(T)` or `Err(peepmatic_runtime::Error)`.
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 /// Errors that `peepmatic_runtime` may generate.
-#[derive(Debug, Error)]
-#[error(transparent)]
+#[derive(Debug)]
 pub struct Error {
-    #[from]
     inner: Box<ErrorInner>,
 }
 
-#[derive(Debug, Error)]
+#[derive(Debug)]
 enum ErrorInner {
-    #[error(transparent)]
-    Io(#[from] io::Error),
+    #[cfg(feature = "std")]
+    Io(io::Error),
+
+    #[cfg(feature = "construct")]
+    Bincode(bincode::Error),
+
+    Decode(DecodeError),
+}
 
-    #[error(transparent)]
-    Bincode(#[from] bincode::Error),
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &*self.inner {
+            #[cfg(feature = "std")]
+            ErrorInner::Io(e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "construct")]
+            ErrorInner::Bincode(e) => fmt::Display::fmt(e, f),
+            ErrorInner::Decode(e) => fmt::Display::fmt(e, f),
+        }
+    }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &*self.inner {
+            ErrorInner::Io(e) => e.source(),
+            #[cfg(feature = "construct")]
+            ErrorInner::Bincode(e) => e.source(),
+            ErrorInner::Decode(e) => e.source(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Error {
-        let e: ErrorInner = e.into();
-        e.into()
+        ErrorInner::Io(e).into()
     }
 }
 
+#[cfg(feature = "construct")]
 impl From<bincode::Error> for Error {
     fn from(e: bincode::Error) -> Error {
-        let e: ErrorInner = e.into();
-        e.into()
+        ErrorInner::Bincode(e).into()
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Error {
+        ErrorInner::Decode(e).into()
     }
 }
 
 impl From<ErrorInner> for Error {
     fn from(e: ErrorInner) -> Error {
-        Box::new(e).into()
+        Error { inner: Box::new(e) }
     }
 }
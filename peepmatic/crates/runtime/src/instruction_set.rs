@@ -4,7 +4,7 @@ use crate::{
     part::{Constant, Part},
     r#type::Type,
 };
-use std::{fmt::Debug, hash::Hash, num::NonZeroU32};
+use core::{fmt::Debug, hash::Hash, num::NonZeroU32};
 
 /// A trait for interfacing with actual instruction sequences.
 ///
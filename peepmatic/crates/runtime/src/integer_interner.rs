@@ -5,17 +5,17 @@
 //! few compared to the full range of `u64`) integers we are matching against
 //! here and then reference them by `IntegerId`.
 
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{
+    fmt,
+    marker::PhantomData,
+    num::{NonZeroU16, NonZeroU32},
+};
 use serde::{
     de::{Deserializer, SeqAccess, Visitor},
     ser::{SerializeSeq, Serializer},
     Deserialize, Serialize,
 };
-use std::{
-    collections::BTreeMap,
-    fmt,
-    marker::PhantomData,
-    num::{NonZeroU16, NonZeroU32},
-};
 
 /// An identifier for an interned integer.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -48,7 +48,7 @@ impl IntegerInterner {
             return *id;
         }
 
-        assert!((self.values.len() as u64) < (std::u16::MAX as u64));
+        assert!((self.values.len() as u64) < (u16::MAX as u64));
         let id = IntegerId(unsafe { NonZeroU16::new_unchecked(self.values.len() as u16 + 1) });
 
         self.values.push(value);
@@ -141,8 +141,8 @@ impl<'de> Visitor<'de> for IntegerInternerVisitor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::iter::successors;
     use serde_test::{assert_tokens, Token};
-    use std::iter::successors;
 
     #[derive(Debug, Serialize, Deserialize)]
     #[serde(transparent)]
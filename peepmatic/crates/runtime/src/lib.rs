@@ -13,11 +13,21 @@
 //! deserialize an already-built peephole optimizer, and then use it.
 //!
 //! That's all that is contained here in this crate.
+//!
+//! Loading and using peephole optimizers only needs `alloc`, so this crate is
+//! `no_std` unless its `"std"` cargo feature is enabled.
 
 #![deny(missing_docs)]
 #![deny(missing_debug_implementations)]
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+extern crate std;
 
 pub mod cc;
+mod decode;
 pub mod error;
 pub mod instruction_set;
 pub mod integer_interner;
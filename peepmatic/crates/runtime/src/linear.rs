@@ -11,8 +11,9 @@ use crate::{
     r#type::{BitWidth, Type},
     unquote::UnquoteOperator,
 };
+use alloc::vec::Vec;
+use core::{fmt::Debug, hash::Hash, num::NonZeroU32};
 use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, hash::Hash, num::NonZeroU32};
 
 /// A set of linear optimizations.
 #[derive(Debug)]
@@ -226,16 +227,16 @@ mod tests {
 
     #[test]
     fn match_result_size() {
-        assert_eq!(std::mem::size_of::<MatchResult>(), 4);
+        assert_eq!(core::mem::size_of::<MatchResult>(), 4);
     }
 
     #[test]
     fn match_op_size() {
-        assert_eq!(std::mem::size_of::<MatchOp>(), 6);
+        assert_eq!(core::mem::size_of::<MatchOp>(), 6);
     }
 
     #[test]
     fn action_size() {
-        assert_eq!(std::mem::size_of::<Action<TestOperator>>(), 16);
+        assert_eq!(core::mem::size_of::<Action<TestOperator>>(), 16);
     }
 }
@@ -7,9 +7,10 @@ use crate::{
     linear::{Action, MatchOp, MatchResult},
     optimizer::PeepholeOptimizer,
 };
+use alloc::{boxed::Box, vec};
+use core::{fmt::Debug, hash::Hash};
 use peepmatic_automata::Automaton;
 use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, hash::Hash};
 
 #[cfg(feature = "construct")]
 use std::fs;
@@ -39,11 +40,14 @@ where
     TOperator: 'static + Copy + Debug + Eq + Hash,
 {
     /// Deserialize a `PeepholeOptimizations` from bytes.
+    ///
+    /// The bytes must be in the `bincode` format written by
+    /// `serialize_to_file`. This does not require `std`.
     pub fn deserialize<'a>(serialized: &'a [u8]) -> Result<Self>
     where
         TOperator: serde::Deserialize<'a>,
     {
-        let peep_opt: Self = bincode::deserialize(serialized)?;
+        let peep_opt: Self = crate::decode::from_bytes(serialized)?;
         Ok(peep_opt)
     }
 
@@ -80,7 +84,7 @@ where
     ) -> PeepholeOptimizer<'peep, 'ctx, TInstructionSet>
     where
         TInstructionSet: InstructionSet<'ctx, Operator = TOperator>,
-        TOperator: Into<std::num::NonZeroU32>,
+        TOperator: Into<core::num::NonZeroU32>,
     {
         PeepholeOptimizer {
             peep_opt: self,
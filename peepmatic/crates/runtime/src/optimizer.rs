@@ -8,13 +8,14 @@ use crate::{
     r#type::{BitWidth, Type},
     unquote::UnquoteOperator,
 };
-use peepmatic_automata::State;
-use std::{
+use alloc::{vec, vec::Vec};
+use core::{
     convert::TryFrom,
     fmt::{self, Debug},
     mem,
     num::NonZeroU32,
 };
+use peepmatic_automata::State;
 
 /// A peephole optimizer instance that can apply a set of peephole
 /// optimizations to instructions.
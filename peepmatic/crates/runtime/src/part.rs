@@ -1,7 +1,7 @@
 //! Parts of instructions.
 
 use crate::{cc::ConditionCode, r#type::BitWidth};
-use std::fmt::Debug;
+use core::fmt::Debug;
 
 /// A constant value.
 ///
@@ -1,7 +1,7 @@
 //! Types.
 
+use core::{convert::TryFrom, fmt};
 use serde::{Deserialize, Serialize};
-use std::{convert::TryFrom, fmt};
 
 /// A bit width of a type.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -10,6 +10,7 @@
 
 #![deny(missing_docs)]
 #![deny(missing_debug_implementations)]
+#![no_std]
 
 #[macro_use]
 mod operator;
@@ -40,6 +40,7 @@ impl FuzzingEnv {
         Ok(Label::Resolved {
             name: format!("{:?}", bix),
             bix,
+            args: vec![],
         })
     }
 
@@ -384,6 +385,7 @@ impl Arbitrary for Func {
         let entry = Some(Label::Resolved {
             name: "entry".to_string(),
             bix: BlockIx::new(0),
+            args: vec![],
         });
 
         let mut insts = TypedIxVec::new();
@@ -420,6 +422,7 @@ impl Arbitrary for Func {
                 start: InstIx::new(start),
                 len,
                 estimated_execution_frequency: 0,
+                params: vec![],
             };
             blocks.push(block);
 
@@ -434,6 +437,13 @@ impl Arbitrary for Func {
             reftype_reg_start: Some(num_virtual_regs as u32),
             insns: insts,
             blocks,
+            block_frequencies: None,
+            reg_hints: Map::default(),
+            pinned_regs: vec![],
+            float_spill_slots: None,
+            report_reftyped_regs: false,
+            swaps: false,
+            split_edges: true,
         })
     }
 }
@@ -4,7 +4,10 @@ mod test_cases;
 mod test_framework;
 mod validator;
 
-use regalloc::{allocate_registers_with_opts, Algorithm, BacktrackingOptions, IRSnapshot, Options};
+use regalloc::{
+    allocate_registers_with_opts, Algorithm, BacktrackingOptions, ChordalOptions,
+    GraphColoringOptions, IRSnapshot, Options,
+};
 use test_framework::{make_universe, run_func, RunStage};
 use validator::check_results;
 
@@ -43,7 +46,9 @@ fn main() {
                 .short("a")
                 .takes_value(true)
                 .required(true)
-                .possible_values(&["bt", "lsra", "btc", "lsrac"])
+                .possible_values(&[
+                    "bt", "lsra", "color", "chordal", "btc", "lsrac", "colorc", "chordalc",
+                ])
                 .help("algorithm name"),
         )
         .arg(
@@ -51,6 +56,11 @@ fn main() {
             .short("q")
             .takes_value(false)
             .help("whether to run in quiet mode (i.e. not print the function's body before and after regalloc)"))
+        .arg(
+            clap::Arg::with_name("dump")
+            .short("d")
+            .takes_value(false)
+            .help("print a dump of the allocation's live ranges, spills, reloads and moves"))
         .arg(
             clap::Arg::with_name("snapshot")
             .short("s")
@@ -71,20 +81,61 @@ fn main() {
     }
 
     let quiet = matches.is_present("quiet");
+    let dump = matches.is_present("dump");
 
     // Find what the algorithm is going to be.
     let algorithm = matches.value_of("algorithm").unwrap();
     let opts = match algorithm {
         "bt" | "btc" => Options {
             run_checker: algorithm == "btc",
+            dump_allocation: dump,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
             algorithm: Algorithm::Backtracking(BacktrackingOptions {
                 request_block_annotations: true,
+                scratch_free: false,
+                memory_pins: vec![],
+                spill_cost_model: None,
+                fuel: None,
             }),
         },
         "lsra" | "lsrac" => Options {
             run_checker: algorithm == "lsrac",
+            dump_allocation: dump,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
             algorithm: Algorithm::LinearScan(Default::default()),
         },
+        "color" | "colorc" => Options {
+            run_checker: algorithm == "colorc",
+            dump_allocation: dump,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::GraphColoring(GraphColoringOptions {
+                request_block_annotations: true,
+            }),
+        },
+        "chordal" | "chordalc" => Options {
+            run_checker: algorithm == "chordalc",
+            dump_allocation: dump,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::Chordal(ChordalOptions {
+                request_block_annotations: true,
+            }),
+        },
         // Unreachable because of defined "possible_values".
         _ => unreachable!(),
     };
@@ -145,6 +196,10 @@ fn main() {
     // Update the function itself. This bridges the gap from the generic
     // interface to our specific test ISA.
     let mb_block_anns = result.block_annotations.clone();
+    if let Some(dump) = &result.allocation_dump {
+        println!("allocation dump:");
+        print!("{}", dump);
+    }
     func.update_from_alloc(result);
 
     if !quiet {
@@ -251,7 +306,10 @@ fn run_snapshot(path: &str, opts: Options, quiet: bool) {
 
 #[cfg(test)]
 mod test_utils {
-    use regalloc::{allocate_registers, AlgorithmWithDefaults, RegAllocError, RegAllocResult};
+    use regalloc::{
+        allocate_registers, AlgorithmWithDefaults, LinearScanOptions, Reg, RegAllocError,
+        RegAllocResult, RegClass, RegHint,
+    };
 
     use super::*;
     use crate::test_framework::Func;
@@ -271,8 +329,18 @@ mod test_utils {
 
         let opts = Options {
             run_checker,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
             algorithm: Algorithm::Backtracking(BacktrackingOptions {
                 request_block_annotations: false,
+                scratch_free: false,
+                memory_pins: vec![],
+                spill_cost_model: None,
+                fuel: None,
             }),
         };
 
@@ -303,6 +371,266 @@ mod test_utils {
         check_results(&before_regalloc_result, &after_regalloc_result);
     }
 
+    pub fn check_color(func_name: &str, num_gpr: usize, num_fpu: usize) {
+        let _ = pretty_env_logger::try_init();
+        let mut func = test_cases::find_func(func_name).unwrap();
+        let reg_universe = make_universe(num_gpr, num_fpu);
+
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::GraphColoring(Default::default()),
+        };
+
+        let mut encoded = IRSnapshot::from_function(&func, &reg_universe);
+        encoded
+            .allocate(opts.clone())
+            .expect("generic allocator failed!");
+
+        let before_regalloc_result = run_func(
+            &func,
+            "Before allocation",
+            &reg_universe,
+            RunStage::BeforeRegalloc,
+        );
+        let sri = func.get_stackmap_request();
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, sri.as_ref(), opts)
+            .unwrap_or_else(|err| {
+                panic!("allocation failed: {}", err);
+            });
+
+        func.update_from_alloc(result);
+        let after_regalloc_result = run_func(
+            &func,
+            "After allocation",
+            &reg_universe,
+            RunStage::AfterRegalloc,
+        );
+        check_results(&before_regalloc_result, &after_regalloc_result);
+    }
+
+    // Note: num_gpr/num_fpu: must include the scratch register.  No snapshot
+    // is taken, since snapshots don't record block parameters.
+    pub fn run_chordal(
+        func_name: &str,
+        num_gpr: usize,
+        num_fpu: usize,
+    ) -> Result<RegAllocResult<Func>, RegAllocError> {
+        let _ = pretty_env_logger::try_init();
+        let mut func = test_cases::find_func(func_name).unwrap();
+        let reg_universe = make_universe(num_gpr, num_fpu);
+        allocate_registers(
+            &mut func,
+            &reg_universe,
+            None,
+            AlgorithmWithDefaults::Chordal,
+        )
+    }
+
+    // Note: num_gpr/num_fpu: must include the scratch register.
+    pub fn check_chordal(func_name: &str, num_gpr: usize, num_fpu: usize) {
+        let _ = pretty_env_logger::try_init();
+        let mut func = test_cases::find_func(func_name).unwrap();
+        let reg_universe = make_universe(num_gpr, num_fpu);
+        let before_regalloc_result = run_func(
+            &func,
+            "Before allocation",
+            &reg_universe,
+            RunStage::BeforeRegalloc,
+        );
+        func.print("BEFORE", &None);
+
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::Chordal(Default::default()),
+        };
+        let sri = func.get_stackmap_request();
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, sri.as_ref(), opts)
+            .unwrap_or_else(|err| {
+                panic!("allocation failed: {}", err);
+            });
+        func.update_from_alloc(result);
+        func.print("AFTER", &None);
+
+        let after_regalloc_result = run_func(
+            &func,
+            "After allocation",
+            &reg_universe,
+            RunStage::AfterRegalloc,
+        );
+        check_results(&before_regalloc_result, &after_regalloc_result);
+    }
+
+    // Allocates with the checker enabled, pretending that the client has profile data that
+    // disagrees with the loop depths.  No snapshot is taken, since snapshots don't record block
+    // frequencies.
+    pub fn check_with_profile(
+        func_name: &str,
+        num_gpr: usize,
+        num_fpu: usize,
+        algorithm: Algorithm,
+    ) {
+        let mut func = test_cases::find_func(func_name).unwrap();
+        let num_blocks = func.blocks.len();
+        func.block_frequencies = Some((0..num_blocks).map(|bix| 1 + (bix * 37) % 1000).collect());
+        check_checked(func, num_gpr, num_fpu, algorithm);
+    }
+
+    // Allocates with the checker enabled, with allocation hints for all the virtual registers:
+    // a mix of real registers, some of them of the wrong class, and other virtual registers.
+    pub fn check_with_hints(func_name: &str, num_gpr: usize, num_fpu: usize, algorithm: Algorithm) {
+        let mut func = test_cases::find_func(func_name).unwrap();
+        let reg_universe = make_universe(num_gpr, num_fpu);
+        for index in 0..func.num_virtual_regs {
+            let vreg = Reg::new_virtual(RegClass::I32, index).to_virtual_reg();
+            let hint = if index % 2 == 0 {
+                let regs = &reg_universe.regs;
+                RegHint::Real(regs[index as usize % regs.len()].0)
+            } else {
+                let other = (index * 7) % func.num_virtual_regs;
+                RegHint::SameAs(Reg::new_virtual(RegClass::I32, other).to_virtual_reg())
+            };
+            func.reg_hints.insert(vreg, hint);
+        }
+        check_checked(func, num_gpr, num_fpu, algorithm);
+    }
+
+    // Allocates with the checker enabled, with float spill slots of the given size and
+    // alignment.  The interpreter checks that the float slots are aligned, and that no other
+    // value overlaps them.
+    pub fn check_with_float_spill_slots(
+        func_name: &str,
+        num_gpr: usize,
+        num_fpu: usize,
+        algorithm: Algorithm,
+        float_spill_slots: (u32, u32),
+    ) {
+        let mut func = test_cases::find_func(func_name).unwrap();
+        func.float_spill_slots = Some(float_spill_slots);
+        check_checked(func, num_gpr, num_fpu, algorithm);
+    }
+
+    // Allocates with the checker enabled, asking for the registers that hold references at the
+    // safepoints to be reported in the stackmaps.  Returns how many registers were reported, over
+    // all the safepoints.
+    pub fn check_with_stackmap_regs(
+        func_name: &str,
+        num_gpr: usize,
+        num_fpu: usize,
+        algorithm: Algorithm,
+    ) -> usize {
+        let _ = pretty_env_logger::try_init();
+        let mut func = test_cases::find_func(func_name).unwrap();
+        func.report_reftyped_regs = true;
+        let reg_universe = make_universe(num_gpr, num_fpu);
+        let before_regalloc_result = run_func(
+            &func,
+            "Before allocation",
+            &reg_universe,
+            RunStage::BeforeRegalloc,
+        );
+
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm,
+        };
+        let sri = func.get_stackmap_request().unwrap();
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, Some(&sri), opts)
+            .unwrap_or_else(|err| {
+                panic!("allocation failed: {}", err);
+            });
+        assert_eq!(result.stackmap_regs.len(), sri.safepoint_insns.len());
+        let num_regs = result.stackmap_regs.iter().map(|regs| regs.len()).sum();
+        func.update_from_alloc(result);
+
+        let after_regalloc_result = run_func(
+            &func,
+            "After allocation",
+            &reg_universe,
+            RunStage::AfterRegalloc,
+        );
+        check_results(&before_regalloc_result, &after_regalloc_result);
+        num_regs
+    }
+
+    // Allocates with the checker enabled, letting the allocator split the critical edges.
+    pub fn check_split_critical_edges(
+        func_name: &str,
+        num_gpr: usize,
+        num_fpu: usize,
+        algorithm: Algorithm,
+    ) {
+        let func = test_cases::find_func(func_name).unwrap();
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: true,
+            large_functions: false,
+            algorithm,
+        };
+        check_checked_with_opts(func, num_gpr, num_fpu, opts);
+    }
+
+    fn check_checked(func: Func, num_gpr: usize, num_fpu: usize, algorithm: Algorithm) {
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm,
+        };
+        check_checked_with_opts(func, num_gpr, num_fpu, opts);
+    }
+
+    fn check_checked_with_opts(mut func: Func, num_gpr: usize, num_fpu: usize, opts: Options) {
+        let _ = pretty_env_logger::try_init();
+        let reg_universe = make_universe(num_gpr, num_fpu);
+        let before_regalloc_result = run_func(
+            &func,
+            "Before allocation",
+            &reg_universe,
+            RunStage::BeforeRegalloc,
+        );
+
+        let sri = func.get_stackmap_request();
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, sri.as_ref(), opts)
+            .unwrap_or_else(|err| {
+                panic!("allocation failed: {}", err);
+            });
+        func.update_from_alloc(result);
+
+        let after_regalloc_result = run_func(
+            &func,
+            "After allocation",
+            &reg_universe,
+            RunStage::AfterRegalloc,
+        );
+        check_results(&before_regalloc_result, &after_regalloc_result);
+    }
+
     // Note: num_gpr/num_fpu: must include the scratch register.
     pub fn run_lsra(
         func_name: &str,
@@ -322,6 +650,16 @@ mod test_utils {
 
     // Note: num_gpr/num_fpu: must include the scratch register.
     pub fn check_lsra(func_name: &str, num_gpr: usize, num_fpu: usize) {
+        check_lsra_with_opts(func_name, num_gpr, num_fpu, Default::default());
+    }
+
+    // Note: num_gpr/num_fpu: must include the scratch register.
+    pub fn check_lsra_with_opts(
+        func_name: &str,
+        num_gpr: usize,
+        num_fpu: usize,
+        lsra_opts: LinearScanOptions,
+    ) {
         let _ = pretty_env_logger::try_init();
         let mut func = test_cases::find_func(func_name).unwrap();
         let reg_universe = make_universe(num_gpr, num_fpu);
@@ -335,7 +673,13 @@ mod test_utils {
 
         let opts = Options {
             run_checker: true,
-            algorithm: Algorithm::LinearScan(Default::default()),
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::LinearScan(lsra_opts),
         };
 
         let mut encoded = IRSnapshot::from_function(&func, &reg_universe);
@@ -360,6 +704,131 @@ mod test_utils {
         check_results(&before_regalloc_result, &after_regalloc_result);
     }
 
+    // Allocates with linear scan and the checker enabled, letting the allocator resolve move
+    // cycles with swaps.  Returns the number of moves the allocator inserted, counting each swap
+    // as one, along with the number of swaps among them.
+    pub fn check_lsra_with_swaps(
+        func_name: &str,
+        num_gpr: usize,
+        num_fpu: usize,
+        lsra_opts: LinearScanOptions,
+        swaps: bool,
+    ) -> (usize, usize) {
+        let _ = pretty_env_logger::try_init();
+        let mut func = test_cases::find_func(func_name).unwrap();
+        func.swaps = swaps;
+        let reg_universe = make_universe(num_gpr, num_fpu);
+        let before_regalloc_result = run_func(
+            &func,
+            "Before allocation",
+            &reg_universe,
+            RunStage::BeforeRegalloc,
+        );
+
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: true,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::LinearScan(lsra_opts),
+        };
+        let num_insns = func.insns.len() as usize;
+        let sri = func.get_stackmap_request();
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, sri.as_ref(), opts)
+            .unwrap_or_else(|err| {
+                panic!("allocation failed: {}", err);
+            });
+        func.update_from_alloc(result);
+
+        let after_regalloc_result = run_func(
+            &func,
+            "After allocation",
+            &reg_universe,
+            RunStage::AfterRegalloc,
+        );
+        check_results(&before_regalloc_result, &after_regalloc_result);
+
+        let num_swaps = func
+            .insns
+            .iter()
+            .filter(|inst| matches!(inst, crate::test_framework::Inst::Swap { .. }))
+            .count();
+        (func.insns.len() as usize - num_insns, num_swaps)
+    }
+
+    // Allocates with linear scan and the checker enabled, optionally letting the integer values
+    // spill to the floating-point registers.  Returns the number of spills and reloads in the
+    // allocated function, along with the number of copies from an integer register to a
+    // floating-point one.
+    pub fn check_lsra_spill_to_fpu(
+        func_name: &str,
+        num_gpr: usize,
+        num_fpu: usize,
+        spill_to_fpu: bool,
+    ) -> (usize, usize) {
+        let _ = pretty_env_logger::try_init();
+        let mut func = test_cases::find_func(func_name).unwrap();
+        let mut reg_universe = make_universe(num_gpr, num_fpu);
+        if spill_to_fpu {
+            reg_universe.allocable_by_class[RegClass::I32.rc_to_usize()]
+                .as_mut()
+                .unwrap()
+                .spill_class = Some(RegClass::F32);
+        }
+        let before_regalloc_result = run_func(
+            &func,
+            "Before allocation",
+            &reg_universe,
+            RunStage::BeforeRegalloc,
+        );
+
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: true,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::LinearScan(Default::default()),
+        };
+        let sri = func.get_stackmap_request();
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, sri.as_ref(), opts)
+            .unwrap_or_else(|err| {
+                panic!("allocation failed: {}", err);
+            });
+        func.update_from_alloc(result);
+
+        let after_regalloc_result = run_func(
+            &func,
+            "After allocation",
+            &reg_universe,
+            RunStage::AfterRegalloc,
+        );
+        check_results(&before_regalloc_result, &after_regalloc_result);
+
+        use crate::test_framework::Inst;
+        let num_spills = func
+            .insns
+            .iter()
+            .filter(|inst| matches!(inst, Inst::Spill { .. } | Inst::Reload { .. }))
+            .count();
+        let num_copies_to_fpu = func
+            .insns
+            .iter()
+            .filter(|inst| match inst {
+                Inst::Copy { dst, src } => {
+                    src.get_class() == RegClass::I32 && dst.get_class() == RegClass::F32
+                }
+                _ => false,
+            })
+            .count();
+        (num_spills, num_copies_to_fpu)
+    }
+
     // Note: num_gpr/num_fpu: must include the scratch register.
     pub fn loop_lsra(func_name: &str, mut num_gpr: usize) {
         let _ = pretty_env_logger::try_init();
@@ -378,6 +847,12 @@ mod test_utils {
 
         let opts = Options {
             run_checker: false,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
             algorithm: Algorithm::LinearScan(Default::default()),
         };
         loop {
@@ -427,6 +902,10 @@ fn bt_badness() {
     test_utils::check_bt("badness", 1, 0);
 }
 #[test]
+fn color_badness() {
+    test_utils::check_color("badness", 1, 0);
+}
+#[test]
 fn lsra_badness() {
     test_utils::check_lsra("badness", 2, 0);
 }
@@ -437,6 +916,10 @@ fn bt_straight_line() {
     test_utils::check_bt("straight_line", 1, 0);
 }
 #[test]
+fn color_straight_line() {
+    test_utils::check_color("straight_line", 1, 0);
+}
+#[test]
 fn lsra_straight_line() {
     test_utils::check_lsra("straight_line", 2, 0);
 }
@@ -447,6 +930,10 @@ fn bt_fill_then_sum() {
     test_utils::check_bt("fill_then_sum", 8, 8);
 }
 #[test]
+fn color_fill_then_sum() {
+    test_utils::check_color("fill_then_sum", 8, 8);
+}
+#[test]
 fn lsra_fill_then_sum() {
     assert!(test_utils::run_lsra("fill_then_sum", 1, 0).is_err());
     assert!(test_utils::run_lsra("fill_then_sum", 2, 0).is_err());
@@ -461,6 +948,10 @@ fn bt_ssort() {
     test_utils::check_bt("ssort", 8, 8);
 }
 #[test]
+fn color_ssort() {
+    test_utils::check_color("ssort", 8, 8);
+}
+#[test]
 fn btc_ssort() {
     test_utils::check_bt("ssort", 8, 8);
     //TODO reenable once #47 is fixed.
@@ -503,6 +994,10 @@ fn bt_3_loops() {
     test_utils::check_bt("3_loops", 8, 8);
 }
 #[test]
+fn color_3_loops() {
+    test_utils::check_color("3_loops", 8, 8);
+}
+#[test]
 fn lsra_3_loops() {
     assert!(test_utils::run_lsra("3_loops", 1, 0).is_err());
     assert!(test_utils::run_lsra("3_loops", 2, 0).is_err());
@@ -515,6 +1010,10 @@ fn bt_stmts() {
     test_utils::check_bt("stmts", 8, 8);
 }
 #[test]
+fn color_stmts() {
+    test_utils::check_color("stmts", 8, 8);
+}
+#[test]
 fn lsra_stmts() {
     assert!(test_utils::run_lsra("stmts", 1, 0).is_err());
     assert!(test_utils::run_lsra("stmts", 2, 0).is_err());
@@ -527,6 +1026,10 @@ fn bt_needs_splitting() {
     test_utils::check_bt("needs_splitting", 8, 8);
 }
 #[test]
+fn color_needs_splitting() {
+    test_utils::check_color("needs_splitting", 8, 8);
+}
+#[test]
 fn lsra_needs_splitting() {
     assert!(test_utils::run_lsra("needs_splitting", 1, 0).is_err());
     assert!(test_utils::run_lsra("needs_splitting", 2, 0).is_err());
@@ -539,6 +1042,10 @@ fn bt_needs_splitting2() {
     test_utils::check_bt("needs_splitting2", 8, 8);
 }
 #[test]
+fn color_needs_splitting2() {
+    test_utils::check_color("needs_splitting2", 8, 8);
+}
+#[test]
 fn lsra_needs_splitting2() {
     assert!(test_utils::run_lsra("needs_splitting2", 1, 0).is_err());
     assert!(test_utils::run_lsra("needs_splitting2", 2, 0).is_err());
@@ -553,6 +1060,10 @@ fn lsra_needs_splitting2() {
 fn bt_qsort() {
     test_utils::check_bt("qsort", 8, 8);
 }
+#[test]
+fn color_qsort() {
+    test_utils::check_color("qsort", 8, 8);
+}
 
 #[test]
 fn btc_qsort() {
@@ -629,6 +1140,70 @@ fn lsra_qsort_18() {
     test_utils::check_lsra("qsort", 18, 0);
 }
 
+#[test]
+fn lsra_qsort_split_strategies() {
+    use regalloc::{LinearScanOptions, OptimalSplitStrategy::*};
+    for &split_strategy in &[From, To, NextFrom, NextNextFrom, PrevTo, PrevPrevTo, Mid] {
+        for &(partial_split, partial_split_near_end) in
+            &[(false, false), (true, false), (true, true)]
+        {
+            let opts = LinearScanOptions {
+                split_strategy,
+                partial_split,
+                partial_split_near_end,
+                split_around_calls: false,
+                scratch_free: false,
+                stats: true,
+                large_stats: false,
+            };
+            test_utils::check_lsra_with_opts("qsort", 6, 0, opts);
+        }
+    }
+}
+
+#[test]
+fn lsra_stats() {
+    use regalloc::LinearScanOptions;
+
+    let reg_universe = make_universe(6, 0);
+    let func = test_cases::find_func("qsort").unwrap();
+    let allocate = |algorithm| {
+        let opts = Options {
+            run_checker: true,
+            algorithm,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+        };
+        allocate_registers_with_opts(&mut func.clone(), &reg_universe, None, opts)
+            .unwrap_or_else(|err| panic!("allocation failed: {}", err))
+    };
+
+    let stats = allocate(Algorithm::LinearScan(LinearScanOptions {
+        stats: true,
+        ..Default::default()
+    }))
+    .stats
+    .expect("statistics were requested");
+    assert!(stats.num_vregs < func.num_virtual_regs as usize);
+    assert!(stats.num_virtual_ranges > 0);
+    assert!(stats.peak_active > 0);
+    assert!(stats.num_try_allocate_reg >= stats.num_virtual_ranges);
+    assert!(stats.num_try_allocate_reg_success <= stats.num_try_allocate_reg);
+    assert!(stats.num_reg_splits_success <= stats.num_reg_splits);
+
+    // Only collected on request, and by the linear scan allocator.
+    assert!(allocate(Algorithm::LinearScan(Default::default()))
+        .stats
+        .is_none());
+    assert!(allocate(Algorithm::Backtracking(Default::default()))
+        .stats
+        .is_none());
+}
+
 #[test]
 fn lsra_critical_edge_invariant() {
     assert!(matches!(
@@ -639,6 +1214,22 @@ fn lsra_critical_edge_invariant() {
     ));
 }
 
+#[test]
+fn analysis_error_source() {
+    use std::error::Error;
+
+    let err = match test_utils::run_lsra("lsra_invariant_incorrect", 5, 0) {
+        Err(err) => err,
+        Ok(_) => panic!("allocation should fail"),
+    };
+    let source = err.source().expect("analysis errors have a source");
+    assert!(matches!(
+        source.downcast_ref::<regalloc::AnalysisError>(),
+        Some(regalloc::AnalysisError::LsraCriticalEdge { .. })
+    ));
+    assert_eq!(err.to_string(), format!("analysis error: {}", source));
+}
+
 #[test]
 fn lsra_fuzz_block_start_end() {
     test_utils::check_lsra("lsra_fuzz_block_start_end", 5, 0);
@@ -650,6 +1241,10 @@ fn bt_fill_then_sum_2a() {
     test_utils::check_bt("fill_then_sum_2a", 8, 8);
 }
 #[test]
+fn color_fill_then_sum_2a() {
+    test_utils::check_color("fill_then_sum_2a", 8, 8);
+}
+#[test]
 fn lsra_fill_then_sum_2a() {
     assert!(test_utils::run_lsra("fill_then_sum_2a", 1, 0).is_err());
     assert!(test_utils::run_lsra("fill_then_sum_2a", 2, 0).is_err());
@@ -663,6 +1258,10 @@ fn bt_ssort_2a() {
     test_utils::check_bt("ssort_2a", 8, 8);
 }
 #[test]
+fn color_ssort_2a() {
+    test_utils::check_color("ssort_2a", 8, 8);
+}
+#[test]
 fn lsra_2a_ssort() {
     assert!(test_utils::run_lsra("ssort_2a", 1, 0).is_err());
     assert!(test_utils::run_lsra("ssort_2a", 2, 0).is_err());
@@ -675,6 +1274,10 @@ fn bt_fp1() {
     test_utils::check_bt("fp1", 8, 8);
 }
 #[test]
+fn color_fp1() {
+    test_utils::check_color("fp1", 8, 8);
+}
+#[test]
 fn lsra_fp1() {
     assert!(test_utils::run_lsra("fp1", 2, 1).is_err());
     assert!(test_utils::run_lsra("fp1", 1, 2).is_err());
@@ -688,6 +1291,10 @@ fn bt_fp2() {
     test_utils::check_bt("fp2", 8, 8);
 }
 #[test]
+fn color_fp2() {
+    test_utils::check_color("fp2", 8, 8);
+}
+#[test]
 fn lsra_fp2() {
     for i in 3..8 {
         for j in 3..8 {
@@ -696,6 +1303,19 @@ fn lsra_fp2() {
     }
 }
 
+// Universes of more than 64 registers, where the FPUs of fp2 lie past the first word of the
+// allocators' register bitsets.
+#[test]
+fn bt_fp2_wide_universe() {
+    test_utils::check_bt("fp2", 100, 100);
+    test_utils::check_bt("qsort", 130, 3);
+}
+#[test]
+fn lsra_fp2_wide_universe() {
+    test_utils::check_lsra("fp2", 100, 100);
+    test_utils::check_lsra("qsort", 130, 3);
+}
+
 // Requires at least 1 GPR.
 #[test]
 fn lsra_simple_spill() {
@@ -719,6 +1339,17 @@ fn lsra_stmt_repeat() {
     test_utils::loop_lsra("stmt_repeat", 3)
 }
 
+// many_blocks has enough blocks for the analysis to process them in parallel.
+#[test]
+fn bt_many_blocks() {
+    assert!(test_cases::find_func("many_blocks").unwrap().blocks.len() > 1024);
+    test_utils::check_bt("many_blocks", 4, 0);
+}
+#[test]
+fn lsra_many_blocks() {
+    test_utils::check_lsra("many_blocks", 5, 0);
+}
+
 #[test]
 fn any_use_modified() {
     test_utils::check_bt("use_mod", 1, 0);
@@ -733,6 +1364,13 @@ fn lsra_multi_split() {
     test_utils::check_lsra("lsra_multi_split", 5, 0);
 }
 #[test]
+fn lsra_spill_slot_sharing() {
+    test_utils::check_lsra("lsra_spill_slot_sharing", 3, 0);
+    // The values spilled in the two phases share slots; without that, six slots are needed.
+    let result = test_utils::run_lsra("lsra_spill_slot_sharing", 3, 0).unwrap();
+    assert_eq!(result.num_spill_slots, 4);
+}
+#[test]
 fn lsra_double_succ() {
     test_utils::check_lsra("lsra_double_succ", 5, 2);
 }
@@ -766,9 +1404,17 @@ fn bt_analysis_fuzz1() {
     test_utils::check_bt("fuzz1", 3, 3);
 }
 #[test]
+fn color_analysis_fuzz1() {
+    test_utils::check_color("fuzz1", 3, 3);
+}
+#[test]
 fn bt_analysis_fuzz2() {
     test_utils::check_bt("fuzz2", 3, 3);
 }
+#[test]
+fn color_analysis_fuzz2() {
+    test_utils::check_color("fuzz2", 3, 3);
+}
 
 #[test]
 fn lsra_analysis_fuzz1() {
@@ -790,6 +1436,15 @@ fn bt_fuzz_stackmap() {
     test_utils::check_bt("fuzz_stackmap", 5, 5);
     test_utils::check_bt("fuzz_stackmap2", 5, 5);
     test_utils::check_bt("fuzz_stackmap3", 5, 5);
+    test_utils::check_bt("stackmap_loop", 4, 0);
+}
+#[test]
+fn color_fuzz_stackmap() {
+    test_utils::check_color("stackmap", 5, 5);
+    test_utils::check_color("fuzz_stackmap", 5, 5);
+    test_utils::check_color("fuzz_stackmap2", 5, 5);
+    test_utils::check_color("fuzz_stackmap3", 5, 5);
+    test_utils::check_color("stackmap_loop", 4, 0);
 }
 
 #[test]
@@ -798,4 +1453,1638 @@ fn lsra_fuzz_stackmap() {
     test_utils::check_lsra("fuzz_stackmap", 5, 5);
     test_utils::check_lsra("fuzz_stackmap2", 5, 5);
     test_utils::check_lsra("fuzz_stackmap3", 5, 5);
+    test_utils::check_lsra("stackmap_loop", 4, 0);
+}
+
+#[test]
+fn stackmap_regs() {
+    use regalloc::LinearScanOptions;
+    for algorithm in vec![
+        Algorithm::Backtracking(BacktrackingOptions::default()),
+        Algorithm::GraphColoring(GraphColoringOptions::default()),
+        Algorithm::LinearScan(LinearScanOptions::default()),
+    ] {
+        let mut num_regs = 0;
+        for &(func_name, num_gpr, num_fpu) in &[
+            ("stackmap", 5, 5),
+            ("fuzz_stackmap", 5, 5),
+            ("fuzz_stackmap2", 5, 5),
+            ("fuzz_stackmap3", 5, 5),
+            ("stackmap_loop", 4, 0),
+        ] {
+            num_regs += test_utils::check_with_stackmap_regs(
+                func_name,
+                num_gpr,
+                num_fpu,
+                algorithm.clone(),
+            );
+        }
+        // Some of the references stay in registers across the safepoints.
+        assert!(num_regs > 0, "no register reported by {:?}", algorithm);
+    }
+}
+
+#[test]
+fn chordal_ssa_fib() {
+    test_utils::check_chordal("ssa_fib", 8, 0);
+    test_utils::check_chordal("ssa_fib", 4, 0);
+}
+#[test]
+fn chordal_ssa_swap() {
+    test_utils::check_chordal("ssa_swap", 8, 0);
+    test_utils::check_chordal("ssa_swap", 3, 0);
+}
+#[test]
+fn chordal_ssa_diamond() {
+    test_utils::check_chordal("ssa_diamond", 8, 0);
+    test_utils::check_chordal("ssa_diamond", 3, 0);
+}
+#[test]
+fn chordal_ssa_rotate() {
+    for num_gpr in 4..10 {
+        test_utils::check_chordal("ssa_rotate", num_gpr, 0);
+    }
+}
+
+#[test]
+fn chordal_requires_ssa() {
+    assert!(matches!(
+        test_utils::run_chordal("ssort", 8, 8),
+        Err(regalloc::RegAllocError::Analysis(
+            regalloc::AnalysisError::NotInSsaForm(..)
+        ))
+    ));
+}
+
+#[test]
+fn profile_block_frequencies() {
+    for num_gpr in 4..8 {
+        test_utils::check_with_profile(
+            "3_loops",
+            num_gpr,
+            0,
+            Algorithm::Backtracking(Default::default()),
+        );
+        test_utils::check_with_profile(
+            "3_loops",
+            num_gpr,
+            0,
+            Algorithm::LinearScan(Default::default()),
+        );
+        test_utils::check_with_profile(
+            "3_loops",
+            num_gpr,
+            0,
+            Algorithm::GraphColoring(Default::default()),
+        );
+        test_utils::check_with_profile(
+            "qsort",
+            num_gpr,
+            0,
+            Algorithm::LinearScan(Default::default()),
+        );
+    }
+}
+
+#[test]
+fn reg_hints() {
+    for num_gpr in 4..8 {
+        for func_name in &["3_loops", "qsort", "stmts", "fp2"] {
+            test_utils::check_with_hints(
+                func_name,
+                num_gpr,
+                4,
+                Algorithm::Backtracking(Default::default()),
+            );
+            test_utils::check_with_hints(
+                func_name,
+                num_gpr,
+                4,
+                Algorithm::LinearScan(Default::default()),
+            );
+        }
+    }
+}
+
+#[test]
+fn reg_hints_are_honoured() {
+    use regalloc::{RegClass, RegHint};
+    use test_framework::{i_add, i_finish, i_imm, i_print_i, Func, Inst, RI_R};
+
+    let algorithms = vec![
+        Algorithm::Backtracking(Default::default()),
+        Algorithm::LinearScan(Default::default()),
+    ];
+    for algorithm in algorithms {
+        let mut func = Func::new("hints");
+        func.set_entry("Lstart");
+        let x = func.new_virtual_reg(RegClass::I32);
+        let y = func.new_virtual_reg(RegClass::I32);
+        let z = func.new_virtual_reg(RegClass::I32);
+        func.block(
+            "Lstart",
+            vec![
+                i_imm(x, 1),
+                i_imm(y, 2),
+                i_add(z, x, RI_R(y)),
+                i_print_i(z),
+                i_finish(None),
+            ],
+        );
+        func.finish();
+
+        let reg_universe = make_universe(6, 0);
+        let r1 = reg_universe.regs[1].0;
+        let r3 = reg_universe.regs[3].0;
+        func.reg_hints.insert(x.to_virtual_reg(), RegHint::Real(r3));
+        func.reg_hints.insert(y.to_virtual_reg(), RegHint::Real(r1));
+        func.reg_hints
+            .insert(z.to_virtual_reg(), RegHint::SameAs(x.to_virtual_reg()));
+
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm,
+        };
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts).unwrap();
+        func.update_from_alloc(result);
+
+        let dsts: Vec<_> = func
+            .insns
+            .iter()
+            .filter_map(|inst| match inst {
+                Inst::Imm { dst, .. } | Inst::BinOp { dst, .. } => Some(*dst),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(dsts, vec![r3.to_reg(), r1.to_reg(), r3.to_reg()]);
+    }
+}
+
+#[test]
+fn fixed_reg_operands() {
+    use regalloc::RegClass;
+    use test_framework::{i_add_fixed, i_finish, i_imm, i_print_i, Func, Inst};
+
+    let algorithms = vec![
+        Algorithm::Backtracking(Default::default()),
+        Algorithm::LinearScan(Default::default()),
+    ];
+    for algorithm in algorithms {
+        let reg_universe = make_universe(6, 0);
+        let fixed = reg_universe.regs[2].0;
+
+        let mut func = Func::new("fixed");
+        func.set_entry("Lstart");
+        let x = func.new_virtual_reg(RegClass::I32);
+        let y = func.new_virtual_reg(RegClass::I32);
+        let z = func.new_virtual_reg(RegClass::I32);
+        let w = func.new_virtual_reg(RegClass::I32);
+        func.block(
+            "Lstart",
+            vec![
+                i_imm(x, 10),
+                i_imm(y, 3),
+                i_add_fixed(z, x, y, fixed),
+                i_add_fixed(w, z, y, fixed),
+                i_print_i(w),
+                // `x` is still live when `z` overwrites its register, so it needs a copy.
+                i_print_i(x),
+                i_finish(None),
+            ],
+        );
+        func.finish();
+
+        let before_regalloc_result = run_func(
+            &func,
+            "Before allocation",
+            &reg_universe,
+            RunStage::BeforeRegalloc,
+        );
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm,
+        };
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts).unwrap();
+        func.update_from_alloc(result);
+        // The interpreter checks that the constrained operands ended up in `fixed`.
+        let after_regalloc_result = run_func(
+            &func,
+            "After allocation",
+            &reg_universe,
+            RunStage::AfterRegalloc,
+        );
+        check_results(&before_regalloc_result, &after_regalloc_result);
+
+        let num_copies = func
+            .insns
+            .iter()
+            .filter(|inst| matches!(inst, Inst::Copy { .. }))
+            .count();
+        assert_eq!(num_copies, 1);
+    }
+}
+
+#[test]
+fn bt_scratch_free_swap() {
+    use regalloc::RegClass;
+    use test_framework::{i_add_fixed, i_finish, i_imm, i_print_i, Func, Inst};
+
+    // Two registers, neither of which is reserved as a scratch register.
+    let reg_universe = make_universe(2, 0);
+    let r0 = reg_universe.regs[0].0;
+
+    // After `c` is defined, `c` is in r0 and `b` in the other register; `d` then needs `b` in r0
+    // while `c` stays live, so the two values swap registers with no free register to go through.
+    let mut func = Func::new("bt_scratch_free_swap");
+    func.set_entry("Lstart");
+    let a = func.new_virtual_reg(RegClass::I32);
+    let b = func.new_virtual_reg(RegClass::I32);
+    let c = func.new_virtual_reg(RegClass::I32);
+    let d = func.new_virtual_reg(RegClass::I32);
+    func.block(
+        "Lstart",
+        vec![
+            i_imm(a, 1),
+            i_imm(b, 2),
+            i_add_fixed(c, a, b, r0),
+            i_add_fixed(d, b, c, r0),
+            i_print_i(c),
+            i_print_i(d),
+            i_finish(None),
+        ],
+    );
+    func.finish();
+
+    let before_regalloc_result = run_func(
+        &func,
+        "Before allocation",
+        &reg_universe,
+        RunStage::BeforeRegalloc,
+    );
+
+    // Without fuel, linear scan does the allocation instead, and must not take a scratch register
+    // either.
+    for &fuel in &[None, Some(0)] {
+        let mut func = func.clone();
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::Backtracking(BacktrackingOptions {
+                scratch_free: true,
+                fuel,
+                ..Default::default()
+            }),
+        };
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts)
+            .unwrap_or_else(|err| panic!("allocation failed: {}", err));
+        assert!(result.num_spill_slots > 0);
+        func.update_from_alloc(result);
+        let after_regalloc_result = run_func(
+            &func,
+            "After allocation",
+            &reg_universe,
+            RunStage::AfterRegalloc,
+        );
+        check_results(&before_regalloc_result, &after_regalloc_result);
+
+        // The swap goes through the stack.
+        let spills = func
+            .insns
+            .iter()
+            .filter(|inst| matches!(inst, Inst::Spill { .. }))
+            .count();
+        let reloads = func
+            .insns
+            .iter()
+            .filter(|inst| matches!(inst, Inst::Reload { .. }))
+            .count();
+        assert!(spills > 0 && reloads > 0);
+    }
+}
+
+#[test]
+fn tied_operands() {
+    use regalloc::RegClass;
+    use test_framework::{i_add_tied, i_finish, i_imm, i_print_i, Func, Inst};
+
+    let algorithms = vec![
+        Algorithm::Backtracking(Default::default()),
+        Algorithm::LinearScan(Default::default()),
+    ];
+    for algorithm in algorithms {
+        let reg_universe = make_universe(6, 0);
+
+        let mut func = Func::new("tied");
+        func.set_entry("Lstart");
+        let x = func.new_virtual_reg(RegClass::I32);
+        let y = func.new_virtual_reg(RegClass::I32);
+        let z = func.new_virtual_reg(RegClass::I32);
+        let w = func.new_virtual_reg(RegClass::I32);
+        func.block(
+            "Lstart",
+            vec![
+                i_imm(x, 10),
+                i_imm(y, 3),
+                // `x` dies here, so `z` can take over its register.
+                i_add_tied(z, x, y),
+                // `z` is still live afterwards, so it needs a copy.
+                i_add_tied(w, z, y),
+                i_print_i(w),
+                i_print_i(z),
+                i_finish(None),
+            ],
+        );
+        func.finish();
+
+        let before_regalloc_result = run_func(
+            &func,
+            "Before allocation",
+            &reg_universe,
+            RunStage::BeforeRegalloc,
+        );
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm,
+        };
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts).unwrap();
+        func.update_from_alloc(result);
+        // The interpreter checks that the tied operands ended up in the same register.
+        let after_regalloc_result = run_func(
+            &func,
+            "After allocation",
+            &reg_universe,
+            RunStage::AfterRegalloc,
+        );
+        check_results(&before_regalloc_result, &after_regalloc_result);
+
+        let num_copies = func
+            .insns
+            .iter()
+            .filter(|inst| matches!(inst, Inst::Copy { .. }))
+            .count();
+        assert_eq!(num_copies, 1);
+    }
+}
+
+#[test]
+fn pinned_regs() {
+    use regalloc::RegClass;
+    use test_framework::{
+        i_add, i_cmp_lt, i_finish, i_goto, i_goto_ctf, i_imm, i_mul, i_print_i, Func, Inst, RI_I,
+        RI_R,
+    };
+
+    let algorithms = vec![
+        Algorithm::Backtracking(Default::default()),
+        Algorithm::LinearScan(Default::default()),
+    ];
+    for algorithm in algorithms {
+        let reg_universe = make_universe(5, 0);
+        let pinned = reg_universe.regs[1].0;
+
+        // `ctx` stays live through the loop, which needs every other register.
+        let mut func = Func::new("pinned");
+        func.set_entry("Lstart");
+        let ctx = func.new_virtual_reg(RegClass::I32);
+        let i = func.new_virtual_reg(RegClass::I32);
+        let sum = func.new_virtual_reg(RegClass::I32);
+        let t = func.new_virtual_reg(RegClass::I32);
+        let c = func.new_virtual_reg(RegClass::I32);
+        func.block(
+            "Lstart",
+            vec![i_imm(ctx, 7), i_imm(i, 0), i_imm(sum, 0), i_goto("Lloop")],
+        );
+        func.block(
+            "Lloop",
+            vec![i_cmp_lt(c, i, RI_I(10)), i_goto_ctf(c, "Lbody", "Lexit")],
+        );
+        func.block(
+            "Lbody",
+            vec![
+                i_mul(t, i, RI_R(ctx)),
+                i_add(sum, sum, RI_R(t)),
+                i_add(i, i, RI_I(1)),
+                i_goto("Lloop"),
+            ],
+        );
+        func.block(
+            "Lexit",
+            vec![i_print_i(sum), i_print_i(ctx), i_finish(None)],
+        );
+        func.finish();
+        func.pinned_regs.push((ctx.to_virtual_reg(), pinned));
+
+        let before_regalloc_result = run_func(
+            &func,
+            "Before allocation",
+            &reg_universe,
+            RunStage::BeforeRegalloc,
+        );
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm,
+        };
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts).unwrap();
+        func.update_from_alloc(result);
+        let after_regalloc_result = run_func(
+            &func,
+            "After allocation",
+            &reg_universe,
+            RunStage::AfterRegalloc,
+        );
+        check_results(&before_regalloc_result, &after_regalloc_result);
+
+        // `ctx` is defined straight into its register, and nothing ever moves it.
+        assert!(matches!(
+            func.insns[regalloc::InstIx::new(0)],
+            Inst::Imm { dst, .. } if dst == pinned.to_reg()
+        ));
+        assert!(func.insns.iter().all(|inst| match inst {
+            Inst::Spill { src, .. } => *src != pinned,
+            Inst::Copy { src, .. } => *src != pinned.to_reg(),
+            _ => true,
+        }));
+    }
+}
+
+#[test]
+fn pinned_reg_conflicts() {
+    use regalloc::{AnalysisError, RegAllocError, RegClass};
+    use test_framework::{i_add, i_add_fixed, i_finish, i_imm, i_print_i, Func, RI_R};
+
+    let reg_universe = make_universe(6, 0);
+    let pinned = reg_universe.regs[2].0;
+    let allocate = |func: &mut Func| {
+        regalloc::allocate_registers(
+            func,
+            &reg_universe,
+            None,
+            regalloc::AlgorithmWithDefaults::Backtracking,
+        )
+    };
+
+    // The fixed operands of the second instruction overwrite `ctx` while it is still live.
+    let mut func = Func::new("fixed_conflict");
+    func.set_entry("Lstart");
+    let ctx = func.new_virtual_reg(RegClass::I32);
+    let x = func.new_virtual_reg(RegClass::I32);
+    let y = func.new_virtual_reg(RegClass::I32);
+    func.block(
+        "Lstart",
+        vec![
+            i_imm(ctx, 1),
+            i_imm(x, 2),
+            i_add_fixed(y, x, x, pinned),
+            i_print_i(y),
+            i_print_i(ctx),
+            i_finish(None),
+        ],
+    );
+    func.finish();
+    func.pinned_regs.push((ctx.to_virtual_reg(), pinned));
+    match allocate(&mut func) {
+        Err(RegAllocError::Analysis(AnalysisError::PinnedRegConflict { vreg, rreg })) => {
+            assert_eq!(vreg, ctx.to_virtual_reg());
+            assert_eq!(rreg, pinned);
+        }
+        _ => panic!("expected a pinned register conflict"),
+    }
+
+    // Two virtual registers pinned to the same register may not be live at the same time...
+    let mut func = Func::new("overlap");
+    func.set_entry("Lstart");
+    let x = func.new_virtual_reg(RegClass::I32);
+    let y = func.new_virtual_reg(RegClass::I32);
+    let z = func.new_virtual_reg(RegClass::I32);
+    func.block(
+        "Lstart",
+        vec![
+            i_imm(x, 1),
+            i_imm(y, 2),
+            i_add(z, x, RI_R(y)),
+            i_print_i(z),
+            i_finish(None),
+        ],
+    );
+    func.finish();
+    func.pinned_regs.push((x.to_virtual_reg(), pinned));
+    func.pinned_regs.push((y.to_virtual_reg(), pinned));
+    assert!(matches!(
+        allocate(&mut func),
+        Err(RegAllocError::Analysis(
+            AnalysisError::PinnedRegConflict { .. }
+        ))
+    ));
+
+    // ... but they may if their live ranges only touch.
+    func.pinned_regs = vec![(x.to_virtual_reg(), pinned), (z.to_virtual_reg(), pinned)];
+    assert!(allocate(&mut func).is_ok());
+}
+
+#[test]
+fn block_frequencies_mismatch() {
+    let mut func = test_cases::find_func("3_loops").unwrap();
+    func.block_frequencies = Some(vec![1]);
+    assert!(matches!(
+        regalloc::allocate_registers(
+            &mut func,
+            &make_universe(8, 0),
+            None,
+            regalloc::AlgorithmWithDefaults::Backtracking,
+        ),
+        Err(regalloc::RegAllocError::Analysis(
+            regalloc::AnalysisError::BlockFrequenciesMismatch
+        ))
+    ));
+}
+
+#[test]
+fn block_params_require_chordal() {
+    assert!(matches!(
+        test_utils::run_lsra("ssa_fib", 8, 0),
+        Err(regalloc::RegAllocError::Analysis(
+            regalloc::AnalysisError::UnsupportedBlockParams(..)
+        ))
+    ));
+}
+
+#[test]
+fn extra_reg_classes() {
+    use regalloc::{Reg, RegClass, RegClassInfo};
+
+    // Mask registers, which the test functions don't use, after the integer and float ones.
+    let mut reg_universe = make_universe(6, 6);
+    let first = reg_universe.regs.len();
+    for i in 0..4 {
+        let reg = Reg::new_real(RegClass::MASK, /*enc=*/ 0, (first + i) as u8).to_real_reg();
+        reg_universe.regs.push((reg, format!("K{}", i)));
+    }
+    let last = reg_universe.regs.len() - 1;
+    reg_universe.allocable = reg_universe.regs.len();
+    reg_universe
+        .allocable_by_class
+        .resize(RegClass::MASK.rc_to_usize() + 1, None);
+    reg_universe.allocable_by_class[RegClass::MASK.rc_to_usize()] = Some(RegClassInfo {
+        first,
+        last,
+        suggested_scratch: Some(last),
+        spill_class: None,
+    });
+    reg_universe.check_is_sane();
+
+    // A universe that doesn't describe the F32 class at all.
+    let mut int_universe = make_universe(6, 0);
+    int_universe.allocable_by_class.truncate(1);
+    int_universe.check_is_sane();
+
+    let algorithms = vec![
+        Algorithm::Backtracking(Default::default()),
+        Algorithm::LinearScan(Default::default()),
+        Algorithm::GraphColoring(Default::default()),
+    ];
+    for algorithm in algorithms {
+        let mut func = test_cases::find_func("fp2").unwrap();
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: algorithm.clone(),
+        };
+        allocate_registers_with_opts(&mut func, &reg_universe, None, opts).unwrap();
+
+        let mut func = test_cases::find_func("fp2").unwrap();
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm,
+        };
+        assert!(matches!(
+            allocate_registers_with_opts(&mut func, &int_universe, None, opts),
+            Err(regalloc::RegAllocError::OutOfRegisters(RegClass::F32))
+        ));
+    }
+}
+
+#[test]
+fn allocation_dumps() {
+    let reg_universe = make_universe(4, 0);
+    let algorithms = vec![
+        Algorithm::Backtracking(Default::default()),
+        Algorithm::LinearScan(Default::default()),
+        Algorithm::GraphColoring(Default::default()),
+    ];
+    for algorithm in algorithms {
+        let allocate = |dump_allocation| {
+            let mut func = test_cases::find_func("qsort").unwrap();
+            let num_blocks = func.blocks.len() as usize;
+            let opts = Options {
+                run_checker: true,
+                dump_allocation,
+                annotate: false,
+                deterministic: false,
+                report_moves: false,
+                split_critical_edges: false,
+                large_functions: false,
+                algorithm: algorithm.clone(),
+            };
+            let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts)
+                .unwrap_or_else(|err| panic!("allocation failed: {}", err));
+            (num_blocks, result.allocation_dump)
+        };
+
+        assert!(allocate(false).1.is_none());
+        let (num_blocks, dump) = allocate(true);
+        let dump = dump.unwrap();
+
+        // Every block is described, with the live ranges in it and the inserted instructions.
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0], "block b0 [i0 .. i6]");
+        assert_eq!(
+            lines.iter().filter(|l| l.starts_with("block b")).count(),
+            num_blocks
+        );
+        assert!(lines.iter().any(|l| l.starts_with("  range v")));
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("  at ") && l.contains(": spill S")));
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("  at ") && l.contains(": reload ")));
+
+        // The same allocation is dumped identically.
+        assert_eq!(allocate(true).1.unwrap(), dump);
+    }
+}
+
+#[test]
+fn block_annotations() {
+    let reg_universe = make_universe(4, 0);
+    let algorithms = vec![
+        Algorithm::Backtracking(Default::default()),
+        Algorithm::LinearScan(Default::default()),
+        Algorithm::GraphColoring(Default::default()),
+    ];
+    for algorithm in algorithms {
+        let allocate = |annotate| {
+            let mut func = test_cases::find_func("qsort").unwrap();
+            let num_blocks = func.blocks.len();
+            let opts = Options {
+                run_checker: true,
+                dump_allocation: true,
+                annotate,
+                deterministic: true,
+                report_moves: false,
+                split_critical_edges: false,
+                large_functions: false,
+                algorithm: algorithm.clone(),
+            };
+            let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts)
+                .unwrap_or_else(|err| panic!("allocation failed: {}", err));
+            (num_blocks, result.block_annotations, result.allocation_dump)
+        };
+
+        assert!(allocate(false).1.is_none());
+        let (num_blocks, anns, dump) = allocate(true);
+        let anns = anns.unwrap();
+        assert_eq!(anns.len(), num_blocks);
+
+        // Each block is framed by its live-in and live-out sets, around the same description as
+        // in the allocation dump.
+        let dump = dump.unwrap();
+        let mut headers = dump.lines().filter(|l| l.starts_with("block "));
+        let mut rebuilt = String::new();
+        for block_anns in anns.iter() {
+            let (live_in, rest) = block_anns.split_first().unwrap();
+            let (live_out, desc) = rest.split_last().unwrap();
+            assert!(live_in.starts_with("live in: "));
+            assert!(live_out.starts_with("live out: "));
+            rebuilt += headers.next().unwrap();
+            rebuilt.push('\n');
+            for line in desc {
+                rebuilt += "  ";
+                rebuilt += line;
+                rebuilt.push('\n');
+            }
+        }
+        assert_eq!(rebuilt, dump);
+
+        // The entry block of qsort has nothing live in, and its loop carries values around.
+        assert_eq!(anns[regalloc::BlockIx::new(0)][0], "live in: ");
+        assert!(anns.iter().any(|block_anns| block_anns[0].contains('v')));
+    }
+}
+
+#[test]
+fn deterministic_allocations() {
+    // Few enough real registers to require evictions and spills.
+    let reg_universe = make_universe(4, 0);
+    let cases = vec![
+        (Algorithm::Backtracking(Default::default()), "qsort"),
+        (Algorithm::LinearScan(Default::default()), "qsort"),
+        (Algorithm::GraphColoring(Default::default()), "qsort"),
+        (Algorithm::Chordal(Default::default()), "ssa_fib"),
+    ];
+    for (algorithm, func_name) in cases {
+        let allocate = |deterministic| {
+            let mut func = test_cases::find_func(func_name).unwrap();
+            let opts = Options {
+                run_checker: true,
+                dump_allocation: true,
+                annotate: false,
+                deterministic,
+                report_moves: false,
+                split_critical_edges: false,
+                large_functions: false,
+                algorithm: algorithm.clone(),
+            };
+            let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts)
+                .unwrap_or_else(|err| panic!("allocation failed: {}", err));
+            result.allocation_dump.unwrap()
+        };
+
+        allocate(false);
+        let dump = allocate(true);
+        assert_eq!(allocate(true), dump);
+    }
+}
+
+#[test]
+fn register_pressure() {
+    use regalloc::{analyze_pressure, RegClass};
+
+    let func = test_cases::find_func("ssa_fib").unwrap();
+    let reg_universe = make_universe(4, 4);
+    let pressure = analyze_pressure(&func, &reg_universe).unwrap();
+    let max_live = |rc| pressure.iter().map(|p| p.get(rc)).collect::<Vec<_>>();
+
+    // The loop header also holds the comparison result along with `n` and its three parameters.
+    assert_eq!(max_live(RegClass::I32), [4, 5, 4, 1]);
+    assert_eq!(max_live(RegClass::F32), [0, 0, 0, 0]);
+}
+
+#[test]
+fn update_analysis() {
+    use regalloc::analysis_main::{run_analysis, update_analysis};
+    use regalloc::{AlgorithmWithDefaults, AnalysisError, Reg, RegClass};
+    use test_framework::{i_finish, i_goto, i_goto_ctf, i_imm, i_print_i, Func, Inst};
+
+    // A diamond, to which `extra` adds instructions in the entry and left blocks, given a spare
+    // register, and where `right_ctf` makes the right block branch to the left one as well.
+    let make = |extra: &dyn Fn(Reg) -> (Vec<Inst>, Vec<Inst>), right_ctf: bool| {
+        let mut func = Func::new("update_analysis");
+        func.set_entry("Lstart");
+        let x = func.new_virtual_reg(RegClass::I32);
+        let c = func.new_virtual_reg(RegClass::I32);
+        let y = func.new_virtual_reg(RegClass::I32);
+        let (mut entry, extra_left) = extra(y);
+        entry.extend(vec![
+            i_imm(x, 1),
+            i_imm(c, 0),
+            i_goto_ctf(c, "Lleft", "Lright"),
+        ]);
+        func.block("Lstart", entry);
+        let mut left = vec![i_print_i(x)];
+        left.extend(extra_left);
+        left.push(i_goto("Lend"));
+        func.block("Lleft", left);
+        let right = if right_ctf {
+            i_goto_ctf(c, "Lend", "Lleft")
+        } else {
+            i_goto("Lend")
+        };
+        func.block("Lright", vec![right]);
+        func.block("Lend", vec![i_finish(None)]);
+        func.finish();
+        func
+    };
+
+    let reg_universe = make_universe(4, 0);
+    let no_vregs = vec![];
+    let analyze = |func: &Func| {
+        run_analysis(
+            func,
+            &reg_universe,
+            AlgorithmWithDefaults::Backtracking,
+            false,
+            RegClass::I64,
+            &no_vregs,
+            true,
+            false,
+        )
+    };
+    let update = |func: &Func, prev| {
+        update_analysis(
+            func,
+            prev,
+            &reg_universe,
+            AlgorithmWithDefaults::Backtracking,
+            false,
+            RegClass::I64,
+            &no_vregs,
+            true,
+            false,
+        )
+    };
+
+    // Instructions inserted into the blocks keep the control flow analysis, but their registers
+    // are taken into account.
+    let info = analyze(&make(&|_| (vec![], vec![]), false)).unwrap();
+    let func = make(&|y| (vec![], vec![i_imm(y, 2), i_print_i(y)]), false);
+    let info = update(&func, info).unwrap();
+    let func = make(&|y| (vec![i_print_i(y)], vec![]), false);
+    match update(&func, info) {
+        Err(AnalysisError::EntryLiveinValues(regs)) => assert_eq!(regs.len(), 1),
+        _ => panic!("use of an undefined register accepted"),
+    }
+
+    // A new edge redoes the control flow analysis.
+    let info = analyze(&make(&|_| (vec![], vec![]), false)).unwrap();
+    match update(&make(&|_| (vec![], vec![]), true), info) {
+        Err(AnalysisError::CriticalEdge { .. }) => {}
+        _ => panic!("critical edge accepted"),
+    }
+}
+
+#[test]
+fn spill_cost_model() {
+    use regalloc::{InstIx, RegClass, SpillCostModel, VirtualReg};
+    use std::sync::Arc;
+    use test_framework::{i_finish, i_imm, i_print_i, Func, Inst};
+
+    /// Makes one virtual register too expensive to spill.
+    struct ExpensiveVreg(VirtualReg);
+    impl SpillCostModel for ExpensiveVreg {
+        fn mention_cost(&self, vreg: VirtualReg, _iix: InstIx, frequency: u32) -> u32 {
+            if vreg == self.0 {
+                1_000_000
+            } else {
+                frequency
+            }
+        }
+    }
+
+    // Returns which of `x` and `y` got spilled, when `expensive` of them is too expensive to spill.
+    let allocate = |expensive: Option<usize>| {
+        // `x` and `y` are equally cheap to spill around `a`.
+        let mut func = Func::new("spill_cost_model");
+        func.set_entry("Lstart");
+        let x = func.new_virtual_reg(RegClass::I32);
+        let y = func.new_virtual_reg(RegClass::I32);
+        let a = func.new_virtual_reg(RegClass::I32);
+        func.block(
+            "Lstart",
+            vec![
+                i_imm(x, 1),
+                i_imm(y, 2),
+                i_imm(a, 3),
+                i_print_i(a),
+                i_print_i(x),
+                i_print_i(y),
+                i_finish(None),
+            ],
+        );
+        func.finish();
+        let vregs = [x.to_virtual_reg(), y.to_virtual_reg()];
+        let spill_cost_model = expensive
+            .map(|i| Arc::new(ExpensiveVreg(vregs[i])) as Arc<dyn SpillCostModel + Send + Sync>);
+
+        let reg_universe = make_universe(2, 0);
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::Backtracking(BacktrackingOptions {
+                spill_cost_model,
+                ..Default::default()
+            }),
+        };
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts)
+            .unwrap_or_else(|err| panic!("allocation failed: {}", err));
+        func.update_from_alloc(result);
+
+        // The spilled one is stored right after its definition.
+        let imms: Vec<bool> = func
+            .insns
+            .iter()
+            .zip(func.insns.iter().skip(1))
+            .filter_map(|insns| match insns {
+                (Inst::Imm { .. }, Inst::Spill { .. }) => Some(true),
+                (Inst::Imm { .. }, _) => Some(false),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(imms.iter().filter(|&&spilled| spilled).count(), 1);
+        imms.iter().position(|&spilled| spilled).unwrap()
+    };
+
+    let spilled = allocate(None);
+    assert_eq!(allocate(Some(spilled)), 1 - spilled);
+    assert_eq!(allocate(Some(1 - spilled)), spilled);
+}
+
+#[test]
+fn backtracking_fuel() {
+    use regalloc::{InstIx, MemoryPin, RegAllocError};
+
+    // Few enough real registers to require evictions and spills.
+    let reg_universe = make_universe(4, 0);
+    let allocate = |fuel, memory_pins| {
+        let mut func = test_cases::find_func("qsort").unwrap();
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: true,
+            annotate: false,
+            deterministic: true,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::Backtracking(BacktrackingOptions {
+                fuel,
+                memory_pins,
+                ..Default::default()
+            }),
+        };
+        allocate_registers_with_opts(&mut func, &reg_universe, None, opts)
+            .map(|result| result.allocation_dump.unwrap())
+    };
+
+    // With enough fuel, the backtracking allocator does the allocation; otherwise linear scan
+    // takes over.
+    let bt_dump = allocate(None, vec![]).unwrap();
+    assert_eq!(allocate(Some(100_000), vec![]).unwrap(), bt_dump);
+    assert_ne!(allocate(Some(10), vec![]).unwrap(), bt_dump);
+
+    // Only the backtracking allocator can honour memory pins.
+    let pins = vec![MemoryPin {
+        iix: InstIx::new(0),
+        vregs: vec![],
+    }];
+    assert!(allocate(Some(100_000), pins.clone()).is_ok());
+    match allocate(Some(10), pins) {
+        Err(RegAllocError::OutOfFuel) => {}
+        res => panic!("unexpected result: {:?}", res.map(|_| ())),
+    }
+}
+
+#[test]
+fn memory_pin_slots() {
+    use regalloc::{compare_allocators, InstIx, MemoryPin, RegAllocError, RegClass};
+    use test_framework::{i_finish, i_imm, i_print_i, Func, Inst};
+
+    // At `i2`, `x` is used, `y` is live across it, and `z` isn't live yet.
+    let mut func = Func::new("memory_pin_slots");
+    func.set_entry("Lstart");
+    let x = func.new_virtual_reg(RegClass::I32);
+    let y = func.new_virtual_reg(RegClass::I32);
+    let z = func.new_virtual_reg(RegClass::I32);
+    func.block(
+        "Lstart",
+        vec![
+            i_imm(x, 1),
+            i_imm(y, 2),
+            i_print_i(x),
+            i_imm(z, 3),
+            i_print_i(y),
+            i_print_i(z),
+            i_finish(None),
+        ],
+    );
+    func.finish();
+    let pins = vec![MemoryPin {
+        iix: InstIx::new(2),
+        vregs: vec![x.to_virtual_reg(), y.to_virtual_reg(), z.to_virtual_reg()],
+    }];
+
+    let reg_universe = make_universe(4, 0);
+    let opts = Options {
+        run_checker: true,
+        dump_allocation: false,
+        annotate: false,
+        deterministic: true,
+        report_moves: false,
+        split_critical_edges: false,
+        large_functions: false,
+        algorithm: Algorithm::Backtracking(Default::default()),
+    };
+    let backtracking = BacktrackingOptions {
+        memory_pins: pins,
+        ..Default::default()
+    };
+
+    let mut pinned = func.clone();
+    let result = allocate_registers_with_opts(
+        &mut pinned,
+        &reg_universe,
+        None,
+        Options {
+            algorithm: Algorithm::Backtracking(backtracking.clone()),
+            ..opts.clone()
+        },
+    )
+    .unwrap_or_else(|err| panic!("allocation failed: {}", err));
+
+    // Both live vregs get a slot of their own; `z` doesn't.
+    assert_eq!(result.memory_pin_slots.len(), 1);
+    let slots = &result.memory_pin_slots[0];
+    let pinned_vregs: Vec<_> = slots.iter().map(|&(vreg, _)| vreg).collect();
+    assert_eq!(pinned_vregs, vec![x.to_virtual_reg(), y.to_virtual_reg()]);
+    assert_ne!(slots[0].1, slots[1].1);
+    assert!(slots
+        .iter()
+        .all(|&(_, slot)| slot.get() < result.num_spill_slots));
+
+    // Both are stored to their slot right before the pinned instruction.
+    let pinned_at = result
+        .orig_insn_map
+        .iter()
+        .position(|&orig| orig == InstIx::new(2))
+        .unwrap();
+    assert!(pinned_at >= 2);
+    let mut stored: Vec<_> = result.insns[pinned_at - 2..pinned_at]
+        .iter()
+        .map(|insn| match insn {
+            Inst::Spill { dst, .. } => *dst,
+            other => panic!(
+                "expected a spill before the pinned instruction, got {:?}",
+                other
+            ),
+        })
+        .collect();
+    let mut expected: Vec<_> = slots.iter().map(|&(_, slot)| slot).collect();
+    stored.sort_by_key(|slot| slot.get());
+    expected.sort_by_key(|slot| slot.get());
+    assert_eq!(stored, expected);
+
+    // Linear scan can't honour the pins.
+    let comparison = compare_allocators(
+        &func,
+        &reg_universe,
+        None,
+        backtracking,
+        Default::default(),
+        opts,
+    );
+    assert_eq!(
+        comparison.backtracking.unwrap().memory_pin_slots,
+        result.memory_pin_slots
+    );
+    match comparison.linear_scan {
+        Err(RegAllocError::UnsupportedMemoryPins) => {}
+        res => panic!("unexpected result: {:?}", res.map(|_| ())),
+    }
+    assert!(comparison.linear_scan_summary.is_none());
+}
+
+#[test]
+fn move_report() {
+    use regalloc::{Function, LinearScanOptions};
+
+    let reg_universe = make_universe(8, 0);
+    let func = test_cases::find_func("qsort").unwrap();
+    let moves: Vec<_> = func
+        .insn_indices()
+        .into_iter()
+        .filter(|&iix| func.is_move(func.get_insn(iix)).is_some())
+        .collect();
+    assert!(!moves.is_empty());
+
+    for algorithm in vec![
+        Algorithm::Backtracking(BacktrackingOptions::default()),
+        Algorithm::LinearScan(LinearScanOptions::default()),
+    ] {
+        let allocate = |report_moves| {
+            let mut func = func.clone();
+            let opts = Options {
+                run_checker: true,
+                algorithm: algorithm.clone(),
+                dump_allocation: false,
+                annotate: false,
+                deterministic: false,
+                report_moves,
+                split_critical_edges: false,
+                large_functions: false,
+            };
+            allocate_registers_with_opts(&mut func, &reg_universe, None, opts)
+                .unwrap_or_else(|err| panic!("allocation failed: {}", err))
+                .move_report
+        };
+        assert!(allocate(false).is_none());
+
+        // Each move is reported once, and some of them can be coalesced.
+        let report = allocate(true).unwrap();
+        let mut reported = report.eliminated.clone();
+        reported.extend(report.remaining.iter().cloned());
+        reported.sort();
+        assert_eq!(reported, moves);
+        assert!(!report.eliminated.is_empty());
+    }
+}
+
+#[test]
+fn spill_slot_layouts() {
+    use regalloc::LinearScanOptions;
+    for algorithm in vec![
+        Algorithm::Backtracking(BacktrackingOptions::default()),
+        Algorithm::GraphColoring(GraphColoringOptions::default()),
+        Algorithm::LinearScan(LinearScanOptions::default()),
+    ] {
+        // Float slots larger than the integer ones, aligned on their size, more than their
+        // size, and less than their size.
+        for &float_spill_slots in &[(2, 2), (2, 4), (3, 2)] {
+            test_utils::check_with_float_spill_slots(
+                "spill_slot_layouts",
+                3,
+                3,
+                algorithm.clone(),
+                float_spill_slots,
+            );
+        }
+    }
+}
+
+#[test]
+fn split_critical_edges() {
+    use regalloc::{AnalysisError, BlockIx, LinearScanOptions, RegAllocError};
+
+    let reg_universe = make_universe(3, 0);
+    let func = test_cases::find_func("critical_edges").unwrap();
+    for algorithm in vec![
+        Algorithm::Backtracking(BacktrackingOptions::default()),
+        Algorithm::GraphColoring(GraphColoringOptions::default()),
+        Algorithm::LinearScan(LinearScanOptions::default()),
+    ] {
+        let allocate = |split_critical_edges| {
+            let opts = Options {
+                run_checker: true,
+                algorithm: algorithm.clone(),
+                dump_allocation: false,
+                annotate: false,
+                deterministic: false,
+                report_moves: false,
+                split_critical_edges,
+                large_functions: false,
+            };
+            allocate_registers_with_opts(&mut func.clone(), &reg_universe, None, opts)
+        };
+        assert!(matches!(
+            allocate(false),
+            Err(RegAllocError::Analysis(AnalysisError::CriticalEdge { .. }))
+        ));
+
+        // A block is added on the edge from `loop` to `join`, and on the one back from `join`.
+        let res = allocate(true).unwrap_or_else(|err| panic!("allocation failed: {}", err));
+        assert_eq!(
+            res.split_edges,
+            vec![
+                (BlockIx::new(1), BlockIx::new(3)),
+                (BlockIx::new(3), BlockIx::new(1))
+            ]
+        );
+        assert_eq!(res.target_map.len(), 7);
+
+        test_utils::check_split_critical_edges("critical_edges", 3, 0, algorithm);
+    }
+
+    // Clients which can't generate the jumps of the new blocks can't have edges split.
+    let mut func = func.clone();
+    func.split_edges = false;
+    let opts = Options {
+        split_critical_edges: true,
+        ..Options::default()
+    };
+    assert!(matches!(
+        allocate_registers_with_opts(&mut func, &reg_universe, None, opts),
+        Err(RegAllocError::Other(_))
+    ));
+}
+
+#[test]
+fn large_functions() {
+    use regalloc::{AnalysisError, BlockIx, LinearScanOptions, RegAllocError, RegClass};
+    use test_framework::{i_finish, i_imm, Func, Inst, Label};
+
+    // A binary tree of a million blocks, at the default limit; a chain would make the dominator
+    // sets quadratic.  The labels are resolved up front, as `Func::finish` would look each of
+    // them up by name.
+    let num_blocks = 1 << 20;
+    let label = |i: u32| Label::Resolved {
+        name: format!("b{}", i),
+        bix: BlockIx::new(i),
+        args: vec![],
+    };
+    let mut func = Func::new("large");
+    func.entry = Some(label(0));
+    let cond = func.new_virtual_reg(RegClass::I32);
+    for i in 0..num_blocks {
+        let mut insns = if i == 0 { vec![i_imm(cond, 0)] } else { vec![] };
+        insns.push(if 2 * i + 2 < num_blocks {
+            Inst::GotoCTF {
+                cond,
+                target_true: label(2 * i + 1),
+                target_false: label(2 * i + 2),
+            }
+        } else if 2 * i + 1 < num_blocks {
+            Inst::Goto {
+                target: label(2 * i + 1),
+            }
+        } else {
+            i_finish(None)
+        });
+        func.block(&format!("b{}", i), insns);
+    }
+
+    let reg_universe = make_universe(2, 0);
+    let allocate = |large_functions| {
+        let opts = Options {
+            run_checker: false,
+            algorithm: Algorithm::LinearScan(LinearScanOptions::default()),
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions,
+        };
+        allocate_registers_with_opts(&mut func.clone(), &reg_universe, None, opts)
+    };
+    assert!(matches!(
+        allocate(false),
+        Err(RegAllocError::Analysis(
+            AnalysisError::ImplementationLimitsExceeded
+        ))
+    ));
+
+    let res = allocate(true).unwrap_or_else(|err| panic!("allocation failed: {}", err));
+    assert_eq!(res.target_map.len(), num_blocks);
+}
+
+#[test]
+fn lsra_split_around_calls() {
+    use regalloc::LinearScanOptions;
+    for &split_around_calls in &[false, true] {
+        let opts = LinearScanOptions {
+            split_around_calls,
+            ..Default::default()
+        };
+        test_utils::check_lsra_with_opts("lsra_split_around_calls", 4, 0, opts);
+    }
+}
+
+#[test]
+fn lsra_move_cycles() {
+    use regalloc::LinearScanOptions;
+    // With one register less when it isn't reserved as a scratch register, qsort ends up with a
+    // move cycle at a block boundary.
+    for &(func_name, num_gpr, scratch_free, expected_swaps) in &[
+        ("qsort", 8, false, 1),
+        ("qsort", 7, true, 1),
+        ("qsort", 6, false, 0),
+        ("ssort", 4, true, 0),
+        ("3_loops", 3, false, 0),
+    ] {
+        let opts = || LinearScanOptions {
+            scratch_free,
+            ..Default::default()
+        };
+        let (num_moves, num_swaps) =
+            test_utils::check_lsra_with_swaps(func_name, num_gpr, 0, opts(), true);
+        let (num_moves_without_swaps, no_swaps) =
+            test_utils::check_lsra_with_swaps(func_name, num_gpr, 0, opts(), false);
+        assert_eq!(num_swaps, expected_swaps);
+        assert_eq!(no_swaps, 0);
+        // The cycles swap two registers, which takes a single swap instead of three moves
+        // through a scratch register.
+        assert_eq!(num_moves + 2 * num_swaps, num_moves_without_swaps);
+    }
+}
+
+#[test]
+fn lsra_spill_to_fpu() {
+    // Integer code leaves the floating-point registers idle, so the spilled values fit in them.
+    for &(func_name, num_gpr) in &[
+        ("qsort", 6),
+        ("ssort", 4),
+        ("3_loops", 3),
+        ("fill_then_sum", 4),
+    ] {
+        let (num_spills, num_copies) =
+            test_utils::check_lsra_spill_to_fpu(func_name, num_gpr, 0, false);
+        assert!(num_spills > 0);
+        assert_eq!(num_copies, 0);
+        let (num_spills, num_copies) =
+            test_utils::check_lsra_spill_to_fpu(func_name, num_gpr, 32, true);
+        assert_eq!(num_spills, 0);
+        assert!(num_copies > 0);
+    }
+    // Without enough floating-point registers, some values are still spilled to the stack.
+    let (num_spills, num_copies) = test_utils::check_lsra_spill_to_fpu("qsort", 6, 2, true);
+    assert!(num_spills > 0);
+    assert!(num_copies > 0);
+}
+
+#[test]
+fn lsra_scratch_free() {
+    use regalloc::LinearScanOptions;
+    let opts = || LinearScanOptions {
+        scratch_free: true,
+        ..Default::default()
+    };
+    // Without a reserved scratch register, each function needs one register less than with it.
+    for &(func_name, min_gpr) in &[
+        ("ssort", 2),
+        ("qsort", 3),
+        ("3_loops", 2),
+        ("stmts", 2),
+        ("needs_splitting", 2),
+        ("needs_splitting2", 2),
+    ] {
+        for num_gpr in min_gpr..min_gpr + 3 {
+            test_utils::check_lsra_with_opts(func_name, num_gpr, 0, opts());
+        }
+    }
+}
+
+#[test]
+fn fuzzing_random_functions() {
+    use regalloc::fuzzing;
+    let _ = pretty_env_logger::try_init();
+    for &(num_gpr, num_fpu) in &[(5, 5), (3, 2), (9, 0)] {
+        let reg_universe = make_universe(num_gpr, num_fpu);
+        // The calls clobber the first half of the registers of each class.
+        let call_clobbers = reg_universe
+            .allocable_by_class
+            .iter()
+            .flatten()
+            .flat_map(|info| info.first..info.first + (info.last - info.first + 1) / 2)
+            .map(|index| reg_universe.regs[index].0)
+            .collect();
+        let config = fuzzing::Config {
+            call_clobbers,
+            ..Default::default()
+        };
+        let stats = fuzzing::run(&reg_universe, &config, 0..300)
+            .unwrap_or_else(|failure| panic!("{}", failure));
+        assert_eq!(stats.num_functions, 300);
+        assert!(stats.num_spill_slots > 0);
+    }
+}
+
+#[test]
+fn aliased_regs() {
+    use crate::test_framework::*;
+    use regalloc::{Reg, RegClass};
+    let _ = pretty_env_logger::try_init();
+
+    // R0 is overlapped by W0, a narrower view of it that isn't allocable.  Writing W0 clobbers
+    // R0, so none of the values live across that write may stay in R0; the checker verifies it.
+    let mut reg_universe = make_universe(4, 0);
+    let w0_index = reg_universe.regs.len();
+    let w0 = Reg::new_real(RegClass::I32, 0, w0_index as u8);
+    reg_universe.regs.push((w0.to_real_reg(), "W0".to_string()));
+    reg_universe.aliases.push((0, w0_index));
+    reg_universe.check_is_sane();
+    let r0 = reg_universe.regs[0].0;
+
+    for &use_bt in &[true, false] {
+        let mut func = Func::new("aliased_regs");
+        func.set_entry("entry");
+        let v0 = func.new_virtual_reg(RegClass::I32);
+        let v1 = func.new_virtual_reg(RegClass::I32);
+        let v2 = func.new_virtual_reg(RegClass::I32);
+        let v3 = func.new_virtual_reg(RegClass::I32);
+        func.block(
+            "entry",
+            vec![
+                i_imm(v0, 1),
+                i_imm(v1, 2),
+                i_imm(v2, 3),
+                i_imm(v3, 4),
+                i_imm(w0, 42),
+                i_add(v0, v0, RI_R(v1)),
+                i_add(v0, v0, RI_R(v2)),
+                i_add(v0, v0, RI_R(v3)),
+                i_print_i(v0),
+                i_finish(None),
+            ],
+        );
+        func.finish();
+
+        let algorithm = if use_bt {
+            Algorithm::Backtracking(Default::default())
+        } else {
+            Algorithm::LinearScan(Default::default())
+        };
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: true,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm,
+        };
+        let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts)
+            .unwrap_or_else(|err| panic!("allocation failed: {}", err));
+        if use_bt {
+            // Four values are live across the write, and only R1 to R3 survive it.
+            assert_eq!(result.num_spill_slots, 1);
+            assert!(result.clobbered_registers.contains(r0));
+        }
+    }
+}
+
+#[test]
+fn lsra_reloads_out_of_loops() {
+    use crate::test_framework::*;
+    use regalloc::RegClass;
+    let _ = pretty_env_logger::try_init();
+
+    // `x` is spilled in the entry block, where five values are live with four registers, and
+    // only used in the loop.  It should be reloaded once in the preheader, not on every iteration.
+    let mut func = Func::new("reloads_out_of_loops");
+    func.set_entry("entry");
+    let x = func.new_virtual_reg(RegClass::I32);
+    let n = func.new_virtual_reg(RegClass::I32);
+    let s = func.new_virtual_reg(RegClass::I32);
+    let a = func.new_virtual_reg(RegClass::I32);
+    let b = func.new_virtual_reg(RegClass::I32);
+    let c = func.new_virtual_reg(RegClass::I32);
+    func.block(
+        "entry",
+        vec![
+            i_imm(x, 3),
+            i_imm(n, 10),
+            i_imm(s, 0),
+            i_imm(a, 1),
+            i_imm(b, 2),
+            i_add(s, s, RI_R(a)),
+            i_add(s, s, RI_R(b)),
+            i_goto("preheader"),
+        ],
+    );
+    func.block("preheader", vec![i_goto("loop")]);
+    func.block(
+        "loop",
+        vec![
+            i_add(s, s, RI_R(x)),
+            i_sub(n, n, RI_I(1)),
+            i_cmp_gt(c, n, RI_I(0)),
+            i_goto_ctf(c, "latch", "exit"),
+        ],
+    );
+    func.block("latch", vec![i_goto("loop")]);
+    func.block("exit", vec![i_print_i(s), i_finish(None)]);
+    func.finish();
+
+    let reg_universe = make_universe(5, 0);
+    let before_regalloc_result = run_func(
+        &func,
+        "Before allocation",
+        &reg_universe,
+        RunStage::BeforeRegalloc,
+    );
+
+    let opts = Options {
+        run_checker: true,
+        dump_allocation: false,
+        annotate: false,
+        deterministic: true,
+        report_moves: false,
+        split_critical_edges: false,
+        large_functions: false,
+        algorithm: Algorithm::LinearScan(Default::default()),
+    };
+    let result = allocate_registers_with_opts(&mut func, &reg_universe, None, opts)
+        .unwrap_or_else(|err| panic!("allocation failed: {}", err));
+    func.update_from_alloc(result);
+
+    let after_regalloc_result = run_func(
+        &func,
+        "After allocation",
+        &reg_universe,
+        RunStage::AfterRegalloc,
+    );
+    check_results(&before_regalloc_result, &after_regalloc_result);
+    assert_eq!(after_regalloc_result.unwrap().num_reloads, 1);
+}
+
+#[test]
+fn compare_allocators_shares_analysis() {
+    use regalloc::{compare_allocators, LinearScanOptions};
+    let _ = pretty_env_logger::try_init();
+
+    for &func_name in &["qsort", "3_loops", "stmts", "fill_then_sum", "fp2"] {
+        let func = test_cases::find_func(func_name).unwrap();
+        let reg_universe = make_universe(4, 4);
+        let opts = Options {
+            run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: true,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
+            algorithm: Algorithm::Backtracking(Default::default()),
+        };
+        let comparison = compare_allocators(
+            &func,
+            &reg_universe,
+            None,
+            Default::default(),
+            Default::default(),
+            opts.clone(),
+        );
+        assert!(comparison.shared_analysis);
+
+        // Each allocator gets the same result as when it analyses the function itself.
+        for (result, summary, algorithm) in vec![
+            (
+                comparison.backtracking,
+                comparison.backtracking_summary,
+                Algorithm::Backtracking(Default::default()),
+            ),
+            (
+                comparison.linear_scan,
+                comparison.linear_scan_summary,
+                Algorithm::LinearScan(Default::default()),
+            ),
+        ] {
+            let result = result.unwrap_or_else(|err| panic!("allocation failed: {}", err));
+            let summary = summary.unwrap();
+            let mut alone = func.clone();
+            let alone = allocate_registers_with_opts(
+                &mut alone,
+                &reg_universe,
+                None,
+                Options {
+                    algorithm,
+                    ..opts.clone()
+                },
+            )
+            .unwrap();
+            assert_eq!(result.insns.len(), alone.insns.len());
+            assert_eq!(result.num_spill_slots, alone.num_spill_slots);
+            assert_eq!(summary.num_insns, result.insns.len());
+            assert_eq!(summary.num_spill_slots, result.num_spill_slots);
+            assert!(summary.num_moves + summary.num_spills_and_reloads <= summary.num_insns);
+        }
+
+        // The allocators analyse the function separately when they reserve different scratches.
+        let comparison = compare_allocators(
+            &func,
+            &reg_universe,
+            None,
+            Default::default(),
+            LinearScanOptions {
+                scratch_free: true,
+                ..Default::default()
+            },
+            opts,
+        );
+        assert!(!comparison.shared_analysis);
+        assert!(comparison.backtracking.is_ok());
+    }
 }
@@ -768,6 +768,17 @@ pub fn parse_content(func_name: &str, content: &str) -> ParseResult<Func> {
                     insts.push(Inst::Safepoint);
                 }
 
+                "call" => {
+                    let mut clobbers = vec![];
+                    if let Some(var_name) = parser.try_read_ident_sameline() {
+                        clobbers.extend(parser.var(&var_name));
+                        while parser.try_read_char(',').is_some() {
+                            clobbers.push(parser.read_var()?);
+                        }
+                    }
+                    insts.push(i_call(clobbers));
+                }
+
                 "sub" => {
                     let dst = parser.read_var()?;
                     parser.expect_char(',')?;
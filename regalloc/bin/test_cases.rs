@@ -1411,8 +1411,230 @@ fn test_stmt_repeat() -> Func {
     bif.finish(stmts, None)
 }
 
+/// A long straight run of conditionals, giving a function with more blocks than the analysis
+/// handles on a single thread when it can use several.
+fn test_many_blocks() -> Func {
+    let mut bif = Blockifier::new("many_blocks");
+
+    let i = bif.new_virtual_reg(RegClass::I32);
+    let t = bif.new_virtual_reg(RegClass::I32);
+    let sum = bif.new_virtual_reg(RegClass::I32);
+    let bi = bif.new_virtual_reg(RegClass::I32);
+
+    let mut stmts = vec![s_imm(i, 0), s_imm(sum, 0)];
+    for n in 0..600 {
+        stmts.push(s_and(t, i, RI_I(3)));
+        stmts.push(s_cmp_eq(bi, t, RI_I(0)));
+        stmts.push(s_if_then(bi, vec![s_addm(sum, RI_I(n))]));
+        stmts.push(s_addm(i, RI_I(1)));
+    }
+    stmts.push(s_print_i(sum));
+    stmts.push(s_print_s("\n"));
+
+    bif.finish(stmts, None)
+}
+
 // This is the list of available tests.  This function returns either the
 // requested Func, or if not found, a list of the available ones.
+/// SSA form: a loop computing Fibonacci numbers, with its state in block
+/// parameters.
+fn test_ssa_fib() -> Func {
+    let mut func = Func::new("ssa_fib");
+    func.set_entry("Lstart");
+
+    let n = func.new_virtual_reg(RegClass::I32);
+    let a0 = func.new_virtual_reg(RegClass::I32);
+    let b0 = func.new_virtual_reg(RegClass::I32);
+    let i0 = func.new_virtual_reg(RegClass::I32);
+    let a = func.new_virtual_reg(RegClass::I32);
+    let b = func.new_virtual_reg(RegClass::I32);
+    let i = func.new_virtual_reg(RegClass::I32);
+    let c = func.new_virtual_reg(RegClass::I32);
+    let t = func.new_virtual_reg(RegClass::I32);
+    let i1 = func.new_virtual_reg(RegClass::I32);
+
+    func.block(
+        "Lstart",
+        vec![
+            i_imm(n, 20),
+            i_imm(a0, 0),
+            i_imm(b0, 1),
+            i_imm(i0, 0),
+            i_goto_args("Lloop", vec![a0, b0, i0]),
+        ],
+    );
+    func.block_with_params(
+        "Lloop",
+        vec![a, b, i],
+        vec![i_cmp_lt(c, i, RI_R(n)), i_goto_ctf(c, "Lbody", "Lexit")],
+    );
+    func.block(
+        "Lbody",
+        vec![
+            i_add(t, a, RI_R(b)),
+            i_add(i1, i, RI_I(1)),
+            i_goto_args("Lloop", vec![b, t, i1]),
+        ],
+    );
+    func.block("Lexit", vec![i_print_i(a), i_print_s("\n"), i_finish(None)]);
+    func.finish();
+    func
+}
+
+/// SSA form: a loop swapping its two block parameters on every iteration, so
+/// that the copies to them form a cycle.
+fn test_ssa_swap() -> Func {
+    let mut func = Func::new("ssa_swap");
+    func.set_entry("Lstart");
+
+    let x0 = func.new_virtual_reg(RegClass::I32);
+    let y0 = func.new_virtual_reg(RegClass::I32);
+    let i0 = func.new_virtual_reg(RegClass::I32);
+    let x = func.new_virtual_reg(RegClass::I32);
+    let y = func.new_virtual_reg(RegClass::I32);
+    let i = func.new_virtual_reg(RegClass::I32);
+    let i1 = func.new_virtual_reg(RegClass::I32);
+    let c = func.new_virtual_reg(RegClass::I32);
+
+    func.block(
+        "Lstart",
+        vec![
+            i_imm(x0, 1),
+            i_imm(y0, 2),
+            i_imm(i0, 0),
+            i_goto_args("Lloop", vec![x0, y0, i0]),
+        ],
+    );
+    func.block_with_params(
+        "Lloop",
+        vec![x, y, i],
+        vec![
+            i_print_i(x),
+            i_print_s(" "),
+            i_print_i(y),
+            i_print_s("\n"),
+            i_add(i1, i, RI_I(1)),
+            i_cmp_lt(c, i1, RI_I(5)),
+            i_goto_ctf(c, "Lagain", "Lexit"),
+        ],
+    );
+    func.block("Lagain", vec![i_goto_args("Lloop", vec![y, x, i1])]);
+    func.block("Lexit", vec![i_finish(None)]);
+    func.finish();
+    func
+}
+
+/// SSA form: a diamond, passing arguments on both edges of a conditional
+/// branch, and merging the results in a block parameter.
+fn test_ssa_diamond() -> Func {
+    let mut func = Func::new("ssa_diamond");
+    func.set_entry("Lstart");
+
+    let a = func.new_virtual_reg(RegClass::I32);
+    let b = func.new_virtual_reg(RegClass::I32);
+    let c = func.new_virtual_reg(RegClass::I32);
+    let x = func.new_virtual_reg(RegClass::I32);
+    let y = func.new_virtual_reg(RegClass::I32);
+    let z = func.new_virtual_reg(RegClass::I32);
+    let w = func.new_virtual_reg(RegClass::I32);
+    let r = func.new_virtual_reg(RegClass::I32);
+
+    func.block(
+        "Lstart",
+        vec![
+            i_imm(a, 5),
+            i_imm(b, 7),
+            i_cmp_lt(c, a, RI_R(b)),
+            i_goto_ctf_args(c, "Lthen", vec![a], "Lelse", vec![b]),
+        ],
+    );
+    func.block_with_params(
+        "Lthen",
+        vec![x],
+        vec![i_add(y, x, RI_I(100)), i_goto_args("Ljoin", vec![y])],
+    );
+    func.block_with_params(
+        "Lelse",
+        vec![z],
+        vec![i_mul(w, z, RI_I(2)), i_goto_args("Ljoin", vec![w])],
+    );
+    func.block_with_params(
+        "Ljoin",
+        vec![r],
+        vec![i_print_i(r), i_print_s("\n"), i_finish(None)],
+    );
+    func.finish();
+    func
+}
+
+/// SSA form: a loop rotating six block parameters, more than there are
+/// registers when allocating with few of them, so that some parameters are
+/// spilled and the copies to them go between registers and spill slots.
+fn test_ssa_rotate() -> Func {
+    let mut func = Func::new("ssa_rotate");
+    func.set_entry("Lstart");
+
+    let mut init = vec![];
+    let mut params = vec![];
+    for _ in 0..6 {
+        init.push(func.new_virtual_reg(RegClass::I32));
+        params.push(func.new_virtual_reg(RegClass::I32));
+    }
+    let i0 = func.new_virtual_reg(RegClass::I32);
+    let i = func.new_virtual_reg(RegClass::I32);
+    let i1 = func.new_virtual_reg(RegClass::I32);
+    let c = func.new_virtual_reg(RegClass::I32);
+    let q = func.new_virtual_reg(RegClass::I32);
+    let mut sums = vec![];
+    let mut products = vec![];
+    for _ in 0..5 {
+        sums.push(func.new_virtual_reg(RegClass::I32));
+        products.push(func.new_virtual_reg(RegClass::I32));
+    }
+
+    let mut insns = vec![];
+    for (n, reg) in init.iter().enumerate() {
+        insns.push(i_imm(*reg, n as u32 + 1));
+    }
+    insns.push(i_imm(i0, 0));
+    let mut args = init.clone();
+    args.push(i0);
+    insns.push(i_goto_args("Lloop", args));
+    func.block("Lstart", insns);
+
+    let mut loop_params = params.clone();
+    loop_params.push(i);
+    func.block_with_params(
+        "Lloop",
+        loop_params,
+        vec![i_cmp_lt(c, i, RI_I(10)), i_goto_ctf(c, "Lbody", "Lexit")],
+    );
+
+    let mut args = params[1..].to_vec();
+    args.push(q);
+    args.push(i1);
+    func.block(
+        "Lbody",
+        vec![
+            i_add(q, params[0], RI_R(i)),
+            i_add(i1, i, RI_I(1)),
+            i_goto_args("Lloop", args),
+        ],
+    );
+
+    let mut insns = vec![i_add(sums[0], params[0], RI_R(params[1]))];
+    for n in 1..5 {
+        insns.push(i_mul(products[n], sums[n - 1], RI_I(3)));
+        insns.push(i_add(sums[n], products[n], RI_R(params[n + 1])));
+    }
+    insns.push(i_print_i(sums[4]));
+    insns.push(i_print_s("\n"));
+    insns.push(i_finish(None));
+    func.block("Lexit", insns);
+    func.finish();
+    func
+}
+
 pub fn find_func(name: &str) -> Result<Func, Vec<String>> {
     // This is really stupid.  Fortunately it's not performance critical :)
     let all_funcs = vec![
@@ -1429,6 +1651,11 @@ pub fn find_func(name: &str) -> Result<Func, Vec<String>> {
         test_fp2(),              // floating point with loops and arrays
         test_stmt_repeat(),
         test_stmt_loop(),
+        test_ssa_fib(),     // SSA form, loop with block parameters
+        test_ssa_swap(),    // SSA form, cyclic copies to block parameters
+        test_ssa_diamond(), // SSA form, arguments on conditional branches
+        test_ssa_rotate(),  // SSA form, spilled block parameters
+        test_many_blocks(),
     ];
 
     let mut all_names = Vec::new();
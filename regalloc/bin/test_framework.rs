@@ -19,22 +19,44 @@ use log::debug;
 // and Inst (instructions).  Also the get-regs and map-regs operations for
 // them.  Destinations are on the left.
 
+// A label can carry arguments, which are passed to the parameters of the
+// target block when jumping there.
 #[derive(Clone)]
 pub enum Label {
-    Unresolved { name: String },
-    Resolved { name: String, bix: BlockIx },
+    Unresolved {
+        name: String,
+        args: Vec<Reg>,
+    },
+    Resolved {
+        name: String,
+        bix: BlockIx,
+        args: Vec<Reg>,
+    },
 }
 
 impl Label {
     pub fn new_unresolved(name: String) -> Label {
-        Label::Unresolved { name }
+        Label::Unresolved { name, args: vec![] }
+    }
+    pub fn new_unresolved_with_args(name: String, args: Vec<Reg>) -> Label {
+        Label::Unresolved { name, args }
     }
     pub fn get_block_ix(&self) -> BlockIx {
         match self {
-            Label::Resolved { name: _, bix } => *bix,
+            Label::Resolved { bix, .. } => *bix,
             Label::Unresolved { .. } => panic!("Label::getBlockIx: unresolved label!"),
         }
     }
+    pub fn get_args(&self) -> &[Reg] {
+        match self {
+            Label::Resolved { args, .. } | Label::Unresolved { args, .. } => args,
+        }
+    }
+    pub fn clear_args(&mut self) {
+        match self {
+            Label::Resolved { args, .. } | Label::Unresolved { args, .. } => args.clear(),
+        }
+    }
     pub fn type_checks(&self, cx: &ValidatorContext) -> bool {
         match self {
             Label::Unresolved { .. } => false,
@@ -49,9 +71,13 @@ impl Label {
 impl fmt::Debug for Label {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Label::Unresolved { name } => write!(fmt, "??:{}", &name),
-            Label::Resolved { name, bix } => write!(fmt, "{:?}:{}", bix, name),
+            Label::Unresolved { name, .. } => write!(fmt, "??:{}", &name)?,
+            Label::Resolved { name, bix, .. } => write!(fmt, "{:?}:{}", bix, name)?,
+        }
+        if !self.get_args().is_empty() {
+            write!(fmt, "{:?}", self.get_args())?;
         }
+        Ok(())
     }
 }
 
@@ -334,6 +360,10 @@ pub enum Inst {
         dst: Reg,
         src: Reg,
     },
+    Swap {
+        reg1: Reg,
+        reg2: Reg,
+    }, // exchanges two registers of the same class, like x86's `xchg`
     BinOp {
         op: BinOp,
         dst: Reg,
@@ -345,6 +375,19 @@ pub enum Inst {
         dst: Reg,
         src_right: RI,
     }, // "mod" semantics for `dst`
+    BinOpFixed {
+        op: BinOp,
+        dst: Reg,
+        src_left: Reg,
+        src_right: Reg,
+        fixed: RealReg,
+    }, // `dst` and `src_left` must be in `fixed`, like x86's `div`
+    BinOpTied {
+        op: BinOp,
+        dst: Reg,
+        src_left: Reg,
+        src_right: Reg,
+    }, // `dst` must be in the same register as `src_left`, like x86's `add`
     BinOpF {
         op: BinOpF,
         dst: Reg,
@@ -376,6 +419,9 @@ pub enum Inst {
         src: Reg,
     },
     Safepoint,
+    Call {
+        clobbers: Vec<Reg>,
+    }, // the `clobbers` must be real registers
     Spill {
         dst: SpillSlot,
         src: RealReg,
@@ -450,8 +496,8 @@ pub fn i_storef(addr: AM, src: Reg) -> Inst {
     debug_assert!(src.get_class() == RegClass::F32);
     Inst::StoreF { addr, src }
 }
+// Integer values spilled to floating-point registers are spilled and reloaded from there too.
 fn i_spill(dst: SpillSlot, src: RealReg) -> Inst {
-    debug_assert!(src.get_class() == RegClass::I32);
     Inst::Spill { dst, src }
 }
 fn i_spillf(dst: SpillSlot, src: RealReg) -> Inst {
@@ -459,7 +505,6 @@ fn i_spillf(dst: SpillSlot, src: RealReg) -> Inst {
     Inst::SpillF { dst, src }
 }
 fn i_reload(dst: RealReg, src: SpillSlot) -> Inst {
-    debug_assert!(dst.get_class() == RegClass::I32);
     Inst::Reload { dst, src }
 }
 fn i_reloadf(dst: RealReg, src: SpillSlot) -> Inst {
@@ -479,6 +524,25 @@ pub fn i_goto_ctf<'a>(cond: Reg, target_true: &'a str, target_false: &'a str) ->
         target_false: Label::new_unresolved(target_false.to_string()),
     }
 }
+pub fn i_goto_args<'a>(target: &'a str, args: Vec<Reg>) -> Inst {
+    Inst::Goto {
+        target: Label::new_unresolved_with_args(target.to_string(), args),
+    }
+}
+pub fn i_goto_ctf_args<'a>(
+    cond: Reg,
+    target_true: &'a str,
+    args_true: Vec<Reg>,
+    target_false: &'a str,
+    args_false: Vec<Reg>,
+) -> Inst {
+    debug_assert!(cond.get_class() == RegClass::I32);
+    Inst::GotoCTF {
+        cond,
+        target_true: Label::new_unresolved_with_args(target_true.to_string(), args_true),
+        target_false: Label::new_unresolved_with_args(target_false.to_string(), args_false),
+    }
+}
 pub fn i_print_s<'a>(str: &'a str) -> Inst {
     Inst::PrintS {
         str: str.to_string(),
@@ -496,6 +560,11 @@ pub fn i_finish(reg: Option<Reg>) -> Inst {
     Inst::Finish { reg }
 }
 
+pub fn i_call(clobbers: Vec<Reg>) -> Inst {
+    debug_assert!(clobbers.iter().all(|reg| reg.is_real()));
+    Inst::Call { clobbers }
+}
+
 pub fn i_add(dst: Reg, src_left: Reg, src_right: RI) -> Inst {
     debug_assert!(dst.get_class() == RegClass::I32);
     debug_assert!(src_left.get_class() == RegClass::I32);
@@ -697,6 +766,32 @@ pub fn i_cmp_ltm(dst: Reg, src_right: RI) -> Inst {
     }
 }
 
+pub fn i_add_fixed(dst: Reg, src_left: Reg, src_right: Reg, fixed: RealReg) -> Inst {
+    debug_assert!(dst.get_class() == RegClass::I32);
+    debug_assert!(src_left.get_class() == RegClass::I32);
+    debug_assert!(src_right.get_class() == RegClass::I32);
+    debug_assert!(fixed.get_class() == RegClass::I32);
+    Inst::BinOpFixed {
+        op: BinOp::Add,
+        dst,
+        src_left,
+        src_right,
+        fixed,
+    }
+}
+
+pub fn i_add_tied(dst: Reg, src_left: Reg, src_right: Reg) -> Inst {
+    debug_assert!(dst.get_class() == RegClass::I32);
+    debug_assert!(src_left.get_class() == RegClass::I32);
+    debug_assert!(src_right.get_class() == RegClass::I32);
+    Inst::BinOpTied {
+        op: BinOp::Add,
+        dst,
+        src_left,
+        src_right,
+    }
+}
+
 pub fn i_fadd(dst: Reg, src_left: Reg, src_right: Reg) -> Inst {
     debug_assert!(dst.get_class() == RegClass::F32);
     debug_assert!(src_left.get_class() == RegClass::F32);
@@ -765,6 +860,7 @@ impl fmt::Debug for Inst {
             Inst::ImmF { dst, imm } => write!(fmt, "immf    {:?}, {:?}", dst, imm),
             Inst::Copy { dst, src } => write!(fmt, "copy    {:?}, {:?}", dst, src),
             Inst::CopyF { dst, src } => write!(fmt, "copyf   {:?}, {:?}", dst, src),
+            Inst::Swap { reg1, reg2 } => write!(fmt, "swap    {:?}, {:?}", reg1, reg2),
             Inst::BinOp {
                 op,
                 dst,
@@ -785,6 +881,34 @@ impl fmt::Debug for Inst {
                 dst,
                 src_right
             ),
+            Inst::BinOpFixed {
+                op,
+                dst,
+                src_left,
+                src_right,
+                fixed,
+            } => write!(
+                fmt,
+                "{} {:?}, {:?}, {:?} @{:?}",
+                ljustify(op.to_string(), 7),
+                dst,
+                src_left,
+                src_right,
+                fixed
+            ),
+            Inst::BinOpTied {
+                op,
+                dst,
+                src_left,
+                src_right,
+            } => write!(
+                fmt,
+                "{} {:?}, {:?}, {:?} tied",
+                ljustify(op.to_string(), 7),
+                dst,
+                src_left,
+                src_right
+            ),
             Inst::BinOpF {
                 op,
                 dst,
@@ -805,6 +929,7 @@ impl fmt::Debug for Inst {
             Inst::MakeRef { dst, src } => write!(fmt, "makeref {:?}, {:?}", dst, src),
             Inst::UseRef { dst, src } => write!(fmt, "useref  {:?}, {:?}", dst, src),
             Inst::Safepoint => write!(fmt, "safepoint"),
+            Inst::Call { clobbers } => write!(fmt, "call    {:?}", clobbers),
             Inst::Spill { dst, src } => write!(fmt, "SPILL   {:?}, {:?}", dst, src),
             Inst::SpillF { dst, src } => write!(fmt, "SPILLF  {:?}, {:?}", dst, src),
             Inst::Reload { dst, src } => write!(fmt, "RELOAD  {:?}, {:?}", dst, src),
@@ -889,6 +1014,10 @@ impl Inst {
                 collector.add_def(Writable::from_reg(*dst));
                 collector.add_use(*src);
             }
+            Inst::Swap { reg1, reg2 } => {
+                collector.add_mod(Writable::from_reg(*reg1));
+                collector.add_mod(Writable::from_reg(*reg2));
+            }
             Inst::BinOp {
                 op: _,
                 dst,
@@ -907,6 +1036,26 @@ impl Inst {
                 collector.add_mod(Writable::from_reg(*dst));
                 src_right.add_reg_reads_to(collector);
             }
+            Inst::BinOpFixed {
+                op: _,
+                dst,
+                src_left,
+                src_right,
+                fixed,
+            } => {
+                collector.add_fixed_def(Writable::from_reg(*dst), *fixed);
+                collector.add_fixed_use(*src_left, *fixed);
+                collector.add_use(*src_right);
+            }
+            Inst::BinOpTied {
+                op: _,
+                dst,
+                src_left,
+                src_right,
+            } => {
+                collector.add_tied_def(Writable::from_reg(*dst), *src_left);
+                collector.add_use(*src_right);
+            }
             Inst::BinOpF {
                 op: _,
                 dst,
@@ -934,6 +1083,11 @@ impl Inst {
                 collector.add_use(*src);
             }
             Inst::Safepoint => {}
+            Inst::Call { clobbers } => {
+                for &reg in clobbers {
+                    collector.add_def(Writable::from_reg(reg));
+                }
+            }
             Inst::Load { dst, addr } => {
                 collector.add_def(Writable::from_reg(*dst));
                 addr.add_reg_reads_to(collector);
@@ -991,6 +1145,10 @@ impl Inst {
                 dst.apply_defs(mapper);
                 src.apply_uses(mapper);
             }
+            Inst::Swap { reg1, reg2 } => {
+                reg1.apply_mods(mapper);
+                reg2.apply_mods(mapper);
+            }
             Inst::BinOp {
                 op: _,
                 dst,
@@ -1009,6 +1167,27 @@ impl Inst {
                 dst.apply_mods(mapper);
                 src_right.apply_uses(mapper);
             }
+            Inst::BinOpFixed {
+                op: _,
+                dst,
+                src_left,
+                src_right,
+                fixed: _,
+            } => {
+                dst.apply_defs(mapper);
+                src_left.apply_uses(mapper);
+                src_right.apply_uses(mapper);
+            }
+            Inst::BinOpTied {
+                op: _,
+                dst,
+                src_left,
+                src_right,
+            } => {
+                dst.apply_defs(mapper);
+                src_left.apply_uses(mapper);
+                src_right.apply_uses(mapper);
+            }
             Inst::BinOpF {
                 op: _,
                 dst,
@@ -1027,7 +1206,7 @@ impl Inst {
                 dst.apply_defs(mapper);
                 src.apply_uses(mapper);
             }
-            Inst::Safepoint => {}
+            Inst::Safepoint | Inst::Call { .. } => {}
             Inst::Store { addr, src } => {
                 addr.apply_uses(mapper);
                 src.apply_uses(mapper);
@@ -1107,6 +1286,13 @@ impl Inst {
             Inst::CopyF { dst, src } => {
                 cx.check_reg_rc(src, RegRef::Use, F32) && cx.check_reg_rc(dst, RegRef::Def, F32)
             }
+            Inst::Swap { reg1, reg2 } => {
+                let rc = reg1.get_class();
+                cx.check_reg_rc(reg1, RegRef::Use, rc)
+                    && cx.check_reg_rc(reg2, RegRef::Use, rc)
+                    && cx.check_reg_rc(reg1, RegRef::Def, rc)
+                    && cx.check_reg_rc(reg2, RegRef::Def, rc)
+            }
             Inst::Load { dst, addr } => {
                 addr.type_checks(cx) && cx.check_reg_rc(dst, RegRef::Def, I32)
             }
@@ -1152,6 +1338,23 @@ impl Inst {
                     && src_right.type_checks(cx)
                     && cx.check_reg_rc(dst, RegRef::Def, I32)
             }
+            Inst::BinOpFixed {
+                op: _,
+                dst,
+                src_left,
+                src_right,
+                fixed: _,
+            }
+            | Inst::BinOpTied {
+                op: _,
+                dst,
+                src_left,
+                src_right,
+            } => {
+                cx.check_reg_rc(src_left, RegRef::Use, I32)
+                    && cx.check_reg_rc(src_right, RegRef::Use, I32)
+                    && cx.check_reg_rc(dst, RegRef::Def, I32)
+            }
             Inst::BinOpF {
                 op: _,
                 dst,
@@ -1163,6 +1366,7 @@ impl Inst {
                     && cx.check_reg_rc(dst, RegRef::Def, F32)
             }
             Inst::Safepoint => true,
+            Inst::Call { clobbers } => clobbers.iter().all(|reg| reg.is_real()),
 
             // These are not user instructions.
             Inst::Spill { .. }
@@ -1383,13 +1587,23 @@ impl<'a> IState<'a> {
     }
 
     fn set_spill_slot_f32(&mut self, slot: SpillSlot, val: f32) {
+        // A float fills all the slots of its size, so that reading any other value overlapping
+        // it fails.
+        let (size, align) = self.func.float_spill_slots.unwrap_or((1, 1));
+        assert!(
+            slot.get() % align == 0,
+            "IState::setSpillSlotF32: misaligned slot # {}",
+            slot.get()
+        );
         // Auto-resize the vector if necessary
         let ix = slot.get_usize();
-        if ix >= self.slots.len() {
-            self.slots.resize(ix + 1, None);
+        let end = ix + size as usize;
+        if end > self.slots.len() {
+            self.slots.resize(end, None);
+        }
+        for slot in &mut self.slots[ix..end] {
+            *slot = Some(Value::F32(val));
         }
-        debug_assert!(ix < self.slots.len());
-        self.slots[ix] = Some(Value::F32(val));
     }
 
     fn get_reg(&self, reg: Reg) -> IResult<Value> {
@@ -1412,6 +1626,21 @@ impl<'a> IState<'a> {
         self.set_reg(reg, Value::Ref(val))
     }
 
+    // Jump to `target`, passing its arguments to the parameters of the target
+    // block, all at once.
+    fn goto(&mut self, target: &Label) -> IResult<()> {
+        let block = &self.func.blocks[target.get_block_ix()];
+        let mut vals = vec![];
+        for arg in target.get_args() {
+            vals.push(self.get_reg(*arg)?);
+        }
+        for (param, val) in block.params.iter().zip(vals) {
+            self.set_reg(*param, val);
+        }
+        self.nia = block.start;
+        Ok(())
+    }
+
     fn set_reg(&mut self, reg: Reg, val: Value) {
         if reg.is_virtual() {
             self.set_virtual_reg(reg.to_virtual_reg(), val);
@@ -1489,6 +1718,12 @@ impl<'a> IState<'a> {
             Inst::ImmF { dst, imm } => self.set_reg_f32(*dst, *imm),
             Inst::Copy { dst, src } => self.set_reg(*dst, self.get_reg(*src)?),
             Inst::CopyF { dst, src } => self.set_reg_f32(*dst, self.get_reg(*src)?.to_f32()),
+            Inst::Swap { reg1, reg2 } => {
+                let val1 = self.get_reg(*reg1)?;
+                let val2 = self.get_reg(*reg2)?;
+                self.set_reg(*reg1, val2);
+                self.set_reg(*reg2, val1);
+            }
             Inst::BinOp {
                 op,
                 dst,
@@ -1506,6 +1741,37 @@ impl<'a> IState<'a> {
                 dst_v = op.calc(dst_v, src_right_v)?;
                 self.set_reg_u32(*dst, dst_v);
             }
+            Inst::BinOpFixed {
+                op,
+                dst,
+                src_left,
+                src_right,
+                fixed,
+            } => {
+                for reg in &[*dst, *src_left] {
+                    if reg.is_real() && *reg != fixed.to_reg() {
+                        return Err(format!("{:?} should be in {:?}", reg, fixed));
+                    }
+                }
+                let src_left_v = self.get_reg(*src_left)?.to_u32();
+                let src_right_v = self.get_reg(*src_right)?.to_u32();
+                let dst_v = op.calc(src_left_v, src_right_v)?;
+                self.set_reg_u32(*dst, dst_v);
+            }
+            Inst::BinOpTied {
+                op,
+                dst,
+                src_left,
+                src_right,
+            } => {
+                if dst.is_real() && src_left.is_real() && dst != src_left {
+                    return Err(format!("{:?} should be in {:?}", dst, src_left));
+                }
+                let src_left_v = self.get_reg(*src_left)?.to_u32();
+                let src_right_v = self.get_reg(*src_right)?.to_u32();
+                let dst_v = op.calc(src_left_v, src_right_v)?;
+                self.set_reg_u32(*dst, dst_v);
+            }
             Inst::BinOpF {
                 op,
                 dst,
@@ -1526,6 +1792,12 @@ impl<'a> IState<'a> {
                 self.set_reg_u32(*dst, src_v);
             }
             Inst::Safepoint => {}
+            Inst::Call { clobbers } => {
+                // The callee leaves garbage in the clobbered registers.
+                for reg in clobbers {
+                    self.rregs[reg.get_index()] = None;
+                }
+            }
             Inst::Load { dst, addr } => {
                 let addr_v = self.get_AM(addr)?;
                 let dst_v = self.get_mem(addr_v)?.cast_to_u32();
@@ -1570,7 +1842,7 @@ impl<'a> IState<'a> {
                 self.set_reg_f32(dst.to_reg(), src_v);
                 self.num_reloads += 1;
             }
-            Inst::Goto { target } => self.nia = self.func.blocks[target.get_block_ix()].start,
+            Inst::Goto { target } => self.goto(target)?,
             Inst::GotoCTF {
                 cond,
                 target_true,
@@ -1581,7 +1853,7 @@ impl<'a> IState<'a> {
                 } else {
                     target_false
                 };
-                self.nia = self.func.blocks[target.get_block_ix()].start;
+                self.goto(target)?;
             }
             Inst::PrintS { str } => {
                 self.stdout += str;
@@ -1686,6 +1958,8 @@ pub struct Block {
     pub start: InstIx,
     pub len: u32,
     pub estimated_execution_frequency: u16,
+    // Block parameters, only for functions in SSA form.
+    pub params: Vec<Reg>,
 }
 
 impl Block {
@@ -1695,6 +1969,7 @@ impl Block {
             start,
             len,
             estimated_execution_frequency: 1,
+            params: vec![],
         }
     }
 }
@@ -1712,6 +1987,29 @@ pub struct Func {
     // must represent the ordering some other way; rearranging Func::blocks is
     // not allowed.
     pub blocks: TypedIxVec<BlockIx, Block>, // indexed by BlockIx
+
+    // Profile-derived block frequencies, indexed by BlockIx, if the test provides them.
+    pub block_frequencies: Option<Vec<u32>>,
+
+    // Allocation hints for virtual registers, if the test provides them.
+    pub reg_hints: Map<VirtualReg, RegHint>,
+
+    // Virtual registers pinned to a real register, if the test provides them.
+    pub pinned_regs: Vec<(VirtualReg, RealReg)>,
+
+    // The size and alignment of the spill slots of float registers, in slots, if the test
+    // provides them.  By default every value takes a single slot.
+    pub float_spill_slots: Option<(u32, u32)>,
+
+    // Whether the stackmap request asks for the registers holding references at safepoints,
+    // instead of having them spilled around the safepoints.
+    pub report_reftyped_regs: bool,
+
+    // Whether the allocator may generate swaps to resolve move cycles.
+    pub swaps: bool,
+
+    // Whether the allocator may split critical edges.
+    pub split_edges: bool,
 }
 
 // Find a block Ix for a block name
@@ -1735,12 +2033,20 @@ impl Func {
             reftype_reg_start: None,
             insns: TypedIxVec::<InstIx, Inst>::new(),
             blocks: TypedIxVec::<BlockIx, Block>::new(),
+            block_frequencies: None,
+            reg_hints: Map::default(),
+            pinned_regs: vec![],
+            float_spill_slots: None,
+            report_reftyped_regs: false,
+            swaps: false,
+            split_edges: true,
         }
     }
 
     pub fn set_entry(&mut self, entry: &str) {
         self.entry = Some(Label::Unresolved {
             name: entry.to_string(),
+            args: vec![],
         });
     }
 
@@ -1867,11 +2173,17 @@ impl Func {
 
     // Add a block to the Func
     pub fn block<'a>(&mut self, name: &'a str, insns: Vec<Inst>) {
+        self.block_with_params(name, vec![], insns);
+    }
+
+    // Add a block with parameters to the Func, which must then be in SSA form
+    pub fn block_with_params<'a>(&mut self, name: &'a str, params: Vec<Reg>, insns: Vec<Inst>) {
         let mut insns = TypedIxVec::from_vec(insns);
         let start = self.insns.len();
         let len = insns.len() as u32;
         self.insns.append(&mut insns);
-        let b = Block::new(name.to_string(), InstIx::new(start), len);
+        let mut b = Block::new(name.to_string(), InstIx::new(start), len);
+        b.params = params;
         self.blocks.push(b);
     }
 
@@ -1914,11 +2226,18 @@ impl Func {
 
     pub fn update_from_alloc(&mut self, result: regalloc::RegAllocResult<Func>) {
         self.insns = TypedIxVec::from_vec(result.insns);
+        // The blocks that the allocator inserted on critical edges come last.
+        for _ in &result.split_edges {
+            let name = format!("split{}", self.blocks.len());
+            self.blocks.push(Block::new(name, InstIx::new(0), 0));
+        }
         let num_blocks = self.blocks.len();
         let mut i = 0;
         for bix in self.blocks.range() {
             let block = &mut self.blocks[bix];
             block.start = result.target_map[bix];
+            // The allocator has inserted the copies to the block parameters.
+            block.params.clear();
             block.len = if i + 1 < num_blocks {
                 result.target_map[BlockIx::new(i + 1)].get()
             } else {
@@ -1926,6 +2245,20 @@ impl Func {
             } - block.start.get();
             i += 1;
         }
+        for insn in self.insns.iter_mut() {
+            match insn {
+                Inst::Goto { target } => target.clear_args(),
+                Inst::GotoCTF {
+                    target_true,
+                    target_false,
+                    ..
+                } => {
+                    target_true.clear_args();
+                    target_false.clear_args();
+                }
+                _ => {}
+            }
+        }
     }
 
     pub fn get_stackmap_request(&self) -> Option<StackmapRequestInfo> {
@@ -1953,6 +2286,7 @@ impl Func {
                     reftype_class: RegClass::I32,
                     reftyped_vregs,
                     safepoint_insns,
+                    report_reftyped_regs: self.report_reftyped_regs,
                 })
             }
         })
@@ -1964,9 +2298,10 @@ where
     F: Fn(String) -> BlockIx,
 {
     let resolved = match label {
-        Label::Unresolved { name } => Label::Resolved {
+        Label::Unresolved { name, args } => Label::Resolved {
             name: name.clone(),
             bix: lookup(name.clone()),
+            args: args.clone(),
         },
         Label::Resolved { .. } => panic!("resolveLabel: is already resolved!"),
     };
@@ -2292,6 +2627,56 @@ impl regalloc::Function for Func {
         Cow::Owned(self.insns[last_insn].get_targets())
     }
 
+    fn block_params(&self, block: BlockIx) -> Cow<[VirtualReg]> {
+        Cow::Owned(
+            self.blocks[block]
+                .params
+                .iter()
+                .map(|reg| reg.to_virtual_reg())
+                .collect(),
+        )
+    }
+
+    fn block_args(&self, block: BlockIx, succ_ix: usize) -> Cow<[VirtualReg]> {
+        let last_insn = self.blocks[block].start.plus(self.blocks[block].len - 1);
+        let target = match &self.insns[last_insn] {
+            Inst::Goto { target } => target,
+            Inst::GotoCTF {
+                target_true,
+                target_false,
+                ..
+            } => {
+                if succ_ix == 0 {
+                    target_true
+                } else {
+                    target_false
+                }
+            }
+            _ => return Cow::Borrowed(&[]),
+        };
+        Cow::Owned(
+            target
+                .get_args()
+                .iter()
+                .map(|reg| reg.to_virtual_reg())
+                .collect(),
+        )
+    }
+
+    fn block_frequencies(&self) -> Option<Cow<[u32]>> {
+        self.block_frequencies
+            .as_ref()
+            .map(|freqs| Cow::Borrowed(&freqs[..]))
+    }
+
+    fn reg_hint(&self, vreg: VirtualReg) -> Option<RegHint> {
+        self.reg_hints.get(&vreg).cloned()
+    }
+
+    fn pinned_regs(&self) -> Cow<[(VirtualReg, RealReg)]> {
+        Cow::Borrowed(&self.pinned_regs)
+    }
+
     fn is_ret(&self, insn: InstIx) -> bool {
         match &self.insns[insn] {
             &Inst::Finish { .. } => true,
@@ -2322,6 +2707,13 @@ impl regalloc::Function for Func {
         }
     }
 
+    fn is_call(&self, insn: &Self::Inst) -> bool {
+        match insn {
+            &Inst::Call { .. } => true,
+            _ => false,
+        }
+    }
+
     fn get_num_vregs(&self) -> usize {
         self.num_virtual_regs as usize
     }
@@ -2330,9 +2722,20 @@ impl regalloc::Function for Func {
     /// 64-bit machine, spill slots may nominally be 64-bit words, but a 128-bit
     /// vector value will require two slots.  The regalloc will always align on
     /// this size.
-    fn get_spillslot_size(&self, _regclass: RegClass, _for_vreg: VirtualReg) -> u32 {
-        // For our simple test ISA, every value occupies one spill slot.
-        1
+    fn get_spillslot_size(&self, regclass: RegClass, _for_vreg: VirtualReg) -> u32 {
+        // For our simple test ISA, every value occupies one spill slot, unless the test makes
+        // floats larger.
+        match (regclass, self.float_spill_slots) {
+            (RegClass::F32, Some((size, _))) => size,
+            _ => 1,
+        }
+    }
+
+    fn get_spillslot_align(&self, regclass: RegClass, _for_vreg: VirtualReg) -> u32 {
+        match (regclass, self.float_spill_slots) {
+            (RegClass::F32, Some((_, align))) => align,
+            _ => 1,
+        }
     }
 
     /// Generate a spill instruction for insertion into the instruction sequence.
@@ -2340,9 +2743,10 @@ impl regalloc::Function for Func {
         &self,
         to_slot: SpillSlot,
         from_reg: RealReg,
-        _for_vreg: Option<VirtualReg>,
+        for_vreg: Option<VirtualReg>,
     ) -> Self::Inst {
-        match from_reg.get_class() {
+        // A register may hold a value of another class when it is spilled to it.
+        match for_vreg.map_or(from_reg.get_class(), |vreg| vreg.get_class()) {
             RegClass::I32 => i_spill(to_slot, from_reg),
             RegClass::F32 => i_spillf(to_slot, from_reg),
             _ => panic!("Unused register class in test ISA was used"),
@@ -2354,9 +2758,9 @@ impl regalloc::Function for Func {
         &self,
         to_reg: Writable<RealReg>,
         from_slot: SpillSlot,
-        _for_vreg: Option<VirtualReg>,
+        for_vreg: Option<VirtualReg>,
     ) -> Self::Inst {
-        match to_reg.to_reg().get_class() {
+        match for_vreg.map_or(to_reg.to_reg().get_class(), |vreg| vreg.get_class()) {
             RegClass::I32 => i_reload(to_reg.to_reg(), from_slot),
             RegClass::F32 => i_reloadf(to_reg.to_reg(), from_slot),
             _ => panic!("Unused register class in test ISA was used"),
@@ -2369,9 +2773,10 @@ impl regalloc::Function for Func {
         &self,
         to_reg: Writable<RealReg>,
         from_reg: RealReg,
-        _for_vreg: VirtualReg,
+        for_vreg: VirtualReg,
     ) -> Self::Inst {
-        match to_reg.to_reg().get_class() {
+        // Integer values may be spilled to floating-point registers, which `Copy` moves as is.
+        match for_vreg.get_class() {
             RegClass::I32 => Inst::Copy {
                 src: from_reg.to_reg(),
                 dst: to_reg.to_reg().to_reg(),
@@ -2384,11 +2789,71 @@ impl regalloc::Function for Func {
         }
     }
 
+    // Swaps are always claimed, so that `gen_swap` declining them exercises the fallback to a
+    // scratch location.
+    fn can_swap(&self, _regclass: RegClass) -> bool {
+        true
+    }
+
+    fn gen_swap(
+        &self,
+        reg1: Writable<RealReg>,
+        reg2: Writable<RealReg>,
+        _for_vreg1: VirtualReg,
+        _for_vreg2: VirtualReg,
+    ) -> Option<Self::Inst> {
+        if !self.swaps {
+            return None;
+        }
+        Some(Inst::Swap {
+            reg1: reg1.to_reg().to_reg(),
+            reg2: reg2.to_reg().to_reg(),
+        })
+    }
+
     /// Generate an instruction which is a no-op and has zero length.
     fn gen_zero_len_nop(&self) -> Self::Inst {
         Inst::NopZ {}
     }
 
+    fn can_split_edges(&self) -> bool {
+        self.split_edges
+    }
+
+    fn gen_jump(&self, target: BlockIx) -> Self::Inst {
+        Inst::Goto {
+            target: Label::Resolved {
+                name: self.blocks[target].name.clone(),
+                bix: target,
+                args: vec![],
+            },
+        }
+    }
+
+    fn redirect_succ(&self, insn: &mut Self::Inst, succ_ix: usize, target: BlockIx) {
+        let label = match insn {
+            Inst::Goto { target } => target,
+            Inst::GotoCTF {
+                target_true,
+                target_false,
+                ..
+            } => {
+                if succ_ix == 0 {
+                    target_true
+                } else {
+                    target_false
+                }
+            }
+            _ => panic!("Func::redirect_succ: not a branch: {:?}", insn),
+        };
+        // The new blocks get their names in `update_from_alloc`.
+        *label = Label::Resolved {
+            name: format!("split{}", target.get()),
+            bix: target,
+            args: vec![],
+        };
+    }
+
     /// Try to alter an existing instruction to use a value directly in a
     /// spillslot (accessing memory directly) instead of the given register. May
     /// be useful on ISAs that have mem/reg ops, like x86.
@@ -2425,7 +2890,8 @@ pub fn make_universe(num_i32: usize, num_f32: usize) -> RealRegUniverse {
     }
 
     let mut regs = Vec::<(RealReg, String)>::new();
-    let mut allocable_by_class = [None; NUM_REG_CLASSES];
+    // Only describe the classes that are used, F32 being the last of them.
+    let mut allocable_by_class = vec![None; RegClass::F32.rc_to_usize() + 1];
     let mut index = 0u8;
 
     if num_i32 > 0 {
@@ -2441,6 +2907,7 @@ pub fn make_universe(num_i32: usize, num_f32: usize) -> RealRegUniverse {
             first,
             last,
             suggested_scratch: Some(last),
+            spill_class: None,
         });
     }
 
@@ -2457,6 +2924,7 @@ pub fn make_universe(num_i32: usize, num_f32: usize) -> RealRegUniverse {
             first,
             last,
             suggested_scratch: Some(last),
+            spill_class: None,
         });
     }
 
@@ -2468,6 +2936,7 @@ pub fn make_universe(num_i32: usize, num_f32: usize) -> RealRegUniverse {
         // for this example, all regs are allocable
         allocable,
         allocable_by_class,
+        aliases: vec![],
     };
     univ.check_is_sane();
 
@@ -62,8 +62,8 @@ impl<'rru> Context<'rru> {
             if self.real_reg_universe.regs[index].0 != reg.as_real_reg().unwrap() {
                 return false;
             }
-            match self.real_reg_universe.allocable_by_class[rc as usize] {
-                Some(ref reg_info) => index >= reg_info.first && index <= reg_info.last,
+            match self.real_reg_universe.class_info(rc) {
+                Some(reg_info) => index >= reg_info.first && index <= reg_info.last,
                 None => false,
             }
         }
@@ -133,6 +133,14 @@ pub fn validate(func: &Func, real_reg_universe: &RealRegUniverse) -> Result<(),
         if b.start.get().checked_add(b.len).is_none() {
             return Err("too many block instructions".into());
         }
+        for param in &b.params {
+            if !cx.check_reg(*param, RegRef::Def) {
+                return Err(format!(
+                    "param {:?} of block {} does not type check",
+                    param, b.name
+                ));
+            }
+        }
         for i in b.start.dotdot(b.start.plus(b.len)) {
             if i.get() >= func.insns.len() {
                 return Err(format!(
@@ -177,21 +185,42 @@ pub fn validate(func: &Func, real_reg_universe: &RealRegUniverse) -> Result<(),
         }
     }
 
-    if let Err(err) = regalloc::analysis_main::run_analysis(
+    // Block parameters are only accepted by the analysis for SSA-form input.
+    let algorithm = if func.blocks.iter().any(|b| !b.params.is_empty()) {
+        AlgorithmWithDefaults::Chordal
+    } else {
+        AlgorithmWithDefaults::Backtracking
+    };
+
+    // Operands fixed to a real register only become visible to the analysis once the allocator
+    // has inserted the copies to and from that register, so such functions can't be checked here.
+    let has_fixed_regs = func.insns.iter().any(|inst| match inst {
+        Inst::BinOpFixed { .. } => true,
+        _ => false,
+    });
+    if has_fixed_regs {
+        return Ok(());
+    }
+
+    match regalloc::analysis_main::run_analysis(
         func,
         real_reg_universe,
         // The next four params merely ensure that we get all possible analysis results from
         // `run_analysis`.  There's no implied claim about which algorithm we're using or
         // validating.
-        AlgorithmWithDefaults::Backtracking,
+        algorithm,
         /*client_wants_stackmaps=*/ true,
         /*reftype_class=*/ RegClass::I64,
         /*reftyped_vregs=*/ &vec![],
+        /*reserve_scratches=*/ true,
+        /*deterministic=*/ false,
     ) {
-        return Err(err.to_string());
+        // Critical edges don't prevent running the function, and the allocator splits them if
+        // asked to; see `Options::split_critical_edges`.  They stop the analysis though, so the
+        // rest of the function isn't checked then.
+        Ok(_) | Err(AnalysisError::CriticalEdge { .. }) => Ok(()),
+        Err(err) => Err(err.to_string()),
     }
-
-    Ok(())
 }
 
 pub fn check_results(
@@ -19,6 +19,12 @@ fuzz_target!(|func: ir::Func| {
     let opts = regalloc::Options {
         //TODO reenable checking once #47 is fixed.
         run_checker: false,
+        dump_allocation: false,
+        annotate: false,
+        deterministic: false,
+        report_moves: false,
+        split_critical_edges: false,
+        large_functions: false,
 
         algorithm: regalloc::Algorithm::Backtracking(Default::default()),
     };
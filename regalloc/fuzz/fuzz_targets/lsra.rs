@@ -34,6 +34,12 @@ fuzz_target!(|func: ir::Func| {
         sri.as_ref(),
         regalloc::Options {
             run_checker: true,
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
             algorithm: regalloc::Algorithm::LinearScan(Default::default()),
         },
     ) {
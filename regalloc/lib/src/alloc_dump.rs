@@ -0,0 +1,241 @@
+//! Textual dumps of the decisions made by the allocators.
+//!
+//! When `Options::dump_allocation` is set, the allocators describe the allocation they made in
+//! `RegAllocResult::allocation_dump`, without going through the `log` crate.  For each block, the
+//! dump lists the parts of virtual register live ranges within the block and the locations they
+//! were assigned, followed by the instructions the allocator inserted into the block:
+//!
+//! ```text
+//! block b1 [i4 .. i7]
+//!   range v2I [i4.r, i5.u] in %r1
+//!   range v3I [i5.d, i7.s] in %r0
+//!   at i5.r: reload %r1 <- S0 (v2I)
+//!   at i7.s: spill S1 <- %r0 (v3I)
+//! ```
+//!
+//! Everything is listed in a fixed order, so that dumps of the same function produced by two
+//! versions of the allocator can be compared with a plain diff.
+//!
+//! When `Options::annotate` is set, the same description of each block, framed by the registers
+//! live into and out of it, is attached to the block in `RegAllocResult::block_annotations`.
+
+use crate::data_structures::{BlockIx, InstPoint, RealRegUniverse, Reg, TypedIxVec, VirtualReg};
+use crate::inst_stream::{ExtPoint, InstExtPoint, InstToInsert, InstToInsertAndExtPoint, ValueLoc};
+use crate::sparse_set::SparseSet;
+use crate::Function;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// A part of the live range of a virtual register, and where the allocator put it.  It may span
+/// several blocks.
+pub(crate) struct DumpedRange {
+    pub(crate) vreg: VirtualReg,
+    pub(crate) first: InstPoint,
+    pub(crate) last: InstPoint,
+    pub(crate) loc: ValueLoc,
+}
+
+/// Describe an allocation of `func`, given the live ranges with their locations and the
+/// instructions to be inserted.  This must be called before the instruction stream is edited.
+pub(crate) fn dump_allocation<F: Function>(
+    func: &F,
+    reg_universe: &RealRegUniverse,
+    ranges: &[DumpedRange],
+    insts_to_add: &[InstToInsertAndExtPoint],
+) -> String {
+    let descs = describe_blocks(func, reg_universe, ranges, insts_to_add);
+    let mut dump = String::new();
+    for bix in func.blocks() {
+        let insns = func.block_insns(bix);
+        writeln!(
+            dump,
+            "block {:?} [{:?} .. {:?}]",
+            bix,
+            insns.start(),
+            insns.last()
+        )
+        .unwrap();
+        for desc in &descs[bix] {
+            writeln!(dump, "  {}", desc).unwrap();
+        }
+    }
+    dump
+}
+
+/// Annotate each block of `func` with the registers live into it, the parts of live ranges
+/// within it and their locations, the instructions the allocator inserted into it, and the
+/// registers live out of it, for `RegAllocResult::block_annotations`:
+///
+/// ```text
+/// live in: v2I, %r0
+/// range v2I [i4.r, i5.u] in %r1
+/// at i5.r: reload %r1 <- S0 (v2I)
+/// live out: %r0
+/// ```
+///
+/// This must be called before the instruction stream is edited.
+pub(crate) fn annotate_blocks<F: Function>(
+    func: &F,
+    reg_universe: &RealRegUniverse,
+    ranges: &[DumpedRange],
+    insts_to_add: &[InstToInsertAndExtPoint],
+    liveins: &TypedIxVec<BlockIx, SparseSet<Reg>>,
+    liveouts: &TypedIxVec<BlockIx, SparseSet<Reg>>,
+) -> TypedIxVec<BlockIx, Vec<String>> {
+    let show_regs = |regs: &SparseSet<Reg>| {
+        let regs: Vec<String> = regs
+            .iter_ordered(true)
+            .map(|reg| reg.show_with_rru(reg_universe))
+            .collect();
+        regs.join(", ")
+    };
+
+    let mut descs = describe_blocks(func, reg_universe, ranges, insts_to_add);
+    for bix in func.blocks() {
+        let desc = &mut descs[bix];
+        desc.insert(0, format!("live in: {}", show_regs(&liveins[bix])));
+        desc.push(format!("live out: {}", show_regs(&liveouts[bix])));
+    }
+    descs
+}
+
+/// Describe the allocation of each block of `func`: the parts of live ranges within it, then
+/// the instructions inserted into it, one per line.
+fn describe_blocks<F: Function>(
+    func: &F,
+    reg_universe: &RealRegUniverse,
+    ranges: &[DumpedRange],
+    insts_to_add: &[InstToInsertAndExtPoint],
+) -> TypedIxVec<BlockIx, Vec<String>> {
+    let mut insn_blocks = vec![BlockIx::new(0); func.insns().len()];
+    for bix in func.blocks() {
+        for iix in func.block_insns(bix) {
+            insn_blocks[iix.get() as usize] = bix;
+        }
+    }
+
+    // Split the ranges at block boundaries.
+    let mut block_ranges =
+        TypedIxVec::<BlockIx, Vec<(InstPoint, InstPoint, VirtualReg, ValueLoc)>>::new();
+    block_ranges.resize(func.blocks().len() as u32, vec![]);
+    for range in ranges {
+        let first_block = insn_blocks[range.first.iix().get() as usize];
+        let last_block = insn_blocks[range.last.iix().get() as usize];
+        for bix in first_block.get()..=last_block.get() {
+            let bix = BlockIx::new(bix);
+            let insns = func.block_insns(bix);
+            let first = range.first.max(InstPoint::new_reload(insns.start()));
+            let last = range.last.min(InstPoint::new_spill(insns.last()));
+            block_ranges[bix].push((first, last, range.vreg, range.loc));
+        }
+    }
+
+    // The inserted instructions are listed in the order in which they are inserted.
+    let mut insts_to_add: Vec<&InstToInsertAndExtPoint> = insts_to_add.iter().collect();
+    insts_to_add.sort_by_key(|to_add| to_add.iep.clone());
+    let mut insts_to_add = insts_to_add.into_iter().peekable();
+
+    let show_reg = |reg: Reg| reg.show_with_rru(reg_universe);
+    let show_loc = |loc: ValueLoc| match loc {
+        ValueLoc::Reg(rreg) => show_reg(rreg.to_reg()),
+        ValueLoc::Slot(slot) => format!("{:?}", slot),
+    };
+    let show_vreg = |for_vreg: Option<VirtualReg>| match for_vreg {
+        Some(vreg) => format!(" ({:?})", vreg),
+        None => String::new(),
+    };
+
+    let mut descs = TypedIxVec::<BlockIx, Vec<String>>::new();
+    descs.resize(func.blocks().len() as u32, vec![]);
+    for bix in func.blocks() {
+        let insns = func.block_insns(bix);
+        let desc = &mut descs[bix];
+
+        let ranges = &mut block_ranges[bix];
+        ranges.sort_unstable_by_key(|&(first, last, vreg, _)| (first, last, vreg));
+        for &(first, last, vreg, loc) in ranges.iter() {
+            desc.push(format!(
+                "range {:?} [{:?}, {:?}] in {}",
+                vreg,
+                first,
+                last,
+                show_loc(loc)
+            ));
+        }
+
+        while let Some(to_add) = insts_to_add.next_if(|to_add| to_add.iep.iix <= insns.last()) {
+            let inst = match to_add.inst {
+                InstToInsert::Spill {
+                    to_slot,
+                    from_reg,
+                    for_vreg,
+                } => format!(
+                    "spill {:?} <- {}{}",
+                    to_slot,
+                    show_reg(from_reg.to_reg()),
+                    show_vreg(for_vreg)
+                ),
+                InstToInsert::Reload {
+                    to_reg,
+                    from_slot,
+                    for_vreg,
+                } => format!(
+                    "reload {} <- {:?}{}",
+                    show_reg(to_reg.to_reg().to_reg()),
+                    from_slot,
+                    show_vreg(for_vreg)
+                ),
+                InstToInsert::Move {
+                    to_reg,
+                    from_reg,
+                    for_vreg,
+                } => format!(
+                    "move {} <- {}{}",
+                    show_reg(to_reg.to_reg().to_reg()),
+                    show_reg(from_reg.to_reg()),
+                    show_vreg(Some(for_vreg))
+                ),
+                InstToInsert::Swap {
+                    reg1,
+                    reg2,
+                    for_vreg1,
+                    for_vreg2,
+                } => format!(
+                    "swap {}{} <-> {}{}",
+                    show_reg(reg1.to_reg().to_reg()),
+                    show_vreg(Some(for_vreg1)),
+                    show_reg(reg2.to_reg().to_reg()),
+                    show_vreg(Some(for_vreg2))
+                ),
+                InstToInsert::ChangeSpillSlotOwnership {
+                    slot,
+                    from_reg,
+                    to_reg,
+                    ..
+                } => format!("transfer {:?} from {:?} to {:?}", slot, from_reg, to_reg),
+                InstToInsert::BindBlockParam {
+                    loc, arg, param, ..
+                } => format!("bind {:?} to {:?} in {}", param, arg, show_loc(loc)),
+            };
+            desc.push(format!("at {}: {}", show_ext_point(&to_add.iep), inst));
+        }
+    }
+
+    descs
+}
+
+fn show_ext_point(iep: &InstExtPoint) -> String {
+    let pt = match iep.extpt {
+        ExtPoint::Reload => "r",
+        ExtPoint::SpillBefore => "sb",
+        ExtPoint::Use => "u",
+        ExtPoint::Def => "d",
+        ExtPoint::ReloadAfter => "ra",
+        ExtPoint::Spill => "s",
+    };
+    format!("{:?}.{}", iep.iix, pt)
+}
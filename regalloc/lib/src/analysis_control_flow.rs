@@ -29,6 +29,7 @@ const CROSSCHECK_DOMS: bool = false;
 // This is trivial, but it's sometimes useful to have.
 // Note: confusingly, the `Range` here is data_structures::Range, not
 // std::ops::Range.
+#[derive(Clone)]
 pub struct InstIxToBlockIxMap {
     vek: TypedIxVec<BlockIx, Range<InstIx>>,
 }
@@ -634,6 +635,7 @@ fn calc_loop_depths(
 // preord and postord sequences, and loop depths.
 
 // CFGInfo contains CFG-related info computed from a Func.
+#[derive(Clone)]
 pub struct CFGInfo {
     // All these TypedIxVecs and plain Vecs contain one element per Block in the
     // Func.
@@ -651,33 +653,62 @@ pub struct CFGInfo {
     pub depth_map: TypedIxVec<BlockIx, u32>,
 }
 
+// By default, functions may have at most 1 million blocks.  That will soak up 20 index bits,
+// leaving a "safety margin" of 12 bits for indices for induced structures (RangeFragIx, InstIx,
+// VirtualRangeIx, RealRangeIx, etc).
+//
+// Similarly, limit the number of instructions to 16 million.  This allows 16 insns per block
+// with the worst-case number of blocks.  Because each insn typically generates somewhat less
+// than one new value, this check also has the effect of limiting the number of virtual registers
+// to roughly the same amount (16 million).
+const DEFAULT_MAX_BLOCKS: usize = 1024 * 1024;
+const DEFAULT_MAX_INSNS: usize = 16 * 1024 * 1024;
+
+// With `Options::large_functions`, the limits are those of the packed formats instead: instruction
+// indices get 30 bits in an `InstPoint`, and virtual register indices 28 bits in a `Reg`.  Keep
+// the number of instructions, and so of virtual registers, below the latter; there can't be more
+// blocks than instructions.  The induced structures are checked as they are created instead, see
+// `check_num_range_frags`.
+const LARGE_MAX_BLOCKS: usize = 256 * 1024 * 1024;
+const LARGE_MAX_INSNS: usize = 256 * 1024 * 1024;
+
+/// Check that `func` is within the implementation limits, which are wider with
+/// `Options::large_functions`.
+pub(crate) fn check_function_size<F: Function>(
+    func: &F,
+    large_functions: bool,
+) -> Result<(), AnalysisError> {
+    let (max_blocks, max_insns) = if large_functions {
+        (LARGE_MAX_BLOCKS, LARGE_MAX_INSNS)
+    } else {
+        (DEFAULT_MAX_BLOCKS, DEFAULT_MAX_INSNS)
+    };
+    if func.blocks().len() >= max_blocks || func.insns().len() >= max_insns {
+        return Err(AnalysisError::ImplementationLimitsExceeded);
+    }
+    Ok(())
+}
+
+/// Check that the live range fragments can be numbered.  Their indices, and those of the live
+/// ranges made from them, must fit in the 31 bits of a `RangeId`.
+pub(crate) fn check_num_range_frags(num_frags: usize) -> Result<(), AnalysisError> {
+    if num_frags >= 1 << 31 {
+        return Err(AnalysisError::ImplementationLimitsExceeded);
+    }
+    Ok(())
+}
+
 impl CFGInfo {
     #[inline(never)]
     pub fn create<F: Function>(func: &F) -> Result<Self, AnalysisError> {
         info!("    CFGInfo::create: begin");
 
         // Throw out insanely large inputs.  They'll probably cause failure later
-        // on.
-        let num_blocks_usize = func.blocks().len();
-        if num_blocks_usize >= 1 * 1024 * 1024 {
-            // 1 million blocks should be enough for anyone.  That will soak up 20
-            // index bits, leaving a "safety margin" of 12 bits for indices for
-            // induced structures (RangeFragIx, InstIx, VirtualRangeIx, RealRangeIx,
-            // etc).
-            return Err(AnalysisError::ImplementationLimitsExceeded);
-        }
-
-        // Similarly, limit the number of instructions to 16 million.  This allows
-        // 16 insns per block with the worst-case number of blocks.  Because each
-        // insn typically generates somewhat less than one new value, this check
-        // also has the effect of limiting the number of virtual registers to
-        // roughly the same amount (16 million).
-        if func.insns().len() >= 16 * 1024 * 1024 {
-            return Err(AnalysisError::ImplementationLimitsExceeded);
-        }
+        // on.  The default, tighter limits are checked by `allocate_registers_with_opts`.
+        check_function_size(func, /* large_functions = */ true)?;
 
         // Now we know we're safe to narrow it to u32.
-        let num_blocks = num_blocks_usize as u32;
+        let num_blocks = func.blocks().len() as u32;
 
         // === BEGIN compute successor and predecessor maps ===
         //
@@ -740,4 +771,21 @@ impl CFGInfo {
             depth_map,
         })
     }
+
+    /// Whether `func` still has the blocks, entry and edges this was computed from, so that it
+    /// still applies after the instructions within the blocks were changed.
+    pub(crate) fn matches<F: Function>(&self, func: &F) -> bool {
+        if func.blocks().len() != self.succ_map.len() as usize
+            || self.pre_ord.first() != Some(&func.entry_block())
+        {
+            return false;
+        }
+        func.blocks().into_iter().all(|bix| {
+            let mut succs = SparseSetU::<[BlockIx; 4]>::empty();
+            for succ in func.block_succs(bix).iter() {
+                succs.insert(*succ);
+            }
+            succs.equals(&self.succ_map[bix])
+        })
+    }
 }
@@ -10,7 +10,10 @@ use crate::data_structures::*;
 use crate::sparse_set::SparseSet;
 use crate::union_find::{ToFromU32, UnionFind};
 use crate::Function;
-use crate::{analysis_control_flow::CFGInfo, analysis_main::DepthBasedFrequencies};
+use crate::{
+    analysis_control_flow::CFGInfo,
+    analysis_main::{AnalysisError, BlockFrequencies},
+};
 use alloc::format;
 use alloc::string::ToString;
 use alloc::vec;
@@ -45,7 +48,10 @@ use alloc::vec;
 // or mod role.  Sanitisation will also detect this case and return an error.
 // Mentions of a scratch register in a def role are tolerated; however, since
 // no instruction may use or modify a scratch register, all such writes are
-// dead..
+// dead..  This check can be disabled per function (see
+// `BacktrackingOptions::scratch_free` and `LinearScanOptions::scratch_free`),
+// in which case the scratch registers are treated like any other allocable
+// register.
 //
 // In all of the above, "mentions" of a real register really means "uses,
 // defines or modifications of said register".  It doesn't matter whether the
@@ -170,27 +176,22 @@ pub fn add_raw_reg_vecs_for_insn<F: Function>(
     bounds.defs_len = defs_len as u8;
     bounds.mods_len = mods_len as u8;
 
+    clean_up_groups(reg_vecs, bounds);
+}
+
+// ==== LOCAL FN ====
+// Perform cleanups (1) and (2) above on the groups of an instruction.
+#[inline(never)]
+fn clean_up_groups(reg_vecs: &mut RegVecs, bounds: &mut RegVecBounds) {
     // First, de-dup the three new groups.
     if bounds.uses_len > 0 {
-        remove_dups_from_group(
-            &mut collector.reg_vecs.uses,
-            bounds.uses_start,
-            &mut bounds.uses_len,
-        );
+        remove_dups_from_group(&mut reg_vecs.uses, bounds.uses_start, &mut bounds.uses_len);
     }
     if bounds.defs_len > 0 {
-        remove_dups_from_group(
-            &mut collector.reg_vecs.defs,
-            bounds.defs_start,
-            &mut bounds.defs_len,
-        );
+        remove_dups_from_group(&mut reg_vecs.defs, bounds.defs_start, &mut bounds.defs_len);
     }
     if bounds.mods_len > 0 {
-        remove_dups_from_group(
-            &mut collector.reg_vecs.mods,
-            bounds.mods_start,
-            &mut bounds.mods_len,
-        );
+        remove_dups_from_group(&mut reg_vecs.mods, bounds.mods_start, &mut bounds.mods_len);
     }
 
     // And finally, remove modified registers from the set of used and defined
@@ -198,20 +199,20 @@ pub fn add_raw_reg_vecs_for_insn<F: Function>(
     if bounds.mods_len > 0 {
         if bounds.uses_len > 0 {
             remove_mods_from_group(
-                &mut collector.reg_vecs.uses,
+                &mut reg_vecs.uses,
                 bounds.uses_start,
                 &mut bounds.uses_len,
-                &collector.reg_vecs.mods,
+                &reg_vecs.mods,
                 bounds.mods_start,
                 bounds.mods_len,
             );
         }
         if bounds.defs_len > 0 {
             remove_mods_from_group(
-                &mut collector.reg_vecs.defs,
+                &mut reg_vecs.defs,
                 bounds.defs_start,
                 &mut bounds.defs_len,
-                &collector.reg_vecs.mods,
+                &reg_vecs.mods,
                 bounds.mods_start,
                 bounds.mods_len,
             );
@@ -219,6 +220,36 @@ pub fn add_raw_reg_vecs_for_insn<F: Function>(
     }
 }
 
+// ==== LOCAL FN ====
+// Add to the group in `regs[start, +len)`, which must be the last group of
+// `regs`, the allocable registers aliasing its real registers (see
+// `RealRegUniverse::aliases`), and update `*len` accordingly.  This may add
+// duplicates.
+#[inline(never)]
+fn add_aliases_to_group(
+    reg_universe: &RealRegUniverse,
+    regs: &mut Vec<Reg>,
+    start: u32,
+    len: &mut u8,
+) {
+    // Drop the registers leaked by the cleanups, if any, so that the group
+    // can grow at the end.
+    regs.truncate(start as usize + *len as usize);
+    for i in start as usize..start as usize + *len as usize {
+        let reg = regs[i];
+        if reg.is_real() && reg.get_index() < reg_universe.regs.len() {
+            regs.extend(
+                reg_universe
+                    .allocable_aliases(reg.get_index())
+                    .map(|alias| alias.to_reg()),
+            );
+        }
+    }
+    let new_len_usize = regs.len() - start as usize;
+    assert!(new_len_usize < 256);
+    *len = new_len_usize as u8;
+}
+
 // ==== LOCAL FN ====
 // This is the fundamental keep-or-don't-keep? predicate for sanitization.  To
 // do this exactly right we also need to know whether the register is
@@ -229,6 +260,7 @@ fn sanitize_should_retain_reg(
     reg_universe: &RealRegUniverse,
     reg: Reg,
     reg_is_defd: bool,
+    reserve_scratches: bool,
 ) -> Result<bool, RealReg> {
     // Retain all virtual regs.
     if reg.is_virtual() {
@@ -253,7 +285,11 @@ fn sanitize_should_retain_reg(
 
     // It isn't allowed for the client to give us an instruction which reads or
     // modifies one of the scratch registers.  It is however allowed to write a
-    // scratch register.
+    // scratch register.  None of this applies if the allocator was asked not to
+    // reserve the scratch registers.
+    if !reserve_scratches {
+        return Ok(true);
+    }
     for reg_info in &reg_universe.allocable_by_class {
         if let Some(reg_info) = reg_info {
             if let Some(scratch_idx) = &reg_info.suggested_scratch {
@@ -287,6 +323,7 @@ fn sanitize_group(
     start: u32,
     len: &mut u8,
     is_def_group: bool,
+    reserve_scratches: bool,
 ) -> Result<(), RealReg> {
     // Make a single compacting pass over the group.  'rd' = read point in the
     // group, 'wr' = write point in the group.
@@ -294,7 +331,7 @@ fn sanitize_group(
     for rd in start as usize..start as usize + *len as usize {
         let reg = regs[rd];
         // This call can fail:
-        if sanitize_should_retain_reg(reg_universe, reg, is_def_group)? {
+        if sanitize_should_retain_reg(reg_universe, reg, is_def_group, reserve_scratches)? {
             if wr != rd {
                 regs[wr] = reg;
             }
@@ -320,11 +357,37 @@ fn add_san_reg_vecs_for_insn<F: Function>(
     reg_universe: &RealRegUniverse,
     reg_vecs: &mut RegVecs,
     bounds: &mut RegVecBounds,
+    reserve_scratches: bool,
 ) -> Result<(), RealReg> {
     // Get the raw reg usages.  These will be dup-free and mod-cleaned-up
     // (meaning cleanups (1) and (3) have been done).
     add_raw_reg_vecs_for_insn::<F>(inst, reg_vecs, bounds);
 
+    // A mention of a register is a mention of the allocable registers
+    // overlapping it too.  Clean up the groups again afterwards, since an
+    // alias may have been mentioned already, or in another role.
+    if !reg_universe.aliases.is_empty() {
+        add_aliases_to_group(
+            reg_universe,
+            &mut reg_vecs.uses,
+            bounds.uses_start,
+            &mut bounds.uses_len,
+        );
+        add_aliases_to_group(
+            reg_universe,
+            &mut reg_vecs.defs,
+            bounds.defs_start,
+            &mut bounds.defs_len,
+        );
+        add_aliases_to_group(
+            reg_universe,
+            &mut reg_vecs.mods,
+            bounds.mods_start,
+            &mut bounds.mods_len,
+        );
+        clean_up_groups(reg_vecs, bounds);
+    }
+
     // Finally and sanitize them.  Any errors from sanitization are propagated.
     if bounds.uses_len > 0 {
         sanitize_group(
@@ -333,6 +396,7 @@ fn add_san_reg_vecs_for_insn<F: Function>(
             bounds.uses_start,
             &mut bounds.uses_len,
             /*is_def_group=*/ false,
+            reserve_scratches,
         )?;
     }
     if bounds.defs_len > 0 {
@@ -342,6 +406,7 @@ fn add_san_reg_vecs_for_insn<F: Function>(
             bounds.defs_start,
             &mut bounds.defs_len,
             /*is_def_group=*/ true,
+            reserve_scratches,
         )?;
     }
     if bounds.mods_len > 0 {
@@ -351,6 +416,7 @@ fn add_san_reg_vecs_for_insn<F: Function>(
             bounds.mods_start,
             &mut bounds.mods_len,
             /*is_def_group=*/ false,
+            reserve_scratches,
         )?;
     }
 
@@ -362,6 +428,7 @@ fn add_san_reg_vecs_for_insn<F: Function>(
 pub fn get_sanitized_reg_uses_for_func<F: Function>(
     func: &F,
     reg_universe: &RealRegUniverse,
+    reserve_scratches: bool,
 ) -> Result<RegVecsAndBounds, RealReg> {
     // These are modified by the per-insn loop.
     let mut reg_vecs = RegVecs::new(false);
@@ -373,7 +440,13 @@ pub fn get_sanitized_reg_uses_for_func<F: Function>(
     // errors from sanitization are propagated.
     for insn in func.insns() {
         let mut bounds = RegVecBounds::new();
-        add_san_reg_vecs_for_insn::<F>(insn, &reg_universe, &mut reg_vecs, &mut bounds)?;
+        add_san_reg_vecs_for_insn::<F>(
+            insn,
+            &reg_universe,
+            &mut reg_vecs,
+            &mut bounds,
+            reserve_scratches,
+        )?;
 
         bounds_vec.push(bounds);
     }
@@ -498,7 +571,15 @@ pub fn get_san_reg_sets_for_insn<F: Function>(
     let mut reg_vecs = RegVecs::new(false);
     let mut bounds = RegVecBounds::new();
 
-    add_san_reg_vecs_for_insn::<F>(inst, &reg_universe, &mut reg_vecs, &mut bounds)?;
+    // Whether the scratch registers were mentioned legitimately has already been
+    // checked by the analysis phase, so don't check it again here.
+    add_san_reg_vecs_for_insn::<F>(
+        inst,
+        &reg_universe,
+        &mut reg_vecs,
+        &mut bounds,
+        /*reserve_scratches=*/ false,
+    )?;
 
     // Make up a fake RegVecsAndBounds for just this insn, so we can hand it to
     // RegVecsAndBounds::get_reg_sets_for_iix.
@@ -511,9 +592,111 @@ pub fn get_san_reg_sets_for_insn<F: Function>(
     Ok(single_insn_rvb.get_reg_sets_for_iix(InstIx::new(0)))
 }
 
+//=============================================================================
+// Data flow analysis: per-block work, in parallel for large functions
+
+// Functions with fewer blocks than this are analysed on the current thread only: handing the
+// blocks out to other threads costs more than it saves on small functions.
+const MIN_BLOCKS_FOR_PARALLEL_ANALYSIS: usize = 1024;
+
+// Whether the blocks of `func` are worth analysing in parallel, which needs the `parallel`
+// feature.
+fn analyse_in_parallel<F: Function>(func: &F) -> bool {
+    cfg!(feature = "parallel") && func.blocks().len() >= MIN_BLOCKS_FOR_PARALLEL_ANALYSIS
+}
+
+// Compute `f` for each block of `func`, and return the results in block order.  `f` gets the
+// block's instructions and scratch storage created by `init`.  When analysing in parallel, the
+// blocks are shared out among rayon's threads, each with its own scratch storage.
+fn map_blocks<F, S, T, I, M>(func: &F, init: I, f: M) -> Vec<T>
+where
+    F: Function,
+    T: Send,
+    I: Fn() -> S + Sync + Send,
+    M: Fn(&mut S, BlockIx, Range<InstIx>) -> T + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    {
+        if analyse_in_parallel(func) {
+            use rayon::prelude::*;
+            let blocks: Vec<(BlockIx, Range<InstIx>)> = func
+                .blocks()
+                .into_iter()
+                .map(|bix| (bix, func.block_insns(bix)))
+                .collect();
+            return blocks
+                .into_par_iter()
+                .map_init(init, |scratch, (bix, block_insns)| {
+                    f(scratch, bix, block_insns)
+                })
+                .collect();
+        }
+    }
+    let mut scratch = init();
+    func.blocks()
+        .into_iter()
+        .map(|bix| f(&mut scratch, bix, func.block_insns(bix)))
+        .collect()
+}
+
 //=============================================================================
 // Data flow analysis: calculation of per-block register def and use sets
 
+// The def and use sets of the block made of `block_insns`.
+fn calc_def_and_use_for_block(
+    rvb: &RegVecsAndBounds,
+    block_insns: Range<InstIx>,
+) -> (SparseSet<Reg>, SparseSet<Reg>) {
+    let mut def = SparseSet::empty();
+    let mut uce = SparseSet::empty();
+    for iix in block_insns {
+        let bounds_for_iix = &rvb.bounds[iix];
+        // Add to `uce`, any registers for which the first event in this block
+        // is a read.  Dealing with the "first event" constraint is a bit
+        // tricky.  In the next two loops, `u` and `m` is used (either read or
+        // modified) by the instruction.  Whether or not we should consider it
+        // live-in for the block depends on whether it was been written earlier
+        // in the block.  We can determine that by checking whether it is
+        // already in the def set for the block.
+        // FIXME: isn't thus just:
+        //   uce union= (regs_u minus def)   followed by
+        //   uce union= (regs_m minus def)
+        for i in bounds_for_iix.uses_start as usize
+            ..bounds_for_iix.uses_start as usize + bounds_for_iix.uses_len as usize
+        {
+            let u = rvb.vecs.uses[i];
+            if !def.contains(u) {
+                uce.insert(u);
+            }
+        }
+        for i in bounds_for_iix.mods_start as usize
+            ..bounds_for_iix.mods_start as usize + bounds_for_iix.mods_len as usize
+        {
+            let m = rvb.vecs.mods[i];
+            if !def.contains(m) {
+                uce.insert(m);
+            }
+        }
+
+        // Now add to `def`, all registers written by the instruction.
+        // This is simpler.
+        // FIXME: isn't this just: def union= (regs_d union regs_m) ?
+        for i in bounds_for_iix.defs_start as usize
+            ..bounds_for_iix.defs_start as usize + bounds_for_iix.defs_len as usize
+        {
+            let d = rvb.vecs.defs[i];
+            def.insert(d);
+        }
+        for i in bounds_for_iix.mods_start as usize
+            ..bounds_for_iix.mods_start as usize + bounds_for_iix.mods_len as usize
+        {
+            let m = rvb.vecs.mods[i];
+            def.insert(m);
+        }
+    }
+    (def, uce)
+}
+
 // Returned TypedIxVecs contain one element per block
 #[inline(never)]
 pub fn calc_def_and_use<F: Function>(
@@ -528,54 +711,12 @@ pub fn calc_def_and_use<F: Function>(
     assert!(rvb.is_sanitized());
     let mut def_sets = TypedIxVec::new();
     let mut use_sets = TypedIxVec::new();
-    for b in func.blocks() {
-        let mut def = SparseSet::empty();
-        let mut uce = SparseSet::empty();
-        for iix in func.block_insns(b) {
-            let bounds_for_iix = &rvb.bounds[iix];
-            // Add to `uce`, any registers for which the first event in this block
-            // is a read.  Dealing with the "first event" constraint is a bit
-            // tricky.  In the next two loops, `u` and `m` is used (either read or
-            // modified) by the instruction.  Whether or not we should consider it
-            // live-in for the block depends on whether it was been written earlier
-            // in the block.  We can determine that by checking whether it is
-            // already in the def set for the block.
-            // FIXME: isn't thus just:
-            //   uce union= (regs_u minus def)   followed by
-            //   uce union= (regs_m minus def)
-            for i in bounds_for_iix.uses_start as usize
-                ..bounds_for_iix.uses_start as usize + bounds_for_iix.uses_len as usize
-            {
-                let u = rvb.vecs.uses[i];
-                if !def.contains(u) {
-                    uce.insert(u);
-                }
-            }
-            for i in bounds_for_iix.mods_start as usize
-                ..bounds_for_iix.mods_start as usize + bounds_for_iix.mods_len as usize
-            {
-                let m = rvb.vecs.mods[i];
-                if !def.contains(m) {
-                    uce.insert(m);
-                }
-            }
-
-            // Now add to `def`, all registers written by the instruction.
-            // This is simpler.
-            // FIXME: isn't this just: def union= (regs_d union regs_m) ?
-            for i in bounds_for_iix.defs_start as usize
-                ..bounds_for_iix.defs_start as usize + bounds_for_iix.defs_len as usize
-            {
-                let d = rvb.vecs.defs[i];
-                def.insert(d);
-            }
-            for i in bounds_for_iix.mods_start as usize
-                ..bounds_for_iix.mods_start as usize + bounds_for_iix.mods_len as usize
-            {
-                let m = rvb.vecs.mods[i];
-                def.insert(m);
-            }
-        }
+    let per_block = map_blocks(
+        func,
+        || (),
+        |_, _bix, block_insns| calc_def_and_use_for_block(rvb, block_insns),
+    );
+    for (def, uce) in per_block {
         def_sets.push(def);
         use_sets.push(uce);
     }
@@ -741,6 +882,170 @@ pub fn calc_livein_and_liveout<F: Function>(
     (liveins, liveouts)
 }
 
+//=============================================================================
+// Data flow analysis: block parameters, for functions in SSA form
+
+// Block parameters are treated as being defined at the start of their block, before its first
+// instruction, and the arguments passed to them as being read at the end of the predecessor
+// block, after its last instruction.  The copies from the latter to the former are inserted
+// later, by the allocator itself.  The arguments don't appear in the `RegVecsAndBounds`, since
+// they aren't mentioned by any instruction.
+
+/// Reject functions with block parameters, for the allocators that don't support them.
+#[inline(never)]
+pub(crate) fn check_no_block_params<F: Function>(func: &F) -> Result<(), AnalysisError> {
+    for b in func.blocks() {
+        if !func.block_params(b).is_empty() {
+            return Err(AnalysisError::UnsupportedBlockParams(b));
+        }
+    }
+    Ok(())
+}
+
+/// Check that the block parameters of `func`, and the arguments passed to them, are well formed,
+/// and that every virtual register is defined exactly once, counting block parameters as
+/// definitions.
+#[inline(never)]
+pub(crate) fn check_block_params<F: Function>(
+    func: &F,
+    rvb: &RegVecsAndBounds,
+    cfg_info: &CFGInfo,
+) -> Result<(), AnalysisError> {
+    info!("    check_block_params: begin");
+    let mut num_defs = vec![0u8; func.get_num_vregs()];
+    let mut add_def = |r: Reg| -> Result<(), AnalysisError> {
+        if r.is_virtual() {
+            let count = &mut num_defs[r.get_index()];
+            if *count > 0 {
+                return Err(AnalysisError::NotInSsaForm(r.to_virtual_reg()));
+            }
+            *count = 1;
+        }
+        Ok(())
+    };
+
+    // A modified register is read before being written, so it counts as a (second) definition.
+    for r in rvb.vecs.defs.iter().chain(rvb.vecs.mods.iter()) {
+        add_def(*r)?;
+    }
+
+    for b in func.blocks() {
+        let params = func.block_params(b);
+        if b == func.entry_block() && !params.is_empty() {
+            return Err(AnalysisError::EntryLiveinValues(
+                params.iter().map(|vreg| vreg.to_reg()).collect(),
+            ));
+        }
+        for param in params.iter() {
+            add_def(param.to_reg())?;
+        }
+
+        let last_iix = func.block_insns(b).last();
+        let bounds = &rvb.bounds[last_iix];
+        let succs = func.block_succs(b);
+        for (succ_ix, &succ) in succs.iter().enumerate() {
+            let args = func.block_args(b, succ_ix);
+            let succ_params = func.block_params(succ);
+            if args.len() != succ_params.len()
+                || args
+                    .iter()
+                    .zip(succ_params.iter())
+                    .any(|(arg, param)| arg.get_class() != param.get_class())
+            {
+                return Err(AnalysisError::BlockArgsMismatch { from: b, to: succ });
+            }
+            if succ_params.is_empty() {
+                continue;
+            }
+            // The arguments passed on each edge must be copied somewhere on that edge, so two
+            // edges from the same block to a block with parameters can't share its start.
+            if succs.iter().filter(|&&s| s == succ).count() > 1 {
+                return Err(AnalysisError::CriticalEdge { from: b, to: succ });
+            }
+            // If the successor has several predecessors, the copies go before the control flow
+            // instruction, which must then not mention any register.
+            if cfg_info.pred_map[succ].card() > 1
+                && bounds.uses_len + bounds.defs_len + bounds.mods_len > 0
+            {
+                return Err(AnalysisError::ChordalCriticalEdge {
+                    block: b,
+                    inst: last_iix,
+                });
+            }
+        }
+    }
+
+    info!("    check_block_params: end");
+    Ok(())
+}
+
+/// Amend the per-block def and use sets for block parameters and arguments.  Returns, for each
+/// block, the set of its parameters that it reads; those need to be live in to it.
+#[inline(never)]
+pub(crate) fn add_block_params_to_def_and_use<F: Function>(
+    func: &F,
+    def_sets_per_block: &mut TypedIxVec<BlockIx, SparseSet<Reg>>,
+    use_sets_per_block: &mut TypedIxVec<BlockIx, SparseSet<Reg>>,
+) -> TypedIxVec<BlockIx, SparseSet<Reg>> {
+    let mut live_params = TypedIxVec::<BlockIx, SparseSet<Reg>>::new();
+    for b in func.blocks() {
+        let params = func.block_params(b);
+        let mut read_params = SparseSet::<Reg>::empty();
+        for param in params.iter() {
+            let param = param.to_reg();
+            if use_sets_per_block[b].contains(param) {
+                read_params.insert(param);
+            }
+            def_sets_per_block[b].insert(param);
+        }
+        use_sets_per_block[b].remove(&read_params);
+        live_params.push(read_params);
+    }
+
+    for b in func.blocks() {
+        for succ_ix in 0..func.block_succs(b).len() {
+            for arg in func.block_args(b, succ_ix).iter() {
+                let arg = arg.to_reg();
+                if !def_sets_per_block[b].contains(arg) {
+                    use_sets_per_block[b].insert(arg);
+                }
+            }
+        }
+    }
+
+    live_params
+}
+
+/// Amend the per-block live-in and live-out sets for block parameters and arguments, given the
+/// parameters read by each block as computed by `add_block_params_to_def_and_use`.  Arguments are
+/// live out of the blocks passing them, and parameters are live in to their block if they're
+/// either read by it or live out of it.
+#[inline(never)]
+pub(crate) fn add_block_params_to_livein_and_liveout<F: Function>(
+    func: &F,
+    mut live_params: TypedIxVec<BlockIx, SparseSet<Reg>>,
+    livein_sets_per_block: &mut TypedIxVec<BlockIx, SparseSet<Reg>>,
+    liveout_sets_per_block: &mut TypedIxVec<BlockIx, SparseSet<Reg>>,
+) {
+    for b in func.blocks() {
+        for succ_ix in 0..func.block_succs(b).len() {
+            for arg in func.block_args(b, succ_ix).iter() {
+                liveout_sets_per_block[b].insert(arg.to_reg());
+            }
+        }
+    }
+
+    for b in func.blocks() {
+        for param in func.block_params(b).iter() {
+            let param = param.to_reg();
+            if liveout_sets_per_block[b].contains(param) {
+                live_params[b].insert(param);
+            }
+        }
+        livein_sets_per_block[b].union(&live_params[b]);
+    }
+}
+
 //=============================================================================
 // Computation of RangeFrags (Live Range Fragments), aggregated per register.
 // This does not produce complete live ranges.  That is done later, by
@@ -882,28 +1187,28 @@ fn emit_range_frag(
     out_map[reg_to_reg_ix(num_real_regs, reg) as usize].push(new_fix);
 }
 
-/// Calculate all the RangeFrags for `bix`.  Add them to `out_frags` and corresponding metrics
-/// data to `out_frag_metrics`.  Add to `out_map`, the associated RangeFragIxs, segregated by
-/// Reg.  `bix`, `livein`, `liveout` and `rvb` are expected to be valid in the context of the
-/// Func `f` (duh!).
+/// Calculate all the RangeFrags for `bix`, whose instructions are `block_insns`, and pass each
+/// of them to `emit` along with its Reg and metrics data.  `bix`, `livein`, `liveout` and `rvb`
+/// are expected to be valid in the context of the same Func (duh!).
 #[inline(never)]
-fn get_range_frags_for_block<F: Function>(
+fn get_range_frags_for_block<E: FnMut(Reg, RangeFrag, RangeFragMetrics)>(
     // Constants
-    func: &F,
+    block_insns: Range<InstIx>,
     rvb: &RegVecsAndBounds,
     reg_universe: &RealRegUniverse,
     vreg_classes: &Vec</*vreg index,*/ RegClass>,
     bix: BlockIx,
     livein: &SparseSet<Reg>,
     liveout: &SparseSet<Reg>,
+    // Iterate over `livein` and `liveout` in register order, so that the fragments are numbered
+    // independently of the layout of the sets.
+    sorted: bool,
     // Preallocated storage for use in this function.  They do not carry any useful information
     // in between calls here.
     visited: &mut Vec<u32>,
     state: &mut Vec</*rreg index, then vreg index, */ Option<ProtoRangeFrag>>,
-    // These accumulate the results of RangeFrag/RangeFragMetrics across multiple calls here.
-    out_map: &mut Vec</*rreg index, then vreg index, */ SmallVec<[RangeFragIx; 8]>>,
-    out_frags: &mut TypedIxVec<RangeFragIx, RangeFrag>,
-    out_frag_metrics: &mut TypedIxVec<RangeFragIx, RangeFragMetrics>,
+    // Receives the RangeFrags of the block, along with their Reg and metrics, in order.
+    emit: &mut E,
 ) {
     #[inline(always)]
     fn plus1(n: u16) -> u16 {
@@ -930,16 +1235,16 @@ fn get_range_frags_for_block<F: Function>(
     visited.clear();
 
     // Some handy constants.
-    assert!(func.block_insns(bix).len() >= 1);
-    let first_iix_in_block = func.block_insns(bix).first();
-    let last_iix_in_block = func.block_insns(bix).last();
+    assert!(block_insns.len() >= 1);
+    let first_iix_in_block = block_insns.first();
+    let last_iix_in_block = block_insns.last();
     let first_pt_in_block = InstPoint::new_use(first_iix_in_block);
     let last_pt_in_block = InstPoint::new_def(last_iix_in_block);
     let num_real_regs = reg_universe.regs.len() as u32;
 
     // First, set up `state` as if all of `livein` had been written just prior to the block.
-    for r in livein.iter() {
-        let r_state_ix = reg_to_reg_ix(num_real_regs, *r) as usize;
+    for r in livein.iter_ordered(sorted) {
+        let r_state_ix = reg_to_reg_ix(num_real_regs, r) as usize;
         debug_assert!(state[r_state_ix].is_none());
         state[r_state_ix] = Some(ProtoRangeFrag {
             num_mentions: 0,
@@ -951,7 +1256,7 @@ fn get_range_frags_for_block<F: Function>(
 
     // Now visit each instruction in turn, examining first the registers it reads, then those it
     // modifies, and finally those it writes.
-    for iix in func.block_insns(bix) {
+    for iix in block_insns {
         let bounds_for_iix = &rvb.bounds[iix];
 
         // Examine reads.  This is pretty simple.  They simply extend an existing ProtoRangeFrag
@@ -1035,16 +1340,8 @@ fn get_range_frags_for_block<F: Function>(
                     }
 
                     let (frag, frag_metrics) =
-                        RangeFrag::new_with_metrics(func, bix, *first, *last, *num_mentions);
-                    emit_range_frag(
-                        out_map,
-                        out_frags,
-                        out_frag_metrics,
-                        num_real_regs,
-                        *r,
-                        &frag,
-                        &frag_metrics,
-                    );
+                        RangeFrag::new_with_metrics(block_insns, bix, *first, *last, *num_mentions);
+                    emit(*r, frag, frag_metrics);
                     let new_pt = InstPoint::new_def(iix);
                     // Reuse the previous entry for this new definition of the same vreg.
                     *num_mentions = 1;
@@ -1059,8 +1356,8 @@ fn get_range_frags_for_block<F: Function>(
     // deal with ProtoRangeFrags in `state` that are for registers not listed as live-out.
 
     // Deal with live-out Regs.  Treat each one as if it is read just after the block.
-    for r in liveout.iter() {
-        let r_state_ix = reg_to_reg_ix(num_real_regs, *r) as usize;
+    for r in liveout.iter_ordered(sorted) {
+        let r_state_ix = reg_to_reg_ix(num_real_regs, r) as usize;
         let state_elem_p = &mut state[r_state_ix];
         match state_elem_p {
             // This can't happen.  `r` is in `liveout`, but this implies that it is neither
@@ -1072,21 +1369,13 @@ fn get_range_frags_for_block<F: Function>(
                 // because it must be read "after" the block.  Create a `LiveOut` or `Thru` frag
                 // accordingly.
                 let (frag, frag_metrics) = RangeFrag::new_with_metrics(
-                    func,
+                    block_insns,
                     bix,
                     pf.first,
                     last_pt_in_block,
                     pf.num_mentions,
                 );
-                emit_range_frag(
-                    out_map,
-                    out_frags,
-                    out_frag_metrics,
-                    num_real_regs,
-                    *r,
-                    &frag,
-                    &frag_metrics,
-                );
+                emit(r, frag, frag_metrics);
                 // Remove the entry from `state` so that the following loop doesn't process it
                 // again.
                 *state_elem_p = None;
@@ -1104,18 +1393,15 @@ fn get_range_frags_for_block<F: Function>(
                 if pf.first == pf.last {
                     debug_assert!(pf.num_mentions == 1);
                 }
-                let (frag, frag_metrics) =
-                    RangeFrag::new_with_metrics(func, bix, pf.first, pf.last, pf.num_mentions);
-                let r = reg_ix_to_reg(reg_universe, vreg_classes, *r_state_ix);
-                emit_range_frag(
-                    out_map,
-                    out_frags,
-                    out_frag_metrics,
-                    num_real_regs,
-                    r,
-                    &frag,
-                    &frag_metrics,
+                let (frag, frag_metrics) = RangeFrag::new_with_metrics(
+                    block_insns,
+                    bix,
+                    pf.first,
+                    pf.last,
+                    pf.num_mentions,
                 );
+                let r = reg_ix_to_reg(reg_universe, vreg_classes, *r_state_ix);
+                emit(r, frag, frag_metrics);
                 // Maintain invariant that all `state` entries are `None` in between calls to
                 // this function.
                 *state_elem_p = None;
@@ -1131,6 +1417,7 @@ pub fn get_range_frags<F: Function>(
     reg_universe: &RealRegUniverse,
     livein_sets_per_block: &TypedIxVec<BlockIx, SparseSet<Reg>>,
     liveout_sets_per_block: &TypedIxVec<BlockIx, SparseSet<Reg>>,
+    deterministic: bool,
 ) -> (
     Vec</*rreg index, then vreg index, */ SmallVec<[RangeFragIx; 8]>>,
     TypedIxVec<RangeFragIx, RangeFrag>,
@@ -1144,13 +1431,20 @@ pub fn get_range_frags<F: Function>(
 
     // In order that we can work with unified-reg-indices (see comments above), we need to know
     // the `RegClass` for each virtual register.  That info is collected here.
+    // Block parameters are included, since they may be passed on as arguments without ever
+    // being mentioned by an instruction.
     let mut vreg_classes = vec![RegClass::INVALID; func.get_num_vregs()];
+    let mut params = Vec::<Reg>::new();
+    for b in func.blocks() {
+        params.extend(func.block_params(b).iter().map(|vreg| vreg.to_reg()));
+    }
     for r in rvb
         .vecs
         .uses
         .iter()
         .chain(rvb.vecs.defs.iter())
         .chain(rvb.vecs.mods.iter())
+        .chain(params.iter())
     {
         if r.is_real() {
             continue;
@@ -1170,16 +1464,6 @@ pub fn get_range_frags<F: Function>(
     let num_virtual_regs = vreg_classes.len();
     let num_regs = num_real_regs + num_virtual_regs;
 
-    // A state variable that's reused across calls to `get_range_frags_for_block`.  When not in
-    // a call to `get_range_frags_for_block`, all entries should be `None`.
-    let mut state = Vec::</*rreg index, then vreg index, */ Option<ProtoRangeFrag>>::new();
-    state.resize(num_regs, None);
-
-    // Scratch storage needed by `get_range_frags_for_block`.  Doesn't carry any useful info in
-    // between calls.  Start it off not-quite-empty since it will always get used at least a
-    // bit.
-    let mut visited = Vec::<u32>::with_capacity(32);
-
     // `RangeFrag`/`RangeFragMetrics` are collected across multiple calls to
     // `get_range_frag_for_blocks` in these three vectors.  In other words, they collect the
     // overall results for this function.
@@ -1189,31 +1473,88 @@ pub fn get_range_frags<F: Function>(
         Vec::</*rreg index, then vreg index, */ SmallVec<[RangeFragIx; 8]>>::default();
     result_map.resize(num_regs, smallvec![]);
 
-    for bix in func.blocks() {
-        get_range_frags_for_block(
-            func,
-            rvb,
-            reg_universe,
-            &vreg_classes,
-            bix,
-            &livein_sets_per_block[bix],
-            &liveout_sets_per_block[bix],
-            &mut visited,
-            &mut state,
-            &mut result_map,
-            &mut result_frags,
-            &mut result_frag_metrics,
-        );
+    // Scratch storage for `get_range_frags_for_block`: a state variable that's reused across
+    // calls, all of whose entries should be `None` when not in a call, and `visited`, which
+    // doesn't carry any useful info in between calls.  Start the latter off not-quite-empty
+    // since it will always get used at least a bit.
+    let new_scratch = || {
+        let mut state = Vec::</*rreg index, then vreg index, */ Option<ProtoRangeFrag>>::new();
+        state.resize(num_regs, None);
+        let visited = Vec::<u32>::with_capacity(32);
+        (state, visited)
+    };
+
+    if analyse_in_parallel(func) {
+        // The fragments of each block are gathered first, and then numbered in block order, so
+        // that the numbering doesn't depend on the scheduling of the blocks.
+        let frags_per_block =
+            map_blocks(func, new_scratch, |(state, visited), bix, block_insns| {
+                let mut frags = Vec::new();
+                get_range_frags_for_block(
+                    block_insns,
+                    rvb,
+                    reg_universe,
+                    &vreg_classes,
+                    bix,
+                    &livein_sets_per_block[bix],
+                    &liveout_sets_per_block[bix],
+                    deterministic,
+                    visited,
+                    state,
+                    &mut |reg, frag, frag_metrics| frags.push((reg, frag, frag_metrics)),
+                );
+                frags
+            });
+        for (reg, frag, frag_metrics) in frags_per_block.into_iter().flatten() {
+            emit_range_frag(
+                &mut result_map,
+                &mut result_frags,
+                &mut result_frag_metrics,
+                num_real_regs as u32,
+                reg,
+                &frag,
+                &frag_metrics,
+            );
+        }
+    } else {
+        let (mut state, mut visited) = new_scratch();
+        for bix in func.blocks() {
+            get_range_frags_for_block(
+                func.block_insns(bix),
+                rvb,
+                reg_universe,
+                &vreg_classes,
+                bix,
+                &livein_sets_per_block[bix],
+                &liveout_sets_per_block[bix],
+                deterministic,
+                &mut visited,
+                &mut state,
+                &mut |reg, frag, frag_metrics| {
+                    emit_range_frag(
+                        &mut result_map,
+                        &mut result_frags,
+                        &mut result_frag_metrics,
+                        num_real_regs as u32,
+                        reg,
+                        &frag,
+                        &frag_metrics,
+                    )
+                },
+            );
+        }
+
+        assert!(state.len() == num_regs);
+        // This is pretty cheap (once per fn) and any failure will be catastrophic since it means
+        // we may have forgotten some live range fragments.  Hence `assert!` and not
+        // `debug_assert!`.
+        for state_elem in &state {
+            assert!(state_elem.is_none());
+        }
     }
 
-    assert!(state.len() == num_regs);
     assert!(result_map.len() == num_regs);
     assert!(vreg_classes.len() == num_virtual_regs);
-    // This is pretty cheap (once per fn) and any failure will be catastrophic since it means we
-    // may have forgotten some live range fragments.  Hence `assert!` and not `debug_assert!`.
-    for state_elem in &state {
-        assert!(state_elem.is_none());
-    }
 
     if log_enabled!(Level::Debug) {
         debug!("");
@@ -1365,7 +1706,7 @@ fn calc_virtual_range_metrics(
     sorted_frag_ixs: &SortedRangeFragIxs,
     frag_env: &TypedIxVec<RangeFragIx, RangeFrag>,
     frag_metrics_env: &TypedIxVec<RangeFragIx, RangeFragMetrics>,
-    estimated_frequencies: &DepthBasedFrequencies,
+    estimated_frequencies: &BlockFrequencies,
 ) -> (u16, u32, SpillCost) {
     assert!(frag_env.len() == frag_metrics_env.len());
 
@@ -1419,7 +1760,7 @@ fn create_and_add_range(
     sorted_frag_ixs: SortedRangeFragIxs,
     frag_env: &TypedIxVec<RangeFragIx, RangeFrag>,
     frag_metrics_env: &TypedIxVec<RangeFragIx, RangeFragMetrics>,
-    estimated_frequencies: &DepthBasedFrequencies,
+    estimated_frequencies: &BlockFrequencies,
 ) {
     if reg.is_virtual() {
         // First, compute the VirtualRange metrics.  This has to be done
@@ -1493,7 +1834,7 @@ pub(crate) fn merge_range_frags(
     frag_ix_vec_per_reg: &Vec</*rreg index, then vreg index, */ SmallVec<[RangeFragIx; 8]>>,
     frag_env: &TypedIxVec<RangeFragIx, RangeFrag>,
     frag_metrics_env: &TypedIxVec<RangeFragIx, RangeFragMetrics>,
-    estimated_frequencies: &DepthBasedFrequencies,
+    estimated_frequencies: &BlockFrequencies,
     cfg_info: &CFGInfo,
     reg_universe: &RealRegUniverse,
     vreg_classes: &Vec</*vreg index,*/ RegClass>,
@@ -1901,7 +2242,7 @@ pub(crate) fn compute_reg_to_ranges_maps<F: Function>(
 pub(crate) fn collect_move_info<F: Function>(
     func: &F,
     reg_vecs_and_bounds: &RegVecsAndBounds,
-    estimated_frequency: &DepthBasedFrequencies,
+    estimated_frequency: &BlockFrequencies,
 ) -> MoveInfo {
     let mut moves = Vec::new();
     for b in func.blocks() {
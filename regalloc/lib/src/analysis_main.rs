@@ -6,20 +6,24 @@ use crate::data_structures::*;
 use crate::sparse_set::SparseSet;
 use crate::AlgorithmWithDefaults;
 use crate::{
-    analysis_control_flow::{CFGInfo, InstIxToBlockIxMap},
+    analysis_control_flow::{
+        check_function_size, check_num_range_frags, CFGInfo, InstIxToBlockIxMap,
+    },
     analysis_reftypes::ReftypeAnalysis,
 };
 use crate::{
     analysis_data_flow::{
-        calc_def_and_use, calc_livein_and_liveout, collect_move_info, compute_reg_to_ranges_maps,
-        get_range_frags, get_sanitized_reg_uses_for_func, merge_range_frags,
+        add_block_params_to_def_and_use, add_block_params_to_livein_and_liveout, calc_def_and_use,
+        calc_livein_and_liveout, check_block_params, check_no_block_params, collect_move_info,
+        compute_reg_to_ranges_maps, get_range_frags, get_sanitized_reg_uses_for_func,
+        merge_range_frags,
     },
     analysis_reftypes::core_reftypes_analysis,
 };
 use crate::{Function, Reg};
 use alloc::format;
-use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use core::fmt;
 
 //=============================================================================
 // Overall analysis return results, for both control- and data-flow analyses.
@@ -50,7 +54,8 @@ pub enum AnalysisError {
     UnreachableBlocks,
 
     /// Implementation limits exceeded.  The incoming function is too big.  It
-    /// may contain at most 1 million basic blocks and 16 million instructions.
+    /// may contain at most 1 million basic blocks and 16 million instructions,
+    /// or 256 million of each with `Options::large_functions`.
     ImplementationLimitsExceeded,
 
     /// Linear scan requires that if a block ends with a control flow
@@ -63,13 +68,38 @@ pub enum AnalysisError {
     /// For details, see the comment in linear_scan::analysis generating this
     /// error.
     LsraCriticalEdge { block: BlockIx, inst: InstIx },
+
+    /// The function has block parameters, but the selected algorithm doesn't support them.  Only
+    /// the chordal allocator does.
+    UnsupportedBlockParams(BlockIx),
+
+    /// A virtual register is defined more than once, counting block parameters as definitions.
+    /// The chordal allocator requires its input to be in SSA form.
+    NotInSsaForm(VirtualReg),
+
+    /// The arguments that a block passes to a successor don't match the successor's block
+    /// parameters, either in number or in register class.
+    BlockArgsMismatch { from: BlockIx, to: BlockIx },
+
+    /// The chordal allocator requires that if a block ends with a control flow instruction that
+    /// has at least one register mention (use, mod or def), then the successor blocks that have
+    /// parameters must have a single predecessor.  That's because the copies to the block
+    /// parameters are inserted before that instruction otherwise.
+    ChordalCriticalEdge { block: BlockIx, inst: InstIx },
+
+    /// The client's `block_frequencies` don't provide exactly one frequency per block.
+    BlockFrequenciesMismatch,
+
+    /// The real register of a pinned virtual register is written by something else while the
+    /// virtual register is live; see `Function::pinned_regs`.
+    PinnedRegConflict { vreg: VirtualReg, rreg: RealReg },
 }
 
-impl ToString for AnalysisError {
-    fn to_string(&self) -> String {
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             AnalysisError::CriticalEdge { from, to } => {
-                format!("critical edge detected, from {:?} to {:?}", from, to)
+                write!(f, "critical edge detected, from {:?} to {:?}", from, to)
             }
             AnalysisError::EntryLiveinValues(regs) => {
                 let regs_string = regs
@@ -77,39 +107,86 @@ impl ToString for AnalysisError {
                     .map(|reg| format!("{:?}", reg))
                     .collect::<Vec<_>>()
                     .join(", ");
-                format!(
+                write!(
+                    f,
                     "entry block has love-in value not present in function liveins: {}",
                     regs_string
                 )
             }
             AnalysisError::IllegalRealReg(reg) => {
-                format!(
+                write!(
+                    f,
                     "instructions mention real register {:?}, which either isn't defined in the
                     register universe, or is a 'suggested_scratch' register",
                     reg
                 )
             }
-            AnalysisError::UnreachableBlocks => "at least one block is unreachable".to_string(),
-            AnalysisError::ImplementationLimitsExceeded => {
-                "implementation limits exceeded (more than 1 million blocks or 16 million insns)"
-                    .to_string()
-            }
+            AnalysisError::UnreachableBlocks => write!(f, "at least one block is unreachable"),
+            AnalysisError::ImplementationLimitsExceeded => write!(
+                f,
+                "implementation limits exceeded (more than 1 million blocks or 16 million insns, \
+                 or 256 million of either with large functions enabled)"
+            ),
             AnalysisError::LsraCriticalEdge { block, inst } => {
-                format!(
+                write!(
+                    f,
                     "block {:?} ends with control flow instruction {:?} that mentions a register,
                     and at least one of the multiple successors has several predecessors; consider
                     splitting the outgoing edges!",
                     block, inst
                 )
             }
+            AnalysisError::UnsupportedBlockParams(block) => {
+                write!(
+                    f,
+                    "block {:?} has parameters, which only the chordal allocator supports",
+                    block
+                )
+            }
+            AnalysisError::NotInSsaForm(vreg) => {
+                write!(f, "virtual register {:?} is defined more than once", vreg)
+            }
+            AnalysisError::BlockArgsMismatch { from, to } => {
+                write!(
+                    f,
+                    "the arguments passed from block {:?} don't match the parameters of block {:?}",
+                    from, to
+                )
+            }
+            AnalysisError::ChordalCriticalEdge { block, inst } => {
+                write!(
+                    f,
+                    "block {:?} ends with control flow instruction {:?} that mentions a register,
+                    and passes arguments to a successor with several predecessors; consider
+                    splitting the outgoing edges!",
+                    block, inst
+                )
+            }
+            AnalysisError::BlockFrequenciesMismatch => write!(
+                f,
+                "the client's block frequencies don't match the number of blocks"
+            ),
+            AnalysisError::PinnedRegConflict { vreg, rreg } => {
+                write!(
+                    f,
+                    "real register {:?} is overwritten while virtual register {:?}, which is pinned
+                    to it, is live",
+                    rreg, vreg
+                )
+            }
         }
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for AnalysisError {}
+
 //=============================================================================
 // Top level for all analysis activities.
 
 pub struct AnalysisInfo {
+    /// The control flow analysis, which `update_analysis` keeps if the blocks don't change
+    pub(crate) cfg_info: CFGInfo,
     /// The sanitized per-insn reg-use info
     pub(crate) reg_vecs_and_bounds: RegVecsAndBounds,
     /// The real-reg live ranges
@@ -121,7 +198,11 @@ pub struct AnalysisInfo {
     /// The fragment metrics table
     pub(crate) range_metrics: TypedIxVec<RangeFragIx, RangeFragMetrics>,
     /// Estimated execution frequency per block
-    pub(crate) estimated_frequencies: DepthBasedFrequencies,
+    pub(crate) estimated_frequencies: BlockFrequencies,
+    /// The registers live into each block
+    pub(crate) livein_sets_per_block: TypedIxVec<BlockIx, SparseSet<Reg>>,
+    /// The registers live out of each block
+    pub(crate) liveout_sets_per_block: TypedIxVec<BlockIx, SparseSet<Reg>>,
     /// Maps InstIxs to BlockIxs
     pub(crate) inst_to_block_map: InstIxToBlockIxMap,
     /// Maps from RealRegs to sets of RealRanges and VirtualRegs to sets of VirtualRanges
@@ -141,6 +222,8 @@ pub fn run_analysis<F: Function>(
     client_wants_stackmaps: bool,
     reftype_class: RegClass,
     reftyped_vregs: &Vec<VirtualReg>, // as supplied by the client
+    reserve_scratches: bool,
+    deterministic: bool,
 ) -> Result<AnalysisInfo, AnalysisError> {
     info!("run_analysis: begin");
     info!(
@@ -149,83 +232,177 @@ pub fn run_analysis<F: Function>(
         func.insns().len()
     );
 
-    // LSRA uses its own analysis.
-    assert!(!client_wants_stackmaps || algorithm != AlgorithmWithDefaults::LinearScan);
-
     info!("  run_analysis: begin control flow analysis");
 
     // First do control flow analysis.  This is (relatively) simple.  Note that
     // this can fail, for various reasons; we propagate the failure if so.
     let cfg_info = CFGInfo::create(func)?;
 
-    // Create the InstIx-to-BlockIx map.  This isn't really control-flow
-    // analysis, but needs to be done at some point.
-    let inst_to_block_map = InstIxToBlockIxMap::new(func);
-
-    // Annotate each Block with its estimated execution frequency.
-    let estimated_frequencies = DepthBasedFrequencies::new(func, &cfg_info);
-
-    info!("  run_analysis: end control flow analysis");
-
-    // Now perform dataflow analysis.  This is somewhat more complex.
-    info!("  run_analysis: begin data flow analysis");
-
-    // See `get_sanitized_reg_uses_for_func` for the meaning of "sanitized".
-    let reg_vecs_and_bounds = get_sanitized_reg_uses_for_func(func, reg_universe)
-        .map_err(|reg| AnalysisError::IllegalRealReg(reg))?;
-    assert!(reg_vecs_and_bounds.is_sanitized());
+    let res = run_analysis_with_cfg(
+        func,
+        cfg_info,
+        reg_universe,
+        algorithm,
+        client_wants_stackmaps,
+        reftype_class,
+        reftyped_vregs,
+        reserve_scratches,
+        deterministic,
+    );
+    info!("run_analysis: end");
+    res
+}
 
-    // Calculate block-local def/use sets.
-    let (def_sets_per_block, use_sets_per_block) =
-        calc_def_and_use(func, &reg_vecs_and_bounds, &reg_universe);
-    debug_assert!(def_sets_per_block.len() == func.blocks().len() as u32);
-    debug_assert!(use_sets_per_block.len() == func.blocks().len() as u32);
+/// Redo the analysis `prev` of `func` after the client changed it, e.g. by inserting
+/// instructions, with the same parameters as `run_analysis`.
+///
+/// If `func` still has the blocks and edges `prev` was computed from, its control flow analysis
+/// (the predecessors, block orders and loop depths) and the estimated block frequencies derived
+/// from it are kept, and only the data flow analysis and live ranges are recomputed.  This is the
+/// case when instructions were inserted into or removed from existing blocks, without changing
+/// their branches.  Otherwise, e.g. when blocks were inserted, this is the same as
+/// `run_analysis`.
+#[inline(never)]
+pub fn update_analysis<F: Function>(
+    func: &F,
+    prev: AnalysisInfo,
+    reg_universe: &RealRegUniverse,
+    algorithm: AlgorithmWithDefaults,
+    client_wants_stackmaps: bool,
+    reftype_class: RegClass,
+    reftyped_vregs: &Vec<VirtualReg>, // as supplied by the client
+    reserve_scratches: bool,
+    deterministic: bool,
+) -> Result<AnalysisInfo, AnalysisError> {
+    info!("update_analysis: begin");
+    info!(
+        "  update_analysis: {} blocks, {} insns",
+        func.blocks().len(),
+        func.insns().len()
+    );
 
-    // Calculate live-in and live-out sets per block, using the traditional
-    // iterate-to-a-fixed-point scheme.
+    let cfg_info = if prev.cfg_info.matches(func) {
+        info!("  update_analysis: keeping control flow analysis");
+        // The instructions may have grown past what `CFGInfo::create` accepts.
+        check_function_size(func, /* large_functions = */ true)?;
+        prev.cfg_info
+    } else {
+        info!("  update_analysis: redoing control flow analysis");
+        CFGInfo::create(func)?
+    };
 
-    // `liveout_sets_per_block` is amended below for return blocks, hence `mut`.
-    let (livein_sets_per_block, mut liveout_sets_per_block) = calc_livein_and_liveout(
+    let res = run_analysis_with_cfg(
         func,
-        &def_sets_per_block,
-        &use_sets_per_block,
-        &cfg_info,
-        &reg_universe,
+        cfg_info,
+        reg_universe,
+        algorithm,
+        client_wants_stackmaps,
+        reftype_class,
+        reftyped_vregs,
+        reserve_scratches,
+        deterministic,
     );
-    debug_assert!(livein_sets_per_block.len() == func.blocks().len() as u32);
-    debug_assert!(liveout_sets_per_block.len() == func.blocks().len() as u32);
+    info!("update_analysis: end");
+    res
+}
 
-    // Verify livein set of entry block against liveins specified by function
-    // (e.g., ABI params).
-    let func_liveins = SparseSet::from_vec(
-        func.func_liveins()
-            .to_vec()
-            .into_iter()
-            .map(|rreg| rreg.to_reg())
-            .collect(),
-    );
-    if !livein_sets_per_block[func.entry_block()].is_subset_of(&func_liveins) {
-        let mut regs = livein_sets_per_block[func.entry_block()].clone();
-        regs.remove(&func_liveins);
-        return Err(AnalysisError::EntryLiveinValues(regs.to_vec()));
-    }
+/// The part of the analysis of `func` following the control flow analysis `cfg_info`.
+fn run_analysis_with_cfg<F: Function>(
+    func: &F,
+    cfg_info: CFGInfo,
+    reg_universe: &RealRegUniverse,
+    algorithm: AlgorithmWithDefaults,
+    client_wants_stackmaps: bool,
+    reftype_class: RegClass,
+    reftyped_vregs: &Vec<VirtualReg>,
+    reserve_scratches: bool,
+    deterministic: bool,
+) -> Result<AnalysisInfo, AnalysisError> {
+    // Only the chordal allocator accepts functions in SSA form with block parameters.
+    let ssa_form = algorithm == AlgorithmWithDefaults::Chordal;
+    let common = CommonAnalysis::new(func, cfg_info, reg_universe, ssa_form, reserve_scratches)?;
+    run_analysis_with_common(
+        func,
+        common,
+        reg_universe,
+        algorithm,
+        client_wants_stackmaps,
+        reftype_class,
+        reftyped_vregs,
+        deterministic,
+    )
+}
 
-    // Add function liveouts to every block ending in a return.
-    let func_liveouts = SparseSet::from_vec(
-        func.func_liveouts()
-            .to_vec()
-            .into_iter()
-            .map(|rreg| rreg.to_reg())
-            .collect(),
-    );
-    for block in func.blocks() {
-        let last_iix = func.block_insns(block).last();
-        if func.is_ret(last_iix) {
-            liveout_sets_per_block[block].union(&func_liveouts);
-        }
+/// The parts of the analysis of a function that don't depend on the allocator: its control flow
+/// analysis, the execution frequencies of its blocks and the liveness of its registers.  The
+/// backtracking and linear scan allocators can start from the same one; see
+/// `compare_allocators`.
+#[derive(Clone)]
+pub(crate) struct CommonAnalysis {
+    pub(crate) cfg_info: CFGInfo,
+    pub(crate) inst_to_block_map: InstIxToBlockIxMap,
+    pub(crate) estimated_frequencies: BlockFrequencies,
+    pub(crate) liveness: Liveness,
+}
+
+impl CommonAnalysis {
+    /// Analyses `func`, given its control flow analysis `cfg_info`.  `ssa_form` and
+    /// `reserve_scratches` are as for `run_liveness_analysis`.
+    pub(crate) fn new<F: Function>(
+        func: &F,
+        cfg_info: CFGInfo,
+        reg_universe: &RealRegUniverse,
+        ssa_form: bool,
+        reserve_scratches: bool,
+    ) -> Result<Self, AnalysisError> {
+        // Create the InstIx-to-BlockIx map.  This isn't really control-flow
+        // analysis, but needs to be done at some point.
+        let inst_to_block_map = InstIxToBlockIxMap::new(func);
+
+        // Annotate each Block with its (possibly estimated) execution frequency.
+        let estimated_frequencies = BlockFrequencies::new(func, &cfg_info)?;
+
+        info!("  run_analysis: end control flow analysis");
+
+        // Now perform dataflow analysis.  This is somewhat more complex.
+        info!("  run_analysis: begin data flow analysis");
+
+        let liveness =
+            run_liveness_analysis(func, reg_universe, &cfg_info, ssa_form, reserve_scratches)?;
+
+        info!("  run_analysis: end data flow analysis");
+
+        Ok(Self {
+            cfg_info,
+            inst_to_block_map,
+            estimated_frequencies,
+            liveness,
+        })
     }
+}
 
-    info!("  run_analysis: end data flow analysis");
+/// The part of the analysis of `func` following the allocator-independent analysis `common`.
+pub(crate) fn run_analysis_with_common<F: Function>(
+    func: &F,
+    common: CommonAnalysis,
+    reg_universe: &RealRegUniverse,
+    algorithm: AlgorithmWithDefaults,
+    client_wants_stackmaps: bool,
+    reftype_class: RegClass,
+    reftyped_vregs: &Vec<VirtualReg>,
+    deterministic: bool,
+) -> Result<AnalysisInfo, AnalysisError> {
+    let CommonAnalysis {
+        cfg_info,
+        inst_to_block_map,
+        estimated_frequencies,
+        liveness:
+            Liveness {
+                reg_vecs_and_bounds,
+                livein_sets_per_block,
+                liveout_sets_per_block,
+            },
+    } = common;
 
     // Dataflow analysis is now complete.  Now compute the virtual and real live
     // ranges, in two steps: (1) compute RangeFrags, and (2) merge them
@@ -239,7 +416,9 @@ pub fn run_analysis<F: Function>(
         &reg_universe,
         &livein_sets_per_block,
         &liveout_sets_per_block,
+        deterministic,
     );
+    check_num_range_frags(frag_env.elems().len())?;
 
     // These have to be mut because they may get changed below by the call to
     // `to_reftypes_analysis`.
@@ -276,9 +455,10 @@ pub fn run_analysis<F: Function>(
     // Now a bit of auxiliary info collection, which isn't really either control- or data-flow
     // analysis.
 
-    // For BT and/or reftypes, we'll also need the reg-to-ranges maps and information about moves.
+    // For BT, graph coloring and/or reftypes, we'll also need the reg-to-ranges maps and
+    // information about moves.
     let (reg_to_ranges_maps, move_info) =
-        if client_wants_stackmaps || algorithm == AlgorithmWithDefaults::Backtracking {
+        if client_wants_stackmaps || algorithm != AlgorithmWithDefaults::LinearScan {
             (
                 Some(compute_reg_to_ranges_maps(
                     func,
@@ -312,26 +492,146 @@ pub fn run_analysis<F: Function>(
         info!("  run_analysis: end reftypes analysis");
     }
 
-    info!("run_analysis: end");
-
     Ok(AnalysisInfo {
+        cfg_info,
         reg_vecs_and_bounds,
         real_ranges: rlr_env,
         virtual_ranges: vlr_env,
         range_frags: frag_env,
         range_metrics: frag_metrics_env,
         estimated_frequencies,
+        livein_sets_per_block,
+        liveout_sets_per_block,
         inst_to_block_map,
         reg_to_ranges_maps,
         move_info,
     })
 }
 
-/// A small wrapper for estimated execution frequencies, based on the block's loop depth.
-pub(crate) struct DepthBasedFrequencies(TypedIxVec<BlockIx, u32>);
+/// The results of the data flow analysis of a function: the sanitized register uses of its
+/// instructions, and the registers live into and out of each of its blocks.
+#[derive(Clone)]
+pub(crate) struct Liveness {
+    pub(crate) reg_vecs_and_bounds: RegVecsAndBounds,
+    pub(crate) livein_sets_per_block: TypedIxVec<BlockIx, SparseSet<Reg>>,
+    pub(crate) liveout_sets_per_block: TypedIxVec<BlockIx, SparseSet<Reg>>,
+}
+
+/// Compute the live-in and live-out sets of each block of `func`.  If `ssa_form` is set, the
+/// block parameters of `func` are checked and taken into account; otherwise `func` must not
+/// have any.
+#[inline(never)]
+pub(crate) fn run_liveness_analysis<F: Function>(
+    func: &F,
+    reg_universe: &RealRegUniverse,
+    cfg_info: &CFGInfo,
+    ssa_form: bool,
+    reserve_scratches: bool,
+) -> Result<Liveness, AnalysisError> {
+    // See `get_sanitized_reg_uses_for_func` for the meaning of "sanitized".
+    let reg_vecs_and_bounds =
+        get_sanitized_reg_uses_for_func(func, reg_universe, reserve_scratches)
+            .map_err(|reg| AnalysisError::IllegalRealReg(reg))?;
+    assert!(reg_vecs_and_bounds.is_sanitized());
+
+    if ssa_form {
+        check_block_params(func, &reg_vecs_and_bounds, cfg_info)?;
+    } else {
+        check_no_block_params(func)?;
+    }
+
+    // Calculate block-local def/use sets.
+    let (mut def_sets_per_block, mut use_sets_per_block) =
+        calc_def_and_use(func, &reg_vecs_and_bounds, &reg_universe);
+    debug_assert!(def_sets_per_block.len() == func.blocks().len() as u32);
+    debug_assert!(use_sets_per_block.len() == func.blocks().len() as u32);
+
+    let live_params_per_block = if ssa_form {
+        Some(add_block_params_to_def_and_use(
+            func,
+            &mut def_sets_per_block,
+            &mut use_sets_per_block,
+        ))
+    } else {
+        None
+    };
+
+    // Calculate live-in and live-out sets per block, using the traditional
+    // iterate-to-a-fixed-point scheme.
+
+    // `liveout_sets_per_block` is amended below for return blocks, hence `mut`.
+    let (mut livein_sets_per_block, mut liveout_sets_per_block) = calc_livein_and_liveout(
+        func,
+        &def_sets_per_block,
+        &use_sets_per_block,
+        cfg_info,
+        &reg_universe,
+    );
+    debug_assert!(livein_sets_per_block.len() == func.blocks().len() as u32);
+    debug_assert!(liveout_sets_per_block.len() == func.blocks().len() as u32);
+
+    if let Some(live_params_per_block) = live_params_per_block {
+        add_block_params_to_livein_and_liveout(
+            func,
+            live_params_per_block,
+            &mut livein_sets_per_block,
+            &mut liveout_sets_per_block,
+        );
+    }
+
+    // Verify livein set of entry block against liveins specified by function
+    // (e.g., ABI params).
+    let func_liveins = SparseSet::from_vec(
+        func.func_liveins()
+            .to_vec()
+            .into_iter()
+            .map(|rreg| rreg.to_reg())
+            .collect(),
+    );
+    if !livein_sets_per_block[func.entry_block()].is_subset_of(&func_liveins) {
+        let mut regs = livein_sets_per_block[func.entry_block()].clone();
+        regs.remove(&func_liveins);
+        return Err(AnalysisError::EntryLiveinValues(regs.to_vec()));
+    }
+
+    // Add function liveouts to every block ending in a return.
+    let func_liveouts = SparseSet::from_vec(
+        func.func_liveouts()
+            .to_vec()
+            .into_iter()
+            .map(|rreg| rreg.to_reg())
+            .collect(),
+    );
+    for block in func.blocks() {
+        let last_iix = func.block_insns(block).last();
+        if func.is_ret(last_iix) {
+            liveout_sets_per_block[block].union(&func_liveouts);
+        }
+    }
+
+    Ok(Liveness {
+        reg_vecs_and_bounds,
+        livein_sets_per_block,
+        liveout_sets_per_block,
+    })
+}
+
+/// A small wrapper for the execution frequencies of the blocks.  These are supplied by the
+/// client if it has profile data, and otherwise estimated from the block's loop depth.
+#[derive(Clone)]
+pub(crate) struct BlockFrequencies(TypedIxVec<BlockIx, u32>);
+
+impl BlockFrequencies {
+    pub(crate) fn new<F: Function>(func: &F, cfg_info: &CFGInfo) -> Result<Self, AnalysisError> {
+        if let Some(frequencies) = func.block_frequencies() {
+            if frequencies.len() != func.blocks().len() {
+                return Err(AnalysisError::BlockFrequenciesMismatch);
+            }
+            // A zero cost would make spill costs vanish, which the allocators don't expect.
+            let values = frequencies.iter().map(|&freq| u32::max(freq, 1)).collect();
+            return Ok(Self(TypedIxVec::from_vec(values)));
+        }
 
-impl DepthBasedFrequencies {
-    pub(crate) fn new<F: Function>(func: &F, cfg_info: &CFGInfo) -> Self {
         let mut values = TypedIxVec::new();
         for bix in func.blocks() {
             let mut estimated_frequency = 1;
@@ -342,7 +642,7 @@ impl DepthBasedFrequencies {
             assert!(bix == BlockIx::new(values.len()));
             values.push(estimated_frequency);
         }
-        Self(values)
+        Ok(Self(values))
     }
     pub(crate) fn len(&self) -> u32 {
         self.0.len()
@@ -4,12 +4,16 @@
 //! Core implementation of the backtracking allocator.
 
 use alloc::fmt;
+use alloc::sync::Arc;
 use core::default;
 use log::{debug, info, log_enabled, Level};
 use smallvec::SmallVec;
 
+use crate::alloc_dump::{self, DumpedRange};
 use crate::analysis_data_flow::{add_raw_reg_vecs_for_insn, does_inst_use_def_or_mod_reg};
-use crate::analysis_main::{run_analysis, AnalysisInfo};
+use crate::analysis_main::{
+    run_analysis, run_analysis_with_common, AnalysisInfo, BlockFrequencies, CommonAnalysis,
+};
 use crate::avl_tree::{AVLTree, AVL_NULL};
 use crate::bt_coalescing_analysis::{do_coalescing_analysis, Hint};
 use crate::bt_commitment_map::{CommitmentMap, RangeFragAndRangeId};
@@ -22,11 +26,15 @@ use crate::data_structures::{
     VirtualReg, Writable,
 };
 use crate::inst_stream::{
-    edit_inst_stream, ExtPoint, InstExtPoint, InstToInsert, InstToInsertAndExtPoint,
+    edit_inst_stream, ExtPoint, InstExtPoint, InstToInsert, InstToInsertAndExtPoint, ValueLoc,
 };
-use crate::sparse_set::SparseSetU;
+use crate::real_reg_set::RealRegBitSet;
+use crate::sparse_set::{SparseSet, SparseSetU};
+use crate::spill_cost::{apply_spill_cost_model, SpillCostModel};
 use crate::union_find::UnionFindEquivClasses;
-use crate::{AlgorithmWithDefaults, Function, RegAllocError, RegAllocResult, StackmapRequestInfo};
+use crate::{
+    AlgorithmWithDefaults, Function, RegAllocError, RegAllocResult, RegHint, StackmapRequestInfo,
+};
 use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec;
@@ -36,22 +44,75 @@ use alloc::vec::Vec;
 pub struct BacktrackingOptions {
     /// Should the register allocator generate block annotations?
     pub request_block_annotations: bool,
+
+    /// Should the `suggested_scratch` registers be handed out like any other register?  The
+    /// backtracking allocator never needs a scratch register of its own: values only ever move
+    /// between registers and spill slots, so there are no register-to-register move cycles to
+    /// break, and any temporary it needs lives on the stack.  When this is set, the incoming code
+    /// may freely use, def or mod the suggested scratch registers, which lets clients on
+    /// register-starved targets make every register allocatable for this function.
+    pub scratch_free: bool,
+
+    /// Instructions at which some virtual registers must have a copy of their value in memory,
+    /// in ascending order of instruction index.  See `MemoryPin`.
+    pub memory_pins: Vec<MemoryPin>,
+
+    /// The model of the cost of spilling virtual registers, which decides which ones get evicted
+    /// and spilled first.  See `SpillCostModel`; `None` uses the allocator's own model.
+    pub spill_cost_model: Option<Arc<dyn SpillCostModel + Send + Sync>>,
+
+    /// The largest number of virtual ranges the main allocation loop may process, or `None` for
+    /// no limit.  Evicted ranges and the ranges created by spilling count again when they are
+    /// processed.  Pathological functions can take the allocator a long time, so this bounds the
+    /// time it takes: when the fuel runs out, the function is allocated by the linear scan
+    /// allocator instead, with its default options but the same `scratch_free`.  Linear scan
+    /// can't honour memory pins, so with memory pins, running out of fuel is reported as
+    /// `RegAllocError::OutOfFuel`.
+    pub fuel: Option<u32>,
 }
 
 impl default::Default for BacktrackingOptions {
     fn default() -> Self {
         Self {
             request_block_annotations: false,
+            scratch_free: false,
+            memory_pins: vec![],
+            spill_cost_model: None,
+            fuel: None,
         }
     }
 }
 
+/// A request that, while instruction `iix` executes, each of `vregs` that is live across it can
+/// be found in a spill slot.  Exception-handling runtimes need this for calls that may unwind:
+/// the unwinder can't recover values that only live in registers clobbered by the callee.
+///
+/// A pinned vreg that is held in a register at `iix` is additionally stored to a spill slot
+/// immediately before `iix`; one that is already spilled at `iix` is left where it is.  Either
+/// way, the slot is reported back in `RegAllocResult::memory_pin_slots`.
+///
+/// Only the backtracking allocator honours memory pins: where another allocator would allocate
+/// the function instead, the allocation fails with `RegAllocError::OutOfFuel` or
+/// `RegAllocError::UnsupportedMemoryPins`.
+#[derive(Clone, Debug)]
+pub struct MemoryPin {
+    /// The instruction, in the incoming instruction stream.
+    pub iix: InstIx,
+    /// The virtual registers whose value must be in memory across `iix`.
+    pub vregs: Vec<VirtualReg>,
+}
+
 impl fmt::Debug for BacktrackingOptions {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(
             fmt,
-            "backtracking (block annotations: {})",
-            self.request_block_annotations
+            "backtracking (block annotations: {}, scratch free: {}, memory pins: {}, \
+             client spill cost model: {}, fuel: {:?})",
+            self.request_block_annotations,
+            self.scratch_free,
+            self.memory_pins.len(),
+            self.spill_cost_model.is_some(),
+            self.fuel
         )
     }
 }
@@ -62,7 +123,7 @@ impl fmt::Debug for BacktrackingOptions {
 // Relevant methods are expected to be parameterised by the same VirtualRange
 // env as used in calls to `VirtualRangePrioQ`.
 
-struct PerRealReg {
+pub(crate) struct PerRealReg {
     // The current committed fragments for this RealReg.
     committed: CommitmentMap,
 
@@ -74,7 +135,7 @@ struct PerRealReg {
     vlrixs_assigned: Set<VirtualRangeIx>,
 }
 impl PerRealReg {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             committed: CommitmentMap::new(),
             vlrixs_assigned: Set::<VirtualRangeIx>::empty(),
@@ -82,7 +143,7 @@ impl PerRealReg {
     }
 
     #[inline(never)]
-    fn add_RealRange(
+    pub(crate) fn add_RealRange(
         &mut self,
         to_add_rlrix: RealRangeIx,
         rlr_env: &TypedIxVec<RealRangeIx, RealRange>,
@@ -100,7 +161,7 @@ impl PerRealReg {
     }
 
     #[inline(never)]
-    fn add_VirtualRange(
+    pub(crate) fn add_VirtualRange(
         &mut self,
         to_add_vlrix: VirtualRangeIx,
         vlr_env: &TypedIxVec<VirtualRangeIx, VirtualRange>,
@@ -113,7 +174,7 @@ impl PerRealReg {
     }
 
     #[inline(never)]
-    fn del_VirtualRange(
+    pub(crate) fn del_VirtualRange(
         &mut self,
         to_del_vlrix: VirtualRangeIx,
         vlr_env: &TypedIxVec<VirtualRangeIx, VirtualRange>,
@@ -273,7 +334,7 @@ impl PerRealReg {
     //   non-infinite-cost eviction candidates.  This is by design (so as to
     //   guarantee that we can always allocate spill/reload bridges).
     #[inline(never)]
-    fn find_evict_set<IsAllowedToEvict>(
+    pub(crate) fn find_evict_set<IsAllowedToEvict>(
         &self,
         would_like_to_add: VirtualRangeIx,
         allowed_to_evict: &IsAllowedToEvict,
@@ -391,7 +452,8 @@ fn print_RA_state(
 
 // This creates the artefacts for a safepoint/stackmap at some insn `iix`: the set of reftyped
 // spill slots, the spills to be placed at `iix.r` (yes, you read that right) and the reloads to
-// be placed at `iix.s`.
+// be placed at `iix.s`.  If `report_regs` is set, the reftyped real regs live at `iix.u` are
+// returned instead of being spilled and reloaded around `iix`.
 //
 // This consults:
 //
@@ -410,7 +472,16 @@ fn get_stackmap_artefacts_at(
     rlr_env: &TypedIxVec<RealRangeIx, RealRange>,
     vlr_env: &TypedIxVec<VirtualRangeIx, VirtualRange>,
     iix: InstIx,
-) -> Result<(Vec<InstToInsert>, Vec<InstToInsert>, Vec<SpillSlot>), RegAllocError> {
+    report_regs: bool,
+) -> Result<
+    (
+        Vec<InstToInsert>,
+        Vec<InstToInsert>,
+        Vec<SpillSlot>,
+        Vec<RealReg>,
+    ),
+    RegAllocError,
+> {
     // From a code generation perspective, what we need to compute is:
     //
     // * Sbefore: real regs that are live at `iix.u`, that are reftypes
@@ -449,7 +520,7 @@ fn get_stackmap_artefacts_at(
     // FIXME change this to SparseSet
     let mut s_before = Set::<RealReg>::empty();
 
-    let rci = univ.allocable_by_class[reftype_class.rc_to_usize()];
+    let rci = univ.class_info(reftype_class);
     if rci.is_none() {
         return Err(RegAllocError::Other(
             "stackmap request: no regs in specified reftype class".to_string(),
@@ -510,6 +581,18 @@ fn get_stackmap_artefacts_at(
 
     debug!("Safter = {:?}", s_before);
 
+    if report_regs {
+        // The reftyped regs stay where they are; the client will find them in the stackmap.
+        let mut reftyped_regs: Vec<RealReg> = s_before.iter().cloned().collect();
+        reftyped_regs.sort_unstable();
+        let reftyped_spillslots = spill_slot_allocator.get_reftyped_spillslots_at_inst_point(pt);
+        debug!(
+            "reftyped_spillslots = {:?}, reftyped_regs = {:?}",
+            reftyped_spillslots, reftyped_regs
+        );
+        return Ok((vec![], vec![], reftyped_spillslots, reftyped_regs));
+    }
+
     // Create the spill insns, as defined by Sbefore.  This has the side effect of recording the
     // spill in `spill_slot_allocator`, so we can later ask it to tell us all the reftyped spill
     // slots.
@@ -557,7 +640,102 @@ fn get_stackmap_artefacts_at(
 
     // And we're done!
 
-    Ok((spill_insns, reload_insns, reftyped_spillslots))
+    Ok((spill_insns, reload_insns, reftyped_spillslots, vec![]))
+}
+
+//=============================================================================
+// Memory pinning support
+
+// This creates the artefacts for a `MemoryPin` at some insn `iix`: the spills to be placed at
+// `iix.sb`, and the spill slot holding each pinned vreg that is live at `iix.u`.
+//
+// This consults:
+//
+// * the commitment maps, to figure out which pinned vregs are in a real register at `iix.u`.
+//   Those get stored to a fresh spill slot that is live from `iix.r` to `iix.s`.  Since the
+//   register still holds the value after the store, no reload is needed afterwards.
+//
+// * the spill slot assignments of VirtualRanges, for pinned vregs which are already spilled at
+//   `iix.u` and hence need no extra code at all.
+//
+// Pinned vregs that aren't live at `iix.u` don't get a slot.
+
+fn get_memory_pin_artefacts_at<F: Function>(
+    func: &F,
+    spill_slot_allocator: &mut SpillSlotAllocator,
+    univ: &RealRegUniverse,
+    per_real_reg: &Vec<PerRealReg>,
+    vlr_env: &TypedIxVec<VirtualRangeIx, VirtualRange>,
+    vlr_slot_env: &TypedIxVec<VirtualRangeIx, Option<SpillSlot>>,
+    pin: &MemoryPin,
+) -> (Vec<InstToInsert>, Vec<(VirtualReg, SpillSlot)>) {
+    let iix = pin.iix;
+    let pt = InstPoint::new_use(iix);
+    let frag = RangeFrag::new(InstPoint::new_reload(iix), InstPoint::new_spill(iix));
+
+    let mut spill_insns = Vec::<InstToInsert>::new();
+    let mut slots = Vec::<(VirtualReg, SpillSlot)>::new();
+
+    debug!("computing memory pin info at {:?}", pt);
+
+    'pinned_vregs: for vreg in &pin.vregs {
+        let rc = vreg.get_class();
+
+        // Is `vreg` in a real register at `iix.u`?
+        if let Some(rci) = univ.class_info(rc) {
+            for rreg_no in rci.first..rci.last + 1 {
+                let mb_range_id = per_real_reg[rreg_no].committed.lookup_inst_point(pt);
+                let vlrix = match mb_range_id {
+                    Some(range_id) if range_id.is_virtual() => range_id.to_virtual(),
+                    _ => continue,
+                };
+                let vlr = &vlr_env[vlrix];
+                if vlr.vreg != *vreg {
+                    continue;
+                }
+                let req_size = func.get_spillslot_size(rc, *vreg);
+                let req_align = func.get_spillslot_align(rc, *vreg);
+                let to_slot = spill_slot_allocator.alloc_spillslot_for_frag(
+                    frag.clone(),
+                    req_size,
+                    req_align,
+                    vlr.is_ref,
+                );
+                debug!(
+                    " pinned {:?} is in real reg {:?}; storing to {:?}",
+                    vreg, rreg_no, to_slot
+                );
+                spill_insns.push(InstToInsert::Spill {
+                    to_slot,
+                    from_reg: univ.regs[rreg_no].0,
+                    for_vreg: Some(*vreg),
+                });
+                slots.push((*vreg, to_slot));
+                continue 'pinned_vregs;
+            }
+        }
+
+        // Is it spilled at `iix.u`?  VirtualRanges created during allocation don't have a spill
+        // slot of their own, so only those in `vlr_slot_env` can match.
+        for i in 0..vlr_slot_env.len() {
+            let vlrix = VirtualRangeIx::new(i);
+            let vlr = &vlr_env[vlrix];
+            if vlr.vreg != *vreg {
+                continue;
+            }
+            if let Some(slot) = vlr_slot_env[vlrix] {
+                if vlr.sorted_frags.iter().any(|frag| frag.contains(&pt)) {
+                    debug!(" pinned {:?} is already in {:?}", vreg, slot);
+                    slots.push((*vreg, slot));
+                    continue 'pinned_vregs;
+                }
+            }
+        }
+
+        debug!(" pinned {:?} is not live", vreg);
+    }
+
+    (spill_insns, slots)
 }
 
 //=============================================================================
@@ -621,7 +799,7 @@ impl fmt::Debug for BridgeKind {
 }
 
 #[derive(Clone, Copy)]
-struct EditListItem {
+pub(crate) struct EditListItem {
     // This holds enough information to create a spill or reload instruction,
     // or both, and also specifies where in the instruction stream it/they
     // should be added.  Note that if the edit list as a whole specifies
@@ -636,10 +814,10 @@ struct EditListItem {
     // Despite (2) we also carry here the InstIx of the affected instruction
     // (there should be only one) since computing it via (2) is expensive.
     // This however gives a redundancy in representation against (2).  Beware!
-    slot: SpillSlot,
-    vlrix: VirtualRangeIx,
-    kind: BridgeKind,
-    iix: InstIx,
+    pub(crate) slot: SpillSlot,
+    pub(crate) vlrix: VirtualRangeIx,
+    pub(crate) kind: BridgeKind,
+    pub(crate) iix: InstIx,
 }
 
 impl fmt::Debug for EditListItem {
@@ -665,23 +843,47 @@ pub fn alloc_main<F: Function>(
     reg_universe: &RealRegUniverse,
     stackmap_request: Option<&StackmapRequestInfo>,
     use_checker: bool,
+    dump_allocation: bool,
+    annotate: bool,
+    deterministic: bool,
     opts: &BacktrackingOptions,
+    common: Option<CommonAnalysis>,
 ) -> Result<RegAllocResult<F>, RegAllocError> {
     // -------- Initial arrangements for stackmaps --------
     let empty_vec_vregs = vec![];
-    let empty_vec_iixs = vec![];
-    let (client_wants_stackmaps, reftype_class, reftyped_vregs, safepoint_insns) =
-        match stackmap_request {
-            Some(&StackmapRequestInfo {
-                reftype_class,
-                ref reftyped_vregs,
-                ref safepoint_insns,
-            }) => (true, reftype_class, reftyped_vregs, safepoint_insns),
-            None => (false, RegClass::INVALID, &empty_vec_vregs, &empty_vec_iixs),
-        };
+    let (client_wants_stackmaps, reftype_class, reftyped_vregs) = match stackmap_request {
+        Some(&StackmapRequestInfo {
+            reftype_class,
+            ref reftyped_vregs,
+            ..
+        }) => (true, reftype_class, reftyped_vregs),
+        None => (false, RegClass::INVALID, &empty_vec_vregs),
+    };
+
+    // -------- Sanity-check the memory pins --------
+    let num_insns = func.insns().len();
+    let num_vregs = func.get_num_vregs();
+    for (i, pin) in opts.memory_pins.iter().enumerate() {
+        if pin.iix.get() as usize >= num_insns {
+            return Err(RegAllocError::Other(
+                "memory pin: out of range insn".to_string(),
+            ));
+        }
+        if i > 0 && opts.memory_pins[i - 1].iix >= pin.iix {
+            return Err(RegAllocError::Other(
+                "memory pin: non-ascending insns".to_string(),
+            ));
+        }
+        if pin.vregs.iter().any(|vreg| vreg.get_index() >= num_vregs) {
+            return Err(RegAllocError::Other(
+                "memory pin: out of range vreg".to_string(),
+            ));
+        }
+    }
 
     // -------- Perform initial liveness analysis --------
-    // Note that the analysis phase can fail; hence we propagate any error.
+    // Note that the analysis phase can fail; hence we propagate any error.  It starts from
+    // `common`, the allocator-independent part of the analysis, if it has been done already.
     let AnalysisInfo {
         reg_vecs_and_bounds,
         real_ranges: rlr_env,
@@ -689,17 +891,34 @@ pub fn alloc_main<F: Function>(
         range_frags: frag_env,
         range_metrics: frag_metrics_env,
         estimated_frequencies: est_freqs,
+        livein_sets_per_block,
+        liveout_sets_per_block,
         inst_to_block_map,
         reg_to_ranges_maps: mb_reg_to_ranges_maps,
         move_info: mb_move_info,
-    } = run_analysis(
-        func,
-        reg_universe,
-        AlgorithmWithDefaults::Backtracking,
-        client_wants_stackmaps,
-        reftype_class,
-        reftyped_vregs,
-    )
+        ..
+    } = match common {
+        Some(common) => run_analysis_with_common(
+            func,
+            common,
+            reg_universe,
+            AlgorithmWithDefaults::Backtracking,
+            client_wants_stackmaps,
+            reftype_class,
+            reftyped_vregs,
+            deterministic,
+        ),
+        None => run_analysis(
+            func,
+            reg_universe,
+            AlgorithmWithDefaults::Backtracking,
+            client_wants_stackmaps,
+            reftype_class,
+            reftyped_vregs,
+            /*reserve_scratches=*/ !opts.scratch_free,
+            deterministic,
+        ),
+    }
     .map_err(|err| RegAllocError::Analysis(err))?;
 
     assert!(reg_vecs_and_bounds.is_sanitized());
@@ -709,6 +928,17 @@ pub fn alloc_main<F: Function>(
     let reg_to_ranges_maps = mb_reg_to_ranges_maps.unwrap();
     let move_info = mb_move_info.unwrap();
 
+    // Let the client's model decide the spill costs, before they get adjusted for coalescing.
+    if let Some(model) = &opts.spill_cost_model {
+        apply_spill_cost_model(
+            &**model,
+            &reg_vecs_and_bounds,
+            &inst_to_block_map,
+            &est_freqs,
+            &mut vlr_env,
+        );
+    }
+
     // Also perform analysis that finds all coalescing opportunities.
     let coalescing_info = do_coalescing_analysis(
         func,
@@ -805,10 +1035,14 @@ pub fn alloc_main<F: Function>(
     info!("alloc_main:   main allocation loop: begin");
 
     // ======== BEGIN Main allocation loop ========
-    let mut num_vlrs_processed = 0; // stats only
+    let mut num_vlrs_processed = 0; // stats, and the fuel used
     let mut num_vlrs_spilled = 0; // stats only
     let mut num_vlrs_evicted = 0; // stats only
 
+    // The registers in `hinted_regs` below, to de-dup the hints without searching the list, which
+    // gets long for VLRs with many neighbours in wide universes.
+    let mut hinted_set = RealRegBitSet::for_universe(reg_universe);
+
     'main_allocation_loop: loop {
         debug!("-- still TODO          {}", prioQ.len());
         if false {
@@ -833,6 +1067,10 @@ pub fn alloc_main<F: Function>(
             break 'main_allocation_loop;
         }
 
+        if opts.fuel == Some(num_vlrs_processed) {
+            info!("alloc_main:   out of fuel");
+            return Err(RegAllocError::OutOfFuel);
+        }
         num_vlrs_processed += 1;
         let curr_vlrix = mb_curr_vlrix.unwrap();
         let curr_vlr = &vlr_env[curr_vlrix];
@@ -842,7 +1080,6 @@ pub fn alloc_main<F: Function>(
         assert!(curr_vlr.vreg.to_reg().is_virtual());
         assert!(curr_vlr.rreg.is_none());
         let curr_vlr_regclass = curr_vlr.vreg.get_class();
-        let curr_vlr_rc = curr_vlr_regclass.rc_to_usize();
 
         // ====== BEGIN Try to do coalescing ======
         //
@@ -854,6 +1091,7 @@ pub fn alloc_main<F: Function>(
         // that ordering when copying into `hinted_regs`.
         assert!(hints.len() == vlr_env.len());
         let mut hinted_regs = SmallVec::<[RealReg; 8]>::new();
+        hinted_set.clear();
 
         // === BEGIN collect all hints for `curr_vlr` ===
         // `hints` has one entry per VLR, but only for VLRs which existed
@@ -877,7 +1115,7 @@ pub fn alloc_main<F: Function>(
                 // effectively doing the same via repeated lookups in the
                 // CommitmentMaps.
                 if let Some(rreg) = mb_cand {
-                    if !hinted_regs.iter().any(|r| *r == rreg) {
+                    if hinted_set.insert(rreg) {
                         hinted_regs.push(rreg);
                     }
                 }
@@ -912,7 +1150,7 @@ pub fn alloc_main<F: Function>(
                     if vlrix != curr_vlrix {
                         if let Some(rreg) = vlr_env[vlrix].rreg {
                             // Add `rreg` as a cand, if we don't already have it.
-                            if !hinted_regs.iter().any(|r| *r == rreg) {
+                            if hinted_set.insert(rreg) {
                                 hinted_regs.push(rreg);
                             }
                         }
@@ -987,25 +1225,25 @@ pub fn alloc_main<F: Function>(
                 // Ensure forward progress
                 assert!(total_evict_cost.is_less_than(&curr_vlr.spill_cost));
                 // Evict all evictees in the set
-                for vlrix_to_evict in vlrixs_to_evict.iter() {
+                for vlrix_to_evict in vlrixs_to_evict.iter_ordered(deterministic) {
                     // Ensure we're not evicting anything in `curr_vlrix`'s eclass.
                     // This should be guaranteed us by find_evict_set.
                     assert!(
-                        vlrEquivClasses.in_same_equivalence_class(*vlrix_to_evict, curr_vlrix)
+                        vlrEquivClasses.in_same_equivalence_class(vlrix_to_evict, curr_vlrix)
                             != Some(true)
                     );
                     // Evict ..
                     debug!(
                         "--   CO evict          {:?}:  {:?}",
-                        *vlrix_to_evict, &vlr_env[*vlrix_to_evict]
+                        vlrix_to_evict, &vlr_env[vlrix_to_evict]
                     );
-                    per_real_reg[rregNo].del_VirtualRange(*vlrix_to_evict, &vlr_env);
-                    prioQ.add_VirtualRange(&vlr_env, *vlrix_to_evict);
+                    per_real_reg[rregNo].del_VirtualRange(vlrix_to_evict, &vlr_env);
+                    prioQ.add_VirtualRange(&vlr_env, vlrix_to_evict);
                     // Directly modify bits of vlr_env.  This means we have to abandon
                     // the immutable borrow for curr_vlr, but that's OK -- we won't need
                     // it again (in this loop iteration).
-                    debug_assert!(vlr_env[*vlrix_to_evict].rreg.is_some());
-                    vlr_env[*vlrix_to_evict].rreg = None;
+                    debug_assert!(vlr_env[vlrix_to_evict].rreg.is_some());
+                    vlr_env[vlrix_to_evict].rreg = None;
                     num_vlrs_evicted += 1;
                 }
                 // .. and reassign.
@@ -1031,13 +1269,48 @@ pub fn alloc_main<F: Function>(
         // anything -- then stop the search at that point, since searching further
         // is pointless.
 
-        let (first_in_rc, last_in_rc) = match &reg_universe.allocable_by_class[curr_vlr_rc] {
-            &None => {
+        let (first_in_rc, last_in_rc) = match reg_universe.class_info(curr_vlr_regclass) {
+            None => {
                 return Err(RegAllocError::OutOfRegisters(curr_vlr_regclass));
             }
-            &Some(ref info) => (info.first, info.last),
+            Some(info) => (info.first, info.last),
         };
 
+        // ====== BEGIN Try the client's hint ======
+        //
+        // The client's hint is only honoured if the hinted register is free for the whole of
+        // `curr_vlr`: it's a preference, not a reason to evict anybody.
+        let client_hinted_reg = match func.reg_hint(curr_vlr.vreg) {
+            Some(RegHint::Real(rreg)) => Some(rreg),
+            Some(RegHint::SameAs(other_vreg)) => reg_to_ranges_maps
+                .vreg_to_vlrs_map
+                .get(other_vreg.get_index())
+                .and_then(|vlrixs| vlrixs.iter().find_map(|vlrix| vlr_env[*vlrix].rreg)),
+            None => None,
+        };
+        if let Some(rreg) = client_hinted_reg {
+            let rregNo = rreg.get_index();
+            if rreg.get_class() == curr_vlr_regclass
+                && rregNo >= first_in_rc
+                && rregNo <= last_in_rc
+                && reg_universe.regs[rregNo].0 == rreg
+            {
+                let mb_evict_info = per_real_reg[rregNo].find_evict_set(
+                    curr_vlrix,
+                    &|_vlrix_to_evict| false,
+                    &vlr_env,
+                );
+                if let Some((vlrixs_to_evict, _)) = mb_evict_info {
+                    assert!(vlrixs_to_evict.is_empty());
+                    debug!("--   HI alloc to       {}", reg_universe.regs[rregNo].1);
+                    per_real_reg[rregNo].add_VirtualRange(curr_vlrix, &vlr_env);
+                    vlr_env[curr_vlrix].rreg = Some(rreg);
+                    continue 'main_allocation_loop;
+                }
+            }
+        }
+        // ====== END Try the client's hint ======
+
         let mut best_so_far: Option<(
             /*rreg index*/ usize,
             SparseSetU<[VirtualRangeIx; 4]>,
@@ -1115,16 +1388,16 @@ pub fn alloc_main<F: Function>(
             assert!(total_spill_cost.is_less_than(&curr_vlr.spill_cost));
             // Now the same evict-reassign section as with the coalescing logic above.
             // Evict all evictees in the set
-            for vlrix_to_evict in vlrixs_to_evict.iter() {
+            for vlrix_to_evict in vlrixs_to_evict.iter_ordered(deterministic) {
                 // Evict ..
                 debug!(
                     "--   DI evict          {:?}:  {:?}",
-                    *vlrix_to_evict, &vlr_env[*vlrix_to_evict]
+                    vlrix_to_evict, &vlr_env[vlrix_to_evict]
                 );
-                per_real_reg[rregNo].del_VirtualRange(*vlrix_to_evict, &vlr_env);
-                prioQ.add_VirtualRange(&vlr_env, *vlrix_to_evict);
-                debug_assert!(vlr_env[*vlrix_to_evict].rreg.is_some());
-                vlr_env[*vlrix_to_evict].rreg = None;
+                per_real_reg[rregNo].del_VirtualRange(vlrix_to_evict, &vlr_env);
+                prioQ.add_VirtualRange(&vlr_env, vlrix_to_evict);
+                debug_assert!(vlr_env[vlrix_to_evict].rreg.is_some());
+                vlr_env[vlrix_to_evict].rreg = None;
                 num_vlrs_evicted += 1;
             }
             // .. and reassign.
@@ -1416,13 +1689,98 @@ pub fn alloc_main<F: Function>(
         );
     }
 
+    info!(
+        "alloc_main:   out: VLRs: {} initially, {} processed",
+        num_vlrs_initial, num_vlrs_processed
+    );
+    info!(
+        "alloc_main:   out: VLRs: {} evicted, {} spilled",
+        num_vlrs_evicted, num_vlrs_spilled
+    );
+
+    finish_allocation(
+        func,
+        reg_universe,
+        stackmap_request,
+        use_checker,
+        dump_allocation,
+        annotate,
+        deterministic,
+        opts.request_block_annotations,
+        &opts.memory_pins,
+        &reg_vecs_and_bounds,
+        &rlr_env,
+        &vlr_env,
+        &vlr_slot_env,
+        &per_real_reg,
+        &is_vv_boundary_move,
+        &est_freqs,
+        &livein_sets_per_block,
+        &liveout_sets_per_block,
+        edit_list_move,
+        edit_list_other,
+        vec![],
+        spill_slot_allocator,
+    )
+}
+
+// Allocator tail, shared with the graph coloring and chordal allocators.  Given
+// the final assignment of VirtualRanges to real registers, as recorded in
+// `per_real_reg`, and the edit lists describing the spill and reload bridges,
+// this performs spill slot coalescing, creates all spill, reload and stackmap
+// instructions, edits the instruction stream and builds the RegAllocResult.
+// `edge_moves` are extra instructions to insert, which come before any spill
+// or reload at the same point; the chordal allocator uses them for the copies
+// to block parameters.
+
+#[inline(never)]
+pub(crate) fn finish_allocation<F: Function>(
+    func: &mut F,
+    reg_universe: &RealRegUniverse,
+    stackmap_request: Option<&StackmapRequestInfo>,
+    use_checker: bool,
+    dump_allocation: bool,
+    annotate: bool,
+    deterministic: bool,
+    request_block_annotations: bool,
+    memory_pins: &[MemoryPin],
+    reg_vecs_and_bounds: &RegVecsAndBounds,
+    rlr_env: &TypedIxVec<RealRangeIx, RealRange>,
+    vlr_env: &TypedIxVec<VirtualRangeIx, VirtualRange>,
+    vlr_slot_env: &TypedIxVec<VirtualRangeIx, Option<SpillSlot>>,
+    per_real_reg: &Vec<PerRealReg>,
+    is_vv_boundary_move: &TypedIxVec<InstIx, bool>,
+    est_freqs: &BlockFrequencies,
+    livein_sets_per_block: &TypedIxVec<BlockIx, SparseSet<Reg>>,
+    liveout_sets_per_block: &TypedIxVec<BlockIx, SparseSet<Reg>>,
+    mut edit_list_move: Vec<EditListItem>,
+    mut edit_list_other: Vec<EditListItem>,
+    edge_moves: Vec<InstToInsertAndExtPoint>,
+    mut spill_slot_allocator: SpillSlotAllocator,
+) -> Result<RegAllocResult<F>, RegAllocError> {
+    let empty_vec_iixs = vec![];
+    let (reftype_class, safepoint_insns, report_reftyped_regs) = match stackmap_request {
+        Some(&StackmapRequestInfo {
+            reftype_class,
+            ref safepoint_insns,
+            report_reftyped_regs,
+            ..
+        }) => (reftype_class, safepoint_insns, report_reftyped_regs),
+        None => (RegClass::INVALID, &empty_vec_iixs, false),
+    };
+
     // ======== BEGIN Do spill slot coalescing ========
 
     debug!("");
     info!("alloc_main:   create spills_n_reloads for MOVE insns");
 
-    // Sort `edit_list_move` by the insn with which each item is associated.
-    edit_list_move.sort_unstable_by(|eli1, eli2| eli1.iix.cmp(&eli2.iix));
+    // Sort `edit_list_move` by the insn with which each item is associated.  The entries for an
+    // insn keep their order if the allocation must be deterministic.
+    if deterministic {
+        edit_list_move.sort_by(|eli1, eli2| eli1.iix.cmp(&eli2.iix));
+    } else {
+        edit_list_move.sort_unstable_by(|eli1, eli2| eli1.iix.cmp(&eli2.iix));
+    }
 
     // Now go through `edit_list_move` and find pairs which constitute a
     // spillslot-to-the-same-spillslot move.  What we have in `edit_list_move` is
@@ -1568,7 +1926,7 @@ pub fn alloc_main<F: Function>(
     // Reload and spill instructions are missing.  To generate them, go through
     // the "edit list", which contains info on both how to generate the
     // instructions, and where to insert them.
-    let mut spills_n_reloads = Vec::<InstToInsertAndExtPoint>::new();
+    let mut spills_n_reloads = edge_moves;
     let mut num_spills = 0; // stats only
     let mut num_reloads = 0; // stats only
     for eli in &edit_list_other {
@@ -1669,6 +2027,8 @@ pub fn alloc_main<F: Function>(
 
     // There is one of these for every entry in `safepoint_insns`.
     let mut stackmaps = Vec::<Vec<SpillSlot>>::new();
+    // Likewise, but only if the client wants the reftyped real regs to be reported.
+    let mut stackmap_regs = Vec::<Vec<RealReg>>::new();
 
     if !safepoint_insns.is_empty() {
         info!("alloc_main:   create safepoints and stackmaps");
@@ -1694,17 +2054,22 @@ pub fn alloc_main<F: Function>(
             //
             // FIXME Passing these 3 small vectors around is inefficient.  Use SmallVec or
             // (better) owned-by-this-function vectors instead.
-            let (spills_before, reloads_after, reftyped_spillslots) = get_stackmap_artefacts_at(
-                &mut spill_slot_allocator,
-                &reg_universe,
-                reftype_class,
-                &reg_vecs_and_bounds,
-                &per_real_reg,
-                &rlr_env,
-                &vlr_env,
-                *safepoint_iix,
-            )?;
+            let (spills_before, reloads_after, reftyped_spillslots, reftyped_regs) =
+                get_stackmap_artefacts_at(
+                    &mut spill_slot_allocator,
+                    reg_universe,
+                    reftype_class,
+                    reg_vecs_and_bounds,
+                    per_real_reg,
+                    rlr_env,
+                    vlr_env,
+                    *safepoint_iix,
+                    report_reftyped_regs,
+                )?;
             stackmaps.push(reftyped_spillslots);
+            if report_reftyped_regs {
+                stackmap_regs.push(reftyped_regs);
+            }
             for spill_before in spills_before {
                 spills_n_reloads.push(InstToInsertAndExtPoint::new(
                     spill_before,
@@ -1720,6 +2085,67 @@ pub fn alloc_main<F: Function>(
         }
     }
 
+    // There is one of these for every entry in `memory_pins`.
+    let mut memory_pin_slots = Vec::<Vec<(VirtualReg, SpillSlot)>>::new();
+
+    if !memory_pins.is_empty() {
+        info!("alloc_main:   create memory pins");
+        for pin in memory_pins {
+            let (spills_before, slots) = get_memory_pin_artefacts_at(
+                func,
+                &mut spill_slot_allocator,
+                reg_universe,
+                per_real_reg,
+                vlr_env,
+                vlr_slot_env,
+                pin,
+            );
+            memory_pin_slots.push(slots);
+            for spill_before in spills_before {
+                spills_n_reloads.push(InstToInsertAndExtPoint::new(
+                    spill_before,
+                    InstExtPoint::new(pin.iix, ExtPoint::SpillBefore),
+                ));
+            }
+        }
+    }
+
+    let ranges: Vec<DumpedRange> = if dump_allocation || annotate {
+        frag_map
+            .iter()
+            .map(|&(ref frag, vreg, rreg)| DumpedRange {
+                vreg,
+                first: frag.first,
+                last: frag.last,
+                loc: ValueLoc::Reg(rreg),
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    let allocation_dump = if dump_allocation {
+        Some(alloc_dump::dump_allocation(
+            func,
+            reg_universe,
+            &ranges,
+            &spills_n_reloads,
+        ))
+    } else {
+        None
+    };
+    let mut block_annotations = if annotate {
+        Some(alloc_dump::annotate_blocks(
+            func,
+            reg_universe,
+            &ranges,
+            &spills_n_reloads,
+            livein_sets_per_block,
+            liveout_sets_per_block,
+        ))
+    } else {
+        None
+    };
+
     info!("alloc_main:   edit_inst_stream");
 
     let final_insns_and_targetmap_and_new_safepoints__or_err = edit_inst_stream(
@@ -1727,10 +2153,11 @@ pub fn alloc_main<F: Function>(
         spills_n_reloads,
         &iixs_to_nop_out,
         frag_map,
-        &reg_universe,
+        reg_universe,
         use_checker,
         stackmap_request,
         &stackmaps[..],
+        &stackmap_regs[..],
     );
 
     // ======== END Create final instruction stream ========
@@ -1739,14 +2166,6 @@ pub fn alloc_main<F: Function>(
 
     match final_insns_and_targetmap_and_new_safepoints__or_err {
         Ok((ref final_insns, ..)) => {
-            info!(
-                "alloc_main:   out: VLRs: {} initially, {} processed",
-                num_vlrs_initial, num_vlrs_processed
-            );
-            info!(
-                "alloc_main:   out: VLRs: {} evicted, {} spilled",
-                num_vlrs_evicted, num_vlrs_spilled
-            );
             info!(
                 "alloc_main:   out: insns: {} total, {} spills, {} reloads, {} nopzs",
                 final_insns.len(),
@@ -1788,7 +2207,7 @@ pub fn alloc_main<F: Function>(
     // That's inefficient, but we don't care .. this should only be a temporary
     // fix.
 
-    let mut clobbered_registers: Set<RealReg> = Set::empty();
+    let mut clobbered_registers = RealRegBitSet::for_universe(reg_universe);
 
     // We'll dump all the reg uses in here.  We don't care about the bounds, so just
     // pass a dummy one in the loop.
@@ -1802,8 +2221,14 @@ pub fn alloc_main<F: Function>(
     for reg in reg_vecs.defs.iter().chain(reg_vecs.mods.iter()) {
         assert!(reg.is_real());
         clobbered_registers.insert(reg.to_real_reg());
+        // Writing a register clobbers the registers overlapping it.
+        for alias in reg_universe.allocable_aliases(reg.get_index()) {
+            clobbered_registers.insert(alias);
+        }
     }
 
+    let clobbered_registers = clobbered_registers.to_set(reg_universe);
+
     // And now remove from the set, all those not available to the allocator.
     // But not removing the reserved regs, since we might have modified those.
     clobbered_registers.filter_map(|&reg| {
@@ -1815,15 +2240,17 @@ pub fn alloc_main<F: Function>(
     });
 
     assert!(est_freqs.len() as usize == func.blocks().len());
-    let mut block_annotations = None;
-    if opts.request_block_annotations {
-        let mut anns = TypedIxVec::<BlockIx, Vec<String>>::new();
+    if request_block_annotations {
+        let anns = block_annotations.get_or_insert_with(|| {
+            let mut anns = TypedIxVec::<BlockIx, Vec<String>>::new();
+            anns.resize(est_freqs.len() as u32, vec![]);
+            anns
+        });
         for (estFreq, i) in est_freqs.iter().zip(0..) {
             let bix = BlockIx::new(i);
             let ef_str = format!("RA: bix {:?}, estFreq {}", bix, estFreq);
-            anns.push(vec![ef_str]);
+            anns[bix].insert(0, ef_str);
         }
-        block_annotations = Some(anns);
     }
 
     assert!(stackmaps.len() == safepoint_insns.len());
@@ -1836,7 +2263,13 @@ pub fn alloc_main<F: Function>(
         num_spill_slots: spill_slot_allocator.num_slots_in_use() as u32,
         block_annotations,
         stackmaps,
+        stackmap_regs,
         new_safepoint_insns,
+        memory_pin_slots,
+        allocation_dump,
+        move_report: None,
+        split_edges: vec![],
+        stats: None,
     };
 
     info!("alloc_main: end");
@@ -50,7 +50,8 @@ impl RangeFragAndRefness {
 enum LogicalSpillSlot {
     // This slot is in use and can hold values of size `size` (only).  Note that
     // `InUse` may only appear in `SpillSlotAllocator::slots` positions that
-    // have indices that are 0 % `size`.  Furthermore, after such an entry in
+    // have indices that are 0 % the alignment requested for its values, per
+    // `get_spillslot_align`.  Furthermore, after such an entry in
     // `SpillSlotAllocator::slots`, the next `size` - 1 entries must be
     // `Unavail`.  This is a hard invariant, violation of which will cause
     // overlapping spill slots and potential chaos.
@@ -230,14 +231,15 @@ impl SpillSlotAllocator {
         self.slots.len()
     }
 
-    /// This adds a new, empty slot, for items of the given size, and returns its index.  This
-    /// isn't clever, in the sense that it fails to use some slots that it could use, but at least
-    /// it's simple.  Note, this is a private method.
-    fn add_new_slot(&mut self, req_size: u32) -> u32 {
-        assert!(req_size == 1 || req_size == 2 || req_size == 4 || req_size == 8);
+    /// This adds a new, empty slot, for items of the given size and alignment, and returns its
+    /// index.  This isn't clever, in the sense that it fails to use some slots that it could use,
+    /// but at least it's simple.  Note, this is a private method.
+    fn add_new_slot(&mut self, req_size: u32, req_align: u32) -> u32 {
+        assert!(req_size > 0);
+        assert!(req_align.is_power_of_two());
         // Satisfy alignment constraints.  These entries will unfortunately be
         // wasted (never used).
-        while self.slots.len() % (req_size as usize) != 0 {
+        while self.slots.len() % (req_align as usize) != 0 {
             self.slots.push(LogicalSpillSlot::Unavail);
         }
         // And now the new slot.  The `dflt` value is needed by `AVLTree` to initialise storage
@@ -258,7 +260,6 @@ impl SpillSlotAllocator {
         for _ in 1..req_size {
             self.slots.push(LogicalSpillSlot::Unavail);
         }
-        assert!(self.slots.len() % (req_size as usize) == 0);
 
         res
     }
@@ -337,13 +338,13 @@ impl SpillSlotAllocator {
         //   different slots, which in turn reduces the amount of spilling in
         //   the end.
 
-        // We need to know what regclass, and hence what slot size, we're looking
+        // We need to know what regclass, and hence what slot size and alignment, we're looking
         // for.  Just look at the representative; all VirtualRanges in the eclass
         // must have the same regclass.  (If they don't, the client's is_move
         // function has been giving us wrong information.)
         let vlrix_vreg = vlr_env[vlrix].vreg;
         let req_size = func.get_spillslot_size(vlrix_vreg.get_class(), vlrix_vreg);
-        assert!(req_size == 1 || req_size == 2 || req_size == 4 || req_size == 8);
+        let req_align = func.get_spillslot_align(vlrix_vreg.get_class(), vlrix_vreg);
 
         // Sanity check: if the VLR is reftyped, then it must need a 1-word slot
         // (anything else is nonsensical.)
@@ -372,7 +373,7 @@ impl SpillSlotAllocator {
             if !cand_slot.is_InUse() {
                 continue;
             }
-            if cand_slot.get_size() != req_size {
+            if cand_slot.get_size() != req_size || cand_slot_no % req_align != 0 {
                 continue;
             }
             let tree = &cand_slot.get_tree();
@@ -401,7 +402,7 @@ impl SpillSlotAllocator {
 
         // Pass 1b. If we didn't find a usable slot, allocate a new one.
         let chosen_slotno: u32 = if mb_chosen_slotno.is_none() {
-            self.add_new_slot(req_size)
+            self.add_new_slot(req_size, req_align)
         } else {
             mb_chosen_slotno.unwrap()
         };
@@ -424,7 +425,7 @@ impl SpillSlotAllocator {
                 if !alt_slot.is_InUse() {
                     continue;
                 }
-                if alt_slot.get_size() != req_size {
+                if alt_slot.get_size() != req_size || alt_slotno % req_align != 0 {
                     continue;
                 }
                 if alt_slotno == chosen_slotno {
@@ -440,7 +441,7 @@ impl SpillSlotAllocator {
             }
             // If we get here, it means it won't fit in any slot we currently have.
             // So allocate a new one and use that.
-            let new_slotno = self.add_new_slot(req_size);
+            let new_slotno = self.add_new_slot(req_size, req_align);
             let mut tree = self.slots[new_slotno as usize].get_mut_tree();
             let added = ssal_add_if_possible(&mut tree, &cand_vlr.sorted_frags);
             if added {
@@ -475,13 +476,27 @@ impl SpillSlotAllocator {
     /// is marked reftyped so that a later call to `get_reftyped_spillslots_at_inst_point` will
     /// return it.
     pub(crate) fn alloc_reftyped_spillslot_for_frag(&mut self, frag: RangeFrag) -> SpillSlot {
+        self.alloc_spillslot_for_frag(frag, 1 /*word*/, 1, /*is_ref=*/ true)
+    }
+
+    /// Allocate a spill slot of `req_size` and `req_align` for the single `frag` and return it,
+    /// marking it reftyped or not according to `is_ref`.
+    pub(crate) fn alloc_spillslot_for_frag(
+        &mut self,
+        frag: RangeFrag,
+        req_size: u32,
+        req_align: u32,
+        is_ref: bool,
+    ) -> SpillSlot {
         for i in 0..self.slots.len() {
             match &mut self.slots[i] {
-                LogicalSpillSlot::InUse { size: 1, tree } => {
+                LogicalSpillSlot::InUse { size, tree }
+                    if *size == req_size && i % req_align as usize == 0 =>
+                {
                     if ssal_is_add_frag_possible(&tree, &frag) {
                         // We're in luck.
                         let inserted = tree.insert(
-                            RangeFragAndRefness::new(frag, /*is_ref=*/ true),
+                            RangeFragAndRefness::new(frag, is_ref),
                             Some(&|item1: RangeFragAndRefness, item2: RangeFragAndRefness| {
                                 cmp_range_frags(&item1.frag, &item2.frag)
                             }),
@@ -493,7 +508,7 @@ impl SpillSlotAllocator {
                     // Otherwise move on.
                 }
                 LogicalSpillSlot::InUse { .. } | LogicalSpillSlot::Unavail => {
-                    // Slot isn't is use, or is in use but for values of some non-ref size.
+                    // Slot isn't is use, or is in use but for values of some other size.
                     // Move on.
                 }
             }
@@ -501,8 +516,17 @@ impl SpillSlotAllocator {
         // We tried all slots, but without success.  Add a new one and try again.  This time we
         // must succeed.  Calling recursively is a bit stupid in the sense that we then search
         // again to find the slot we just allocated, but hey.
-        self.add_new_slot(1 /*word*/);
-        self.alloc_reftyped_spillslot_for_frag(frag) // \o/ tailcall \o/
+        self.add_new_slot(req_size, req_align);
+        self.alloc_spillslot_for_frag(frag, req_size, req_align, is_ref) // \o/ tailcall \o/
+    }
+
+    /// Allocate a spill slot of `req_size` and `req_align` which is never shared with anything
+    /// else, and return it.  The chordal allocator uses these as temporaries for the copies on
+    /// CFG edges, which happen between the instructions and so aren't covered by any `RangeFrag`.
+    pub(crate) fn alloc_unshared_spillslot(&mut self, req_size: u32, req_align: u32) -> SpillSlot {
+        let slot_ix = self.add_new_slot(req_size, req_align);
+        self.slots[slot_ix as usize] = LogicalSpillSlot::Unavail;
+        SpillSlot::new(slot_ix)
     }
 
     /// Stackmap support: Examine all the spill slots at `pt` and return those that are reftyped.
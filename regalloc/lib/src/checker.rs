@@ -59,13 +59,14 @@ use crate::data_structures::{
     BlockIx, FxHashSet, InstIx, Map, RealReg, RealRegUniverse, Reg, RegSets, SpillSlot, VirtualReg,
     Writable,
 };
-use crate::inst_stream::{ExtPoint, InstExtPoint, InstToInsertAndExtPoint};
+use crate::inst_stream::{ExtPoint, InstExtPoint, InstToInsertAndExtPoint, ValueLoc};
 use crate::{analysis_data_flow::get_san_reg_sets_for_insn, StackmapRequestInfo};
 use crate::{Function, RegUsageMapper};
 
 use alloc::collections::VecDeque;
 use alloc::vec;
 use core::default::Default;
+use core::fmt;
 use core::hash::Hash;
 use core::result::Result;
 
@@ -73,9 +74,80 @@ use alloc::vec::Vec;
 use log::debug;
 
 /// A set of errors detected by the regalloc checker.
+///
+/// Its `Display` implementation renders a report of every error, along with the state of the
+/// checker at the offending instruction.
 #[derive(Clone, Debug)]
 pub struct CheckerErrors {
-    errors: Vec<CheckerError>,
+    errors: Vec<CheckerErrorReport>,
+}
+
+impl CheckerErrors {
+    /// The errors found, ordered by block and then by position within the block.
+    pub fn errors(&self) -> &[CheckerErrorReport] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for CheckerErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "regalloc checker found {} error(s)", self.errors.len())?;
+        for report in &self.errors {
+            write!(f, "\n{}", report)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CheckerErrors {}
+
+/// An error detected by the regalloc checker, with the context in which it was found.
+#[derive(Clone, Debug)]
+pub struct CheckerErrorReport {
+    /// The error itself.
+    pub error: CheckerError,
+    /// The block containing the offending instruction.
+    pub block: BlockIx,
+    /// The registers of the offending instruction, as given to the allocator, along with the real
+    /// registers they were mapped to: uses first, then defs.  Empty for the instructions inserted
+    /// by the allocator.
+    pub mappings: Vec<(Reg, RealReg)>,
+    /// The symbolic value of each real register just before the offending instruction, sorted by
+    /// register.  Empty if the error was found before the dataflow analysis ran.
+    pub reg_values: Vec<(RealReg, CheckerValue)>,
+    /// The symbolic value of each spill slot just before the offending instruction, sorted by
+    /// slot.  Empty if the error was found before the dataflow analysis ran.
+    pub slot_values: Vec<(SpillSlot, CheckerValue)>,
+}
+
+impl fmt::Display for CheckerErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {} (block {:?})", self.error, self.block)?;
+        if !self.mappings.is_empty() {
+            write!(f, "\n  mappings: ")?;
+            for (i, (orig, mapped)) in self.mappings.iter().enumerate() {
+                let sep = if i == 0 { "" } else { ", " };
+                write!(f, "{}{:?} -> {:?}", sep, orig, mapped)?;
+            }
+        }
+        // Registers still holding their entry value, or holding nothing, are left out: they make
+        // up most of the state and rarely matter.
+        let reg_values = self.reg_values.iter().filter(|&&(rreg, val)| match val {
+            CheckerValue::Unknown => false,
+            CheckerValue::Reg(reg, _) => reg != rreg.to_reg(),
+            CheckerValue::Conflicted => true,
+        });
+        for (rreg, val) in reg_values {
+            write!(f, "\n  {:?} = {}", rreg, val)?;
+        }
+        for (slot, val) in &self.slot_values {
+            if *val != CheckerValue::Unknown {
+                write!(f, "\n  {:?} = {}", slot, val)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 /// A single error detected by the regalloc checker.
@@ -87,6 +159,7 @@ pub enum CheckerError {
     },
     UnknownValueInReg {
         real_reg: RealReg,
+        expected: Reg,
         inst: InstIx,
     },
     IncorrectValueInReg {
@@ -114,6 +187,99 @@ pub enum CheckerError {
         inst: InstIx,
         slot: SpillSlot,
     },
+    StackMapSpecifiesNonRefReg {
+        inst: InstIx,
+        real_reg: RealReg,
+    },
+    StackMapSpecifiesUndefinedReg {
+        inst: InstIx,
+        real_reg: RealReg,
+    },
+}
+
+impl CheckerError {
+    /// The instruction at which the error was found.
+    pub fn inst(&self) -> InstIx {
+        match *self {
+            CheckerError::MissingAllocationForReg { inst, .. }
+            | CheckerError::UnknownValueInReg { inst, .. }
+            | CheckerError::IncorrectValueInReg { inst, .. }
+            | CheckerError::UnknownValueInSlot { inst, .. }
+            | CheckerError::IncorrectValueInSlot { inst, .. }
+            | CheckerError::StackMapSpecifiesNonRefSlot { inst, .. }
+            | CheckerError::StackMapSpecifiesUndefinedSlot { inst, .. }
+            | CheckerError::StackMapSpecifiesNonRefReg { inst, .. }
+            | CheckerError::StackMapSpecifiesUndefinedReg { inst, .. } => inst,
+        }
+    }
+}
+
+impl fmt::Display for CheckerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckerError::MissingAllocationForReg { reg, inst } => {
+                write!(f, "no allocation for {:?} at {:?}", reg, inst)
+            }
+            CheckerError::UnknownValueInReg {
+                real_reg,
+                expected,
+                inst,
+            } => write!(
+                f,
+                "unknown value in {:?} at {:?}: expected {:?}",
+                real_reg, inst, expected
+            ),
+            CheckerError::IncorrectValueInReg {
+                actual,
+                expected,
+                real_reg,
+                inst,
+            } => write!(
+                f,
+                "incorrect value in {:?} at {:?}: expected {:?}, found {:?}",
+                real_reg, inst, expected, actual
+            ),
+            CheckerError::UnknownValueInSlot {
+                slot,
+                expected,
+                inst,
+            } => write!(
+                f,
+                "unknown value in {:?} at {:?}: expected {:?}",
+                slot, inst, expected
+            ),
+            CheckerError::IncorrectValueInSlot {
+                slot,
+                expected,
+                actual,
+                inst,
+            } => write!(
+                f,
+                "incorrect value in {:?} at {:?}: expected {:?}, found {:?}",
+                slot, inst, expected, actual
+            ),
+            CheckerError::StackMapSpecifiesNonRefSlot { inst, slot } => write!(
+                f,
+                "stackmap at {:?} lists {:?}, which doesn't hold a reference",
+                inst, slot
+            ),
+            CheckerError::StackMapSpecifiesUndefinedSlot { inst, slot } => write!(
+                f,
+                "stackmap at {:?} lists {:?}, which holds no known value",
+                inst, slot
+            ),
+            CheckerError::StackMapSpecifiesNonRefReg { inst, real_reg } => write!(
+                f,
+                "stackmap at {:?} lists {:?}, which doesn't hold a reference",
+                inst, real_reg
+            ),
+            CheckerError::StackMapSpecifiesUndefinedReg { inst, real_reg } => write!(
+                f,
+                "stackmap at {:?} lists {:?}, which holds no known value",
+                inst, real_reg
+            ),
+        }
+    }
 }
 
 /// Abstract state for a storage slot (real register or spill slot).
@@ -122,7 +288,7 @@ pub enum CheckerError {
 /// value-points in between, one per real or virtual register. Any two different registers
 /// meet to \bot.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum CheckerValue {
+pub enum CheckerValue {
     /// "top" value: this storage slot has no known value.
     Unknown,
     /// "bottom" value: this storage slot has a conflicted value.
@@ -157,6 +323,17 @@ impl CheckerValue {
     }
 }
 
+impl fmt::Display for CheckerValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckerValue::Unknown => write!(f, "unknown"),
+            CheckerValue::Conflicted => write!(f, "conflicted"),
+            CheckerValue::Reg(reg, false) => write!(f, "{:?}", reg),
+            CheckerValue::Reg(reg, true) => write!(f, "{:?} (ref)", reg),
+        }
+    }
+}
+
 /// State that steps through program points as we scan over the instruction stream.
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct CheckerState {
@@ -235,6 +412,7 @@ impl CheckerState {
                         CheckerValue::Unknown | CheckerValue::Conflicted => {
                             return Err(CheckerError::UnknownValueInReg {
                                 real_reg: mapped,
+                                expected: orig,
                                 inst: inst_ix,
                             });
                         }
@@ -281,15 +459,89 @@ impl CheckerState {
                     _ => {}
                 }
             }
-            &Inst::Safepoint { inst_ix, ref slots } => {
-                self.check_stackmap(inst_ix, slots)?;
+            &Inst::BindBlockParam {
+                inst_ix, loc, arg, ..
+            } => {
+                let val = self.get_value(loc);
+                debug!("checker: inst {:?}: value {:?}", inst, val);
+                match (val, loc) {
+                    (CheckerValue::Unknown, ValueLoc::Reg(real_reg))
+                    | (CheckerValue::Conflicted, ValueLoc::Reg(real_reg)) => {
+                        return Err(CheckerError::UnknownValueInReg {
+                            real_reg,
+                            expected: arg,
+                            inst: inst_ix,
+                        });
+                    }
+                    (CheckerValue::Unknown, ValueLoc::Slot(slot))
+                    | (CheckerValue::Conflicted, ValueLoc::Slot(slot)) => {
+                        return Err(CheckerError::UnknownValueInSlot {
+                            slot,
+                            expected: arg,
+                            inst: inst_ix,
+                        });
+                    }
+                    (CheckerValue::Reg(r, _), ValueLoc::Reg(real_reg)) if r != arg => {
+                        return Err(CheckerError::IncorrectValueInReg {
+                            actual: r,
+                            expected: arg,
+                            real_reg,
+                            inst: inst_ix,
+                        });
+                    }
+                    (CheckerValue::Reg(r, _), ValueLoc::Slot(slot)) if r != arg => {
+                        return Err(CheckerError::IncorrectValueInSlot {
+                            slot,
+                            expected: arg,
+                            actual: r,
+                            inst: inst_ix,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            &Inst::Safepoint {
+                inst_ix,
+                ref slots,
+                ref regs,
+            } => {
+                self.check_stackmap(inst_ix, slots, regs)?;
             }
             _ => {}
         }
         Ok(())
     }
 
-    fn check_stackmap(&self, inst: InstIx, slots: &Vec<SpillSlot>) -> Result<(), CheckerError> {
+    /// Describe `error`, found at `inst` in `block` with this state.
+    fn report(&self, error: CheckerError, block: BlockIx, inst: &Inst) -> CheckerErrorReport {
+        let mut reg_values: Vec<_> = self.reg_values.iter().map(|(&r, &v)| (r, v)).collect();
+        let mut slot_values: Vec<_> = self.spill_slots.iter().map(|(&s, &v)| (s, v)).collect();
+        reg_values.sort_unstable_by_key(|&(rreg, _)| rreg);
+        slot_values.sort_unstable_by_key(|&(slot, _)| slot);
+        CheckerErrorReport {
+            error,
+            block,
+            mappings: inst.mappings(),
+            reg_values,
+            slot_values,
+        }
+    }
+
+    fn get_value(&self, loc: ValueLoc) -> CheckerValue {
+        match loc {
+            ValueLoc::Reg(rreg) => self.reg_values.get(&rreg),
+            ValueLoc::Slot(slot) => self.spill_slots.get(&slot),
+        }
+        .cloned()
+        .unwrap_or(Default::default())
+    }
+
+    fn check_stackmap(
+        &self,
+        inst: InstIx,
+        slots: &Vec<SpillSlot>,
+        regs: &Vec<RealReg>,
+    ) -> Result<(), CheckerError> {
         // N.B.: it's OK for the stackmap to omit a slot that has a ref value in
         // it; it might be dead. We simply update such a slot's value to
         // 'undefined' in the transfer function.
@@ -306,10 +558,23 @@ impl CheckerState {
                 }
             }
         }
+        for &real_reg in regs {
+            match self.reg_values.get(&real_reg) {
+                Some(CheckerValue::Reg(_, false)) => {
+                    return Err(CheckerError::StackMapSpecifiesNonRefReg { inst, real_reg });
+                }
+                Some(CheckerValue::Reg(_, true)) => {
+                    // OK.
+                }
+                _ => {
+                    return Err(CheckerError::StackMapSpecifiesUndefinedReg { inst, real_reg });
+                }
+            }
+        }
         Ok(())
     }
 
-    fn update_stackmap(&mut self, slots: &Vec<SpillSlot>) {
+    fn update_stackmap(&mut self, slots: &Vec<SpillSlot>, regs: &Vec<RealReg>) {
         for (&slot, val) in &mut self.spill_slots {
             if let &mut CheckerValue::Reg(_, true) = val {
                 let in_stackmap = slots.binary_search(&slot).is_ok();
@@ -319,10 +584,13 @@ impl CheckerState {
             }
         }
         // Mark registers holding reference values as unknown, forcing the need for a reload around
-        // the safepoint.
-        for (_, val) in &mut self.reg_values {
+        // the safepoint, unless the stackmap lists them.
+        for (&real_reg, val) in &mut self.reg_values {
             if let &mut CheckerValue::Reg(_, true) = val {
-                *val = CheckerValue::Unknown;
+                let in_stackmap = regs.binary_search(&real_reg).is_ok();
+                if !in_stackmap {
+                    *val = CheckerValue::Unknown;
+                }
             }
         }
     }
@@ -355,6 +623,12 @@ impl CheckerState {
                     .unwrap_or(Default::default());
                 self.reg_values.insert(into.to_reg(), val);
             }
+            &Inst::Swap { reg1, reg2 } => {
+                let val1 = self.reg_values.remove(&reg1).unwrap_or(Default::default());
+                let val2 = self.reg_values.remove(&reg2).unwrap_or(Default::default());
+                self.reg_values.insert(reg1, val2);
+                self.reg_values.insert(reg2, val1);
+            }
             &Inst::ChangeSpillSlotOwnership { slot, to_reg, .. } => {
                 let reftyped = if let Some(val) = self.spill_slots.get(&slot) {
                     match val {
@@ -367,6 +641,17 @@ impl CheckerState {
                 self.spill_slots
                     .insert(slot, CheckerValue::Reg(to_reg, reftyped));
             }
+            &Inst::BindBlockParam { loc, param, .. } => {
+                let reftyped = match self.get_value(loc) {
+                    CheckerValue::Reg(_, reftyped) => reftyped,
+                    _ => false,
+                };
+                let val = CheckerValue::Reg(param, reftyped);
+                match loc {
+                    ValueLoc::Reg(rreg) => self.reg_values.insert(rreg, val),
+                    ValueLoc::Slot(slot) => self.spill_slots.insert(slot, val),
+                };
+            }
             &Inst::Spill { into, from } => {
                 let val = self
                     .reg_values
@@ -383,8 +668,12 @@ impl CheckerState {
                     .unwrap_or(Default::default());
                 self.reg_values.insert(into.to_reg(), val);
             }
-            &Inst::Safepoint { ref slots, .. } => {
-                self.update_stackmap(slots);
+            &Inst::Safepoint {
+                ref slots,
+                ref regs,
+                ..
+            } => {
+                self.update_stackmap(slots, regs);
             }
         }
     }
@@ -405,6 +694,8 @@ pub(crate) enum Inst {
         into: Writable<RealReg>,
         from: RealReg,
     },
+    /// A regalloc-inserted exchange of the values of two registers.
+    Swap { reg1: RealReg, reg2: RealReg },
     /// A spillslot ghost move (between vregs) resulting from an user-program
     /// move whose source and destination regs are both vregs that are currently
     /// spilled.
@@ -414,6 +705,14 @@ pub(crate) enum Inst {
         from_reg: Reg,
         to_reg: Reg,
     },
+    /// A block parameter binding on a CFG edge, for functions in SSA form: `loc` must hold the
+    /// argument `arg` passed on the edge, which from then on is the value of `param`.
+    BindBlockParam {
+        inst_ix: InstIx,
+        loc: ValueLoc,
+        arg: Reg,
+        param: Reg,
+    },
     /// A regular instruction with fixed use and def slots. Contains both
     /// the original registers (as given to the regalloc) and the allocated ones.
     Op {
@@ -424,13 +723,35 @@ pub(crate) enum Inst {
         uses: Vec<RealReg>,
         defs_reftyped: Vec<bool>,
     },
-    /// A safepoint, with a list of expected slots.
+    /// A safepoint, with a list of expected slots and registers.
     Safepoint {
         inst_ix: InstIx,
         slots: Vec<SpillSlot>,
+        regs: Vec<RealReg>,
     },
 }
 
+impl Inst {
+    /// The original registers of an `Op` along with their allocations, uses first.
+    fn mappings(&self) -> Vec<(Reg, RealReg)> {
+        match self {
+            Inst::Op {
+                uses_orig,
+                uses,
+                defs_orig,
+                defs,
+                ..
+            } => uses_orig
+                .iter()
+                .cloned()
+                .zip(uses.iter().cloned())
+                .chain(defs_orig.iter().cloned().zip(defs.iter().cloned()))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Checker {
     bb_entry: BlockIx,
@@ -441,34 +762,17 @@ pub(crate) struct Checker {
     has_run: bool,
 }
 
-fn map_regs<F: Fn(VirtualReg) -> Option<RealReg>>(
-    inst: InstIx,
-    regs: &[Reg],
-    f: &F,
-) -> Result<Vec<RealReg>, CheckerErrors> {
-    let mut errors = Vec::new();
-    let real_regs = regs
-        .iter()
+/// Map `regs` with `f`, leaving `None` for the virtual registers that have no allocation.
+fn map_regs<F: Fn(VirtualReg) -> Option<RealReg>>(regs: &[Reg], f: &F) -> Vec<Option<RealReg>> {
+    regs.iter()
         .map(|r| {
             if r.is_virtual() {
-                f(r.to_virtual_reg()).unwrap_or_else(|| {
-                    errors.push(CheckerError::MissingAllocationForReg {
-                        reg: r.to_virtual_reg(),
-                        inst,
-                    });
-                    // Provide a dummy value for the register, it'll never be read.
-                    Reg::new_real(r.get_class(), 0x0, 0).to_real_reg()
-                })
+                f(r.to_virtual_reg())
             } else {
-                r.to_real_reg()
+                Some(r.to_real_reg())
             }
         })
-        .collect();
-    if errors.is_empty() {
-        Ok(real_regs)
-    } else {
-        Err(CheckerErrors { errors })
-    }
+        .collect()
 }
 
 impl Checker {
@@ -540,21 +844,48 @@ impl Checker {
 
         let uses_orig = uses_set.to_vec();
         let defs_orig = defs_set.to_vec();
-        let uses = map_regs(inst_ix, &uses_orig[..], &|vreg| mapper.get_use(vreg))?;
-        let defs = map_regs(inst_ix, &defs_orig[..], &|vreg| mapper.get_def(vreg))?;
+        let uses = map_regs(&uses_orig[..], &|vreg| mapper.get_use(vreg));
+        let defs = map_regs(&defs_orig[..], &|vreg| mapper.get_def(vreg));
+
+        let mut mappings = vec![];
+        let mut missing = vec![];
+        for (&orig, &mapped) in uses_orig
+            .iter()
+            .zip(&uses)
+            .chain(defs_orig.iter().zip(&defs))
+        {
+            match mapped {
+                Some(rreg) => mappings.push((orig, rreg)),
+                None => missing.push(orig.to_virtual_reg()),
+            }
+        }
+        if !missing.is_empty() {
+            let errors = missing
+                .into_iter()
+                .map(|reg| CheckerErrorReport {
+                    error: CheckerError::MissingAllocationForReg { reg, inst: inst_ix },
+                    block,
+                    mappings: mappings.clone(),
+                    reg_values: vec![],
+                    slot_values: vec![],
+                })
+                .collect();
+            return Err(CheckerErrors { errors });
+        }
+
         let defs_reftyped = defs_orig
             .iter()
             .map(|reg| reg.is_virtual() && self.reftyped_vregs.contains(&reg.to_virtual_reg()))
             .collect();
-        let insts = self.bb_insts.get_mut(&block).unwrap();
         let op = Inst::Op {
             inst_ix,
             uses_orig,
             defs_orig,
-            uses,
-            defs,
+            uses: uses.into_iter().map(Option::unwrap).collect(),
+            defs: defs.into_iter().map(Option::unwrap).collect(),
             defs_reftyped,
         };
+        let insts = self.bb_insts.get_mut(&block).unwrap();
         debug!("add_op: adding {:?}", op);
         insts.push(op);
         Ok(())
@@ -598,12 +929,14 @@ impl Checker {
     /// for errors.
     fn find_errors(&self) -> Result<(), CheckerErrors> {
         let mut errors = vec![];
-        for (block, input) in &self.bb_in {
-            let mut state = input.clone();
-            for inst in self.bb_insts.get(block).unwrap() {
+        let mut blocks: Vec<BlockIx> = self.bb_in.keys().cloned().collect();
+        blocks.sort_unstable();
+        for block in blocks {
+            let mut state = self.bb_in[&block].clone();
+            for inst in &self.bb_insts[&block] {
                 if let Err(e) = state.check(inst) {
                     debug!("Checker error: {:?}", e);
-                    errors.push(e);
+                    errors.push(state.report(e, block, inst));
                 }
                 state.update(inst);
             }
@@ -637,6 +970,7 @@ pub(crate) struct CheckerContext {
 pub(crate) struct CheckerStackmapInfo<'a> {
     pub(crate) request: &'a StackmapRequestInfo,
     pub(crate) stackmaps: &'a [Vec<SpillSlot>],
+    pub(crate) stackmap_regs: &'a [Vec<RealReg>],
 }
 
 impl CheckerContext {
@@ -658,21 +992,30 @@ impl CheckerContext {
 
         let reftyped_vregs = if let Some(info) = stackmap_info {
             assert!(info.request.safepoint_insns.len() == info.stackmaps.len());
-            for (iix, slots) in info
+            // The registers are only listed if the client asked for them.
+            assert!(
+                info.stackmap_regs.is_empty()
+                    || info.request.safepoint_insns.len() == info.stackmap_regs.len()
+            );
+            for (sp_ix, (iix, slots)) in info
                 .request
                 .safepoint_insns
                 .iter()
                 .zip(info.stackmaps.iter())
+                .enumerate()
             {
                 let iep = InstExtPoint::new(*iix, ExtPoint::Use);
                 let mut slots = slots.clone();
                 slots.sort();
+                let mut regs = info.stackmap_regs.get(sp_ix).cloned().unwrap_or_default();
+                regs.sort();
                 checker_inst_map
                     .entry(iep)
                     .or_insert_with(|| vec![])
                     .push(Inst::Safepoint {
                         inst_ix: *iix,
                         slots,
+                        regs,
                     });
             }
             info.request.reftyped_vregs.as_slice()
@@ -751,3 +1094,94 @@ impl Drop for Checker {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RegClass;
+    use alloc::string::ToString;
+
+    fn vreg(idx: u32) -> Reg {
+        Reg::new_virtual(RegClass::I64, idx)
+    }
+    fn rreg(idx: u8) -> RealReg {
+        Reg::new_real(RegClass::I64, /* enc = */ 0, /* index = */ idx).to_real_reg()
+    }
+    fn op(inst: u32, uses: &[(Reg, RealReg)], defs: &[(Reg, RealReg)]) -> Inst {
+        Inst::Op {
+            inst_ix: InstIx::new(inst),
+            uses_orig: uses.iter().map(|&(orig, _)| orig).collect(),
+            uses: uses.iter().map(|&(_, mapped)| mapped).collect(),
+            defs_orig: defs.iter().map(|&(orig, _)| orig).collect(),
+            defs: defs.iter().map(|&(_, mapped)| mapped).collect(),
+            defs_reftyped: vec![false; defs.len()],
+        }
+    }
+
+    #[test]
+    fn test_error_report() {
+        let block = BlockIx::new(0);
+        let mut entry_state = CheckerState::new();
+        for &rreg in &[rreg(0), rreg(1)] {
+            entry_state
+                .reg_values
+                .insert(rreg, CheckerValue::Reg(rreg.to_reg(), false));
+        }
+
+        let mut checker = Checker {
+            bb_entry: block,
+            bb_in: Map::default(),
+            bb_succs: Map::default(),
+            bb_insts: Map::default(),
+            reftyped_vregs: FxHashSet::default(),
+            has_run: false,
+        };
+        checker.bb_in.insert(block, entry_state);
+        checker.bb_succs.insert(block, vec![]);
+        checker.bb_insts.insert(block, vec![]);
+
+        // v0 is clobbered by v1 before being read.
+        checker.add_inst(block, op(0, &[], &[(vreg(0), rreg(0))]));
+        checker.add_inst(block, op(1, &[], &[(vreg(1), rreg(0))]));
+        checker.add_inst(block, op(2, &[(vreg(0), rreg(0))], &[(vreg(2), rreg(1))]));
+
+        let errors = checker.run().unwrap_err();
+        assert_eq!(errors.errors().len(), 1);
+        let report = &errors.errors()[0];
+        match report.error {
+            CheckerError::IncorrectValueInReg {
+                actual,
+                expected,
+                real_reg,
+                inst,
+            } => {
+                assert_eq!(actual, vreg(1));
+                assert_eq!(expected, vreg(0));
+                assert_eq!(real_reg, rreg(0));
+                assert_eq!(inst, InstIx::new(2));
+            }
+            ref error => panic!("unexpected error {:?}", error),
+        }
+        assert_eq!(report.block, block);
+        assert_eq!(
+            report.mappings,
+            vec![(vreg(0), rreg(0)), (vreg(2), rreg(1))]
+        );
+        assert_eq!(
+            report.reg_values,
+            vec![
+                (rreg(0), CheckerValue::Reg(vreg(1), false)),
+                (rreg(1), CheckerValue::Reg(rreg(1).to_reg(), false)),
+            ]
+        );
+        assert!(report.slot_values.is_empty());
+
+        assert_eq!(
+            errors.to_string(),
+            "regalloc checker found 1 error(s)\n\
+             error: incorrect value in r0J at i2: expected v0J, found v1J (block b0)\n  \
+             mappings: v0J -> r0J, v2J -> r1J\n  \
+             r0J = v1J"
+        );
+    }
+}
@@ -0,0 +1,621 @@
+#![allow(non_snake_case)]
+
+//! Implementation of the chordal allocator, for functions in SSA form.
+//!
+//! The interference graph of a function in SSA form is chordal, and visiting its VirtualRanges
+//! in an order where each range's definition comes after the definitions of the ranges visited
+//! before it, in terms of dominance, gives a perfect elimination order.  Greedily giving each
+//! range a register that no overlapping range visited earlier has then needs no more registers
+//! than there are values live at any point.  A preorder walk of the CFG visits every block after
+//! its dominators, so the ranges are visited by the preorder position of the block where they
+//! start, and by their start point within that block.
+//!
+//! Block parameters play the role of phi nodes.  Parameters and the arguments passed to them get
+//! ranges of their own, which are hinted to share a register.  Once every range has a location,
+//! the copies from the arguments to the parameters are inserted on each CFG edge, as a parallel
+//! move.  Copies that form a cycle, or go from a spill slot to another one, use the suggested
+//! scratch register of their class, which is never allocated.
+//!
+//! If a range finds no free register, it evicts the cheapest set of ranges occupying one of the
+//! registers, provided they are cheaper than itself, and is spilled otherwise.  Spilling reuses
+//! the machinery of the graph coloring allocator: spilled ranges are replaced by short spill and
+//! reload bridges, and allocation restarts with the new set of ranges.  Since bridges have an
+//! infinite spill cost, they are never spilled again, and so the process terminates.
+
+use log::{debug, info};
+use smallvec::SmallVec;
+
+use alloc::fmt;
+use core::default;
+
+use crate::analysis_control_flow::CFGInfo;
+use crate::analysis_main::{run_analysis, AnalysisInfo};
+use crate::bt_coalescing_analysis::{do_coalescing_analysis, Hint};
+use crate::bt_main::{finish_allocation, EditListItem, PerRealReg};
+use crate::bt_spillslot_allocator::SpillSlotAllocator;
+use crate::data_structures::{
+    BlockIx, InstIx, InstPoint, RealRangeIx, RealReg, RealRegUniverse, RegClass, RegToRangesMaps,
+    SpillCost, SpillSlot, TypedIxVec, VirtualRange, VirtualRangeIx, VirtualReg, Writable,
+};
+use crate::graph_coloring::spill_virtual_range;
+use crate::inst_stream::{ExtPoint, InstExtPoint, InstToInsert, InstToInsertAndExtPoint, ValueLoc};
+use crate::sparse_set::SparseSetU;
+use crate::{AlgorithmWithDefaults, Function, RegAllocError, RegAllocResult, StackmapRequestInfo};
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Clone)]
+pub struct ChordalOptions {
+    /// Should the register allocator generate block annotations?
+    pub request_block_annotations: bool,
+}
+
+impl default::Default for ChordalOptions {
+    fn default() -> Self {
+        Self {
+            request_block_annotations: false,
+        }
+    }
+}
+
+impl fmt::Debug for ChordalOptions {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "chordal (block annotations: {})",
+            self.request_block_annotations
+        )
+    }
+}
+
+//=============================================================================
+// Register assignment
+
+// Find a register for `vlrix`: preferably one of the hinted ones, else any register of its class
+// other than the scratch register.  If all of them are taken, pick the one whose occupants are
+// cheapest to evict, provided they're cheaper than `vlrix` itself.  Returns the register, along
+// with the ranges to evict from it.
+#[inline(never)]
+fn choose_register(
+    reg_universe: &RealRegUniverse,
+    per_real_reg: &Vec<PerRealReg>,
+    vlr_env: &TypedIxVec<VirtualRangeIx, VirtualRange>,
+    hints: &TypedIxVec<VirtualRangeIx, SmallVec<[Hint; 8]>>,
+    vlrix: VirtualRangeIx,
+) -> Option<(RealReg, SparseSetU<[VirtualRangeIx; 4]>)> {
+    let rc = vlr_env[vlrix].vreg.get_class();
+    let info = reg_universe.class_info(rc)?;
+
+    let mut hinted = SmallVec::<[(u32, RealReg); 8]>::new();
+    for hint in &hints[vlrix] {
+        match *hint {
+            Hint::Exactly(rreg, weight) => hinted.push((weight, rreg)),
+            Hint::SameAs(other, weight) => {
+                if let Some(rreg) = vlr_env[other].rreg {
+                    hinted.push((weight, rreg));
+                }
+            }
+        }
+    }
+    hinted.sort_by(|(w1, _), (w2, _)| w2.cmp(w1));
+
+    let candidates = hinted
+        .iter()
+        .map(|(_, rreg)| rreg.get_index())
+        .chain(info.first..=info.last)
+        .filter(|&index| {
+            index >= info.first && index <= info.last && Some(index) != info.suggested_scratch
+        });
+
+    let mut best: Option<(usize, SparseSetU<[VirtualRangeIx; 4]>, SpillCost)> = None;
+    for index in candidates {
+        match per_real_reg[index].find_evict_set(vlrix, &|_| true, vlr_env) {
+            Some((evict_set, _)) if evict_set.is_empty() => {
+                return Some((reg_universe.regs[index].0, evict_set));
+            }
+            Some((evict_set, evict_cost)) => {
+                let better = match &best {
+                    Some((_, _, best_cost)) => evict_cost.is_less_than(best_cost),
+                    None => true,
+                };
+                if better {
+                    best = Some((index, evict_set, evict_cost));
+                }
+            }
+            None => {}
+        }
+    }
+    best.map(|(index, evict_set, _)| (reg_universe.regs[index].0, evict_set))
+}
+
+//=============================================================================
+// Copies on CFG edges
+
+// The location of the value of `vreg` at `pt`, if it is live there.  Only the original
+// VirtualRanges are considered: a spilled one lives in its spill slot throughout.
+fn location_at(
+    reg_to_ranges_maps: &RegToRangesMaps,
+    vlr_env: &TypedIxVec<VirtualRangeIx, VirtualRange>,
+    vlr_slot_env: &TypedIxVec<VirtualRangeIx, Option<SpillSlot>>,
+    vreg: VirtualReg,
+    pt: InstPoint,
+) -> Option<ValueLoc> {
+    for &vlrix in &reg_to_ranges_maps.vreg_to_vlrs_map[vreg.get_index()] {
+        let vlr = &vlr_env[vlrix];
+        if vlr.sorted_frags.iter().any(|frag| frag.contains(&pt)) {
+            return Some(match vlr.rreg {
+                Some(rreg) => ValueLoc::Reg(rreg),
+                None => ValueLoc::Slot(vlr_slot_env[vlrix].unwrap()),
+            });
+        }
+    }
+    None
+}
+
+// A copy from an argument to a block parameter.
+#[derive(Clone, Copy, Debug)]
+struct EdgeMove {
+    src: ValueLoc,
+    dst: ValueLoc,
+    arg: VirtualReg,
+    param: VirtualReg,
+}
+
+// Sequentializes the parallel moves on CFG edges into spills, reloads and moves.
+struct EdgeMoveResolver<'a, F: Function> {
+    func: &'a F,
+    reg_universe: &'a RealRegUniverse,
+    // Temporary spill slots, by size and alignment, used to break cycles that involve spill
+    // slots.
+    temp_slots: SmallVec<[((u32, u32), SpillSlot); 2]>,
+    insts: Vec<InstToInsertAndExtPoint>,
+}
+
+impl<'a, F: Function> EdgeMoveResolver<'a, F> {
+    fn scratch(&self, rc: RegClass) -> Result<RealReg, RegAllocError> {
+        match self
+            .reg_universe
+            .class_info(rc)
+            .and_then(|info| info.suggested_scratch)
+        {
+            Some(index) => Ok(self.reg_universe.regs[index].0),
+            None => Err(RegAllocError::MissingSuggestedScratchReg(rc)),
+        }
+    }
+
+    fn push(&mut self, inst: InstToInsert, iix: InstIx) {
+        debug!("--   edge move at {:?}: {:?}", iix, inst);
+        let iep = InstExtPoint::new(iix, ExtPoint::Reload);
+        self.insts.push(InstToInsertAndExtPoint::new(inst, iep));
+    }
+
+    // Copy the value of `vreg` from `src` to `dst`.
+    fn copy(
+        &mut self,
+        src: ValueLoc,
+        dst: ValueLoc,
+        vreg: VirtualReg,
+        iix: InstIx,
+    ) -> Result<(), RegAllocError> {
+        match (src, dst) {
+            (ValueLoc::Reg(from_reg), ValueLoc::Reg(to_reg)) => self.push(
+                InstToInsert::Move {
+                    to_reg: Writable::from_reg(to_reg),
+                    from_reg,
+                    for_vreg: vreg,
+                },
+                iix,
+            ),
+            (ValueLoc::Reg(from_reg), ValueLoc::Slot(to_slot)) => self.push(
+                InstToInsert::Spill {
+                    to_slot,
+                    from_reg,
+                    for_vreg: Some(vreg),
+                },
+                iix,
+            ),
+            (ValueLoc::Slot(from_slot), ValueLoc::Reg(to_reg)) => self.push(
+                InstToInsert::Reload {
+                    to_reg: Writable::from_reg(to_reg),
+                    from_slot,
+                    for_vreg: Some(vreg),
+                },
+                iix,
+            ),
+            (ValueLoc::Slot(from_slot), ValueLoc::Slot(to_slot)) => {
+                let scratch = self.scratch(vreg.get_class())?;
+                self.push(
+                    InstToInsert::Reload {
+                        to_reg: Writable::from_reg(scratch),
+                        from_slot,
+                        for_vreg: Some(vreg),
+                    },
+                    iix,
+                );
+                self.push(
+                    InstToInsert::Spill {
+                        to_slot,
+                        from_reg: scratch,
+                        for_vreg: Some(vreg),
+                    },
+                    iix,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Emit `moves`, which happen in parallel, at `iix`.  No two moves have the same destination.
+    fn resolve(
+        &mut self,
+        spill_slot_allocator: &mut SpillSlotAllocator,
+        mut moves: Vec<EdgeMove>,
+        iix: InstIx,
+        use_checker: bool,
+    ) -> Result<(), RegAllocError> {
+        let binds = moves.clone();
+
+        moves.retain(|m| m.src != m.dst);
+        while !moves.is_empty() {
+            // Emit a move whose destination no other pending move reads, if there is one.
+            if let Some(i) = moves
+                .iter()
+                .position(|m| !moves.iter().any(|other| other.src == m.dst))
+            {
+                let m = moves.swap_remove(i);
+                self.copy(m.src, m.dst, m.arg, iix)?;
+                continue;
+            }
+
+            // Otherwise, the remaining moves form cycles.  Break one by saving the value in the
+            // destination of some move to a temporary, and reading it from there instead.  The
+            // moves that read the temporary are emitted before another cycle needs breaking.
+            let saved = moves[0].dst;
+            let vreg = moves.iter().find(|m| m.src == saved).unwrap().arg;
+            let all_regs = moves.iter().all(|m| match (m.src, m.dst) {
+                (ValueLoc::Reg(_), ValueLoc::Reg(_)) => true,
+                _ => false,
+            });
+            let temp = if all_regs {
+                ValueLoc::Reg(self.scratch(vreg.get_class())?)
+            } else {
+                let size = self.func.get_spillslot_size(vreg.get_class(), vreg);
+                let align = self.func.get_spillslot_align(vreg.get_class(), vreg);
+                let slot = match self.temp_slots.iter().find(|(s, _)| *s == (size, align)) {
+                    Some(&(_, slot)) => slot,
+                    None => {
+                        let slot = spill_slot_allocator.alloc_unshared_spillslot(size, align);
+                        self.temp_slots.push(((size, align), slot));
+                        slot
+                    }
+                };
+                ValueLoc::Slot(slot)
+            };
+            self.copy(saved, temp, vreg, iix)?;
+            for m in moves.iter_mut() {
+                if m.src == saved {
+                    m.src = temp;
+                }
+            }
+        }
+
+        // Tell the checker that the parameters now hold the arguments.
+        if use_checker {
+            for m in binds {
+                self.push(
+                    InstToInsert::BindBlockParam {
+                        inst_ix: iix,
+                        loc: m.dst,
+                        arg: m.arg.to_reg(),
+                        param: m.param.to_reg(),
+                    },
+                    iix,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+// The instruction where the copies on the edge from `pred` to `succ` go: at the start of `succ`
+// if it has a single predecessor, else at the end of `pred`, before its control flow
+// instruction.  The analysis phase ensures that the latter doesn't mention any register then.
+fn edge_move_point<F: Function>(
+    func: &F,
+    cfg_info: &CFGInfo,
+    pred: BlockIx,
+    succ: BlockIx,
+) -> InstIx {
+    if cfg_info.pred_map[succ].card() == 1 {
+        func.block_insns(succ).first()
+    } else {
+        func.block_insns(pred).last()
+    }
+}
+
+//=============================================================================
+// Allocator top level
+
+#[inline(never)]
+pub fn run<F: Function>(
+    func: &mut F,
+    reg_universe: &RealRegUniverse,
+    stackmap_request: Option<&StackmapRequestInfo>,
+    use_checker: bool,
+    dump_allocation: bool,
+    annotate: bool,
+    deterministic: bool,
+    opts: &ChordalOptions,
+) -> Result<RegAllocResult<F>, RegAllocError> {
+    let empty_vec_vregs = vec![];
+    let (client_wants_stackmaps, reftype_class, reftyped_vregs) = match stackmap_request {
+        Some(&StackmapRequestInfo {
+            reftype_class,
+            ref reftyped_vregs,
+            ..
+        }) => (true, reftype_class, reftyped_vregs),
+        None => (false, RegClass::INVALID, &empty_vec_vregs),
+    };
+
+    // -------- Perform initial liveness analysis --------
+    let AnalysisInfo {
+        cfg_info,
+        reg_vecs_and_bounds,
+        real_ranges: rlr_env,
+        virtual_ranges: mut vlr_env,
+        range_frags: frag_env,
+        estimated_frequencies: est_freqs,
+        livein_sets_per_block,
+        liveout_sets_per_block,
+        inst_to_block_map,
+        reg_to_ranges_maps: mb_reg_to_ranges_maps,
+        move_info: mb_move_info,
+        ..
+    } = run_analysis(
+        func,
+        reg_universe,
+        AlgorithmWithDefaults::Chordal,
+        client_wants_stackmaps,
+        reftype_class,
+        reftyped_vregs,
+        /*reserve_scratches=*/ true,
+        deterministic,
+    )
+    .map_err(|err| RegAllocError::Analysis(err))?;
+
+    let reg_to_ranges_maps = mb_reg_to_ranges_maps.unwrap(); // ensured by `run_analysis`
+    let move_info = mb_move_info.unwrap(); // ensured by `run_analysis`
+
+    let (mut hints, vlrEquivClasses, is_vv_boundary_move) = do_coalescing_analysis(
+        func,
+        &reg_universe,
+        &rlr_env,
+        &mut vlr_env,
+        &frag_env,
+        &reg_to_ranges_maps,
+        &move_info,
+    );
+
+    // Hint each argument and the parameter it is passed to to share a register, so that the
+    // copy between them disappears.
+    for pred in func.blocks() {
+        let pred_last = InstPoint::new_def(func.block_insns(pred).last());
+        for (succ_ix, &succ) in func.block_succs(pred).iter().enumerate() {
+            let succ_first = InstPoint::new_use(func.block_insns(succ).first());
+            let weight = est_freqs.cost(pred);
+            let args = func.block_args(pred, succ_ix);
+            for (arg, param) in args.iter().zip(func.block_params(succ).iter()) {
+                let find = |vreg: &VirtualReg, pt: InstPoint| {
+                    reg_to_ranges_maps.vreg_to_vlrs_map[vreg.get_index()]
+                        .iter()
+                        .cloned()
+                        .find(|&vlrix| {
+                            vlr_env[vlrix]
+                                .sorted_frags
+                                .iter()
+                                .any(|frag| frag.contains(&pt))
+                        })
+                };
+                if let (Some(arg_vlrix), Some(param_vlrix)) =
+                    (find(arg, pred_last), find(param, succ_first))
+                {
+                    hints[arg_vlrix].push(Hint::SameAs(param_vlrix, weight));
+                    hints[param_vlrix].push(Hint::SameAs(arg_vlrix, weight));
+                }
+            }
+        }
+    }
+
+    info!("chordal: begin");
+    let num_vlrs_initial = vlr_env.len();
+    info!(
+        "chordal:   in: {} VLRs, {} RLRs",
+        num_vlrs_initial,
+        rlr_env.len()
+    );
+
+    // The position of each block in the preorder walk of the CFG.
+    let mut pre_ord_rank = TypedIxVec::<BlockIx, u32>::new();
+    pre_ord_rank.resize(func.blocks().len() as u32, 0);
+    for (rank, &bix) in cfg_info.pre_ord.iter().enumerate() {
+        pre_ord_rank[bix] = rank as u32;
+    }
+
+    let mut vlr_slot_env = TypedIxVec::<VirtualRangeIx, Option<SpillSlot>>::new();
+    vlr_slot_env.resize(num_vlrs_initial, None);
+    let mut spill_slot_allocator = SpillSlotAllocator::new();
+    let mut spilled = TypedIxVec::<VirtualRangeIx, bool>::new();
+    spilled.resize(num_vlrs_initial, false);
+    let mut edit_list_move = Vec::<EditListItem>::new();
+    let mut edit_list_other = Vec::<EditListItem>::new();
+
+    let mut num_rounds = 0; // stats only
+    let mut num_vlrs_evicted = 0; // stats only
+    let mut num_vlrs_spilled = 0; // stats only
+    let per_real_reg = loop {
+        num_rounds += 1;
+        debug!("-- ROUND {}", num_rounds);
+
+        let mut per_real_reg = Vec::<PerRealReg>::new();
+        for _ in 0..reg_universe.allocable {
+            per_real_reg.push(PerRealReg::new());
+        }
+        for (rlrix_no, rlr) in rlr_env.iter().enumerate() {
+            let rregIndex = rlr.rreg.get_index();
+            if rregIndex < reg_universe.allocable {
+                per_real_reg[rregIndex].add_RealRange(
+                    RealRangeIx::new(rlrix_no as u32),
+                    &rlr_env,
+                    &frag_env,
+                );
+            }
+        }
+
+        // Visit the ranges which haven't been spilled in dominance order.
+        let mut order = Vec::<(u32, InstPoint, VirtualRangeIx)>::new();
+        for (vlrix_no, vlr) in vlr_env.iter_mut().enumerate() {
+            let vlrix = VirtualRangeIx::new(vlrix_no as u32);
+            vlr.rreg = None;
+            if spilled[vlrix] {
+                continue;
+            }
+            let key = vlr
+                .sorted_frags
+                .iter()
+                .map(|frag| {
+                    (
+                        pre_ord_rank[inst_to_block_map.map(frag.first.iix())],
+                        frag.first,
+                    )
+                })
+                .min()
+                .unwrap();
+            order.push((key.0, key.1, vlrix));
+        }
+        order.sort_unstable();
+
+        let mut to_spill = Vec::<VirtualRangeIx>::new();
+        for (_, _, vlrix) in order {
+            match choose_register(reg_universe, &per_real_reg, &vlr_env, &hints, vlrix) {
+                Some((rreg, evict_set)) => {
+                    let rregIndex = rreg.get_index();
+                    for evictee in evict_set.iter_ordered(deterministic) {
+                        debug!("--   evict             {:?}", evictee);
+                        per_real_reg[rregIndex].del_VirtualRange(evictee, &vlr_env);
+                        vlr_env[evictee].rreg = None;
+                        to_spill.push(evictee);
+                        num_vlrs_evicted += 1;
+                    }
+                    debug!("--   assign            {:?} -> {:?}", vlrix, rreg);
+                    per_real_reg[rregIndex].add_VirtualRange(vlrix, &vlr_env);
+                    vlr_env[vlrix].rreg = Some(rreg);
+                }
+                None => {
+                    if vlr_env[vlrix].spill_cost.is_infinite() {
+                        return Err(RegAllocError::OutOfRegisters(
+                            vlr_env[vlrix].vreg.get_class(),
+                        ));
+                    }
+                    to_spill.push(vlrix);
+                }
+            }
+        }
+
+        if to_spill.is_empty() {
+            break per_real_reg;
+        }
+        for vlrix in to_spill {
+            spill_virtual_range(
+                func,
+                &reg_vecs_and_bounds,
+                &mut vlr_env,
+                &mut vlr_slot_env,
+                &mut spilled,
+                &mut hints,
+                &mut spill_slot_allocator,
+                &vlrEquivClasses,
+                &is_vv_boundary_move,
+                &mut edit_list_move,
+                &mut edit_list_other,
+                vlrix,
+            );
+            num_vlrs_spilled += 1;
+        }
+    };
+
+    // Now that every value has a location, insert the copies from arguments to parameters.
+    let mut resolver = EdgeMoveResolver {
+        func,
+        reg_universe,
+        temp_slots: SmallVec::new(),
+        insts: vec![],
+    };
+    for pred in func.blocks() {
+        let pred_last = InstPoint::new_def(func.block_insns(pred).last());
+        for (succ_ix, &succ) in func.block_succs(pred).iter().enumerate() {
+            let succ_first = InstPoint::new_use(func.block_insns(succ).first());
+            let args = func.block_args(pred, succ_ix);
+            let mut moves = Vec::<EdgeMove>::new();
+            for (&arg, &param) in args.iter().zip(func.block_params(succ).iter()) {
+                // Dead parameters don't need a copy.
+                let dst = match location_at(
+                    &reg_to_ranges_maps,
+                    &vlr_env,
+                    &vlr_slot_env,
+                    param,
+                    succ_first,
+                ) {
+                    Some(dst) => dst,
+                    None => continue,
+                };
+                let src = location_at(&reg_to_ranges_maps, &vlr_env, &vlr_slot_env, arg, pred_last)
+                    .expect("block argument isn't live out");
+                moves.push(EdgeMove {
+                    src,
+                    dst,
+                    arg,
+                    param,
+                });
+            }
+            if !moves.is_empty() {
+                let iix = edge_move_point(func, &cfg_info, pred, succ);
+                resolver.resolve(&mut spill_slot_allocator, moves, iix, use_checker)?;
+            }
+        }
+    }
+    let edge_moves = resolver.insts;
+
+    info!(
+        "chordal:   out: VLRs: {} initially, {} evicted, {} spilled, {} rounds",
+        num_vlrs_initial, num_vlrs_evicted, num_vlrs_spilled, num_rounds
+    );
+    info!("chordal:   out: {} edge moves", edge_moves.len());
+
+    let res = finish_allocation(
+        func,
+        reg_universe,
+        stackmap_request,
+        use_checker,
+        dump_allocation,
+        annotate,
+        deterministic,
+        opts.request_block_annotations,
+        &[],
+        &reg_vecs_and_bounds,
+        &rlr_env,
+        &vlr_env,
+        &vlr_slot_env,
+        &per_real_reg,
+        &is_vv_boundary_move,
+        &est_freqs,
+        &livein_sets_per_block,
+        &liveout_sets_per_block,
+        edit_list_move,
+        edit_list_other,
+        edge_moves,
+        spill_slot_allocator,
+    );
+
+    info!("chordal: end");
+    res
+}
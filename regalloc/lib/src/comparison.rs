@@ -0,0 +1,158 @@
+//! Allocation of the same function with both the backtracking and the linear scan allocators, so
+//! that clients can pick one of them per function, e.g. when a function moves to another tier.
+//!
+//! Both allocators start from the same control flow analysis, block frequencies and liveness
+//! analysis, which are computed once; each then builds its own live ranges, and allocates its own
+//! copy of the function.  Functions that need one of the wrappers of `allocate_registers_with_opts`
+//! (for critical edges, fixed or tied operands, or pinned virtual registers) are analysed by each
+//! allocator separately, since each wrapper has its own view of the function.
+
+use crate::analysis_control_flow::{check_function_size, CFGInfo};
+use crate::analysis_main::CommonAnalysis;
+use crate::{
+    allocate_registers_with_common, critical_edges, fixed_regs, Algorithm, BacktrackingOptions,
+    Function, LinearScanOptions, Options, RealRegUniverse, RegAllocError, RegAllocResult,
+    StackmapRequestInfo,
+};
+
+use log::info;
+
+/// A summary of the code an allocator produced, for comparing allocators.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocationSummary {
+    /// The number of instructions of the allocated code, a proxy for its size.
+    pub num_insns: usize,
+    /// The number of moves between registers inserted by the allocator.
+    pub num_moves: usize,
+    /// The number of other instructions inserted by the allocator: spills, reloads, and
+    /// exchanges of registers.  The jumps of the blocks on split critical edges don't count.
+    pub num_spills_and_reloads: usize,
+    /// The number of spill slots used.
+    pub num_spill_slots: u32,
+}
+
+impl AllocationSummary {
+    fn new<F: Function>(func: &F, result: &RegAllocResult<F>) -> Self {
+        let mut num_moves = 0;
+        let mut num_inserted = 0;
+        for (insn, orig) in result.insns.iter().zip(result.orig_insn_map.iter()) {
+            if orig.is_invalid() {
+                num_inserted += 1;
+                if func.is_move(insn).is_some() {
+                    num_moves += 1;
+                }
+            }
+        }
+        Self {
+            num_insns: result.insns.len(),
+            num_moves,
+            num_spills_and_reloads: num_inserted - num_moves - result.split_edges.len(),
+            num_spill_slots: result.num_spill_slots,
+        }
+    }
+}
+
+/// The results of allocating the same function with the backtracking and the linear scan
+/// allocators; see `compare_allocators`.
+pub struct AllocatorComparison<F: Function> {
+    /// The result of the backtracking allocator.
+    pub backtracking: Result<RegAllocResult<F>, RegAllocError>,
+    /// The result of the linear scan allocator.
+    pub linear_scan: Result<RegAllocResult<F>, RegAllocError>,
+    /// The summary of `backtracking`, if it succeeded.
+    pub backtracking_summary: Option<AllocationSummary>,
+    /// The summary of `linear_scan`, if it succeeded.
+    pub linear_scan_summary: Option<AllocationSummary>,
+    /// Whether both allocators started from the same analysis of the function.  They don't if the
+    /// function needs one of the wrappers of `allocate_registers_with_opts`, if the two options
+    /// disagree on `scratch_free`, or if the analysis failed, in which case both allocators
+    /// report the failure.
+    pub shared_analysis: bool,
+}
+
+/// Allocate registers for a copy of `func` with the backtracking allocator, and for another copy
+/// with the linear scan allocator, analysing `func` only once for both whenever possible.  `func`
+/// itself is left alone.
+///
+/// `backtracking` and `linear_scan` are the options of the two allocators; `opts.algorithm` is
+/// ignored.  Otherwise, this is the same as calling `allocate_registers_with_opts` on two copies
+/// of `func`.  Linear scan can't honour the memory pins of `backtracking`, so with memory pins,
+/// its result is `RegAllocError::UnsupportedMemoryPins`.
+#[inline(never)]
+pub fn compare_allocators<F: Function + Clone>(
+    func: &F,
+    rreg_universe: &RealRegUniverse,
+    stackmap_info: Option<&StackmapRequestInfo>,
+    backtracking: BacktrackingOptions,
+    linear_scan: LinearScanOptions,
+    opts: Options,
+) -> AllocatorComparison<F> {
+    let reserve_scratches = !backtracking.scratch_free;
+    let has_memory_pins = !backtracking.memory_pins.is_empty();
+    let can_share = backtracking.scratch_free == linear_scan.scratch_free
+        && check_function_size(func, opts.large_functions).is_ok()
+        && func.pinned_regs().is_empty()
+        && fixed_regs::collect_fixed_regs(func).is_none()
+        && (!opts.split_critical_edges || critical_edges::collect_critical_edges(func).is_empty());
+    let common = if can_share {
+        CFGInfo::create(func)
+            .and_then(|cfg_info| {
+                CommonAnalysis::new(
+                    func,
+                    cfg_info,
+                    rreg_universe,
+                    /* ssa_form = */ false,
+                    reserve_scratches,
+                )
+            })
+            .ok()
+    } else {
+        None
+    };
+    let shared_analysis = common.is_some();
+    info!("compare_allocators: shared analysis: {}", shared_analysis);
+
+    let mut bt_func = func.clone();
+    let bt_opts = Options {
+        algorithm: Algorithm::Backtracking(backtracking),
+        ..opts.clone()
+    };
+    let bt_result = allocate_registers_with_common(
+        &mut bt_func,
+        rreg_universe,
+        stackmap_info,
+        bt_opts,
+        common.clone(),
+    );
+
+    let lsra_result = if has_memory_pins {
+        Err(RegAllocError::UnsupportedMemoryPins)
+    } else {
+        let mut lsra_func = func.clone();
+        let lsra_opts = Options {
+            algorithm: Algorithm::LinearScan(linear_scan),
+            ..opts
+        };
+        allocate_registers_with_common(
+            &mut lsra_func,
+            rreg_universe,
+            stackmap_info,
+            lsra_opts,
+            common,
+        )
+    };
+
+    AllocatorComparison {
+        backtracking_summary: bt_result
+            .as_ref()
+            .ok()
+            .map(|res| AllocationSummary::new(func, res)),
+        linear_scan_summary: lsra_result
+            .as_ref()
+            .ok()
+            .map(|res| AllocationSummary::new(func, res)),
+        backtracking: bt_result,
+        linear_scan: lsra_result,
+        shared_analysis,
+    }
+}
@@ -0,0 +1,329 @@
+//! Splitting of critical edges, for clients that don't split them beforehand; see
+//! `Options::split_critical_edges`.
+//!
+//! The allocators need somewhere to put the moves that reconcile the locations of the values
+//! live on a CFG edge, which is only possible if the edge is the only one leaving its source
+//! block, or the only one entering its destination.  Linear scan and the chordal allocator
+//! further put the moves before the branch of the source block if it has a single successor, so
+//! that branch can't mention any register if the successor has several predecessors.
+//!
+//! The function is wrapped into a `SplitEdgeFunction`, which inserts a block holding a single
+//! jump on each edge violating these rules, and redirects the branch of the source block to it.
+//! The new blocks come after the blocks of the client, and their instructions after all the
+//! instructions of the client, so the indices of the client's blocks and instructions don't
+//! change.
+
+use crate::data_structures::{
+    BlockIx, InstIx, Range, RealReg, Reg, RegClass, RegVecs, Set, SpillSlot, TypedIxVec,
+    VirtualReg, Writable,
+};
+use crate::{Function, RegAllocResult, RegHint, RegUsageCollector, RegUsageMapper};
+
+use alloc::borrow::Cow;
+use alloc::vec;
+use alloc::vec::Vec;
+use log::info;
+
+/// Find the edges of `func` that need splitting, as pairs of a block and the index of the
+/// successor in its `block_succs`.  The result is empty if there aren't any.
+pub(crate) fn collect_critical_edges<F: Function>(func: &F) -> Vec<(BlockIx, usize)> {
+    // Count the incoming edges rather than the predecessors, so that two edges from a block to
+    // the same successor are both split.
+    let mut num_preds = vec![0u32; func.blocks().len()];
+    for block in func.blocks() {
+        for &succ in func.block_succs(block).iter() {
+            num_preds[succ.get() as usize] += 1;
+        }
+    }
+
+    let mut reg_vecs = RegVecs::new(false);
+    let mut edges = Vec::new();
+    for block in func.blocks() {
+        let succs = func.block_succs(block);
+        reg_vecs.clear();
+        F::get_regs(
+            func.get_insn(func.block_insns(block).last()),
+            &mut RegUsageCollector::new(&mut reg_vecs),
+        );
+        let mentions_regs =
+            !reg_vecs.uses.is_empty() || !reg_vecs.defs.is_empty() || !reg_vecs.mods.is_empty();
+        for (succ_ix, &succ) in succs.iter().enumerate() {
+            if num_preds[succ.get() as usize] > 1 && (succs.len() > 1 || mentions_regs) {
+                edges.push((block, succ_ix));
+            }
+        }
+    }
+    edges
+}
+
+/// A client function with a new block on each of its critical edges; see the module comment.
+pub(crate) struct SplitEdgeFunction<'a, F: Function> {
+    func: &'a F,
+    insns: Vec<F::Inst>,
+    /// The instructions of the new blocks, which come after the client's ones.
+    block_insns: TypedIxVec<BlockIx, Range<InstIx>>,
+    block_succs: TypedIxVec<BlockIx, Vec<BlockIx>>,
+    /// For each new block, the edge it was inserted on: the source block and the index of the
+    /// successor in its `block_succs`.
+    edges: Vec<(BlockIx, usize)>,
+    /// For each new block, the client's successor of its edge.
+    edge_dsts: Vec<BlockIx>,
+    /// The client's frequencies, extended with the ones of the new blocks.
+    block_frequencies: Option<Vec<u32>>,
+}
+
+impl<'a, F: Function> SplitEdgeFunction<'a, F> {
+    pub(crate) fn new(func: &'a F, edges: Vec<(BlockIx, usize)>) -> Self {
+        info!("splitting {} critical edges", edges.len());
+
+        let mut insns = func.insns().to_vec();
+        let mut block_insns = TypedIxVec::new();
+        let mut block_succs = TypedIxVec::new();
+        for block in func.blocks() {
+            block_insns.push(func.block_insns(block));
+            block_succs.push(func.block_succs(block).into_owned());
+        }
+
+        let num_blocks = func.blocks().len() as u32;
+        let mut edge_dsts = Vec::with_capacity(edges.len());
+        for (i, &(block, succ_ix)) in edges.iter().enumerate() {
+            let new_block = BlockIx::new(num_blocks + i as u32);
+            let dst = block_succs[block][succ_ix];
+            edge_dsts.push(dst);
+
+            let last = func.block_insns(block).last();
+            func.redirect_succ(&mut insns[last.get() as usize], succ_ix, new_block);
+            block_succs[block][succ_ix] = new_block;
+
+            block_insns.push(Range::new(InstIx::new(insns.len() as u32), 1));
+            insns.push(func.gen_jump(dst));
+            block_succs.push(vec![dst]);
+        }
+
+        // An edge can't be taken more often than either of its ends.
+        let block_frequencies = func.block_frequencies().and_then(|freqs| {
+            if freqs.len() != num_blocks as usize {
+                // Let the allocator report the mismatch.
+                return None;
+            }
+            let mut freqs = freqs.into_owned();
+            for (&(block, _), &dst) in edges.iter().zip(edge_dsts.iter()) {
+                let freq = u32::min(freqs[block.get() as usize], freqs[dst.get() as usize]);
+                freqs.push(freq);
+            }
+            Some(freqs)
+        });
+
+        Self {
+            func,
+            insns,
+            block_insns,
+            block_succs,
+            edges,
+            edge_dsts,
+            block_frequencies,
+        }
+    }
+
+    /// Translate an allocation of this function back to the client's function: the instructions
+    /// of the new blocks stay at the end, and are marked as inserted by the allocator.
+    pub(crate) fn map_result(&self, res: RegAllocResult<Self>) -> RegAllocResult<F> {
+        let num_insns = self.func.insns().len() as u32;
+        let mut orig_insn_map = TypedIxVec::new();
+        for &orig in res.orig_insn_map.iter() {
+            orig_insn_map.push(if !orig.is_invalid() && orig.get() >= num_insns {
+                InstIx::invalid_value()
+            } else {
+                orig
+            });
+        }
+
+        RegAllocResult {
+            insns: res.insns,
+            target_map: res.target_map,
+            orig_insn_map,
+            clobbered_registers: res.clobbered_registers,
+            num_spill_slots: res.num_spill_slots,
+            block_annotations: res.block_annotations,
+            stackmaps: res.stackmaps,
+            stackmap_regs: res.stackmap_regs,
+            new_safepoint_insns: res.new_safepoint_insns,
+            memory_pin_slots: res.memory_pin_slots,
+            allocation_dump: res.allocation_dump,
+            move_report: res.move_report,
+            split_edges: self
+                .edges
+                .iter()
+                .zip(self.edge_dsts.iter())
+                .map(|(&(block, _), &dst)| (block, dst))
+                .collect(),
+            stats: res.stats,
+        }
+    }
+
+    /// Whether the edge at `succ_ix` from `block` has a new block on it.
+    fn is_split(&self, block: BlockIx, succ_ix: usize) -> bool {
+        self.edges.contains(&(block, succ_ix))
+    }
+
+    /// The edge of the new block `block`, or `None` if it is one of the client's blocks.
+    fn edge(&self, block: BlockIx) -> Option<(BlockIx, usize)> {
+        let num_blocks = self.func.blocks().len() as u32;
+        if block.get() < num_blocks {
+            None
+        } else {
+            Some(self.edges[(block.get() - num_blocks) as usize])
+        }
+    }
+}
+
+impl<'a, F: Function> Function for SplitEdgeFunction<'a, F> {
+    type Inst = F::Inst;
+
+    fn insns(&self) -> &[Self::Inst] {
+        &self.insns
+    }
+
+    fn insns_mut(&mut self) -> &mut [Self::Inst] {
+        &mut self.insns
+    }
+
+    fn get_insn(&self, insn: InstIx) -> &Self::Inst {
+        &self.insns[insn.get() as usize]
+    }
+
+    fn get_insn_mut(&mut self, insn: InstIx) -> &mut Self::Inst {
+        &mut self.insns[insn.get() as usize]
+    }
+
+    fn blocks(&self) -> Range<BlockIx> {
+        Range::new(BlockIx::new(0), self.block_insns.len() as usize)
+    }
+
+    fn entry_block(&self) -> BlockIx {
+        self.func.entry_block()
+    }
+
+    fn block_insns(&self, block: BlockIx) -> Range<InstIx> {
+        self.block_insns[block]
+    }
+
+    fn block_succs(&self, block: BlockIx) -> Cow<'_, [BlockIx]> {
+        Cow::Borrowed(&self.block_succs[block])
+    }
+
+    fn block_params(&self, block: BlockIx) -> Cow<'_, [VirtualReg]> {
+        match self.edge(block) {
+            Some(_) => Cow::Borrowed(&[]),
+            None => self.func.block_params(block),
+        }
+    }
+
+    fn block_args(&self, block: BlockIx, succ_ix: usize) -> Cow<'_, [VirtualReg]> {
+        // The arguments are passed by the jump of the new block instead.
+        match self.edge(block) {
+            Some((block, succ_ix)) => self.func.block_args(block, succ_ix),
+            None if self.is_split(block, succ_ix) => Cow::Borrowed(&[]),
+            None => self.func.block_args(block, succ_ix),
+        }
+    }
+
+    fn block_frequencies(&self) -> Option<Cow<'_, [u32]>> {
+        match &self.block_frequencies {
+            Some(freqs) => Some(Cow::Borrowed(&freqs[..])),
+            None => self.func.block_frequencies(),
+        }
+    }
+
+    fn is_ret(&self, insn: InstIx) -> bool {
+        insn.get() < self.func.insns().len() as u32 && self.func.is_ret(insn)
+    }
+
+    fn is_included_in_clobbers(&self, insn: &Self::Inst) -> bool {
+        self.func.is_included_in_clobbers(insn)
+    }
+
+    fn is_call(&self, insn: &Self::Inst) -> bool {
+        self.func.is_call(insn)
+    }
+
+    fn get_regs(insn: &Self::Inst, collector: &mut RegUsageCollector) {
+        F::get_regs(insn, collector)
+    }
+
+    fn map_regs<RUM: RegUsageMapper>(insn: &mut Self::Inst, maps: &RUM) {
+        F::map_regs(insn, maps)
+    }
+
+    fn is_move(&self, insn: &Self::Inst) -> Option<(Writable<Reg>, Reg)> {
+        self.func.is_move(insn)
+    }
+
+    fn reg_hint(&self, vreg: VirtualReg) -> Option<RegHint> {
+        self.func.reg_hint(vreg)
+    }
+
+    fn pinned_regs(&self) -> Cow<'_, [(VirtualReg, RealReg)]> {
+        self.func.pinned_regs()
+    }
+
+    fn get_num_vregs(&self) -> usize {
+        self.func.get_num_vregs()
+    }
+
+    fn get_spillslot_size(&self, regclass: RegClass, for_vreg: VirtualReg) -> u32 {
+        self.func.get_spillslot_size(regclass, for_vreg)
+    }
+
+    fn get_spillslot_align(&self, regclass: RegClass, for_vreg: VirtualReg) -> u32 {
+        self.func.get_spillslot_align(regclass, for_vreg)
+    }
+
+    fn gen_spill(
+        &self,
+        to_slot: SpillSlot,
+        from_reg: RealReg,
+        for_vreg: Option<VirtualReg>,
+    ) -> Self::Inst {
+        self.func.gen_spill(to_slot, from_reg, for_vreg)
+    }
+
+    fn gen_reload(
+        &self,
+        to_reg: Writable<RealReg>,
+        from_slot: SpillSlot,
+        for_vreg: Option<VirtualReg>,
+    ) -> Self::Inst {
+        self.func.gen_reload(to_reg, from_slot, for_vreg)
+    }
+
+    fn gen_move(
+        &self,
+        to_reg: Writable<RealReg>,
+        from_reg: RealReg,
+        for_vreg: VirtualReg,
+    ) -> Self::Inst {
+        self.func.gen_move(to_reg, from_reg, for_vreg)
+    }
+
+    fn gen_zero_len_nop(&self) -> Self::Inst {
+        self.func.gen_zero_len_nop()
+    }
+
+    fn maybe_direct_reload(
+        &self,
+        insn: &Self::Inst,
+        reg: VirtualReg,
+        slot: SpillSlot,
+    ) -> Option<Self::Inst> {
+        self.func.maybe_direct_reload(insn, reg, slot)
+    }
+
+    fn func_liveins(&self) -> Set<RealReg> {
+        self.func.func_liveins()
+    }
+
+    fn func_liveouts(&self) -> Set<RealReg> {
+        self.func.func_liveouts()
+    }
+}
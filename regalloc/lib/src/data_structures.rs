@@ -12,7 +12,7 @@ use core::slice::{Iter, IterMut};
 use hashbrown::{HashMap, HashSet};
 use rustc_hash::FxHasher;
 
-use crate::{Function, RegUsageMapper};
+use crate::RegUsageMapper;
 
 use alloc::format;
 use alloc::string::{String, ToString};
@@ -545,9 +545,10 @@ impl<TyIx, Ty: fmt::Debug> fmt::Debug for TypedIxVec<TyIx, Ty> {
 //=============================================================================
 // Definitions of register classes, registers and stack slots, and printing
 // thereof. Note that this register class definition is meant to be
-// architecture-independent: it simply captures common integer/float/vector
-// types that machines are likely to use. TODO: investigate whether we need a
-// more flexible register-class definition mechanism.
+// architecture-independent: it captures common integer/float/vector types that
+// machines are likely to use, plus predicate/mask registers and a class for
+// whatever special-purpose register file a target may have.  A target only
+// describes the classes it uses, in its `RealRegUniverse`.
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
@@ -557,12 +558,15 @@ pub enum RegClass {
     I64 = 2,
     F64 = 3,
     V128 = 4,
-    INVALID = 5,
+    MASK = 5,
+    SPECIAL = 6,
+    INVALID = 7,
 }
 
-/// The number of register classes that exist.
+/// The maximum number of register classes.  A `RealRegUniverse` describes at
+/// most this many classes, and usually fewer.
 /// N.B.: must be <= 7 (fit into 3 bits) for 32-bit VReg/RReg packed format!
-pub const NUM_REG_CLASSES: usize = 5;
+pub const MAX_NUM_REG_CLASSES: usize = 7;
 
 impl RegClass {
     /// Convert a register class to a u32 index.
@@ -584,6 +588,8 @@ impl RegClass {
             2 => RegClass::I64,
             3 => RegClass::F64,
             4 => RegClass::V128,
+            5 => RegClass::MASK,
+            6 => RegClass::SPECIAL,
             _ => panic!("RegClass::rc_from_u32"),
         }
     }
@@ -595,6 +601,8 @@ impl RegClass {
             RegClass::F32 => "F",
             RegClass::F64 => "D",
             RegClass::V128 => "V",
+            RegClass::MASK => "M",
+            RegClass::SPECIAL => "S",
             RegClass::INVALID => panic!("RegClass::short_name"),
         }
     }
@@ -606,6 +614,8 @@ impl RegClass {
             RegClass::F32 => "F32",
             RegClass::F64 => "F32",
             RegClass::V128 => "V128",
+            RegClass::MASK => "MASK",
+            RegClass::SPECIAL => "SPECIAL",
             RegClass::INVALID => panic!("RegClass::long_name"),
         }
     }
@@ -1032,6 +1042,69 @@ impl<'a> RegUsageCollector<'a> {
         }
     }
 
+    /// Add a use of `r` that the instruction requires to be in `rreg`, e.g. the dividend of an
+    /// x86 `div`, which must be in RAX.  If `r` is a virtual register, the allocator copies it
+    /// into `rreg` just before the instruction, unless it is allocated there anyway, and
+    /// `Function::map_regs` maps it to `rreg`.  A constrained virtual register must not be
+    /// mentioned otherwise by the instruction, and no two of them may be fixed to the same real
+    /// register for the same access.
+    pub fn add_fixed_use(&mut self, r: Reg, rreg: RealReg) {
+        self.reg_vecs.uses.push(rreg.to_reg());
+        if r.is_virtual() {
+            self.reg_vecs.fixed.push(FixedReg {
+                vreg: r.to_virtual_reg(),
+                rreg,
+                kind: FixedRegKind::Use,
+            });
+        }
+    }
+
+    /// Add a def of `r` that the instruction requires to be in `rreg`.  If `r` is a virtual
+    /// register, the allocator copies `rreg` into it just after the instruction, unless it is
+    /// allocated there anyway, so the instruction must not be the last one of its block.  The
+    /// same restrictions as for `add_fixed_use` apply.
+    pub fn add_fixed_def(&mut self, r: Writable<Reg>, rreg: RealReg) {
+        self.reg_vecs.defs.push(rreg.to_reg());
+        if r.to_reg().is_virtual() {
+            self.reg_vecs.fixed.push(FixedReg {
+                vreg: r.to_reg().to_virtual_reg(),
+                rreg,
+                kind: FixedRegKind::Def,
+            });
+        }
+    }
+
+    /// Add a mod of `r` that the instruction requires to be in `rreg`; this combines the copies
+    /// of `add_fixed_use` and `add_fixed_def`, and their restrictions.
+    pub fn add_fixed_mod(&mut self, r: Writable<Reg>, rreg: RealReg) {
+        self.reg_vecs.mods.push(rreg.to_reg());
+        if r.to_reg().is_virtual() {
+            self.reg_vecs.fixed.push(FixedReg {
+                vreg: r.to_reg().to_virtual_reg(),
+                rreg,
+                kind: FixedRegKind::Mod,
+            });
+        }
+    }
+
+    /// Add a def of `def` that the instruction requires to be in the same register as its use of
+    /// `use_`, e.g. the destination of an x86 `add`, which overwrites its first source.  The
+    /// allocator copies `use_` into the register of `def` just before the instruction, unless it
+    /// manages to allocate both to the same register, which it tries to, and `Function::map_regs`
+    /// maps both to the register of `def`.  `use_` may otherwise only be used by the
+    /// instruction, and `def` must not be mentioned otherwise at all.  If both are real
+    /// registers, they must be the same.
+    pub fn add_tied_def(&mut self, def: Writable<Reg>, use_: Reg) {
+        let def = def.to_reg();
+        if def == use_ {
+            self.reg_vecs.mods.push(def);
+            return;
+        }
+        self.reg_vecs.uses.push(use_);
+        self.reg_vecs.defs.push(def);
+        self.reg_vecs.tied.push(TiedReg { def, use_ });
+    }
+
     // The presence of the following two is a hack, needed to support fuzzing
     // in the test framework.  Real clients should not call them.
     pub fn get_use_def_mod_vecs_test_framework_only(&self) -> (Vec<Reg>, Vec<Reg>, Vec<Reg>) {
@@ -1054,20 +1127,52 @@ impl<'a> RegUsageCollector<'a> {
 // three vectors be the same type, but comes at the cost of the loss of being
 // able to differentiate readonly vs read/write registers in the Rust type
 // system.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RegVecs {
     pub uses: Vec<Reg>,
     pub defs: Vec<Reg>,
     pub mods: Vec<Reg>,
+    // The operands constrained to a real register, added by `RegUsageCollector::add_fixed_*`.
+    // Their real registers are also recorded in `uses`, `defs` and `mods` above.
+    pub(crate) fixed: Vec<FixedReg>,
+    // The defs tied to a use, added by `RegUsageCollector::add_tied_def`.  Both registers are
+    // also recorded in `uses` and `defs` above.
+    pub(crate) tied: Vec<TiedReg>,
     sanitized: bool,
 }
 
+/// How an instruction accesses a register operand that it requires to be in a given real
+/// register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FixedRegKind {
+    Use,
+    Def,
+    Mod,
+}
+
+/// A virtual register operand that an instruction requires to be in a given real register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FixedReg {
+    pub(crate) vreg: VirtualReg,
+    pub(crate) rreg: RealReg,
+    pub(crate) kind: FixedRegKind,
+}
+
+/// A def that an instruction requires to be in the same register as one of its uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TiedReg {
+    pub(crate) def: Reg,
+    pub(crate) use_: Reg,
+}
+
 impl RegVecs {
     pub fn new(sanitized: bool) -> Self {
         Self {
             uses: vec![],
             defs: vec![],
             mods: vec![],
+            fixed: vec![],
+            tied: vec![],
             sanitized,
         }
     }
@@ -1081,6 +1186,8 @@ impl RegVecs {
         self.uses.clear();
         self.defs.clear();
         self.mods.clear();
+        self.fixed.clear();
+        self.tied.clear();
     }
 }
 
@@ -1115,6 +1222,7 @@ impl RegVecBounds {
 
 // This is the primary structure.  We compute just one of these for an entire
 // function.
+#[derive(Clone)]
 pub struct RegVecsAndBounds {
     // The three vectors of registers.  These can be arbitrarily long.
     pub vecs: RegVecs,
@@ -1220,10 +1328,24 @@ pub struct RealRegUniverse {
     // Information about groups of allocable registers. Used to quickly address
     // only a group of allocable registers belonging to the same register class.
     // Indexes into `allocable_by_class` are RegClass values, such as
-    // RegClass::F32. If the resulting entry is `None` then there are no
-    // registers in that class.  Otherwise the value is a `RegClassInfo`, which
-    // provides a register range and possibly information about fixed uses.
-    pub allocable_by_class: [Option<RegClassInfo>; NUM_REG_CLASSES],
+    // RegClass::F32. If the resulting entry is `None`, or there is no entry
+    // because the index is past the end, then there are no registers in that
+    // class.  Otherwise the value is a `RegClassInfo`, which provides a
+    // register range and possibly information about fixed uses.  There can be
+    // at most `MAX_NUM_REG_CLASSES` entries.
+    pub allocable_by_class: Vec<Option<RegClassInfo>>,
+
+    // Pairs of indices into `regs` of registers that overlap, such as the
+    // S, D and Q views of the ARM floating-point registers, or AX within EAX
+    // on x86.  A use, def or mod of a register in incoming code also counts
+    // as a use, def or mod of each allocable register aliasing it, so that,
+    // for example, writing EAX clobbers whatever the allocator keeps in RAX.
+    //
+    // The allocators don't keep apart virtual registers allocated to
+    // overlapping registers, so at most one register of each pair may be
+    // allocable; the other views must be listed among the non-allocable
+    // registers.  This is checked by RealRegUniverse::check_is_sane().
+    pub aliases: Vec<(usize, usize)>,
 }
 
 /// Information about a single register class in the `RealRegUniverse`.
@@ -1258,14 +1380,60 @@ pub struct RegClassInfo {
     // a register for various maneuvers, for example a spillslot-to-spillslot
     // move when no (other) registers are free.
     pub suggested_scratch: Option<usize>,
+
+    // Another register class, if any, whose registers can hold the values of
+    // this class when they would otherwise be spilled to the stack.  For
+    // example, on a target with few integer registers, integer values can be
+    // parked in idle floating-point registers instead of memory.  It must be
+    // another class with allocable registers.
+    //
+    // Setting it is a promise from the client that its `gen_move` can copy
+    // the values of this class into and out of the registers of the other
+    // class, and that its `gen_spill` and `gen_reload` can save and restore
+    // such registers, using the class of `for_vreg` when it is given.
+    // Currently only the linear scan allocator makes use of it.
+    pub spill_class: Option<RegClass>,
 }
 
 impl RealRegUniverse {
+    /// The number of register classes described by this universe, including
+    /// those without registers.  Indices of register classes are below it.
+    #[inline(always)]
+    pub fn num_reg_classes(&self) -> usize {
+        self.allocable_by_class.len()
+    }
+
+    /// The allocable registers of class `rc`, if there are any.
+    #[inline(always)]
+    pub fn class_info(&self, rc: RegClass) -> Option<&RegClassInfo> {
+        self.allocable_by_class
+            .get(rc.rc_to_usize())
+            .and_then(|info| info.as_ref())
+    }
+
+    /// The allocable registers that overlap the register of index `index`.
+    pub(crate) fn allocable_aliases(&self, index: usize) -> impl Iterator<Item = RealReg> + '_ {
+        self.aliases.iter().filter_map(move |&(a, b)| {
+            let other = if a == index {
+                b
+            } else if b == index {
+                a
+            } else {
+                return None;
+            };
+            if other < self.allocable {
+                Some(self.regs[other].0)
+            } else {
+                None
+            }
+        })
+    }
+
     /// Show it in a pretty way.
     pub fn show(&self) -> Vec<String> {
         let mut res = vec![];
         // Show the allocables
-        for class_num in 0..NUM_REG_CLASSES {
+        for class_num in 0..self.num_reg_classes() {
             let class_info = match &self.allocable_by_class[class_num] {
                 None => continue,
                 Some(info) => info,
@@ -1286,6 +1454,9 @@ impl RealRegUniverse {
                 }
                 class_str = class_str + " ";
             }
+            if let Some(spill_class) = class_info.spill_class {
+                class_str = class_str + &format!("(spills to {})", spill_class.short_name());
+            }
             res.push(class_str);
         }
         // And the non-allocables
@@ -1300,6 +1471,14 @@ impl RealRegUniverse {
             }
             res.push(stragglers);
         }
+        // And the overlapping registers
+        if !self.aliases.is_empty() {
+            let mut aliases = "aliases: ".to_string();
+            for &(a, b) in &self.aliases {
+                aliases = aliases + &format!("{}/{} ", self.regs[a].1, self.regs[b].1);
+            }
+            res.push(aliases);
+        }
         res
     }
 
@@ -1322,6 +1501,10 @@ impl RealRegUniverse {
         if ok {
             ok = regs_allocable <= regs_len;
         }
+        // There can't be more register classes than fit in a `Reg`.
+        if ok {
+            ok = self.num_reg_classes() <= MAX_NUM_REG_CLASSES;
+        }
         // All registers must have an index value which points back at the
         // `regs` slot they are in.  Also they really must be real regs.
         if ok {
@@ -1335,14 +1518,15 @@ impl RealRegUniverse {
         // The allocatable regclass groupings defined by `allocable_first` and
         // `allocable_last` must be contiguous.
         if ok {
-            let mut regclass_used = [false; NUM_REG_CLASSES];
-            for rc in 0..NUM_REG_CLASSES {
-                regclass_used[rc] = false;
-            }
+            let mut regclass_used = vec![false; self.num_reg_classes()];
             for i in 0..regs_allocable {
                 let (reg, _name) = &self.regs[i];
                 let rc = reg.get_class().rc_to_u32() as usize;
-                regclass_used[rc] = true;
+                // Allocable registers must belong to a class of the universe.
+                match regclass_used.get_mut(rc) {
+                    Some(used) => *used = true,
+                    None => ok = false,
+                }
             }
             // Scan forward through each grouping, checking that the listed
             // registers really are of the claimed class.  Also count the
@@ -1350,7 +1534,7 @@ impl RealRegUniverse {
             // ensure that the groupings cover all allocated registers exactly
             // once, and that all classes are contiguous groups.
             let mut regs_visited = 0;
-            for rc in 0..NUM_REG_CLASSES {
+            for rc in 0..self.num_reg_classes() {
                 match &self.allocable_by_class[rc] {
                     &None => {
                         if regclass_used[rc] {
@@ -1361,6 +1545,7 @@ impl RealRegUniverse {
                         first,
                         last,
                         suggested_scratch,
+                        spill_class,
                     }) => {
                         if !regclass_used[rc] {
                             ok = false;
@@ -1381,6 +1566,14 @@ impl RealRegUniverse {
                                 }
                             }
                         }
+                        // Values can only be spilled to the registers of
+                        // another class of the universe.
+                        if ok {
+                            if let Some(spill_class) = spill_class {
+                                ok = spill_class.rc_to_usize() != rc
+                                    && self.class_info(spill_class).is_some();
+                            }
+                        }
                     }
                 }
             }
@@ -1388,6 +1581,16 @@ impl RealRegUniverse {
                 ok = false;
             }
         }
+        // Aliases must be pairs of distinct registers of the universe, at
+        // most one of which is allocable.
+        if ok {
+            ok = self.aliases.iter().all(|&(a, b)| {
+                a != b
+                    && a < regs_len
+                    && b < regs_len
+                    && (a >= regs_allocable || b >= regs_allocable)
+            });
+        }
         // So finally ..
         if !ok {
             panic!("RealRegUniverse::check_is_sane: invalid RealRegUniverse");
@@ -1637,22 +1840,22 @@ impl RangeFrag {
         }
     }
 
-    pub fn new_with_metrics<F: Function>(
-        f: &F,
+    pub fn new_with_metrics(
+        block_insns: Range<InstIx>,
         bix: BlockIx,
         first: InstPoint,
         last: InstPoint,
         count: u16,
     ) -> (Self, RangeFragMetrics) {
-        debug_assert!(f.block_insns(bix).len() >= 1);
-        debug_assert!(f.block_insns(bix).contains(first.iix()));
-        debug_assert!(f.block_insns(bix).contains(last.iix()));
+        debug_assert!(block_insns.len() >= 1);
+        debug_assert!(block_insns.contains(first.iix()));
+        debug_assert!(block_insns.contains(last.iix()));
         debug_assert!(first <= last);
         if first == last {
             debug_assert!(count == 1);
         }
-        let first_iix_in_block = f.block_insns(bix).first();
-        let last_iix_in_block = f.block_insns(bix).last();
+        let first_iix_in_block = block_insns.first();
+        let last_iix_in_block = block_insns.last();
         let first_pt_in_block = InstPoint::new_use(first_iix_in_block);
         let last_pt_in_block = InstPoint::new_def(last_iix_in_block);
         let kind = match (first == first_pt_in_block, last == last_pt_in_block) {
@@ -0,0 +1,581 @@
+//! Support for operands that instructions require to be in a given real register.
+//!
+//! Clients declare these with `RegUsageCollector::add_fixed_use` and friends.  None of the
+//! allocators knows about them: instead, the function is wrapped into a `FixedRegFunction`, which
+//! inserts a copy between each constrained virtual register and its real register around the
+//! instruction, presents the instruction as mentioning the real register directly, and hints the
+//! virtual register to that real register.  When the allocator honours the hint, the copy becomes
+//! an identity move, which is removed again when the result is mapped back to the client's
+//! function; so fixups only remain where the value can't live in the required register.
+//!
+//! Defs tied to a use by `RegUsageCollector::add_tied_def` are handled the same way: a copy from
+//! the use to the def is inserted before the instruction, which is presented as modifying the
+//! def, and the two registers are hinted to each other.  So the copy only remains where the use
+//! is still needed after the instruction, or the allocator couldn't keep both in one register.
+
+use crate::data_structures::{
+    BlockIx, FixedReg, FixedRegKind, InstIx, Map, Range, RealReg, Reg, RegClass, RegVecs, Set,
+    SpillSlot, TiedReg, TypedIxVec, VirtualReg, Writable,
+};
+use crate::{
+    Algorithm, Function, RegAllocError, RegAllocResult, RegHint, RegUsageCollector, RegUsageMapper,
+    SpillCostModel, StackmapRequestInfo,
+};
+
+use alloc::borrow::Cow;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use smallvec::SmallVec;
+
+/// The constraints on the operands of an instruction.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InstConstraints {
+    fixed: SmallVec<[FixedReg; 2]>,
+    tied: SmallVec<[TiedReg; 1]>,
+}
+
+/// Collect the register constraints of each instruction of `func`, or `None` if there aren't
+/// any, which is by far the most common case.
+pub(crate) fn collect_fixed_regs<F: Function>(
+    func: &F,
+) -> Option<TypedIxVec<InstIx, InstConstraints>> {
+    let mut reg_vecs = RegVecs::new(false);
+    let mut constraints = TypedIxVec::new();
+    let mut any = false;
+    for insn in func.insns() {
+        reg_vecs.clear();
+        F::get_regs(insn, &mut RegUsageCollector::new(&mut reg_vecs));
+        any |= !reg_vecs.fixed.is_empty() || !reg_vecs.tied.is_empty();
+        constraints.push(InstConstraints {
+            fixed: reg_vecs.fixed.iter().cloned().collect(),
+            tied: reg_vecs.tied.iter().cloned().collect(),
+        });
+    }
+    if any {
+        Some(constraints)
+    } else {
+        None
+    }
+}
+
+/// An instruction of a `FixedRegFunction`.
+#[derive(Clone, Debug)]
+pub(crate) enum FixedRegInst<I> {
+    /// An instruction of the client, with the constraints on its operands.
+    Client {
+        insn: I,
+        fixed: SmallVec<[FixedReg; 2]>,
+        tied: SmallVec<[TiedReg; 1]>,
+    },
+    /// A copy between a constrained virtual register and its real register.
+    Copy {
+        dst: Writable<Reg>,
+        src: Reg,
+        vreg: VirtualReg,
+    },
+}
+
+/// A client function with copies inserted around the instructions that have constrained
+/// operands; see the module comment.
+pub(crate) struct FixedRegFunction<'a, F: Function> {
+    func: &'a F,
+    insns: Vec<FixedRegInst<F::Inst>>,
+    block_insns: TypedIxVec<BlockIx, Range<InstIx>>,
+    /// For each instruction, the index of the client instruction it stems from, or an invalid
+    /// index for the inserted copies.
+    orig_insns: TypedIxVec<InstIx, InstIx>,
+    /// For each client instruction, its index in `insns`.
+    new_insns: TypedIxVec<InstIx, InstIx>,
+    hints: Map<VirtualReg, RegHint>,
+}
+
+impl<'a, F: Function> FixedRegFunction<'a, F> {
+    pub(crate) fn new(
+        func: &'a F,
+        mut constraints: TypedIxVec<InstIx, InstConstraints>,
+    ) -> Result<Self, RegAllocError> {
+        let mut insns = Vec::with_capacity(func.insns().len());
+        let mut block_insns = TypedIxVec::new();
+        let mut orig_insns = TypedIxVec::new();
+        let mut new_insns = TypedIxVec::new();
+        new_insns.resize(func.insns().len() as u32, InstIx::invalid_value());
+        let mut hints = Map::default();
+
+        for block in func.blocks() {
+            let start = insns.len();
+            let range = func.block_insns(block);
+            for iix in range {
+                let InstConstraints { fixed, tied } = core::mem::take(&mut constraints[iix]);
+                for f in fixed.iter().filter(|f| f.kind != FixedRegKind::Def) {
+                    insns.push(FixedRegInst::Copy {
+                        dst: Writable::from_reg(f.rreg.to_reg()),
+                        src: f.vreg.to_reg(),
+                        vreg: f.vreg,
+                    });
+                    orig_insns.push(InstIx::invalid_value());
+                }
+                for t in &tied {
+                    let vreg = match (t.def.is_virtual(), t.use_.is_virtual()) {
+                        (true, true) => {
+                            let (def, use_) = (t.def.to_virtual_reg(), t.use_.to_virtual_reg());
+                            hints.entry(def).or_insert(RegHint::SameAs(use_));
+                            hints.entry(use_).or_insert(RegHint::SameAs(def));
+                            def
+                        }
+                        (true, false) => {
+                            let def = t.def.to_virtual_reg();
+                            hints
+                                .entry(def)
+                                .or_insert(RegHint::Real(t.use_.to_real_reg()));
+                            def
+                        }
+                        (false, true) => {
+                            let use_ = t.use_.to_virtual_reg();
+                            hints
+                                .entry(use_)
+                                .or_insert(RegHint::Real(t.def.to_real_reg()));
+                            use_
+                        }
+                        (false, false) => {
+                            return Err(RegAllocError::Other(
+                                "tied def and use in different real registers".to_string(),
+                            ));
+                        }
+                    };
+                    insns.push(FixedRegInst::Copy {
+                        dst: Writable::from_reg(t.def),
+                        src: t.use_,
+                        vreg,
+                    });
+                    orig_insns.push(InstIx::invalid_value());
+                }
+                new_insns[iix] = InstIx::new(insns.len() as u32);
+                orig_insns.push(iix);
+                let defs: SmallVec<[FixedReg; 2]> = fixed
+                    .iter()
+                    .filter(|f| f.kind != FixedRegKind::Use)
+                    .cloned()
+                    .collect();
+                for f in &fixed {
+                    hints.entry(f.vreg).or_insert(RegHint::Real(f.rreg));
+                }
+                insns.push(FixedRegInst::Client {
+                    insn: func.get_insn(iix).clone(),
+                    fixed,
+                    tied,
+                });
+                if !defs.is_empty() && iix == range.last() {
+                    return Err(RegAllocError::Other(
+                        "fixed def or mod on the last instruction of a block".to_string(),
+                    ));
+                }
+                for f in defs {
+                    insns.push(FixedRegInst::Copy {
+                        dst: Writable::from_reg(f.vreg.to_reg()),
+                        src: f.rreg.to_reg(),
+                        vreg: f.vreg,
+                    });
+                    orig_insns.push(InstIx::invalid_value());
+                }
+            }
+            block_insns.push(Range::new(InstIx::new(start as u32), insns.len() - start));
+        }
+
+        Ok(Self {
+            func,
+            insns,
+            block_insns,
+            orig_insns,
+            new_insns,
+            hints,
+        })
+    }
+
+    /// Translate a stackmap request on the client's function to this one.
+    pub(crate) fn map_stackmap_info(&self, info: &StackmapRequestInfo) -> StackmapRequestInfo {
+        StackmapRequestInfo {
+            reftype_class: info.reftype_class,
+            reftyped_vregs: info.reftyped_vregs.clone(),
+            safepoint_insns: info
+                .safepoint_insns
+                .iter()
+                .map(|&iix| self.new_insns[iix])
+                .collect(),
+            report_reftyped_regs: info.report_reftyped_regs,
+        }
+    }
+
+    /// Translate the instruction indices in allocator options on the client's function to this
+    /// one.
+    pub(crate) fn map_algorithm(&self, algorithm: &Algorithm) -> Algorithm {
+        let mut algorithm = algorithm.clone();
+        if let Algorithm::Backtracking(opts) = &mut algorithm {
+            for pin in &mut opts.memory_pins {
+                pin.iix = self.new_insns[pin.iix];
+            }
+            if let Some(model) = opts.spill_cost_model.take() {
+                opts.spill_cost_model = Some(Arc::new(FixedRegSpillCostModel {
+                    model,
+                    orig_insns: self.orig_insns.clone(),
+                }));
+            }
+        }
+        algorithm
+    }
+
+    /// Translate an allocation of this function back to the client's function, turning the
+    /// copies into client moves and dropping the ones that ended up as identity moves.
+    pub(crate) fn map_result(&self, res: RegAllocResult<Self>) -> RegAllocResult<F> {
+        // For each instruction of the allocation, its index once identity copies are removed.
+        let mut new_ixs = Vec::with_capacity(res.insns.len() + 1);
+        let mut insns = Vec::with_capacity(res.insns.len());
+        let mut orig_insn_map = TypedIxVec::new();
+        for (insn, &orig) in res.insns.into_iter().zip(res.orig_insn_map.iter()) {
+            new_ixs.push(InstIx::new(insns.len() as u32));
+            let insn = match insn {
+                FixedRegInst::Client { insn, .. } => insn,
+                FixedRegInst::Copy { dst, src, vreg } => {
+                    if dst.to_reg() == src {
+                        continue;
+                    }
+                    self.func.gen_move(
+                        Writable::from_reg(dst.to_reg().to_real_reg()),
+                        src.to_real_reg(),
+                        vreg,
+                    )
+                }
+            };
+            insns.push(insn);
+            orig_insn_map.push(if orig.is_invalid() {
+                orig
+            } else {
+                self.orig_insns[orig]
+            });
+        }
+        new_ixs.push(InstIx::new(insns.len() as u32));
+
+        let mut target_map = TypedIxVec::new();
+        for &iix in res.target_map.iter() {
+            target_map.push(new_ixs[iix.get() as usize]);
+        }
+
+        RegAllocResult {
+            insns,
+            target_map,
+            orig_insn_map,
+            clobbered_registers: res.clobbered_registers,
+            num_spill_slots: res.num_spill_slots,
+            block_annotations: res.block_annotations,
+            stackmaps: res.stackmaps,
+            stackmap_regs: res.stackmap_regs,
+            new_safepoint_insns: res
+                .new_safepoint_insns
+                .iter()
+                .map(|iix| new_ixs[iix.get() as usize])
+                .collect(),
+            memory_pin_slots: res.memory_pin_slots,
+            allocation_dump: res.allocation_dump,
+            move_report: res.move_report,
+            split_edges: res.split_edges,
+            stats: res.stats,
+        }
+    }
+}
+
+/// Maps the constrained operands of a client instruction to their real registers, the tied
+/// ones to the register of their def, and all the others as the allocator decided.
+#[derive(Debug)]
+struct FixedRegMapper<'a, RUM: RegUsageMapper> {
+    maps: &'a RUM,
+    fixed: &'a [FixedReg],
+    tied: &'a [TiedReg],
+}
+
+impl<'a, RUM: RegUsageMapper> FixedRegMapper<'a, RUM> {
+    fn lookup(&self, vreg: VirtualReg, kind: FixedRegKind) -> Option<RealReg> {
+        self.fixed
+            .iter()
+            .find(|f| f.vreg == vreg && (f.kind == kind || f.kind == FixedRegKind::Mod))
+            .map(|f| f.rreg)
+    }
+
+    /// The register of the tied `def`, which the instruction is presented as modifying.
+    fn tied_def_reg(&self, def: Reg) -> Option<RealReg> {
+        if def.is_virtual() {
+            self.maps.get_mod(def.to_virtual_reg())
+        } else {
+            Some(def.to_real_reg())
+        }
+    }
+}
+
+impl<'a, RUM: RegUsageMapper> RegUsageMapper for FixedRegMapper<'a, RUM> {
+    fn get_use(&self, vreg: VirtualReg) -> Option<RealReg> {
+        self.lookup(vreg, FixedRegKind::Use)
+            .or_else(|| {
+                let t = self.tied.iter().find(|t| t.use_ == vreg.to_reg())?;
+                self.tied_def_reg(t.def)
+            })
+            .or_else(|| self.maps.get_use(vreg))
+    }
+
+    fn get_def(&self, vreg: VirtualReg) -> Option<RealReg> {
+        self.lookup(vreg, FixedRegKind::Def)
+            .or_else(|| {
+                let t = self.tied.iter().find(|t| t.def == vreg.to_reg())?;
+                self.tied_def_reg(t.def)
+            })
+            .or_else(|| self.maps.get_def(vreg))
+    }
+
+    fn get_mod(&self, vreg: VirtualReg) -> Option<RealReg> {
+        self.lookup(vreg, FixedRegKind::Mod)
+            .or_else(|| self.maps.get_mod(vreg))
+    }
+}
+
+impl<'a, F: Function> Function for FixedRegFunction<'a, F> {
+    type Inst = FixedRegInst<F::Inst>;
+
+    fn insns(&self) -> &[Self::Inst] {
+        &self.insns
+    }
+
+    fn insns_mut(&mut self) -> &mut [Self::Inst] {
+        &mut self.insns
+    }
+
+    fn get_insn(&self, insn: InstIx) -> &Self::Inst {
+        &self.insns[insn.get() as usize]
+    }
+
+    fn get_insn_mut(&mut self, insn: InstIx) -> &mut Self::Inst {
+        &mut self.insns[insn.get() as usize]
+    }
+
+    fn blocks(&self) -> Range<BlockIx> {
+        self.func.blocks()
+    }
+
+    fn entry_block(&self) -> BlockIx {
+        self.func.entry_block()
+    }
+
+    fn block_insns(&self, block: BlockIx) -> Range<InstIx> {
+        self.block_insns[block]
+    }
+
+    fn block_succs(&self, block: BlockIx) -> Cow<'_, [BlockIx]> {
+        self.func.block_succs(block)
+    }
+
+    fn block_params(&self, block: BlockIx) -> Cow<'_, [VirtualReg]> {
+        self.func.block_params(block)
+    }
+
+    fn block_args(&self, block: BlockIx, succ_ix: usize) -> Cow<'_, [VirtualReg]> {
+        self.func.block_args(block, succ_ix)
+    }
+
+    fn block_frequencies(&self) -> Option<Cow<'_, [u32]>> {
+        self.func.block_frequencies()
+    }
+
+    fn is_ret(&self, insn: InstIx) -> bool {
+        let orig = self.orig_insns[insn];
+        !orig.is_invalid() && self.func.is_ret(orig)
+    }
+
+    fn is_included_in_clobbers(&self, insn: &Self::Inst) -> bool {
+        match insn {
+            FixedRegInst::Client { insn, .. } => self.func.is_included_in_clobbers(insn),
+            FixedRegInst::Copy { .. } => true,
+        }
+    }
+
+    fn is_call(&self, insn: &Self::Inst) -> bool {
+        match insn {
+            FixedRegInst::Client { insn, .. } => self.func.is_call(insn),
+            FixedRegInst::Copy { .. } => false,
+        }
+    }
+
+    fn get_regs(insn: &Self::Inst, collector: &mut RegUsageCollector) {
+        match insn {
+            FixedRegInst::Client { insn, .. } => {
+                let uses_start = collector.reg_vecs.uses.len();
+                let defs_start = collector.reg_vecs.defs.len();
+                let tied_start = collector.reg_vecs.tied.len();
+                F::get_regs(insn, collector);
+                // The tied uses are read by the copies before the instruction, which then only
+                // modifies the registers of the defs.  Once the registers are mapped, the tied
+                // operands are the same register, and the client reports a plain mod.
+                let reg_vecs = &mut *collector.reg_vecs;
+                for i in tied_start..reg_vecs.tied.len() {
+                    let TiedReg { def, use_ } = reg_vecs.tied[i];
+                    remove_from(&mut reg_vecs.uses, uses_start, use_);
+                    remove_from(&mut reg_vecs.defs, defs_start, def);
+                    reg_vecs.mods.push(def);
+                }
+            }
+            FixedRegInst::Copy { dst, src, .. } => {
+                collector.add_use(*src);
+                collector.add_def(*dst);
+            }
+        }
+    }
+
+    fn map_regs<RUM: RegUsageMapper>(insn: &mut Self::Inst, maps: &RUM) {
+        match insn {
+            FixedRegInst::Client { insn, fixed, tied } => {
+                F::map_regs(insn, &FixedRegMapper { maps, fixed, tied })
+            }
+            FixedRegInst::Copy { dst, src, .. } => {
+                if src.is_virtual() {
+                    if let Some(rreg) = maps.get_use(src.to_virtual_reg()) {
+                        *src = rreg.to_reg();
+                    }
+                }
+                if dst.to_reg().is_virtual() {
+                    if let Some(rreg) = maps.get_def(dst.to_reg().to_virtual_reg()) {
+                        *dst = Writable::from_reg(rreg.to_reg());
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_move(&self, insn: &Self::Inst) -> Option<(Writable<Reg>, Reg)> {
+        match insn {
+            FixedRegInst::Client { insn, .. } => self.func.is_move(insn),
+            FixedRegInst::Copy { dst, src, .. } => Some((*dst, *src)),
+        }
+    }
+
+    fn reg_hint(&self, vreg: VirtualReg) -> Option<RegHint> {
+        self.func
+            .reg_hint(vreg)
+            .or_else(|| self.hints.get(&vreg).copied())
+    }
+
+    fn pinned_regs(&self) -> Cow<'_, [(VirtualReg, RealReg)]> {
+        self.func.pinned_regs()
+    }
+
+    fn get_num_vregs(&self) -> usize {
+        self.func.get_num_vregs()
+    }
+
+    fn get_spillslot_size(&self, regclass: RegClass, for_vreg: VirtualReg) -> u32 {
+        self.func.get_spillslot_size(regclass, for_vreg)
+    }
+
+    fn get_spillslot_align(&self, regclass: RegClass, for_vreg: VirtualReg) -> u32 {
+        self.func.get_spillslot_align(regclass, for_vreg)
+    }
+
+    fn gen_spill(
+        &self,
+        to_slot: SpillSlot,
+        from_reg: RealReg,
+        for_vreg: Option<VirtualReg>,
+    ) -> Self::Inst {
+        FixedRegInst::Client {
+            insn: self.func.gen_spill(to_slot, from_reg, for_vreg),
+            fixed: SmallVec::new(),
+            tied: SmallVec::new(),
+        }
+    }
+
+    fn gen_reload(
+        &self,
+        to_reg: Writable<RealReg>,
+        from_slot: SpillSlot,
+        for_vreg: Option<VirtualReg>,
+    ) -> Self::Inst {
+        FixedRegInst::Client {
+            insn: self.func.gen_reload(to_reg, from_slot, for_vreg),
+            fixed: SmallVec::new(),
+            tied: SmallVec::new(),
+        }
+    }
+
+    fn gen_move(
+        &self,
+        to_reg: Writable<RealReg>,
+        from_reg: RealReg,
+        for_vreg: VirtualReg,
+    ) -> Self::Inst {
+        FixedRegInst::Client {
+            insn: self.func.gen_move(to_reg, from_reg, for_vreg),
+            fixed: SmallVec::new(),
+            tied: SmallVec::new(),
+        }
+    }
+
+    fn gen_zero_len_nop(&self) -> Self::Inst {
+        FixedRegInst::Client {
+            insn: self.func.gen_zero_len_nop(),
+            fixed: SmallVec::new(),
+            tied: SmallVec::new(),
+        }
+    }
+
+    fn maybe_direct_reload(
+        &self,
+        insn: &Self::Inst,
+        reg: VirtualReg,
+        slot: SpillSlot,
+    ) -> Option<Self::Inst> {
+        match insn {
+            FixedRegInst::Client { insn, fixed, tied } => self
+                .func
+                .maybe_direct_reload(insn, reg, slot)
+                .map(|insn| FixedRegInst::Client {
+                    insn,
+                    fixed: fixed.clone(),
+                    tied: tied.clone(),
+                }),
+            FixedRegInst::Copy { .. } => None,
+        }
+    }
+
+    fn func_liveins(&self) -> Set<RealReg> {
+        self.func.func_liveins()
+    }
+
+    fn func_liveouts(&self) -> Set<RealReg> {
+        self.func.func_liveouts()
+    }
+}
+
+/// Remove all occurrences of `reg` from `regs[start..]`.
+fn remove_from(regs: &mut Vec<Reg>, start: usize, reg: Reg) {
+    let mut i = start;
+    while i < regs.len() {
+        if regs[i] == reg {
+            regs.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// A client's spill cost model, applied to the instructions of a `FixedRegFunction`.
+struct FixedRegSpillCostModel {
+    model: Arc<dyn SpillCostModel + Send + Sync>,
+    orig_insns: TypedIxVec<InstIx, InstIx>,
+}
+
+impl SpillCostModel for FixedRegSpillCostModel {
+    fn mention_cost(&self, vreg: VirtualReg, iix: InstIx, frequency: u32) -> u32 {
+        // The copies aren't the client's instructions, so they get the allocator's own cost.
+        let orig = self.orig_insns[iix];
+        if orig.is_invalid() {
+            frequency
+        } else {
+            self.model.mention_cost(vreg, orig, frequency)
+        }
+    }
+}
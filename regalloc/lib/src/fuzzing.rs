@@ -0,0 +1,535 @@
+//! Random functions to check the allocators with.
+//!
+//! This generates random functions for a given register universe, in the generic form of
+//! `IRFunction`, which implements `Function`: random control flow graphs, with uses, defs and mods
+//! of virtual registers, moves, calls clobbering real registers, and moves to and from real
+//! registers.  It also runs the allocators on them with the checker enabled, so that clients can
+//! check the allocators against the universe of their own instruction set, e.g. in their
+//! continuous integration:
+//!
+//! ```ignore
+//! let config = regalloc::fuzzing::Config {
+//!     call_clobbers: caller_saved_regs(),
+//!     ..Default::default()
+//! };
+//! if let Err(failure) = regalloc::fuzzing::run(&universe, &config, 0..10_000) {
+//!     panic!("{}", failure);
+//! }
+//! ```
+//!
+//! The functions are valid allocator inputs by construction: every virtual register is defined on
+//! all the paths leading to its uses, all the blocks are reachable, there are no critical edges,
+//! and no instruction mentions more registers of a class at once than there are allocable ones.
+//! So any error from an allocator, checker error or not, is reported as a failure.  A given seed
+//! always generates the same function for a given universe and configuration.
+
+use crate::snapshot::{IRFunction, IRInst, IRInstKind, IRSnapshot};
+use crate::{
+    Algorithm, AlgorithmWithDefaults, BlockIx, InstIx, Options, Range, RealReg, RealRegUniverse,
+    Reg, RegAllocError, RegClass, Set, VirtualReg, Writable, MAX_NUM_REG_CLASSES,
+};
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use log::debug;
+
+/// The shape of the random functions.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The maximum number of blocks, not counting the blocks that split critical edges.
+    pub max_blocks: usize,
+    /// The maximum number of instructions in a block, not counting the control flow instruction
+    /// ending it.
+    pub max_block_insts: usize,
+    /// The maximum number of virtual registers.
+    pub max_vregs: usize,
+    /// The maximum number of registers that an instruction uses, and of those it defines.
+    pub max_operands: usize,
+    /// The real registers that calls clobber.  If there are none, the functions have no calls.
+    pub call_clobbers: Vec<RealReg>,
+    /// The algorithms that `check` and `run` allocate each function with.
+    pub algorithms: Vec<AlgorithmWithDefaults>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_blocks: 8,
+            max_block_insts: 12,
+            max_vregs: 24,
+            max_operands: 3,
+            call_clobbers: Vec::new(),
+            algorithms: vec![
+                AlgorithmWithDefaults::Backtracking,
+                AlgorithmWithDefaults::LinearScan,
+            ],
+        }
+    }
+}
+
+/// A random number generator (splitmix64), so that the functions only depend on the seeds.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, which must not be empty.
+    fn below(&mut self, n: usize) -> usize {
+        debug_assert!(n > 0);
+        (self.next() % n as u64) as usize
+    }
+
+    fn percent(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+}
+
+/// The number of registers of each class that an instruction mentions, so as to stay within the
+/// allocable ones.
+#[derive(Clone, Copy, Default)]
+struct ClassCounts([usize; MAX_NUM_REG_CLASSES]);
+
+struct Generator<'a> {
+    rng: Rng,
+    config: &'a Config,
+    /// The number of allocable registers of each class, not counting its scratch register.
+    avail: [usize; MAX_NUM_REG_CLASSES],
+    /// The allocable registers that the instructions may mention, i.e. other than the scratch
+    /// registers.
+    real_regs: Vec<RealReg>,
+    /// The class of each virtual register.
+    vreg_classes: Vec<RegClass>,
+    /// The virtual registers defined in the entry block, before anything else; the others are
+    /// defined in each block before they're used there.
+    num_globals: usize,
+    insts: Vec<IRInst>,
+}
+
+impl<'a> Generator<'a> {
+    fn new(reg_universe: &RealRegUniverse, config: &'a Config, seed: u64) -> Self {
+        let mut rng = Rng(seed);
+        let mut avail = [0; MAX_NUM_REG_CLASSES];
+        let mut real_regs = Vec::new();
+        let mut classes = Vec::new();
+        for (rc, info) in reg_universe.allocable_by_class.iter().enumerate() {
+            let info = match info {
+                Some(info) => info,
+                None => continue,
+            };
+            for index in info.first..=info.last {
+                if Some(index) != info.suggested_scratch {
+                    real_regs.push(reg_universe.regs[index].0);
+                    avail[rc] += 1;
+                }
+            }
+            if avail[rc] > 0 {
+                classes.push(RegClass::rc_from_u32(rc as u32));
+            }
+        }
+        assert!(
+            !classes.is_empty(),
+            "the universe has no allocable registers besides the scratch ones"
+        );
+
+        let num_vregs = 1 + rng.below(config.max_vregs.max(1));
+        let vreg_classes = (0..num_vregs).map(|_| rng.pick(&classes)).collect();
+        let num_globals = 1 + rng.below(num_vregs);
+
+        Self {
+            rng,
+            config,
+            avail,
+            real_regs,
+            vreg_classes,
+            num_globals,
+            insts: Vec::new(),
+        }
+    }
+
+    fn vreg(&self, index: usize) -> VirtualReg {
+        Reg::new_virtual(self.vreg_classes[index], index as u32).to_virtual_reg()
+    }
+
+    /// Picks up to `max` distinct virtual registers among `candidates`, other than those in
+    /// `excluded`, counting them in `counts` and staying within the allocable registers.
+    fn operands(
+        &mut self,
+        candidates: &[usize],
+        max: usize,
+        excluded: &[Reg],
+        counts: &mut ClassCounts,
+    ) -> Vec<Reg> {
+        let mut regs: Vec<Reg> = Vec::new();
+        if candidates.is_empty() {
+            return regs;
+        }
+        for _ in 0..self.rng.below(max + 1) {
+            let index = self.rng.pick(candidates);
+            let reg = self.vreg(index).to_reg();
+            let rc = reg.get_class().rc_to_usize();
+            if regs.contains(&reg) || excluded.contains(&reg) || counts.0[rc] == self.avail[rc] {
+                continue;
+            }
+            counts.0[rc] += 1;
+            regs.push(reg);
+        }
+        regs
+    }
+
+    fn push(&mut self, kind: IRInstKind, uses: Vec<Reg>, mods: Vec<Reg>, defs: Vec<Reg>) {
+        self.insts.push(IRInst {
+            reg_uses: uses,
+            reg_mods: mods.into_iter().map(Writable::from_reg).collect(),
+            reg_defs: defs.into_iter().map(Writable::from_reg).collect(),
+            kind,
+        });
+    }
+
+    /// Generates an instruction, given the virtual registers defined so far in the block.
+    fn inst(&mut self, defined: &mut Vec<usize>) {
+        let all: Vec<usize> = (0..self.vreg_classes.len()).collect();
+        match self.rng.below(20) {
+            // A call, using some values and clobbering registers.
+            0..=1 if !self.config.call_clobbers.is_empty() => {
+                let uses = self.operands(
+                    defined,
+                    self.config.max_operands,
+                    &[],
+                    &mut Default::default(),
+                );
+                let defs = self
+                    .config
+                    .call_clobbers
+                    .iter()
+                    .map(|r| r.to_reg())
+                    .collect();
+                self.push(IRInstKind::UserCall, uses, vec![], defs);
+            }
+
+            // A move of a value to a real register, and from there to a virtual register, as
+            // around calls and at function boundaries.
+            2..=3 if !self.real_regs.is_empty() => {
+                let rreg = self.rng.pick(&self.real_regs).to_reg();
+                let of_class = |indices: &[usize]| -> Vec<usize> {
+                    indices
+                        .iter()
+                        .cloned()
+                        .filter(|&index| self.vreg_classes[index] == rreg.get_class())
+                        .collect()
+                };
+                let sources = of_class(defined);
+                let dsts = of_class(&all);
+                if sources.is_empty() {
+                    return;
+                }
+                let src = self.rng.pick(&sources);
+                let src = self.vreg(src).to_reg();
+                self.push(IRInstKind::UserMove, vec![src], vec![], vec![rreg]);
+                let dst = self.rng.pick(&dsts);
+                self.push(
+                    IRInstKind::UserMove,
+                    vec![rreg],
+                    vec![],
+                    vec![self.vreg(dst).to_reg()],
+                );
+                if !defined.contains(&dst) {
+                    defined.push(dst);
+                }
+            }
+
+            // A move between virtual registers.
+            4..=6 if !defined.is_empty() => {
+                let src = self.rng.pick(defined);
+                let rc = self.vreg_classes[src];
+                let dsts: Vec<usize> = all
+                    .iter()
+                    .cloned()
+                    .filter(|&index| index != src && self.vreg_classes[index] == rc)
+                    .collect();
+                if dsts.is_empty() {
+                    return;
+                }
+                let dst = self.rng.pick(&dsts);
+                self.push(
+                    IRInstKind::UserMove,
+                    vec![self.vreg(src).to_reg()],
+                    vec![],
+                    vec![self.vreg(dst).to_reg()],
+                );
+                if !defined.contains(&dst) {
+                    defined.push(dst);
+                }
+            }
+
+            // Any other instruction, using, modifying and defining virtual registers.
+            _ => {
+                let max = self.config.max_operands;
+                let mut use_counts = ClassCounts::default();
+                let uses = self.operands(defined, max, &[], &mut use_counts);
+                let mods = if self.rng.percent(25) {
+                    self.operands(defined, 1, &uses, &mut use_counts)
+                } else {
+                    vec![]
+                };
+                let mut def_counts = ClassCounts::default();
+                for reg in &mods {
+                    def_counts.0[reg.get_class().rc_to_usize()] += 1;
+                }
+                let excluded: Vec<Reg> = uses.iter().chain(mods.iter()).cloned().collect();
+                let defs = self.operands(&all, max, &excluded, &mut def_counts);
+                for reg in &defs {
+                    let index = reg.get_index();
+                    if !defined.contains(&index) {
+                        defined.push(index);
+                    }
+                }
+                self.push(IRInstKind::UserOther, uses, mods, defs);
+            }
+        }
+    }
+
+    /// Generates the control flow graph: the successors of each block.  The blocks are all
+    /// reachable from the entry block, which has no predecessors, and there are no critical
+    /// edges.
+    ///
+    /// Returns the successors of each block, and the number of blocks before the ones splitting
+    /// critical edges.
+    fn cfg(&mut self) -> (Vec<Vec<BlockIx>>, usize) {
+        let num_blocks = 1 + self.rng.below(self.config.max_blocks.max(1));
+        let mut succs: Vec<Vec<usize>> = vec![vec![]; num_blocks];
+
+        // A spanning tree first, so that all the blocks are reachable.  The previous block never
+        // has any successor yet, so there's always a block with room for one more.
+        for block in 1..num_blocks {
+            let parents: Vec<usize> = (0..block).filter(|&b| succs[b].len() < 2).collect();
+            let parent = self.rng.pick(&parents);
+            succs[parent].push(block);
+        }
+        // Then other edges, possibly making loops.
+        if num_blocks > 1 {
+            for block in 0..num_blocks {
+                while succs[block].len() < 2 && self.rng.percent(30) {
+                    let succ = 1 + self.rng.below(num_blocks - 1);
+                    if !succs[block].contains(&succ) {
+                        succs[block].push(succ);
+                    }
+                }
+            }
+        }
+
+        // Split the critical edges with blocks that just jump to their destination.
+        let mut num_preds = vec![0; num_blocks];
+        for &succ in succs.iter().flatten() {
+            num_preds[succ] += 1;
+        }
+        for block in 0..num_blocks {
+            if succs[block].len() < 2 {
+                continue;
+            }
+            for ix in 0..succs[block].len() {
+                let succ = succs[block][ix];
+                if num_preds[succ] > 1 {
+                    succs[block][ix] = succs.len();
+                    succs.push(vec![succ]);
+                }
+            }
+        }
+
+        let succs = succs
+            .into_iter()
+            .map(|succs| {
+                succs
+                    .into_iter()
+                    .map(|succ| BlockIx::new(succ as u32))
+                    .collect()
+            })
+            .collect();
+        (succs, num_blocks)
+    }
+
+    fn function(mut self) -> IRFunction {
+        let (block_succs, num_blocks) = self.cfg();
+        let globals: Vec<usize> = (0..self.num_globals).collect();
+
+        let mut block_ranges = Vec::new();
+        for (block, succs) in block_succs.iter().enumerate() {
+            let start = self.insts.len();
+
+            if block == 0 {
+                // Define the global virtual registers first.
+                let mut counts = ClassCounts::default();
+                let mut defs = Vec::new();
+                for &index in &globals {
+                    let reg = self.vreg(index).to_reg();
+                    let rc = reg.get_class().rc_to_usize();
+                    if defs.len() == self.config.max_operands.max(1)
+                        || counts.0[rc] == self.avail[rc]
+                    {
+                        self.push(IRInstKind::UserOther, vec![], vec![], defs);
+                        defs = Vec::new();
+                        counts = ClassCounts::default();
+                    }
+                    counts.0[rc] += 1;
+                    defs.push(reg);
+                }
+                self.push(IRInstKind::UserOther, vec![], vec![], defs);
+            }
+            let mut defined = globals.clone();
+
+            // The blocks splitting critical edges only jump.
+            if block < num_blocks {
+                for _ in 0..self.rng.below(self.config.max_block_insts + 1) {
+                    self.inst(&mut defined);
+                }
+            }
+
+            match succs.len() {
+                0 => {
+                    let max = self.config.max_operands;
+                    let uses = self.operands(&defined, max, &[], &mut Default::default());
+                    self.push(IRInstKind::UserReturn, uses, vec![], vec![]);
+                }
+                1 => self.push(IRInstKind::UserOther, vec![], vec![], vec![]),
+                _ => {
+                    let cond = self.operands(&defined, 1, &[], &mut Default::default());
+                    self.push(IRInstKind::UserOther, cond, vec![], vec![]);
+                }
+            }
+
+            block_ranges.push(Range::new(
+                InstIx::new(start as u32),
+                self.insts.len() - start,
+            ));
+        }
+
+        let vreg_spill_slot_layouts = self
+            .vreg_classes
+            .iter()
+            .map(|&rc| Some((1, 1, rc)))
+            .collect();
+        IRFunction {
+            instructions: self.insts,
+            block_ranges,
+            block_succs,
+            entry_block: BlockIx::new(0),
+            liveins: Set::empty(),
+            liveouts: Set::empty(),
+            vreg_spill_slot_layouts,
+            num_vregs: self.vreg_classes.len(),
+        }
+    }
+}
+
+/// Generates the random function of `seed`, for `reg_universe`.
+pub fn generate(reg_universe: &RealRegUniverse, config: &Config, seed: u64) -> IRSnapshot {
+    let func = Generator::new(reg_universe, config, seed).function();
+    IRSnapshot::new(reg_universe.clone(), func)
+}
+
+/// An allocation of a random function that failed.
+#[derive(Clone)]
+pub struct Failure {
+    /// The seed of the function.
+    pub seed: u64,
+    /// The algorithm that failed.
+    pub algorithm: AlgorithmWithDefaults,
+    /// The function and its universe.  With the `enable-serde` feature, it can be saved to replay
+    /// the failure, e.g. with minira's `--snapshot` option.
+    pub snapshot: IRSnapshot,
+    /// The error from the allocator; a checker error if the allocation was wrong.
+    pub error: RegAllocError,
+}
+
+impl fmt::Debug for Failure {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "Failure {{ seed: {}, algorithm: {:?}, error: {:?} }}",
+            self.seed, self.algorithm, self.error
+        )
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{:?} allocation of the random function of seed {} failed: {}",
+            self.algorithm, self.seed, self.error
+        )
+    }
+}
+
+/// Statistics about a run of the allocators on random functions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of functions generated.
+    pub num_functions: usize,
+    /// The number of instructions in them, in total.
+    pub num_insts: usize,
+    /// The number of spill slots used by the allocations, in total.
+    pub num_spill_slots: usize,
+}
+
+/// Allocates the random function of `seed` with each of the algorithms of `config`, with the
+/// checker enabled.
+pub fn check(
+    reg_universe: &RealRegUniverse,
+    config: &Config,
+    seed: u64,
+    stats: &mut Stats,
+) -> Result<(), Failure> {
+    let snapshot = generate(reg_universe, config, seed);
+    debug!(
+        "fuzzing: seed {}, {} instructions",
+        seed,
+        snapshot.func().instructions.len()
+    );
+    stats.num_functions += 1;
+    stats.num_insts += snapshot.func().instructions.len();
+
+    for &algorithm in &config.algorithms {
+        let opts = Options {
+            run_checker: true,
+            algorithm: Algorithm::with_defaults(algorithm),
+            ..Default::default()
+        };
+        match snapshot.clone().allocate(opts) {
+            Ok(result) => stats.num_spill_slots += result.num_spill_slots as usize,
+            Err(error) => {
+                return Err(Failure {
+                    seed,
+                    algorithm,
+                    snapshot,
+                    error,
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Allocates the random functions of all the `seeds` with each of the algorithms of `config`, with
+/// the checker enabled, stopping at the first failure.
+pub fn run(
+    reg_universe: &RealRegUniverse,
+    config: &Config,
+    seeds: core::ops::Range<u64>,
+) -> Result<Stats, Failure> {
+    let mut stats = Stats::default();
+    for seed in seeds {
+        check(reg_universe, config, seed, &mut stats)?;
+    }
+    Ok(stats)
+}
@@ -0,0 +1,936 @@
+#![allow(non_snake_case)]
+
+//! Implementation of the graph coloring allocator.
+//!
+//! This is a Chaitin-Briggs style allocator with iterated register coalescing, following
+//!   Iterated Register Coalescing, by George and Appel, 1996.
+//!
+//! It reuses the liveness analysis, the coalescing analysis and the spilling machinery of the
+//! backtracking allocator.  Each round builds an interference graph over the VirtualRanges that
+//! are still around, and then simplifies, coalesces, freezes and spills nodes until the graph is
+//! empty.  Popping the nodes off the select stack then assigns them registers.  VirtualRanges
+//! that can't be colored are spilled, by replacing them with short spill and reload bridges just
+//! like the backtracking allocator does, and a new round starts with the resulting set of
+//! ranges.  Since bridges have an infinite spill cost, they are never spilled again, and so the
+//! process terminates.
+//!
+//! RealRanges aren't nodes of the graph.  Instead each node carries the set of real registers
+//! whose RealRanges it overlaps, which it can't be assigned; these count towards its degree.
+
+use log::{debug, info};
+use smallvec::SmallVec;
+
+use alloc::fmt;
+use core::default;
+
+use crate::analysis_data_flow::does_inst_use_def_or_mod_reg;
+use crate::analysis_main::{run_analysis, AnalysisInfo};
+use crate::bt_coalescing_analysis::{do_coalescing_analysis, Hint};
+use crate::bt_main::{finish_allocation, BridgeKind, EditListItem, PerRealReg};
+use crate::bt_spillslot_allocator::SpillSlotAllocator;
+use crate::data_structures::{
+    InstIx, InstPoint, Point, RangeFrag, RangeFragIx, RealRange, RealRangeIx, RealReg,
+    RealRegUniverse, RegClass, RegVecsAndBounds, Set, SortedRangeFrags, SpillCost, SpillSlot,
+    TypedIxVec, VirtualRange, VirtualRangeIx,
+};
+use crate::union_find::UnionFindEquivClasses;
+use crate::{AlgorithmWithDefaults, Function, RegAllocError, RegAllocResult, StackmapRequestInfo};
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Clone)]
+pub struct GraphColoringOptions {
+    /// Should the register allocator generate block annotations?
+    pub request_block_annotations: bool,
+}
+
+impl default::Default for GraphColoringOptions {
+    fn default() -> Self {
+        Self {
+            request_block_annotations: false,
+        }
+    }
+}
+
+impl fmt::Debug for GraphColoringOptions {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "graph coloring (block annotations: {})",
+            self.request_block_annotations
+        )
+    }
+}
+
+//=============================================================================
+// The interference graph
+//
+// Nodes are identified by the index of their VirtualRange.  Worklists are plain vectors with
+// lazy deletion: moving a node (or move) to another worklist only updates its state, and stale
+// entries are skipped when popped.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum NodeState {
+    // Spilled in an earlier round, hence not part of the graph.
+    Absent,
+    // Low degree, not move related.
+    Simplify,
+    // Low degree, move related.
+    Freeze,
+    // High degree.
+    Spill,
+    // Merged into the node given by `alias`.
+    Coalesced,
+    // Removed from the graph, waiting on the select stack to be colored.
+    OnStack,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum MoveState {
+    // Not yet considered for coalescing.
+    Worklist,
+    // Considered, but not yet ready for coalescing.
+    Active,
+    Coalesced,
+    // Its source and destination interfere.
+    Constrained,
+    // No longer considered for coalescing.
+    Frozen,
+}
+
+// A copy between two VirtualRanges, as found by the coalescing analysis.  Which of them is the
+// source of the copy doesn't matter here.
+struct Move {
+    src: u32,
+    dst: u32,
+    state: MoveState,
+}
+
+struct Graph {
+    state: Vec<NodeState>,
+    // The number of registers each node may be assigned, ignoring interference.
+    num_regs: Vec<u32>,
+    // The number of neighbours in the graph.
+    degree: Vec<u32>,
+    // The real registers whose RealRanges overlap the node, ascending by index.
+    forbidden: Vec<SmallVec<[RealReg; 4]>>,
+    spill_cost: Vec<SpillCost>,
+    adj_set: Set<(u32, u32)>,
+    adj_list: Vec<SmallVec<[u32; 8]>>,
+    alias: Vec<u32>,
+    moves: Vec<Move>,
+    move_list: Vec<SmallVec<[u32; 4]>>,
+
+    simplify_worklist: Vec<u32>,
+    freeze_worklist: Vec<u32>,
+    spill_worklist: Vec<u32>,
+    worklist_moves: Vec<u32>,
+    select_stack: Vec<u32>,
+
+    num_coalesced_moves: usize, // stats only
+}
+
+impl Graph {
+    // Build the interference graph for all VirtualRanges which haven't been spilled.
+    #[inline(never)]
+    fn build(
+        reg_universe: &RealRegUniverse,
+        rlr_env: &TypedIxVec<RealRangeIx, RealRange>,
+        vlr_env: &TypedIxVec<VirtualRangeIx, VirtualRange>,
+        frag_env: &TypedIxVec<RangeFragIx, RangeFrag>,
+        spilled: &TypedIxVec<VirtualRangeIx, bool>,
+        hints: &TypedIxVec<VirtualRangeIx, SmallVec<[Hint; 8]>>,
+    ) -> Result<Self, RegAllocError> {
+        let num_nodes = vlr_env.len() as usize;
+
+        let mut num_regs_by_class = vec![0u32; reg_universe.num_reg_classes()];
+        for (rc, info) in reg_universe.allocable_by_class.iter().enumerate() {
+            if let Some(info) = info {
+                num_regs_by_class[rc] = (info.last - info.first + 1) as u32;
+            }
+        }
+
+        let mut graph = Graph {
+            state: vec![NodeState::Absent; num_nodes],
+            num_regs: vec![0; num_nodes],
+            degree: vec![0; num_nodes],
+            forbidden: vec![SmallVec::new(); num_nodes],
+            spill_cost: vec![SpillCost::zero(); num_nodes],
+            adj_set: Set::empty(),
+            adj_list: vec![SmallVec::new(); num_nodes],
+            alias: (0..num_nodes as u32).collect(),
+            moves: vec![],
+            move_list: vec![SmallVec::new(); num_nodes],
+            simplify_worklist: vec![],
+            freeze_worklist: vec![],
+            spill_worklist: vec![],
+            worklist_moves: vec![],
+            select_stack: vec![],
+            num_coalesced_moves: 0,
+        };
+
+        // Collect the frags of all participating ranges, and sweep over them in order of start
+        // point, keeping track of the frags which are live at that point.
+        let mut frags = Vec::<(
+            RangeFrag,
+            /*virtual?*/ bool,
+            /*vlrix or rreg index*/ u32,
+        )>::new();
+        for (vlrix, vlr) in vlr_env.iter().enumerate() {
+            if spilled[VirtualRangeIx::new(vlrix as u32)] {
+                continue;
+            }
+            let rc = vlr.vreg.get_class();
+            let num_regs = num_regs_by_class.get(rc.rc_to_usize()).copied();
+            if num_regs.unwrap_or(0) == 0 {
+                return Err(RegAllocError::OutOfRegisters(rc));
+            }
+            graph.state[vlrix] = NodeState::Simplify;
+            graph.num_regs[vlrix] = num_regs.unwrap();
+            graph.spill_cost[vlrix] = vlr.spill_cost;
+            for frag in vlr.sorted_frags.iter() {
+                frags.push((frag.clone(), true, vlrix as u32));
+            }
+        }
+        for rlr in rlr_env.iter() {
+            // RealRegs that are not part of the allocatable set don't concern us.
+            if rlr.rreg.get_index() >= reg_universe.allocable {
+                continue;
+            }
+            for fix in rlr.sorted_frags.iter() {
+                frags.push((frag_env[*fix].clone(), false, rlr.rreg.get_index() as u32));
+            }
+        }
+        frags.sort_unstable_by_key(|(frag, _, _)| frag.first);
+
+        let mut live = Vec::<(RangeFrag, bool, u32)>::new();
+        for (frag, is_virtual, ix) in frags {
+            live.retain(|(live_frag, _, _)| live_frag.last >= frag.first);
+            for &(_, live_is_virtual, live_ix) in &live {
+                match (is_virtual, live_is_virtual) {
+                    (true, true) => {
+                        let class = vlr_env[VirtualRangeIx::new(ix)].vreg.get_class();
+                        let live_class = vlr_env[VirtualRangeIx::new(live_ix)].vreg.get_class();
+                        if class == live_class {
+                            graph.add_edge(ix, live_ix);
+                        }
+                    }
+                    (true, false) => graph.add_forbidden(reg_universe, vlr_env, ix, live_ix),
+                    (false, true) => graph.add_forbidden(reg_universe, vlr_env, live_ix, ix),
+                    (false, false) => {}
+                }
+            }
+            live.push((frag, is_virtual, ix));
+        }
+
+        // Record the copies between participating VirtualRanges.  The coalescing analysis
+        // gives a hint for each end of a copy, so only look at one of them.  Copies with the
+        // highest estimated execution count get considered first.
+        let mut weighted_moves = Vec::<(u32, u32, u32)>::new();
+        for (vlrix, vlr_hints) in hints.iter().enumerate() {
+            let vlrix = vlrix as u32;
+            if graph.state[vlrix as usize] == NodeState::Absent {
+                continue;
+            }
+            for hint in vlr_hints {
+                if let Hint::SameAs(other_vlrix, weight) = hint {
+                    let other = other_vlrix.get();
+                    if vlrix < other && graph.state[other as usize] != NodeState::Absent {
+                        weighted_moves.push((*weight, vlrix, other));
+                    }
+                }
+            }
+        }
+        weighted_moves.sort_by(|(w1, _, _), (w2, _, _)| w1.cmp(w2));
+        for (_, src, dst) in weighted_moves {
+            let mix = graph.moves.len() as u32;
+            graph.moves.push(Move {
+                src,
+                dst,
+                state: MoveState::Worklist,
+            });
+            graph.move_list[src as usize].push(mix);
+            graph.move_list[dst as usize].push(mix);
+            graph.worklist_moves.push(mix);
+        }
+
+        Ok(graph)
+    }
+
+    fn add_edge(&mut self, u: u32, v: u32) {
+        if u != v && !self.adj_set.contains((u, v)) {
+            self.adj_set.insert((u, v));
+            self.adj_set.insert((v, u));
+            self.adj_list[u as usize].push(v);
+            self.adj_list[v as usize].push(u);
+            self.degree[u as usize] += 1;
+            self.degree[v as usize] += 1;
+        }
+    }
+
+    fn add_forbidden(
+        &mut self,
+        reg_universe: &RealRegUniverse,
+        vlr_env: &TypedIxVec<VirtualRangeIx, VirtualRange>,
+        n: u32,
+        rreg_ix: u32,
+    ) {
+        let rreg = reg_universe.regs[rreg_ix as usize].0;
+        if rreg.get_class() != vlr_env[VirtualRangeIx::new(n)].vreg.get_class() {
+            return;
+        }
+        let forbidden = &mut self.forbidden[n as usize];
+        if let Err(pos) = forbidden.binary_search_by_key(&rreg.get_index(), |r| r.get_index()) {
+            forbidden.insert(pos, rreg);
+        }
+    }
+
+    fn is_significant(&self, n: u32) -> bool {
+        let n = n as usize;
+        self.degree[n] as usize + self.forbidden[n].len() >= self.num_regs[n] as usize
+    }
+
+    fn is_in_graph(&self, n: u32) -> bool {
+        match self.state[n as usize] {
+            NodeState::Simplify | NodeState::Freeze | NodeState::Spill => true,
+            NodeState::Absent | NodeState::Coalesced | NodeState::OnStack => false,
+        }
+    }
+
+    fn adjacent(&self, n: u32) -> SmallVec<[u32; 8]> {
+        self.adj_list[n as usize]
+            .iter()
+            .cloned()
+            .filter(|&m| self.is_in_graph(m))
+            .collect()
+    }
+
+    fn node_moves(&self, n: u32) -> SmallVec<[u32; 4]> {
+        self.move_list[n as usize]
+            .iter()
+            .cloned()
+            .filter(|&mix| {
+                let state = self.moves[mix as usize].state;
+                state == MoveState::Worklist || state == MoveState::Active
+            })
+            .collect()
+    }
+
+    fn is_move_related(&self, n: u32) -> bool {
+        !self.node_moves(n).is_empty()
+    }
+
+    fn get_alias(&self, mut n: u32) -> u32 {
+        while self.state[n as usize] == NodeState::Coalesced {
+            n = self.alias[n as usize];
+        }
+        n
+    }
+
+    fn set_state(&mut self, n: u32, state: NodeState) {
+        self.state[n as usize] = state;
+        match state {
+            NodeState::Simplify => self.simplify_worklist.push(n),
+            NodeState::Freeze => self.freeze_worklist.push(n),
+            NodeState::Spill => self.spill_worklist.push(n),
+            NodeState::OnStack => self.select_stack.push(n),
+            NodeState::Absent | NodeState::Coalesced => {}
+        }
+    }
+
+    fn make_worklists(&mut self) {
+        for n in 0..self.state.len() as u32 {
+            if self.state[n as usize] == NodeState::Absent {
+                continue;
+            }
+            if self.is_significant(n) {
+                self.set_state(n, NodeState::Spill);
+            } else if self.is_move_related(n) {
+                self.set_state(n, NodeState::Freeze);
+            } else {
+                self.set_state(n, NodeState::Simplify);
+            }
+        }
+    }
+
+    // Pop a node of state `state` off `worklist`, skipping stale entries.
+    fn pop_worklist(
+        worklist: &mut Vec<u32>,
+        states: &[NodeState],
+        state: NodeState,
+    ) -> Option<u32> {
+        while let Some(n) = worklist.pop() {
+            if states[n as usize] == state {
+                return Some(n);
+            }
+        }
+        None
+    }
+
+    // Run the main loop of the algorithm, leaving all nodes either coalesced or on the select
+    // stack.
+    #[inline(never)]
+    fn simplify_coalesce_freeze_spill(&mut self) {
+        self.make_worklists();
+        loop {
+            if let Some(n) = Self::pop_worklist(
+                &mut self.simplify_worklist,
+                &self.state,
+                NodeState::Simplify,
+            ) {
+                self.simplify(n);
+            } else if let Some(mix) = self.pop_worklist_move() {
+                self.coalesce(mix);
+            } else if let Some(n) =
+                Self::pop_worklist(&mut self.freeze_worklist, &self.state, NodeState::Freeze)
+            {
+                self.freeze(n);
+            } else if let Some(n) = self.pop_spill_candidate() {
+                debug!("--   potential spill   {:?}", VirtualRangeIx::new(n));
+                self.freeze(n);
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn simplify(&mut self, n: u32) {
+        self.set_state(n, NodeState::OnStack);
+        for m in self.adjacent(n) {
+            self.decrement_degree(m);
+        }
+    }
+
+    fn decrement_degree(&mut self, m: u32) {
+        let was_significant = self.is_significant(m);
+        self.degree[m as usize] -= 1;
+        if was_significant && !self.is_significant(m) && self.state[m as usize] == NodeState::Spill
+        {
+            let mut nodes = self.adjacent(m);
+            nodes.push(m);
+            self.enable_moves(&nodes);
+            if self.is_move_related(m) {
+                self.set_state(m, NodeState::Freeze);
+            } else {
+                self.set_state(m, NodeState::Simplify);
+            }
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[u32]) {
+        for &n in nodes {
+            for mix in self.node_moves(n) {
+                if self.moves[mix as usize].state == MoveState::Active {
+                    self.moves[mix as usize].state = MoveState::Worklist;
+                    self.worklist_moves.push(mix);
+                }
+            }
+        }
+    }
+
+    fn pop_worklist_move(&mut self) -> Option<u32> {
+        while let Some(mix) = self.worklist_moves.pop() {
+            if self.moves[mix as usize].state == MoveState::Worklist {
+                return Some(mix);
+            }
+        }
+        None
+    }
+
+    fn add_worklist(&mut self, u: u32) {
+        if self.state[u as usize] == NodeState::Freeze
+            && !self.is_move_related(u)
+            && !self.is_significant(u)
+        {
+            self.set_state(u, NodeState::Simplify);
+        }
+    }
+
+    // The Briggs test: `u` and `v` can be merged without making the graph uncolorable if the
+    // merged node has fewer than K neighbours of significant degree.
+    fn is_conservative(&self, u: u32, v: u32) -> bool {
+        let mut neighbours = self.adjacent(u);
+        neighbours.extend(self.adjacent(v));
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        let mut forbidden = self.forbidden[u as usize].clone();
+        for rreg in &self.forbidden[v as usize] {
+            if !forbidden.contains(rreg) {
+                forbidden.push(*rreg);
+            }
+        }
+        let mut k = forbidden.len();
+        for m in neighbours {
+            // A neighbour of both `u` and `v` loses one neighbour by the merge.
+            let shared = self.adj_set.contains((m, u)) && self.adj_set.contains((m, v));
+            let degree = self.degree[m as usize] as usize - if shared { 1 } else { 0 };
+            if degree + self.forbidden[m as usize].len() >= self.num_regs[m as usize] as usize {
+                k += 1;
+            }
+        }
+        k < self.num_regs[u as usize] as usize
+    }
+
+    fn coalesce(&mut self, mix: u32) {
+        let Move { src, dst, .. } = self.moves[mix as usize];
+        let (u, v) = (self.get_alias(src), self.get_alias(dst));
+        if u == v {
+            self.moves[mix as usize].state = MoveState::Coalesced;
+            self.num_coalesced_moves += 1;
+            self.add_worklist(u);
+        } else if self.adj_set.contains((u, v)) {
+            self.moves[mix as usize].state = MoveState::Constrained;
+            self.add_worklist(u);
+            self.add_worklist(v);
+        } else if self.is_conservative(u, v) {
+            self.moves[mix as usize].state = MoveState::Coalesced;
+            self.num_coalesced_moves += 1;
+            self.combine(u, v);
+            self.add_worklist(u);
+        } else {
+            self.moves[mix as usize].state = MoveState::Active;
+        }
+    }
+
+    fn combine(&mut self, u: u32, v: u32) {
+        debug!(
+            "--   coalesce          {:?} into {:?}",
+            VirtualRangeIx::new(v),
+            VirtualRangeIx::new(u)
+        );
+        self.set_state(v, NodeState::Coalesced);
+        self.alias[v as usize] = u;
+        let v_moves = self.move_list[v as usize].clone();
+        self.move_list[u as usize].extend(v_moves);
+        self.enable_moves(&[v]);
+        let v_forbidden = self.forbidden[v as usize].clone();
+        for rreg in v_forbidden {
+            let forbidden = &mut self.forbidden[u as usize];
+            if let Err(pos) = forbidden.binary_search_by_key(&rreg.get_index(), |r| r.get_index()) {
+                forbidden.insert(pos, rreg);
+            }
+        }
+        let v_cost = self.spill_cost[v as usize];
+        self.spill_cost[u as usize].add(&v_cost);
+        for t in self.adjacent(v) {
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+        if self.is_significant(u) && self.state[u as usize] == NodeState::Freeze {
+            self.set_state(u, NodeState::Spill);
+        }
+    }
+
+    fn freeze(&mut self, u: u32) {
+        self.set_state(u, NodeState::Simplify);
+        self.freeze_moves(u);
+    }
+
+    fn freeze_moves(&mut self, u: u32) {
+        for mix in self.node_moves(u) {
+            let Move { src, dst, .. } = self.moves[mix as usize];
+            let v = if self.get_alias(dst) == self.get_alias(u) {
+                self.get_alias(src)
+            } else {
+                self.get_alias(dst)
+            };
+            self.moves[mix as usize].state = MoveState::Frozen;
+            if self.state[v as usize] == NodeState::Freeze && !self.is_move_related(v) {
+                self.set_state(v, NodeState::Simplify);
+            }
+        }
+    }
+
+    // Choose the node to optimistically push on the select stack when nothing else can be done:
+    // the one with the lowest spill cost per unit of degree.  Nodes with an infinite spill cost
+    // are only chosen if there is nothing else, which pushes them late and hence colors them
+    // early.
+    fn pop_spill_candidate(&mut self) -> Option<u32> {
+        let state = &self.state;
+        self.spill_worklist
+            .retain(|&n| state[n as usize] == NodeState::Spill);
+        let mut best: Option<(usize, f32)> = None;
+        for (i, &n) in self.spill_worklist.iter().enumerate() {
+            let n = n as usize;
+            let degree = (self.degree[n] as usize + self.forbidden[n].len()) as f32;
+            let metric = match self.spill_cost[n] {
+                SpillCost::Finite(cost) => cost / degree,
+                SpillCost::Infinite => core::f32::INFINITY,
+            };
+            if best.map_or(true, |(_, best_metric)| metric < best_metric) {
+                best = Some((i, metric));
+            }
+        }
+        best.map(|(i, _)| self.spill_worklist.swap_remove(i))
+    }
+
+    // Pop all nodes off the select stack and assign them registers.  Returns the assignment for
+    // every node in the graph, or the nodes that couldn't be colored.
+    #[inline(never)]
+    fn assign_colors(
+        &mut self,
+        reg_universe: &RealRegUniverse,
+        vlr_env: &TypedIxVec<VirtualRangeIx, VirtualRange>,
+        hints: &TypedIxVec<VirtualRangeIx, SmallVec<[Hint; 8]>>,
+    ) -> Result<Vec<Option<RealReg>>, Vec<u32>> {
+        let num_nodes = self.state.len();
+
+        let mut members = vec![SmallVec::<[u32; 2]>::new(); num_nodes];
+        for n in 0..num_nodes as u32 {
+            if self.state[n as usize] == NodeState::Coalesced {
+                members[self.get_alias(n) as usize].push(n);
+            }
+        }
+
+        let mut colors = vec![None::<RealReg>; num_nodes];
+        let mut spilled = Vec::<u32>::new();
+        while let Some(n) = self.select_stack.pop() {
+            let rc = vlr_env[VirtualRangeIx::new(n)].vreg.get_class();
+            let info = reg_universe.class_info(rc).unwrap();
+            let mut ok = vec![true; info.last - info.first + 1];
+            for rreg in &self.forbidden[n as usize] {
+                ok[rreg.get_index() - info.first] = false;
+            }
+            for &w in &self.adj_list[n as usize] {
+                if let Some(rreg) = colors[self.get_alias(w) as usize] {
+                    ok[rreg.get_index() - info.first] = false;
+                }
+            }
+
+            // Prefer the registers hinted at by the coalescing analysis, in order of decreasing
+            // weight.  This takes care of copies that weren't coalesced away, as well as copies
+            // from and to real registers.
+            let mut hinted = SmallVec::<[(u32, RealReg); 8]>::new();
+            for &m in core::iter::once(&n).chain(members[n as usize].iter()) {
+                for hint in &hints[VirtualRangeIx::new(m)] {
+                    match *hint {
+                        Hint::Exactly(rreg, weight) => hinted.push((weight, rreg)),
+                        Hint::SameAs(other, weight) => {
+                            if let Some(rreg) = colors[self.get_alias(other.get()) as usize] {
+                                hinted.push((weight, rreg));
+                            }
+                        }
+                    }
+                }
+            }
+            hinted.sort_by(|(w1, _), (w2, _)| w2.cmp(w1));
+
+            let is_ok = |rreg: &RealReg| {
+                let index = rreg.get_index();
+                index >= info.first && index <= info.last && ok[index - info.first]
+            };
+            let color = hinted
+                .iter()
+                .map(|(_, rreg)| *rreg)
+                .find(|rreg| is_ok(rreg))
+                .or_else(|| {
+                    (info.first..=info.last)
+                        .map(|index| reg_universe.regs[index].0)
+                        .find(|rreg| is_ok(rreg))
+                });
+            match color {
+                Some(rreg) => colors[n as usize] = Some(rreg),
+                None => {
+                    spilled.push(n);
+                    spilled.extend(members[n as usize].iter().cloned());
+                }
+            }
+        }
+
+        if !spilled.is_empty() {
+            return Err(spilled);
+        }
+        for n in 0..num_nodes as u32 {
+            if self.state[n as usize] == NodeState::Coalesced {
+                colors[n as usize] = colors[self.get_alias(n) as usize];
+            }
+        }
+        Ok(colors)
+    }
+}
+
+//=============================================================================
+// Spilling
+
+// Spill `vlrix` to its spill slot, replacing it by bridges that carry the value from a reload to
+// each use, and from each def to a spill.  This works exactly like spilling in the backtracking
+// allocator, see `alloc_main` for details.
+#[inline(never)]
+pub(crate) fn spill_virtual_range<F: Function>(
+    func: &F,
+    reg_vecs_and_bounds: &RegVecsAndBounds,
+    vlr_env: &mut TypedIxVec<VirtualRangeIx, VirtualRange>,
+    vlr_slot_env: &mut TypedIxVec<VirtualRangeIx, Option<SpillSlot>>,
+    spilled: &mut TypedIxVec<VirtualRangeIx, bool>,
+    hints: &mut TypedIxVec<VirtualRangeIx, SmallVec<[Hint; 8]>>,
+    spill_slot_allocator: &mut SpillSlotAllocator,
+    vlrEquivClasses: &UnionFindEquivClasses<VirtualRangeIx>,
+    is_vv_boundary_move: &TypedIxVec<InstIx, bool>,
+    edit_list_move: &mut Vec<EditListItem>,
+    edit_list_other: &mut Vec<EditListItem>,
+    vlrix: VirtualRangeIx,
+) {
+    debug!("--   spill             {:?}:  {:?}", vlrix, &vlr_env[vlrix]);
+    assert!(vlr_env[vlrix].spill_cost.is_finite());
+    spilled[vlrix] = true;
+
+    if vlr_slot_env[vlrix].is_none() {
+        spill_slot_allocator.alloc_spill_slots(vlr_slot_env, func, vlr_env, vlrEquivClasses, vlrix);
+        assert!(vlr_slot_env[vlrix].is_some());
+    }
+    let slot = vlr_slot_env[vlrix].unwrap();
+
+    let vlr = &vlr_env[vlrix];
+    if vlr.is_ref {
+        spill_slot_allocator.notify_spillage_of_reftyped_vlr(slot, &vlr.sorted_frags);
+    }
+
+    let vreg = vlr.vreg;
+    let is_ref = vlr.is_ref;
+    let mut bridges = SmallVec::<[(InstIx, BridgeKind); 32]>::new();
+    for frag in vlr.sorted_frags.iter() {
+        for iix in frag.first.iix().dotdot(frag.last.iix().plus(1)) {
+            let (uses, defs, mods) =
+                does_inst_use_def_or_mod_reg(reg_vecs_and_bounds, iix, vreg.to_reg());
+            if uses && frag.contains(&InstPoint::new_use(iix)) {
+                bridges.push((iix, BridgeKind::RtoU));
+            }
+            if mods
+                && frag.contains(&InstPoint::new_use(iix))
+                && frag.contains(&InstPoint::new_def(iix))
+            {
+                bridges.push((iix, BridgeKind::RtoS));
+            }
+            if defs && frag.contains(&InstPoint::new_def(iix)) {
+                bridges.push((iix, BridgeKind::DtoS));
+            }
+        }
+    }
+
+    for (iix, kind) in bridges {
+        let (first_pt, last_pt) = match kind {
+            BridgeKind::RtoU => (Point::Reload, Point::Use),
+            BridgeKind::RtoS => (Point::Reload, Point::Spill),
+            BridgeKind::DtoS => (Point::Def, Point::Spill),
+        };
+        let new_vlr = VirtualRange {
+            vreg,
+            rreg: None,
+            sorted_frags: SortedRangeFrags::unit(RangeFrag {
+                first: InstPoint::new(iix, first_pt),
+                last: InstPoint::new(iix, last_pt),
+            }),
+            is_ref,
+            size: 1,
+            total_cost: 0xFFFF_FFFFu32,
+            spill_cost: SpillCost::infinite(),
+        };
+        let new_vlrix = VirtualRangeIx::new(vlr_env.len() as u32);
+        debug!(
+            "--     new VirtRange    {:?}  :=  {:?}",
+            new_vlrix, &new_vlr
+        );
+        vlr_env.push(new_vlr);
+        spilled.push(false);
+        hints.push(SmallVec::new());
+
+        let new_eli = EditListItem {
+            slot,
+            vlrix: new_vlrix,
+            kind,
+            iix,
+        };
+        if is_vv_boundary_move[iix] {
+            edit_list_move.push(new_eli);
+        } else {
+            edit_list_other.push(new_eli);
+        }
+    }
+}
+
+//=============================================================================
+// Allocator top level
+
+#[inline(never)]
+pub fn run<F: Function>(
+    func: &mut F,
+    reg_universe: &RealRegUniverse,
+    stackmap_request: Option<&StackmapRequestInfo>,
+    use_checker: bool,
+    dump_allocation: bool,
+    annotate: bool,
+    deterministic: bool,
+    opts: &GraphColoringOptions,
+) -> Result<RegAllocResult<F>, RegAllocError> {
+    let empty_vec_vregs = vec![];
+    let (client_wants_stackmaps, reftype_class, reftyped_vregs) = match stackmap_request {
+        Some(&StackmapRequestInfo {
+            reftype_class,
+            ref reftyped_vregs,
+            ..
+        }) => (true, reftype_class, reftyped_vregs),
+        None => (false, RegClass::INVALID, &empty_vec_vregs),
+    };
+
+    // -------- Perform initial liveness analysis --------
+    let AnalysisInfo {
+        reg_vecs_and_bounds,
+        real_ranges: rlr_env,
+        virtual_ranges: mut vlr_env,
+        range_frags: frag_env,
+        estimated_frequencies: est_freqs,
+        livein_sets_per_block,
+        liveout_sets_per_block,
+        reg_to_ranges_maps: mb_reg_to_ranges_maps,
+        move_info: mb_move_info,
+        ..
+    } = run_analysis(
+        func,
+        reg_universe,
+        AlgorithmWithDefaults::GraphColoring,
+        client_wants_stackmaps,
+        reftype_class,
+        reftyped_vregs,
+        /*reserve_scratches=*/ true,
+        deterministic,
+    )
+    .map_err(|err| RegAllocError::Analysis(err))?;
+
+    let reg_to_ranges_maps = mb_reg_to_ranges_maps.unwrap(); // ensured by `run_analysis`
+    let move_info = mb_move_info.unwrap(); // ensured by `run_analysis`
+
+    let (mut hints, vlrEquivClasses, is_vv_boundary_move) = do_coalescing_analysis(
+        func,
+        &reg_universe,
+        &rlr_env,
+        &mut vlr_env,
+        &frag_env,
+        &reg_to_ranges_maps,
+        &move_info,
+    );
+
+    info!("graph_coloring: begin");
+    let num_vlrs_initial = vlr_env.len();
+    info!(
+        "graph_coloring:   in: {} VLRs, {} RLRs",
+        num_vlrs_initial,
+        rlr_env.len()
+    );
+
+    let mut vlr_slot_env = TypedIxVec::<VirtualRangeIx, Option<SpillSlot>>::new();
+    vlr_slot_env.resize(num_vlrs_initial, None);
+    let mut spill_slot_allocator = SpillSlotAllocator::new();
+    let mut spilled = TypedIxVec::<VirtualRangeIx, bool>::new();
+    spilled.resize(num_vlrs_initial, false);
+    let mut edit_list_move = Vec::<EditListItem>::new();
+    let mut edit_list_other = Vec::<EditListItem>::new();
+
+    let mut num_rounds = 0; // stats only
+    let mut num_vlrs_spilled = 0; // stats only
+    let colors = loop {
+        num_rounds += 1;
+        debug!("-- ROUND {}", num_rounds);
+
+        let mut graph = Graph::build(
+            reg_universe,
+            &rlr_env,
+            &vlr_env,
+            &frag_env,
+            &spilled,
+            &hints,
+        )?;
+        graph.simplify_coalesce_freeze_spill();
+        let coloring = graph.assign_colors(reg_universe, &vlr_env, &hints);
+        info!(
+            "graph_coloring:   round {}: {} moves coalesced",
+            num_rounds, graph.num_coalesced_moves
+        );
+
+        match coloring {
+            Ok(colors) => break colors,
+            Err(to_spill) => {
+                for n in to_spill {
+                    let vlrix = VirtualRangeIx::new(n);
+                    if vlr_env[vlrix].spill_cost.is_infinite() {
+                        return Err(RegAllocError::OutOfRegisters(
+                            vlr_env[vlrix].vreg.get_class(),
+                        ));
+                    }
+                    spill_virtual_range(
+                        func,
+                        &reg_vecs_and_bounds,
+                        &mut vlr_env,
+                        &mut vlr_slot_env,
+                        &mut spilled,
+                        &mut hints,
+                        &mut spill_slot_allocator,
+                        &vlrEquivClasses,
+                        &is_vv_boundary_move,
+                        &mut edit_list_move,
+                        &mut edit_list_other,
+                        vlrix,
+                    );
+                    num_vlrs_spilled += 1;
+                }
+            }
+        }
+    };
+
+    // Record the assignment, in the form `finish_allocation` expects.
+    let mut per_real_reg = Vec::<PerRealReg>::new();
+    for _ in 0..reg_universe.allocable {
+        per_real_reg.push(PerRealReg::new());
+    }
+    for (rlrix_no, rlr) in rlr_env.iter().enumerate() {
+        let rregIndex = rlr.rreg.get_index();
+        if rregIndex < reg_universe.allocable {
+            per_real_reg[rregIndex].add_RealRange(
+                RealRangeIx::new(rlrix_no as u32),
+                &rlr_env,
+                &frag_env,
+            );
+        }
+    }
+    for (vlrix_no, color) in colors.into_iter().enumerate() {
+        let vlrix = VirtualRangeIx::new(vlrix_no as u32);
+        if let Some(rreg) = color {
+            vlr_env[vlrix].rreg = Some(rreg);
+            per_real_reg[rreg.get_index()].add_VirtualRange(vlrix, &vlr_env);
+        } else {
+            debug_assert!(spilled[vlrix]);
+        }
+    }
+
+    info!(
+        "graph_coloring:   out: VLRs: {} initially, {} spilled, {} rounds",
+        num_vlrs_initial, num_vlrs_spilled, num_rounds
+    );
+
+    let res = finish_allocation(
+        func,
+        reg_universe,
+        stackmap_request,
+        use_checker,
+        dump_allocation,
+        annotate,
+        deterministic,
+        opts.request_block_annotations,
+        &[],
+        &reg_vecs_and_bounds,
+        &rlr_env,
+        &vlr_env,
+        &vlr_slot_env,
+        &per_real_reg,
+        &is_vv_boundary_move,
+        &est_freqs,
+        &livein_sets_per_block,
+        &liveout_sets_per_block,
+        edit_list_move,
+        edit_list_other,
+        vec![],
+        spill_slot_allocator,
+    );
+
+    info!("graph_coloring: end");
+    res
+}
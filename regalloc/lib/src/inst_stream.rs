@@ -35,6 +35,14 @@ pub(crate) enum InstToInsert {
         from_reg: RealReg,
         for_vreg: VirtualReg,
     },
+    /// An exchange of the values of two registers, where `for_vreg1` is the virtual register
+    /// whose value `reg1` holds before the swap, and `for_vreg2` the one whose value `reg2` holds.
+    Swap {
+        reg1: Writable<RealReg>,
+        reg2: Writable<RealReg>,
+        for_vreg1: VirtualReg,
+        for_vreg2: VirtualReg,
+    },
     /// A spillslot reassignment (to another vreg). In the edited instruction
     /// stream, this is a nop, but this is needed for the checker to properly
     /// track the symbolic values in slots. Always originates from a move
@@ -46,6 +54,23 @@ pub(crate) enum InstToInsert {
         from_reg: Reg,
         to_reg: Reg,
     },
+    /// The binding of a block parameter to the argument passed to it on a CFG edge, for functions
+    /// in SSA form.  Like `ChangeSpillSlotOwnership`, this is a nop in the edited instruction
+    /// stream, and only tells the checker that `loc`, which held `arg`, now holds `param`.
+    /// Always follows the moves that copy the arguments into place on that edge.
+    BindBlockParam {
+        inst_ix: InstIx,
+        loc: ValueLoc,
+        arg: Reg,
+        param: Reg,
+    },
+}
+
+/// The location of a value: either a real register or a spill slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum ValueLoc {
+    Reg(RealReg),
+    Slot(SpillSlot),
 }
 
 impl InstToInsert {
@@ -66,7 +91,17 @@ impl InstToInsert {
                 from_reg,
                 for_vreg,
             } => Some(f.gen_move(to_reg, from_reg, for_vreg)),
-            &InstToInsert::ChangeSpillSlotOwnership { .. } => None,
+            &InstToInsert::Swap {
+                reg1,
+                reg2,
+                for_vreg1,
+                for_vreg2,
+            } => Some(
+                f.gen_swap(reg1, reg2, for_vreg1, for_vreg2)
+                    .expect("swap planned without an instruction for it"),
+            ),
+            &InstToInsert::ChangeSpillSlotOwnership { .. }
+            | &InstToInsert::BindBlockParam { .. } => None,
         }
     }
 
@@ -90,6 +125,10 @@ impl InstToInsert {
                 into: to_reg,
                 from: from_reg,
             },
+            &InstToInsert::Swap { reg1, reg2, .. } => CheckerInst::Swap {
+                reg1: reg1.to_reg(),
+                reg2: reg2.to_reg(),
+            },
             &InstToInsert::ChangeSpillSlotOwnership {
                 inst_ix,
                 slot,
@@ -101,6 +140,17 @@ impl InstToInsert {
                 from_reg,
                 to_reg,
             },
+            &InstToInsert::BindBlockParam {
+                inst_ix,
+                loc,
+                arg,
+                param,
+            } => CheckerInst::BindBlockParam {
+                inst_ix,
+                loc,
+                arg,
+                param,
+            },
         }
     }
 }
@@ -229,13 +279,17 @@ fn map_vregs_to_rregs<F: Function>(
     use_checker: bool,
     stackmap_request: Option<&StackmapRequestInfo>,
     stackmaps: &[Vec<SpillSlot>],
+    stackmap_regs: &[Vec<RealReg>],
 ) -> Result<(), CheckerErrors> {
     // Set up checker state, if indicated by our configuration.
     let mut checker: Option<CheckerContext> = None;
     let mut insn_blocks: Vec<BlockIx> = vec![];
     if use_checker {
-        let stackmap_info =
-            stackmap_request.map(|request| CheckerStackmapInfo { request, stackmaps });
+        let stackmap_info = stackmap_request.map(|request| CheckerStackmapInfo {
+            request,
+            stackmaps,
+            stackmap_regs,
+        });
         checker = Some(CheckerContext::new(
             func,
             reg_universe,
@@ -519,6 +573,172 @@ fn map_vregs_to_rregs<F: Function>(
     }
 }
 
+//=============================================================================
+// Remove the redundant instructions among those the core algorithm has asked us to add.
+//
+// The instructions to insert between two consecutive instructions of the same block run one after
+// the other, and they may contain leftovers of the allocation: moves of a register to itself,
+// moves immediately undone by the opposite move, reloads of a value that's already in the
+// register, spills of a value that's already in the slot, and reloads of registers overwritten
+// before being read.  This peephole pass removes them, before the checker sees the inserted
+// instructions, so it verifies the result.
+//
+// The pseudo-instructions `ChangeSpillSlotOwnership` and `BindBlockParam` rename the values the
+// checker tracks, so nothing is removed across them.
+
+#[inline(never)]
+pub(crate) fn remove_redundant_insts<F: Function>(
+    func: &F,
+    insts_to_add: &mut Vec<InstToInsertAndExtPoint>,
+) {
+    if insts_to_add.is_empty() {
+        return;
+    }
+
+    // Same (stable) sort as in `add_spills_reloads_and_moves`.
+    insts_to_add.sort_by_key(|to_add| to_add.iep.clone());
+
+    let mut ends_block = vec![false; func.insns().len()];
+    for bix in func.blocks() {
+        ends_block[func.block_insns(bix).last().get() as usize] = true;
+    }
+
+    // Identifies the gap between two original instructions where an inserted instruction goes.
+    // Inserted instructions at the use or def points, if any, are left alone.
+    let gap = |iep: &InstExtPoint| -> (InstIx, u8) {
+        match iep.extpt {
+            ExtPoint::Reload | ExtPoint::SpillBefore => (iep.iix, 0),
+            ExtPoint::Use => (iep.iix, 1),
+            ExtPoint::Def => (iep.iix, 2),
+            ExtPoint::ReloadAfter | ExtPoint::Spill => {
+                if ends_block[iep.iix.get() as usize] {
+                    (iep.iix, 3)
+                } else {
+                    (iep.iix.plus(1), 0)
+                }
+            }
+        }
+    };
+
+    let mut redundant = vec![false; insts_to_add.len()];
+    let mut start = 0;
+    while start < insts_to_add.len() {
+        let run_gap = gap(&insts_to_add[start].iep);
+        let mut end = start + 1;
+        while end < insts_to_add.len() && gap(&insts_to_add[end].iep) == run_gap {
+            end += 1;
+        }
+        find_redundant_insts(&insts_to_add[start..end], &mut redundant[start..end]);
+        start = end;
+    }
+
+    let num_removed = redundant
+        .iter()
+        .filter(|&&is_redundant| is_redundant)
+        .count();
+    if num_removed > 0 {
+        let mut redundant = redundant.into_iter();
+        insts_to_add.retain(|_| !redundant.next().unwrap());
+        trace!("removed {} redundant inserted instructions", num_removed);
+    }
+}
+
+// Marks the redundant instructions of a run of instructions inserted in the same gap.
+fn find_redundant_insts(run: &[InstToInsertAndExtPoint], redundant: &mut [bool]) {
+    // Forward: the pairs of locations known to hold the same value.  A spill may overwrite other
+    // slots overlapping the one it writes, so it forgets about all the slots.
+    let mut same = Vec::<(ValueLoc, ValueLoc)>::new();
+    let is_same = |same: &Vec<(ValueLoc, ValueLoc)>, a: ValueLoc, b: ValueLoc| {
+        a == b || same.iter().any(|&pair| pair == (a, b) || pair == (b, a))
+    };
+    let is_slot = |loc: &ValueLoc| matches!(loc, ValueLoc::Slot(_));
+    for (i, to_add) in run.iter().enumerate() {
+        match to_add.inst {
+            InstToInsert::Move {
+                to_reg, from_reg, ..
+            } => {
+                let (to, from) = (ValueLoc::Reg(to_reg.to_reg()), ValueLoc::Reg(from_reg));
+                if is_same(&same, to, from) {
+                    redundant[i] = true;
+                } else {
+                    same.retain(|&(a, b)| a != to && b != to);
+                    same.push((to, from));
+                }
+            }
+            InstToInsert::Reload {
+                to_reg, from_slot, ..
+            } => {
+                let (to, from) = (ValueLoc::Reg(to_reg.to_reg()), ValueLoc::Slot(from_slot));
+                if is_same(&same, to, from) {
+                    redundant[i] = true;
+                } else {
+                    same.retain(|&(a, b)| a != to && b != to);
+                    same.push((to, from));
+                }
+            }
+            InstToInsert::Spill {
+                to_slot, from_reg, ..
+            } => {
+                let (to, from) = (ValueLoc::Slot(to_slot), ValueLoc::Reg(from_reg));
+                if is_same(&same, to, from) {
+                    redundant[i] = true;
+                } else {
+                    same.retain(|(a, b)| !is_slot(a) && !is_slot(b));
+                    same.push((to, from));
+                }
+            }
+            InstToInsert::Swap { reg1, reg2, .. } => {
+                let (loc1, loc2) = (ValueLoc::Reg(reg1.to_reg()), ValueLoc::Reg(reg2.to_reg()));
+                if is_same(&same, loc1, loc2) {
+                    redundant[i] = true;
+                } else {
+                    same.retain(|&(a, b)| a != loc1 && b != loc1 && a != loc2 && b != loc2);
+                }
+            }
+            InstToInsert::ChangeSpillSlotOwnership { .. } | InstToInsert::BindBlockParam { .. } => {
+                same.clear()
+            }
+        }
+    }
+
+    // Backward: the registers overwritten before being read, until the end of the run.  Past
+    // it, the instruction following the run may read any register.
+    let mut overwritten = Vec::<RealReg>::new();
+    for (i, to_add) in run.iter().enumerate().rev() {
+        if redundant[i] {
+            continue;
+        }
+        match to_add.inst {
+            InstToInsert::Move {
+                to_reg, from_reg, ..
+            } => {
+                if overwritten.contains(&to_reg.to_reg()) {
+                    redundant[i] = true;
+                } else {
+                    overwritten.push(to_reg.to_reg());
+                    overwritten.retain(|&reg| reg != from_reg);
+                }
+            }
+            InstToInsert::Reload { to_reg, .. } => {
+                if overwritten.contains(&to_reg.to_reg()) {
+                    redundant[i] = true;
+                } else {
+                    overwritten.push(to_reg.to_reg());
+                }
+            }
+            InstToInsert::Spill { from_reg, .. } => {
+                overwritten.retain(|&reg| reg != from_reg);
+            }
+            InstToInsert::Swap { reg1, reg2, .. } => {
+                overwritten.retain(|&reg| reg != reg1.to_reg() && reg != reg2.to_reg());
+            }
+            InstToInsert::ChangeSpillSlotOwnership { .. } | InstToInsert::BindBlockParam { .. } => {
+                overwritten.clear()
+            }
+        }
+    }
+}
+
 //=============================================================================
 // Take the real-register-only code created by `map_vregs_to_rregs` and
 // interleave extra instructions (spills, reloads and moves) that the core
@@ -638,13 +858,14 @@ pub(crate) fn add_spills_reloads_and_moves<F: Function>(
 #[inline(never)]
 pub(crate) fn edit_inst_stream<F: Function>(
     func: &mut F,
-    insts_to_add: Vec<InstToInsertAndExtPoint>,
+    mut insts_to_add: Vec<InstToInsertAndExtPoint>,
     iixs_to_nop_out: &Vec<InstIx>,
     frag_map: Vec<(RangeFrag, VirtualReg, RealReg)>,
     reg_universe: &RealRegUniverse,
     use_checker: bool,
     stackmap_request: Option<&StackmapRequestInfo>,
     stackmaps: &[Vec<SpillSlot>],
+    stackmap_regs: &[Vec<RealReg>],
 ) -> Result<
     (
         Vec<F::Inst>,
@@ -654,6 +875,8 @@ pub(crate) fn edit_inst_stream<F: Function>(
     ),
     RegAllocError,
 > {
+    remove_redundant_insts(func, &mut insts_to_add);
+
     map_vregs_to_rregs(
         func,
         frag_map,
@@ -663,6 +886,7 @@ pub(crate) fn edit_inst_stream<F: Function>(
         use_checker,
         stackmap_request,
         stackmaps,
+        stackmap_regs,
     )
     .map_err(|e| RegAllocError::RegChecker(e))?;
 
@@ -673,3 +897,92 @@ pub(crate) fn edit_inst_stream<F: Function>(
     )
     .map_err(|e| RegAllocError::Other(e))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RegClass;
+
+    fn rreg(index: u8) -> RealReg {
+        Reg::new_real(RegClass::I64, /* enc = */ index, index).to_real_reg()
+    }
+
+    fn vreg(index: u32) -> VirtualReg {
+        Reg::new_virtual(RegClass::I64, index).to_virtual_reg()
+    }
+
+    fn mov(from: u8, to: u8) -> InstToInsert {
+        InstToInsert::Move {
+            to_reg: Writable::from_reg(rreg(to)),
+            from_reg: rreg(from),
+            for_vreg: vreg(0),
+        }
+    }
+
+    fn spill(from: u8, to: u32) -> InstToInsert {
+        InstToInsert::Spill {
+            to_slot: SpillSlot::new(to),
+            from_reg: rreg(from),
+            for_vreg: Some(vreg(0)),
+        }
+    }
+
+    fn reload(from: u32, to: u8) -> InstToInsert {
+        InstToInsert::Reload {
+            to_reg: Writable::from_reg(rreg(to)),
+            from_slot: SpillSlot::new(from),
+            for_vreg: Some(vreg(0)),
+        }
+    }
+
+    fn redundant(insts: Vec<InstToInsert>) -> Vec<bool> {
+        let iep = InstExtPoint::new(InstIx::new(0), ExtPoint::Reload);
+        let run: Vec<_> = insts
+            .into_iter()
+            .map(|inst| InstToInsertAndExtPoint::new(inst, iep.clone()))
+            .collect();
+        let mut redundant = vec![false; run.len()];
+        find_redundant_insts(&run, &mut redundant);
+        redundant
+    }
+
+    #[test]
+    fn test_find_redundant_insts() {
+        // Self-moves, and moves undone by the opposite move.
+        assert_eq!(
+            redundant(vec![mov(1, 1), mov(1, 2), mov(2, 1)]),
+            [true, false, true]
+        );
+        // Reloads of the value just spilled, and spills of the value just reloaded.
+        assert_eq!(
+            redundant(vec![spill(1, 0), reload(0, 1), reload(0, 2), spill(2, 0)]),
+            [false, true, false, true]
+        );
+        // Spilling to any slot forgets about the others, which it may overlap.
+        assert_eq!(
+            redundant(vec![reload(0, 1), spill(2, 1), spill(1, 0)]),
+            [false, false, false]
+        );
+        // Writing a register forgets about its old value: the last move copies the reloaded value.
+        // The first one is dead, though, since the reload overwrites its destination.
+        assert_eq!(
+            redundant(vec![mov(1, 2), reload(0, 2), mov(2, 1)]),
+            [true, false, false]
+        );
+        // Registers overwritten before being read.
+        assert_eq!(
+            redundant(vec![
+                reload(0, 1),
+                mov(2, 3),
+                reload(1, 1),
+                mov(3, 4),
+                mov(4, 3)
+            ]),
+            [true, false, false, false, true]
+        );
+        assert_eq!(
+            redundant(vec![reload(0, 1), spill(1, 2), reload(1, 1)]),
+            [false, false, false]
+        );
+    }
+}
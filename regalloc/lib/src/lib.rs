@@ -11,6 +11,8 @@
 #![allow(dead_code, non_fmt_panic)]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 // Make the analysis module public for fuzzing.
 #[cfg(feature = "fuzzing")]
@@ -18,6 +20,11 @@ pub mod analysis_main;
 #[cfg(not(feature = "fuzzing"))]
 mod analysis_main;
 
+// Random functions to check the allocators with.
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+
+mod alloc_dump;
 mod analysis_control_flow;
 mod analysis_data_flow;
 mod analysis_reftypes;
@@ -28,13 +35,23 @@ mod bt_main;
 mod bt_spillslot_allocator;
 mod bt_vlr_priority_queue;
 mod checker;
+mod chordal;
+mod comparison;
+mod critical_edges;
 mod data_structures;
+mod fixed_regs;
+mod graph_coloring;
 mod inst_stream;
 mod linear_scan;
+mod move_report;
+mod pinned_regs;
 mod pretty_print;
+mod real_reg_set;
 mod reg_maps;
+mod reg_pressure;
 mod snapshot;
 mod sparse_set;
+mod spill_cost;
 mod union_find;
 
 use alloc::string::{String, ToString};
@@ -43,6 +60,8 @@ use alloc::{borrow::Cow, fmt};
 use core::default;
 use log::{info, log_enabled, Level};
 
+use crate::analysis_main::CommonAnalysis;
+
 // Stuff that is defined by the library
 
 // Pretty-printing utilities.
@@ -68,7 +87,7 @@ pub use crate::data_structures::VirtualReg;
 
 pub use crate::data_structures::Writable;
 
-pub use crate::data_structures::NUM_REG_CLASSES;
+pub use crate::data_structures::MAX_NUM_REG_CLASSES;
 
 // Spill slots
 
@@ -101,11 +120,11 @@ pub use crate::data_structures::SpillSlot;
 // the correctness of the final allocation.  However, it will affect the
 // quality of final allocation.  Clients are recommended to list, for each
 // class, the callee-saved registers first, and the caller-saved registers
-// after that.  The currently supported allocation algorithms (Backtracking
-// and LinearScan) will try to use the first available registers in each
-// class, that is to say, callee-saved ones first.  The purpose of this is to
-// try and minimise spilling around calls by avoiding use of caller-saved ones
-// if possible.
+// after that.  The currently supported allocation algorithms (Backtracking,
+// LinearScan, GraphColoring and Chordal) will try to use the first available
+// registers in each class, that is to say, callee-saved ones first.  The
+// purpose of this is to try and minimise spilling around calls by avoiding use
+// of caller-saved ones if possible.
 //
 // There is a twist here, however.  The abovementioned heuristic works well
 // for non-leaf functions (functions that contain at least one call).  But for
@@ -150,9 +169,9 @@ pub use crate::data_structures::SpillSlot;
 //     it wants, change the value in it at any time, without changing the
 //     behaviour of the final generated code.
 //
-// Currently, the LinearScan algorithm may use the reserved registers.  The
-// Backtracking algorithm will ignore the hints and treat them as "normal"
-// allocatable registers.
+// Currently, the LinearScan and Chordal algorithms may use the reserved
+// registers.  The Backtracking and GraphColoring algorithms will ignore the
+// hints and treat them as "normal" allocatable registers.
 
 pub use crate::data_structures::RealRegUniverse;
 pub use crate::data_structures::RegClassInfo;
@@ -181,6 +200,18 @@ pub trait RegUsageMapper: fmt::Debug {
     fn get_mod(&self, vreg: VirtualReg) -> Option<RealReg>;
 }
 
+/// A client's preference for the register that a virtual register should be allocated to; see
+/// `Function::reg_hint`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegHint {
+    /// Prefer this real register, e.g. the one an ABI argument arrives in or a return value
+    /// leaves in.
+    Real(RealReg),
+    /// Prefer the real register allocated to this other virtual register, e.g. because they're
+    /// connected by a move.
+    SameAs(VirtualReg),
+}
+
 // TypedIxVector, so that the interface can speak about vectors of blocks and
 // instructions.
 
@@ -228,6 +259,44 @@ pub trait Function {
     /// Get CFG successors for a given block.
     fn block_succs(&self, block: BlockIx) -> Cow<[BlockIx]>;
 
+    /// Get the parameters of a given block, for functions in SSA form.  Each parameter is
+    /// considered to be defined at the start of the block, by the values that the predecessors
+    /// pass to it; see `block_args`.  The entry block may not have any parameters.
+    ///
+    /// Only the chordal allocator accepts functions with block parameters; the other allocators
+    /// reject them with `AnalysisError::UnsupportedBlockParams`.
+    fn block_params(&self, _block: BlockIx) -> Cow<[VirtualReg]> {
+        // Default impl: no block parameters, the function is not in SSA form.
+        Cow::Borrowed(&[])
+    }
+
+    /// Get the values that the last instruction of `block` passes to the parameters of its
+    /// `succ_ix`th successor, in the order given by `block_succs`.  There must be exactly one
+    /// value per parameter, of the same register class.
+    ///
+    /// These are conceptually copied to the parameters on the CFG edge, in parallel, so they
+    /// must not be reported by `get_regs` for the branch instruction.  The allocator inserts the
+    /// copies itself, at the end of `block` if it has a single successor, and at the start of
+    /// the successor otherwise; hence that branch instruction may not mention any registers if
+    /// the successor has several predecessors.
+    fn block_args(&self, _block: BlockIx, _succ_ix: usize) -> Cow<[VirtualReg]> {
+        // Default impl: no block parameters, hence no arguments either.
+        Cow::Borrowed(&[])
+    }
+
+    /// Get profile-derived execution frequencies for all the blocks, indexed by `BlockIx`, if
+    /// the client has them.  Only their ratios matter: they weigh the spill costs of live ranges
+    /// and the costs of moves, and guide where linear scan splits live ranges.  Frequencies of
+    /// zero are treated as one, and very large ones saturate the spill costs, so it's best to
+    /// scale them so that the hottest block has a frequency of a few thousand at most.
+    ///
+    /// If this returns `None`, frequencies are estimated from the loop depth of each block, as
+    /// 10 to the power of the depth, with the depth capped at 3.
+    fn block_frequencies(&self) -> Option<Cow<[u32]>> {
+        // Default impl: no profile data.
+        None
+    }
+
     /// Determine whether an instruction is a return instruction.
     fn is_ret(&self, insn: InstIx) -> bool;
 
@@ -250,12 +319,22 @@ pub trait Function {
         true
     }
 
+    /// Is the given instruction a call?  The registers the callee clobbers must still be
+    /// reported as defs or mods by `get_regs`; this only tells the linear scan allocator where
+    /// it may split the live ranges crossing calls, see `LinearScanOptions::split_around_calls`.
+    /// The other allocators ignore it.
+    fn is_call(&self, _insn: &Self::Inst) -> bool {
+        false
+    }
+
     // --------------------------
     // Instruction register slots
     // --------------------------
 
     /// Add to `collector` the used, defined, and modified registers for an
-    /// instruction.
+    /// instruction.  Operands that the instruction requires to be in a
+    /// particular real register can be declared with
+    /// `RegUsageCollector::add_fixed_use` and friends.
     fn get_regs(insn: &Self::Inst, collector: &mut RegUsageCollector);
 
     /// Map each register slot through a virtual-to-real mapping indexed
@@ -273,6 +352,37 @@ pub trait Function {
     /// Allow the regalloc to query whether this is a move. Returns (dst, src).
     fn is_move(&self, insn: &Self::Inst) -> Option<(Writable<Reg>, Reg)>;
 
+    /// Get the allocation hint for a virtual register, if the client has one.  The backtracking
+    /// and linear scan allocators honour it when the hinted register is free over the whole live
+    /// range (or split part of it) being allocated, and otherwise allocate as if there were no
+    /// hint; the other allocators ignore hints.  Hints for registers of the wrong class or outside
+    /// the allocable registers are ignored, as are `SameAs` hints whose other virtual register
+    /// hasn't been allocated a register yet at that point.
+    ///
+    /// A hint only ever changes which register gets picked, never the correctness of the
+    /// allocation, so clients can use it freely, e.g. to keep ABI arguments and return values in
+    /// place and save the moves around calls and at function boundaries.
+    fn reg_hint(&self, _vreg: VirtualReg) -> Option<RegHint> {
+        // Default impl: no hints.
+        None
+    }
+
+    /// Get the virtual registers that are pinned to a real register for their whole lifetime,
+    /// e.g. one holding a VM context pointer that runtime helpers expect in a given register.
+    /// Every mention of a pinned virtual register is allocated to its real register, which
+    /// remains available to other virtual registers wherever the pinned one isn't live; this is
+    /// unlike leaving the real register out of the allocable ones in the universe.
+    ///
+    /// The real register must be of the same class, and nothing else may write it while the
+    /// pinned virtual register is live: neither an instruction mentioning the real register,
+    /// such as a call clobbering it, nor another virtual register pinned or fixed to it.  Such
+    /// overlaps are reported as `AnalysisError::PinnedRegConflict`.  Pinned virtual registers
+    /// can't be reftyped, pinned to memory, or passed as block parameters.
+    fn pinned_regs(&self) -> Cow<[(VirtualReg, RealReg)]> {
+        // Default impl: no pinned virtual registers.
+        Cow::Borrowed(&[])
+    }
+
     /// Get the precise number of `VirtualReg` in use in this function, to allow preallocating data
     /// structures. This number *must* be a correct lower-bound, otherwise invalid index failures
     /// may happen; it is of course better if it is exact.
@@ -284,8 +394,8 @@ pub trait Function {
 
     /// How many logical spill slots does the given regclass require?  E.g., on a
     /// 64-bit machine, spill slots may nominally be 64-bit words, but a 128-bit
-    /// vector value will require two slots.  The regalloc will always align on
-    /// this size.
+    /// vector value will require two slots.  The regalloc aligns the slots as
+    /// `get_spillslot_align` says.
     ///
     /// This passes the associated virtual register to the client as well,
     /// because the way in which we spill a real register may depend on the
@@ -295,6 +405,19 @@ pub trait Function {
     /// than for a true V128.
     fn get_spillslot_size(&self, regclass: RegClass, for_vreg: VirtualReg) -> u32;
 
+    /// The alignment of the spill slots for the given regclass and virtual register, in logical
+    /// spill slot units: the first slot allocated to a value is always a multiple of it.  It must
+    /// be a power of two.
+    ///
+    /// By default, this is the size from `get_spillslot_size`, rounded up to a power of two, so
+    /// that e.g. a 128-bit vector taking two 64-bit slots is 128-bit aligned.  A client whose
+    /// 256-bit vectors only need 128-bit alignment can return a smaller value, so as to waste
+    /// less of the frame on padding.
+    fn get_spillslot_align(&self, regclass: RegClass, for_vreg: VirtualReg) -> u32 {
+        self.get_spillslot_size(regclass, for_vreg)
+            .next_power_of_two()
+    }
+
     /// Generate a spill instruction for insertion into the instruction
     /// sequence. The associated virtual register (whose value is being spilled)
     /// is passed, if it exists, so that the client may make decisions about the
@@ -329,9 +452,57 @@ pub trait Function {
         for_vreg: VirtualReg,
     ) -> Self::Inst;
 
+    /// Whether `gen_swap` can exchange the values of two registers of the class `regclass`,
+    /// e.g. with an `xchg` instruction.  When it can, the linear scan allocator resolves the
+    /// cycles of parallel register moves with swaps rather than through a scratch location.
+    fn can_swap(&self, _regclass: RegClass) -> bool {
+        false
+    }
+
+    /// Generate an instruction exchanging the values of `reg1` and `reg2` for insertion into the
+    /// instruction sequence, where `for_vreg1` is the virtual register whose value `reg1` holds
+    /// before the swap, and `for_vreg2` the one whose value `reg2` holds.  The returned
+    /// instruction must not modify the machine's condition codes.
+    ///
+    /// This is only used for register classes for which `can_swap` returns true.  When it
+    /// returns `None` for any of the swaps of a cycle, the cycle goes through a scratch location
+    /// instead.
+    fn gen_swap(
+        &self,
+        _reg1: Writable<RealReg>,
+        _reg2: Writable<RealReg>,
+        _for_vreg1: VirtualReg,
+        _for_vreg2: VirtualReg,
+    ) -> Option<Self::Inst> {
+        None
+    }
+
     /// Generate an instruction which is a no-op and has zero length.
     fn gen_zero_len_nop(&self) -> Self::Inst;
 
+    /// Whether `gen_jump` and `redirect_succ` are implemented, so that the allocator can split
+    /// critical edges.  Setting `Options::split_critical_edges` is an error when this returns
+    /// false.
+    fn can_split_edges(&self) -> bool {
+        false
+    }
+
+    /// Generate an unconditional jump to `target`, which mentions no registers.
+    ///
+    /// This and `redirect_succ` are only used to split critical edges, when
+    /// `Options::split_critical_edges` is set; clients for which `can_split_edges` returns false
+    /// needn't implement them.
+    fn gen_jump(&self, _target: BlockIx) -> Self::Inst {
+        unimplemented!("Function::gen_jump is required to split critical edges")
+    }
+
+    /// Make the control flow instruction `insn` branch to `target` instead of its `succ_ix`th
+    /// successor, in the order given by `block_succs`.  The other successors must not change.
+    /// See `gen_jump`.
+    fn redirect_succ(&self, _insn: &mut Self::Inst, _succ_ix: usize, _target: BlockIx) {
+        unimplemented!("Function::redirect_succ is required to split critical edges")
+    }
+
     /// Try to alter an existing instruction to use a value directly in a
     /// spillslot (accessing memory directly) instead of the given register. May
     /// be useful on ISAs that have mem/reg ops, like x86.
@@ -399,18 +570,48 @@ pub struct RegAllocResult<F: Function> {
     /// How many spill slots were used?
     pub num_spill_slots: u32,
 
-    /// Block annotation strings, for debugging.  Requires requesting in the
-    /// call to `allocate_registers`.  Creating of these annotations is
-    /// potentially expensive, so don't request them if you don't need them.
+    /// Block annotation strings, for debugging.  Requires setting `Options::annotate`, or
+    /// requesting the block frequencies in the options of the backtracking, graph coloring or
+    /// chordal allocators.  Creating of these annotations is potentially expensive, so don't
+    /// request them if you don't need them.
     pub block_annotations: Option<TypedIxVec<BlockIx, Vec<String>>>,
 
     /// If stackmap support was requested: one stackmap for each of the safepoint instructions
     /// declared.  Otherwise empty.
     pub stackmaps: Vec<Vec<SpillSlot>>,
 
+    /// If stackmap support was requested with `StackmapRequestInfo::report_reftyped_regs`: for
+    /// each of the safepoint instructions declared, the real registers holding reftyped values
+    /// live at it, in ascending order.  Otherwise empty.
+    pub stackmap_regs: Vec<Vec<RealReg>>,
+
     /// If stackmap support was requested: one InstIx for each safepoint instruction declared,
     /// indicating the corresponding location in the final instruction stream.  Otherwise empty.
     pub new_safepoint_insns: Vec<InstIx>,
+
+    /// If memory pins were requested (see `BacktrackingOptions::memory_pins`): one entry for each
+    /// pin, listing the spill slot that holds each pinned virtual register live across the
+    /// pinned instruction.  Otherwise empty.
+    pub memory_pin_slots: Vec<Vec<(VirtualReg, SpillSlot)>>,
+
+    /// If a dump was requested (see `Options::dump_allocation`): a textual description of the
+    /// allocation, listing for each block the live ranges with their locations and the inserted
+    /// spills, reloads and moves.  Otherwise `None`.
+    pub allocation_dump: Option<String>,
+
+    /// If a report of the moves was requested (see `Options::report_moves`): which of the moves
+    /// between virtual registers were eliminated, and which remain.  Otherwise `None`.
+    pub move_report: Option<MoveReport>,
+
+    /// If critical edges were split (see `Options::split_critical_edges`): for each new block,
+    /// the source and destination blocks of the edge it was inserted on.  The new blocks are
+    /// numbered after the blocks of the function, in this order; their starts are in
+    /// `target_map`, and their instructions come after all the others.  Otherwise empty.
+    pub split_edges: Vec<(BlockIx, BlockIx)>,
+
+    /// If statistics were requested (see `LinearScanOptions::stats`): the statistics collected
+    /// by the linear scan allocator.  Otherwise `None`.
+    pub stats: Option<LinearScanStatistics>,
 }
 
 /// A choice of register allocation algorithm to run.
@@ -418,15 +619,21 @@ pub struct RegAllocResult<F: Function> {
 pub enum AlgorithmWithDefaults {
     Backtracking,
     LinearScan,
+    GraphColoring,
+    Chordal,
 }
 
 pub use crate::analysis_main::AnalysisError;
-pub use crate::checker::{CheckerError, CheckerErrors};
+pub use crate::checker::{CheckerError, CheckerErrorReport, CheckerErrors, CheckerValue};
+pub use crate::move_report::MoveReport;
+pub use crate::reg_pressure::{analyze_pressure, RegPressure};
 
 /// An error from the register allocator.
 #[derive(Clone, Debug)]
 pub enum RegAllocError {
     OutOfRegisters(RegClass),
+    OutOfFuel,
+    UnsupportedMemoryPins,
     MissingSuggestedScratchReg(RegClass),
     Analysis(AnalysisError),
     RegChecker(CheckerErrors),
@@ -435,17 +642,50 @@ pub enum RegAllocError {
 
 impl fmt::Display for RegAllocError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            RegAllocError::Analysis(err) => write!(f, "analysis error: {}", err),
+            RegAllocError::RegChecker(errors) => write!(f, "{}", errors),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
-pub use crate::bt_main::BacktrackingOptions;
-pub use crate::linear_scan::LinearScanOptions;
+#[cfg(feature = "std")]
+impl std::error::Error for RegAllocError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RegAllocError::Analysis(err) => Some(err),
+            RegAllocError::RegChecker(errors) => Some(errors),
+            _ => None,
+        }
+    }
+}
+
+pub use crate::bt_main::{BacktrackingOptions, MemoryPin};
+pub use crate::chordal::ChordalOptions;
+pub use crate::comparison::{compare_allocators, AllocationSummary, AllocatorComparison};
+pub use crate::graph_coloring::GraphColoringOptions;
+pub use crate::linear_scan::{LinearScanOptions, LinearScanStatistics, OptimalSplitStrategy};
+pub use crate::spill_cost::SpillCostModel;
 
 #[derive(Clone)]
 pub enum Algorithm {
     LinearScan(LinearScanOptions),
     Backtracking(BacktrackingOptions),
+    GraphColoring(GraphColoringOptions),
+    Chordal(ChordalOptions),
+}
+
+impl Algorithm {
+    /// The algorithm `algorithm`, with its default options.
+    pub(crate) fn with_defaults(algorithm: AlgorithmWithDefaults) -> Self {
+        match algorithm {
+            AlgorithmWithDefaults::Backtracking => Algorithm::Backtracking(Default::default()),
+            AlgorithmWithDefaults::LinearScan => Algorithm::LinearScan(Default::default()),
+            AlgorithmWithDefaults::GraphColoring => Algorithm::GraphColoring(Default::default()),
+            AlgorithmWithDefaults::Chordal => Algorithm::Chordal(Default::default()),
+        }
+    }
 }
 
 impl fmt::Debug for Algorithm {
@@ -453,6 +693,8 @@ impl fmt::Debug for Algorithm {
         match self {
             Algorithm::LinearScan(opts) => write!(fmt, "{:?}", opts),
             Algorithm::Backtracking(opts) => write!(fmt, "{:?}", opts),
+            Algorithm::GraphColoring(opts) => write!(fmt, "{:?}", opts),
+            Algorithm::Chordal(opts) => write!(fmt, "{:?}", opts),
         }
     }
 }
@@ -467,6 +709,60 @@ pub struct Options {
     /// Which algorithm should be used for register allocation? By default, selects backtracking,
     /// which is slower to compile but creates code of better quality.
     pub algorithm: Algorithm,
+
+    /// Should the register allocator describe the allocation it made in
+    /// `RegAllocResult::allocation_dump`? Building the dump is potentially expensive, so this is
+    /// disabled by default.
+    pub dump_allocation: bool,
+
+    /// Should the register allocator annotate each block in `RegAllocResult::block_annotations`
+    /// with the registers live into and out of it, the locations assigned to the live ranges
+    /// within it, and the instructions it inserted into it?  The annotations refer to the
+    /// instructions of the incoming function.  Building them is potentially expensive, so this
+    /// is disabled by default.
+    pub annotate: bool,
+
+    /// Should the allocation depend only on the function and the options, so that it is
+    /// identical across runs, hosts and toolchains? By default, some ties are broken by the
+    /// iteration order of hash-based sets, which differs between targets, or by unstable sorts,
+    /// which may differ between versions of the standard library.  When this is set:
+    ///
+    /// - the live range fragments of each block are created in register order, which fixes the
+    ///   numbering of live ranges, and so the tie-breaking between ranges of equal priority;
+    /// - the backtracking and chordal allocators evict ranges in index order, which fixes the
+    ///   order in which they are reconsidered or spilled;
+    /// - the moves between spill slots are coalesced in a stable order;
+    /// - the linear scan allocator inserts the moves at block boundaries in register order and
+    ///   its fixup moves in instruction order, which fixes their order and the spill slots used
+    ///   to break move cycles.
+    ///
+    /// This costs some sorting, so it is disabled by default.
+    pub deterministic: bool,
+
+    /// Should the register allocator report which moves between virtual registers it eliminated,
+    /// in `RegAllocResult::move_report`? This is meant for tuning the code generation of the
+    /// client, so it is disabled by default.
+    pub report_moves: bool,
+
+    /// Should the register allocator split the critical edges of the function itself, instead of
+    /// rejecting them?  Each new block holds a jump generated by `Function::gen_jump`, and the
+    /// branch to it is redirected with `Function::redirect_succ`; the client finds the new
+    /// blocks in `RegAllocResult::split_edges`.  Besides the edges from a block with several
+    /// successors to a block with several predecessors, this splits the edges that linear scan
+    /// and the chordal allocator reject when the branch mentions a register.  Disabled by
+    /// default.
+    ///
+    /// This requires `Function::can_split_edges`; allocation fails otherwise.
+    pub split_critical_edges: bool,
+
+    /// Should the register allocator accept functions with up to 256 million blocks and
+    /// instructions, instead of 1 million blocks and 16 million instructions?  The default limits
+    /// leave a safety margin in the 32-bit indices of the allocator's internal structures; when
+    /// this is set, the allocator checks that the structures it creates fit in them instead, and
+    /// reports `AnalysisError::ImplementationLimitsExceeded` if they don't.  This is meant for
+    /// very large machine-generated functions, which take a lot of memory to allocate.  Disabled
+    /// by default.
+    pub large_functions: bool,
 }
 
 impl default::Default for Options {
@@ -474,6 +770,12 @@ impl default::Default for Options {
         Self {
             run_checker: false,
             algorithm: Algorithm::Backtracking(Default::default()),
+            dump_allocation: false,
+            annotate: false,
+            deterministic: false,
+            report_moves: false,
+            split_critical_edges: false,
+            large_functions: false,
         }
     }
 }
@@ -482,8 +784,16 @@ impl fmt::Debug for Options {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "checker: {:?}, algorithm: {:?}",
-            self.run_checker, self.algorithm
+            "checker: {:?}, algorithm: {:?}, dump: {:?}, annotate: {:?}, deterministic: {:?}, \
+             report moves: {:?}, split critical edges: {:?}, large functions: {:?}",
+            self.run_checker,
+            self.algorithm,
+            self.dump_allocation,
+            self.annotate,
+            self.deterministic,
+            self.report_moves,
+            self.split_critical_edges,
+            self.large_functions
         )
     }
 }
@@ -506,6 +816,13 @@ pub struct StackmapRequestInfo {
     /// the values of all live references, so it would be meaningless for a safepoint
     /// instruction also to attempt to do that -- we'd end up with two competing new values.
     pub safepoint_insns: Vec<InstIx>,
+
+    /// Where reftyped values held in registers across a safepoint are reported.  If false, they
+    /// are spilled before the safepoint instruction and reloaded after it, so that every live
+    /// reference is in one of the spill slots of the stackmap.  If true, they stay in their
+    /// registers, which are listed in `RegAllocResult::stackmap_regs`; the client's runtime must
+    /// then be able to find (and update) the registers at the safepoint.
+    pub report_reftyped_regs: bool,
 }
 
 /// Allocate registers for a function's code, given a universe of real registers that we are
@@ -513,8 +830,8 @@ pub struct StackmapRequestInfo {
 ///
 /// The control flow graph must not contain any critical edges, that is, any edge coming from a
 /// block with multiple successors must not flow into a block with multiple predecessors. The
-/// embedder must have split critical edges before handing over the function to this function.
-/// Otherwise, an error will be returned.
+/// embedder must have split critical edges before handing over the function to this function,
+/// unless `Options::split_critical_edges` is set.  Otherwise, an error will be returned.
 ///
 /// Allocation may succeed, returning a `RegAllocResult` with the new instruction sequence, or
 /// it may fail, returning an error.
@@ -528,6 +845,19 @@ pub fn allocate_registers_with_opts<F: Function>(
     rreg_universe: &RealRegUniverse,
     stackmap_info: Option<&StackmapRequestInfo>,
     opts: Options,
+) -> Result<RegAllocResult<F>, RegAllocError> {
+    allocate_registers_with_common(func, rreg_universe, stackmap_info, opts, None)
+}
+
+// Same as `allocate_registers_with_opts`, where `common` is the allocator-independent part of the
+// analysis of `func`, if it has been done already.  It only applies to functions which don't need
+// any of the wrappers below, and to the backtracking and linear scan allocators.
+pub(crate) fn allocate_registers_with_common<F: Function>(
+    func: &mut F,
+    rreg_universe: &RealRegUniverse,
+    stackmap_info: Option<&StackmapRequestInfo>,
+    opts: Options,
+    common: Option<CommonAnalysis>,
 ) -> Result<RegAllocResult<F>, RegAllocError> {
     info!("");
     info!("================ regalloc.rs: BEGIN function ================");
@@ -541,11 +871,15 @@ pub fn allocate_registers_with_opts<F: Function>(
         }
     }
 
+    analysis_control_flow::check_function_size(func, opts.large_functions)
+        .map_err(RegAllocError::Analysis)?;
+
     // If stackmap support has been requested, perform some initial sanity checks.
     if let Some(&StackmapRequestInfo {
         reftype_class,
         ref reftyped_vregs,
         ref safepoint_insns,
+        ..
     }) = stackmap_info
     {
         if reftype_class != RegClass::I64 && reftype_class != RegClass::I32 {
@@ -596,20 +930,176 @@ pub fn allocate_registers_with_opts<F: Function>(
         // done deep in the stackmap creation logic, for BT in `get_stackmap_artefacts_at`.
     }
 
-    let run_checker = opts.run_checker;
-    let res = match &opts.algorithm {
-        Algorithm::Backtracking(opts) => {
-            bt_main::alloc_main(func, rreg_universe, stackmap_info, run_checker, opts)
-        }
-        Algorithm::LinearScan(opts) => {
-            linear_scan::run(func, rreg_universe, stackmap_info, run_checker, opts)
+    let moves = if opts.report_moves {
+        Some(move_report::collect_vreg_moves(func))
+    } else {
+        None
+    };
+
+    // Critical edges are split by allocating a wrapper around the function, which adds a block
+    // on each of them; see `critical_edges`.  It leaves the indices of the client's instructions
+    // alone, so the stackmap request and the options still apply.
+    let edges = if opts.split_critical_edges {
+        if !func.can_split_edges() {
+            return Err(RegAllocError::Other(
+                "split_critical_edges: the function can't split edges".to_string(),
+            ));
         }
+        critical_edges::collect_critical_edges(func)
+    } else {
+        Vec::new()
+    };
+    let res = if edges.is_empty() {
+        run_with_fixed_regs(func, rreg_universe, stackmap_info, opts, common)
+    } else {
+        let mut wrapper = critical_edges::SplitEdgeFunction::new(func, edges);
+        run_with_fixed_regs(&mut wrapper, rreg_universe, stackmap_info, opts, None)
+            .map(|res| wrapper.map_result(res))
     };
+    let res = res.map(|mut res| {
+        if let Some(moves) = moves {
+            res.move_report = Some(move_report::make_move_report(func, moves, &res));
+        }
+        res
+    });
 
     info!("================ regalloc.rs: END function ================");
     res
 }
 
+// Operands constrained to a real register or tied to another operand are handled by allocating a
+// wrapper around the function, which inserts copies to and from the real registers, and from the
+// tied uses to their defs; see `fixed_regs`.
+fn run_with_fixed_regs<F: Function>(
+    func: &mut F,
+    rreg_universe: &RealRegUniverse,
+    stackmap_info: Option<&StackmapRequestInfo>,
+    opts: Options,
+    common: Option<CommonAnalysis>,
+) -> Result<RegAllocResult<F>, RegAllocError> {
+    match fixed_regs::collect_fixed_regs(func) {
+        None => run_with_pinned_regs(func, rreg_universe, stackmap_info, &opts, common),
+        Some(fixed) => {
+            let mut wrapper = fixed_regs::FixedRegFunction::new(func, fixed)?;
+            let stackmap_info = stackmap_info.map(|info| wrapper.map_stackmap_info(info));
+            let opts = Options {
+                algorithm: wrapper.map_algorithm(&opts.algorithm),
+                ..opts
+            };
+            run_with_pinned_regs(
+                &mut wrapper,
+                rreg_universe,
+                stackmap_info.as_ref(),
+                &opts,
+                None,
+            )
+            .map(|res| wrapper.map_result(res))
+        }
+    }
+}
+
+// Pinned virtual registers are handled by allocating a wrapper around the function, which
+// presents them as their real registers; see `pinned_regs`.
+fn run_with_pinned_regs<F: Function>(
+    func: &mut F,
+    rreg_universe: &RealRegUniverse,
+    stackmap_info: Option<&StackmapRequestInfo>,
+    opts: &Options,
+    common: Option<CommonAnalysis>,
+) -> Result<RegAllocResult<F>, RegAllocError> {
+    let pins = pinned_regs::collect_pinned_regs(func, stackmap_info, &opts.algorithm)?;
+    if pins.is_empty() {
+        return run_algorithm(func, rreg_universe, stackmap_info, opts, common);
+    }
+    let mut wrapper = pinned_regs::PinnedRegFunction::new(func, &pins)?;
+    run_algorithm(&mut wrapper, rreg_universe, stackmap_info, opts, None)
+        .map(|res| wrapper.map_result(res))
+}
+
+fn run_algorithm<F: Function>(
+    func: &mut F,
+    rreg_universe: &RealRegUniverse,
+    stackmap_info: Option<&StackmapRequestInfo>,
+    opts: &Options,
+    common: Option<CommonAnalysis>,
+) -> Result<RegAllocResult<F>, RegAllocError> {
+    let run_checker = opts.run_checker;
+    let dump = opts.dump_allocation;
+    let annotate = opts.annotate;
+    let det = opts.deterministic;
+    match &opts.algorithm {
+        Algorithm::Backtracking(opts) => {
+            match bt_main::alloc_main(
+                func,
+                rreg_universe,
+                stackmap_info,
+                run_checker,
+                dump,
+                annotate,
+                det,
+                opts,
+                common,
+            ) {
+                // The backtracking allocator doesn't touch the function until it is done, so
+                // allocating it from scratch is fine.
+                Err(RegAllocError::OutOfFuel) if opts.memory_pins.is_empty() => {
+                    info!("backtracking allocator out of fuel, falling back to linear scan");
+                    // The incoming code may use the scratch registers if the backtracking
+                    // allocator didn't reserve them, so linear scan mustn't either; it then
+                    // breaks its move cycles through spill slots.
+                    let lsra_opts = LinearScanOptions {
+                        scratch_free: opts.scratch_free,
+                        ..Default::default()
+                    };
+                    linear_scan::run(
+                        func,
+                        rreg_universe,
+                        stackmap_info,
+                        run_checker,
+                        dump,
+                        annotate,
+                        det,
+                        &lsra_opts,
+                        None,
+                    )
+                }
+                res => res,
+            }
+        }
+        Algorithm::LinearScan(opts) => linear_scan::run(
+            func,
+            rreg_universe,
+            stackmap_info,
+            run_checker,
+            dump,
+            annotate,
+            det,
+            opts,
+            common,
+        ),
+        Algorithm::GraphColoring(opts) => graph_coloring::run(
+            func,
+            rreg_universe,
+            stackmap_info,
+            run_checker,
+            dump,
+            annotate,
+            det,
+            opts,
+        ),
+        Algorithm::Chordal(opts) => chordal::run(
+            func,
+            rreg_universe,
+            stackmap_info,
+            run_checker,
+            dump,
+            annotate,
+            det,
+            opts,
+        ),
+    }
+}
+
 /// Allocate registers for a function's code, given a universe of real registers that we are
 /// allowed to use.
 ///
@@ -630,16 +1120,12 @@ pub fn allocate_registers<F: Function>(
     stackmap_info: Option<&StackmapRequestInfo>,
     algorithm: AlgorithmWithDefaults,
 ) -> Result<RegAllocResult<F>, RegAllocError> {
-    let algorithm = match algorithm {
-        AlgorithmWithDefaults::Backtracking => Algorithm::Backtracking(Default::default()),
-        AlgorithmWithDefaults::LinearScan => Algorithm::LinearScan(Default::default()),
-    };
     let opts = Options {
-        algorithm,
+        algorithm: Algorithm::with_defaults(algorithm),
         ..Default::default()
     };
     allocate_registers_with_opts(func, rreg_universe, stackmap_info, opts)
 }
 
 // Facilities to snapshot regalloc inputs and reproduce them in regalloc.rs.
-pub use crate::snapshot::IRSnapshot;
+pub use crate::snapshot::{IRFunction, IRInst, IRSnapshot};
@@ -1,12 +1,9 @@
 use super::{FixedInterval, IntId, Intervals, Mention, MentionMap, Safepoints, VirtualInterval};
 use crate::{
-    analysis_control_flow::{CFGInfo, InstIxToBlockIxMap},
+    analysis_control_flow::{check_num_range_frags, CFGInfo, InstIxToBlockIxMap},
     analysis_data_flow::collect_move_info,
-    analysis_data_flow::{
-        calc_def_and_use, calc_livein_and_liveout, get_sanitized_reg_uses_for_func, reg_ix_to_reg,
-        reg_to_reg_ix,
-    },
-    analysis_main::DepthBasedFrequencies,
+    analysis_data_flow::{reg_ix_to_reg, reg_to_reg_ix},
+    analysis_main::{BlockFrequencies, CommonAnalysis, Liveness},
     analysis_reftypes::{core_reftypes_analysis, ReftypeAnalysis},
     data_structures::*,
     sparse_set::SparseSet,
@@ -133,14 +130,22 @@ pub(crate) struct AnalysisInfo {
     /// Liveouts per block.
     pub(crate) liveouts: TypedIxVec<BlockIx, SparseSet<Reg>>,
     /// Maps InstIxs to BlockIxs.
-    pub(crate) _inst_to_block_map: InstIxToBlockIxMap,
+    pub(crate) inst_to_block_map: InstIxToBlockIxMap,
+    /// Execution frequency per block.
+    pub(crate) frequencies: BlockFrequencies,
 }
 
+/// Analyses `func` for linear scan.  `common` is the allocator-independent part of the
+/// analysis, if it has been done already; it must have been done with `reserve_scratches`, and
+/// not in SSA form.
 #[inline(never)]
 pub(crate) fn run<F: Function>(
     func: &F,
     reg_universe: &RealRegUniverse,
     stackmap_request: Option<&StackmapRequestInfo>,
+    reserve_scratches: bool,
+    deterministic: bool,
+    common: Option<CommonAnalysis>,
 ) -> Result<AnalysisInfo, AnalysisError> {
     info!(
         "run_analysis: begin: {} blocks, {} insns",
@@ -148,72 +153,39 @@ pub(crate) fn run<F: Function>(
         func.insns().len()
     );
 
-    // First do control flow analysis.  This is (relatively) simple.  Note that this can fail, for
-    // various reasons; we propagate the failure if so.  Also create the InstIx-to-BlockIx map;
-    // this isn't really control-flow analysis, but needs to be done at some point.
-
-    info!("  run_analysis: begin control flow analysis");
-    let cfg_info = CFGInfo::create(func)?;
-    let inst_to_block_map = InstIxToBlockIxMap::new(func);
-    info!("  run_analysis: end control flow analysis");
-
-    info!("  run_analysis: begin data flow analysis");
-
-    // See `get_sanitized_reg_uses_for_func` for the meaning of "sanitized".
-    let reg_vecs_and_bounds = get_sanitized_reg_uses_for_func(func, reg_universe)
-        .map_err(|reg| AnalysisError::IllegalRealReg(reg))?;
-    assert!(reg_vecs_and_bounds.is_sanitized());
-
-    // Calculate block-local def/use sets.
-    let (def_sets_per_block, use_sets_per_block) =
-        calc_def_and_use(func, &reg_vecs_and_bounds, &reg_universe);
-    debug_assert!(def_sets_per_block.len() == func.blocks().len() as u32);
-    debug_assert!(use_sets_per_block.len() == func.blocks().len() as u32);
-
-    // Calculate live-in and live-out sets per block, using the traditional
-    // iterate-to-a-fixed-point scheme.
-    // `liveout_sets_per_block` is amended below for return blocks, hence `mut`.
-
-    let (livein_sets_per_block, mut liveout_sets_per_block) = calc_livein_and_liveout(
-        func,
-        &def_sets_per_block,
-        &use_sets_per_block,
-        &cfg_info,
-        &reg_universe,
-    );
-    debug_assert!(livein_sets_per_block.len() == func.blocks().len() as u32);
-    debug_assert!(liveout_sets_per_block.len() == func.blocks().len() as u32);
-
-    // Verify livein set of entry block against liveins specified by function (e.g., ABI params).
-    let func_liveins = SparseSet::from_vec(
-        func.func_liveins()
-            .to_vec()
-            .into_iter()
-            .map(|rreg| rreg.to_reg())
-            .collect(),
-    );
-    if !livein_sets_per_block[func.entry_block()].is_subset_of(&func_liveins) {
-        let mut regs = livein_sets_per_block[func.entry_block()].clone();
-        regs.remove(&func_liveins);
-        return Err(AnalysisError::EntryLiveinValues(regs.to_vec()));
-    }
-
-    // Add function liveouts to every block ending in a return.
-    let func_liveouts = SparseSet::from_vec(
-        func.func_liveouts()
-            .to_vec()
-            .into_iter()
-            .map(|rreg| rreg.to_reg())
-            .collect(),
-    );
+    // Control flow analysis, the InstIx-to-BlockIx map, the block frequencies, and data flow
+    // analysis, which checks that `func` has no block parameters.  Note that these can fail, for
+    // various reasons; we propagate the failure if so.
+    let common = match common {
+        Some(common) => common,
+        None => {
+            info!("  run_analysis: begin control flow analysis");
+            let cfg_info = CFGInfo::create(func)?;
+            CommonAnalysis::new(
+                func,
+                cfg_info,
+                reg_universe,
+                /* ssa_form = */ false,
+                reserve_scratches,
+            )?
+        }
+    };
+    let CommonAnalysis {
+        cfg_info,
+        inst_to_block_map,
+        estimated_frequencies: frequencies,
+        liveness:
+            Liveness {
+                reg_vecs_and_bounds,
+                livein_sets_per_block,
+                liveout_sets_per_block,
+            },
+    } = common;
 
     for block in func.blocks() {
         let last_iix = func.block_insns(block).last();
-        if func.is_ret(last_iix) {
-            liveout_sets_per_block[block].union(&func_liveouts);
-        }
 
-        // While we're here: consider if the (ending) control flow instruction has register mentions (any
+        // Consider if the (ending) control flow instruction has register mentions (any
         // use/def/mod).
         //
         // If that's the case, then the successor blocks must have at most one predecessor,
@@ -232,8 +204,6 @@ pub(crate) fn run<F: Function>(
         }
     }
 
-    info!("  run_analysis: end data flow analysis");
-
     info!("  run_analysis: begin liveness analysis");
     let (frag_ixs_per_reg, mut frag_env, frag_metrics_env, vreg_classes) = get_range_frags(
         func,
@@ -242,7 +212,9 @@ pub(crate) fn run<F: Function>(
         &reg_vecs_and_bounds,
         &livein_sets_per_block,
         &liveout_sets_per_block,
+        deterministic,
     );
+    check_num_range_frags(frag_env.len())?;
 
     let (mut fixed_intervals, mut virtual_intervals, vreg_to_vranges) = merge_range_frags(
         func,
@@ -263,9 +235,7 @@ pub(crate) fn run<F: Function>(
     }
 
     if let Some(stackmap_request) = stackmap_request {
-        // TODO depth-based is fine, using the plain depth would be sufficient too.
-        let estimator = DepthBasedFrequencies::new(func, &cfg_info);
-        let move_info = collect_move_info(func, &reg_vecs_and_bounds, &estimator);
+        let move_info = collect_move_info(func, &reg_vecs_and_bounds, &frequencies);
 
         do_reftype_analysis(
             &move_info,
@@ -290,7 +260,8 @@ pub(crate) fn run<F: Function>(
         intervals,
         liveins: livein_sets_per_block,
         liveouts: liveout_sets_per_block,
-        _inst_to_block_map: inst_to_block_map,
+        inst_to_block_map,
+        frequencies,
     })
 }
 
@@ -407,6 +378,9 @@ fn get_range_frags_for_block<F: Function>(
     bix: BlockIx,
     livein: &SparseSet<Reg>,
     liveout: &SparseSet<Reg>,
+    // Iterate over `livein` and `liveout` in register order, so that the fragments are numbered
+    // independently of the layout of the sets.
+    sorted: bool,
     // Temporary state reusable across function calls.
     visited: &mut Vec<u32>,
     state: &mut Vec</*rreg index, then vreg index, */ Option<RangeFrag>>,
@@ -415,20 +389,17 @@ fn get_range_frags_for_block<F: Function>(
     out_frags: &mut Vec<RangeFrag>,
     out_frag_metrics: &mut Vec<RangeFragMetrics>,
 ) {
-    // Iterate to the next safepoint contained in this block.
-    let first_sp_ix = {
-        let mut first = None;
-        if let Some(safepoints) = safepoints {
-            let first_block_iix = func.block_insns(bix).first();
-            for (i, sp_iix) in safepoints.iter().enumerate() {
-                if *sp_iix >= first_block_iix {
-                    first = Some(i);
-                    break;
-                }
-            }
+    // Find the first safepoint contained in this block, or after it.  Safepoints are sorted, so
+    // there's no need to go through all the ones of the previous blocks.
+    let first_sp_ix = safepoints.and_then(|safepoints| {
+        let first_block_iix = func.block_insns(bix).first();
+        let first = safepoints.partition_point(|sp_iix| *sp_iix < first_block_iix);
+        if first < safepoints.len() {
+            Some(first)
+        } else {
+            None
         }
-        first
-    };
+    });
 
     let mut emit_range_frag =
         |r: Reg, mut frag: RangeFrag, frag_metrics: RangeFragMetrics, num_real_regs: u32| {
@@ -469,8 +440,8 @@ fn get_range_frags_for_block<F: Function>(
     let num_real_regs = reg_universe.regs.len() as u32;
 
     // First, set up `state` as if all of `livein` had been written just prior to the block.
-    for reg in livein.iter() {
-        let reg_state_ix = reg_to_reg_ix(num_real_regs, *reg) as usize;
+    for reg in livein.iter_ordered(sorted) {
+        let reg_state_ix = reg_to_reg_ix(num_real_regs, reg) as usize;
         debug_assert!(state[reg_state_ix].is_none());
         state[reg_state_ix] = Some(RangeFrag {
             mentions: MentionMap::new(),
@@ -606,9 +577,9 @@ fn get_range_frags_for_block<F: Function>(
     // deal with RangeFrag in `state` that are for registers not listed as live-out.
 
     // Deal with live-out Regs.  Treat each one as if it is read just after the block.
-    for reg in liveout.iter() {
+    for reg in liveout.iter_ordered(sorted) {
         // Remove the entry from `state` so that the following loop doesn't process it again.
-        let reg_state_ix = reg_to_reg_ix(num_real_regs, *reg) as usize;
+        let reg_state_ix = reg_to_reg_ix(num_real_regs, reg) as usize;
         let prev_frag = mem::replace(&mut state[reg_state_ix], None)
             .expect("a liveout register must have been defined before");
         let (frag, frag_metrics) = RangeFrag::new(
@@ -620,7 +591,7 @@ fn get_range_frags_for_block<F: Function>(
             prev_frag.ref_typed,
             prev_frag.safepoints,
         );
-        emit_range_frag(*reg, frag, frag_metrics, num_real_regs);
+        emit_range_frag(reg, frag, frag_metrics, num_real_regs);
     }
 
     // Finally, round up any remaining RangeFrag left in `state`.
@@ -650,6 +621,7 @@ fn get_range_frags<F: Function>(
     rvb: &RegVecsAndBounds,
     liveins: &TypedIxVec<BlockIx, SparseSet<Reg>>,
     liveouts: &TypedIxVec<BlockIx, SparseSet<Reg>>,
+    deterministic: bool,
 ) -> (
     Vec</*rreg index, then vreg index, */ SmallVec<[RangeFragIx; 8]>>,
     Vec<RangeFrag>,
@@ -702,6 +674,7 @@ fn get_range_frags<F: Function>(
             bix,
             &liveins[bix],
             &liveouts[bix],
+            deterministic,
             &mut tmp_visited,
             &mut tmp_state,
             &mut result_map,
@@ -803,15 +776,41 @@ fn merge_range_frags<F: Function>(
 
     let mut result_virtual = Vec::new();
 
+    // Reused across registers and equivalence classes, respectively.
     let mut triples = Vec::<(RangeFragIx, RangeFragKind, BlockIx)>::new();
+    let mut frag_ixs = SmallVec::<[RangeFragIx; 4]>::new();
+
+    // The real registers come first in `frag_ix_vec_per_reg`.  All the frags of a real register
+    // end up in its fixed interval, whichever live ranges they'd otherwise merge into, so there's
+    // no merging to do for them.
+    let num_real_regs = reg_universe.regs.len();
+    for (reg_ix, all_frag_ixs_for_reg) in frag_ix_vec_per_reg[..num_real_regs].iter().enumerate() {
+        if all_frag_ixs_for_reg.is_empty() {
+            continue;
+        }
+        let reg = reg_ix_to_reg(reg_universe, vreg_classes, reg_ix as u32);
+        debug_assert!(reg.is_real());
+        flush_interval(
+            &mut result_fixed,
+            &mut result_virtual,
+            &mut vreg_to_vranges,
+            wants_stackmaps,
+            reg,
+            all_frag_ixs_for_reg,
+            &frag_metrics_env,
+            frag_env,
+        )?;
+    }
 
     // BEGIN per_reg_loop
-    for (reg_ix, all_frag_ixs_for_reg) in frag_ix_vec_per_reg.iter().enumerate() {
+    for (reg_ix, all_frag_ixs_for_reg) in frag_ix_vec_per_reg.iter().enumerate().skip(num_real_regs)
+    {
         let reg = reg_ix_to_reg(reg_universe, vreg_classes, reg_ix as u32);
+        debug_assert!(reg.is_virtual());
 
         let num_reg_frags = all_frag_ixs_for_reg.len();
 
-        // The reg might never have been mentioned at all, especially if it's a real reg.
+        // The reg might never have been mentioned at all.
         if num_reg_frags == 0 {
             continue;
         }
@@ -893,108 +892,35 @@ fn merge_range_frags<F: Function>(
         // direction.  Hence checking one of the directions is enough.
         let mut eclasses_uf = UnionFind::<usize>::new(triples_len);
 
-        // We have two schemes for group merging, one of which is N^2 in the
-        // length of triples, the other is N-log-N, but with higher constant
-        // factors.  Some experimentation with the bz2 test on a Cortex A57 puts
-        // the optimal crossover point between 200 and 300; it's not critical.
-        // Having this protects us against bad behaviour for huge inputs whilst
-        // still being fast for small inputs.
-        if triples_len <= 250 {
-            // The simple way, which is N^2 in the length of `triples`.
-            for (ix, (_fix, kind, bix)) in triples.iter().enumerate() {
-                // Deal with liveness flows outbound from `fix`. Meaning, (1) above.
-                if *kind == RangeFragKind::LiveOut || *kind == RangeFragKind::Thru {
-                    for b in cfg_info.succ_map[*bix].iter() {
-                        // Visit all entries in `triples` that are for `b`.
-                        for (ix2, (_fix2, kind2, bix2)) in triples.iter().enumerate() {
-                            if *bix2 != *b || *kind2 == RangeFragKind::LiveOut {
-                                continue;
-                            }
-                            debug_assert!(
-                                *kind2 == RangeFragKind::LiveIn || *kind2 == RangeFragKind::Thru
-                            );
-                            // Now we know that liveness for this reg "flows" from `triples[ix]` to
-                            // `triples[ix2]`.  So those two frags must be part of the same live
-                            // range.  Note this.
-                            if ix != ix2 {
-                                eclasses_uf.union(ix, ix2); // Order of args irrelevant
-                            }
-                        }
-                    }
-                }
-            } // outermost iteration over `triples`
-        } else {
-            // The more complex way, which is N-log-N in the length of `triples`.  This is the same
-            // as the simple way, except that the innermost loop, which is a linear search in
-            // `triples` to find entries for some block `b`, is replaced by a binary search.  This
-            // means that `triples` first needs to be sorted by block index.
-            triples.sort_unstable_by_key(|(_, _, bix)| *bix);
-
-            for (ix, (_fix, kind, bix)) in triples.iter().enumerate() {
-                // Deal with liveness flows outbound from `fix`.  Meaning, (1) above.
-                if *kind == RangeFragKind::LiveOut || *kind == RangeFragKind::Thru {
-                    for b in cfg_info.succ_map[*bix].iter() {
-                        // Visit all entries in `triples` that are for `b`.  Binary search
-                        // `triples` to find the lowest-indexed entry for `b`.
-                        let mut ix_left = 0;
-                        let mut ix_right = triples_len;
-                        while ix_left < ix_right {
-                            let m = (ix_left + ix_right) >> 1;
-                            if triples[m].2 < *b {
-                                ix_left = m + 1;
-                            } else {
-                                ix_right = m;
-                            }
-                        }
-
-                        // It might be that there is no block for `b` in the sequence.  That's
-                        // legit; it just means that block `bix` jumps to a successor where the
-                        // associated register isn't live-in/thru.  A failure to find `b` can be
-                        // indicated one of two ways:
-                        //
-                        // * ix_left == triples_len
-                        // * ix_left < triples_len and b < triples[ix_left].b
-                        //
-                        // In both cases I *think* the 'loop_over_entries_for_b below will not do
-                        // anything.  But this is all a bit hairy, so let's convert the second
-                        // variant into the first, so as to make it obvious that the loop won't do
-                        // anything.
-
-                        // ix_left now holds the lowest index of any `triples` entry for block `b`.
-                        // Assert this.
-                        if ix_left < triples_len && *b < triples[ix_left].2 {
-                            ix_left = triples_len;
+        // The frags of each register are collected block by block, so `triples` is already
+        // sorted by block index, and the entries for a block can be found by binary search.
+        debug_assert!(triples.windows(2).all(|pair| pair[0].2 <= pair[1].2));
+
+        for (ix, (_fix, kind, bix)) in triples.iter().enumerate() {
+            // Deal with liveness flows outbound from `fix`.  Meaning, (1) above.
+            if *kind == RangeFragKind::LiveOut || *kind == RangeFragKind::Thru {
+                for b in cfg_info.succ_map[*bix].iter() {
+                    // Visit all entries in `triples` that are for `b`.  There might be none; that
+                    // just means that block `bix` jumps to a successor where the register isn't
+                    // live-in/thru.
+                    let first_for_b = triples.partition_point(|(_, _, bix2)| *bix2 < *b);
+                    for (ix2, (_fix2, kind2, bix2)) in triples.iter().enumerate().skip(first_for_b)
+                    {
+                        if *bix2 != *b {
+                            // We've come to the end of the sequence of `b`-blocks.
+                            break;
                         }
-                        if ix_left < triples_len {
-                            assert!(ix_left == 0 || triples[ix_left - 1].2 < *b);
+                        if *kind2 == RangeFragKind::LiveOut {
+                            continue;
                         }
-
-                        // ix2 plays the same role as in the quadratic version.  ix_left and
-                        // ix_right are not used after this point.
-                        let mut ix2 = ix_left;
-                        loop {
-                            let (_fix2, kind2, bix2) = match triples.get(ix2) {
-                                None => break,
-                                Some(triple) => *triple,
-                            };
-                            if *b < bix2 {
-                                // We've come to the end of the sequence of `b`-blocks.
-                                break;
-                            }
-                            debug_assert!(*b == bix2);
-                            if kind2 == RangeFragKind::LiveOut {
-                                ix2 += 1;
-                                continue;
-                            }
-                            // Now we know that liveness for this reg "flows" from `triples[ix]` to
-                            // `triples[ix2]`.  So those two frags must be part of the same live
-                            // range.  Note this.
-                            eclasses_uf.union(ix, ix2);
-                            ix2 += 1;
-                        }
-
-                        if ix2 + 1 < triples_len {
-                            debug_assert!(*b < triples[ix2 + 1].2);
+                        debug_assert!(
+                            *kind2 == RangeFragKind::LiveIn || *kind2 == RangeFragKind::Thru
+                        );
+                        // Now we know that liveness for this reg "flows" from `triples[ix]` to
+                        // `triples[ix2]`.  So those two frags must be part of the same live
+                        // range.  Note this.
+                        if ix != ix2 {
+                            eclasses_uf.union(ix, ix2); // Order of args irrelevant
                         }
                     }
                 }
@@ -1007,7 +933,7 @@ fn merge_range_frags<F: Function>(
         let eclasses = eclasses_uf.get_equiv_classes();
         for leader_triple_ix in eclasses.equiv_class_leaders_iter() {
             // `leader_triple_ix` is an eclass leader.  Enumerate the whole eclass.
-            let mut frag_ixs = SmallVec::<[RangeFragIx; 4]>::new();
+            frag_ixs.clear();
             for triple_ix in eclasses.equiv_class_elems_iter(leader_triple_ix) {
                 frag_ixs.push(triples[triple_ix].0 /*first field is frag ix*/);
             }
@@ -1,11 +1,14 @@
 use super::{
-    analysis::BlockPos, last_use, next_use, IntId, Intervals, Mention, MentionMap,
-    OptimalSplitStrategy, RegUses, Statistics, VirtualInterval,
+    analysis::BlockPos, last_use, next_use, IntId, Intervals, LinearScanStatistics, Mention,
+    MentionMap, OptimalSplitStrategy, RegUses, VirtualInterval,
 };
 use crate::{
+    analysis_control_flow::InstIxToBlockIxMap,
+    analysis_main::BlockFrequencies,
     data_structures::{InstPoint, Point, RegVecsAndBounds},
-    Function, InstIx, LinearScanOptions, RealReg, RealRegUniverse, Reg, RegAllocError, SpillSlot,
-    VirtualReg, NUM_REG_CLASSES,
+    real_reg_set::RealRegBitSet,
+    BlockIx, Function, InstIx, LinearScanOptions, RealReg, RealRegUniverse, Reg, RegAllocError,
+    RegHint, SpillSlot, VirtualReg,
 };
 
 use crate::data_structures::FxHashMap as HashMap;
@@ -61,7 +64,8 @@ impl ActivityTracker {
         let mut inactive = Vec::with_capacity(intervals.fixeds.len());
         for fixed in &intervals.fixeds {
             let rreg = fixed.reg;
-            if !fixed.frags.is_empty() && scratches_by_rc[rreg.get_class() as usize] != Some(rreg) {
+            let scratch = scratches_by_rc.get(rreg.get_class() as usize).copied();
+            if !fixed.frags.is_empty() && scratch != Some(Some(rreg)) {
                 inactive.push((rreg, 0))
             }
         }
@@ -76,7 +80,12 @@ impl ActivityTracker {
         self.active.push(ActiveInt::Virtual(id));
     }
 
-    fn update(&mut self, start: InstPoint, stats: &mut Option<Statistics>, intervals: &Intervals) {
+    fn update(
+        &mut self,
+        start: InstPoint,
+        stats: &mut Option<LinearScanStatistics>,
+        intervals: &Intervals,
+    ) {
         // From active, only possible transitions are to active or expired.
         // From inactive, only possible transitions are to inactive, active or expired.
         // => active has an upper bound.
@@ -195,12 +204,24 @@ pub(crate) fn run<F: Function>(
     opts: &LinearScanOptions,
     func: &F,
     reg_uses: &RegVecsAndBounds,
+    inst_to_block_map: &InstIxToBlockIxMap,
+    frequencies: &BlockFrequencies,
     reg_universe: &RealRegUniverse,
     scratches_by_rc: &[Option<RealReg>],
     intervals: Intervals,
-    stats: Option<Statistics>,
-) -> Result<(Intervals, u32), RegAllocError> {
-    let mut state = State::new(opts, func, &reg_uses, scratches_by_rc, intervals, stats);
+    stats: Option<LinearScanStatistics>,
+    spill_refs_at_safepoints: bool,
+) -> Result<(Intervals, u32, Option<LinearScanStatistics>), RegAllocError> {
+    let mut state = State::new(
+        opts,
+        func,
+        &reg_uses,
+        inst_to_block_map,
+        frequencies,
+        scratches_by_rc,
+        intervals,
+        stats,
+    );
     let mut reusable = ReusableState::new(reg_universe, scratches_by_rc);
 
     #[cfg(debug_assertions)]
@@ -229,7 +250,7 @@ pub(crate) fn run<F: Function>(
             }
 
             if state.intervals.get(id).location.reg().is_some() {
-                if maybe_handle_safepoints(&mut state, id) {
+                if !spill_refs_at_safepoints || maybe_handle_safepoints(&mut state, id) {
                     // Even it if may have been spilled (because of a safepoint), the parent
                     // interval may still be active.
                     state.activity.set_active(id);
@@ -251,7 +272,7 @@ pub(crate) fn run<F: Function>(
         debug!("");
     }
 
-    Ok((state.intervals, state.next_spill_slot.get()))
+    Ok((state.intervals, state.next_spill_slot.get(), state.stats))
 }
 
 /// A mapping from real reg to some T.
@@ -297,6 +318,17 @@ impl<T: Copy> RegisterMapping<T> {
         }
     }
 
+    /// The value for `rreg`, if it's a register of this class other than the scratch.
+    fn get(&self, rreg: RealReg) -> Option<T> {
+        if rreg.get_class() as usize != self.reg_class_index || Some(rreg) == self.scratch {
+            return None;
+        }
+        rreg.get_index()
+            .checked_sub(self.offset)
+            .and_then(|index| self.regs.get(index))
+            .map(|pair| pair.1)
+    }
+
     fn iter<'a>(&'a self) -> RegisterMappingIter<T> {
         RegisterMappingIter {
             iter: self.regs.iter(),
@@ -364,13 +396,14 @@ struct ReusableState {
     computed_inactive: bool,
     reg_to_instpoint_1: Vec<RegisterMapping<InstPoint>>,
     reg_to_instpoint_2: Vec<RegisterMapping<InstPoint>>,
+    blocked_regs: RealRegBitSet,
 }
 
 impl ReusableState {
     fn new(reg_universe: &RealRegUniverse, scratches: &[Option<RealReg>]) -> Self {
-        let mut reg_to_instpoint_1 = Vec::with_capacity(NUM_REG_CLASSES);
+        let mut reg_to_instpoint_1 = Vec::with_capacity(reg_universe.num_reg_classes());
 
-        for i in 0..NUM_REG_CLASSES {
+        for i in 0..reg_universe.num_reg_classes() {
             let scratch = scratches[i];
             reg_to_instpoint_1.push(RegisterMapping::with_default(
                 i,
@@ -387,6 +420,7 @@ impl ReusableState {
             computed_inactive: false,
             reg_to_instpoint_1,
             reg_to_instpoint_2,
+            blocked_regs: RealRegBitSet::for_universe(reg_universe),
         }
     }
 }
@@ -453,9 +487,15 @@ struct State<'a, F: Function> {
     func: &'a F,
     reg_uses: &'a RegUses,
     opts: &'a LinearScanOptions,
+    inst_to_block_map: &'a InstIxToBlockIxMap,
+    frequencies: &'a BlockFrequencies,
 
     intervals: Intervals,
 
+    /// Maps virtual registers to their initial interval, the root of their split tree, for
+    /// resolving `RegHint::SameAs` hints.
+    root_intervals: HashMap<VirtualReg, IntId>,
+
     /// Intervals that are starting after the current interval's start position.
     unhandled: UnhandledIntervals,
 
@@ -467,7 +507,7 @@ struct State<'a, F: Function> {
     spill_map: HashMap<VirtualReg, SpillSlot>,
 
     activity: ActivityTracker,
-    stats: Option<Statistics>,
+    stats: Option<LinearScanStatistics>,
 }
 
 impl<'a, F: Function> State<'a, F> {
@@ -475,13 +515,17 @@ impl<'a, F: Function> State<'a, F> {
         opts: &'a LinearScanOptions,
         func: &'a F,
         reg_uses: &'a RegUses,
+        inst_to_block_map: &'a InstIxToBlockIxMap,
+        frequencies: &'a BlockFrequencies,
         scratches_by_rc: &[Option<RealReg>],
         intervals: Intervals,
-        stats: Option<Statistics>,
+        stats: Option<LinearScanStatistics>,
     ) -> Self {
         let mut unhandled = UnhandledIntervals::new();
+        let mut root_intervals = HashMap::default();
         for int in intervals.virtuals.iter() {
             unhandled.insert(int.id, &intervals);
+            root_intervals.insert(int.vreg, int.id);
         }
 
         let activity = ActivityTracker::new(&intervals, scratches_by_rc);
@@ -490,7 +534,10 @@ impl<'a, F: Function> State<'a, F> {
             func,
             reg_uses,
             opts,
+            inst_to_block_map,
+            frequencies,
             intervals,
+            root_intervals,
             unhandled,
             next_spill_slot: SpillSlot::new(0),
             spill_map: HashMap::default(),
@@ -516,8 +563,9 @@ impl<'a, F: Function> State<'a, F> {
             *spill_slot
         } else {
             let size_slot = self.func.get_spillslot_size(vreg.get_class(), vreg);
-            let spill_slot = self.next_spill_slot.round_up(size_slot);
-            self.next_spill_slot = spill_slot.inc(1);
+            let align_slot = self.func.get_spillslot_align(vreg.get_class(), vreg);
+            let spill_slot = self.next_spill_slot.round_up(align_slot);
+            self.next_spill_slot = spill_slot.inc(size_slot);
             self.spill_map.insert(vreg, spill_slot);
             spill_slot
         };
@@ -599,8 +647,39 @@ fn lazy_compute_inactive(
 ///
 /// The escape for inactive intervals make this function overall cheap.
 
+/// Returns the register the client hints for the interval `id`, if any.
+///
+/// For a `RegHint::SameAs` hint, this is the register of the other virtual register's closest
+/// interval before `id`, or failing that, its closest interval after `id`. Overlapping intervals
+/// are not considered, since their register can't be free for `id` anyway.
+fn hinted_reg<F: Function>(state: &State<F>, id: IntId) -> Option<RealReg> {
+    let int = state.intervals.get(id);
+    let other_vreg = match state.func.reg_hint(int.vreg)? {
+        RegHint::Real(rreg) => return Some(rreg),
+        RegHint::SameAs(other_vreg) => other_vreg,
+    };
+
+    let mut before: Option<(InstPoint, RealReg)> = None;
+    let mut after: Option<(InstPoint, RealReg)> = None;
+    let mut next = state.root_intervals.get(&other_vreg).cloned();
+    while let Some(other_id) = next {
+        let other = state.intervals.get(other_id);
+        if let Some(reg) = other.location.reg() {
+            if other.end < int.start && before.map_or(true, |(end, _)| other.end > end) {
+                before = Some((other.end, reg));
+            } else if other.start > int.end && after.map_or(true, |(start, _)| other.start < start)
+            {
+                after = Some((other.start, reg));
+            }
+        }
+        next = other.child;
+    }
+    before.or(after).map(|(_, reg)| reg)
+}
+
 /// Naive heuristic to select a register when we're not aware of any conflict.
-/// Currently, it chooses the register with the furthest next use.
+/// Currently, it chooses the client's hinted register if it's free for the whole interval, and
+/// otherwise the register with the furthest next use.
 #[inline(never)]
 fn select_naive_reg<F: Function>(
     reusable: &mut ReusableState,
@@ -611,9 +690,12 @@ fn select_naive_reg<F: Function>(
     let free_until_pos = &mut reusable.reg_to_instpoint_1[reg_class as usize];
     free_until_pos.clear();
 
-    let mut num_free = usize::max(1, free_until_pos.regs.len()) - 1;
+    let mut num_free = free_until_pos.iter().count();
 
-    // All registers currently in use are blocked.
+    // All registers currently in use are blocked. A register can be in use by several active
+    // intervals, so remember which are blocked already to count each only once.
+    let blocked_regs = &mut reusable.blocked_regs;
+    blocked_regs.clear();
     for &aid in &state.activity.active {
         let reg = match aid {
             ActiveInt::Virtual(int_id) => {
@@ -626,7 +708,7 @@ fn select_naive_reg<F: Function>(
             ActiveInt::Fixed((real_reg, _)) => real_reg,
         };
 
-        if reg.get_class() == reg_class {
+        if reg.get_class() == reg_class && blocked_regs.insert(reg) {
             free_until_pos[reg] = InstPoint::min_value();
             num_free -= 1;
         }
@@ -656,6 +738,15 @@ fn select_naive_reg<F: Function>(
         }
     }
 
+    // Honour the client's hint if the register is free for the whole interval.
+    if let Some(hint) = hinted_reg(state, id) {
+        let int_end = state.intervals.get(id).end;
+        if let Some(pos) = free_until_pos.get(hint).filter(|&pos| pos > int_end) {
+            debug!("select_naive_reg: using hinted register {:?}", hint);
+            return Some((hint, pos));
+        }
+    }
+
     // Find the register with the furthest next use, if there's any.
     let mut best_reg = None;
     let mut best_pos = InstPoint::min_value();
@@ -692,7 +783,9 @@ fn try_allocate_reg<F: Function>(
     );
 
     if best_pos <= state.intervals.get(id).end {
-        if !state.opts.partial_split || !try_split_regs(state, id, best_pos) {
+        let split = (state.opts.split_around_calls && split_around_call(state, id, best_pos))
+            || (state.opts.partial_split && try_split_regs(state, id, best_pos));
+        if !split {
             return false;
         }
     }
@@ -868,8 +961,12 @@ fn allocate_blocked_reg<F: Function>(
                 block_pos[best_reg], int_end
             );
 
-            if !state.opts.partial_split || !try_split_regs(state, cur_id, block_pos[best_reg]) {
-                split_and_spill(state, cur_id, block_pos[best_reg]);
+            let blocked_at = block_pos[best_reg];
+            let split = (state.opts.split_around_calls
+                && split_around_call(state, cur_id, blocked_at))
+                || (state.opts.partial_split && try_split_regs(state, cur_id, blocked_at));
+            if !split {
+                split_and_spill(state, cur_id, blocked_at);
             }
         }
 
@@ -977,6 +1074,14 @@ fn find_optimal_split_pos<F: Function>(
         return from;
     }
 
+    // The split position is where the spill happens, so if the range crosses into a block that
+    // runs less often than the one of `from`, split at its start instead.
+    let from_block = state.inst_to_block_map.map(from.iix());
+    if let Some(pos) = coldest_block_start(state, id, from, to, from_block) {
+        trace!("split moved to the start of a colder block: {:?}", pos);
+        return pos;
+    }
+
     let candidate = match state.opts.split_strategy {
         OptimalSplitStrategy::To => Some(to),
         OptimalSplitStrategy::NextFrom => Some(next_pos(from)),
@@ -1001,6 +1106,41 @@ fn find_optimal_split_pos<F: Function>(
     from
 }
 
+/// Finds the start of the least frequently executed block starting in `(from, to]`, if it's less
+/// frequently executed than the block `than`.  Ties go to the latest block, so as to keep the
+/// interval in a register for as long as possible when spilling, and to reload it as late as
+/// possible.
+fn coldest_block_start<F: Function>(
+    state: &State<F>,
+    id: IntId,
+    from: InstPoint,
+    to: InstPoint,
+    than: BlockIx,
+) -> Option<InstPoint> {
+    let from_block = state.inst_to_block_map.map(from.iix());
+    let to_block = state.inst_to_block_map.map(to.iix());
+    let than_freq = state.frequencies.cost(than);
+    let mut best: Option<(u32, InstPoint)> = None;
+
+    // Blocks are laid out in order, so the ones in the range are the ones in between.
+    let mut bix = from_block;
+    while bix < to_block {
+        bix = bix.plus(1);
+        let freq = state.frequencies.cost(bix);
+        let pos = InstPoint::new_use(state.func.block_insns(bix).first());
+        if freq < than_freq
+            && best.map_or(true, |(best_freq, _)| freq <= best_freq)
+            && pos > from
+            && pos <= to
+            && state.intervals.get(id).covers(pos)
+        {
+            best = Some((freq, pos));
+        }
+    }
+
+    best.map(|(_, pos)| pos)
+}
+
 fn prev_pos(mut pos: InstPoint) -> InstPoint {
     match pos.pt() {
         Point::Def => {
@@ -1066,14 +1206,27 @@ fn split_and_spill<F: Function>(state: &mut State<F>, id: IntId, split_pos: Inst
         }
     };
 
-    // Split until the next register use.
+    // Split until the next register use.  If that use runs more often than some block on the way
+    // to it, like the header of a loop entered from a colder preheader, reload at the start of
+    // the coldest such block instead, out of the loop.
     match next_use(&state.intervals.get(child), split_pos, &state.reg_uses) {
         Some(next_use_pos) => {
+            let use_block = state.inst_to_block_map.map(next_use_pos.iix());
+            // Intervals starting before the split position have been handled already.
+            let earliest = InstPoint::max(state.intervals.get(child).start, split_pos);
+            let reload_pos =
+                match coldest_block_start(state, child, earliest, next_use_pos, use_block) {
+                    Some(pos) => {
+                        trace!("reload moved to the start of a colder block: {:?}", pos);
+                        pos
+                    }
+                    None => next_use_pos,
+                };
             debug!(
-                "split spilled interval before next use @ {:?}",
-                next_use_pos
+                "split spilled interval before next use @ {:?}, reloading @ {:?}",
+                next_use_pos, reload_pos
             );
-            let child = split(state, child, next_use_pos);
+            let child = split(state, child, reload_pos);
             state.insert_unhandled(child);
         }
         None => {
@@ -1138,6 +1291,46 @@ fn try_split_regs<F: Function>(
     true
 }
 
+/// If the register of the interval is blocked from `blocked_at` by a call that the interval
+/// crosses without mentioning it, split the interval right before and right after the call, and
+/// spill the part in between.  The part after the call is put back into the queue of intervals to
+/// allocate, unless it has no uses left.  Returns true if it did split the interval, false
+/// otherwise.
+fn split_around_call<F: Function>(state: &mut State<F>, id: IntId, blocked_at: InstPoint) -> bool {
+    let call = blocked_at.iix();
+    if !state.func.is_call(state.func.get_insn(call)) {
+        return false;
+    }
+
+    let before = InstPoint::new_use(call);
+    let after = InstPoint::new_use(call.plus(1));
+    let int = state.intervals.get(id);
+    if int.start >= before || int.end < after {
+        return false;
+    }
+    // An argument or result of the call has to be in a register at the call.
+    if int
+        .mentions()
+        .binary_search_by_key(&call, |mention| mention.0)
+        .is_ok()
+    {
+        return false;
+    }
+
+    debug!(
+        "split_around_call {:?}: spill across the call at {:?}",
+        id, call
+    );
+    let child = split(state, id, before);
+    state.spill(child);
+    if next_use(&state.intervals.get(child), after, &state.reg_uses).is_some() {
+        let child = split(state, child, after);
+        state.insert_unhandled(child);
+    }
+
+    true
+}
+
 /// Splits the interval at the given position.
 ///
 /// The split position must either be a Def of the current vreg, or it must be
@@ -10,15 +10,25 @@ use alloc::fmt;
 use core::{cmp::Ordering, default};
 
 use crate::{
-    checker::CheckerContext, reg_maps::MentionRegUsageMapper, Function, RealRegUniverse,
-    RegAllocError, RegAllocResult, RegClass, Set, SpillSlot, VirtualReg, NUM_REG_CLASSES,
+    alloc_dump::{self, DumpedRange},
+    analysis_main::CommonAnalysis,
+    checker::CheckerContext,
+    reg_maps::MentionRegUsageMapper,
+    Function, RealRegUniverse, RegAllocError, RegAllocResult, RegClass, Set, SpillSlot, VirtualReg,
 };
 use crate::{
     checker::CheckerStackmapInfo,
-    inst_stream::{add_spills_reloads_and_moves, InstToInsertAndExtPoint},
+    inst_stream::{
+        add_spills_reloads_and_moves, remove_redundant_insts, InstToInsert,
+        InstToInsertAndExtPoint, ValueLoc,
+    },
 };
 use crate::{
-    data_structures::{BlockIx, InstIx, InstPoint, Point, RealReg, RegVecsAndBounds},
+    data_structures::{
+        BlockIx, InstIx, InstPoint, Point, RealReg, Reg, RegVecsAndBounds, TypedIxVec,
+    },
+    real_reg_set::RealRegBitSet,
+    sparse_set::SparseSet,
     CheckerErrors, StackmapRequestInfo,
 };
 
@@ -29,89 +39,132 @@ use self::analysis::{BlockBoundary, BlockPos};
 use alloc::format;
 use alloc::vec;
 use alloc::vec::Vec;
+#[cfg(feature = "enable-serde")]
+use serde::{Deserialize, Serialize};
 
 mod analysis;
 mod assign_registers;
 mod resolve_moves;
-
-#[derive(Default)]
-pub(crate) struct Statistics {
-    only_large: bool,
-
-    num_fixed: usize,
-    num_vregs: usize,
-    num_virtual_ranges: usize,
-
-    peak_active: usize,
-    peak_inactive: usize,
-
-    num_try_allocate_reg: usize,
-    num_try_allocate_reg_success: usize,
-
-    num_reg_splits: usize,
-    num_reg_splits_success: usize,
+mod spill_regs;
+mod spill_slots;
+
+/// Statistics about a run of the linear scan allocator, collected when requested with
+/// `LinearScanOptions::stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct LinearScanStatistics {
+    /// The number of fixed intervals, that is, of real registers mentioned by the function.
+    pub num_fixed: usize,
+    /// The highest index of a virtual register of the function.
+    pub num_vregs: usize,
+    /// The number of virtual intervals, before any splitting.
+    pub num_virtual_ranges: usize,
+
+    /// The largest number of simultaneously active intervals.
+    pub peak_active: usize,
+    /// The largest number of simultaneously inactive intervals.
+    pub peak_inactive: usize,
+
+    /// How many times a register free over the whole lifetime of an interval was looked for.
+    pub num_try_allocate_reg: usize,
+    /// How many of these attempts succeeded.
+    pub num_try_allocate_reg_success: usize,
+
+    /// How many times an interval was tried to be split so that its first part stays in a
+    /// register.
+    pub num_reg_splits: usize,
+    /// How many of these attempts succeeded.
+    pub num_reg_splits_success: usize,
 }
 
-impl Drop for Statistics {
-    fn drop(&mut self) {
-        if self.only_large && self.num_vregs < 1000 {
+impl LinearScanStatistics {
+    fn log(&self, only_large: bool) {
+        if only_large && self.num_vregs < 1000 {
             return;
         }
+        info!(
+            "stats: {} fixed; {} vreg; {} vranges; {} peak-active; {} peak-inactive; {} direct-alloc; {} total-alloc; {} partial-splits; {} partial-splits-attempts",
+            self.num_fixed,
+            self.num_vregs,
+            self.num_virtual_ranges,
+            self.peak_active,
+            self.peak_inactive,
+            self.num_try_allocate_reg_success,
+            self.num_try_allocate_reg,
+            self.num_reg_splits_success,
+            self.num_reg_splits,
+        );
     }
 }
 
 /// Which strategy should we use when trying to find the best split position?
 /// TODO Consider loop depth to avoid splitting in the middle of a loop
 /// whenever possible.
-#[derive(Copy, Clone, Debug)]
-enum OptimalSplitStrategy {
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OptimalSplitStrategy {
+    /// Split at the start of the range where the interval may stay in its register.
     From,
+    /// Split at the end of the range where the interval may stay in its register.
     To,
+    /// Split at the position following `From`.
     NextFrom,
+    /// Split two positions after `From`.
     NextNextFrom,
+    /// Split at the position preceding `To`.
     PrevTo,
+    /// Split two positions before `To`.
     PrevPrevTo,
+    /// Split in the middle of `From` and `To`.
     Mid,
 }
 
+/// Tweakable options for the linear scan allocator.
 #[derive(Clone)]
 pub struct LinearScanOptions {
-    split_strategy: OptimalSplitStrategy,
-    partial_split: bool,
-    partial_split_near_end: bool,
-    stats: bool,
-    large_stats: bool,
+    /// Where should intervals be split when they can only stay in a register for part of their
+    /// lifetime?
+    pub split_strategy: OptimalSplitStrategy,
+
+    /// When no register is free for a whole interval, should the allocator try to keep its first
+    /// part in a register that is free for a while, before falling back to spilling it?
+    pub partial_split: bool,
+
+    /// When splitting partially, should the split be placed just before the register stops
+    /// being free, rather than just after the previous use of the interval?
+    pub partial_split_near_end: bool,
+
+    /// When an interval can't stay in a register across a call, should it be split right
+    /// before and right after the call, so that only the part crossing the call is spilled?
+    /// Calls are the instructions for which `Function::is_call` holds.
+    pub split_around_calls: bool,
+
+    /// Should the allocator do without the `suggested_scratch` registers?  Linear scan only needs a
+    /// scratch register to break the move cycles it can't resolve with swaps (see
+    /// `Function::can_swap`).  When this is set, the scratch registers are handed out like any
+    /// other register, and the incoming code may freely mention them; each cycle then goes
+    /// through a register that is free where it is resolved, or, when there is none, through a
+    /// spill slot, borrowing a register whose value is saved to an emergency spill slot if the
+    /// cycle has to copy between slots.  This spares a register on targets with small register
+    /// files.
+    pub scratch_free: bool,
+
+    /// Should the allocator collect statistics about each function?  They are returned in
+    /// `RegAllocResult::stats`, and logged at the `info` level.
+    pub stats: bool,
+
+    /// When collecting statistics, should they only be logged for functions with at least 1000
+    /// virtual registers?  They are returned in any case.
+    pub large_stats: bool,
 }
 
 impl default::Default for LinearScanOptions {
     fn default() -> Self {
-        // no_std : stubbed
-        /*
-        // Useful for debugging.
-        let optimal_split_strategy = match env::var("LSRA_SPLIT") {
-            Ok(s) => match s.as_str() {
-                "t" | "to" => OptimalSplitStrategy::To,
-                "n" => OptimalSplitStrategy::NextFrom,
-                "nn" => OptimalSplitStrategy::NextNextFrom,
-                "p" => OptimalSplitStrategy::PrevTo,
-                "pp" => OptimalSplitStrategy::PrevPrevTo,
-                "m" | "mid" => OptimalSplitStrategy::Mid,
-                _ => OptimalSplitStrategy::From,
-            },
-            Err(_) => OptimalSplitStrategy::From,
-        };
-
-        let large_stats = env::var("LSRA_LARGE_STATS").is_ok();
-        let stats = env::var("LSRA_STATS").is_ok() || large_stats;
-
-        let partial_split = env::var("LSRA_PARTIAL").is_ok();
-        let partial_split_near_end = env::var("LSRA_PARTIAL_END").is_ok();
-        */
-
         Self {
             split_strategy: OptimalSplitStrategy::From,
             partial_split: false,
             partial_split_near_end: false,
+            split_around_calls: false,
+            scratch_free: false,
             stats: false,
             large_stats: false,
         }
@@ -121,7 +174,28 @@ impl default::Default for LinearScanOptions {
 impl fmt::Debug for LinearScanOptions {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         writeln!(fmt, "linear scan")?;
-        write!(fmt, "  split: {:?}", self.split_strategy)
+        write!(fmt, "  split: {:?}", self.split_strategy)?;
+        if self.partial_split {
+            write!(
+                fmt,
+                ", partial (near {})",
+                if self.partial_split_near_end {
+                    "end"
+                } else {
+                    "start"
+                }
+            )?;
+        }
+        if self.split_around_calls {
+            write!(fmt, ", around calls")?;
+        }
+        if self.scratch_free {
+            write!(fmt, ", scratch free")?;
+        }
+        if self.stats {
+            write!(fmt, ", stats")?;
+        }
+        Ok(())
     }
 }
 
@@ -583,12 +657,17 @@ fn last_use(interval: &VirtualInterval, pos: InstPoint, _reg_uses: &RegUses) ->
 }
 
 /// Checks that each register class has its own scratch register in addition to one available
-/// register, and creates a mapping of register class -> scratch register.
+/// register, and creates a mapping of register class -> scratch register.  Without scratch
+/// registers (see `LinearScanOptions::scratch_free`), the mapping is empty for all classes.
 fn compute_scratches(
     reg_universe: &RealRegUniverse,
+    scratch_free: bool,
 ) -> Result<Vec<Option<RealReg>>, RegAllocError> {
-    let mut scratches_by_rc = vec![None; NUM_REG_CLASSES];
-    for i in 0..NUM_REG_CLASSES {
+    let mut scratches_by_rc = vec![None; reg_universe.num_reg_classes()];
+    if scratch_free {
+        return Ok(scratches_by_rc);
+    }
+    for i in 0..reg_universe.num_reg_classes() {
         if let Some(info) = &reg_universe.allocable_by_class[i] {
             if info.first == info.last {
                 return Err(RegAllocError::Other(
@@ -614,13 +693,20 @@ fn compute_scratches(
 ///
 /// Allocation can fail if there are insufficient registers to even generate spill/reload code, or
 /// if the function appears to have any undefined VirtualReg/RealReg uses.
+///
+/// The analysis starts from `common`, the allocator-independent part of it, if it has been done
+/// already.
 #[inline(never)]
 pub(crate) fn run<F: Function>(
     func: &mut F,
     reg_universe: &RealRegUniverse,
     stackmap_request: Option<&StackmapRequestInfo>,
     use_checker: bool,
+    dump_allocation: bool,
+    annotate: bool,
+    deterministic: bool,
     opts: &LinearScanOptions,
+    common: Option<CommonAnalysis>,
 ) -> Result<RegAllocResult<F>, RegAllocError> {
     let AnalysisInfo {
         reg_vecs_and_bounds: reg_uses,
@@ -628,14 +714,31 @@ pub(crate) fn run<F: Function>(
         liveins,
         liveouts,
         cfg,
-        ..
-    } = analysis::run(func, reg_universe, stackmap_request)
-        .map_err(|err| RegAllocError::Analysis(err))?;
+        inst_to_block_map,
+        frequencies,
+    } = analysis::run(
+        func,
+        reg_universe,
+        stackmap_request,
+        !opts.scratch_free,
+        deterministic,
+        common,
+    )
+    .map_err(|err| RegAllocError::Analysis(err))?;
+
+    let scratches_by_rc = compute_scratches(reg_universe, opts.scratch_free)?;
 
-    let scratches_by_rc = compute_scratches(reg_universe)?;
+    // The per-class bookkeeping only covers the classes of the universe.
+    if let Some(int) = intervals
+        .virtuals
+        .iter()
+        .find(|int| int.vreg.get_class().rc_to_usize() >= scratches_by_rc.len())
+    {
+        return Err(RegAllocError::OutOfRegisters(int.vreg.get_class()));
+    }
 
     let stats = if opts.stats {
-        let mut stats = Statistics::default();
+        let mut stats = LinearScanStatistics::default();
         stats.num_fixed = intervals.fixeds.len();
         stats.num_virtual_ranges = intervals.virtuals.len();
         stats.num_vregs = intervals
@@ -643,7 +746,6 @@ pub(crate) fn run<F: Function>(
             .iter()
             .map(|virt| virt.vreg.get_index())
             .fold(0, |a, b| usize::max(a, b));
-        stats.only_large = opts.large_stats;
         Some(stats)
     } else {
         None
@@ -665,37 +767,59 @@ pub(crate) fn run<F: Function>(
         trace!("");
     }
 
-    let (intervals, mut num_spill_slots) = assign_registers::run(
+    // Unless the client wants to find them in registers, reftyped values are spilled around
+    // safepoints.
+    let spill_refs_at_safepoints =
+        stackmap_request.map_or(false, |request| !request.report_reftyped_regs);
+
+    let (mut intervals, num_spill_slots, stats) = assign_registers::run(
         opts,
         func,
         &reg_uses,
+        &inst_to_block_map,
+        &frequencies,
         reg_universe,
         &scratches_by_rc,
         intervals,
         stats,
+        spill_refs_at_safepoints,
     )?;
 
-    let virtuals = &intervals.virtuals;
+    if let Some(stats) = &stats {
+        stats.log(opts.large_stats);
+    }
+
+    spill_regs::run(reg_universe, &scratches_by_rc, &mut intervals);
+    let mut num_spill_slots = spill_slots::run(func, &mut intervals, num_spill_slots);
 
+    let scratches = resolve_moves::Scratches::new(reg_universe, &scratches_by_rc, &intervals);
     let memory_moves = resolve_moves::run(
         func,
         &cfg,
         &reg_uses,
-        virtuals,
+        &intervals.virtuals,
         &liveins,
         &liveouts,
         &mut num_spill_slots,
-        &scratches_by_rc,
+        &scratches,
+        deterministic,
     );
 
+    let virtuals = &intervals.virtuals;
+
     apply_registers(
         func,
         virtuals,
         memory_moves,
         reg_universe,
         num_spill_slots,
+        &liveins,
+        &liveouts,
         use_checker,
+        dump_allocation,
+        annotate,
         stackmap_request,
+        stats,
     )
 }
 
@@ -708,10 +832,11 @@ fn set_registers<F: Function>(
     memory_moves: &Vec<InstToInsertAndExtPoint>,
     stackmap_request: Option<&StackmapRequestInfo>,
     stackmaps: &[Vec<SpillSlot>],
+    stackmap_regs: &[Vec<RealReg>],
 ) -> Result<Set<RealReg>, CheckerErrors> {
     info!("set_registers");
 
-    let mut clobbered_registers = Set::empty();
+    let mut clobbered_registers = RealRegBitSet::for_universe(reg_universe);
 
     // Collect all the regs per instruction and mention set.
     let capacity = virtual_intervals
@@ -721,7 +846,7 @@ fn set_registers<F: Function>(
 
     if capacity == 0 {
         // No virtual registers have been allocated, exit early.
-        return Ok(clobbered_registers);
+        return Ok(Set::empty());
     }
 
     let mut mention_map = Vec::with_capacity(capacity);
@@ -748,8 +873,11 @@ fn set_registers<F: Function>(
     let mut checker: Option<CheckerContext> = None;
     let mut insn_blocks: Vec<BlockIx> = vec![];
     if use_checker {
-        let stackmap_info =
-            stackmap_request.map(|request| CheckerStackmapInfo { request, stackmaps });
+        let stackmap_info = stackmap_request.map(|request| CheckerStackmapInfo {
+            request,
+            stackmaps,
+            stackmap_regs,
+        });
         checker = Some(CheckerContext::new(
             func,
             reg_universe,
@@ -838,32 +966,65 @@ fn set_registers<F: Function>(
         mapper.clear();
     }
 
+    // Move cycles may go through a scratch register that isn't assigned to any interval.
+    for mov in memory_moves {
+        match mov.inst {
+            InstToInsert::Reload { to_reg, .. } | InstToInsert::Move { to_reg, .. } => {
+                clobbered_registers.insert(to_reg.to_reg());
+            }
+            InstToInsert::Swap { reg1, reg2, .. } => {
+                clobbered_registers.insert(reg1.to_reg());
+                clobbered_registers.insert(reg2.to_reg());
+            }
+            _ => {}
+        }
+    }
+
     if let Some(checker) = checker {
         checker.run()?;
     }
 
-    Ok(clobbered_registers)
+    Ok(clobbered_registers.to_set(reg_universe))
 }
 
+/// Computes the spill slots holding reftyped values at each safepoint, and the real registers
+/// holding them if the client asked for them.
 fn compute_stackmaps(
     intervals: &[VirtualInterval],
     stackmap_request: Option<&StackmapRequestInfo>,
-) -> Vec<Vec<SpillSlot>> {
+) -> (Vec<Vec<SpillSlot>>, Vec<Vec<RealReg>>) {
     if let Some(request) = stackmap_request {
-        let mut stackmaps = vec![Vec::new(); request.safepoint_insns.len()];
+        let num_safepoints = request.safepoint_insns.len();
+        let mut stackmaps = vec![Vec::new(); num_safepoints];
+        let mut stackmap_regs = if request.report_reftyped_regs {
+            vec![Vec::new(); num_safepoints]
+        } else {
+            vec![]
+        };
         for int in intervals {
             if !int.ref_typed {
                 continue;
             }
-            if let Some(slot) = int.location.spill() {
-                for &(_sp_iix, sp_ix) in &int.safepoints {
-                    stackmaps[sp_ix].push(slot);
+            match int.location {
+                Location::Stack(slot) => {
+                    for &(_sp_iix, sp_ix) in &int.safepoints {
+                        stackmaps[sp_ix].push(slot);
+                    }
+                }
+                Location::Reg(rreg) if request.report_reftyped_regs => {
+                    for &(_sp_iix, sp_ix) in &int.safepoints {
+                        stackmap_regs[sp_ix].push(rreg);
+                    }
                 }
+                _ => {}
             }
         }
-        stackmaps
+        for regs in &mut stackmap_regs {
+            regs.sort_unstable();
+        }
+        (stackmaps, stackmap_regs)
     } else {
-        vec![]
+        (vec![], vec![])
     }
 }
 
@@ -872,15 +1033,22 @@ fn compute_stackmaps(
 fn apply_registers<F: Function>(
     func: &mut F,
     virtual_intervals: &Vec<VirtualInterval>,
-    memory_moves: Vec<InstToInsertAndExtPoint>,
+    mut memory_moves: Vec<InstToInsertAndExtPoint>,
     reg_universe: &RealRegUniverse,
     num_spill_slots: u32,
+    liveins: &TypedIxVec<BlockIx, SparseSet<Reg>>,
+    liveouts: &TypedIxVec<BlockIx, SparseSet<Reg>>,
     use_checker: bool,
+    dump_allocation: bool,
+    annotate: bool,
     stackmap_request: Option<&StackmapRequestInfo>,
+    stats: Option<LinearScanStatistics>,
 ) -> Result<RegAllocResult<F>, RegAllocError> {
     info!("apply_registers");
 
-    let stackmaps = compute_stackmaps(virtual_intervals, stackmap_request.clone());
+    remove_redundant_insts(func, &mut memory_moves);
+
+    let (stackmaps, stackmap_regs) = compute_stackmaps(virtual_intervals, stackmap_request);
 
     let clobbered_registers = set_registers(
         func,
@@ -890,9 +1058,53 @@ fn apply_registers<F: Function>(
         &memory_moves,
         stackmap_request,
         &stackmaps,
+        &stackmap_regs,
     )
     .map_err(|err| RegAllocError::RegChecker(err))?;
 
+    let ranges: Vec<DumpedRange> = if dump_allocation || annotate {
+        virtual_intervals
+            .iter()
+            .filter_map(|int| {
+                let loc = match int.location {
+                    Location::None => return None,
+                    Location::Reg(rreg) => ValueLoc::Reg(rreg),
+                    Location::Stack(slot) => ValueLoc::Slot(slot),
+                };
+                Some(DumpedRange {
+                    vreg: int.vreg,
+                    first: int.start,
+                    last: int.end,
+                    loc,
+                })
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    let allocation_dump = if dump_allocation {
+        Some(alloc_dump::dump_allocation(
+            func,
+            reg_universe,
+            &ranges,
+            &memory_moves,
+        ))
+    } else {
+        None
+    };
+    let block_annotations = if annotate {
+        Some(alloc_dump::annotate_blocks(
+            func,
+            reg_universe,
+            &ranges,
+            &memory_moves,
+            liveins,
+            liveouts,
+        ))
+    } else {
+        None
+    };
+
     let (final_insns, target_map, new_to_old_insn_map, new_safepoint_insns) =
         add_spills_reloads_and_moves(
             func,
@@ -917,8 +1129,14 @@ fn apply_registers<F: Function>(
         orig_insn_map: new_to_old_insn_map,
         clobbered_registers,
         num_spill_slots,
-        block_annotations: None,
+        block_annotations,
         stackmaps,
+        stackmap_regs,
         new_safepoint_insns,
+        memory_pin_slots: vec![],
+        allocation_dump,
+        move_report: None,
+        split_edges: vec![],
+        stats,
     })
 }
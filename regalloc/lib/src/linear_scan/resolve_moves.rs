@@ -1,10 +1,10 @@
-use super::{analysis::BlockPos, next_use, IntId, Location, RegUses, VirtualInterval};
+use super::{analysis::BlockPos, next_use, IntId, Intervals, Location, RegUses, VirtualInterval};
 use crate::{
     analysis_control_flow::CFGInfo,
     data_structures::{BlockIx, InstPoint, Point},
     inst_stream::{InstExtPoint, InstToInsert, InstToInsertAndExtPoint},
     sparse_set::SparseSet,
-    Function, RealReg, Reg, SpillSlot, TypedIxVec, VirtualReg, Writable,
+    Function, RealReg, RealRegUniverse, Reg, SpillSlot, TypedIxVec, VirtualReg, Writable,
 };
 
 use crate::data_structures::FxHashMap as HashMap;
@@ -18,11 +18,11 @@ fn resolve_moves_in_block<F: Function>(
     func: &F,
     intervals: &Vec<VirtualInterval>,
     reg_uses: &RegUses,
-    scratches_by_rc: &[Option<RealReg>],
+    scratches: &Scratches,
     spill_slot: &mut u32,
     moves_in_blocks: &mut Vec<InstToInsertAndExtPoint>,
     tmp_ordered_moves: &mut Vec<MoveOp>,
-    tmp_stack: &mut Vec<MoveOp>,
+    deterministic: bool,
 ) {
     let mut block_ends = HashSet::default();
     let mut block_starts = HashSet::default();
@@ -71,6 +71,34 @@ fn resolve_moves_in_block<F: Function>(
         match interval.location {
             Location::None => panic!("interval has no location after regalloc!"),
 
+            Location::Reg(rreg) if rreg.get_class() != vreg.get_class() => {
+                // This interval has been spilled to a register of another class. As for a spill
+                // to the stack, move the value there after the last def or before the last use.
+                let from_rreg = match parent.location {
+                    Location::Reg(from_rreg) if from_rreg != rreg => from_rreg,
+                    _ => continue,
+                };
+                let mut at_inst = parent.end;
+                at_inst.set_pt(if at_inst.pt() == Point::Use {
+                    Point::Reload
+                } else {
+                    debug_assert!(at_inst.pt() == Point::Def);
+                    Point::Spill
+                });
+                debug!(
+                    "inblock fixup: {:?} spill {:?} -> {:?} at {:?}",
+                    interval.id, from_rreg, rreg, at_inst
+                );
+                spills_at_inst.push(InstToInsertAndExtPoint::new(
+                    InstToInsert::Move {
+                        to_reg: Writable::from_reg(rreg),
+                        from_reg: from_rreg,
+                        for_vreg: vreg,
+                    },
+                    InstExtPoint::from_inst_point(at_inst),
+                ));
+            }
+
             Location::Reg(rreg) => {
                 // Reconnect with the parent location, by adding a move if needed.
                 if let Some(next_use) = next_use(interval, child_start, reg_uses) {
@@ -158,13 +186,18 @@ fn resolve_moves_in_block<F: Function>(
     // reloads. Reloads may include several moves that must happen in parallel
     // (e.g. if two real regs must be swapped), so process them first. Once all
     // the parallel assignments have been done, push forward all the spills.
+    let mut reloads_at_inst: Vec<_> = reloads_at_inst.into_iter().collect();
+    if deterministic {
+        reloads_at_inst.sort_unstable_by_key(|&(at_inst, _)| at_inst);
+    }
     for (at_inst, mut pending_moves) in reloads_at_inst {
-        schedule_moves(&mut pending_moves, tmp_ordered_moves, tmp_stack);
+        schedule_moves(&mut pending_moves, tmp_ordered_moves);
         emit_moves(
+            func,
             at_inst,
             &tmp_ordered_moves,
             spill_slot,
-            scratches_by_rc,
+            scratches,
             moves_in_blocks,
         );
     }
@@ -294,12 +327,12 @@ fn resolve_moves_across_blocks<F: Function>(
     liveins: &TypedIxVec<BlockIx, SparseSet<Reg>>,
     liveouts: &TypedIxVec<BlockIx, SparseSet<Reg>>,
     intervals: &Vec<VirtualInterval>,
-    scratches_by_rc: &[Option<RealReg>],
+    scratches: &Scratches,
     spill_slot: &mut u32,
     moves_at_block_starts: &mut Vec<InstToInsertAndExtPoint>,
     moves_at_block_ends: &mut Vec<InstToInsertAndExtPoint>,
     tmp_ordered_moves: &mut Vec<MoveOp>,
-    tmp_stack: &mut Vec<MoveOp>,
+    deterministic: bool,
 ) {
     let mut parallel_move_map = HashMap::default();
 
@@ -322,7 +355,7 @@ fn resolve_moves_across_blocks<F: Function>(
             .all(|succ| cfg.pred_map[*succ].card() == 1);
         assert!(successors.card() == 1 || all_succ_have_one_pred);
 
-        for &reg in liveouts[block].iter() {
+        for reg in liveouts[block].iter_ordered(deterministic) {
             let vreg = if let Some(vreg) = reg.as_virtual_reg() {
                 vreg
             } else {
@@ -437,25 +470,31 @@ fn resolve_moves_across_blocks<F: Function>(
         }
 
         // Flush the memory moves caused by block fixups for this block.
-        for (at_inst, (move_insts, block_pos)) in parallel_move_map.iter_mut() {
-            schedule_moves(move_insts, tmp_ordered_moves, tmp_stack);
+        let mut parallel_moves: Vec<_> = parallel_move_map.iter_mut().collect();
+        if deterministic {
+            parallel_moves.sort_unstable_by_key(|&(at_inst, _)| *at_inst);
+        }
+        for (at_inst, (move_insts, block_pos)) in parallel_moves {
+            schedule_moves(move_insts, tmp_ordered_moves);
 
             match block_pos {
                 BlockPos::Start => {
                     emit_moves(
+                        func,
                         *at_inst,
                         &tmp_ordered_moves,
                         spill_slot,
-                        scratches_by_rc,
+                        scratches,
                         moves_at_block_starts,
                     );
                 }
                 BlockPos::End => {
                     emit_moves(
+                        func,
                         *at_inst,
                         &tmp_ordered_moves,
                         spill_slot,
-                        scratches_by_rc,
+                        scratches,
                         moves_at_block_ends,
                     );
                 }
@@ -477,7 +516,8 @@ pub(crate) fn run<F: Function>(
     liveins: &TypedIxVec<BlockIx, SparseSet<Reg>>,
     liveouts: &TypedIxVec<BlockIx, SparseSet<Reg>>,
     spill_slot: &mut u32,
-    scratches_by_rc: &[Option<RealReg>],
+    scratches: &Scratches,
+    deterministic: bool,
 ) -> Vec<InstToInsertAndExtPoint> {
     info!("resolve_moves");
 
@@ -493,17 +533,16 @@ pub(crate) fn run<F: Function>(
     let mut moves_at_block_ends = Vec::new();
     let mut moves_in_blocks = Vec::new();
 
-    let mut tmp_stack = Vec::new();
     let mut tmp_ordered_moves = Vec::new();
     resolve_moves_in_block(
         func,
         intervals,
         reg_uses,
-        scratches_by_rc,
+        scratches,
         spill_slot,
         &mut moves_in_blocks,
         &mut tmp_ordered_moves,
-        &mut tmp_stack,
+        deterministic,
     );
 
     resolve_moves_across_blocks(
@@ -512,12 +551,12 @@ pub(crate) fn run<F: Function>(
         liveins,
         liveouts,
         intervals,
-        scratches_by_rc,
+        scratches,
         spill_slot,
         &mut moves_at_block_starts,
         &mut moves_at_block_ends,
         &mut tmp_ordered_moves,
-        &mut tmp_stack,
+        deterministic,
     );
 
     let mut insts_and_points = moves_at_block_starts;
@@ -528,7 +567,7 @@ pub(crate) fn run<F: Function>(
     insts_and_points
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 enum MoveOperand {
     Reg(RealReg),
     Stack(SpillSlot),
@@ -538,24 +577,28 @@ impl MoveOperand {
     fn aliases(&self, other: &Self) -> bool {
         self == other
     }
+
+    fn reg(&self) -> Option<RealReg> {
+        match *self {
+            MoveOperand::Reg(rreg) => Some(rreg),
+            MoveOperand::Stack(_) => None,
+        }
+    }
 }
 
 struct MoveOp {
     from: MoveOperand,
     to: MoveOperand,
     vreg: VirtualReg,
-    cycle_begin: Option<usize>,
-    cycle_end: Option<usize>,
+    /// Is this the first move of a cycle?  See `schedule_moves`.
+    cycle_begin: bool,
 }
 
 impl fmt::Debug for MoveOp {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(fmt, "{:?}: {:?} -> {:?}", self.vreg, self.from, self.to)?;
-        if let Some(ref begin) = self.cycle_begin {
-            write!(fmt, ", start of cycle #{}", begin)?;
-        }
-        if let Some(ref end) = self.cycle_end {
-            write!(fmt, ", end of cycle #{}", end)?;
+        if self.cycle_begin {
+            write!(fmt, ", start of cycle")?;
         }
         Ok(())
     }
@@ -567,8 +610,7 @@ impl MoveOp {
             from: MoveOperand::Reg(from),
             to: MoveOperand::Reg(to),
             vreg,
-            cycle_begin: None,
-            cycle_end: None,
+            cycle_begin: false,
         }
     }
 
@@ -577,8 +619,7 @@ impl MoveOp {
             from: MoveOperand::Reg(from),
             to: MoveOperand::Stack(to),
             vreg,
-            cycle_begin: None,
-            cycle_end: None,
+            cycle_begin: false,
         }
     }
 
@@ -587,282 +628,494 @@ impl MoveOp {
             from: MoveOperand::Stack(from),
             to: MoveOperand::Reg(to),
             vreg,
-            cycle_begin: None,
-            cycle_end: None,
+            cycle_begin: false,
         }
     }
 
     fn gen_inst(&self) -> InstToInsert {
-        match self.from {
-            MoveOperand::Reg(from) => match self.to {
-                MoveOperand::Reg(to) => InstToInsert::Move {
-                    to_reg: Writable::from_reg(to),
-                    from_reg: from,
-                    for_vreg: self.vreg,
-                },
-                MoveOperand::Stack(to) => InstToInsert::Spill {
-                    to_slot: to,
-                    from_reg: from,
-                    for_vreg: Some(self.vreg),
-                },
+        gen_copy(self.from, self.to, self.vreg)
+    }
+}
+
+/// The instruction copying the value of `vreg` from `from` to `to`, which can't both be spill
+/// slots.
+fn gen_copy(from: MoveOperand, to: MoveOperand, vreg: VirtualReg) -> InstToInsert {
+    match from {
+        MoveOperand::Reg(from) => match to {
+            MoveOperand::Reg(to) => InstToInsert::Move {
+                to_reg: Writable::from_reg(to),
+                from_reg: from,
+                for_vreg: vreg,
             },
-            MoveOperand::Stack(from) => match self.to {
-                MoveOperand::Reg(to) => InstToInsert::Reload {
-                    to_reg: Writable::from_reg(to),
-                    from_slot: from,
-                    for_vreg: Some(self.vreg),
-                },
-                MoveOperand::Stack(_to) => unreachable!("stack to stack move"),
+            MoveOperand::Stack(to) => InstToInsert::Spill {
+                to_slot: to,
+                from_reg: from,
+                for_vreg: Some(vreg),
             },
-        }
+        },
+        MoveOperand::Stack(from) => match to {
+            MoveOperand::Reg(to) => InstToInsert::Reload {
+                to_reg: Writable::from_reg(to),
+                from_slot: from,
+                for_vreg: Some(vreg),
+            },
+            MoveOperand::Stack(_to) => unreachable!("stack to stack move"),
+        },
     }
 }
 
-fn find_blocking_move<'a>(
-    pending: &'a mut Vec<MoveOp>,
-    last: &MoveOp,
-) -> Option<(usize, &'a mut MoveOp)> {
-    for (i, other) in pending.iter_mut().enumerate() {
-        if other.from.aliases(&last.to) {
-            return Some((i, other));
+/// Given a pending list of parallel moves, returns a list of moves ordered in a correct way,
+/// i.e., no move clobbers the source of a later one, except within cycles.
+///
+/// The moves that aren't part of a cycle come first.  Each move whose destination no other
+/// pending move reads can be done right away, after which the move writing its source may in
+/// turn be done, and so on.  The moves left over form disjoint cycles, since each location is
+/// written by at most one move; each cycle follows, starting with a move flagged `cycle_begin`,
+/// with every move reading the destination of the one before it, and the last one writing the
+/// source of the first.  `emit_moves` breaks them.
+#[inline(never)]
+fn schedule_moves(pending: &mut Vec<MoveOp>, ordered_moves: &mut Vec<MoveOp>) {
+    ordered_moves.clear();
+
+    trace!("pending moves: {:#?}", pending);
+
+    let is_blocked =
+        |pending: &[MoveOp], mov: &MoveOp| pending.iter().any(|other| other.from.aliases(&mov.to));
+
+    let mut i = 0;
+    while i < pending.len() {
+        if is_blocked(pending, &pending[i]) {
+            i += 1;
+            continue;
+        }
+        let mut next = Some(i);
+        while let Some(j) = next {
+            let mov = pending.remove(j);
+            if j < i {
+                i -= 1;
+            }
+            trace!("scheduling move {:?}", mov);
+            debug_assert!(
+                mov.from != mov.to,
+                "spurious moves should not have been inserted"
+            );
+            next = pending
+                .iter()
+                .position(|other| other.to.aliases(&mov.from))
+                .filter(|&k| !is_blocked(pending, &pending[k]));
+            ordered_moves.push(mov);
         }
     }
-    None
-}
 
-fn find_cycled_move<'a>(
-    stack: &'a mut Vec<MoveOp>,
-    from: &mut usize,
-    last: &MoveOp,
-) -> Option<&'a mut MoveOp> {
-    for i in *from..stack.len() {
-        *from += 1;
-        let other = &stack[i];
-        if other.from.aliases(&last.to) {
-            return Some(&mut stack[i]);
+    while let Some(mut mov) = pending.pop() {
+        trace!("found cycle starting with {:?}", mov);
+        mov.cycle_begin = true;
+        let from = mov.from;
+        let mut to = mov.to;
+        ordered_moves.push(mov);
+        while let Some(j) = pending.iter().position(|other| other.from.aliases(&to)) {
+            let mov = pending.remove(j);
+            to = mov.to;
+            ordered_moves.push(mov);
         }
+        debug_assert!(to.aliases(&from), "unclosed move cycle");
     }
-    None
 }
 
-/// Given a pending list of moves, returns a list of moves ordered in a correct
-/// way, i.e., no move clobbers another one.
-#[inline(never)]
-fn schedule_moves(
-    pending: &mut Vec<MoveOp>,
-    ordered_moves: &mut Vec<MoveOp>,
-    stack: &mut Vec<MoveOp>,
-) {
-    ordered_moves.clear();
-
-    let mut num_cycles = 0;
-    let mut cur_cycles = 0;
+/// Provides the registers through which `emit_moves` breaks move cycles, and copies values
+/// between spill slots when a cycle goes through one.
+pub(crate) struct Scratches<'a> {
+    /// The scratch register reserved for each register class, if any.
+    reserved: &'a [Option<RealReg>],
+    /// The allocable registers of each register class, among which a free one is looked for
+    /// when the class has no reserved scratch register.
+    allocable_by_rc: Vec<SmallVec<[RealReg; 16]>>,
+    intervals: &'a Intervals,
+}
 
-    trace!("pending moves: {:#?}", pending);
+impl<'a> Scratches<'a> {
+    pub(crate) fn new(
+        reg_universe: &RealRegUniverse,
+        reserved: &'a [Option<RealReg>],
+        intervals: &'a Intervals,
+    ) -> Self {
+        let allocable_by_rc = reg_universe
+            .allocable_by_class
+            .iter()
+            .map(|info| match info {
+                Some(info) => (info.first..=info.last)
+                    .map(|i| reg_universe.regs[i].0)
+                    .collect(),
+                None => SmallVec::new(),
+            })
+            .collect();
+        Self {
+            reserved,
+            allocable_by_rc,
+            intervals,
+        }
+    }
 
-    while let Some(pm) = pending.pop() {
-        trace!("handling pending move {:?}", pm);
-        debug_assert!(
-            pm.from != pm.to,
-            "spurious moves should not have been inserted"
-        );
+    /// Returns a register for a value of `vreg`'s class that is free in the parallel move
+    /// `ordered_moves` at `at_inst`: the reserved scratch register of the class if there is one,
+    /// or else an allocable register that holds no value there, if any.
+    fn find_free(
+        &self,
+        at_inst: InstPoint,
+        vreg: VirtualReg,
+        ordered_moves: &[MoveOp],
+    ) -> Option<RealReg> {
+        let rc = vreg.get_class();
+        if let Some(scratch) = self.reserved[rc as usize] {
+            return Some(scratch);
+        }
 
-        stack.clear();
-        stack.push(pm);
-
-        while !stack.is_empty() {
-            let blocking_pair = find_blocking_move(pending, stack.last().unwrap());
-
-            if let Some((blocking_idx, blocking)) = blocking_pair {
-                trace!("found blocker: {:?}", blocking);
-                let mut stack_cur = 0;
-
-                let has_cycles =
-                    if let Some(mut cycled) = find_cycled_move(stack, &mut stack_cur, blocking) {
-                        trace!("found cycle: {:?}", cycled);
-                        debug_assert!(cycled.cycle_end.is_none());
-                        cycled.cycle_end = Some(cur_cycles);
-                        true
-                    } else {
-                        false
-                    };
-
-                if has_cycles {
-                    loop {
-                        match find_cycled_move(stack, &mut stack_cur, blocking) {
-                            Some(ref mut cycled) => {
-                                trace!("found more cycles ending on blocker: {:?}", cycled);
-                                debug_assert!(cycled.cycle_end.is_none());
-                                cycled.cycle_end = Some(cur_cycles);
-                            }
-                            None => break,
-                        }
-                    }
+        // Besides the operands of the moves, a register is busy if a value lives in it at the
+        // point of the instruction next to `at_inst`.  This accounts for the other moves and
+        // spills at the same point: their sources that aren't operands of these moves live
+        // until this point, and so do their destinations from it.
+        let busy_at = match at_inst.pt() {
+            Point::Reload => InstPoint::new_use(at_inst.iix()),
+            Point::Spill => InstPoint::new_def(at_inst.iix()),
+            _ => unreachable!("moves are only inserted at reload and spill points"),
+        };
 
-                    debug_assert!(blocking.cycle_begin.is_none());
-                    blocking.cycle_begin = Some(cur_cycles);
-                    cur_cycles += 1;
-                }
+        let free = self.allocable_by_rc[rc as usize]
+            .iter()
+            .copied()
+            .find(|&rreg| {
+                let operand = MoveOperand::Reg(rreg);
+                !ordered_moves
+                    .iter()
+                    .any(|mov| mov.from == operand || mov.to == operand)
+                    && !self.intervals.virtuals.iter().any(|int| {
+                        int.location.reg() == Some(rreg)
+                            && int.start <= busy_at
+                            && int.end >= busy_at
+                    })
+                    && !self.intervals.fixeds.iter().any(|fixed| {
+                        fixed.reg == rreg
+                            && fixed
+                                .frags
+                                .iter()
+                                .any(|frag| frag.first <= busy_at && frag.last >= busy_at)
+                    })
+            });
+        if let Some(rreg) = free {
+            trace!("found free scratch {:?} at {:?}", rreg, at_inst);
+        }
+        free
+    }
 
-                let blocking = pending.remove(blocking_idx);
-                stack.push(blocking);
-            } else {
-                // There's no blocking move! We can push this in the ordered list of
-                // moves.
-                // TODO IonMonkey has more optimizations for this case.
-                let last = stack.pop().unwrap();
-                ordered_moves.push(last);
-            }
+    /// Returns a scratch register for a value of `vreg`'s class, in the parallel move
+    /// `ordered_moves` at `at_inst`, along with the spill slot the value of the register must be
+    /// saved to while it is used, if it isn't free there.
+    fn acquire<F: Function>(
+        &self,
+        func: &F,
+        at_inst: InstPoint,
+        vreg: VirtualReg,
+        ordered_moves: &[MoveOp],
+        emergency_slot: &mut Option<(SpillSlot, u32)>,
+        num_spill_slots: &mut u32,
+    ) -> (RealReg, Option<SpillSlot>) {
+        if let Some(rreg) = self.find_free(at_inst, vreg, ordered_moves) {
+            return (rreg, None);
         }
 
-        if num_cycles < cur_cycles {
-            num_cycles = cur_cycles;
+        let regs = &self.allocable_by_rc[vreg.get_class() as usize];
+        let rreg = *regs
+            .first()
+            .expect("no register in the class of a moved value");
+        let slot = reuse_or_alloc_slot(func, vreg, emergency_slot, num_spill_slots);
+        trace!(
+            "no free scratch at {:?}, saving {:?} to {:?}",
+            at_inst,
+            rreg,
+            slot
+        );
+        (rreg, Some(slot))
+    }
+}
+
+/// Returns `slot` if it is large and aligned enough for a value of `vreg`, or makes it a new spill
+/// slot that is.
+fn reuse_or_alloc_slot<F: Function>(
+    func: &F,
+    vreg: VirtualReg,
+    slot: &mut Option<(SpillSlot, u32)>,
+    num_spill_slots: &mut u32,
+) -> SpillSlot {
+    let size = func.get_spillslot_size(vreg.get_class(), vreg);
+    let align = func.get_spillslot_align(vreg.get_class(), vreg);
+    match *slot {
+        Some((prev, prev_size)) if prev_size >= size && prev.get() % align == 0 => prev,
+        _ => {
+            let new_slot = SpillSlot::new(*num_spill_slots).round_up(align);
+            *num_spill_slots = new_slot.inc(size).get();
+            *slot = Some((new_slot, size));
+            new_slot
         }
-        cur_cycles = 0;
+    }
+}
+
+/// Pushes the moves of a value between two spill slots through the scratch register `scratch`,
+/// saving and restoring its previous value around them if it isn't free.
+fn emit_stack_to_stack(
+    at_inst: InstPoint,
+    (scratch, saved): (RealReg, Option<SpillSlot>),
+    from_slot: SpillSlot,
+    to_slot: SpillSlot,
+    vreg: VirtualReg,
+    moves_in_blocks: &mut Vec<InstToInsertAndExtPoint>,
+) {
+    let mut insts = SmallVec::<[InstToInsert; 4]>::new();
+    if let Some(saved) = saved {
+        insts.push(InstToInsert::Spill {
+            to_slot: saved,
+            from_reg: scratch,
+            for_vreg: None,
+        });
+    }
+    insts.push(InstToInsert::Reload {
+        to_reg: Writable::from_reg(scratch),
+        from_slot,
+        for_vreg: Some(vreg),
+    });
+    insts.push(InstToInsert::Spill {
+        to_slot,
+        from_reg: scratch,
+        for_vreg: Some(vreg),
+    });
+    if let Some(saved) = saved {
+        insts.push(InstToInsert::Reload {
+            to_reg: Writable::from_reg(scratch),
+            from_slot: saved,
+            for_vreg: None,
+        });
+    }
+    for inst in insts {
+        moves_in_blocks.push(InstToInsertAndExtPoint::new(
+            inst,
+            InstExtPoint::from_inst_point(at_inst),
+        ));
     }
 }
 
 #[inline(never)]
-fn emit_moves(
+fn emit_moves<F: Function>(
+    func: &F,
     at_inst: InstPoint,
     ordered_moves: &Vec<MoveOp>,
     num_spill_slots: &mut u32,
-    scratches_by_rc: &[Option<RealReg>],
+    scratches: &Scratches,
     moves_in_blocks: &mut Vec<InstToInsertAndExtPoint>,
 ) {
-    let mut spill_slot = None;
-    let mut in_cycle = false;
+    let mut cycle_slot: Option<(SpillSlot, u32)> = None;
+    let mut emergency_slot = None;
 
     trace!("emit_moves");
 
-    for mov in ordered_moves {
-        if let Some(_) = &mov.cycle_end {
-            debug_assert!(in_cycle);
-
-            // There is some pattern:
-            //   (A -> B)
-            //   (B -> A)
-            // This case handles (B -> A), which we reach last. We emit a move from
-            // the saved value of B, to A.
-            match mov.to {
-                MoveOperand::Reg(dst_reg) => {
-                    let inst = InstToInsert::Reload {
-                        to_reg: Writable::from_reg(dst_reg),
-                        from_slot: spill_slot.expect("should have a cycle spill slot"),
-                        for_vreg: Some(mov.vreg),
-                    };
-                    moves_in_blocks.push(InstToInsertAndExtPoint::new(
-                        inst,
-                        InstExtPoint::from_inst_point(at_inst),
-                    ));
-                    trace!(
-                        "finishing cycle: {:?} -> {:?}",
-                        spill_slot.unwrap(),
-                        dst_reg
-                    );
-                }
-                MoveOperand::Stack(dst_spill) => {
-                    let scratch = scratches_by_rc[mov.vreg.get_class() as usize]
-                        .expect("missing scratch reg");
-                    let inst = InstToInsert::Reload {
-                        to_reg: Writable::from_reg(scratch),
-                        from_slot: spill_slot.expect("should have a cycle spill slot"),
-                        for_vreg: Some(mov.vreg),
-                    };
-                    moves_in_blocks.push(InstToInsertAndExtPoint::new(
-                        inst,
-                        InstExtPoint::from_inst_point(at_inst),
-                    ));
-                    let inst = InstToInsert::Spill {
-                        to_slot: dst_spill,
-                        from_reg: scratch,
-                        for_vreg: Some(mov.vreg),
-                    };
-                    moves_in_blocks.push(InstToInsertAndExtPoint::new(
-                        inst,
-                        InstExtPoint::from_inst_point(at_inst),
-                    ));
-                    trace!(
-                        "finishing cycle: {:?} -> {:?} -> {:?}",
-                        spill_slot.unwrap(),
-                        scratch,
-                        dst_spill
-                    );
-                }
-            };
-
-            in_cycle = false;
+    let mut i = 0;
+    while i < ordered_moves.len() {
+        let mov = &ordered_moves[i];
+        if !mov.cycle_begin {
+            // A normal move which is not part of a cycle.
+            moves_in_blocks.push(InstToInsertAndExtPoint::new(
+                mov.gen_inst(),
+                InstExtPoint::from_inst_point(at_inst),
+            ));
+            trace!("moving {:?} -> {:?}", mov.from, mov.to);
+            i += 1;
             continue;
         }
 
-        if let Some(_) = &mov.cycle_begin {
-            debug_assert!(!in_cycle);
-
-            // There is some pattern:
-            //   (A -> B)
-            //   (B -> A)
-            // This case handles (A -> B), which we reach first. We save B, then allow
-            // the original move to continue.
-            match spill_slot {
-                Some(_) => {}
-                None => {
-                    spill_slot = Some(SpillSlot::new(*num_spill_slots));
-                    *num_spill_slots += 1;
-                }
-            }
-
-            match mov.to {
-                MoveOperand::Reg(src_reg) => {
-                    let inst = InstToInsert::Spill {
-                        to_slot: spill_slot.unwrap(),
-                        from_reg: src_reg,
-                        for_vreg: Some(mov.vreg),
-                    };
-                    moves_in_blocks.push(InstToInsertAndExtPoint::new(
-                        inst,
-                        InstExtPoint::from_inst_point(at_inst),
-                    ));
-                    trace!("starting cycle: {:?} -> {:?}", src_reg, spill_slot.unwrap());
-                }
-                MoveOperand::Stack(src_spill) => {
-                    let scratch = scratches_by_rc[mov.vreg.get_class() as usize]
-                        .expect("missing scratch reg");
-                    let inst = InstToInsert::Reload {
-                        to_reg: Writable::from_reg(scratch),
-                        from_slot: src_spill,
-                        for_vreg: Some(mov.vreg),
-                    };
-                    moves_in_blocks.push(InstToInsertAndExtPoint::new(
-                        inst,
-                        InstExtPoint::from_inst_point(at_inst),
-                    ));
-                    let inst = InstToInsert::Spill {
-                        to_slot: spill_slot.expect("should have a cycle spill slot"),
-                        from_reg: scratch,
-                        for_vreg: Some(mov.vreg),
-                    };
-                    moves_in_blocks.push(InstToInsertAndExtPoint::new(
-                        inst,
-                        InstExtPoint::from_inst_point(at_inst),
-                    ));
-                    trace!(
-                        "starting cycle: {:?} -> {:?} -> {:?}",
-                        src_spill,
-                        scratch,
-                        spill_slot.unwrap()
-                    );
-                }
-            };
-
-            in_cycle = true;
-        }
+        let len = 1 + ordered_moves[i + 1..]
+            .iter()
+            .take_while(|mov| !mov.cycle_begin)
+            .count();
+        emit_cycle(
+            func,
+            at_inst,
+            &ordered_moves[i..i + len],
+            ordered_moves,
+            &mut cycle_slot,
+            &mut emergency_slot,
+            num_spill_slots,
+            scratches,
+            moves_in_blocks,
+        );
+        i += len;
+    }
+}
 
-        // A normal move which is not part of a cycle.
-        let inst = mov.gen_inst();
+/// Emits the moves of `cycle`, a cycle of the parallel move `ordered_moves` as laid out by
+/// `schedule_moves`.
+///
+/// A cycle of registers is resolved with swaps when the client can generate all of them, which
+/// takes one instruction less than a move per value.  Otherwise, the source of one move is saved in a
+/// scratch location, the other moves are done walking the cycle backwards, and the saved value
+/// is finally copied to the destination of the first move.  The scratch location is a free
+/// register if there is one, or else a spill slot.
+fn emit_cycle<F: Function>(
+    func: &F,
+    at_inst: InstPoint,
+    cycle: &[MoveOp],
+    ordered_moves: &[MoveOp],
+    cycle_slot: &mut Option<(SpillSlot, u32)>,
+    emergency_slot: &mut Option<(SpillSlot, u32)>,
+    num_spill_slots: &mut u32,
+    scratches: &Scratches,
+    moves_in_blocks: &mut Vec<InstToInsertAndExtPoint>,
+) {
+    let mut push = |inst: InstToInsert| {
         moves_in_blocks.push(InstToInsertAndExtPoint::new(
             inst,
             InstExtPoint::from_inst_point(at_inst),
         ));
+    };
+
+    let n = cycle.len();
+    // Values spilled to registers of another class (see `RegClassInfo::spill_class`) can't be
+    // swapped with those of their own class.
+    let rc = cycle[0].vreg.get_class();
+    let all_regs_of_class = cycle.iter().all(|mov| {
+        mov.vreg.get_class() == rc && mov.from.reg().map_or(false, |reg| reg.get_class() == rc)
+    });
+    if all_regs_of_class && func.can_swap(rc) {
+        // Swapping the first register with the next one of the cycle leaves the value of the
+        // first move in its destination, and the value of the second one in the first register.
+        // Swapping again with the register after that does the second move, and so on.
+        let first = Writable::from_reg(cycle[0].from.reg().unwrap());
+        let swaps: Vec<_> = (1..n)
+            .map(|k| {
+                let other = Writable::from_reg(cycle[k].from.reg().unwrap());
+                (first, other, cycle[k - 1].vreg, cycle[k].vreg)
+            })
+            .collect();
+        if swaps
+            .iter()
+            .all(|&(reg1, reg2, vreg1, vreg2)| func.gen_swap(reg1, reg2, vreg1, vreg2).is_some())
+        {
+            for (reg1, reg2, for_vreg1, for_vreg2) in swaps {
+                trace!("swapping {:?} <-> {:?}", reg1, reg2);
+                push(InstToInsert::Swap {
+                    reg1,
+                    reg2,
+                    for_vreg1,
+                    for_vreg2,
+                });
+            }
+            return;
+        }
+    }
+
+    // The moves of a cycle never go between spill slots, so any cycle has a register as the
+    // source of one of its moves; through a spill slot, the cycle is best broken at a move
+    // between registers, whose source can be spilled and destination reloaded directly.
+    let last = n - 1;
+    let scratch = scratches.find_free(at_inst, cycle[last].vreg, ordered_moves);
+    let brk = if scratch.is_some() {
+        last
+    } else {
+        cycle
+            .iter()
+            .rposition(|mov| mov.from.reg().is_some() && mov.to.reg().is_some())
+            .or_else(|| cycle.iter().rposition(|mov| mov.from.reg().is_some()))
+            .expect("move cycle without any register")
+    };
+    let saved = &cycle[brk];
+
+    let tmp = match scratch {
+        Some(rreg) => MoveOperand::Reg(rreg),
+        None => MoveOperand::Stack(reuse_or_alloc_slot(
+            func,
+            saved.vreg,
+            cycle_slot,
+            num_spill_slots,
+        )),
+    };
+    trace!("starting cycle: {:?} -> {:?}", saved.from, tmp);
+    push(gen_copy(saved.from, tmp, saved.vreg));
+
+    for k in 1..n {
+        let mov = &cycle[(brk + n - k) % n];
         trace!("moving {:?} -> {:?}", mov.from, mov.to);
+        push(mov.gen_inst());
+    }
+
+    match (tmp, saved.to) {
+        (MoveOperand::Stack(tmp_slot), MoveOperand::Stack(dst_slot)) => {
+            let scratch = scratches.acquire(
+                func,
+                at_inst,
+                saved.vreg,
+                ordered_moves,
+                emergency_slot,
+                num_spill_slots,
+            );
+            trace!(
+                "finishing cycle: {:?} -> {:?} -> {:?}",
+                tmp_slot,
+                scratch.0,
+                dst_slot
+            );
+            emit_stack_to_stack(
+                at_inst,
+                scratch,
+                tmp_slot,
+                dst_slot,
+                saved.vreg,
+                moves_in_blocks,
+            );
+        }
+        (tmp, to) => {
+            trace!("finishing cycle: {:?} -> {:?}", tmp, to);
+            push(gen_copy(tmp, to, saved.vreg));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RegClass, VirtualReg};
+    use alloc::vec;
+
+    fn rreg(index: u8) -> RealReg {
+        Reg::new_real(RegClass::I64, /* enc = */ index, index).to_real_reg()
+    }
+
+    fn vreg(index: u32) -> VirtualReg {
+        Reg::new_virtual(RegClass::I64, index).to_virtual_reg()
+    }
+
+    #[test]
+    fn test_schedule_moves() {
+        // r0 -> r1 -> r2 -> r0 is a cycle, out of which r0 is also copied to r3, whose value
+        // goes to the slot s0, and then to r4.
+        let mut pending = vec![
+            MoveOp::new_move(rreg(0), rreg(1), vreg(0)),
+            MoveOp::new_reload(SpillSlot::new(0), rreg(4), vreg(1)),
+            MoveOp::new_move(rreg(1), rreg(2), vreg(2)),
+            MoveOp::new_spill(rreg(3), SpillSlot::new(0), vreg(3)),
+            MoveOp::new_move(rreg(2), rreg(0), vreg(4)),
+            MoveOp::new_move(rreg(0), rreg(3), vreg(0)),
+        ];
+        let mut ordered_moves = Vec::new();
+        schedule_moves(&mut pending, &mut ordered_moves);
+        assert!(pending.is_empty());
+
+        let vregs: Vec<_> = ordered_moves.iter().map(|mov| mov.vreg).collect();
+        assert_eq!(vregs[..3], [vreg(1), vreg(3), vreg(0)]);
+        assert!(ordered_moves[..3].iter().all(|mov| !mov.cycle_begin));
+
+        // The cycle comes last, with each move reading the destination of the previous one.
+        let cycle = &ordered_moves[3..];
+        assert!(cycle[0].cycle_begin);
+        assert!(cycle[1..].iter().all(|mov| !mov.cycle_begin));
+        for (i, mov) in cycle.iter().enumerate() {
+            assert_eq!(mov.to, cycle[(i + 1) % cycle.len()].from);
+        }
     }
 }
@@ -0,0 +1,142 @@
+//! Spilling to registers of another class.
+//!
+//! A register class of the universe may name a spill class (see `RegClassInfo::spill_class`),
+//! whose registers can hold its values.  On targets with few integer registers, the
+//! floating-point registers are often idle in integer code, and parking a spilled value in one of
+//! them is much cheaper than a round trip through memory.
+//!
+//! This post-pass runs after the main allocation loop, once the intervals of the spill class have
+//! their registers.  Like spill slots, a spill register is chosen per virtual register: all the
+//! spilled intervals of a virtual register move to a register of the spill class that is free
+//! over all of them, if there is one, and otherwise remain on the stack.  So a value only ever
+//! travels between its own class and the spill register, and the move resolution turns the
+//! spills and reloads into moves between the two.
+
+use super::{IntId, Intervals, Location, VirtualInterval};
+use crate::data_structures::{FxHashMap as HashMap, InstPoint};
+use crate::{RealReg, RealRegUniverse, VirtualReg};
+
+use alloc::vec;
+use alloc::vec::Vec;
+use log::{debug, info};
+use smallvec::SmallVec;
+
+/// The span over which an interval occupies its location: from the end of the previous interval
+/// of its virtual register, where a spilled value is moved in, to the start of the next one,
+/// where its value is moved out.
+fn occupancy(intervals: &Intervals, int: &VirtualInterval) -> (InstPoint, InstPoint) {
+    let start = match int.parent {
+        Some(parent) => intervals.get(parent).end.min(int.start),
+        None => int.start,
+    };
+    let end = match int.child {
+        Some(child) => intervals.get(child).start.max(int.end),
+        None => int.end,
+    };
+    (start, end)
+}
+
+fn overlaps(spans: &[(InstPoint, InstPoint)], (start, end): (InstPoint, InstPoint)) -> bool {
+    spans
+        .iter()
+        .any(|&(other_start, other_end)| other_start <= end && start <= other_end)
+}
+
+/// Moves the spilled intervals of the virtual registers whose class has a spill class to free
+/// registers of that class, when there are any.
+#[inline(never)]
+pub(crate) fn run(
+    reg_universe: &RealRegUniverse,
+    scratches_by_rc: &[Option<RealReg>],
+    intervals: &mut Intervals,
+) {
+    // The spilled intervals of each virtual register that may go to a register.  Reftyped values
+    // must stay on the stack, where the stackmaps find them.
+    let mut spilled = HashMap::<VirtualReg, SmallVec<[IntId; 4]>>::default();
+    for int in &intervals.virtuals {
+        if int.location.spill().is_none() || int.ref_typed {
+            continue;
+        }
+        let has_spill_class = reg_universe
+            .class_info(int.vreg.get_class())
+            .map_or(false, |info| info.spill_class.is_some());
+        if has_spill_class {
+            spilled.entry(int.vreg).or_default().push(int.id);
+        }
+    }
+    if spilled.is_empty() {
+        return;
+    }
+
+    // Visit the virtual registers in the order of their first spill, so that the result doesn't
+    // depend on the hash map's iteration order.
+    let mut spilled: Vec<(VirtualReg, SmallVec<[IntId; 4]>)> = spilled.into_iter().collect();
+    spilled.sort_unstable_by_key(|(vreg, ids)| {
+        let first = ids.iter().map(|&id| intervals.get(id).start).min();
+        (first, vreg.get_index())
+    });
+
+    info!(
+        "spill_regs: looking for registers for {} spilled vregs",
+        spilled.len()
+    );
+
+    // The spans over which each register of the universe is busy, filled in lazily for the
+    // registers of the spill classes.
+    let mut busy: Vec<Option<Vec<(InstPoint, InstPoint)>>> = vec![None; reg_universe.regs.len()];
+
+    let mut num_moved = 0;
+    for (vreg, ids) in spilled {
+        let spill_class = reg_universe
+            .class_info(vreg.get_class())
+            .and_then(|info| info.spill_class)
+            .unwrap();
+        let info = match reg_universe.class_info(spill_class) {
+            Some(info) => info,
+            None => continue,
+        };
+        let spans: SmallVec<[(InstPoint, InstPoint); 4]> = ids
+            .iter()
+            .map(|&id| occupancy(intervals, intervals.get(id)))
+            .collect();
+
+        let scratch = scratches_by_rc[spill_class.rc_to_usize()];
+        let mut found = None;
+        for index in info.first..=info.last {
+            let rreg = reg_universe.regs[index].0;
+            if Some(rreg) == scratch {
+                continue;
+            }
+            let busy = busy[index].get_or_insert_with(|| busy_spans(intervals, rreg));
+            if spans.iter().all(|&span| !overlaps(busy, span)) {
+                busy.extend_from_slice(&spans);
+                found = Some(rreg);
+                break;
+            }
+        }
+
+        if let Some(rreg) = found {
+            debug!("spill_regs: {:?} spilled to {:?}", vreg, rreg);
+            for id in ids {
+                intervals.get_mut(id).location = Location::Reg(rreg);
+            }
+            num_moved += 1;
+        }
+    }
+
+    info!("spill_regs: {} vregs spilled to registers", num_moved);
+}
+
+/// The spans over which `rreg` holds the value of a fixed or virtual interval.
+fn busy_spans(intervals: &Intervals, rreg: RealReg) -> Vec<(InstPoint, InstPoint)> {
+    let mut spans: Vec<_> = intervals
+        .virtuals
+        .iter()
+        .filter(|int| int.location.reg() == Some(rreg))
+        .map(|int| occupancy(intervals, int))
+        .collect();
+    for fixed in intervals.fixeds.iter().filter(|fixed| fixed.reg == rreg) {
+        spans.extend(fixed.frags.iter().map(|frag| (frag.first, frag.last)));
+    }
+    spans
+}
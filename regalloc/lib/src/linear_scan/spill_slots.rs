@@ -0,0 +1,118 @@
+//! Spill slot coloring.
+//!
+//! The main allocation loop hands out a fresh spill slot to every virtual register it spills, so
+//! that all the intervals of a virtual register agree on its stack location. This post-pass then
+//! shares slots between virtual registers whose live ranges never overlap, which shrinks the
+//! frame.
+//!
+//! The live range of a spilled virtual register is approximated by the span from the start of
+//! its first interval to the end of its last one, in linear order. Any program point at which the
+//! register's value is needed, and any point at which it may be stored into its slot, lies within
+//! that span, so two registers whose spans are disjoint can safely live in the same slot. Spans
+//! are intervals on a line, so greedily assigning the lowest free slot in start order is optimal
+//! when all slots have the same size.
+
+use super::{Intervals, Location};
+use crate::data_structures::{FxHashMap as HashMap, InstPoint};
+use crate::{Function, SpillSlot, VirtualReg};
+
+use alloc::vec;
+use alloc::vec::Vec;
+use log::{debug, info};
+
+struct SpilledVreg {
+    vreg: VirtualReg,
+    slot: SpillSlot,
+    start: InstPoint,
+    end: InstPoint,
+}
+
+/// Reassigns the spill slots of all the intervals so that virtual registers with disjoint live
+/// ranges share slots, and returns the new number of spill slots.
+#[inline(never)]
+pub(crate) fn run<F: Function>(func: &F, intervals: &mut Intervals, num_spill_slots: u32) -> u32 {
+    info!("spill_slots: coloring {} spill slots", num_spill_slots);
+
+    let mut spans = HashMap::<VirtualReg, (InstPoint, InstPoint)>::default();
+    for int in &intervals.virtuals {
+        spans
+            .entry(int.vreg)
+            .and_modify(|(start, end)| {
+                *start = (*start).min(int.start);
+                *end = (*end).max(int.end);
+            })
+            .or_insert((int.start, int.end));
+    }
+
+    // Each virtual register has at most one spill slot, shared by all its spilled intervals.
+    let mut spilled = HashMap::<VirtualReg, SpillSlot>::default();
+    for int in &intervals.virtuals {
+        if let Location::Stack(slot) = int.location {
+            debug_assert!(spilled.get(&int.vreg).map_or(true, |&s| s == slot));
+            spilled.insert(int.vreg, slot);
+        }
+    }
+
+    let mut spilled: Vec<SpilledVreg> = spilled
+        .into_iter()
+        .map(|(vreg, slot)| {
+            let (start, end) = spans[&vreg];
+            SpilledVreg {
+                vreg,
+                slot,
+                start,
+                end,
+            }
+        })
+        .collect();
+    // Sort by slot too, so the result doesn't depend on the hash map's iteration order.
+    spilled.sort_unstable_by_key(|s| (s.start, s.slot.get()));
+
+    // For each new slot, the end of the span of the virtual register currently occupying it.
+    let mut occupied_until: Vec<Option<InstPoint>> = vec![];
+    let mut remap = HashMap::<SpillSlot, SpillSlot>::default();
+    for s in &spilled {
+        let size = func.get_spillslot_size(s.vreg.get_class(), s.vreg);
+        let align = func.get_spillslot_align(s.vreg.get_class(), s.vreg);
+        let is_free = |slot: u32| match occupied_until.get(slot as usize) {
+            Some(Some(end)) => *end < s.start,
+            _ => true,
+        };
+        let mut new_slot = SpillSlot::new(0);
+        while !(new_slot.get()..new_slot.get() + size).all(is_free) {
+            new_slot = new_slot.inc(align);
+        }
+        let slots = new_slot.get() as usize..(new_slot.get() + size) as usize;
+        if occupied_until.len() < slots.end {
+            occupied_until.resize(slots.end, None);
+        }
+        for slot in slots {
+            occupied_until[slot] = Some(s.end);
+        }
+        debug!(
+            "spill_slots: {:?} [{:?}; {:?}] {:?} -> {:?}",
+            s.vreg, s.start, s.end, s.slot, new_slot
+        );
+        remap.insert(s.slot, new_slot);
+    }
+
+    // With slots of different sizes, alignment padding could in theory make the coloring worse
+    // than the original assignment; keep the latter in that case.
+    let num_colored = occupied_until.len() as u32;
+    if num_colored >= num_spill_slots {
+        info!("spill_slots: coloring didn't save any slots");
+        return num_spill_slots;
+    }
+
+    for int in intervals.virtuals.iter_mut() {
+        if let Location::Stack(slot) = int.location {
+            int.location = Location::Stack(remap[&slot]);
+        }
+    }
+
+    info!(
+        "spill_slots: {} spill slots after coloring, down from {}",
+        num_colored, num_spill_slots
+    );
+    num_colored
+}
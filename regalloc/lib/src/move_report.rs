@@ -0,0 +1,63 @@
+//! Reports of the moves between virtual registers that allocation eliminated.
+//!
+//! The allocators try to give both registers of a move the same location, so that the move does
+//! nothing and can be removed; see `Options::report_moves`.  The moves that remain are the ones
+//! worth avoiding in the client's code generation, e.g. by choosing other virtual registers, or by
+//! giving the allocator hints.
+
+use alloc::vec::Vec;
+
+use crate::data_structures::InstIx;
+use crate::{Function, RegAllocResult};
+
+/// The moves between virtual registers of a function, and which of them remain after
+/// allocation, from `RegAllocResult::move_report`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MoveReport {
+    /// The moves that were coalesced away: either they are not in the allocated code, or both
+    /// of their registers were allocated to the same real register, so that they do nothing.
+    /// These are indices of the incoming instruction stream, in ascending order.
+    pub eliminated: Vec<InstIx>,
+    /// The moves that still copy between two different real registers in the allocated code, in
+    /// the same form as `eliminated`.
+    pub remaining: Vec<InstIx>,
+}
+
+/// The moves between virtual registers of `func`, in ascending order.
+pub(crate) fn collect_vreg_moves<F: Function>(func: &F) -> Vec<InstIx> {
+    func.insn_indices()
+        .into_iter()
+        .filter(|&iix| match func.is_move(func.get_insn(iix)) {
+            Some((dst, src)) => dst.to_reg().is_virtual() && src.is_virtual(),
+            None => false,
+        })
+        .collect()
+}
+
+/// Sort the `moves` of `func` according to whether they remain in the allocation `res`.
+pub(crate) fn make_move_report<F: Function>(
+    func: &F,
+    moves: Vec<InstIx>,
+    res: &RegAllocResult<F>,
+) -> MoveReport {
+    let mut remains = Vec::new();
+    remains.resize(func.insns().len(), false);
+    for (insn, &orig) in res.insns.iter().zip(res.orig_insn_map.iter()) {
+        if orig.is_invalid() {
+            continue;
+        }
+        if let Some((dst, src)) = func.is_move(insn) {
+            if dst.to_reg() != src {
+                remains[orig.get() as usize] = true;
+            }
+        }
+    }
+
+    let (remaining, eliminated) = moves
+        .into_iter()
+        .partition(|&iix| remains[iix.get() as usize]);
+    MoveReport {
+        eliminated,
+        remaining,
+    }
+}
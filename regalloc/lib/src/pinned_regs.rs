@@ -0,0 +1,504 @@
+//! Support for virtual registers that the client pins to a real register for their whole
+//! lifetime; see `Function::pinned_regs`.
+//!
+//! Like fixed operands (see `fixed_regs`), these are invisible to the allocators: the function
+//! is wrapped into a `PinnedRegFunction`, which presents every mention of a pinned virtual
+//! register as a mention of its real register.  The allocators then see the real register's live
+//! range and allocate the other virtual registers around it, while the real register remains
+//! available everywhere else.  Since nothing would stop the incoming code from overwriting the
+//! real register while the pinned virtual register is live, the wrapper checks that beforehand.
+
+use crate::analysis_main::AnalysisError;
+use crate::data_structures::{
+    BlockIx, InstIx, Map, Range, RealReg, Reg, RegClass, RegVecs, Set, SpillSlot, TypedIxVec,
+    VirtualReg, Writable,
+};
+use crate::{
+    Algorithm, Function, RegAllocError, RegAllocResult, RegHint, RegUsageCollector, RegUsageMapper,
+    StackmapRequestInfo,
+};
+
+use alloc::borrow::Cow;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use log::info;
+use smallvec::SmallVec;
+
+/// Collect the pinned virtual registers of `func`, checking that each of them can be pinned.
+/// The result is empty if there aren't any, which is by far the most common case.
+pub(crate) fn collect_pinned_regs<F: Function>(
+    func: &F,
+    stackmap_info: Option<&StackmapRequestInfo>,
+    algorithm: &Algorithm,
+) -> Result<Map<VirtualReg, RealReg>, RegAllocError> {
+    let pins: Map<VirtualReg, RealReg> = func.pinned_regs().iter().cloned().collect();
+    if pins.is_empty() {
+        return Ok(pins);
+    }
+
+    for (vreg, rreg) in &pins {
+        if vreg.get_class() != rreg.get_class() {
+            return Err(RegAllocError::Other(
+                "pinned vreg: real register of a different class".to_string(),
+            ));
+        }
+        if vreg.get_index() >= func.get_num_vregs() {
+            return Err(RegAllocError::Other(
+                "pinned vreg: out of range vreg".to_string(),
+            ));
+        }
+    }
+
+    // A pinned virtual register has no live range of its own, so it can't be part of anything
+    // that the allocators track per virtual register.
+    if let Some(info) = stackmap_info {
+        if info
+            .reftyped_vregs
+            .iter()
+            .any(|vreg| pins.contains_key(vreg))
+        {
+            return Err(RegAllocError::Other(
+                "pinned vreg: vreg is reftyped".to_string(),
+            ));
+        }
+    }
+    if let Algorithm::Backtracking(opts) = algorithm {
+        let mut pinned_vregs = opts.memory_pins.iter().flat_map(|pin| pin.vregs.iter());
+        if pinned_vregs.any(|vreg| pins.contains_key(vreg)) {
+            return Err(RegAllocError::Other(
+                "pinned vreg: vreg is pinned to memory".to_string(),
+            ));
+        }
+    }
+    for block in func.blocks() {
+        let params = func.block_params(block);
+        let args = (0..func.block_succs(block).len()).flat_map(|i| {
+            func.block_args(block, i)
+                .iter()
+                .cloned()
+                .collect::<SmallVec<[VirtualReg; 4]>>()
+        });
+        if params
+            .iter()
+            .cloned()
+            .chain(args)
+            .any(|vreg| pins.contains_key(&vreg))
+        {
+            return Err(RegAllocError::Other(
+                "pinned vreg: vreg is a block parameter or argument".to_string(),
+            ));
+        }
+    }
+
+    Ok(pins)
+}
+
+/// An instruction of a `PinnedRegFunction`: an instruction of the client, and the pinned virtual
+/// registers to rewrite when presenting it to the allocator.
+#[derive(Clone, Debug)]
+pub(crate) struct PinnedRegInst<'p, I> {
+    insn: I,
+    pins: &'p Map<VirtualReg, RealReg>,
+}
+
+fn pin_reg(pins: &Map<VirtualReg, RealReg>, reg: Reg) -> Reg {
+    if reg.is_virtual() {
+        if let Some(rreg) = pins.get(&reg.to_virtual_reg()) {
+            return rreg.to_reg();
+        }
+    }
+    reg
+}
+
+/// A client function with its pinned virtual registers replaced by their real registers; see
+/// the module comment.
+pub(crate) struct PinnedRegFunction<'a, 'p, F: Function> {
+    func: &'a F,
+    insns: Vec<PinnedRegInst<'p, F::Inst>>,
+    pins: &'p Map<VirtualReg, RealReg>,
+}
+
+impl<'a, 'p, F: Function> PinnedRegFunction<'a, 'p, F> {
+    pub(crate) fn new(
+        func: &'a F,
+        pins: &'p Map<VirtualReg, RealReg>,
+    ) -> Result<Self, RegAllocError> {
+        check_conflicts(func, pins)?;
+        let insns = func
+            .insns()
+            .iter()
+            .map(|insn| PinnedRegInst {
+                insn: insn.clone(),
+                pins,
+            })
+            .collect();
+        Ok(Self { func, insns, pins })
+    }
+
+    /// Translate an allocation of this function back to the client's function.  Instruction
+    /// indices are the same in both.
+    pub(crate) fn map_result(&self, res: RegAllocResult<Self>) -> RegAllocResult<F> {
+        RegAllocResult {
+            insns: res.insns.into_iter().map(|insn| insn.insn).collect(),
+            target_map: res.target_map,
+            orig_insn_map: res.orig_insn_map,
+            clobbered_registers: res.clobbered_registers,
+            num_spill_slots: res.num_spill_slots,
+            block_annotations: res.block_annotations,
+            stackmaps: res.stackmaps,
+            stackmap_regs: res.stackmap_regs,
+            new_safepoint_insns: res.new_safepoint_insns,
+            memory_pin_slots: res.memory_pin_slots,
+            allocation_dump: res.allocation_dump,
+            move_report: res.move_report,
+            split_edges: res.split_edges,
+            stats: res.stats,
+        }
+    }
+
+    fn wrap(&self, insn: F::Inst) -> PinnedRegInst<'p, F::Inst> {
+        PinnedRegInst {
+            insn,
+            pins: self.pins,
+        }
+    }
+}
+
+/// Check that nothing but a pinned virtual register writes its real register while it is live.
+///
+/// This runs a liveness analysis over the "owners" of the pinned real registers: the pinned
+/// virtual registers, and the real registers themselves as mentioned by the instructions.  Two
+/// owners of the same real register conflict if one of them is written while the other one is
+/// live.
+fn check_conflicts<F: Function>(
+    func: &F,
+    pins: &Map<VirtualReg, RealReg>,
+) -> Result<(), RegAllocError> {
+    let targets: Set<RealReg> = Set::from_vec(pins.values().cloned().collect());
+    let owner = |reg: Reg| -> Option<Reg> {
+        let tracked = if reg.is_virtual() {
+            pins.contains_key(&reg.to_virtual_reg())
+        } else {
+            targets.contains(reg.to_real_reg())
+        };
+        if tracked {
+            Some(reg)
+        } else {
+            None
+        }
+    };
+    let liveouts: SmallVec<[Reg; 4]> = func
+        .func_liveouts()
+        .iter()
+        .filter_map(|rreg| owner(rreg.to_reg()))
+        .collect();
+
+    // The owners each instruction reads and writes; mods count as both.
+    let mut reads = TypedIxVec::<InstIx, SmallVec<[Reg; 2]>>::new();
+    let mut writes = TypedIxVec::<InstIx, SmallVec<[Reg; 2]>>::new();
+    let mut reg_vecs = RegVecs::new(false);
+    for iix in func.insn_indices() {
+        reg_vecs.clear();
+        F::get_regs(
+            func.get_insn(iix),
+            &mut RegUsageCollector::new(&mut reg_vecs),
+        );
+        let mut insn_reads: SmallVec<[Reg; 2]> = reg_vecs
+            .uses
+            .iter()
+            .chain(reg_vecs.mods.iter())
+            .filter_map(|&reg| owner(reg))
+            .collect();
+        if func.is_ret(iix) {
+            insn_reads.extend(liveouts.iter().cloned());
+        }
+        reads.push(insn_reads);
+        writes.push(
+            reg_vecs
+                .defs
+                .iter()
+                .chain(reg_vecs.mods.iter())
+                .filter_map(|&reg| owner(reg))
+                .collect(),
+        );
+    }
+
+    // Per-block upward-exposed reads and writes, then live-outs to a fixed point.
+    let num_blocks = func.blocks().len() as u32;
+    let mut block_uses = TypedIxVec::<BlockIx, Set<Reg>>::new();
+    let mut block_defs = TypedIxVec::<BlockIx, Set<Reg>>::new();
+    for block in func.blocks() {
+        let mut uses = Set::empty();
+        let mut defs = Set::empty();
+        for iix in func.block_insns(block) {
+            for &reg in &reads[iix] {
+                if !defs.contains(reg) {
+                    uses.insert(reg);
+                }
+            }
+            for &reg in &writes[iix] {
+                defs.insert(reg);
+            }
+        }
+        block_uses.push(uses);
+        block_defs.push(defs);
+    }
+    let mut live_in = TypedIxVec::<BlockIx, Set<Reg>>::new();
+    live_in.resize(num_blocks, Set::empty());
+    let mut live_out = TypedIxVec::<BlockIx, Set<Reg>>::new();
+    live_out.resize(num_blocks, Set::empty());
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in (0..num_blocks).rev().map(BlockIx::new) {
+            let mut out = Set::empty();
+            for &succ in func.block_succs(block).iter() {
+                out.union(&live_in[succ]);
+            }
+            let mut live = out.clone();
+            live.remove(&block_defs[block]);
+            live.union(&block_uses[block]);
+            if !live.equals(&live_in[block]) {
+                live_in[block] = live;
+                changed = true;
+            }
+            live_out[block] = out;
+        }
+    }
+
+    let target = |reg: Reg| -> RealReg {
+        if reg.is_virtual() {
+            pins[&reg.to_virtual_reg()]
+        } else {
+            reg.to_real_reg()
+        }
+    };
+    for block in func.blocks() {
+        let mut live = live_out[block].clone();
+        let insns = func.block_insns(block);
+        for iix in (0..insns.len() as u32).rev().map(|i| insns.start().plus(i)) {
+            for &written in &writes[iix] {
+                let rreg = target(written);
+                for other in live.iter() {
+                    if *other == written || target(*other) != rreg {
+                        continue;
+                    }
+                    let vreg = if written.is_virtual() {
+                        written
+                    } else {
+                        *other
+                    };
+                    info!(
+                        "pinned vreg {:?}: {:?} is overwritten at {:?} while {:?} is live",
+                        vreg, rreg, iix, other
+                    );
+                    return Err(RegAllocError::Analysis(AnalysisError::PinnedRegConflict {
+                        vreg: vreg.to_virtual_reg(),
+                        rreg,
+                    }));
+                }
+            }
+            for &reg in &writes[iix] {
+                live.delete(reg);
+            }
+            for &reg in &reads[iix] {
+                live.insert(reg);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps the pinned virtual registers to their real registers, and all the others as the
+/// allocator decided.
+#[derive(Debug)]
+struct PinnedRegMapper<'a, RUM: RegUsageMapper> {
+    maps: &'a RUM,
+    pins: &'a Map<VirtualReg, RealReg>,
+}
+
+impl<'a, RUM: RegUsageMapper> RegUsageMapper for PinnedRegMapper<'a, RUM> {
+    fn get_use(&self, vreg: VirtualReg) -> Option<RealReg> {
+        self.pins
+            .get(&vreg)
+            .cloned()
+            .or_else(|| self.maps.get_use(vreg))
+    }
+
+    fn get_def(&self, vreg: VirtualReg) -> Option<RealReg> {
+        self.pins
+            .get(&vreg)
+            .cloned()
+            .or_else(|| self.maps.get_def(vreg))
+    }
+
+    fn get_mod(&self, vreg: VirtualReg) -> Option<RealReg> {
+        self.pins
+            .get(&vreg)
+            .cloned()
+            .or_else(|| self.maps.get_mod(vreg))
+    }
+}
+
+impl<'a, 'p, F: Function> Function for PinnedRegFunction<'a, 'p, F> {
+    type Inst = PinnedRegInst<'p, F::Inst>;
+
+    fn insns(&self) -> &[Self::Inst] {
+        &self.insns
+    }
+
+    fn insns_mut(&mut self) -> &mut [Self::Inst] {
+        &mut self.insns
+    }
+
+    fn get_insn(&self, insn: InstIx) -> &Self::Inst {
+        &self.insns[insn.get() as usize]
+    }
+
+    fn get_insn_mut(&mut self, insn: InstIx) -> &mut Self::Inst {
+        &mut self.insns[insn.get() as usize]
+    }
+
+    fn blocks(&self) -> Range<BlockIx> {
+        self.func.blocks()
+    }
+
+    fn entry_block(&self) -> BlockIx {
+        self.func.entry_block()
+    }
+
+    fn block_insns(&self, block: BlockIx) -> Range<InstIx> {
+        self.func.block_insns(block)
+    }
+
+    fn block_succs(&self, block: BlockIx) -> Cow<'_, [BlockIx]> {
+        self.func.block_succs(block)
+    }
+
+    fn block_params(&self, block: BlockIx) -> Cow<'_, [VirtualReg]> {
+        self.func.block_params(block)
+    }
+
+    fn block_args(&self, block: BlockIx, succ_ix: usize) -> Cow<'_, [VirtualReg]> {
+        self.func.block_args(block, succ_ix)
+    }
+
+    fn block_frequencies(&self) -> Option<Cow<'_, [u32]>> {
+        self.func.block_frequencies()
+    }
+
+    fn is_ret(&self, insn: InstIx) -> bool {
+        self.func.is_ret(insn)
+    }
+
+    fn is_included_in_clobbers(&self, insn: &Self::Inst) -> bool {
+        self.func.is_included_in_clobbers(&insn.insn)
+    }
+
+    fn is_call(&self, insn: &Self::Inst) -> bool {
+        self.func.is_call(&insn.insn)
+    }
+
+    fn get_regs(insn: &Self::Inst, collector: &mut RegUsageCollector) {
+        let num_uses = collector.reg_vecs.uses.len();
+        let num_defs = collector.reg_vecs.defs.len();
+        let num_mods = collector.reg_vecs.mods.len();
+        F::get_regs(&insn.insn, collector);
+        let reg_vecs = &mut collector.reg_vecs;
+        let pinned = reg_vecs.uses[num_uses..]
+            .iter_mut()
+            .chain(reg_vecs.defs[num_defs..].iter_mut())
+            .chain(reg_vecs.mods[num_mods..].iter_mut());
+        for reg in pinned {
+            *reg = pin_reg(insn.pins, *reg);
+        }
+    }
+
+    fn map_regs<RUM: RegUsageMapper>(insn: &mut Self::Inst, maps: &RUM) {
+        let pins = insn.pins;
+        F::map_regs(&mut insn.insn, &PinnedRegMapper { maps, pins })
+    }
+
+    fn is_move(&self, insn: &Self::Inst) -> Option<(Writable<Reg>, Reg)> {
+        self.func.is_move(&insn.insn).map(|(dst, src)| {
+            (
+                Writable::from_reg(pin_reg(self.pins, dst.to_reg())),
+                pin_reg(self.pins, src),
+            )
+        })
+    }
+
+    fn reg_hint(&self, vreg: VirtualReg) -> Option<RegHint> {
+        // The pinned virtual registers are never allocated, so hint their real register instead.
+        match self.func.reg_hint(vreg) {
+            Some(RegHint::SameAs(other)) => match self.pins.get(&other) {
+                Some(&rreg) => Some(RegHint::Real(rreg)),
+                None => Some(RegHint::SameAs(other)),
+            },
+            hint => hint,
+        }
+    }
+
+    fn get_num_vregs(&self) -> usize {
+        self.func.get_num_vregs()
+    }
+
+    fn get_spillslot_size(&self, regclass: RegClass, for_vreg: VirtualReg) -> u32 {
+        self.func.get_spillslot_size(regclass, for_vreg)
+    }
+
+    fn get_spillslot_align(&self, regclass: RegClass, for_vreg: VirtualReg) -> u32 {
+        self.func.get_spillslot_align(regclass, for_vreg)
+    }
+
+    fn gen_spill(
+        &self,
+        to_slot: SpillSlot,
+        from_reg: RealReg,
+        for_vreg: Option<VirtualReg>,
+    ) -> Self::Inst {
+        self.wrap(self.func.gen_spill(to_slot, from_reg, for_vreg))
+    }
+
+    fn gen_reload(
+        &self,
+        to_reg: Writable<RealReg>,
+        from_slot: SpillSlot,
+        for_vreg: Option<VirtualReg>,
+    ) -> Self::Inst {
+        self.wrap(self.func.gen_reload(to_reg, from_slot, for_vreg))
+    }
+
+    fn gen_move(
+        &self,
+        to_reg: Writable<RealReg>,
+        from_reg: RealReg,
+        for_vreg: VirtualReg,
+    ) -> Self::Inst {
+        self.wrap(self.func.gen_move(to_reg, from_reg, for_vreg))
+    }
+
+    fn gen_zero_len_nop(&self) -> Self::Inst {
+        self.wrap(self.func.gen_zero_len_nop())
+    }
+
+    fn maybe_direct_reload(
+        &self,
+        insn: &Self::Inst,
+        reg: VirtualReg,
+        slot: SpillSlot,
+    ) -> Option<Self::Inst> {
+        self.func
+            .maybe_direct_reload(&insn.insn, reg, slot)
+            .map(|insn| self.wrap(insn))
+    }
+
+    fn func_liveins(&self) -> Set<RealReg> {
+        self.func.func_liveins()
+    }
+
+    fn func_liveouts(&self) -> Set<RealReg> {
+        self.func.func_liveouts()
+    }
+}
@@ -0,0 +1,187 @@
+//! A bitset of real registers, sized to the register universe.
+//!
+//! `Set<RealReg>` is a hash set, which is a poor fit for the inner loops of the allocators: every
+//! insertion and lookup hashes the register, even though real registers are densely numbered by
+//! their index in the universe.  A universe holds at most 256 registers, so a fixed array of four
+//! words can represent any set of them; only as many words as the universe needs are used, so that
+//! the common universes of up to 64 registers pay for a single word.
+
+use crate::data_structures::{RealReg, RealRegUniverse, Set};
+use alloc::fmt;
+
+/// The maximum number of words of a `RealRegBitSet`, enough for the 256 registers a universe can
+/// hold.
+const MAX_WORDS: usize = 4;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct RealRegBitSet {
+    words: [u64; MAX_WORDS],
+    /// The number of words in use, given by the size of the universe.
+    num_words: usize,
+}
+
+impl RealRegBitSet {
+    /// An empty set, able to hold any register of `reg_universe`.
+    pub fn for_universe(reg_universe: &RealRegUniverse) -> Self {
+        Self::with_capacity(reg_universe.regs.len())
+    }
+
+    /// An empty set, able to hold the registers with indices below `num_regs`.
+    pub fn with_capacity(num_regs: usize) -> Self {
+        let num_words = (num_regs + 63) / 64;
+        assert!(
+            num_words <= MAX_WORDS,
+            "too many registers for a RealRegBitSet"
+        );
+        Self {
+            words: [0; MAX_WORDS],
+            num_words,
+        }
+    }
+
+    #[inline(always)]
+    fn word_and_mask(&self, rreg: RealReg) -> (usize, u64) {
+        let index = rreg.get_index();
+        debug_assert!(index < self.num_words * 64, "register out of the universe");
+        (index / 64, 1 << (index % 64))
+    }
+
+    /// Add `rreg` to the set.  Returns whether it wasn't in the set already.
+    #[inline(always)]
+    pub fn insert(&mut self, rreg: RealReg) -> bool {
+        let (word, mask) = self.word_and_mask(rreg);
+        let absent = self.words[word] & mask == 0;
+        self.words[word] |= mask;
+        absent
+    }
+
+    #[inline(always)]
+    pub fn delete(&mut self, rreg: RealReg) {
+        let (word, mask) = self.word_and_mask(rreg);
+        self.words[word] &= !mask;
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, rreg: RealReg) -> bool {
+        let (word, mask) = self.word_and_mask(rreg);
+        self.words[word] & mask != 0
+    }
+
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        for word in &mut self.words[..self.num_words] {
+            *word = 0;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words[..self.num_words].iter().all(|&word| word == 0)
+    }
+
+    pub fn card(&self) -> usize {
+        self.words[..self.num_words]
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Iterate over the indices of the registers in the set, in increasing order.
+    pub fn iter_indices(&self) -> RealRegBitSetIter<'_> {
+        RealRegBitSetIter {
+            words: &self.words[..self.num_words],
+            base: 0,
+            current: self.words[..self.num_words].first().copied().unwrap_or(0),
+        }
+    }
+
+    /// The same registers, as a `Set<RealReg>` of the registers of `reg_universe`.
+    pub fn to_set(&self, reg_universe: &RealRegUniverse) -> Set<RealReg> {
+        let mut set = Set::empty();
+        for index in self.iter_indices() {
+            set.insert(reg_universe.regs[index].0);
+        }
+        set
+    }
+}
+
+impl fmt::Debug for RealRegBitSet {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_set().entries(self.iter_indices()).finish()
+    }
+}
+
+pub struct RealRegBitSetIter<'a> {
+    words: &'a [u64],
+    /// The index of the first register of the current word.
+    base: usize,
+    /// The bits of the current word not visited yet.
+    current: u64,
+}
+
+impl<'a> Iterator for RealRegBitSetIter<'a> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        while self.current == 0 {
+            self.base += 64;
+            let word = self.base / 64;
+            if word >= self.words.len() {
+                return None;
+            }
+            self.current = self.words[word];
+        }
+        let bit = self.current.trailing_zeros() as usize;
+        self.current &= self.current - 1;
+        Some(self.base + bit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Reg, RegClass};
+    use alloc::vec::Vec;
+
+    fn rreg(idx: u8) -> RealReg {
+        Reg::new_real(RegClass::I64, /* enc = */ 0, /* index = */ idx).to_real_reg()
+    }
+
+    #[test]
+    fn test_real_reg_bit_set() {
+        let mut set = RealRegBitSet::with_capacity(200);
+        assert!(set.is_empty());
+
+        for &idx in &[3, 63, 64, 130, 199, 64] {
+            set.insert(rreg(idx));
+        }
+        assert!(!set.insert(rreg(130)));
+        assert_eq!(set.card(), 5);
+        assert!(set.contains(rreg(64)));
+        assert!(!set.contains(rreg(65)));
+        assert_eq!(
+            set.iter_indices().collect::<Vec<_>>(),
+            [3, 63, 64, 130, 199]
+        );
+
+        set.delete(rreg(63));
+        set.delete(rreg(199));
+        assert_eq!(set.iter_indices().collect::<Vec<_>>(), [3, 64, 130]);
+
+        set.clear();
+        assert!(set.is_empty());
+        assert_eq!(set.iter_indices().next(), None);
+    }
+
+    #[test]
+    fn test_real_reg_bit_set_sizes() {
+        let empty = RealRegBitSet::with_capacity(0);
+        assert!(empty.is_empty());
+        assert_eq!(empty.iter_indices().next(), None);
+
+        let mut full = RealRegBitSet::with_capacity(256);
+        for idx in 0..=255 {
+            assert!(full.insert(rreg(idx)));
+        }
+        assert_eq!(full.card(), 256);
+        assert!(full.iter_indices().eq(0..256));
+    }
+}
@@ -0,0 +1,117 @@
+//! Register pressure analysis.
+//!
+//! `analyze_pressure` reports, for each block, the largest number of registers of each class that
+//! are live at once within the block.  Clients can run it before allocation to find the blocks
+//! where the allocator will have to spill, and split live ranges or restructure the code there
+//! beforehand.
+
+use crate::analysis_control_flow::CFGInfo;
+use crate::analysis_main::{run_liveness_analysis, AnalysisError, Liveness};
+use crate::data_structures::{
+    BlockIx, RealRegUniverse, Reg, RegClass, Set, TypedIxVec, MAX_NUM_REG_CLASSES,
+};
+use crate::Function;
+
+use log::info;
+
+/// The largest number of registers of each class live at once at some point of a block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegPressure {
+    max_live: [u32; MAX_NUM_REG_CLASSES],
+}
+
+impl RegPressure {
+    /// The largest number of registers of class `rc` live at once.
+    pub fn get(&self, rc: RegClass) -> u32 {
+        self.max_live[rc.rc_to_usize()]
+    }
+
+    fn include(&mut self, live: &[u32; MAX_NUM_REG_CLASSES]) {
+        for (max, &live) in self.max_live.iter_mut().zip(live.iter()) {
+            *max = u32::max(*max, live);
+        }
+    }
+}
+
+/// Compute the register pressure in each block of `func`.
+///
+/// Both the virtual registers and the real registers available to the allocator count towards
+/// the pressure.  A register defined by an instruction counts at that instruction even if it is
+/// never read, since it still needs a register there.  The pressure of a class can be compared
+/// to the number of registers of the class in `reg_universe`: the allocator can only avoid
+/// spilling in blocks where it doesn't exceed it.
+///
+/// If `func` has block parameters, it must be in SSA form, as the chordal allocator requires.
+/// The same analysis errors as with `allocate_registers` are reported for malformed functions.
+pub fn analyze_pressure<F: Function>(
+    func: &F,
+    reg_universe: &RealRegUniverse,
+) -> Result<TypedIxVec<BlockIx, RegPressure>, AnalysisError> {
+    info!("analyze_pressure: begin");
+
+    let cfg_info = CFGInfo::create(func)?;
+    let ssa_form = func
+        .blocks()
+        .into_iter()
+        .any(|bix| !func.block_params(bix).is_empty());
+    let Liveness {
+        reg_vecs_and_bounds: rvb,
+        liveout_sets_per_block,
+        ..
+    } = run_liveness_analysis(
+        func,
+        reg_universe,
+        &cfg_info,
+        ssa_form,
+        /*reserve_scratches=*/ false,
+    )?;
+
+    let mut pressure = TypedIxVec::new();
+    for bix in func.blocks() {
+        // Walk the block backwards from its live-out registers, keeping track of the number of
+        // live registers of each class.
+        let mut live = Set::<Reg>::empty();
+        let mut num_live = [0u32; MAX_NUM_REG_CLASSES];
+        for &reg in liveout_sets_per_block[bix].iter() {
+            add_live(&mut live, &mut num_live, reg);
+        }
+
+        let mut block_pressure = RegPressure::default();
+        block_pressure.include(&num_live);
+        let insns = func.block_insns(bix);
+        for iix in (0..insns.len() as u32).rev().map(|i| insns.start().plus(i)) {
+            let regs = rvb.get_reg_sets_for_iix(iix);
+
+            // Right after the instruction, its defined registers are live, read or not.
+            for &reg in regs.defs.iter().chain(regs.mods.iter()) {
+                add_live(&mut live, &mut num_live, reg);
+            }
+            block_pressure.include(&num_live);
+
+            // Right before it, its read registers are live, and the ones it only writes aren't.
+            for &reg in regs.defs.iter() {
+                if live.contains(reg) {
+                    live.delete(reg);
+                    num_live[reg.get_class().rc_to_usize()] -= 1;
+                }
+            }
+            for &reg in regs.uses.iter() {
+                add_live(&mut live, &mut num_live, reg);
+            }
+            block_pressure.include(&num_live);
+        }
+
+        pressure.push(block_pressure);
+    }
+
+    info!("analyze_pressure: end");
+    Ok(pressure)
+}
+
+/// Add `reg` to the `live` registers, counting it in `num_live` if it wasn't live already.
+fn add_live(live: &mut Set<Reg>, num_live: &mut [u32; MAX_NUM_REG_CLASSES], reg: Reg) {
+    if !live.contains(reg) {
+        live.insert(reg);
+        num_live[reg.get_class().rc_to_usize()] += 1;
+    }
+}
@@ -17,36 +17,38 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
-enum IRInstKind {
+pub(crate) enum IRInstKind {
     Spill { vreg: Option<VirtualReg> },
     Reload { vreg: Option<VirtualReg> },
     Move { vreg: VirtualReg },
     ZeroLenNop,
     UserReturn,
     UserMove,
+    UserCall,
     UserOther,
 }
 
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct IRInst {
-    reg_uses: Vec<Reg>,
-    reg_mods: Vec<Writable<Reg>>,
-    reg_defs: Vec<Writable<Reg>>,
-    kind: IRInstKind,
+    pub(crate) reg_uses: Vec<Reg>,
+    pub(crate) reg_mods: Vec<Writable<Reg>>,
+    pub(crate) reg_defs: Vec<Writable<Reg>>,
+    pub(crate) kind: IRInstKind,
 }
 
 #[derive(Clone)]
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct IRFunction {
-    instructions: Vec<IRInst>,
-    block_ranges: Vec<Range<InstIx>>,
-    block_succs: Vec<Vec<BlockIx>>,
-    entry_block: BlockIx,
-    liveins: Set<RealReg>,
-    liveouts: Set<RealReg>,
-    vreg_spill_slot_sizes: Vec<Option<(u32, RegClass)>>,
-    num_vregs: usize,
+    pub(crate) instructions: Vec<IRInst>,
+    pub(crate) block_ranges: Vec<Range<InstIx>>,
+    pub(crate) block_succs: Vec<Vec<BlockIx>>,
+    pub(crate) entry_block: BlockIx,
+    pub(crate) liveins: Set<RealReg>,
+    pub(crate) liveouts: Set<RealReg>,
+    // The spill slot size, alignment and class of each virtual register.
+    pub(crate) vreg_spill_slot_layouts: Vec<Option<(u32, u32, RegClass)>>,
+    pub(crate) num_vregs: usize,
 }
 
 #[derive(Clone)]
@@ -57,6 +59,20 @@ pub struct IRSnapshot {
 }
 
 impl IRSnapshot {
+    pub(crate) fn new(reg_universe: RealRegUniverse, func: IRFunction) -> Self {
+        Self { reg_universe, func }
+    }
+
+    /// The snapshotted function, which implements `Function`.
+    pub fn func(&self) -> &IRFunction {
+        &self.func
+    }
+
+    /// The register universe the function is allocated against.
+    pub fn reg_universe(&self) -> &RealRegUniverse {
+        &self.reg_universe
+    }
+
     fn new_inst<F: Function>(func: &F, ix: InstIx, inst: &F::Inst) -> IRInst {
         let mut reg_vecs = RegVecs::new(/* sanitized */ false);
 
@@ -67,6 +83,8 @@ impl IRSnapshot {
             IRInstKind::UserMove
         } else if func.is_ret(ix) {
             IRInstKind::UserReturn
+        } else if func.is_call(inst) {
+            IRInstKind::UserCall
         } else {
             IRInstKind::UserOther
         };
@@ -102,23 +120,25 @@ impl IRSnapshot {
             block_succs.push(func.block_succs(block).into());
         }
 
-        let vreg_spill_slot_sizes = {
-            let mut array: Vec<Option<(u32, RegClass)>> = Vec::new();
+        let vreg_spill_slot_layouts = {
+            let mut array: Vec<Option<(u32, u32, RegClass)>> = Vec::new();
 
             let mut handle_reg = |reg: &Reg| {
                 if let Some(vreg) = reg.as_virtual_reg() {
                     let rc = vreg.get_class();
                     let spill_slot_size = func.get_spillslot_size(rc, vreg);
+                    let spill_slot_align = func.get_spillslot_align(rc, vreg);
                     let index = vreg.get_index();
                     if index >= array.len() {
                         array.resize(index + 1, None);
                     }
                     let entry = &mut array[vreg.get_index()];
                     match entry {
-                        None => *entry = Some((spill_slot_size, rc)),
-                        Some((prev_size, prev_rc)) => {
+                        None => *entry = Some((spill_slot_size, spill_slot_align, rc)),
+                        Some((prev_size, prev_align, prev_rc)) => {
                             assert_eq!(*prev_rc, rc);
                             assert_eq!(*prev_size, spill_slot_size);
+                            assert_eq!(*prev_align, spill_slot_align);
                         }
                     }
                 }
@@ -152,7 +172,7 @@ impl IRSnapshot {
                 entry_block,
                 liveins,
                 liveouts,
-                vreg_spill_slot_sizes,
+                vreg_spill_slot_layouts,
                 num_vregs: func.get_num_vregs(),
             },
         }
@@ -215,6 +235,14 @@ impl Function for IRFunction {
         }
     }
 
+    fn is_call(&self, insn: &Self::Inst) -> bool {
+        if let IRInstKind::UserCall = insn.kind {
+            true
+        } else {
+            false
+        }
+    }
+
     // Blocks.
     fn blocks(&self) -> Range<BlockIx> {
         Range::new(BlockIx::new(0), self.block_ranges.len())
@@ -305,11 +333,18 @@ impl Function for IRFunction {
 
     fn get_spillslot_size(&self, regclass: RegClass, for_vreg: VirtualReg) -> u32 {
         let entry =
-            self.vreg_spill_slot_sizes[for_vreg.get_index()].expect("missing spillslot info");
-        assert_eq!(entry.1, regclass);
+            self.vreg_spill_slot_layouts[for_vreg.get_index()].expect("missing spillslot info");
+        assert_eq!(entry.2, regclass);
         return entry.0;
     }
 
+    fn get_spillslot_align(&self, regclass: RegClass, for_vreg: VirtualReg) -> u32 {
+        let entry =
+            self.vreg_spill_slot_layouts[for_vreg.get_index()].expect("missing spillslot info");
+        assert_eq!(entry.2, regclass);
+        return entry.1;
+    }
+
     fn maybe_direct_reload(
         &self,
         _insn: &Self::Inst,
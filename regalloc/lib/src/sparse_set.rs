@@ -20,7 +20,7 @@ pub type SparseSet<T> = SparseSetU<[T; 12]>;
 use crate::data_structures::FxHashSet;
 use alloc::format;
 use alloc::string::ToString;
-use alloc::vec::Vec;
+use alloc::vec::{self, Vec};
 use core::mem::MaybeUninit;
 use core::ptr::{read, write};
 
@@ -674,6 +674,41 @@ impl<'a, A: Array> Iterator for SparseSetUIter<'a, A> {
     }
 }
 
+impl<A> SparseSetU<A>
+where
+    A: Array,
+    A::Item: Eq + Ord + Hash + Copy + fmt::Debug,
+{
+    /// Iterate over the elements in ascending order if `sorted` is set, and in the order of
+    /// `iter` otherwise.  The latter is cheaper, but depends on the order in which the elements
+    /// were inserted and, for large sets, on the layout of the hash table, which differs between
+    /// targets.
+    pub fn iter_ordered(&self, sorted: bool) -> SparseSetUOrderedIter<A> {
+        if sorted {
+            SparseSetUOrderedIter::Sorted(self.to_vec().into_iter())
+        } else {
+            SparseSetUOrderedIter::Unordered(self.iter())
+        }
+    }
+}
+
+pub enum SparseSetUOrderedIter<'a, A: Array> {
+    Unordered(SparseSetUIter<'a, A>),
+    Sorted(vec::IntoIter<A::Item>),
+}
+impl<'a, A: Array> Iterator for SparseSetUOrderedIter<'a, A>
+where
+    A::Item: Copy,
+{
+    type Item = A::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SparseSetUOrderedIter::Unordered(iter) => iter.next().cloned(),
+            SparseSetUOrderedIter::Sorted(iter) => iter.next(),
+        }
+    }
+}
+
 // ================ Testing machinery for SparseSetU ================
 
 #[cfg(test)]
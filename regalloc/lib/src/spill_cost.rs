@@ -0,0 +1,63 @@
+//! Client-supplied spill cost models for the backtracking allocator.
+//!
+//! The backtracking allocator evicts and spills the virtual ranges that are the cheapest to
+//! spill.  By default, the cost of spilling a range is the number of instructions mentioning its
+//! virtual register, each weighted by the estimated execution frequency of its block, divided by
+//! the length of the range so as to favour short ranges.  A `SpillCostModel` replaces the cost of
+//! each mention, e.g. to make values used in addressing modes more expensive to spill, or to
+//! account for the latency of reloads on the target.
+
+use crate::analysis_control_flow::InstIxToBlockIxMap;
+use crate::analysis_data_flow::does_inst_use_def_or_mod_reg;
+use crate::analysis_main::BlockFrequencies;
+use crate::data_structures::{
+    InstIx, InstPoint, RegVecsAndBounds, SpillCost, TypedIxVec, VirtualRange, VirtualRangeIx,
+    VirtualReg,
+};
+
+/// A model of the cost of spilling virtual registers, see `BacktrackingOptions::spill_cost_model`.
+pub trait SpillCostModel {
+    /// The cost of the reload or spill that instruction `iix`, which mentions `vreg`, would need
+    /// if `vreg` were spilled there.  `frequency` is the execution frequency of the block of
+    /// `iix`, as estimated by the allocator or supplied through `Function::block_frequencies`.
+    ///
+    /// The allocator's own model returns `frequency`.
+    fn mention_cost(&self, vreg: VirtualReg, iix: InstIx, frequency: u32) -> u32;
+}
+
+/// Recompute the costs of the virtual ranges in `vlr_env` with `model`.
+pub(crate) fn apply_spill_cost_model(
+    model: &dyn SpillCostModel,
+    rvb: &RegVecsAndBounds,
+    inst_to_block_map: &InstIxToBlockIxMap,
+    est_freqs: &BlockFrequencies,
+    vlr_env: &mut TypedIxVec<VirtualRangeIx, VirtualRange>,
+) {
+    for vlr in vlr_env.iter_mut() {
+        let reg = vlr.vreg.to_reg();
+        let mut total_cost = 0u32;
+        for frag in vlr.sorted_frags.iter() {
+            for iix in frag.first.iix().get()..=frag.last.iix().get() {
+                let iix = InstIx::new(iix);
+                let (uses, defs, mods) = does_inst_use_def_or_mod_reg(rvb, iix, reg);
+                // Only count the mentions within the fragment.
+                let in_frag = |pt| frag.first <= pt && pt <= frag.last;
+                let used = (uses || mods) && in_frag(InstPoint::new_use(iix));
+                let defined = (defs || mods) && in_frag(InstPoint::new_def(iix));
+                if used || defined {
+                    let frequency = est_freqs.cost(inst_to_block_map.map(iix));
+                    let cost = model.mention_cost(vlr.vreg, iix, frequency);
+                    total_cost = total_cost.saturating_add(cost);
+                }
+            }
+        }
+
+        // As in the coalescing analysis, a zero cost would be a problem for the commitment maps.
+        vlr.total_cost = total_cost;
+        vlr.spill_cost = if total_cost == 0 {
+            SpillCost::finite(1.0e-6)
+        } else {
+            SpillCost::finite(total_cost as f32 / vlr.size as f32)
+        };
+    }
+}